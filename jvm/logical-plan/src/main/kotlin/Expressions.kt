@@ -95,6 +95,24 @@ class LiteralDouble(val n: Double): LogicalExpr {
 /** Convenience method to create a LiteralDouble */
 fun lit(value: Double) = LiteralDouble(value)
 
+/**
+ * Logical expression representing a literal boolean value.
+ */
+class LiteralBoolean(val b: Boolean): LogicalExpr {
+
+    override fun toField(input: LogicalPlan): Field {
+        return Field(b.toString(), ArrowTypes.BooleanType)
+    }
+
+    override fun toString(): String {
+        return b.toString()
+    }
+
+}
+
+/** Convenience method to create a LiteralBoolean */
+fun lit(value: Boolean) = LiteralBoolean(value)
+
 class CastExpr(val expr: LogicalExpr, val dataType: ArrowType) : LogicalExpr {
     override fun toField(input: LogicalPlan): Field {
         return Field(expr.toField(input).name, dataType)
@@ -135,6 +153,32 @@ class Not(expr: LogicalExpr): UnaryExpr("not", "NOT", expr) {
 
 }
 
+/** Logical expression representing an IS NULL test */
+class IsNull(expr: LogicalExpr): UnaryExpr("is_null", "IS NULL", expr) {
+
+    override fun toField(input: LogicalPlan): Field {
+        return Field(name, ArrowTypes.BooleanType)
+    }
+
+    override fun toString(): String {
+        return "$expr IS NULL"
+    }
+
+}
+
+/** Logical expression representing an IS NOT NULL test */
+class IsNotNull(expr: LogicalExpr): UnaryExpr("is_not_null", "IS NOT NULL", expr) {
+
+    override fun toField(input: LogicalPlan): Field {
+        return Field(name, ArrowTypes.BooleanType)
+    }
+
+    override fun toString(): String {
+        return "$expr IS NOT NULL"
+    }
+
+}
+
 /** Binary expressions that return a boolean type */
 abstract class BooleanBinaryExpr(name: String,
                                  op: String,
@@ -248,6 +292,17 @@ infix fun LogicalExpr.alias(alias: String) : Alias {
     return Alias(this, alias)
 }
 
+/** Sort expression, which orders by an expression in ascending or descending order */
+class SortExpr(val expr: LogicalExpr, val asc: Boolean) : LogicalExpr {
+    override fun toField(input: LogicalPlan): Field {
+        return expr.toField(input)
+    }
+
+    override fun toString(): String {
+        return "$expr ${if (asc) "ASC" else "DESC"}"
+    }
+}
+
 /** Scalar function */
 class ScalarFunction(val name: String, val args: List<LogicalExpr>, val returnType: ArrowType ) : LogicalExpr {
     override fun toField(input: LogicalPlan): Field {
@@ -0,0 +1,24 @@
+package org.ballistacompute.logical
+
+import org.ballistacompute.datatypes.Schema
+
+/**
+ * Logical plan representing an inner equijoin of two inputs, where each pair of column indices
+ * refers to a column of the left input and a column of the right input that must be equal
+ */
+class Join(val left: LogicalPlan,
+           val right: LogicalPlan,
+           val on: List<Pair<Int, Int>>): LogicalPlan {
+    override fun schema(): Schema {
+        // the output has the columns of the left input followed by those of the right input
+        return Schema(left.schema().fields + right.schema().fields)
+    }
+
+    override fun children(): List<LogicalPlan> {
+        return listOf(left, right)
+    }
+
+    override fun toString(): String {
+        return "Join: ${ on.map { "#${it.first} = #${it.second}" }.joinToString(", ") }"
+    }
+}
@@ -0,0 +1,20 @@
+package org.ballistacompute.logical
+
+import org.ballistacompute.datatypes.Schema
+
+/**
+ * Logical plan representing a sort of the input by a list of sort expressions
+ */
+class Sort(val input: LogicalPlan, val expr: List<SortExpr>): LogicalPlan {
+    override fun schema(): Schema {
+        return input.schema()
+    }
+
+    override fun children(): List<LogicalPlan> {
+        return listOf(input)
+    }
+
+    override fun toString(): String {
+        return "Sort: ${ expr.map { it.toString() }.joinToString(", ") }"
+    }
+}
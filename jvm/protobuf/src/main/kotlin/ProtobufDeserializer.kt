@@ -26,6 +26,13 @@ class ProtobufDeserializer {
             val groupExpr = node.aggregate.groupExprList.map { fromProto(it) }
             val aggrExpr = node.aggregate.aggrExprList.map { fromProto(it) as AggregateExpr }
             Aggregate(input, groupExpr, aggrExpr)
+        } else if (node.hasSort()) {
+            Sort(fromProto(node.input),
+                    node.sort.exprList.map { fromProto(it) as SortExpr })
+        } else if (node.hasJoin()) {
+            Join(fromProto(node.input),
+                    fromProto(node.right),
+                    node.join.leftColumnsList.zip(node.join.rightColumnsList))
         } else {
             throw RuntimeException("Failed to parse logical operator: $node")
         }
@@ -49,6 +56,7 @@ class ProtobufDeserializer {
                 "subtract" -> Subtract(ll, rr)
                 "multiply" -> Multiply(ll, rr)
                 "divide" -> Divide(ll, rr)
+                "modulus" -> Modulus(ll, rr)
                 else -> throw RuntimeException("Failed to parse logical binary expression: $node")
             }
         } else if (node.hasColumnIndex) {
@@ -61,6 +69,20 @@ class ProtobufDeserializer {
             lit(node.literalLong)
         } else if (node.hasLiteralDouble) {
             lit(node.literalDouble)
+        } else if (node.hasLiteralBool) {
+            lit(node.literalBool)
+        } else if (node.hasNotExpr()) {
+            Not(fromProto(node.notExpr))
+        } else if (node.hasIsNullExpr()) {
+            IsNull(fromProto(node.isNullExpr))
+        } else if (node.hasIsNotNullExpr()) {
+            IsNotNull(fromProto(node.isNotNullExpr))
+        } else if (node.hasAlias()) {
+            Alias(fromProto(node.alias.expr), node.alias.alias)
+        } else if (node.hasCast()) {
+            CastExpr(fromProto(node.cast.expr), fromProto(node.cast.arrowTypeValue))
+        } else if (node.hasSort()) {
+            SortExpr(fromProto(node.sort.expr), node.sort.asc)
         } else if (node.hasAggregateExpr()) {
             val aggr = node.aggregateExpr
             val expr = fromProto(aggr.expr)
@@ -69,6 +91,7 @@ class ProtobufDeserializer {
                 AggregateFunction.MAX -> Max(expr)
                 AggregateFunction.SUM -> Sum(expr)
                 AggregateFunction.AVG -> Avg(expr)
+                AggregateFunction.COUNT -> Count(expr)
                 else -> throw RuntimeException("Failed to parse logical aggregate expression: ${aggr.aggrFunction}")
             }
 
@@ -80,31 +103,33 @@ class ProtobufDeserializer {
     fun fromProto(schema: Schema): org.ballistacompute.datatypes.Schema {
 
         val arrowFields = schema.columnsList.map {
+            val fieldType = org.apache.arrow.vector.types.pojo.FieldType(true, fromProto(it.arrowTypeValue), null)
+            org.apache.arrow.vector.types.pojo.Field(it.name, fieldType, listOf())
+        }
 
-            //TODO add all types
-            val dt = when (it.arrowTypeValue) {
-                ArrowType.UTF8_VALUE -> ArrowTypes.StringType
+        return org.ballistacompute.datatypes.SchemaConverter.fromArrow(org.apache.arrow.vector.types.pojo.Schema(arrowFields))
+    }
 
-                ArrowType.INT8_VALUE -> ArrowTypes.Int8Type
-                ArrowType.INT16_VALUE -> ArrowTypes.Int16Type
-                ArrowType.INT32_VALUE -> ArrowTypes.Int32Type
-                ArrowType.INT64_VALUE -> ArrowTypes.Int64Type
+    fun fromProto(arrowTypeValue: Int): org.apache.arrow.vector.types.pojo.ArrowType {
+        //TODO add all types
+        return when (arrowTypeValue) {
+            ArrowType.BOOL_VALUE -> ArrowTypes.BooleanType
+            ArrowType.UTF8_VALUE -> ArrowTypes.StringType
 
-                ArrowType.UINT8_VALUE -> ArrowTypes.UInt8Type
-                ArrowType.UINT16_VALUE -> ArrowTypes.UInt16Type
-                ArrowType.UINT32_VALUE -> ArrowTypes.UInt32Type
-                ArrowType.UINT64_VALUE -> ArrowTypes.UInt64Type
+            ArrowType.INT8_VALUE -> ArrowTypes.Int8Type
+            ArrowType.INT16_VALUE -> ArrowTypes.Int16Type
+            ArrowType.INT32_VALUE -> ArrowTypes.Int32Type
+            ArrowType.INT64_VALUE -> ArrowTypes.Int64Type
 
-                ArrowType.FLOAT_VALUE -> ArrowTypes.FloatType
-                ArrowType.DOUBLE_VALUE -> ArrowTypes.DoubleType
+            ArrowType.UINT8_VALUE -> ArrowTypes.UInt8Type
+            ArrowType.UINT16_VALUE -> ArrowTypes.UInt16Type
+            ArrowType.UINT32_VALUE -> ArrowTypes.UInt32Type
+            ArrowType.UINT64_VALUE -> ArrowTypes.UInt64Type
 
-                else -> throw IllegalStateException("Failed to parse Arrow data type enum from protobuf: ${it.arrowTypeValue}")
-            }
+            ArrowType.FLOAT_VALUE -> ArrowTypes.FloatType
+            ArrowType.DOUBLE_VALUE -> ArrowTypes.DoubleType
 
-            val fieldType = org.apache.arrow.vector.types.pojo.FieldType(true, dt, null)
-            org.apache.arrow.vector.types.pojo.Field(it.name, fieldType, listOf())
+            else -> throw IllegalStateException("Failed to parse Arrow data type enum from protobuf: $arrowTypeValue")
         }
-
-        return org.ballistacompute.datatypes.SchemaConverter.fromArrow(org.apache.arrow.vector.types.pojo.Schema(arrowFields))
     }
 }
\ No newline at end of file
@@ -1,4 +1,11 @@
 fn main() {
-    prost_build::compile_protos(&["proto/ballista.proto"], &["proto"])
-        .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
+    prost_build::compile_protos(
+        &[
+            "proto/ballista.proto",
+            "proto/flight_sql.proto",
+            "proto/substrait.proto",
+        ],
+        &["proto"],
+    )
+    .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
 }
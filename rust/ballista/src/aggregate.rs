@@ -0,0 +1,926 @@
+//! Hash aggregation with a memory budget.
+//!
+//! DataFusion holds every group of an aggregate in memory, which can exhaust the memory of
+//! an executor for group-bys with many groups. When `ballista.aggregate.memoryLimit` is
+//! set, aggregates that group by columns and compute `COUNT`, `SUM`, `MIN`, `MAX` or `AVG`
+//! of columns are executed here instead. The groups are accumulated in a hash table, and
+//! whenever the estimated size of the table exceeds the budget, the partial aggregates are
+//! spilled to Arrow IPC files, partitioned by the hash of the group keys. Once the input
+//! has been read, the spill files of each partition are merged on their own, so only the
+//! groups of one partition need to fit in memory at a time.
+//!
+//! Aggregates of decimals are always executed here, since DataFusion cannot compute with
+//! them. `SUM`, `MIN`, `MAX` and `AVG` of decimal columns accumulate their unscaled `i128`
+//! values, so the results are exact (see `decimal`).
+//!
+//! The input is not read into memory first. Its batches are passed to the aggregate as the
+//! partitions of the input plan produce them, and scans of files read a file, or a split of
+//! one, at a time as their partitions execute.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::compute::kernels::cast::cast;
+use crate::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datasource::DEFAULT_BATCH_SIZE;
+use crate::decimal::{self, avg_type, parse_decimal};
+use crate::dictionary::{self, DictionaryFileReader, DictionaryFileWriter};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::join::{column_values, compare_values, KeyValue};
+use crate::logicalplan::{format_decimal, Expr};
+
+/// Bytes of memory that an aggregate may use for its groups before spilling them to disk
+pub const AGGREGATE_MEMORY_LIMIT: &str = "ballista.aggregate.memoryLimit";
+
+/// Number of files that spilled groups are partitioned into by the hash of their keys
+const SPILL_PARTITIONS: usize = 16;
+
+/// Estimated bytes used by the hash table for each group, besides its keys and states
+const GROUP_OVERHEAD: usize = 48;
+
+/// Used to give spill files unique names
+static NEXT_SPILL_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+/// An aggregate function of a column of the input, or of every row for `COUNT` of a
+/// literal
+#[derive(Debug, Clone)]
+struct Aggregate {
+    function: Function,
+    column: Option<usize>,
+    /// The scale of a decimal column, whose values are aggregated exactly
+    scale: Option<usize>,
+}
+
+/// The state of an aggregate for a group
+#[derive(Debug, Clone, PartialEq)]
+enum Accumulator {
+    Count(u64),
+    Sum(Option<KeyValue>),
+    Min(Option<KeyValue>),
+    Max(Option<KeyValue>),
+    Avg {
+        sum: f64,
+        count: u64,
+    },
+    /// `SUM`, `MIN` or `MAX` of decimals of a scale, as an unscaled value
+    Decimal {
+        function: Function,
+        scale: usize,
+        value: Option<i128>,
+    },
+    /// `AVG` of decimals of a scale, as their unscaled sum and their count
+    DecimalAvg {
+        scale: usize,
+        sum: i128,
+        count: u64,
+    },
+}
+
+impl Accumulator {
+    fn new(aggregate: &Aggregate) -> Self {
+        match (aggregate.function, aggregate.scale) {
+            (Function::Count, _) => Accumulator::Count(0),
+            (Function::Avg, Some(scale)) => Accumulator::DecimalAvg {
+                scale,
+                sum: 0,
+                count: 0,
+            },
+            (function, Some(scale)) => Accumulator::Decimal {
+                function,
+                scale,
+                value: None,
+            },
+            (Function::Sum, None) => Accumulator::Sum(None),
+            (Function::Min, None) => Accumulator::Min(None),
+            (Function::Max, None) => Accumulator::Max(None),
+            (Function::Avg, None) => Accumulator::Avg { sum: 0.0, count: 0 },
+        }
+    }
+
+    /// Add a value of the input, ignoring null values
+    fn update(&mut self, value: Option<&KeyValue>) -> Result<()> {
+        let value = match value {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        match self {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum(sum) => {
+                *sum = Some(match sum.take() {
+                    Some(sum) => add(&sum, value)?,
+                    None => value.clone(),
+                })
+            }
+            Accumulator::Min(min) => {
+                if min.as_ref().map(|min| compare_values(value, min)) != Some(Ordering::Greater) {
+                    *min = Some(value.clone());
+                }
+            }
+            Accumulator::Max(max) => {
+                if max.as_ref().map(|max| compare_values(value, max)) != Some(Ordering::Less) {
+                    *max = Some(value.clone());
+                }
+            }
+            Accumulator::Avg { sum, count } => {
+                *sum += as_f64(value)?;
+                *count += 1;
+            }
+            Accumulator::Decimal {
+                function,
+                scale,
+                value: current,
+            } => {
+                let value = decimal_value(value, *scale)?;
+                *current = combine_decimals(*function, *current, Some(value))?;
+            }
+            Accumulator::DecimalAvg { scale, sum, count } => {
+                let value = decimal_value(value, *scale)?;
+                *sum = sum.checked_add(value).ok_or_else(decimal_overflow)?;
+                *count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the state of the same aggregate of the same group
+    fn merge(&mut self, other: Accumulator) -> Result<()> {
+        match (self, other) {
+            (Accumulator::Count(count), Accumulator::Count(other)) => *count += other,
+            (Accumulator::Avg { sum, count }, Accumulator::Avg { sum: s, count: c }) => {
+                *sum += s;
+                *count += c;
+            }
+            (
+                Accumulator::Decimal {
+                    function, value, ..
+                },
+                Accumulator::Decimal { value: other, .. },
+            ) => *value = combine_decimals(*function, *value, other)?,
+            (
+                Accumulator::DecimalAvg { sum, count, .. },
+                Accumulator::DecimalAvg {
+                    sum: s, count: c, ..
+                },
+            ) => {
+                *sum = sum.checked_add(s).ok_or_else(decimal_overflow)?;
+                *count += c;
+            }
+            (this, Accumulator::Sum(value))
+            | (this, Accumulator::Min(value))
+            | (this, Accumulator::Max(value)) => this.update(value.as_ref())?,
+            (_, other) => {
+                return Err(ballista_error(&format!(
+                    "Cannot merge aggregate state {:?}",
+                    other
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// The values that the state is spilled as
+    fn state(&self) -> Vec<Option<KeyValue>> {
+        match self {
+            Accumulator::Count(count) => vec![Some(KeyValue::UInt(*count))],
+            Accumulator::Sum(value) | Accumulator::Min(value) | Accumulator::Max(value) => {
+                vec![value.clone()]
+            }
+            Accumulator::Avg { sum, count } => vec![
+                Some(KeyValue::Float(sum.to_bits())),
+                Some(KeyValue::UInt(*count)),
+            ],
+            // decimals are spilled as decimal text
+            Accumulator::Decimal { scale, value, .. } => {
+                vec![value.map(|v| KeyValue::Utf8(format_decimal(v, *scale)))]
+            }
+            Accumulator::DecimalAvg { scale, sum, count } => vec![
+                Some(KeyValue::Utf8(format_decimal(*sum, *scale))),
+                Some(KeyValue::UInt(*count)),
+            ],
+        }
+    }
+
+    /// Decode a state that was spilled with `state`
+    fn from_state(aggregate: &Aggregate, state: &[Option<KeyValue>]) -> Result<Self> {
+        let decimal = |value: &Option<KeyValue>, scale: usize| {
+            value.as_ref().map(|v| decimal_value(v, scale)).transpose()
+        };
+        Ok(match (aggregate.function, aggregate.scale) {
+            (Function::Count, _) => Accumulator::Count(state[0].as_ref().map_or(Ok(0), as_u64)?),
+            (Function::Avg, Some(scale)) => Accumulator::DecimalAvg {
+                scale,
+                sum: decimal(&state[0], scale)?.unwrap_or(0),
+                count: state[1].as_ref().map_or(Ok(0), as_u64)?,
+            },
+            (function, Some(scale)) => Accumulator::Decimal {
+                function,
+                scale,
+                value: decimal(&state[0], scale)?,
+            },
+            (Function::Sum, None) => Accumulator::Sum(state[0].clone()),
+            (Function::Min, None) => Accumulator::Min(state[0].clone()),
+            (Function::Max, None) => Accumulator::Max(state[0].clone()),
+            (Function::Avg, None) => Accumulator::Avg {
+                sum: state[0].as_ref().map_or(Ok(0.0), as_f64)?,
+                count: state[1].as_ref().map_or(Ok(0), as_u64)?,
+            },
+        })
+    }
+
+    /// The result of the aggregate
+    fn value(&self) -> Result<Option<KeyValue>> {
+        Ok(match self {
+            Accumulator::Count(count) => Some(KeyValue::UInt(*count)),
+            Accumulator::Sum(value) | Accumulator::Min(value) | Accumulator::Max(value) => {
+                value.clone()
+            }
+            Accumulator::Avg { count: 0, .. } => None,
+            Accumulator::Avg { sum, count } => {
+                Some(KeyValue::Float((sum / *count as f64).to_bits()))
+            }
+            Accumulator::Decimal { scale, value, .. } => {
+                value.map(|v| KeyValue::Utf8(format_decimal(v, *scale)))
+            }
+            Accumulator::DecimalAvg { count: 0, .. } => None,
+            Accumulator::DecimalAvg { scale, sum, count } => {
+                let average =
+                    decimal::average(*sum, *count, *scale).ok_or_else(decimal_overflow)?;
+                let (_, avg_scale) = avg_type((0, *scale));
+                Some(KeyValue::Utf8(format_decimal(average, avg_scale)))
+            }
+        })
+    }
+}
+
+/// Combine an unscaled decimal value into the `SUM`, `MIN` or `MAX` of unscaled values
+fn combine_decimals(
+    function: Function,
+    current: Option<i128>,
+    value: Option<i128>,
+) -> Result<Option<i128>> {
+    Ok(match (current, value) {
+        (Some(current), Some(value)) => Some(match function {
+            Function::Sum => current.checked_add(value).ok_or_else(decimal_overflow)?,
+            Function::Min => current.min(value),
+            _ => current.max(value),
+        }),
+        (current, value) => current.or(value),
+    })
+}
+
+/// The unscaled value of a decimal of the input, which is decimal text
+fn decimal_value(value: &KeyValue, scale: usize) -> Result<i128> {
+    match value {
+        KeyValue::Utf8(text) => parse_decimal(text, scale).ok_or_else(|| {
+            ballista_error(&format!(
+                "'{}' is not a decimal with a scale of {}",
+                text, scale
+            ))
+        }),
+        other => Err(ballista_error(&format!("{:?} is not a decimal", other))),
+    }
+}
+
+fn decimal_overflow() -> BallistaError {
+    ballista_error("Decimal overflow in an aggregate")
+}
+
+type Groups = HashMap<Vec<Option<KeyValue>>, Vec<Accumulator>>;
+
+/// A grouped aggregation that spills its groups to disk when their estimated size exceeds
+/// the memory limit
+pub struct HashAggregate {
+    group_columns: Vec<usize>,
+    aggregates: Vec<Aggregate>,
+    /// The schema of the output, with the group columns followed by the aggregates
+    schema: SchemaRef,
+    /// The schema of spill files, with the group columns followed by the aggregate states
+    spill_schema: SchemaRef,
+    memory_limit: usize,
+    groups: Groups,
+    /// Estimated bytes used by the groups
+    memory: usize,
+    spill: Option<SpillFiles>,
+    spills: u64,
+}
+
+impl HashAggregate {
+    /// Create the aggregation for an aggregate of an input with the given schema, or `None`
+    /// if some of the expressions of the aggregate are not supported
+    pub fn try_new(
+        group_expr: &[Expr],
+        aggr_expr: &[Expr],
+        input_schema: &Schema,
+        schema: &Schema,
+        memory_limit: usize,
+    ) -> Option<Self> {
+        let mut fields = vec![];
+        let mut group_columns = vec![];
+        for (i, expr) in group_expr.iter().enumerate() {
+            let column = column_index(expr)?;
+            let data_type = value_type(input_schema.field(column).data_type())?;
+            fields.push(Field::new(schema.field(i).name(), data_type, true));
+            group_columns.push(column);
+        }
+
+        let mut aggregates = vec![];
+        for expr in aggr_expr {
+            let expr = match expr {
+                Expr::Alias(expr, _) => expr.as_ref(),
+                expr => expr,
+            };
+            let (name, arg) = match expr {
+                Expr::AggregateFunction { name, args, .. } if args.len() == 1 => (name, &args[0]),
+                _ => return None,
+            };
+            let function = match name.to_uppercase().as_str() {
+                "COUNT" => Function::Count,
+                "SUM" => Function::Sum,
+                "MIN" => Function::Min,
+                "MAX" => Function::Max,
+                "AVG" => Function::Avg,
+                _ => return None,
+            };
+            let column = match (function, arg) {
+                (Function::Count, Expr::Literal(_)) => None,
+                (_, arg) => Some(column_index(arg)?),
+            };
+            let data_type = match column {
+                Some(column) => value_type(input_schema.field(column).data_type())?,
+                None => DataType::UInt64,
+            };
+            let scale = column
+                .and_then(|column| decimal::decimal_type(&Expr::Column(column), input_schema))
+                .map(|(_, scale)| scale);
+            let numeric = data_type != DataType::Boolean && data_type != DataType::Utf8;
+            let state_types = match (function, scale) {
+                (Function::Count, _) => vec![DataType::UInt64],
+                (Function::Avg, Some(_)) => vec![DataType::Utf8, DataType::UInt64],
+                (_, Some(_)) => vec![DataType::Utf8],
+                (Function::Min, None) | (Function::Max, None) => vec![data_type],
+                (Function::Sum, None) if numeric => vec![data_type],
+                (Function::Avg, None) if numeric => vec![DataType::Float64, DataType::UInt64],
+                _ => return None,
+            };
+            for data_type in state_types {
+                fields.push(Field::new(
+                    &format!("state_{}", fields.len()),
+                    data_type,
+                    true,
+                ));
+            }
+            aggregates.push(Aggregate {
+                function,
+                column,
+                scale,
+            });
+        }
+
+        Some(Self {
+            group_columns,
+            aggregates,
+            schema: Arc::new(schema.clone()),
+            spill_schema: Arc::new(Schema::new(fields)),
+            memory_limit,
+            groups: HashMap::new(),
+            memory: 0,
+            spill: None,
+            spills: 0,
+        })
+    }
+
+    /// Number of times that the groups were spilled to disk
+    pub fn spills(&self) -> u64 {
+        self.spills
+    }
+
+    /// Aggregate a batch of the input, spilling the groups if they no longer fit in memory
+    pub fn update(&mut self, batch: &RecordBatch) -> Result<()> {
+        let keys = self
+            .group_columns
+            .iter()
+            .map(|column| input_values(batch, Some(*column)))
+            .collect::<Result<Vec<_>>>()?;
+        let inputs = self
+            .aggregates
+            .iter()
+            .map(|aggregate| input_values(batch, aggregate.column))
+            .collect::<Result<Vec<_>>>()?;
+
+        for row in 0..batch.num_rows() {
+            let key: Vec<Option<KeyValue>> = keys.iter().map(|k| k[row].clone()).collect();
+            let size = group_size(&key, self.aggregates.len());
+            let accumulators = match self.groups.entry(key) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    self.memory += size;
+                    entry.insert(
+                        self.aggregates
+                            .iter()
+                            .map(|aggregate| Accumulator::new(aggregate))
+                            .collect(),
+                    )
+                }
+            };
+            for (accumulator, values) in accumulators.iter_mut().zip(&inputs) {
+                accumulator.update(values[row].as_ref())?;
+            }
+        }
+
+        if self.memory > self.memory_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Produce the results of the aggregate, merging the groups that were spilled
+    pub fn finish(mut self) -> Result<Vec<RecordBatch>> {
+        let mut spill = match self.spill.take() {
+            Some(spill) => spill,
+            None => {
+                // aggregates without grouping columns produce a row even without input
+                if self.group_columns.is_empty() && self.groups.is_empty() {
+                    let accumulators = self
+                        .aggregates
+                        .iter()
+                        .map(|aggregate| Accumulator::new(aggregate))
+                        .collect();
+                    self.groups.insert(vec![], accumulators);
+                }
+                let groups = mem::take(&mut self.groups);
+                return self.output(groups);
+            }
+        };
+
+        let groups = mem::take(&mut self.groups);
+        spill.write(&self.spill_batches(groups)?)?;
+        spill.finish()?;
+
+        let mut batches = vec![];
+        for path in &spill.paths {
+            let mut groups = HashMap::new();
+            let mut reader = DictionaryFileReader::try_new(path)?;
+            while let Some(batch) = reader.next_batch()? {
+                self.merge_spilled(&mut groups, &batch)?;
+            }
+            batches.extend(self.output(groups)?);
+        }
+        Ok(batches)
+    }
+
+    /// Write the groups to the spill files, freeing their memory
+    fn spill(&mut self) -> Result<()> {
+        if self.spill.is_none() {
+            self.spill = Some(SpillFiles::try_new(&self.spill_schema)?);
+        }
+        let groups = mem::take(&mut self.groups);
+        let batches = self.spill_batches(groups)?;
+        self.spill.as_mut().unwrap().write(&batches)?;
+        self.memory = 0;
+        self.spills += 1;
+        Ok(())
+    }
+
+    /// Encode the states of the groups as a batch for each spill partition
+    fn spill_batches(&self, groups: Groups) -> Result<Vec<Option<RecordBatch>>> {
+        let mut partitions: Vec<Vec<Vec<Option<KeyValue>>>> = vec![vec![]; SPILL_PARTITIONS];
+        for (mut key, accumulators) in groups {
+            let partition = spill_partition(&key);
+            key.extend(accumulators.iter().flat_map(|a| a.state()));
+            partitions[partition].push(key);
+        }
+        partitions
+            .into_iter()
+            .map(|rows| match rows.len() {
+                0 => Ok(None),
+                _ => to_batch(&rows, &self.spill_schema).map(Some),
+            })
+            .collect()
+    }
+
+    /// Merge a batch of spilled groups into the groups of a spill partition
+    fn merge_spilled(&self, groups: &mut Groups, batch: &RecordBatch) -> Result<()> {
+        let columns = batch
+            .columns()
+            .iter()
+            .map(|column| {
+                column_values(column).ok_or_else(|| ballista_error("Invalid aggregate spill file"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let key_count = self.group_columns.len();
+        for row in 0..batch.num_rows() {
+            let key: Vec<Option<KeyValue>> = columns[..key_count]
+                .iter()
+                .map(|c| c[row].clone())
+                .collect();
+            let mut column = key_count;
+            let mut accumulators = Vec::with_capacity(self.aggregates.len());
+            for aggregate in &self.aggregates {
+                let width = if aggregate.function == Function::Avg {
+                    2
+                } else {
+                    1
+                };
+                let state: Vec<Option<KeyValue>> = columns[column..column + width]
+                    .iter()
+                    .map(|c| c[row].clone())
+                    .collect();
+                accumulators.push(Accumulator::from_state(aggregate, &state)?);
+                column += width;
+            }
+            match groups.entry(key) {
+                Entry::Occupied(mut entry) => {
+                    for (accumulator, other) in entry.get_mut().iter_mut().zip(accumulators) {
+                        accumulator.merge(other)?;
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(accumulators);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Produce the output rows of the groups
+    fn output(&self, groups: Groups) -> Result<Vec<RecordBatch>> {
+        let rows: Vec<Vec<Option<KeyValue>>> = groups
+            .into_iter()
+            .map(|(mut key, accumulators)| {
+                for accumulator in &accumulators {
+                    key.push(accumulator.value()?);
+                }
+                Ok(key)
+            })
+            .collect::<Result<_>>()?;
+        rows.chunks(DEFAULT_BATCH_SIZE)
+            .map(|rows| to_batch(rows, &self.schema))
+            .collect()
+    }
+}
+
+/// The spill files of an aggregate, one for each spill partition, which are removed when
+/// they are dropped
+struct SpillFiles {
+    paths: Vec<PathBuf>,
+    writers: Vec<DictionaryFileWriter>,
+}
+
+impl SpillFiles {
+    fn try_new(schema: &Schema) -> Result<Self> {
+        let id = NEXT_SPILL_ID.fetch_add(1, atomic::Ordering::SeqCst);
+        let mut paths = vec![];
+        let mut writers = vec![];
+        for partition in 0..SPILL_PARTITIONS {
+            let path = std::env::temp_dir().join(format!(
+                "ballista-aggregate-{}-{}-{}.arrow",
+                std::process::id(),
+                id,
+                partition
+            ));
+            writers.push(DictionaryFileWriter::try_new(
+                &path,
+                Arc::new(schema.clone()),
+            )?);
+            paths.push(path);
+        }
+        Ok(Self { paths, writers })
+    }
+
+    /// Append a batch to the file of each partition that has one
+    fn write(&mut self, batches: &[Option<RecordBatch>]) -> Result<()> {
+        for (writer, batch) in self.writers.iter_mut().zip(batches) {
+            if let Some(batch) = batch {
+                writer.write(batch)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        for writer in &mut self.writers {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SpillFiles {
+    fn drop(&mut self) {
+        self.writers.clear();
+        for path in &self.paths {
+            let _ = dictionary::remove_file(path);
+        }
+    }
+}
+
+/// The index of a column expression
+fn column_index(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Column(i) => Some(*i),
+        Expr::Alias(expr, _) => column_index(expr),
+        _ => None,
+    }
+}
+
+/// The type that values of a column are aggregated and spilled as, or `None` if the
+/// column cannot be aggregated
+fn value_type(data_type: &DataType) -> Option<DataType> {
+    match data_type {
+        DataType::Boolean => Some(DataType::Boolean),
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => {
+            Some(DataType::Int64)
+        }
+        DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => {
+            Some(DataType::UInt64)
+        }
+        DataType::Float32 | DataType::Float64 => Some(DataType::Float64),
+        DataType::Utf8 => Some(DataType::Utf8),
+        DataType::Dictionary(_, values) => value_type(values),
+        _ => None,
+    }
+}
+
+/// The values of a column of a batch, or a non-null value for every row for aggregates of
+/// a literal
+fn input_values(batch: &RecordBatch, column: Option<usize>) -> Result<Vec<Option<KeyValue>>> {
+    match column {
+        Some(column) => {
+            let column = batch.column(column);
+            column_values(column).ok_or_else(|| {
+                ballista_error(&format!(
+                    "Cannot aggregate a column of type {:?}",
+                    column.data_type()
+                ))
+            })
+        }
+        None => Ok(vec![Some(KeyValue::Boolean(true)); batch.num_rows()]),
+    }
+}
+
+/// Estimated bytes of memory used by a group
+fn group_size(key: &[Option<KeyValue>], aggregates: usize) -> usize {
+    let strings: usize = key
+        .iter()
+        .map(|value| match value {
+            Some(KeyValue::Utf8(s)) => s.len(),
+            _ => 0,
+        })
+        .sum();
+    GROUP_OVERHEAD
+        + mem::size_of::<Option<KeyValue>>() * key.len()
+        + strings
+        + mem::size_of::<Accumulator>() * aggregates
+}
+
+fn spill_partition(key: &[Option<KeyValue>]) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % SPILL_PARTITIONS
+}
+
+fn add(a: &KeyValue, b: &KeyValue) -> Result<KeyValue> {
+    match (a, b) {
+        (KeyValue::Int(a), KeyValue::Int(b)) => Ok(KeyValue::Int(a.wrapping_add(*b))),
+        (KeyValue::UInt(a), KeyValue::UInt(b)) => Ok(KeyValue::UInt(a.wrapping_add(*b))),
+        (KeyValue::Float(a), KeyValue::Float(b)) => Ok(KeyValue::Float(
+            (f64::from_bits(*a) + f64::from_bits(*b)).to_bits(),
+        )),
+        _ => Err(ballista_error(&format!("Cannot add {:?} to {:?}", b, a))),
+    }
+}
+
+fn as_i64(value: &KeyValue) -> Result<i64> {
+    match value {
+        KeyValue::Int(v) => Ok(*v),
+        KeyValue::UInt(v) => Ok(*v as i64),
+        KeyValue::Float(v) => Ok(f64::from_bits(*v) as i64),
+        other => Err(ballista_error(&format!("{:?} is not a number", other))),
+    }
+}
+
+fn as_u64(value: &KeyValue) -> Result<u64> {
+    match value {
+        KeyValue::Int(v) => Ok(*v as u64),
+        KeyValue::UInt(v) => Ok(*v),
+        KeyValue::Float(v) => Ok(f64::from_bits(*v) as u64),
+        other => Err(ballista_error(&format!("{:?} is not a number", other))),
+    }
+}
+
+fn as_f64(value: &KeyValue) -> Result<f64> {
+    match value {
+        KeyValue::Int(v) => Ok(*v as f64),
+        KeyValue::UInt(v) => Ok(*v as f64),
+        KeyValue::Float(v) => Ok(f64::from_bits(*v)),
+        other => Err(ballista_error(&format!("{:?} is not a number", other))),
+    }
+}
+
+/// Build a batch from rows of values, converting the values to the types of the schema
+fn to_batch(rows: &[Vec<Option<KeyValue>>], schema: &SchemaRef) -> Result<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let values: Vec<Option<&KeyValue>> = rows.iter().map(|row| row[i].as_ref()).collect();
+            to_array(&values, field.data_type())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+pub(crate) fn to_array(values: &[Option<&KeyValue>], data_type: &DataType) -> Result<ArrayRef> {
+    if let DataType::Dictionary(key_type, value_type) = data_type {
+        // the groups of dictionary columns are encoded again rather than decoded
+        return dictionary::encode(&to_array(values, value_type)?, key_type);
+    }
+    let array: ArrayRef = match value_type(data_type) {
+        Some(DataType::Boolean) => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    None => Ok(None),
+                    Some(KeyValue::Boolean(b)) => Ok(Some(*b)),
+                    Some(other) => Err(ballista_error(&format!("{:?} is not a boolean", other))),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Arc::new(BooleanArray::from(values))
+        }
+        Some(DataType::Int64) => Arc::new(Int64Array::from(numbers(values, as_i64)?)),
+        Some(DataType::UInt64) => Arc::new(UInt64Array::from(numbers(values, as_u64)?)),
+        Some(DataType::Float64) => Arc::new(Float64Array::from(numbers(values, as_f64)?)),
+        Some(DataType::Utf8) => {
+            let mut builder = StringBuilder::new(values.len());
+            for value in values {
+                match value {
+                    None => builder.append_null()?,
+                    Some(KeyValue::Utf8(s)) => builder.append_value(s)?,
+                    Some(other) => {
+                        return Err(ballista_error(&format!("{:?} is not a string", other)))
+                    }
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        _ => {
+            return Err(ballista_error(&format!(
+                "Cannot aggregate into a column of type {:?}",
+                data_type
+            )))
+        }
+    };
+    Ok(cast(&array, data_type)?)
+}
+
+fn numbers<T>(
+    values: &[Option<&KeyValue>],
+    f: fn(&KeyValue) -> Result<T>,
+) -> Result<Vec<Option<T>>> {
+    values
+        .iter()
+        .map(|value| value.map(f).transpose())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logicalplan::{aggregate_expr, ScalarValue};
+
+    #[test]
+    fn spill_and_merge_groups() -> Result<()> {
+        let input_schema = Schema::new(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Int32, true),
+        ]);
+        let schema = Schema::new(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("SUM(value)", DataType::Int64, true),
+            Field::new("COUNT(1)", DataType::UInt64, true),
+            Field::new("AVG(value)", DataType::Float64, true),
+            Field::new("MAX(value)", DataType::Int32, true),
+        ]);
+        let aggr_expr = vec![
+            aggregate_expr("SUM", Expr::Column(1), DataType::Int64),
+            aggregate_expr(
+                "COUNT",
+                Expr::Literal(ScalarValue::UInt8(1)),
+                DataType::UInt64,
+            ),
+            aggregate_expr("AVG", Expr::Column(1), DataType::Float64),
+            aggregate_expr("MAX", Expr::Column(1), DataType::Int32),
+        ];
+        // every batch exceeds the limit, so the groups are spilled after each batch
+        let mut aggregate =
+            HashAggregate::try_new(&[Expr::Column(0)], &aggr_expr, &input_schema, &schema, 1)
+                .unwrap();
+        for values in &[
+            vec![Some(1), Some(2), None],
+            vec![Some(3), Some(4), Some(5)],
+        ] {
+            let batch = RecordBatch::try_new(
+                Arc::new(input_schema.clone()),
+                vec![
+                    Arc::new(StringArray::from(vec!["a", "b", "a"])),
+                    Arc::new(Int32Array::from(values.clone())),
+                ],
+            )?;
+            aggregate.update(&batch)?;
+        }
+        assert_eq!(2, aggregate.spills());
+
+        let mut rows = vec![];
+        for batch in aggregate.finish()? {
+            let keys = batch.column(0).as_any().downcast_ref::<StringArray>();
+            let sums = batch.column(1).as_any().downcast_ref::<Int64Array>();
+            let counts = batch.column(2).as_any().downcast_ref::<UInt64Array>();
+            let averages = batch.column(3).as_any().downcast_ref::<Float64Array>();
+            let maxima = batch.column(4).as_any().downcast_ref::<Int32Array>();
+            for i in 0..batch.num_rows() {
+                rows.push((
+                    keys.unwrap().value(i).to_owned(),
+                    sums.unwrap().value(i),
+                    counts.unwrap().value(i),
+                    averages.unwrap().value(i),
+                    maxima.unwrap().value(i),
+                ));
+            }
+        }
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            vec![
+                ("a".to_owned(), 9, 4, 3.0, 5),
+                ("b".to_owned(), 6, 2, 3.0, 4)
+            ],
+            rows
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn aggregate_decimals_exactly() -> Result<()> {
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert(
+            format!(
+                "{}price",
+                crate::datasource::parquet::DECIMAL_METADATA_PREFIX
+            ),
+            "20,2".to_owned(),
+        );
+        let input_schema =
+            Schema::new_with_metadata(vec![Field::new("price", DataType::Utf8, true)], metadata);
+        let schema = Schema::new(vec![
+            Field::new("SUM(price)", DataType::Utf8, true),
+            Field::new("AVG(price)", DataType::Utf8, true),
+            Field::new("MAX(price)", DataType::Utf8, true),
+        ]);
+        let aggr_expr = vec![
+            aggregate_expr("SUM", Expr::Column(0), DataType::Utf8),
+            aggregate_expr("AVG", Expr::Column(0), DataType::Utf8),
+            aggregate_expr("MAX", Expr::Column(0), DataType::Utf8),
+        ];
+        // the states are spilled after each batch and merged again
+        let mut aggregate =
+            HashAggregate::try_new(&[], &aggr_expr, &input_schema, &schema, 1).unwrap();
+        for values in &[
+            vec![Some("12345678901234567.89"), Some("0.01")],
+            vec![Some("9.00"), None, Some("10.00")],
+        ] {
+            let batch = RecordBatch::try_new(
+                Arc::new(input_schema.clone()),
+                vec![Arc::new(StringArray::from(values.clone()))],
+            )?;
+            aggregate.update(&batch)?;
+        }
+        let batches = aggregate.finish()?;
+        let value = |column: usize| {
+            let array = batches[0].column(column);
+            let text = array.as_any().downcast_ref::<StringArray>().unwrap();
+            text.value(0).to_owned()
+        };
+        assert_eq!("12345678901234586.90", value(0));
+        assert_eq!("3086419725308646.725000", value(1));
+        // decimals are compared by value rather than as text
+        assert_eq!("12345678901234567.89", value(2));
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! Authentication of requests to executors.
+//!
+//! Clients send their credentials in the `authorization` metadata of every request, either
+//! as a bearer token or using basic authentication. An executor that has been configured
+//! with credentials rejects requests that do not match one of them. TLS should be used
+//! when credentials are sent between hosts.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::{ballista_error, Result};
+
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Status};
+
+pub const AUTH_TOKEN: &str = "ballista.auth.token";
+pub const AUTH_USERNAME: &str = "ballista.auth.username";
+pub const AUTH_PASSWORD: &str = "ballista.auth.password";
+
+const AUTHORIZATION: &str = "authorization";
+
+/// Credentials used to authenticate with an executor
+#[derive(Clone, PartialEq)]
+pub enum Credentials {
+    Token(String),
+    UsernamePassword { username: String, password: String },
+}
+
+impl Credentials {
+    /// Read the credentials from the Context settings, returning `None` if authentication
+    /// is not configured
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Option<Self>> {
+        match (
+            settings.get(AUTH_TOKEN),
+            settings.get(AUTH_USERNAME),
+            settings.get(AUTH_PASSWORD),
+        ) {
+            (Some(token), None, None) => Ok(Some(Credentials::Token(token.clone()))),
+            (None, Some(username), Some(password)) => Ok(Some(Credentials::UsernamePassword {
+                username: username.clone(),
+                password: password.clone(),
+            })),
+            (None, None, None) => Ok(None),
+            _ => Err(ballista_error(&format!(
+                "Either {} or both {} and {} must be set",
+                AUTH_TOKEN, AUTH_USERNAME, AUTH_PASSWORD
+            ))),
+        }
+    }
+
+    /// The value of the `authorization` metadata for these credentials
+    fn authorization(&self) -> String {
+        match self {
+            Credentials::Token(token) => format!("Bearer {}", token),
+            Credentials::UsernamePassword { username, password } => format!(
+                "Basic {}",
+                base64::encode(format!("{}:{}", username, password).as_bytes())
+            ),
+        }
+    }
+
+    /// Add the credentials to a request
+    pub fn authorize<T>(&self, request: &mut Request<T>) -> Result<()> {
+        let value = self
+            .authorization()
+            .parse()
+            .map_err(|_| ballista_error("Credentials contain invalid characters"))?;
+        request.metadata_mut().insert(AUTHORIZATION, value);
+        Ok(())
+    }
+}
+
+/// The secrets are not included in debug output
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Credentials::Token(_) => write!(f, "Token(..)"),
+            Credentials::UsernamePassword { username, .. } => f
+                .debug_struct("UsernamePassword")
+                .field("username", username)
+                .finish(),
+        }
+    }
+}
+
+/// Check the credentials sent with a request. All requests are accepted if no credentials
+/// have been configured.
+pub fn authenticate(
+    metadata: &MetadataMap,
+    accepted: &[Credentials],
+) -> std::result::Result<(), Status> {
+    if accepted.is_empty() {
+        return Ok(());
+    }
+    let value = metadata
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| Status::unauthenticated("Missing credentials"))?;
+    if accepted
+        .iter()
+        .any(|c| constant_time_eq(c.authorization().as_bytes(), value.as_bytes()))
+    {
+        Ok(())
+    } else {
+        Err(Status::unauthenticated("Invalid credentials"))
+    }
+}
+
+/// Compare secrets without returning early, so that the time taken does not reveal how
+/// much of the secret was correct
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authenticate_requests() -> Result<()> {
+        let mut settings = HashMap::new();
+        settings.insert(AUTH_USERNAME.to_owned(), "alice".to_owned());
+        settings.insert(AUTH_PASSWORD.to_owned(), "secret".to_owned());
+        let credentials = Credentials::from_settings(&settings)?.unwrap();
+        assert_eq!("Basic YWxpY2U6c2VjcmV0", credentials.authorization());
+        assert!(!format!("{:?}", credentials).contains("secret"));
+
+        let mut request = Request::new(());
+        assert!(authenticate(request.metadata(), &[credentials.clone()]).is_err());
+        assert!(authenticate(request.metadata(), &[]).is_ok());
+        credentials.authorize(&mut request)?;
+        assert!(authenticate(request.metadata(), &[credentials]).is_ok());
+        assert!(authenticate(request.metadata(), &[Credentials::Token("t".to_owned())]).is_err());
+
+        settings.insert(AUTH_TOKEN.to_owned(), "t".to_owned());
+        assert!(Credentials::from_settings(&settings).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Run SQL queries interactively on a local context, or on a cluster.
+//!
+//! ```text
+//! ballista-cli [--host HOST --port PORT] [--file PATH]
+//! ```
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+use ballista::cli::Shell;
+use ballista::dataframe::Context;
+use ballista::BALLISTA_VERSION;
+
+const USAGE: &str = "Usage:
+  ballista-cli [--host HOST --port PORT] [--file PATH]";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    // queries run on a cluster when a scheduler or executor is given
+    let ctx = match (option("--host"), option("--port")) {
+        (Some(host), Some(port)) => Context::remote(&host, port.parse()?, HashMap::new()),
+        _ => Context::local(HashMap::new()),
+    };
+    // statements are read from a file instead of the terminal when one is given
+    let (input, interactive): (Box<dyn BufRead>, bool) = match option("--file") {
+        Some(path) => (Box::new(BufReader::new(File::open(path)?)), false),
+        None => (Box::new(BufReader::new(io::stdin())), true),
+    };
+
+    if interactive {
+        println!("Ballista v{} CLI, enter \\? for help", BALLISTA_VERSION);
+    }
+    let mut shell = Shell::new(ctx);
+    let mut lines = input.lines();
+    loop {
+        if interactive {
+            print!(
+                "{}",
+                if shell.is_continuation() {
+                    "     -> "
+                } else {
+                    "ballista> "
+                }
+            );
+            io::stdout().flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        match shell.read_line(&line).await {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) if interactive => eprintln!("Error: {}", e),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
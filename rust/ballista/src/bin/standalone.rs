@@ -0,0 +1,133 @@
+//! Run the scheduler or an executor of a standalone cluster.
+//!
+//! ```text
+//! standalone scheduler [--port PORT] [--membership-port PORT]
+//!     [--queues NAME:WEIGHT[:MAX_RUNNING_TASKS],...] [--max-tasks-per-executor N]
+//!     [--history-file PATH]
+//! standalone executor --scheduler HOST:PORT [--host HOST] [--port PORT] [--capacity N]
+//!     [--shuffle-dirs DIR,DIR...] [--memory-limit BYTES] [--http-port PORT]
+//!     [--history-file PATH] [--result-cache-bytes BYTES] [--result-cache-ttl-ms MS]
+//! ```
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ballista::client::ClientConfig;
+use ballista::discovery::ExecutorRegistration;
+use ballista::executor::{Server, DEFAULT_PORT};
+use ballista::history::QueryHistory;
+use ballista::scheduler::queues::QueueConfig;
+use ballista::scheduler::{Scheduler, SchedulerConfig};
+use ballista::standalone::{self, DEFAULT_MEMBERSHIP_PORT};
+use ballista::BALLISTA_VERSION;
+
+const USAGE: &str = "Usage:
+  standalone scheduler [--port PORT] [--membership-port PORT]
+      [--queues NAME:WEIGHT[:MAX_RUNNING_TASKS],...] [--max-tasks-per-executor N]
+      [--history-file PATH]
+  standalone executor --scheduler HOST:PORT [--host HOST] [--port PORT] [--capacity N]
+      [--shuffle-dirs DIR,DIR...] [--memory-limit BYTES] [--http-port PORT]
+      [--history-file PATH] [--result-cache-bytes BYTES] [--result-cache-ttl-ms MS]";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // log messages are filtered with RUST_LOG, and informational messages are shown by default
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let role = args.first().map(|s| s.as_str());
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    let port = option("--port")
+        .map(|p| p.parse::<u16>())
+        .transpose()?
+        .unwrap_or(DEFAULT_PORT);
+    // the queries that have run are kept in memory, and also in a file when one is given
+    let history = match option("--history-file") {
+        Some(path) => QueryHistory::default().with_file(Path::new(&path))?,
+        None => QueryHistory::default(),
+    };
+
+    match role {
+        Some("scheduler") => {
+            let membership_port = option("--membership-port")
+                .map(|p| p.parse::<u16>())
+                .transpose()?
+                .unwrap_or(DEFAULT_MEMBERSHIP_PORT);
+            let mut scheduler_config = SchedulerConfig::default();
+            if let Some(n) = option("--max-tasks-per-executor") {
+                scheduler_config.max_tasks_per_executor = Some(n.parse()?);
+            }
+            let queues = match option("--queues") {
+                Some(queues) => queues
+                    .split(',')
+                    .map(QueueConfig::parse)
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => vec![],
+            };
+            let scheduler = Scheduler::new(ClientConfig::default())
+                .with_scheduler_config(scheduler_config)
+                .with_queues(queues);
+            let addr = format!("0.0.0.0:{}", port).parse()?;
+            let membership_addr = format!("0.0.0.0:{}", membership_port).parse()?;
+            println!(
+                "Ballista v{} Rust Scheduler listening on {:?}, accepting executors on {:?}",
+                BALLISTA_VERSION, addr, membership_addr
+            );
+            standalone::run_scheduler(addr, membership_addr, scheduler, history).await?;
+        }
+        Some("executor") => {
+            let scheduler = option("--scheduler").ok_or(USAGE)?;
+            // the scheduler uses the address that the executor connects from by default
+            let host = option("--host").unwrap_or_default();
+            let mut registration = ExecutorRegistration::new(&host, port as usize);
+            if host.is_empty() {
+                registration.id = format!("{}:{}", hostname(), port);
+            }
+            let addr = format!("0.0.0.0:{}", port).parse()?;
+            let mut server = Server::new(addr).with_query_history(history);
+            // the executor runs as many tasks at the same time as it registers as its
+            // capacity, which is one for each CPU by default
+            if let Some(capacity) = option("--capacity") {
+                server = server.with_task_slots(capacity.parse()?);
+            }
+            registration.capacity = server.task_slots();
+            if let Some(dirs) = option("--shuffle-dirs") {
+                server = server.with_shuffle_dirs(dirs.split(',').map(PathBuf::from).collect());
+            }
+            if let Some(limit) = option("--memory-limit") {
+                server = server.with_memory_limit(limit.parse()?);
+            }
+            if let Some(bytes) = option("--result-cache-bytes") {
+                let ttl = option("--result-cache-ttl-ms")
+                    .map(|ms| ms.parse().map(Duration::from_millis))
+                    .transpose()?;
+                server = server.with_result_cache(bytes.parse()?, ttl);
+            }
+            if let Some(http_port) = option("--http-port") {
+                let http_addr = format!("0.0.0.0:{}", http_port.parse::<u16>()?).parse()?;
+                server = server.with_http_addr(http_addr);
+            }
+            println!(
+                "Ballista v{} Rust Executor listening on {:?}, joining scheduler {}",
+                BALLISTA_VERSION, addr, scheduler
+            );
+            standalone::run_executor(server, &scheduler, registration).await?;
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+fn hostname() -> String {
+    env::var("HOSTNAME").unwrap_or_else(|_| "executor".to_owned())
+}
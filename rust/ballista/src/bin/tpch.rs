@@ -0,0 +1,107 @@
+//! Run TPC-H queries on a local context, or on a cluster, and report how long they take.
+//!
+//! ```text
+//! tpch benchmark --path DIR [--format tbl|parquet] [--query N,...] [--iterations N]
+//!     [--partitions N] [--host HOST --port PORT]
+//! tpch convert --path DIR --output DIR
+//! ```
+
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+use ballista::dataframe::Context;
+use ballista::tpch::{convert_to_parquet, TpchFormat, TpchTables, TPCH_QUERIES};
+use ballista::BALLISTA_VERSION;
+
+const USAGE: &str = "Usage:
+  tpch benchmark --path DIR [--format tbl|parquet] [--query N,...] [--iterations N]
+      [--partitions N] [--host HOST --port PORT]
+  tpch convert --path DIR --output DIR";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = args.first().map(|s| s.as_str());
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    let path = match option("--path") {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    // queries run on a cluster when a scheduler or executor is given
+    let ctx = match (option("--host"), option("--port")) {
+        (Some(host), Some(port)) => Context::remote(&host, port.parse()?, HashMap::new()),
+        _ => Context::local(HashMap::new()),
+    };
+
+    match command {
+        Some("benchmark") => {
+            let format =
+                TpchFormat::from_name(&option("--format").unwrap_or_else(|| "tbl".into()))?;
+            let queries = match option("--query") {
+                Some(queries) => queries
+                    .split(',')
+                    .map(|q| q.parse::<usize>())
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => TPCH_QUERIES.to_vec(),
+            };
+            let iterations = option("--iterations")
+                .map(|n| n.parse::<usize>())
+                .transpose()?
+                .unwrap_or(3);
+            let mut tables = TpchTables::new(ctx, &path, format);
+            if let Some(partitions) = option("--partitions") {
+                tables = tables.with_partitions(partitions.parse()?);
+            }
+
+            println!("Ballista v{} TPC-H benchmark of {}", BALLISTA_VERSION, path);
+            for query in queries {
+                let timings = tables.run(query, iterations).await?;
+                for timing in &timings {
+                    println!(
+                        "Query {} iteration {} took {} ms and returned {} rows",
+                        timing.query,
+                        timing.iteration,
+                        timing.elapsed.as_millis(),
+                        timing.rows
+                    );
+                }
+                let total: Duration = timings.iter().map(|t| t.elapsed).sum();
+                println!(
+                    "Query {} took {} ms on average",
+                    query,
+                    total.as_millis() / timings.len().max(1) as u128
+                );
+            }
+        }
+        Some("convert") => {
+            let output = match option("--output") {
+                Some(output) => output,
+                None => {
+                    eprintln!("{}", USAGE);
+                    std::process::exit(1);
+                }
+            };
+            convert_to_parquet(ctx, &path, &output).await?;
+            println!(
+                "Converted the TPC-H tables of {} to Parquet in {}",
+                path, output
+            );
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,387 @@
+//! Export of results through the Arrow C Data Interface.
+//!
+//! The [C Data Interface](https://arrow.apache.org/docs/format/CDataInterface.html) lets
+//! processes that embed Ballista, such as C and C++ programs or JVMs through JNI, consume
+//! collected results without copying them. A batch is exported as an `ArrowArray` of a
+//! struct with a child for each column, and its schema as an `ArrowSchema` of the same
+//! struct type. The exported arrays point into the buffers of the batch, which are kept
+//! alive until the consumer calls the `release` callback of the array.
+//!
+//! Structs that are dropped in Rust before they are moved to a consumer release themselves.
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+use crate::arrow::array::ArrayDataRef;
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, BallistaError, Result};
+
+/// Flag of the `ArrowSchema` of a field that may contain nulls
+pub const ARROW_FLAG_NULLABLE: i64 = 2;
+
+/// The `ArrowSchema` struct of the C Data Interface
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowSchema {
+    pub format: *const c_char,
+    pub name: *const c_char,
+    pub metadata: *const c_char,
+    pub flags: i64,
+    pub n_children: i64,
+    pub children: *mut *mut ArrowSchema,
+    pub dictionary: *mut ArrowSchema,
+    pub release: Option<unsafe extern "C" fn(schema: *mut ArrowSchema)>,
+    pub private_data: *mut c_void,
+}
+
+/// The `ArrowArray` struct of the C Data Interface
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowArray {
+    pub length: i64,
+    pub null_count: i64,
+    pub offset: i64,
+    pub n_buffers: i64,
+    pub n_children: i64,
+    pub buffers: *mut *const c_void,
+    pub children: *mut *mut ArrowArray,
+    pub dictionary: *mut ArrowArray,
+    pub release: Option<unsafe extern "C" fn(array: *mut ArrowArray)>,
+    pub private_data: *mut c_void,
+}
+
+/// What an exported schema owns, which is freed when it is released
+struct SchemaPrivateData {
+    _format: CString,
+    _name: CString,
+    children: Vec<*mut ArrowSchema>,
+    dictionary: *mut ArrowSchema,
+}
+
+/// What an exported array owns, which is freed when it is released. The array data keeps
+/// the buffers that the array points into alive.
+struct ArrayPrivateData {
+    _data: Option<ArrayDataRef>,
+    _buffers: Vec<*const c_void>,
+    children: Vec<*mut ArrowArray>,
+    dictionary: *mut ArrowArray,
+}
+
+/// Export a schema as the `ArrowSchema` of a struct with a child for each field
+pub fn export_schema(schema: &Schema) -> Result<ArrowSchema> {
+    let children = schema
+        .fields()
+        .iter()
+        .map(export_field)
+        .collect::<Result<Vec<_>>>()?;
+    new_schema("+s".to_owned(), "", false, children, None)
+}
+
+/// Export a batch as the `ArrowArray` of a struct with a child for each column, without
+/// copying the buffers of the columns
+pub fn export_batch(batch: &RecordBatch) -> Result<ArrowArray> {
+    let children = batch
+        .columns()
+        .iter()
+        .map(|column| export_array_data(&column.data()))
+        .collect::<Vec<_>>();
+    Ok(new_array(
+        None,
+        batch.num_rows(),
+        0,
+        0,
+        vec![ptr::null()],
+        children,
+        None,
+    ))
+}
+
+/// Export a batch and its schema into structs that the consumer allocated, which then owns
+/// them and must call their `release` callbacks
+///
+/// # Safety
+///
+/// The pointers must be valid for writes of the structs, and any structs that they point to
+/// are overwritten without being released.
+pub unsafe fn export_batch_into(
+    batch: &RecordBatch,
+    array: *mut ArrowArray,
+    schema: *mut ArrowSchema,
+) -> Result<()> {
+    if array.is_null() || schema.is_null() {
+        return Err(ballista_error("Cannot export to null pointers"));
+    }
+    let exported_schema = export_schema(&batch.schema())?;
+    let exported_array = export_batch(batch)?;
+    ptr::write(schema, exported_schema);
+    ptr::write(array, exported_array);
+    Ok(())
+}
+
+/// The format string of a data type, as the C Data Interface defines them. Dictionary types
+/// have the format of their keys.
+fn format(data_type: &DataType) -> Result<String> {
+    let time_unit = |unit: &TimeUnit| match unit {
+        TimeUnit::Second => "s",
+        TimeUnit::Millisecond => "m",
+        TimeUnit::Microsecond => "u",
+        TimeUnit::Nanosecond => "n",
+    };
+    Ok(match data_type {
+        DataType::Boolean => "b".to_owned(),
+        DataType::Int8 => "c".to_owned(),
+        DataType::UInt8 => "C".to_owned(),
+        DataType::Int16 => "s".to_owned(),
+        DataType::UInt16 => "S".to_owned(),
+        DataType::Int32 => "i".to_owned(),
+        DataType::UInt32 => "I".to_owned(),
+        DataType::Int64 => "l".to_owned(),
+        DataType::UInt64 => "L".to_owned(),
+        DataType::Float16 => "e".to_owned(),
+        DataType::Float32 => "f".to_owned(),
+        DataType::Float64 => "g".to_owned(),
+        DataType::Binary => "z".to_owned(),
+        DataType::Utf8 => "u".to_owned(),
+        DataType::FixedSizeBinary(size) => format!("w:{}", size),
+        DataType::Date32(DateUnit::Day) => "tdD".to_owned(),
+        DataType::Date64(DateUnit::Millisecond) => "tdm".to_owned(),
+        DataType::Time32(unit) | DataType::Time64(unit) => format!("tt{}", time_unit(unit)),
+        DataType::Timestamp(unit, tz) => format!(
+            "ts{}:{}",
+            time_unit(unit),
+            tz.as_ref().map(|tz| tz.to_string()).unwrap_or_default()
+        ),
+        DataType::List(_) => "+l".to_owned(),
+        DataType::Struct(_) => "+s".to_owned(),
+        DataType::Dictionary(key_type, _) => format(key_type)?,
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Cannot export {:?} through the C Data Interface",
+                other
+            )))
+        }
+    })
+}
+
+fn export_field(field: &Field) -> Result<ArrowSchema> {
+    export_type(field.name(), field.data_type(), field.is_nullable())
+}
+
+fn export_type(name: &str, data_type: &DataType, nullable: bool) -> Result<ArrowSchema> {
+    let (children, dictionary) = match data_type {
+        DataType::List(item) => (vec![export_type("item", item, true)?], None),
+        DataType::Struct(fields) => (
+            fields
+                .iter()
+                .map(export_field)
+                .collect::<Result<Vec<_>>>()?,
+            None,
+        ),
+        DataType::Dictionary(_, value_type) => (vec![], Some(export_type("", value_type, true)?)),
+        _ => (vec![], None),
+    };
+    new_schema(format(data_type)?, name, nullable, children, dictionary)
+}
+
+/// Export array data without copying its buffers. The values of dictionary arrays are the
+/// child data of the keys, which are exported as the dictionary of the array.
+fn export_array_data(data: &ArrayDataRef) -> ArrowArray {
+    let mut buffers = vec![data
+        .null_buffer()
+        .map(|buffer| buffer.raw_data() as *const c_void)
+        .unwrap_or_else(ptr::null)];
+    buffers.extend(
+        data.buffers()
+            .iter()
+            .map(|buffer| buffer.raw_data() as *const c_void),
+    );
+    let (children, dictionary) = match data.data_type() {
+        DataType::Dictionary(_, _) => (vec![], Some(export_array_data(&data.child_data()[0]))),
+        _ => (
+            data.child_data().iter().map(export_array_data).collect(),
+            None,
+        ),
+    };
+    new_array(
+        Some(data.clone()),
+        data.len(),
+        data.null_count(),
+        data.offset(),
+        buffers,
+        children,
+        dictionary,
+    )
+}
+
+fn new_schema(
+    format: String,
+    name: &str,
+    nullable: bool,
+    children: Vec<ArrowSchema>,
+    dictionary: Option<ArrowSchema>,
+) -> Result<ArrowSchema> {
+    let format = CString::new(format).map_err(|e| ballista_error(&format!("{:?}", e)))?;
+    let name = CString::new(name).map_err(|e| ballista_error(&format!("{:?}", e)))?;
+    let mut private_data = Box::new(SchemaPrivateData {
+        _format: format,
+        _name: name,
+        children: children
+            .into_iter()
+            .map(|child| Box::into_raw(Box::new(child)))
+            .collect(),
+        dictionary: dictionary
+            .map(|dictionary| Box::into_raw(Box::new(dictionary)))
+            .unwrap_or_else(ptr::null_mut),
+    });
+    Ok(ArrowSchema {
+        format: private_data._format.as_ptr(),
+        name: private_data._name.as_ptr(),
+        metadata: ptr::null(),
+        flags: if nullable { ARROW_FLAG_NULLABLE } else { 0 },
+        n_children: private_data.children.len() as i64,
+        children: private_data.children.as_mut_ptr(),
+        dictionary: private_data.dictionary,
+        release: Some(release_schema),
+        private_data: Box::into_raw(private_data) as *mut c_void,
+    })
+}
+
+fn new_array(
+    data: Option<ArrayDataRef>,
+    length: usize,
+    null_count: usize,
+    offset: usize,
+    buffers: Vec<*const c_void>,
+    children: Vec<ArrowArray>,
+    dictionary: Option<ArrowArray>,
+) -> ArrowArray {
+    let mut private_data = Box::new(ArrayPrivateData {
+        _data: data,
+        _buffers: buffers,
+        children: children
+            .into_iter()
+            .map(|child| Box::into_raw(Box::new(child)))
+            .collect(),
+        dictionary: dictionary
+            .map(|dictionary| Box::into_raw(Box::new(dictionary)))
+            .unwrap_or_else(ptr::null_mut),
+    });
+    ArrowArray {
+        length: length as i64,
+        null_count: null_count as i64,
+        offset: offset as i64,
+        n_buffers: private_data._buffers.len() as i64,
+        n_children: private_data.children.len() as i64,
+        buffers: private_data._buffers.as_mut_ptr(),
+        children: private_data.children.as_mut_ptr(),
+        dictionary: private_data.dictionary,
+        release: Some(release_array),
+        private_data: Box::into_raw(private_data) as *mut c_void,
+    }
+}
+
+/// Release a schema and the children and dictionary that have not been moved out of it.
+/// The children were allocated when the schema was exported, so dropping them releases them.
+unsafe extern "C" fn release_schema(schema: *mut ArrowSchema) {
+    if schema.is_null() || (*schema).release.is_none() {
+        return;
+    }
+    let private_data = Box::from_raw((*schema).private_data as *mut SchemaPrivateData);
+    for child in &private_data.children {
+        drop(Box::from_raw(*child));
+    }
+    if !private_data.dictionary.is_null() {
+        drop(Box::from_raw(private_data.dictionary));
+    }
+    (*schema).release = None;
+}
+
+/// Release an array and the children and dictionary that have not been moved out of it
+unsafe extern "C" fn release_array(array: *mut ArrowArray) {
+    if array.is_null() || (*array).release.is_none() {
+        return;
+    }
+    let private_data = Box::from_raw((*array).private_data as *mut ArrayPrivateData);
+    for child in &private_data.children {
+        drop(Box::from_raw(*child));
+    }
+    if !private_data.dictionary.is_null() {
+        drop(Box::from_raw(private_data.dictionary));
+    }
+    (*array).release = None;
+}
+
+impl Drop for ArrowSchema {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self) }
+        }
+    }
+}
+
+impl Drop for ArrowArray {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{Int32Array, StringArray};
+    use std::ffi::CStr;
+    use std::mem::MaybeUninit;
+    use std::sync::Arc;
+
+    #[test]
+    fn export_batch_and_schema() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, true),
+            Field::new("name", DataType::Utf8, false),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Int32Array::from(vec![Some(1), None, Some(3)])),
+                Arc::new(StringArray::from(vec!["a", "b", "c"])),
+            ],
+        )?;
+
+        let mut array = MaybeUninit::<ArrowArray>::uninit();
+        let mut schema = MaybeUninit::<ArrowSchema>::uninit();
+        unsafe {
+            export_batch_into(&batch, array.as_mut_ptr(), schema.as_mut_ptr())?;
+            let array = &mut *array.as_mut_ptr();
+            let schema = &mut *schema.as_mut_ptr();
+
+            assert_eq!("+s", CStr::from_ptr(schema.format).to_str().unwrap());
+            assert_eq!(2, schema.n_children);
+            let name = &**schema.children.offset(1);
+            assert_eq!("name", CStr::from_ptr(name.name).to_str().unwrap());
+            assert_eq!("u", CStr::from_ptr(name.format).to_str().unwrap());
+            assert_eq!(0, name.flags);
+
+            assert_eq!(3, array.length);
+            assert_eq!(2, array.n_children);
+            let ids = &**array.children;
+            assert_eq!(1, ids.null_count);
+            assert_eq!(2, ids.n_buffers);
+            let values = *ids.buffers.offset(1) as *const i32;
+            assert_eq!(3, *values.offset(2));
+            let names = &**array.children.offset(1);
+            assert_eq!(3, names.n_buffers);
+            assert!((*names.buffers).is_null());
+
+            // consumers release the structs that they own
+            (array.release.unwrap())(array);
+            (schema.release.unwrap())(schema);
+            assert!(array.release.is_none());
+            assert!(schema.release.is_none());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+//! Cancellation of in-flight queries.
+//!
+//! A `CancellationToken` is passed to `DataFrame::collect_with_cancellation` and can be
+//! cancelled from another thread or task. Local queries check the token between batches,
+//! and remote queries ask the executor to stop executing the query.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::{BallistaError, Result};
+
+/// How often `cancelled()` checks whether the token has been cancelled
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Token used to cancel a query. Clones share the same state, so cancelling any clone
+/// cancels the query.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the query
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Return an error if the query has been cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(BallistaError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wait until the query is cancelled
+    pub async fn cancelled(&self) {
+        while !self.is_cancelled() {
+            tokio::time::delay_for(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_clone() {
+        let token = CancellationToken::new();
+        assert!(token.check().is_ok());
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        assert!(token.check().is_err());
+    }
+}
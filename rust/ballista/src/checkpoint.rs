@@ -0,0 +1,277 @@
+//! Checkpoints of the state of windowed streaming queries.
+//!
+//! A windowed query holds the rows of its open windows in memory, so the query would have to
+//! read its sources from the beginning to rebuild them after a restart. With
+//! `ballista.execution.streaming.checkpointDir` set, the query instead saves its open windows
+//! and the offsets that its sources had reached to the directory at the interval set with
+//! `ballista.execution.streaming.checkpointInterval`, after the windows that closed in a
+//! micro-batch have been produced. A query that is started with the same directory restores
+//! the windows, and its sources resume from the saved offsets, so the results of windows that
+//! closed after the last checkpoint may be produced again.
+//!
+//! Each checkpoint is a subdirectory with a manifest and an Arrow IPC file of the rows of
+//! each window. The name of the latest checkpoint is written to a `CURRENT` file, which is
+//! replaced atomically once the checkpoint has been written, and older checkpoints are then
+//! removed. Checkpoints are written to the local file system, so the directory should be on
+//! a volume that outlives the process.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::arrow::record_batch::RecordBatchReader;
+use crate::dictionary::{DictionaryFileReader, DictionaryFileWriter};
+use crate::error::{BallistaError, Result};
+use crate::streaming::SourceOffset;
+use crate::window::{SavedWindow, WindowAssigner, WindowSpec, WindowState};
+
+use serde::{Deserialize, Serialize};
+
+/// Setting with the directory that windowed streaming queries save their checkpoints to
+pub const STREAMING_CHECKPOINT_DIR: &str = "ballista.execution.streaming.checkpointDir";
+
+/// Setting with the interval between checkpoints, such as `30s` or `5m`
+pub const STREAMING_CHECKPOINT_INTERVAL: &str = "ballista.execution.streaming.checkpointInterval";
+
+/// Name of the file with the name of the latest checkpoint
+const CURRENT: &str = "CURRENT";
+
+/// The contents of a checkpoint other than the rows of its windows
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    /// The window of the query, which must match the window of the query that restores it
+    window: WindowSpec,
+    max_time: Option<i64>,
+    watermark: Option<i64>,
+    offsets: Vec<SourceOffset>,
+    windows: Vec<ManifestWindow>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestWindow {
+    start: i64,
+    end: i64,
+    /// Name of the file with the rows of the window, if it has any
+    file: Option<String>,
+}
+
+/// Saves the state of a windowed query to a checkpoint directory at an interval
+pub struct Checkpointer {
+    dir: PathBuf,
+    interval: Duration,
+    window: WindowSpec,
+    last_saved: Instant,
+    /// The offsets that the sources have reached, by source and partition
+    offsets: HashMap<(String, i32), i64>,
+    next_checkpoint: u64,
+}
+
+impl Checkpointer {
+    pub fn new(dir: &str, interval: Duration, window: WindowSpec) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            interval,
+            window,
+            last_saved: Instant::now(),
+            offsets: HashMap::new(),
+            next_checkpoint: 0,
+        }
+    }
+
+    /// Restore the open windows of the latest checkpoint, if there is one, returning the
+    /// offsets that the sources resume from
+    pub fn restore(&mut self, assigner: &mut WindowAssigner) -> Result<Vec<SourceOffset>> {
+        let name = match fs::read_to_string(self.dir.join(CURRENT)) {
+            Ok(name) => name.trim().to_owned(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let checkpoint = self.dir.join(&name);
+        let manifest: Manifest =
+            serde_json::from_slice(&fs::read(checkpoint.join("manifest.json"))?).map_err(|e| {
+                BallistaError::General(format!("Invalid checkpoint manifest: {:?}", e))
+            })?;
+        if manifest.window != self.window {
+            return Err(BallistaError::General(format!(
+                "Checkpoint {} was saved by a query with a different window",
+                checkpoint.display()
+            )));
+        }
+        let mut windows = vec![];
+        for window in &manifest.windows {
+            let mut batches = vec![];
+            if let Some(file) = &window.file {
+                let mut reader = DictionaryFileReader::try_new(&checkpoint.join(file))?;
+                while let Some(batch) = reader.next_batch()? {
+                    batches.push(batch);
+                }
+            }
+            windows.push(SavedWindow {
+                start: window.start,
+                end: window.end,
+                batches,
+            });
+        }
+        assigner.restore(WindowState {
+            max_time: manifest.max_time,
+            watermark: manifest.watermark,
+            windows,
+        })?;
+        self.next_checkpoint = checkpoint_number(&name).map_or(0, |n| n + 1);
+        self.offsets = manifest
+            .offsets
+            .iter()
+            .map(|o| ((o.source.clone(), o.partition), o.offset))
+            .collect();
+        Ok(manifest.offsets)
+    }
+
+    /// Record the offsets that the sources have reached with the batches that have been
+    /// pushed to the windows
+    pub fn advance(&mut self, offsets: Vec<SourceOffset>) {
+        for o in offsets {
+            self.offsets.insert((o.source, o.partition), o.offset);
+        }
+    }
+
+    /// Save a checkpoint if the interval has passed since the last one
+    pub fn save_if_due(&mut self, assigner: &WindowAssigner) -> Result<()> {
+        if self.last_saved.elapsed() >= self.interval {
+            self.save(assigner)?;
+        }
+        Ok(())
+    }
+
+    /// Save the open windows of an assigner and the offsets of the sources as the latest
+    /// checkpoint
+    pub fn save(&mut self, assigner: &WindowAssigner) -> Result<()> {
+        let state = assigner.state();
+        let name = format!("checkpoint-{:010}", self.next_checkpoint);
+        let checkpoint = self.dir.join(&name);
+        if checkpoint.exists() {
+            fs::remove_dir_all(&checkpoint)?;
+        }
+        fs::create_dir_all(&checkpoint)?;
+
+        let mut windows = vec![];
+        for (i, window) in state.windows.iter().enumerate() {
+            let file = match window.batches.first() {
+                Some(first) => {
+                    let file = format!("window-{}.arrow", i);
+                    let mut writer =
+                        DictionaryFileWriter::try_new(&checkpoint.join(&file), first.schema())?;
+                    for batch in &window.batches {
+                        writer.write(batch)?;
+                    }
+                    writer.finish()?;
+                    Some(file)
+                }
+                None => None,
+            };
+            windows.push(ManifestWindow {
+                start: window.start,
+                end: window.end,
+                file,
+            });
+        }
+        let mut offsets: Vec<SourceOffset> = self
+            .offsets
+            .iter()
+            .map(|((source, partition), offset)| SourceOffset {
+                source: source.clone(),
+                partition: *partition,
+                offset: *offset,
+            })
+            .collect();
+        offsets.sort_by(|a, b| (&a.source, a.partition).cmp(&(&b.source, b.partition)));
+        let manifest = Manifest {
+            window: self.window.clone(),
+            max_time: state.max_time,
+            watermark: state.watermark,
+            offsets,
+            windows,
+        };
+        let json = serde_json::to_vec(&manifest)?;
+        fs::write(checkpoint.join("manifest.json"), json)?;
+
+        // replace the pointer to the latest checkpoint atomically, then remove the others
+        let current = self.dir.join(CURRENT);
+        let staged = self.dir.join(format!("{}.tmp", CURRENT));
+        fs::write(&staged, &name)?;
+        fs::rename(&staged, &current)?;
+        remove_checkpoints_except(&self.dir, &name)?;
+
+        self.next_checkpoint += 1;
+        self.last_saved = Instant::now();
+        Ok(())
+    }
+}
+
+/// The number of a checkpoint from its name
+fn checkpoint_number(name: &str) -> Option<u64> {
+    name.trim_start_matches("checkpoint-").parse().ok()
+}
+
+/// Remove the checkpoints of a directory other than the given one
+fn remove_checkpoints_except(dir: &Path, keep: &str) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name != keep && checkpoint_number(&name).is_some() {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{Int64Array, StringArray};
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::arrow::record_batch::RecordBatch;
+    use std::sync::Arc;
+
+    #[test]
+    fn restore_open_windows_and_offsets() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("t", DataType::Int64, false),
+            Field::new("k", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![0, 5, 12])),
+                Arc::new(StringArray::from(vec!["a", "b", "a"])),
+            ],
+        )?;
+        let spec = WindowSpec::session("t", Duration::from_millis(10), &["k"]);
+        let dir = std::env::temp_dir().join(format!("ballista-checkpoint-{}", std::process::id()));
+        let dir = dir.to_string_lossy().to_string();
+
+        let mut assigner = WindowAssigner::try_new(&spec, &schema)?;
+        assigner.push(&batch)?;
+        let mut checkpointer = Checkpointer::new(&dir, Duration::from_secs(60), spec.clone());
+        assert!(checkpointer.restore(&mut assigner)?.is_empty());
+        checkpointer.advance(vec![SourceOffset {
+            source: "kafka.events".to_owned(),
+            partition: 0,
+            offset: 3,
+        }]);
+        checkpointer.save(&assigner)?;
+        checkpointer.save(&assigner)?;
+        let expected = assigner.finish()?.len();
+
+        let mut restored = WindowAssigner::try_new(&spec, &schema)?;
+        let mut checkpointer = Checkpointer::new(&dir, Duration::from_secs(60), spec);
+        let offsets = checkpointer.restore(&mut restored)?;
+        // only the latest checkpoint is kept
+        let checkpoints = fs::read_dir(&dir)?.count();
+        fs::remove_dir_all(&dir)?;
+        assert_eq!(offsets[0].offset, 3);
+        assert_eq!(checkpoints, 2);
+        assert_eq!(restored.finish()?.len(), expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+//! An interactive shell that runs SQL queries on a context and prints their results.
+//!
+//! The `ballista-cli` binary reads lines from standard input and passes them to a `Shell`.
+//! SQL statements may span several lines and end with `;`. Lines that start with `\` are
+//! meta-commands, which list and describe tables, register files as tables, time queries
+//! and send results to a file:
+//!
+//! ```text
+//! \d                              list the tables and views
+//! \d NAME                         describe the columns of a table or view
+//! \register NAME PATH [csv|parquet]  register a file or directory as a view
+//! \timing [on|off]                show how long each query takes
+//! \o [FILE]                       write results to a file, or to standard output again
+//! \?                              show the meta-commands
+//! \q                              quit
+//! ```
+//!
+//! Files are registered as views, so queries that read them can also run on a cluster,
+//! where the executors read the files themselves.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::dataframe::Context;
+use crate::error::{BallistaError, Result};
+use crate::timezone::format_timestamp;
+
+use chrono::{Duration, FixedOffset, NaiveDate};
+
+/// The meta-commands of the shell
+pub const HELP: &str = "\\d                                 list the tables and views
+\\d NAME                            describe the columns of a table or view
+\\register NAME PATH [csv|parquet]  register a file or directory as a view
+\\timing [on|off]                   show how long each query takes
+\\o [FILE]                          write results to a file, or to standard output again
+\\?                                 show the meta-commands
+\\q                                 quit";
+
+/// A statement of the shell
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Run a SQL query and print its results
+    Sql(String),
+    /// List the tables and views
+    ListTables,
+    /// Describe the columns of a table or view
+    Describe(String),
+    /// Register a CSV or Parquet file or directory as a view
+    Register {
+        name: String,
+        path: String,
+        format: String,
+    },
+    /// Turn timing on or off, or toggle it
+    Timing(Option<bool>),
+    /// Write results to a file, or to standard output
+    Output(Option<String>),
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parse a meta-command, or a SQL statement without its final `;`
+    pub fn parse(input: &str) -> Result<Command> {
+        let input = input.trim();
+        if !input.starts_with('\\') {
+            return Ok(Command::Sql(input.trim_end_matches(';').trim().to_owned()));
+        }
+        let words: Vec<&str> = input[1..].split_whitespace().collect();
+        let invalid = || {
+            BallistaError::General(format!(
+                "Invalid meta-command '{}', see \\? for the meta-commands",
+                input
+            ))
+        };
+        match words.as_slice() {
+            ["d"] => Ok(Command::ListTables),
+            ["d", name] => Ok(Command::Describe((*name).to_owned())),
+            ["register", name, path] => {
+                let format = if path.ends_with(".parquet") {
+                    "parquet"
+                } else {
+                    "csv"
+                };
+                Ok(Command::Register {
+                    name: (*name).to_owned(),
+                    path: (*path).to_owned(),
+                    format: format.to_owned(),
+                })
+            }
+            ["register", name, path, format] if *format == "csv" || *format == "parquet" => {
+                Ok(Command::Register {
+                    name: (*name).to_owned(),
+                    path: (*path).to_owned(),
+                    format: (*format).to_owned(),
+                })
+            }
+            ["timing"] => Ok(Command::Timing(None)),
+            ["timing", "on"] => Ok(Command::Timing(Some(true))),
+            ["timing", "off"] => Ok(Command::Timing(Some(false))),
+            ["o"] => Ok(Command::Output(None)),
+            ["o", path] => Ok(Command::Output(Some((*path).to_owned()))),
+            ["?"] => Ok(Command::Help),
+            ["q"] => Ok(Command::Quit),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Runs the statements of an interactive session on a context
+pub struct Shell {
+    ctx: Context,
+    timing: bool,
+    output: Option<File>,
+    /// The lines of a SQL statement that has not ended yet
+    buffer: String,
+}
+
+impl Shell {
+    pub fn new(ctx: Context) -> Self {
+        Self {
+            ctx,
+            timing: false,
+            output: None,
+            buffer: String::new(),
+        }
+    }
+
+    /// Whether the shell is part way through a SQL statement
+    pub fn is_continuation(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// Read a line of input, running the statement that it completes, and return whether
+    /// the session continues
+    pub async fn read_line(&mut self, line: &str) -> Result<bool> {
+        if self.buffer.is_empty() && line.trim_start().starts_with('\\') {
+            return self.execute(Command::parse(line)?).await;
+        }
+        if line.trim().is_empty() && self.buffer.is_empty() {
+            return Ok(true);
+        }
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        if !line.trim_end().ends_with(';') {
+            return Ok(true);
+        }
+        let sql = std::mem::take(&mut self.buffer);
+        self.execute(Command::parse(&sql)?).await
+    }
+
+    /// Run a statement, and return whether the session continues
+    pub async fn execute(&mut self, command: Command) -> Result<bool> {
+        let start = Instant::now();
+        match command {
+            Command::Sql(sql) => {
+                let batches = self.ctx.sql(&sql)?.collect().await?;
+                let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+                let table = format_batches(&batches)?;
+                self.write(&format!("{}({} rows)\n", table, rows))?;
+                if self.timing {
+                    println!("Time: {:.3} ms", start.elapsed().as_secs_f64() * 1000.0);
+                }
+            }
+            Command::ListTables => {
+                let rows: Vec<Vec<String>> = self
+                    .ctx
+                    .table_names()
+                    .into_iter()
+                    .map(|n| vec![n])
+                    .collect();
+                self.write(&format_rows(&["name".to_owned()], &rows))?;
+            }
+            Command::Describe(name) => {
+                let df = self.ctx.table(&name)?;
+                let rows: Vec<Vec<String>> = df
+                    .schema()
+                    .fields()
+                    .iter()
+                    .map(|f| {
+                        vec![
+                            f.name().clone(),
+                            format!("{:?}", f.data_type()),
+                            f.is_nullable().to_string(),
+                        ]
+                    })
+                    .collect();
+                let headers = ["column", "type", "nullable"];
+                let headers: Vec<String> = headers.iter().map(|h| (*h).to_owned()).collect();
+                self.write(&format_rows(&headers, &rows))?;
+            }
+            Command::Register { name, path, format } => {
+                let df = match format.as_str() {
+                    "parquet" => self.ctx.read_parquet(&path, None)?,
+                    _ => self.ctx.read_csv(&path, None, None, true)?,
+                };
+                self.ctx.register_view(&name, &df);
+                println!("Registered {} as {}", path, name);
+            }
+            Command::Timing(timing) => {
+                self.timing = timing.unwrap_or(!self.timing);
+                println!("Timing is {}", if self.timing { "on" } else { "off" });
+            }
+            Command::Output(path) => {
+                self.output = match path {
+                    Some(path) => Some(File::create(&path)?),
+                    None => None,
+                };
+            }
+            Command::Help => println!("{}", HELP),
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Write results to the output file, or to standard output
+    fn write(&mut self, text: &str) -> Result<()> {
+        match &mut self.output {
+            Some(file) => file.write_all(text.as_bytes())?,
+            None => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                stdout.write_all(text.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format batches as a table with a header of their column names
+pub fn format_batches(batches: &[RecordBatch]) -> Result<String> {
+    let headers: Vec<String> = match batches.first() {
+        Some(batch) => batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect(),
+        None => return Ok(String::new()),
+    };
+    let mut rows = vec![];
+    for batch in batches {
+        let columns = batch
+            .columns()
+            .iter()
+            .map(format_values)
+            .collect::<Result<Vec<_>>>()?;
+        for row in 0..batch.num_rows() {
+            rows.push(columns.iter().map(|c| c[row].clone()).collect());
+        }
+    }
+    Ok(format_rows(&headers, &rows))
+}
+
+/// Format rows of text as a table, with each column as wide as its widest value
+pub fn format_rows(headers: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let separator = widths
+        .iter()
+        .map(|w| "-".repeat(w + 2))
+        .collect::<Vec<_>>()
+        .join("+");
+    let line = |values: &[String]| {
+        values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!(" {:width$} ", value, width = width))
+            .collect::<Vec<_>>()
+            .join("|")
+            .trim_end()
+            .to_owned()
+    };
+    let mut table = format!("{}\n{}\n", line(headers), separator);
+    for row in rows {
+        table.push_str(&line(row));
+        table.push('\n');
+    }
+    table
+}
+
+/// The text of each value of a column, with nulls as empty strings
+fn format_values(column: &ArrayRef) -> Result<Vec<String>> {
+    macro_rules! values {
+        ($ARRAY:ty) => {
+            values!($ARRAY, |v| format!("{}", v))
+        };
+        ($ARRAY:ty, $FORMAT:expr) => {{
+            let array = column.as_any().downcast_ref::<$ARRAY>().unwrap();
+            Ok((0..array.len())
+                .map(|i| {
+                    if array.is_null(i) {
+                        String::new()
+                    } else {
+                        $FORMAT(array.value(i))
+                    }
+                })
+                .collect())
+        }};
+    }
+    let epoch = NaiveDate::from_ymd(1970, 1, 1);
+    let date = |date: Option<NaiveDate>| match date {
+        Some(date) => date.format("%Y-%m-%d").to_string(),
+        None => String::new(),
+    };
+    let utc = FixedOffset::east(0);
+    let timestamp = |value, unit| format_timestamp(value, unit, &utc).unwrap_or_default();
+    match column.data_type() {
+        DataType::Boolean => values!(BooleanArray),
+        DataType::Int8 => values!(Int8Array),
+        DataType::Int16 => values!(Int16Array),
+        DataType::Int32 => values!(Int32Array),
+        DataType::Int64 => values!(Int64Array),
+        DataType::UInt8 => values!(UInt8Array),
+        DataType::UInt16 => values!(UInt16Array),
+        DataType::UInt32 => values!(UInt32Array),
+        DataType::UInt64 => values!(UInt64Array),
+        DataType::Float32 => values!(Float32Array),
+        DataType::Float64 => values!(Float64Array),
+        DataType::Utf8 => values!(StringArray),
+        DataType::Date32(_) => values!(Date32Array, |v| date(
+            epoch.checked_add_signed(Duration::days(v as i64))
+        )),
+        DataType::Date64(_) => values!(Date64Array, |v| date(
+            epoch.checked_add_signed(Duration::milliseconds(v))
+        )),
+        DataType::Timestamp(unit, _) => {
+            let format = |v| timestamp(v, unit);
+            match unit {
+                TimeUnit::Second => values!(TimestampSecondArray, format),
+                TimeUnit::Millisecond => values!(TimestampMillisecondArray, format),
+                TimeUnit::Microsecond => values!(TimestampMicrosecondArray, format),
+                TimeUnit::Nanosecond => values!(TimestampNanosecondArray, format),
+            }
+        }
+        other => Err(BallistaError::NotImplemented(format!(
+            "Printing columns of type {:?}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{Field, Schema};
+
+    #[test]
+    fn parse_commands_and_format_results() -> Result<()> {
+        assert_eq!(
+            Command::parse("SELECT a FROM t;\n")?,
+            Command::Sql("SELECT a FROM t".to_owned())
+        );
+        assert_eq!(
+            Command::parse("\\d lineitem")?,
+            Command::Describe("lineitem".to_owned())
+        );
+        assert_eq!(
+            Command::parse("\\register t data/t.parquet")?,
+            Command::Register {
+                name: "t".to_owned(),
+                path: "data/t.parquet".to_owned(),
+                format: "parquet".to_owned(),
+            }
+        );
+        assert_eq!(
+            Command::parse("\\timing off")?,
+            Command::Timing(Some(false))
+        );
+        assert!(Command::parse("\\timing maybe").is_err());
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, true),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Int32Array::from(vec![1, 22])),
+                Arc::new(StringArray::from(vec![Some("a"), None])),
+            ],
+        )?;
+        assert_eq!(
+            format_batches(&[batch])?,
+            " id | name\n----+------\n 1  | a\n 22 |\n"
+        );
+        Ok(())
+    }
+}
@@ -1,63 +1,1170 @@
-use std::convert::{TryFrom, TryInto};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use crate::error::BallistaError;
-use crate::plan::Action;
-use crate::protobuf;
+use crate::auth::Credentials;
+use crate::cancel::CancellationToken;
+use crate::compression::BatchCompression;
+#[cfg(feature = "local-execution")]
+use crate::datasource::memory::{pin_messages, MemoryTable};
+use crate::dictionary::{DictionaryDecoder, DictionaryFileWriter};
+use crate::error::{ballista_error, BallistaError};
+use crate::exchange::{exchange_messages, UploadedTable};
+use crate::execution_metrics::{request_metrics, QueryMetrics};
+use crate::history::QueryRecord;
+use crate::memory::MemoryUsage;
+use crate::plan::{
+    Action, CANCEL_ACTION_TYPE, CANCEL_JOB_ACTION_TYPE, COLLECT_JSON_ACTION_TYPE,
+    EXECUTOR_STATUS_ACTION_TYPE, JOB_STATUS_ACTION_TYPE, LIST_JOBS_ACTION_TYPE,
+    QUERY_HISTORY_ACTION_TYPE, REMOVE_SHUFFLE_ACTION_TYPE, SUBMIT_ACTION_TYPE,
+};
+use crate::profile::request_profile;
+use crate::scheduler::jobs::JobInfo;
+use crate::scheduler::queues::{request_queue, SCHEDULER_QUEUE};
+use crate::serde::{encode_protobuf, PLAN_VERSION};
+use crate::shuffle::ShuffleLocation;
+#[cfg(feature = "spark")]
+use crate::spark::encode_spark_action;
+#[cfg(feature = "local-execution")]
+use crate::stream::PartitionStream;
+use crate::stream::{is_partition_end, request_partitions, split_partitions};
+use crate::tls::{self, TlsConfig};
+use crate::trace::request_query_id;
 
-use crate::arrow::datatypes::Schema;
-use crate::arrow::flight::flight_data_to_batch;
+use crate::arrow::array::StringArray;
+use crate::arrow::datatypes::{Schema, SchemaRef};
 
 use crate::arrow::record_batch::RecordBatch;
 use flight::flight_service_client::FlightServiceClient;
-use flight::Ticket;
-use prost::Message;
+use flight::{FlightData, Ticket};
+use futures::future::{self, Either};
+use log::warn;
+use tonic::codec::Streaming;
+use tonic::transport::Channel;
+use tonic::{Code, Status};
+use tracing::info_span;
+use tracing_futures::Instrument;
 
+pub const CLIENT_CONNECT_TIMEOUT_MS: &str = "ballista.client.connectTimeoutMs";
+pub const CLIENT_READ_TIMEOUT_MS: &str = "ballista.client.readTimeoutMs";
+pub const CLIENT_MAX_ATTEMPTS: &str = "ballista.client.maxAttempts";
+pub const CLIENT_RETRY_BACKOFF_MS: &str = "ballista.client.retryBackoffMs";
+pub const CLIENT_POOL_IDLE_TIMEOUT_MS: &str = "ballista.client.poolIdleTimeoutMs";
+pub const PLAN_VERSION_SETTING: &str = "ballista.plan.version";
+
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Pooled connections that have been idle for longer than this are checked before they
+/// are reused
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Timeouts and retry policy for requests to executors
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientConfig {
+    /// Maximum time to wait for a connection to the executor
+    pub connect_timeout: Duration,
+    /// Maximum time to wait for each response message, or no limit if not set
+    pub read_timeout: Option<Duration>,
+    /// Number of times a query is submitted before giving up. Only failures that happen
+    /// before the first batch is received are retried.
+    pub max_attempts: usize,
+    /// Delay before the first retry, which doubles for each subsequent retry
+    pub retry_backoff: Duration,
+    /// Pooled connections that are not used for this long are closed
+    pub pool_idle_timeout: Duration,
+    /// TLS settings, or `None` to connect without TLS
+    pub tls: Option<TlsConfig>,
+    /// Credentials sent with every request, if the executor requires authentication
+    pub credentials: Option<Credentials>,
+    /// Codec that the executor is asked to encode batches with (see `compression`)
+    pub compression: BatchCompression,
+    /// The scheduler queue that queries run in, or the default queue if not set
+    pub queue: Option<String>,
+    /// The id of the query that requests belong to, which is sent so that the spans of
+    /// executors and schedulers can be related to the query
+    pub query_id: Option<String>,
+    /// Whether the executor is asked to send the metrics of the operators of each query
+    /// after its results
+    pub collect_metrics: bool,
+    /// Whether the executor is asked to profile queries, measuring the CPU time of their
+    /// operators. Profiles are sent with the metrics, so `collect_metrics` must be set too.
+    pub profile: bool,
+    /// Whether the executor is asked to mark the end of each partition of the results, so
+    /// that they can be collected by partition
+    pub collect_partitions: bool,
+    /// Version of the plan format that actions are encoded with, which is lowered while
+    /// executors that do not decode the current version are still running
+    pub plan_version: u32,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: None,
+            max_attempts: 3,
+            retry_backoff: Duration::from_millis(100),
+            pool_idle_timeout: Duration::from_secs(300),
+            tls: None,
+            credentials: None,
+            compression: BatchCompression::Uncompressed,
+            queue: None,
+            query_id: None,
+            collect_metrics: false,
+            profile: false,
+            collect_partitions: false,
+            plan_version: PLAN_VERSION,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Read the client configuration from the Context settings
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self, BallistaError> {
+        let default = Self::default();
+        Ok(Self {
+            connect_timeout: parse_setting(settings, CLIENT_CONNECT_TIMEOUT_MS)?
+                .map(Duration::from_millis)
+                .unwrap_or(default.connect_timeout),
+            read_timeout: parse_setting(settings, CLIENT_READ_TIMEOUT_MS)?
+                .map(Duration::from_millis)
+                .or(default.read_timeout),
+            max_attempts: parse_setting(settings, CLIENT_MAX_ATTEMPTS)?
+                .map(|n| n.max(1) as usize)
+                .unwrap_or(default.max_attempts),
+            retry_backoff: parse_setting(settings, CLIENT_RETRY_BACKOFF_MS)?
+                .map(Duration::from_millis)
+                .unwrap_or(default.retry_backoff),
+            pool_idle_timeout: parse_setting(settings, CLIENT_POOL_IDLE_TIMEOUT_MS)?
+                .map(Duration::from_millis)
+                .unwrap_or(default.pool_idle_timeout),
+            tls: TlsConfig::from_settings(settings)?,
+            credentials: Credentials::from_settings(settings)?,
+            compression: BatchCompression::from_settings(settings)?,
+            queue: settings.get(SCHEDULER_QUEUE).cloned(),
+            query_id: None,
+            collect_metrics: false,
+            profile: false,
+            collect_partitions: false,
+            plan_version: match parse_setting(settings, PLAN_VERSION_SETTING)? {
+                Some(version) if version <= PLAN_VERSION as u64 => version as u32,
+                Some(version) => {
+                    warn!("Invalid value for {}: {}", PLAN_VERSION_SETTING, version);
+                    default.plan_version
+                }
+                None => default.plan_version,
+            },
+        })
+    }
+
+    /// Delay before retrying after the given number of failed attempts
+    fn backoff(&self, attempt: usize) -> Duration {
+        (self.retry_backoff * 2u32.pow(attempt.min(16) as u32)).min(MAX_BACKOFF)
+    }
+}
+
+fn parse_setting(
+    settings: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<u64>, BallistaError> {
+    match settings.get(name) {
+        Some(n) => n
+            .parse::<u64>()
+            .map(Some)
+            .map_err(|_| ballista_error(&format!("Invalid value for {}: {}", name, n))),
+        None => Ok(None),
+    }
+}
+
+/// Stream of record batches returned by an executor, or produced by the partitions of a
+/// local query. Batches are decoded as they are received, and the executor only produces
+/// more batches once earlier ones have been consumed, so results do not need to fit in
+/// memory.
+pub struct RecordBatchStream {
+    schema: SchemaRef,
+    source: Source,
+    /// The metrics sent by the executor after the last batch, if they were requested
+    metrics: Option<QueryMetrics>,
+    /// The number of batches received so far
+    received: usize,
+    /// The number of batches that had been received when each partition ended, which the
+    /// executor marks when `ClientConfig::collect_partitions` is set
+    partition_ends: Vec<usize>,
+}
+
+/// Where the batches of a stream come from
+enum Source {
+    Flight {
+        stream: Streaming<FlightData>,
+        client: FlightServiceClient<Channel>,
+        ticket: Vec<u8>,
+        read_timeout: Option<Duration>,
+        credentials: Option<Credentials>,
+        decoder: DictionaryDecoder,
+        /// The host and port of the executor that the batches come from
+        executor: String,
+    },
+    #[cfg(feature = "local-execution")]
+    Local(PartitionStream),
+}
+
+#[cfg(feature = "local-execution")]
+impl From<PartitionStream> for RecordBatchStream {
+    fn from(stream: PartitionStream) -> Self {
+        Self {
+            schema: stream.schema(),
+            source: Source::Local(stream),
+            metrics: None,
+            received: 0,
+            partition_ends: vec![],
+        }
+    }
+}
+
+impl RecordBatchStream {
+    /// The schema of the batches
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Receive the next batch, returning `None` once all batches have been received
+    pub async fn next(&mut self) -> Result<Option<RecordBatch>, BallistaError> {
+        match &mut self.source {
+            Source::Flight {
+                stream,
+                read_timeout,
+                decoder,
+                executor,
+                ..
+            } => {
+                let batch = next_flight_batch(
+                    stream,
+                    *read_timeout,
+                    decoder,
+                    &mut self.metrics,
+                    self.received,
+                    &mut self.partition_ends,
+                )
+                .await
+                .map_err(|e| e.on_executor(executor))?;
+                if batch.is_some() {
+                    self.received += 1;
+                }
+                Ok(batch)
+            }
+            #[cfg(feature = "local-execution")]
+            Source::Local(stream) => stream.next().await,
+        }
+    }
+
+    /// The number of batches that had been received when each of the partitions that have
+    /// been received so far ended. Local queries always record the ends of their partitions,
+    /// while executors only mark them when `ClientConfig::collect_partitions` is set.
+    pub fn partition_ends(&self) -> &[usize] {
+        match &self.source {
+            Source::Flight { .. } => &self.partition_ends,
+            #[cfg(feature = "local-execution")]
+            Source::Local(stream) => stream.partition_ends(),
+        }
+    }
+
+    /// The metrics of the operators of the query, which are received after the last batch
+    /// when `ClientConfig::collect_metrics` is set
+    pub fn metrics(&self) -> Option<&QueryMetrics> {
+        self.metrics.as_ref()
+    }
+
+    /// Receive all of the remaining batches
+    pub async fn collect(mut self) -> Result<Vec<RecordBatch>, BallistaError> {
+        let mut batches = vec![];
+        while let Some(batch) = self.next().await? {
+            batches.push(batch);
+        }
+        Ok(batches)
+    }
+
+    /// Receive all of the remaining batches, cancelling the query if the token is cancelled
+    /// before all of the batches have been received
+    pub async fn collect_with_cancellation(
+        self,
+        token: &CancellationToken,
+    ) -> Result<Vec<RecordBatch>, BallistaError> {
+        Ok(self.collect_with_metrics(token).await?.0)
+    }
+
+    /// Receive all of the remaining batches and the metrics of the query, cancelling the
+    /// query if the token is cancelled before all of the batches have been received
+    pub async fn collect_with_metrics(
+        mut self,
+        token: &CancellationToken,
+    ) -> Result<(Vec<RecordBatch>, Option<QueryMetrics>), BallistaError> {
+        let mut batches = vec![];
+        loop {
+            let next = Box::pin(self.next());
+            let cancelled = Box::pin(token.cancelled());
+            match future::select(next, cancelled).await {
+                Either::Left((batch, _)) => match batch? {
+                    Some(batch) => batches.push(batch),
+                    None => return Ok((batches, self.metrics)),
+                },
+                Either::Right(_) => break,
+            }
+        }
+        self.cancel().await?;
+        Err(BallistaError::Cancelled)
+    }
+
+    /// Receive all of the remaining batches, split into the partitions of the query that
+    /// produced them, cancelling the query if the token is cancelled before all of the
+    /// batches have been received. The batches of executors that do not mark the ends of
+    /// the partitions are returned as a single partition.
+    pub async fn collect_partitioned(
+        mut self,
+        token: &CancellationToken,
+    ) -> Result<Vec<Vec<RecordBatch>>, BallistaError> {
+        let mut batches = vec![];
+        loop {
+            let next = Box::pin(self.next());
+            let cancelled = Box::pin(token.cancelled());
+            match future::select(next, cancelled).await {
+                Either::Left((batch, _)) => match batch? {
+                    Some(batch) => batches.push(batch),
+                    None => return Ok(split_partitions(batches, self.partition_ends())),
+                },
+                Either::Right(_) => break,
+            }
+        }
+        self.cancel().await?;
+        Err(BallistaError::Cancelled)
+    }
+
+    /// Ask the executor to stop executing the query and discard the remaining results
+    pub async fn cancel(self) -> Result<(), BallistaError> {
+        let (mut client, ticket, credentials) = match self.source {
+            Source::Flight {
+                client,
+                ticket,
+                credentials,
+                ..
+            } => (client, ticket, credentials),
+            // local partitions stop once they find that the stream has been dropped
+            #[cfg(feature = "local-execution")]
+            Source::Local(_) => return Ok(()),
+        };
+        let cancel = flight::Action {
+            r#type: CANCEL_ACTION_TYPE.to_owned(),
+            body: ticket,
+        };
+        let request = request(cancel, credentials.as_ref())?;
+        match client.do_action(request).await {
+            // executors that do not support cancellation stop when the stream is dropped
+            Ok(_) => Ok(()),
+            Err(status) if status.code() == Code::Unimplemented => Ok(()),
+            Err(e) => Err(BallistaError::TonicError(e)),
+        }
+    }
+}
+
+/// Receive the next batch of a Flight stream, keeping the metrics of the query if they are
+/// received instead, and recording the number of batches that had been received when each
+/// partition ended
+async fn next_flight_batch(
+    stream: &mut Streaming<FlightData>,
+    read_timeout: Option<Duration>,
+    decoder: &mut DictionaryDecoder,
+    metrics: &mut Option<QueryMetrics>,
+    received: usize,
+    partition_ends: &mut Vec<usize>,
+) -> Result<Option<RecordBatch>, BallistaError> {
+    // all the remaining stream messages should be dictionary and record batches, except
+    // for the ends of the partitions and the metrics of the query that follow the last batch
+    loop {
+        match with_timeout(read_timeout, stream.message())
+            .await?
+            .map_err(BallistaError::TonicError)?
+        {
+            Some(flight_data) => {
+                if is_partition_end(&flight_data) {
+                    partition_ends.push(received);
+                    continue;
+                }
+                if let Some(received) = QueryMetrics::from_flight_data(&flight_data) {
+                    *metrics = Some(received?);
+                    continue;
+                }
+                let flight_data = BatchCompression::decompress(flight_data)?;
+                if let Some(batch) = decoder.decode_flight_data(&flight_data)? {
+                    return Ok(Some(batch));
+                }
+            }
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Default port that executors listen on
+pub const DEFAULT_PORT: u16 = 50051;
+
+/// Connections to executors, keyed by host and port. Requests are multiplexed over a
+/// single HTTP/2 connection, so one connection is kept for each executor and shared by all
+/// of the queries in a Context.
+#[derive(Default)]
+pub struct ConnectionPool {
+    connections: Mutex<HashMap<(String, usize), PooledConnection>>,
+}
+
+struct PooledConnection {
+    channel: Channel,
+    last_used: Instant,
+}
+
+impl fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let connections = self.connections.lock().unwrap();
+        let mut executors: Vec<String> = connections
+            .keys()
+            .map(|(host, port)| format!("{}:{}", host, port))
+            .collect();
+        executors.sort();
+        f.debug_struct("ConnectionPool")
+            .field("connections", &executors)
+            .finish()
+    }
+}
+
+impl ConnectionPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of open connections
+    pub fn len(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get a connection to an executor, reusing an existing connection if it is still
+    /// healthy. Connections that have been idle for too long are closed.
+    async fn get(
+        &self,
+        host: &str,
+        port: usize,
+        config: &ClientConfig,
+    ) -> Result<Channel, RequestError> {
+        let key = (host.to_owned(), port);
+        let now = Instant::now();
+        let pooled = {
+            let mut connections = self.connections.lock().unwrap();
+            connections.retain(|_, c| now.duration_since(c.last_used) < config.pool_idle_timeout);
+            connections
+                .get(&key)
+                .map(|c| (c.channel.clone(), now.duration_since(c.last_used)))
+        };
+
+        let healthy = match pooled {
+            Some((channel, idle)) if idle < HEALTH_CHECK_INTERVAL => Some(channel),
+            Some((channel, _)) => {
+                if is_healthy(channel.clone(), config).await {
+                    Some(channel)
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+        let channel = match healthy {
+            Some(channel) => channel,
+            None => connect(host, port, config).await?,
+        };
+        self.connections.lock().unwrap().insert(
+            key,
+            PooledConnection {
+                channel: channel.clone(),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(channel)
+    }
+
+    /// Discard the connection to an executor after a request failed
+    fn remove(&self, host: &str, port: usize) {
+        self.connections
+            .lock()
+            .unwrap()
+            .remove(&(host.to_owned(), port));
+    }
+}
+
+async fn connect(host: &str, port: usize, config: &ClientConfig) -> Result<Channel, RequestError> {
+    let endpoint = tls::endpoint(host, port, config.tls.as_ref()).map_err(RequestError::fatal)?;
+    with_timeout(Some(config.connect_timeout), endpoint.connect())
+        .await?
+        .map_err(|e| RequestError {
+            retryable: true,
+            error: BallistaError::TonicError(Status::unavailable(format!(
+                "Unable to connect to {}:{}: {:?}",
+                host, port, e
+            ))),
+        })
+}
+
+/// Check that an executor accepts connections and responds to requests
+pub async fn is_reachable(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    config: &ClientConfig,
+) -> bool {
+    match pool.get(host, port, config).await {
+        Ok(channel) => is_healthy(channel, config).await,
+        Err(_) => false,
+    }
+}
+
+/// Check that an executor still responds on a connection
+async fn is_healthy(channel: Channel, config: &ClientConfig) -> bool {
+    let mut client = FlightServiceClient::new(channel);
+    let request = match request(flight::Empty {}, config.credentials.as_ref()) {
+        Ok(request) => request,
+        Err(_) => return false,
+    };
+    match with_timeout(Some(config.connect_timeout), client.list_actions(request)).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(status)) => status.code() == Code::Unimplemented,
+        Err(_) => false,
+    }
+}
+
+/// Execute an action and collect the results
 pub async fn execute_action(
     host: &str,
     port: usize,
     action: Action,
 ) -> Result<Vec<RecordBatch>, BallistaError> {
-    //TODO need to avoid connecting per request
-    let mut client = FlightServiceClient::connect(format!("http://{}:{}", host, port))
+    let pool = ConnectionPool::new();
+    execute_action_stream(&pool, host, port, action, &ClientConfig::default())
+        .await?
+        .collect()
         .await
-        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+}
 
-    let serialized_action: protobuf::Action = action.try_into()?;
-    let mut buf: Vec<u8> = Vec::with_capacity(serialized_action.encoded_len());
-    serialized_action
-        .encode(&mut buf)
-        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+/// Execute an action, returning a stream of the results. Transient failures are retried
+/// according to the configured retry policy.
+pub async fn execute_action_stream(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    action: Action,
+    config: &ClientConfig,
+) -> Result<RecordBatchStream, BallistaError> {
+    let buf = encode_protobuf(action, config.plan_version)?;
+    fetch_with_retries(pool, host, port, Fetch::Action(&buf), config).await
+}
 
-    let request = tonic::Request::new(Ticket { ticket: buf });
+/// Execute an action, returning each row of its results as a JSON object (see `json`). The
+/// executor converts the results, so they are not retried once they have started.
+pub async fn collect_json(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    action: Action,
+    config: &ClientConfig,
+) -> Result<Vec<serde_json::Value>, BallistaError> {
+    let buf = encode_protobuf(action, config.plan_version)?;
+    do_action(pool, host, port, COLLECT_JSON_ACTION_TYPE, buf, config)
+        .await?
+        .iter()
+        .map(|body| Ok(serde_json::from_slice(body)?))
+        .collect()
+}
+
+/// Execute an action on the Spark executor in a Spark session with the given settings,
+/// returning a stream of the results. Actions are encoded in the form that the Spark
+/// executor decodes (see `spark`).
+#[cfg(feature = "spark")]
+pub async fn execute_spark_action_stream(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    action: Action,
+    session_settings: &HashMap<String, String>,
+    config: &ClientConfig,
+) -> Result<RecordBatchStream, BallistaError> {
+    let buf = encode_spark_action(action, session_settings)?;
+    fetch_with_retries(pool, host, port, Fetch::Action(&buf), config).await
+}
+
+/// Execute a query that reads tables uploaded with it, returning a stream of the results.
+/// The query scans each table with a `TableScan` of its name, as described in the
+/// `exchange` module.
+pub async fn execute_exchange_stream(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    action: Action,
+    tables: &[UploadedTable],
+    config: &ClientConfig,
+) -> Result<RecordBatchStream, BallistaError> {
+    let buf = encode_protobuf(action, config.plan_version)?;
+    let messages = exchange_messages(&buf, tables)?;
+    fetch_with_retries(pool, host, port, Fetch::Exchange(&messages), config).await
+}
 
-    let mut stream = client
-        .do_get(request)
+/// Fetch the results for a ticket, such as the ticket for a shuffle partition, returning a
+/// stream of the results
+pub async fn fetch_stream(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    ticket: &[u8],
+    config: &ClientConfig,
+) -> Result<RecordBatchStream, BallistaError> {
+    fetch_with_retries(pool, host, port, Fetch::Ticket(ticket), config).await
+}
+
+/// Fetch a shuffle partition from the executor that wrote it
+pub async fn fetch_shuffle_partition(
+    pool: &ConnectionPool,
+    location: &ShuffleLocation,
+    config: &ClientConfig,
+) -> Result<Vec<RecordBatch>, BallistaError> {
+    let ticket = location.partition_id.ticket();
+    fetch_stream(pool, &location.host, location.port, &ticket, config)
+        .await?
+        .collect()
         .await
-        .map_err(|e| BallistaError::General(format!("{:?}", e)))?
-        .into_inner();
+}
 
-    // the schema should be the first message returned, else client should error
-    let flight_data = stream
-        .message()
+/// Fetch a shuffle partition from the executor that wrote it into a local Arrow IPC file as
+/// the batches are received, so that the partition does not need to fit in memory,
+/// returning the number of rows
+pub async fn fetch_shuffle_partition_to_file(
+    pool: &ConnectionPool,
+    location: &ShuffleLocation,
+    path: &Path,
+    config: &ClientConfig,
+) -> Result<usize, BallistaError> {
+    let ticket = location.partition_id.ticket();
+    let mut stream = fetch_stream(pool, &location.host, location.port, &ticket, config).await?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut writer = DictionaryFileWriter::try_new(path, stream.schema())?;
+    let mut num_rows = 0;
+    while let Some(batch) = stream.next().await? {
+        num_rows += batch.num_rows();
+        writer.write(&batch)?;
+    }
+    writer.finish()?;
+    Ok(num_rows)
+}
+
+/// Ask an executor to remove the shuffle partitions that it wrote for a job
+pub async fn remove_shuffle(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    job_id: &str,
+    config: &ClientConfig,
+) -> Result<(), BallistaError> {
+    let body = job_id.as_bytes().to_vec();
+    do_action(pool, host, port, REMOVE_SHUFFLE_ACTION_TYPE, body, config).await?;
+    Ok(())
+}
+
+/// Fetch the schema of a path or a registered table as an executor sees it (see
+/// `Action::FetchSchema`)
+pub async fn fetch_schema(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    path: &str,
+    file_type: &str,
+    config: &ClientConfig,
+) -> Result<Schema, BallistaError> {
+    let action = Action::FetchSchema {
+        path: path.to_owned(),
+        file_type: file_type.to_owned(),
+    };
+    let stream = execute_action_stream(pool, host, port, action, config).await?;
+    Ok(stream.schema().as_ref().clone())
+}
+
+/// List the tables registered on an executor
+pub async fn list_tables(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    config: &ClientConfig,
+) -> Result<Vec<String>, BallistaError> {
+    let batches = execute_action_stream(pool, host, port, Action::ListTables, config)
+        .await?
+        .collect()
+        .await?;
+    table_names_from_batches(&batches)
+}
+
+/// Read the table names from the batches returned for a `ListTables` action
+fn table_names_from_batches(batches: &[RecordBatch]) -> Result<Vec<String>, BallistaError> {
+    let mut names = vec![];
+    for batch in batches {
+        let column = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| ballista_error("Unexpected schema for table names"))?;
+        names.extend((0..column.len()).map(|i| column.value(i).to_owned()));
+    }
+    Ok(names)
+}
+
+/// List the running and recently completed jobs of a scheduler
+pub async fn list_jobs(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    config: &ClientConfig,
+) -> Result<Vec<JobInfo>, BallistaError> {
+    do_action(pool, host, port, LIST_JOBS_ACTION_TYPE, vec![], config)
+        .await?
+        .iter()
+        .map(|body| JobInfo::from_json(&String::from_utf8_lossy(body)))
+        .collect()
+}
+
+/// Fetch the progress of a job from a scheduler
+pub async fn job_status(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    job_id: usize,
+    config: &ClientConfig,
+) -> Result<JobInfo, BallistaError> {
+    let body = job_id.to_string().into_bytes();
+    let results = do_action(pool, host, port, JOB_STATUS_ACTION_TYPE, body, config).await?;
+    match results.first() {
+        Some(body) => JobInfo::from_json(&String::from_utf8_lossy(body)),
+        None => Err(ballista_error("Scheduler did not return the job")),
+    }
+}
+
+/// Cancel a job that is running on a scheduler
+pub async fn cancel_job(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    job_id: usize,
+    config: &ClientConfig,
+) -> Result<(), BallistaError> {
+    let body = job_id.to_string().into_bytes();
+    do_action(pool, host, port, CANCEL_JOB_ACTION_TYPE, body, config).await?;
+    Ok(())
+}
+
+/// Fetch the queries that have run on an executor or scheduler, oldest first
+pub async fn query_history(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    config: &ClientConfig,
+) -> Result<Vec<QueryRecord>, BallistaError> {
+    do_action(pool, host, port, QUERY_HISTORY_ACTION_TYPE, vec![], config)
+        .await?
+        .iter()
+        .map(|body| QueryRecord::from_json(&String::from_utf8_lossy(body)))
+        .collect()
+}
+
+/// Fetch the memory used by the queries running on an executor
+pub async fn memory_usage(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    config: &ClientConfig,
+) -> Result<MemoryUsage, BallistaError> {
+    let results = do_action(
+        pool,
+        host,
+        port,
+        EXECUTOR_STATUS_ACTION_TYPE,
+        vec![],
+        config,
+    )
+    .await?;
+    match results.first() {
+        Some(body) => MemoryUsage::from_json(&String::from_utf8_lossy(body)),
+        None => Err(ballista_error("Executor did not return its status")),
+    }
+}
+
+/// Perform a Flight action, returning the bodies of its results
+/// Pin a memory table on an executor, which registers it under the given name until it is
+/// replaced, so that queries that scan it can run there without uploading it again (see
+/// `datasource::memory`)
+#[cfg(feature = "local-execution")]
+pub async fn pin_table(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    name: &str,
+    table: &MemoryTable,
+    config: &ClientConfig,
+) -> Result<(), BallistaError> {
+    try_pin_table(pool, host, port, name, table, config)
         .await
-        .map_err(|e| BallistaError::General(format!("{:?}", e)))?
-        .unwrap();
+        .map_err(|e| e.on_executor(&format!("{}:{}", host, port)))
+}
 
-    // convert FlightData to a stream
-    let schema = Arc::new(Schema::try_from(&flight_data)?);
+#[cfg(feature = "local-execution")]
+async fn try_pin_table(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    name: &str,
+    table: &MemoryTable,
+    config: &ClientConfig,
+) -> Result<(), BallistaError> {
+    let messages = futures::stream::iter(pin_messages(name, table)?);
+    let mut client =
+        FlightServiceClient::new(pool.get(host, port, config).await.map_err(|e| e.error)?);
+    let put = request(messages, config.credentials.as_ref())?;
+    let span = info_span!("do_put", host, port, name);
+    let mut stream = with_timeout(config.read_timeout, client.do_put(put))
+        .instrument(span)
+        .await?
+        .map_err(BallistaError::TonicError)?
+        .into_inner();
+    while with_timeout(config.read_timeout, stream.message())
+        .await?
+        .map_err(BallistaError::TonicError)?
+        .is_some()
+    {}
+    Ok(())
+}
 
-    // all the remaining stream messages should be dictionary and record batches
-    let mut batches = vec![];
-    while let Some(flight_data) = stream
-        .message()
+async fn do_action(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    action_type: &str,
+    body: Vec<u8>,
+    config: &ClientConfig,
+) -> Result<Vec<Vec<u8>>, BallistaError> {
+    try_do_action(pool, host, port, action_type, body, config)
         .await
-        .map_err(|e| BallistaError::General(format!("{:?}", e)))?
+        .map_err(|e| e.on_executor(&format!("{}:{}", host, port)))
+}
+
+async fn try_do_action(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    action_type: &str,
+    body: Vec<u8>,
+    config: &ClientConfig,
+) -> Result<Vec<Vec<u8>>, BallistaError> {
+    let mut client =
+        FlightServiceClient::new(pool.get(host, port, config).await.map_err(|e| e.error)?);
+    let action = flight::Action {
+        r#type: action_type.to_owned(),
+        body,
+    };
+    let mut action = request(action, config.credentials.as_ref())?;
+    // actions that execute queries run in the configured queue
+    if let Some(queue) = &config.queue {
+        request_queue(queue, &mut action)?;
+    }
+    if let Some(query_id) = &config.query_id {
+        request_query_id(query_id, &mut action)?;
+    }
+    let span = info_span!("do_action", host, port, action_type);
+    let mut stream = with_timeout(config.read_timeout, client.do_action(action))
+        .instrument(span)
+        .await?
+        .map_err(BallistaError::TonicError)?
+        .into_inner();
+    let mut results = vec![];
+    while let Some(result) = with_timeout(config.read_timeout, stream.message())
+        .await?
+        .map_err(BallistaError::TonicError)?
     {
-        // the unwrap is infallible and thus safe
-        let record_batch = flight_data_to_batch(&flight_data, schema.clone())?.unwrap();
-        batches.push(record_batch);
+        results.push(result.body);
     }
+    Ok(results)
+}
+
+/// What is fetched with `DoGet`
+enum Fetch<'a> {
+    /// A serialized action, which is submitted to obtain the ticket for its results
+    Action(&'a [u8]),
+    /// A ticket for results that already exist
+    Ticket(&'a [u8]),
+    /// The messages of an exchange, which upload tables along with the action
+    Exchange(&'a [FlightData]),
+}
+
+async fn fetch_with_retries(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    fetch: Fetch<'_>,
+    config: &ClientConfig,
+) -> Result<RecordBatchStream, BallistaError> {
+    let mut attempt = 0;
+    loop {
+        let span = info_span!("fetch", host, port, attempt);
+        match try_fetch(pool, host, port, &fetch, config)
+            .instrument(span)
+            .await
+        {
+            Ok(stream) => return Ok(stream),
+            Err(e) if e.retryable && attempt + 1 < config.max_attempts => {
+                // the retry uses a new connection in case the existing one is broken
+                pool.remove(host, port);
+                warn!(
+                    "Retrying request to {}:{} after error: {:?}",
+                    host, port, e.error
+                );
+                tokio::time::delay_for(config.backoff(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.error.on_executor(&format!("{}:{}", host, port))),
+        }
+    }
+}
+
+/// Error from a single attempt at executing an action
+struct RequestError {
+    retryable: bool,
+    error: BallistaError,
+}
 
-    Ok(batches)
+impl RequestError {
+    fn fatal(error: BallistaError) -> Self {
+        Self {
+            retryable: false,
+            error,
+        }
+    }
+
+    fn status(status: Status) -> Self {
+        BallistaError::TonicError(status).into()
+    }
+}
+
+impl From<BallistaError> for RequestError {
+    fn from(error: BallistaError) -> Self {
+        Self {
+            retryable: is_transient(&error),
+            error,
+        }
+    }
+}
+
+/// Whether an error is caused by the connection to an executor or the state of the
+/// executor rather than by the request, so that the request may succeed if it is retried.
+/// Connection failures, timeouts, and executors that are overloaded or shutting down are
+/// transient.
+pub fn is_transient(error: &BallistaError) -> bool {
+    match error.root_cause() {
+        BallistaError::TonicError(status) => match status.code() {
+            Code::Unavailable
+            | Code::DeadlineExceeded
+            | Code::ResourceExhausted
+            | Code::Aborted
+            | Code::Unknown => true,
+            _ => false,
+        },
+        BallistaError::General(message) => message.starts_with("Timed out"),
+        BallistaError::IoError(_) => true,
+        _ => false,
+    }
+}
+
+async fn try_fetch(
+    pool: &ConnectionPool,
+    host: &str,
+    port: usize,
+    fetch: &Fetch<'_>,
+    config: &ClientConfig,
+) -> Result<RecordBatchStream, RequestError> {
+    let mut client = FlightServiceClient::new(pool.get(host, port, config).await?);
+    let (ticket, mut stream) = match fetch {
+        Fetch::Action(action) => {
+            let ticket = submit(&mut client, action, config).await?;
+            let stream = get(&mut client, &ticket, config).await?;
+            (ticket, stream)
+        }
+        Fetch::Ticket(ticket) => (ticket.to_vec(), get(&mut client, ticket, config).await?),
+        // exchanges have no ticket, so they are cancelled by dropping the stream
+        Fetch::Exchange(messages) => {
+            let messages = futures::stream::iter(messages.to_vec());
+            let exchange = query_request(messages, config)?;
+            let stream = with_timeout(config.read_timeout, client.do_exchange(exchange))
+                .await?
+                .map_err(RequestError::status)?
+                .into_inner();
+            (vec![], stream)
+        }
+    };
+
+    // the schema should be the first message returned, else client should error
+    let flight_data = with_timeout(config.read_timeout, stream.message())
+        .await?
+        .map_err(RequestError::status)?
+        .ok_or_else(|| RequestError::fatal(ballista_error("Executor did not return a schema")))?;
+    let decoder = DictionaryDecoder::from_flight_data(&flight_data)?;
+
+    Ok(RecordBatchStream {
+        schema: decoder.schema(),
+        source: Source::Flight {
+            stream,
+            client,
+            ticket,
+            read_timeout: config.read_timeout,
+            credentials: config.credentials.clone(),
+            decoder,
+            executor: format!("{}:{}", host, port),
+        },
+        metrics: None,
+        received: 0,
+        partition_ends: vec![],
+    })
+}
+
+/// Fetch the results for a ticket
+async fn get(
+    client: &mut FlightServiceClient<Channel>,
+    ticket: &[u8],
+    config: &ClientConfig,
+) -> Result<Streaming<FlightData>, RequestError> {
+    let get = query_request(
+        Ticket {
+            ticket: ticket.to_vec(),
+        },
+        config,
+    )?;
+    Ok(with_timeout(config.read_timeout, client.do_get(get))
+        .await?
+        .map_err(RequestError::status)?
+        .into_inner())
+}
+
+/// A request for the results of a query, with the headers that the configuration asks for
+fn query_request<T>(message: T, config: &ClientConfig) -> Result<tonic::Request<T>, RequestError> {
+    let mut request = request(message, config.credentials.as_ref()).map_err(RequestError::fatal)?;
+    config
+        .compression
+        .request(&mut request)
+        .map_err(RequestError::fatal)?;
+    if let Some(queue) = &config.queue {
+        request_queue(queue, &mut request).map_err(RequestError::fatal)?;
+    }
+    if let Some(query_id) = &config.query_id {
+        request_query_id(query_id, &mut request).map_err(RequestError::fatal)?;
+    }
+    if config.collect_metrics {
+        request_metrics(&mut request);
+    }
+    if config.profile {
+        request_profile(&mut request);
+    }
+    if config.collect_partitions {
+        request_partitions(&mut request);
+    }
+    Ok(request)
+}
+
+/// Submit an action, returning the ticket for its results. Executors that do not support
+/// actions execute the serialized action when it is used as the ticket.
+async fn submit(
+    client: &mut FlightServiceClient<Channel>,
+    action: &[u8],
+    config: &ClientConfig,
+) -> Result<Vec<u8>, RequestError> {
+    let submit = flight::Action {
+        r#type: SUBMIT_ACTION_TYPE.to_owned(),
+        body: action.to_vec(),
+    };
+    let mut submit = request(submit, config.credentials.as_ref()).map_err(RequestError::fatal)?;
+    if let Some(queue) = &config.queue {
+        request_queue(queue, &mut submit).map_err(RequestError::fatal)?;
+    }
+    if let Some(query_id) = &config.query_id {
+        request_query_id(query_id, &mut submit).map_err(RequestError::fatal)?;
+    }
+    // submitted queries are planned when they are submitted, which is when the executor
+    // decides whether to profile them and whether to keep the partitions of their results
+    if config.profile {
+        request_profile(&mut submit);
+    }
+    if config.collect_partitions {
+        request_partitions(&mut submit);
+    }
+    let response = with_timeout(config.read_timeout, client.do_action(submit)).await?;
+    match response {
+        Ok(response) => {
+            let result = with_timeout(config.read_timeout, response.into_inner().message())
+                .await?
+                .map_err(RequestError::status)?;
+            result.map(|result| result.body).ok_or_else(|| {
+                RequestError::fatal(ballista_error("Executor did not return a ticket"))
+            })
+        }
+        Err(status) if status.code() == Code::Unimplemented => Ok(action.to_vec()),
+        Err(status) => Err(RequestError::status(status)),
+    }
+}
+
+/// Create a request, adding the credentials if there are any
+fn request<T>(
+    message: T,
+    credentials: Option<&Credentials>,
+) -> Result<tonic::Request<T>, BallistaError> {
+    let mut request = tonic::Request::new(message);
+    if let Some(credentials) = credentials {
+        credentials.authorize(&mut request)?;
+    }
+    Ok(request)
+}
+
+/// Wait for a future to complete, failing if it takes longer than the timeout
+async fn with_timeout<F: Future>(
+    timeout: Option<Duration>,
+    f: F,
+) -> Result<F::Output, BallistaError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, f)
+            .await
+            .map_err(|_| BallistaError::General(format!("Timed out after {:?}", timeout))),
+        None => Ok(f.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_from_settings() -> Result<(), BallistaError> {
+        let mut settings = HashMap::new();
+        settings.insert(CLIENT_READ_TIMEOUT_MS.to_owned(), "5000".to_owned());
+        settings.insert(CLIENT_MAX_ATTEMPTS.to_owned(), "5".to_owned());
+        let config = ClientConfig::from_settings(&settings)?;
+        assert_eq!(Some(Duration::from_secs(5)), config.read_timeout);
+        assert_eq!(5, config.max_attempts);
+        assert_eq!(Duration::from_millis(400), config.backoff(2));
+        assert_eq!(MAX_BACKOFF, config.backoff(20));
+
+        settings.insert(CLIENT_CONNECT_TIMEOUT_MS.to_owned(), "soon".to_owned());
+        assert!(ClientConfig::from_settings(&settings).is_err());
+        Ok(())
+    }
 }
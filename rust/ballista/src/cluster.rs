@@ -13,7 +13,11 @@ use crate::error::BallistaError;
 
 const CLUSTER_LABEL_KEY: &str = "ballista-cluster";
 
-fn execute<T, F>(request: http::Request<Vec<u8>>, response_body: F) -> Result<T, BallistaError>
+/// Send a request to the Kubernetes API, using the kubeconfig or the in-cluster configuration
+pub(crate) fn execute<T, F>(
+    request: http::Request<Vec<u8>>,
+    response_body: F,
+) -> Result<T, BallistaError>
 where
     T: Response,
     F: Fn(http::StatusCode) -> ResponseBody<T>,
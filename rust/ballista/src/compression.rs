@@ -0,0 +1,328 @@
+//! Encoding of record batches sent between clients and executors.
+//!
+//! Batches are sent as Arrow IPC messages, and a `BatchCodec` can encode the body of each
+//! batch message to trade CPU for bandwidth. A client requests a codec by sending its name
+//! in the request metadata. The executor encodes the body of each IPC-encoded batch and
+//! records the codec in the `app_metadata` of the message, so that results from executors
+//! that do not support the codec are still read correctly. Schema messages are never
+//! encoded.
+//!
+//! The `lz4` and `zstd` codecs compress the bodies, and the `delta` codec stores each 64-bit
+//! word of a body as its difference from the previous word before compressing it with zstd,
+//! so that the buffers of sorted or slowly changing values, such as ids, timestamps and
+//! offsets, are sent as runs of small values. Other codecs are registered by name with
+//! `register_batch_codec`, and every process that sends or reads batches with them,
+//! including the executors, must register them.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::{Arc, RwLock};
+
+use crate::error::{ballista_error, BallistaError, Result};
+
+use flight::FlightData;
+use lazy_static::lazy_static;
+use tonic::metadata::MetadataMap;
+use tonic::Request;
+
+/// Codec used to encode results, either `none` for plain Arrow IPC, `lz4`, `zstd`,
+/// `delta`, or the name of a codec registered with `register_batch_codec`
+pub const CLIENT_COMPRESSION: &str = "ballista.client.compression";
+
+const COMPRESSION_METADATA: &str = "x-ballista-compression";
+const ZSTD_LEVEL: i32 = 1;
+
+/// Encodes the bodies of IPC-encoded batch messages
+pub trait BatchCodec: Send + Sync {
+    /// Name of the codec, which clients request it with and which is recorded with the
+    /// batches that it encoded
+    fn name(&self) -> &'static str;
+
+    /// Encode the body of a batch message
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>>;
+
+    /// Decode a body that was encoded with `encode()`
+    fn decode(&self, body: &[u8]) -> Result<Vec<u8>>;
+}
+
+struct Lz4Codec;
+
+impl BatchCodec for Lz4Codec {
+    fn name(&self) -> &'static str {
+        "lz4"
+    }
+
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>> {
+        Ok(lz4::block::compress(body, None, true)?)
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Vec<u8>> {
+        Ok(lz4::block::decompress(body, None)?)
+    }
+}
+
+struct ZstdCodec;
+
+impl BatchCodec for ZstdCodec {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>> {
+        Ok(zstd::encode_all(body, ZSTD_LEVEL)?)
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Vec<u8>> {
+        Ok(zstd::decode_all(body)?)
+    }
+}
+
+/// Stores each little-endian 64-bit word as its wrapping difference from the previous word,
+/// followed by the bytes after the last whole word, compressed with zstd
+struct DeltaCodec;
+
+impl BatchCodec for DeltaCodec {
+    fn name(&self) -> &'static str {
+        "delta"
+    }
+
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>> {
+        let mut deltas = Vec::with_capacity(body.len());
+        let mut previous = 0u64;
+        let words = body.chunks_exact(8);
+        let rest = words.remainder();
+        for word in words {
+            let value = u64::from_le_bytes(word.try_into().unwrap());
+            deltas.extend_from_slice(&value.wrapping_sub(previous).to_le_bytes());
+            previous = value;
+        }
+        deltas.extend_from_slice(rest);
+        Ok(zstd::encode_all(&deltas[..], ZSTD_LEVEL)?)
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Vec<u8>> {
+        let mut values = zstd::decode_all(body)?;
+        let mut previous = 0u64;
+        for word in values.chunks_exact_mut(8) {
+            let delta = u64::from_le_bytes((&*word).try_into().unwrap());
+            previous = previous.wrapping_add(delta);
+            word.copy_from_slice(&previous.to_le_bytes());
+        }
+        Ok(values)
+    }
+}
+
+lazy_static! {
+    /// The codecs that Ballista implements are always registered
+    static ref CODECS: RwLock<HashMap<&'static str, Arc<dyn BatchCodec>>> = {
+        let mut codecs: HashMap<&'static str, Arc<dyn BatchCodec>> = HashMap::new();
+        for codec in vec![
+            Arc::new(Lz4Codec) as Arc<dyn BatchCodec>,
+            Arc::new(ZstdCodec),
+            Arc::new(DeltaCodec),
+        ] {
+            codecs.insert(codec.name(), codec);
+        }
+        RwLock::new(codecs)
+    };
+}
+
+/// Register a codec that batches can be encoded with, replacing any codec that was
+/// registered with the same name before. `none` is reserved for unencoded batches.
+///
+/// The registry is global to the process, and batches only name their codec, so a custom
+/// codec must be registered both in the processes that write batches with it and in the
+/// processes that read them, such as the clients and executors of a cluster.
+pub fn register_batch_codec(codec: Arc<dyn BatchCodec>) -> Result<()> {
+    if codec.name() == "none" {
+        return Err(ballista_error("The batch codec name 'none' is reserved"));
+    }
+    CODECS
+        .write()
+        .expect("batch codec registry lock poisoned")
+        .insert(codec.name(), codec);
+    Ok(())
+}
+
+/// The codec that is registered with the given name
+fn batch_codec(name: &str) -> Option<Arc<dyn BatchCodec>> {
+    CODECS
+        .read()
+        .expect("batch codec registry lock poisoned")
+        .get(name)
+        .cloned()
+}
+
+/// Codec for IPC-encoded batches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchCompression {
+    Uncompressed,
+    Lz4,
+    Zstd,
+    /// A codec that is registered by name, such as `delta`
+    Codec(&'static str),
+}
+
+impl Default for BatchCompression {
+    fn default() -> Self {
+        BatchCompression::Uncompressed
+    }
+}
+
+impl BatchCompression {
+    /// Parse a codec name as produced by `name()`
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "none" => Ok(BatchCompression::Uncompressed),
+            "lz4" => Ok(BatchCompression::Lz4),
+            "zstd" => Ok(BatchCompression::Zstd),
+            other => match batch_codec(other) {
+                Some(codec) => Ok(BatchCompression::Codec(codec.name())),
+                None => Err(ballista_error(&format!(
+                    "Unsupported batch compression '{}'",
+                    other
+                ))),
+            },
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BatchCompression::Uncompressed => "none",
+            BatchCompression::Lz4 => "lz4",
+            BatchCompression::Zstd => "zstd",
+            BatchCompression::Codec(name) => name,
+        }
+    }
+
+    /// Read the codec from the Context settings, defaulting to no compression
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self> {
+        match settings.get(CLIENT_COMPRESSION) {
+            Some(name) => Self::from_name(name),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Ask the executor to compress the results of a request
+    pub fn request<T>(&self, request: &mut Request<T>) -> Result<()> {
+        if *self != BatchCompression::Uncompressed {
+            let value = self
+                .name()
+                .parse()
+                .map_err(|_| ballista_error("Invalid compression metadata"))?;
+            request.metadata_mut().insert(COMPRESSION_METADATA, value);
+        }
+        Ok(())
+    }
+
+    /// Determine the codec requested by a client. Unknown codecs are ignored so that the
+    /// results are sent uncompressed.
+    pub fn requested(metadata: &MetadataMap) -> Self {
+        metadata
+            .get(COMPRESSION_METADATA)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|name| Self::from_name(name).ok())
+            .unwrap_or_default()
+    }
+
+    /// Encode the body of a batch message
+    pub fn compress(&self, mut data: FlightData) -> Result<FlightData> {
+        if *self == BatchCompression::Uncompressed {
+            return Ok(data);
+        }
+        let codec = batch_codec(self.name()).ok_or_else(|| {
+            ballista_error(&format!(
+                "No batch codec is registered as '{}'",
+                self.name()
+            ))
+        })?;
+        data.data_body = codec.encode(&data.data_body)?;
+        data.app_metadata = codec.name().as_bytes().to_vec();
+        Ok(data)
+    }
+
+    /// Decode the body of a batch message if it was encoded with a registered codec
+    pub fn decompress(mut data: FlightData) -> Result<FlightData> {
+        let codec = match std::str::from_utf8(&data.app_metadata)
+            .ok()
+            .and_then(batch_codec)
+        {
+            Some(codec) => codec,
+            None => return Ok(data),
+        };
+        data.data_body = codec.decode(&data.data_body).map_err(|e| {
+            BallistaError::General(format!(
+                "Unable to decompress {} batch: {:?}",
+                codec.name(),
+                e
+            ))
+        })?;
+        data.app_metadata.clear();
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let body: Vec<u8> = "abc".repeat(1000).into_bytes();
+        let delta = BatchCompression::from_name("delta")?;
+        for codec in &[BatchCompression::Lz4, BatchCompression::Zstd, delta] {
+            let data = FlightData {
+                data_body: body.clone(),
+                ..Default::default()
+            };
+            let compressed = codec.compress(data)?;
+            assert!(compressed.data_body.len() < body.len());
+            assert_eq!(body, BatchCompression::decompress(compressed)?.data_body);
+        }
+
+        let mut request = Request::new(());
+        BatchCompression::Zstd.request(&mut request)?;
+        assert_eq!(
+            BatchCompression::Zstd,
+            BatchCompression::requested(request.metadata())
+        );
+        Ok(())
+    }
+
+    /// Reverses the bytes of the body
+    struct ReverseCodec;
+
+    impl BatchCodec for ReverseCodec {
+        fn name(&self) -> &'static str {
+            "reverse"
+        }
+
+        fn encode(&self, body: &[u8]) -> Result<Vec<u8>> {
+            Ok(body.iter().rev().cloned().collect())
+        }
+
+        fn decode(&self, body: &[u8]) -> Result<Vec<u8>> {
+            self.encode(body)
+        }
+    }
+
+    #[test]
+    fn registered_codec() -> Result<()> {
+        assert!(BatchCompression::from_name("reverse").is_err());
+        register_batch_codec(Arc::new(ReverseCodec))?;
+        let codec = BatchCompression::from_name("reverse")?;
+        assert_eq!("reverse", codec.name());
+
+        let data = FlightData {
+            data_body: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let encoded = codec.compress(data)?;
+        assert_eq!(vec![3, 2, 1], encoded.data_body);
+        assert_eq!(
+            vec![1, 2, 3],
+            BatchCompression::decompress(encoded)?.data_body
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! Avro data source.
+//!
+//! The Arrow schema is derived from the writer schema embedded in the Avro file header, so
+//! no schema needs to be provided when reading Avro files.
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{BallistaError, Result};
+
+use avro_rs::types::Value;
+use avro_rs::{Reader, Schema as AvroSchema};
+
+/// Read the Arrow schema from the header of an Avro file
+pub fn avro_schema(path: &str) -> Result<Schema> {
+    let reader = Reader::new(File::open(path)?).map_err(avro_error)?;
+    to_arrow_schema(reader.writer_schema())
+}
+
+/// Convert an Avro record schema to an Arrow schema
+pub fn to_arrow_schema(schema: &AvroSchema) -> Result<Schema> {
+    match schema {
+        AvroSchema::Record { fields, .. } => Ok(Schema::new(
+            fields
+                .iter()
+                .map(|f| {
+                    let (data_type, nullable) = to_arrow_type(&f.schema)?;
+                    Ok(Field::new(&f.name, data_type, nullable))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )),
+        other => Err(BallistaError::General(format!(
+            "Avro files must contain records but the schema is {:?}",
+            other
+        ))),
+    }
+}
+
+/// Convert an Avro type to an Arrow type, returning whether the type is nullable
+fn to_arrow_type(schema: &AvroSchema) -> Result<(DataType, bool)> {
+    match schema {
+        AvroSchema::Boolean => Ok((DataType::Boolean, false)),
+        AvroSchema::Int => Ok((DataType::Int32, false)),
+        AvroSchema::Long => Ok((DataType::Int64, false)),
+        AvroSchema::Float => Ok((DataType::Float32, false)),
+        AvroSchema::Double => Ok((DataType::Float64, false)),
+        AvroSchema::Bytes | AvroSchema::Fixed { .. } => Ok((DataType::Binary, false)),
+        AvroSchema::String | AvroSchema::Enum { .. } | AvroSchema::Uuid => {
+            Ok((DataType::Utf8, false))
+        }
+        AvroSchema::Date => Ok((DataType::Date32(DateUnit::Day), false)),
+        AvroSchema::TimestampMillis => {
+            Ok((DataType::Timestamp(TimeUnit::Millisecond, None), false))
+        }
+        AvroSchema::TimestampMicros => {
+            Ok((DataType::Timestamp(TimeUnit::Microsecond, None), false))
+        }
+        AvroSchema::Union(union) => {
+            // only the common [null, T] union used for optional values is supported
+            let variants: Vec<&AvroSchema> = union
+                .variants()
+                .iter()
+                .filter(|s| **s != AvroSchema::Null)
+                .collect();
+            match variants.as_slice() {
+                [variant] => Ok((to_arrow_type(variant)?.0, true)),
+                _ => Err(BallistaError::NotImplemented(format!(
+                    "Avro union type {:?}",
+                    schema
+                ))),
+            }
+        }
+        other => Err(BallistaError::NotImplemented(format!(
+            "Avro type {:?}",
+            other
+        ))),
+    }
+}
+
+/// Read an Avro file into record batches
+pub fn read_avro_batches(
+    path: &str,
+    schema: &Schema,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let reader = Reader::new(File::open(path)?).map_err(avro_error)?;
+    let schema = Arc::new(schema.clone());
+
+    let mut batches = vec![];
+    let mut rows: Vec<Vec<(String, Value)>> = Vec::with_capacity(batch_size);
+    for value in reader {
+        match value.map_err(avro_error)? {
+            Value::Record(fields) => rows.push(fields),
+            other => {
+                return Err(BallistaError::General(format!(
+                    "Expected Avro record but found {:?}",
+                    other
+                )))
+            }
+        }
+        if rows.len() == batch_size {
+            batches.push(build_batch(&schema, &rows)?);
+            rows.clear();
+        }
+    }
+    if !rows.is_empty() {
+        batches.push(build_batch(&schema, &rows)?);
+    }
+    Ok(batches)
+}
+
+pub(crate) fn build_batch(
+    schema: &Arc<Schema>,
+    rows: &[Vec<(String, Value)>],
+) -> Result<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let values: Vec<&Value> = rows.iter().map(|row| unwrap_union(&row[i].1)).collect();
+            build_array(field.data_type(), &values)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn unwrap_union(value: &Value) -> &Value {
+    match value {
+        Value::Union(v) => v.as_ref(),
+        other => other,
+    }
+}
+
+macro_rules! build_primitive_array {
+    ($VALUES:expr, $BUILDER:ident, $($PATTERN:pat => $VALUE:expr),+) => {{
+        let mut builder = $BUILDER::new($VALUES.len());
+        for value in $VALUES {
+            match value {
+                $($PATTERN => builder.append_value($VALUE)?,)+
+                Value::Null => builder.append_null()?,
+                other => return Err(unexpected_value(other)),
+            }
+        }
+        Ok(Arc::new(builder.finish()) as ArrayRef)
+    }};
+}
+
+fn build_array(data_type: &DataType, values: &[&Value]) -> Result<ArrayRef> {
+    match data_type {
+        DataType::Boolean => {
+            build_primitive_array!(values, BooleanBuilder, Value::Boolean(v) => *v)
+        }
+        DataType::Int32 => build_primitive_array!(values, Int32Builder, Value::Int(v) => *v),
+        DataType::Int64 => build_primitive_array!(values, Int64Builder, Value::Long(v) => *v),
+        DataType::Float32 => build_primitive_array!(values, Float32Builder, Value::Float(v) => *v),
+        DataType::Float64 => build_primitive_array!(values, Float64Builder, Value::Double(v) => *v),
+        DataType::Date32(DateUnit::Day) => build_primitive_array!(values, Date32Builder,
+            Value::Date(v) => *v,
+            Value::Int(v) => *v
+        ),
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            build_primitive_array!(values, TimestampMillisecondBuilder,
+                Value::TimestampMillis(v) => *v,
+                Value::Long(v) => *v
+            )
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            build_primitive_array!(values, TimestampMicrosecondBuilder,
+                Value::TimestampMicros(v) => *v,
+                Value::Long(v) => *v
+            )
+        }
+        DataType::Utf8 => build_primitive_array!(values, StringBuilder,
+            Value::String(v) => v,
+            Value::Enum(_, v) => v,
+            Value::Uuid(v) => &v.to_string()
+        ),
+        DataType::Binary => build_primitive_array!(values, BinaryBuilder,
+            Value::Bytes(v) => v,
+            Value::Fixed(_, v) => v
+        ),
+        other => Err(BallistaError::NotImplemented(format!(
+            "Avro reader does not support data type {:?}",
+            other
+        ))),
+    }
+}
+
+fn unexpected_value(value: &Value) -> BallistaError {
+    BallistaError::General(format!("Unexpected Avro value {:?}", value))
+}
+
+pub(crate) fn avro_error<E: Debug>(e: E) -> BallistaError {
+    BallistaError::General(format!("Avro error: {:?}", e))
+}
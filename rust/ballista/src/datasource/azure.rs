@@ -0,0 +1,399 @@
+//! Azure Blob Storage support using the Blob service REST API (requires the `azure`
+//! feature).
+//!
+//! Both `wasb[s]://container@account.blob.core.windows.net/path` and
+//! `abfs[s]://filesystem@account.dfs.core.windows.net/path` URLs are accessed through the
+//! blob endpoint of the storage account. Requests are authorized with either a SAS token
+//! or a Shared Key signature using the account key.
+
+use std::collections::HashMap;
+
+use crate::datasource::object_store::{read_partial_body, ObjectStore};
+use crate::datasource::with_retry;
+use crate::error::{ballista_error, BallistaError, Result};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_LENGTH};
+use reqwest::Method;
+use sha2::Sha256;
+
+pub const AZURE_ACCOUNT_KEY: &str = "ballista.azure.accountKey";
+pub const AZURE_SAS_TOKEN: &str = "ballista.azure.sasToken";
+pub const AZURE_MAX_RETRIES: &str = "ballista.azure.maxRetries";
+
+const API_VERSION: &str = "2019-12-12";
+
+/// Azure Blob Storage connection settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct AzureConfig {
+    /// Base64 encoded storage account key
+    pub account_key: Option<String>,
+    /// Shared access signature, used instead of the account key when set
+    pub sas_token: Option<String>,
+    pub max_retries: usize,
+}
+
+impl AzureConfig {
+    /// Read the Azure configuration from the Context settings
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self> {
+        let max_retries = match settings.get(AZURE_MAX_RETRIES) {
+            Some(n) => n.parse::<usize>().map_err(|_| {
+                ballista_error(&format!("Invalid value for {}: {}", AZURE_MAX_RETRIES, n))
+            })?,
+            None => 3,
+        };
+        Ok(Self {
+            account_key: settings.get(AZURE_ACCOUNT_KEY).cloned(),
+            sas_token: settings
+                .get(AZURE_SAS_TOKEN)
+                .map(|t| t.trim_start_matches('?').to_owned()),
+            max_retries,
+        })
+    }
+}
+
+/// The location of a blob
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobPath {
+    pub account: String,
+    pub container: String,
+    pub name: String,
+}
+
+/// Determine whether a path refers to Azure Blob Storage
+pub fn is_azure_path(path: &str) -> bool {
+    ["wasb://", "wasbs://", "abfs://", "abfss://"]
+        .iter()
+        .any(|scheme| path.starts_with(scheme))
+}
+
+/// Parse a `wasb[s]://` or `abfs[s]://` URL
+pub fn parse_azure_path(path: &str) -> Result<BlobPath> {
+    let invalid = || ballista_error(&format!("Invalid Azure Blob Storage path: {}", path));
+    if !is_azure_path(path) {
+        return Err(invalid());
+    }
+    let rest = &path[path.find("://").ok_or_else(invalid)? + 3..];
+    let (authority, name) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => (rest, ""),
+    };
+    let mut parts = authority.splitn(2, '@');
+    let container = parts.next().unwrap_or_default();
+    let host = parts.next().ok_or_else(invalid)?;
+    let account = host.split('.').next().unwrap_or_default();
+    if container.is_empty() || account.is_empty() {
+        return Err(invalid());
+    }
+    Ok(BlobPath {
+        account: account.to_owned(),
+        container: container.to_owned(),
+        name: name.to_owned(),
+    })
+}
+
+/// List the blobs under a prefix, returning URLs with the same scheme as the path
+pub fn list_blobs(path: &str, config: &AzureConfig) -> Result<Vec<String>> {
+    let blob_path = parse_azure_path(path)?;
+    let mut base = path[..path.len() - blob_path.name.len()].to_owned();
+    if !base.ends_with('/') {
+        base.push('/');
+    }
+    let client = reqwest::Client::new();
+
+    let mut blobs = vec![];
+    let mut marker: Option<String> = None;
+    loop {
+        let mut query = vec![
+            ("comp".to_owned(), "list".to_owned()),
+            ("prefix".to_owned(), blob_path.name.clone()),
+            ("restype".to_owned(), "container".to_owned()),
+        ];
+        if let Some(marker) = &marker {
+            query.push(("marker".to_owned(), marker.clone()));
+        }
+        let container = BlobPath {
+            name: "".to_owned(),
+            ..blob_path.clone()
+        };
+        let body = with_retry(config.max_retries, || {
+            Ok(send(&client, config, Method::GET, &container, &query, None)?.text()?)
+        })?;
+
+        for blob in xml_elements(&body, "Blob") {
+            if let Some(name) = xml_elements(blob, "Name").first() {
+                let name = xml_unescape(name);
+                if !name.ends_with('/') {
+                    blobs.push(format!("{}{}", base, name));
+                }
+            }
+        }
+        match xml_elements(&body, "NextMarker").first() {
+            Some(next) if !next.is_empty() => marker = Some(xml_unescape(next)),
+            _ => break,
+        }
+    }
+    blobs.sort();
+    Ok(blobs)
+}
+
+/// Object store for Azure Blob Storage
+#[derive(Debug)]
+pub struct AzureStore {
+    config: AzureConfig,
+}
+
+impl AzureStore {
+    pub fn new(config: AzureConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl ObjectStore for AzureStore {
+    fn list(&self, path: &str) -> Result<Vec<String>> {
+        list_blobs(path, &self.config)
+    }
+
+    fn size(&self, path: &str) -> Result<u64> {
+        let blob_path = parse_azure_path(path)?;
+        let client = reqwest::Client::new();
+        let response = with_retry(self.config.max_retries, || {
+            send(&client, &self.config, Method::HEAD, &blob_path, &[], None)
+        })?;
+        response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| ballista_error(&format!("Unable to determine the size of {}", path)))
+    }
+
+    fn read_range(&self, path: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+        let blob_path = parse_azure_path(path)?;
+        let range = format!("bytes={}-{}", start, start + len - 1);
+        let client = reqwest::Client::new();
+        with_retry(self.config.max_retries, || {
+            let mut response = send(
+                &client,
+                &self.config,
+                Method::GET,
+                &blob_path,
+                &[],
+                Some(("x-ms-range", &range)),
+            )?;
+            read_partial_body(&mut response)
+        })
+    }
+
+    /// Objects are written as block blobs
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        let blob_path = parse_azure_path(path)?;
+        let config = &self.config;
+        let client = reqwest::Client::new();
+        with_retry(config.max_retries, || {
+            let len = data.len().to_string();
+            let url = blob_url(&blob_path, &[], config);
+            let mut headers = HeaderMap::new();
+            headers.insert("x-ms-blob-type", HeaderValue::from_static("BlockBlob"));
+            if config.sas_token.is_none() {
+                sign(&mut headers, config, &Method::PUT, &blob_path, &[], &len)?;
+            }
+            client
+                .put(&url)
+                .headers(headers)
+                .body(data.to_vec())
+                .send()?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Send a request without a body
+fn send(
+    client: &reqwest::Client,
+    config: &AzureConfig,
+    method: Method,
+    blob_path: &BlobPath,
+    query: &[(String, String)],
+    extra_header: Option<(&'static str, &str)>,
+) -> Result<reqwest::Response> {
+    let mut headers = HeaderMap::new();
+    if let Some((name, value)) = extra_header {
+        headers.insert(name, HeaderValue::from_str(value).map_err(azure_error)?);
+    }
+    if config.sas_token.is_none() {
+        sign(&mut headers, config, &method, blob_path, query, "")?;
+    }
+    let url = blob_url(blob_path, query, config);
+    Ok(client
+        .request(method, &url)
+        .headers(headers)
+        .send()?
+        .error_for_status()?)
+}
+
+fn blob_url(blob_path: &BlobPath, query: &[(String, String)], config: &AzureConfig) -> String {
+    let mut url = format!(
+        "https://{}.blob.core.windows.net/{}",
+        blob_path.account, blob_path.container
+    );
+    if !blob_path.name.is_empty() {
+        url.push('/');
+        url.push_str(&blob_path.name);
+    }
+    let mut params: Vec<String> = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    if let Some(sas_token) = &config.sas_token {
+        params.push(sas_token.clone());
+    }
+    if !params.is_empty() {
+        url.push('?');
+        url.push_str(&params.join("&"));
+    }
+    url
+}
+
+/// Add the date, version and Shared Key authorization headers to a request
+fn sign(
+    headers: &mut HeaderMap,
+    config: &AzureConfig,
+    method: &Method,
+    blob_path: &BlobPath,
+    query: &[(String, String)],
+    content_length: &str,
+) -> Result<()> {
+    let account_key = config.account_key.as_ref().ok_or_else(|| {
+        ballista_error(&format!(
+            "Azure credentials are not configured, set {} or {}",
+            AZURE_ACCOUNT_KEY, AZURE_SAS_TOKEN
+        ))
+    })?;
+    let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    headers.insert(
+        "x-ms-date",
+        HeaderValue::from_str(&date).map_err(azure_error)?,
+    );
+    headers.insert("x-ms-version", HeaderValue::from_static(API_VERSION));
+
+    let string_to_sign = string_to_sign(headers, method, blob_path, query, content_length);
+    let key = base64::decode(account_key).map_err(azure_error)?;
+    let mut mac = Hmac::<Sha256>::new_varkey(&key).map_err(azure_error)?;
+    mac.input(string_to_sign.as_bytes());
+    let signature = base64::encode(&mac.result().code());
+
+    let authorization = format!("SharedKey {}:{}", blob_path.account, signature);
+    headers.insert(
+        AUTHORIZATION,
+        HeaderValue::from_str(&authorization).map_err(azure_error)?,
+    );
+    Ok(())
+}
+
+/// Build the string to sign for Shared Key authorization
+fn string_to_sign(
+    headers: &HeaderMap,
+    method: &Method,
+    blob_path: &BlobPath,
+    query: &[(String, String)],
+    content_length: &str,
+) -> String {
+    let mut ms_headers: Vec<(String, String)> = headers
+        .iter()
+        .filter(|(name, _)| name.as_str().starts_with("x-ms-"))
+        .map(|(name, value)| {
+            (
+                name.as_str().to_owned(),
+                value.to_str().unwrap_or_default().trim().to_owned(),
+            )
+        })
+        .collect();
+    ms_headers.sort();
+
+    let mut resource = format!("/{}/{}", blob_path.account, blob_path.container);
+    if !blob_path.name.is_empty() {
+        resource.push('/');
+        resource.push_str(&blob_path.name);
+    }
+    let mut query = query.to_vec();
+    query.sort();
+    for (name, value) in query {
+        resource.push_str(&format!("\n{}:{}", name.to_lowercase(), value));
+    }
+
+    // VERB, Content-Encoding, Content-Language, Content-Length, Content-MD5, Content-Type,
+    // Date, If-Modified-Since, If-Match, If-None-Match, If-Unmodified-Since, Range
+    let mut s = format!(
+        "{}\n\n\n{}\n\n\n\n\n\n\n\n\n",
+        method.as_str(),
+        content_length
+    );
+    for (name, value) in ms_headers {
+        s.push_str(&format!("{}:{}\n", name, value));
+    }
+    s.push_str(&resource);
+    s
+}
+
+/// Find the contents of all elements with the given tag name
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut elements = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let content = &rest[start + open.len()..];
+        match content.find(&close) {
+            Some(end) => {
+                elements.push(&content[..end]);
+                rest = &content[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    elements
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn azure_error<E: std::fmt::Debug>(e: E) -> BallistaError {
+    BallistaError::General(format!("Azure error: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_paths() -> Result<()> {
+        let path = parse_azure_path("abfss://data@myaccount.dfs.core.windows.net/a/b.parquet")?;
+        assert_eq!("myaccount", path.account);
+        assert_eq!("data", path.container);
+        assert_eq!("a/b.parquet", path.name);
+
+        let path = parse_azure_path("wasb://logs@myaccount.blob.core.windows.net")?;
+        assert_eq!("logs", path.container);
+        assert_eq!("", path.name);
+
+        assert!(parse_azure_path("wasb://myaccount.blob.core.windows.net/a").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_list_response() {
+        let xml = "<EnumerationResults><Blobs><Blob><Name>a&amp;b.csv</Name></Blob>\
+                   <Blob><Name>c.csv</Name></Blob></Blobs><NextMarker /></EnumerationResults>";
+        let names: Vec<String> = xml_elements(xml, "Blob")
+            .iter()
+            .map(|b| xml_unescape(xml_elements(b, "Name")[0]))
+            .collect();
+        assert_eq!(vec!["a&b.csv", "c.csv"], names);
+        assert!(xml_elements(xml, "NextMarker").is_empty());
+    }
+}
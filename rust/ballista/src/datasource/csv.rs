@@ -0,0 +1,992 @@
+//! A dialect-aware CSV reader.
+//!
+//! DataFusion's CSV data source only understands the default dialect (comma separated,
+//! double-quoted, no escapes) of uncompressed files, so files that need any of the options
+//! in `CsvReadOptions` are parsed here and handed to DataFusion as in-memory batches.
+//!
+//! Scans of several files, or of files that are split into byte ranges to read them as more
+//! partitions, are also read here, as a table with a partition for each split. Scans of
+//! several paths read the files of every path as one scan, with the inferred schemas of the
+//! paths merged.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, SchemaRef, TimeUnit};
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datafusion::datasource::TableProvider;
+use crate::datafusion::error::{ExecutionError, Result as DataFusionResult};
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::ipc::ProjectedReader;
+use crate::datasource::parquet::merge_schemas;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::physical_plan::memory::MemoryReader;
+use crate::timezone::{parse_timezone, to_utc};
+
+use bzip2::read::BzDecoder;
+use chrono::{FixedOffset, NaiveDate, NaiveDateTime};
+use flate2::read::MultiGzDecoder;
+
+pub use crate::file_scan::{CsvCompression, CsvReadOptions};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Number of records that are sampled to infer the schema of a CSV file
+pub const CSV_SCHEMA_INFER_RECORDS: usize = 1000;
+
+/// Open a file, decompressing it according to the options
+fn open(path: &str, options: &CsvReadOptions) -> Result<Box<dyn Read>> {
+    let file = File::open(path).map_err(|e| BallistaError::from(e).in_file(path))?;
+    Ok(match options.compression_for(path) {
+        CsvCompression::Uncompressed => Box::new(file),
+        CsvCompression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        CsvCompression::Bzip2 => Box::new(BzDecoder::new(file)),
+        CsvCompression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+    })
+}
+
+/// Read a CSV file into record batches using the given options. Compressed files are
+/// decompressed while they are read.
+pub fn read_csv_batches(
+    path: &str,
+    schema: &Schema,
+    has_header: bool,
+    options: &CsvReadOptions,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    read_csv(
+        path,
+        open(path, options)?,
+        schema,
+        has_header,
+        options,
+        batch_size,
+    )
+}
+
+/// Infer the schema of a CSV file from its header and the values of its first records.
+/// Each column has the narrowest type that all of its sampled values can be read as, or
+/// `Utf8` if it has no values, and every column is nullable. Columns of files without a
+/// header are named `column_1`, `column_2` and so on.
+pub fn infer_csv_schema(
+    path: &str,
+    has_header: bool,
+    options: &CsvReadOptions,
+    max_records: usize,
+) -> Result<Schema> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .escape(options.escape)
+        .double_quote(options.escape.is_none())
+        .comment(options.comment)
+        .has_headers(has_header)
+        .flexible(true)
+        .from_reader(open(path, options)?);
+    let read_error = |e: csv::Error| ballista_error(&format!("Failed to read {}: {:?}", path, e));
+
+    let names: Vec<String> = if has_header {
+        reader
+            .headers()
+            .map_err(read_error)?
+            .iter()
+            .map(|name| name.to_owned())
+            .collect()
+    } else {
+        vec![]
+    };
+    let mut columns: Vec<InferredType> = vec![];
+    for record in reader.records().take(max_records) {
+        let record = record.map_err(read_error)?;
+        if columns.len() < record.len() {
+            columns.resize(record.len(), InferredType::new());
+        }
+        for (column, value) in columns.iter_mut().zip(record.iter()) {
+            if !value.is_empty() && !options.is_null(value) {
+                column.update(value, options);
+            }
+        }
+    }
+    if columns.len() < names.len() {
+        columns.resize(names.len(), InferredType::new());
+    }
+
+    let fields = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let name = names
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| format!("column_{}", i + 1));
+            Field::new(&name, column.data_type(), true)
+        })
+        .collect();
+    Ok(Schema::new(fields))
+}
+
+/// The schema that files with the inferred schemas are all read with. CSV files are read by
+/// position, so they must have the same columns in the same order, and the types of each
+/// column are promoted to a type that the values of every file can be read as.
+pub fn merge_csv_schemas(schemas: &[Schema]) -> Result<Schema> {
+    fn names(schema: &Schema) -> Vec<&String> {
+        schema.fields().iter().map(|f| f.name()).collect()
+    }
+    if let Some(first) = schemas.first() {
+        if let Some(other) = schemas.iter().find(|s| names(s) != names(first)) {
+            return Err(BallistaError::General(format!(
+                "CSV files have different columns: {:?} and {:?}",
+                names(first),
+                names(other)
+            )));
+        }
+    }
+    merge_schemas(schemas)
+}
+
+/// The types that all of the values of a column seen so far can be read as
+#[derive(Debug, Clone)]
+struct InferredType {
+    any_values: bool,
+    boolean: bool,
+    int: bool,
+    float: bool,
+    date: bool,
+    timestamp: bool,
+}
+
+impl InferredType {
+    fn new() -> Self {
+        Self {
+            any_values: false,
+            boolean: true,
+            int: true,
+            float: true,
+            date: true,
+            timestamp: true,
+        }
+    }
+
+    fn update(&mut self, value: &str, options: &CsvReadOptions) {
+        let trimmed = value.trim();
+        let date_format = options
+            .date_format
+            .as_deref()
+            .unwrap_or(DEFAULT_DATE_FORMAT);
+        let timestamp_format = options
+            .timestamp_format
+            .as_deref()
+            .unwrap_or(DEFAULT_TIMESTAMP_FORMAT);
+        self.any_values = true;
+        self.boolean &= ["true", "false"].contains(&trimmed.to_lowercase().as_str());
+        self.int &= trimmed.parse::<i64>().is_ok();
+        self.float &= trimmed.parse::<f64>().is_ok();
+        self.date &= NaiveDate::parse_from_str(value, date_format).is_ok();
+        self.timestamp &= NaiveDateTime::parse_from_str(value, timestamp_format).is_ok();
+    }
+
+    fn data_type(&self) -> DataType {
+        if !self.any_values {
+            DataType::Utf8
+        } else if self.boolean {
+            DataType::Boolean
+        } else if self.int {
+            DataType::Int64
+        } else if self.float {
+            DataType::Float64
+        } else if self.date {
+            DataType::Date32(DateUnit::Day)
+        } else if self.timestamp {
+            DataType::Timestamp(TimeUnit::Millisecond, None)
+        } else {
+            DataType::Utf8
+        }
+    }
+}
+
+/// A part of a CSV file that is read as a partition
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvSplit {
+    pub path: String,
+    /// The byte range of the file whose records are read, with the records that start
+    /// within the range belonging to the split, or `None` for the whole file
+    pub range: Option<(u64, u64)>,
+}
+
+/// Split CSV files into the partitions that they are read as. Each file is a partition
+/// unless there are fewer files than the requested partitions, in which case uncompressed
+/// files that are larger than an even share of the bytes are split into byte ranges of that
+/// size. The records that a range reads are only found once it is read (see `CsvSplit`), so
+/// splitting a file does not read it.
+pub fn csv_splits(files: &[String], options: &CsvReadOptions) -> Result<Vec<CsvSplit>> {
+    let partitions = options.partitions.unwrap_or(1);
+    let whole = |path: &String| CsvSplit {
+        path: path.clone(),
+        range: None,
+    };
+    if partitions <= files.len() {
+        return Ok(files.iter().map(whole).collect());
+    }
+    let sizes = files
+        .iter()
+        .map(|f| fs::metadata(f).map(|m| m.len()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let total: u64 = sizes.iter().sum();
+    let target = ((total + partitions as u64 - 1) / partitions as u64).max(1);
+
+    let mut splits = vec![];
+    for (file, size) in files.iter().zip(sizes) {
+        if size <= target || options.compression_for(file) != CsvCompression::Uncompressed {
+            splits.push(whole(file));
+            continue;
+        }
+        let mut start = 0;
+        while start < size {
+            let end = (start + target).min(size);
+            splits.push(CsvSplit {
+                path: file.clone(),
+                range: Some((start, end)),
+            });
+            start = end;
+        }
+    }
+    Ok(splits)
+}
+
+/// Whether the bytes of a file up to some offset leave it inside a quoted field or a comment
+/// line, for one guess of whether the offset was inside a quoted field
+#[derive(Debug, Clone, Copy)]
+struct QuoteState {
+    quoted: bool,
+    escaped: bool,
+    comment: bool,
+    line_start: bool,
+    /// Whether a quote has been seen that could not be in this state's position
+    contradicted: bool,
+    /// The first record start found in this state
+    record_start: Option<u64>,
+}
+
+impl QuoteState {
+    fn new(quoted: bool) -> Self {
+        Self {
+            quoted,
+            escaped: false,
+            comment: false,
+            line_start: false,
+            contradicted: false,
+            record_start: None,
+        }
+    }
+
+    /// Follow a byte at a position, given the bytes before and after it
+    fn follow(
+        &mut self,
+        byte: u8,
+        position: u64,
+        previous: u8,
+        next: Option<u8>,
+        options: &CsvReadOptions,
+    ) {
+        let line_start = self.line_start;
+        self.line_start = false;
+        if self.comment {
+            if byte == b'\n' {
+                self.end_record(position);
+            }
+        } else if self.escaped {
+            self.escaped = false;
+        } else if self.quoted && Some(byte) == options.escape {
+            self.escaped = true;
+        } else if byte == options.quote {
+            let separator = |b: u8| b == options.delimiter || b == b'\n' || b == b'\r';
+            let next_is_separator = next.map_or(true, separator);
+            // a quote between a separator and a value can only open a field, and one between
+            // a value and a separator can only close one, while doubled quotes could be either
+            let doubled = previous == options.quote || next == Some(options.quote);
+            let escaped = options.escape.is_some() && Some(previous) == options.escape;
+            if !doubled && !escaped {
+                if separator(previous) && !next_is_separator && self.quoted {
+                    self.contradicted = true;
+                } else if !separator(previous) && next_is_separator && !self.quoted {
+                    self.contradicted = true;
+                }
+            }
+            // doubled quotes toggle twice, leaving the field quoted
+            self.quoted = !self.quoted;
+        } else if line_start && Some(byte) == options.comment && !self.quoted {
+            self.comment = true;
+        } else if byte == b'\n' && !self.quoted {
+            self.end_record(position);
+        }
+    }
+
+    fn end_record(&mut self, position: u64) {
+        self.comment = false;
+        self.line_start = true;
+        if self.record_start.is_none() {
+            self.record_start = Some(position + 1);
+        }
+    }
+}
+
+/// Number of bytes after the first possible record start that are searched for a quote that
+/// decides whether the start of a range is inside a quoted field
+const QUOTE_SEARCH_BYTES: u64 = 64 * 1024;
+
+/// The offset of the first record of an uncompressed CSV file that starts at or after an
+/// offset, or the size of the file if no record starts there. The quotes before the offset
+/// are not read, so the bytes from the offset are followed both as if the offset were inside
+/// a quoted field and as if it were not, until a quote is found that can only open or only
+/// close a field. If no such quote is found soon after the first line break, the offset is
+/// taken to be outside quoted fields. Comment lines are skipped, so quotes in comments are
+/// ignored.
+fn record_start(
+    reader: &mut BufReader<File>,
+    offset: u64,
+    options: &CsvReadOptions,
+) -> Result<u64> {
+    if offset == 0 {
+        return Ok(0);
+    }
+    // the byte before the offset is followed so that a record that starts at the offset is
+    // found by the line break before it, and the byte before that is only read to decide
+    // what a quote at the offset is
+    let mut previous = b'\n';
+    if offset > 1 {
+        reader.seek(SeekFrom::Start(offset - 2))?;
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        previous = byte[0];
+    } else {
+        reader.seek(SeekFrom::Start(offset - 1))?;
+    }
+    let mut states = [QuoteState::new(false), QuoteState::new(true)];
+    let mut position = offset - 1;
+    let mut bytes = reader.bytes().peekable();
+    while let Some(byte) = bytes.next() {
+        let byte = byte?;
+        let next = match bytes.peek() {
+            Some(Ok(next)) => Some(*next),
+            _ => None,
+        };
+        for state in states.iter_mut() {
+            state.follow(byte, position, previous, next, options);
+        }
+        match (states[0].contradicted, states[1].contradicted) {
+            // a malformed file is read as if the offset were not inside a quoted field
+            (true, true) | (false, true) => {
+                if let Some(start) = states[0].record_start {
+                    return Ok(start);
+                }
+            }
+            (true, false) => {
+                if let Some(start) = states[1].record_start {
+                    return Ok(start);
+                }
+            }
+            (false, false) => {
+                if let Some(start) = states[0].record_start {
+                    if position >= start + QUOTE_SEARCH_BYTES {
+                        return Ok(start);
+                    }
+                }
+            }
+        }
+        previous = byte;
+        position += 1;
+    }
+    let state = if states[0].contradicted && !states[1].contradicted {
+        &states[1]
+    } else {
+        &states[0]
+    };
+    Ok(state.record_start.unwrap_or(position))
+}
+
+/// A table of CSV files that are read as they are scanned, with a partition for each split
+pub struct CsvSplitTable {
+    splits: Vec<CsvSplit>,
+    schema: SchemaRef,
+    has_header: bool,
+    options: CsvReadOptions,
+}
+
+impl CsvSplitTable {
+    /// Create a table for the splits of files that have the given schema
+    pub fn new(
+        splits: Vec<CsvSplit>,
+        schema: SchemaRef,
+        has_header: bool,
+        options: CsvReadOptions,
+    ) -> Self {
+        Self {
+            splits,
+            schema,
+            has_header,
+            options,
+        }
+    }
+}
+
+impl TableProvider for CsvSplitTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let projected_schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok(self
+            .splits
+            .iter()
+            .map(|split| {
+                Arc::new(CsvSplitPartition {
+                    split: split.clone(),
+                    schema: self.schema.clone(),
+                    has_header: self.has_header,
+                    options: self.options.clone(),
+                    batch_size,
+                    projection: projection.clone(),
+                    projected_schema: projected_schema.clone(),
+                }) as Arc<dyn Partition>
+            })
+            .collect())
+    }
+}
+
+/// A split of a `CsvSplitTable`
+struct CsvSplitPartition {
+    split: CsvSplit,
+    schema: SchemaRef,
+    has_header: bool,
+    options: CsvReadOptions,
+    batch_size: usize,
+    projection: Vec<usize>,
+    /// The schema of the projected columns
+    projected_schema: SchemaRef,
+}
+
+impl Partition for CsvSplitPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let batches = read_csv_split(
+            &self.split,
+            &self.schema,
+            self.has_header,
+            &self.options,
+            self.batch_size,
+        )
+        .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+        let reader = MemoryReader::new(self.schema.clone(), batches);
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            Box::new(reader),
+            self.projection.clone(),
+            self.projected_schema.clone(),
+        ))))
+    }
+}
+
+/// Read a CSV split into record batches. Only splits that start at the beginning of a file
+/// have a header.
+pub fn read_csv_split(
+    split: &CsvSplit,
+    schema: &Schema,
+    has_header: bool,
+    options: &CsvReadOptions,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    match split.range {
+        Some(range) => read_csv_range(&split.path, range, schema, has_header, options, batch_size),
+        None => read_csv_batches(&split.path, schema, has_header, options, batch_size),
+    }
+}
+
+/// Read the records of an uncompressed CSV file that start within a byte range. The first
+/// records that start at or after each end of the range are found (see `record_start`), so
+/// that each record is read by exactly one of the ranges of a file.
+fn read_csv_range(
+    path: &str,
+    (start, end): (u64, u64),
+    schema: &Schema,
+    has_header: bool,
+    options: &CsvReadOptions,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let file = File::open(path).map_err(|e| BallistaError::from(e).in_file(path))?;
+    let mut reader = BufReader::new(file);
+    let first = record_start(&mut reader, start, options)?;
+    let last = record_start(&mut reader, end, options)?;
+    let mut data = vec![];
+    if first < last {
+        reader.seek(SeekFrom::Start(first))?;
+        reader.take(last - first).read_to_end(&mut data)?;
+    }
+    let has_header = has_header && start == 0;
+    read_csv(path, &data[..], schema, has_header, options, batch_size)
+}
+
+pub(crate) fn read_csv<R: Read>(
+    path: &str,
+    input: R,
+    schema: &Schema,
+    has_header: bool,
+    options: &CsvReadOptions,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .escape(options.escape)
+        .double_quote(options.escape.is_none())
+        .comment(options.comment)
+        .has_headers(has_header)
+        .from_reader(input);
+
+    let schema = Arc::new(schema.clone());
+    let mut batches = vec![];
+    let mut rows: Vec<csv::StringRecord> = Vec::with_capacity(batch_size);
+    for record in reader.records() {
+        let record = record
+            .map_err(|e| BallistaError::General(format!("Failed to read {}: {:?}", path, e)))?;
+        rows.push(record);
+        if rows.len() == batch_size {
+            batches.push(build_batch(&schema, &rows, options)?);
+            rows.clear();
+        }
+    }
+    if !rows.is_empty() {
+        batches.push(build_batch(&schema, &rows, options)?);
+    }
+    Ok(batches)
+}
+
+fn build_batch(
+    schema: &Arc<Schema>,
+    rows: &[csv::StringRecord],
+    options: &CsvReadOptions,
+) -> Result<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| build_array(field.data_type(), rows, i, options))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+macro_rules! build_primitive_array {
+    ($ROWS:expr, $COL:expr, $OPTIONS:expr, $BUILDER:ident, $NATIVE:ty) => {{
+        let mut builder = $BUILDER::new($ROWS.len());
+        for row in $ROWS {
+            match row.get($COL) {
+                Some(s) if !s.is_empty() && !$OPTIONS.is_null(s) => {
+                    let v = s
+                        .trim()
+                        .parse::<$NATIVE>()
+                        .map_err(|_| parse_error(s, stringify!($NATIVE), $COL))?;
+                    builder.append_value(v)?;
+                }
+                _ => builder.append_null()?,
+            }
+        }
+        Ok(Arc::new(builder.finish()) as ArrayRef)
+    }};
+}
+
+macro_rules! build_temporal_array {
+    ($ROWS:expr, $COL:expr, $OPTIONS:expr, $BUILDER:ident, $PARSE:expr) => {{
+        let mut builder = $BUILDER::new($ROWS.len());
+        for row in $ROWS {
+            match row.get($COL) {
+                Some(s) if !s.is_empty() && !$OPTIONS.is_null(s) => {
+                    builder.append_value($PARSE(s.trim())?)?;
+                }
+                _ => builder.append_null()?,
+            }
+        }
+        Ok(Arc::new(builder.finish()) as ArrayRef)
+    }};
+}
+
+fn build_array(
+    data_type: &DataType,
+    rows: &[csv::StringRecord],
+    col: usize,
+    options: &CsvReadOptions,
+) -> Result<ArrayRef> {
+    let date_format = options
+        .date_format
+        .as_deref()
+        .unwrap_or(DEFAULT_DATE_FORMAT);
+    let timestamp_format = options
+        .timestamp_format
+        .as_deref()
+        .unwrap_or(DEFAULT_TIMESTAMP_FORMAT);
+    let timezone = match &options.timezone {
+        Some(timezone) => parse_timezone(timezone)?,
+        None => FixedOffset::east(0),
+    };
+    let parse_timestamp = |s: &str| parse_timestamp(s, timestamp_format, col, &timezone);
+
+    match data_type {
+        DataType::Boolean => {
+            let mut builder = BooleanBuilder::new(rows.len());
+            for row in rows {
+                match row.get(col) {
+                    Some(s) if !s.is_empty() && !options.is_null(s) => {
+                        let v = match s.trim().to_lowercase().as_str() {
+                            "true" => true,
+                            "false" => false,
+                            _ => return Err(parse_error(s, "bool", col)),
+                        };
+                        builder.append_value(v)?;
+                    }
+                    _ => builder.append_null()?,
+                }
+            }
+            Ok(Arc::new(builder.finish()) as ArrayRef)
+        }
+        DataType::Int8 => build_primitive_array!(rows, col, options, Int8Builder, i8),
+        DataType::Int16 => build_primitive_array!(rows, col, options, Int16Builder, i16),
+        DataType::Int32 => build_primitive_array!(rows, col, options, Int32Builder, i32),
+        DataType::Int64 => build_primitive_array!(rows, col, options, Int64Builder, i64),
+        DataType::UInt8 => build_primitive_array!(rows, col, options, UInt8Builder, u8),
+        DataType::UInt16 => build_primitive_array!(rows, col, options, UInt16Builder, u16),
+        DataType::UInt32 => build_primitive_array!(rows, col, options, UInt32Builder, u32),
+        DataType::UInt64 => build_primitive_array!(rows, col, options, UInt64Builder, u64),
+        DataType::Float32 => build_primitive_array!(rows, col, options, Float32Builder, f32),
+        DataType::Float64 => build_primitive_array!(rows, col, options, Float64Builder, f64),
+        DataType::Utf8 => {
+            let mut builder = StringBuilder::new(rows.len());
+            for row in rows {
+                match row.get(col) {
+                    Some(s) if !options.is_null(s) => builder.append_value(s)?,
+                    _ => builder.append_null()?,
+                }
+            }
+            Ok(Arc::new(builder.finish()) as ArrayRef)
+        }
+        DataType::Date32(DateUnit::Day) => build_temporal_array!(
+            rows,
+            col,
+            options,
+            Date32Builder,
+            |s| parse_date(s, date_format, col).map(|days| days as i32)
+        ),
+        DataType::Date64(DateUnit::Millisecond) => build_temporal_array!(
+            rows,
+            col,
+            options,
+            Date64Builder,
+            |s| parse_date(s, date_format, col).map(|days| days * 86_400_000)
+        ),
+        DataType::Timestamp(TimeUnit::Second, _) => {
+            build_temporal_array!(rows, col, options, TimestampSecondBuilder, |s| {
+                parse_timestamp(s).map(|ts| ts.timestamp())
+            })
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            build_temporal_array!(rows, col, options, TimestampMillisecondBuilder, |s| {
+                parse_timestamp(s).map(|ts| ts.timestamp_millis())
+            })
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            build_temporal_array!(rows, col, options, TimestampMicrosecondBuilder, |s| {
+                parse_timestamp(s).map(|ts| ts.timestamp_nanos() / 1_000)
+            })
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            build_temporal_array!(rows, col, options, TimestampNanosecondBuilder, |s| {
+                parse_timestamp(s).map(|ts| ts.timestamp_nanos())
+            })
+        }
+        other => Err(BallistaError::NotImplemented(format!(
+            "CSV reader does not support data type {:?}",
+            other
+        ))),
+    }
+}
+
+/// Parse a date and return the number of days since the UNIX epoch
+fn parse_date(s: &str, format: &str, col: usize) -> Result<i64> {
+    let date = NaiveDate::parse_from_str(s, format).map_err(|_| parse_error(s, format, col))?;
+    Ok((date - NaiveDate::from_ymd(1970, 1, 1)).num_days())
+}
+
+/// Parse a timestamp in a timezone and return the instant in UTC
+fn parse_timestamp(
+    s: &str,
+    format: &str,
+    col: usize,
+    timezone: &FixedOffset,
+) -> Result<NaiveDateTime> {
+    let local =
+        NaiveDateTime::parse_from_str(s, format).map_err(|_| parse_error(s, format, col))?;
+    Ok(to_utc(local, timezone))
+}
+
+fn parse_error(value: &str, expected: &str, col: usize) -> BallistaError {
+    ballista_error(&format!(
+        "Failed to parse '{}' as {} in column {}",
+        value, expected, col
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    #[test]
+    fn read_with_custom_dialect() -> Result<()> {
+        let dir = std::env::temp_dir();
+        let path = dir.join("ballista_csv_options_test.csv");
+        let mut file = File::create(&path)?;
+        file.write_all(
+            b"# generated for testing\nid|name|joined\n1|'a|b'|2020-05-01\n2|NA|01/06/2020\n",
+        )?;
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("joined", DataType::Date32(DateUnit::Day), true),
+        ]);
+
+        let options = CsvReadOptions::new()
+            .with_delimiter(b'|')
+            .with_quote(b'\'')
+            .with_comment(b'#')
+            .with_null_value("NA");
+
+        // the second row uses a different date format and should fail to parse
+        assert!(read_csv_batches(path.to_str().unwrap(), &schema, true, &options, 1024).is_err());
+
+        let options = options.with_date_format("%d/%m/%Y");
+        let mut file = File::create(&path)?;
+        file.write_all(
+            b"# generated for testing\nid|name|joined\n1|'a|b'|01/05/2020\n2|NA|01/06/2020\n",
+        )?;
+        let batches = read_csv_batches(path.to_str().unwrap(), &schema, true, &options, 1024)?;
+        assert_eq!(1, batches.len());
+        assert_eq!(2, batches[0].num_rows());
+
+        let names = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!("a|b", names.value(0));
+        assert!(names.is_null(1));
+
+        let dates = batches[0]
+            .column(2)
+            .as_any()
+            .downcast_ref::<Date32Array>()
+            .unwrap();
+        assert_eq!(18383, dates.value(0));
+        Ok(())
+    }
+
+    #[test]
+    fn read_gzip_compressed() -> Result<()> {
+        let path = std::env::temp_dir().join("ballista_csv_compression_test.csv.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&path)?, flate2::Compression::default());
+        encoder.write_all(b"id,name\n1,a\n2,b\n3,c\n")?;
+        encoder.finish()?;
+
+        let path = path.to_str().unwrap();
+        let options = CsvReadOptions::new();
+        assert_eq!(CsvCompression::Gzip, options.compression_for(path));
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, false),
+        ]);
+        let batches = read_csv_batches(path, &schema, true, &options, 1024)?;
+        assert_eq!(3, batches[0].num_rows());
+        Ok(())
+    }
+
+    #[test]
+    fn infer_schema() -> Result<()> {
+        let path = std::env::temp_dir().join("ballista_csv_infer_test.csv");
+        let mut file = File::create(&path)?;
+        file.write_all(
+            b"id,price,active,joined,name\n1,2.5,true,2020-05-01,a\n2,3,,2020-06-01,\n",
+        )?;
+
+        let path = path.to_str().unwrap();
+        let schema = infer_csv_schema(path, true, &CsvReadOptions::new(), 100)?;
+        let types: Vec<&DataType> = schema.fields().iter().map(|f| f.data_type()).collect();
+        assert_eq!(
+            vec![
+                &DataType::Int64,
+                &DataType::Float64,
+                &DataType::Boolean,
+                &DataType::Date32(DateUnit::Day),
+                &DataType::Utf8,
+            ],
+            types
+        );
+        assert_eq!("joined", schema.field(3).name());
+
+        let schema = infer_csv_schema(path, false, &CsvReadOptions::new(), 100)?;
+        assert_eq!("column_1", schema.field(0).name());
+        assert_eq!(&DataType::Utf8, schema.field(0).data_type());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_schemas_of_paths() -> Result<()> {
+        let a = Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("price", DataType::Int64, true),
+        ]);
+        let b = Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("price", DataType::Float64, true),
+        ]);
+        let merged = merge_csv_schemas(&[a.clone(), b])?;
+        assert_eq!(&DataType::Float64, merged.field(1).data_type());
+
+        let c = Schema::new(vec![Field::new("id", DataType::Int64, true)]);
+        assert!(merge_csv_schemas(&[a, c]).is_err());
+        Ok(())
+    }
+
+    fn read_splits(
+        splits: &[CsvSplit],
+        schema: &Schema,
+        options: &CsvReadOptions,
+    ) -> Result<Vec<RecordBatch>> {
+        let mut batches = vec![];
+        for split in splits {
+            batches.extend(read_csv_split(split, schema, true, options, 1024)?);
+        }
+        Ok(batches)
+    }
+
+    #[test]
+    fn read_byte_range_splits() -> Result<()> {
+        let path = std::env::temp_dir().join("ballista_csv_splits_test.csv");
+        let mut data = b"id,name\n".to_vec();
+        for i in 0..100 {
+            data.extend(format!("{},name-{}\n", i, i).as_bytes());
+        }
+        File::create(&path)?.write_all(&data)?;
+
+        let files = vec![path.to_str().unwrap().to_owned()];
+        let options = CsvReadOptions::new().with_partitions(4);
+        let splits = csv_splits(&files, &options)?;
+        assert_eq!(4, splits.len());
+        // the ranges are even shares of the bytes, whose records are found as they are read
+        let share = (data.len() as u64 + 3) / 4;
+        assert_eq!(Some((0, share)), splits[0].range);
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, false),
+        ]);
+        let batches = read_splits(&splits, &schema, &options)?;
+        let ids: Vec<i32> = batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+                (0..ids.len()).map(|i| ids.value(i)).collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!((0..100).collect::<Vec<_>>(), ids);
+        Ok(())
+    }
+
+    #[test]
+    fn split_files_with_quoted_line_breaks() -> Result<()> {
+        let path = std::env::temp_dir().join("ballista_csv_quoted_splits_test.csv");
+        let mut data = b"id,note\n".to_vec();
+        for i in 0..100 {
+            data.extend(format!("{},\"line\n\"\"{}\"\"\nend\"\n", i, i).as_bytes());
+        }
+        File::create(&path)?.write_all(&data)?;
+
+        let files = vec![path.to_str().unwrap().to_owned()];
+        let options = CsvReadOptions::new().with_partitions(4);
+        let splits = csv_splits(&files, &options)?;
+        assert_eq!(4, splits.len());
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("note", DataType::Utf8, false),
+        ]);
+        let batches = read_splits(&splits, &schema, &options)?;
+        let mut ids = vec![];
+        for batch in &batches {
+            let column = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .unwrap();
+            let notes = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            for i in 0..batch.num_rows() {
+                ids.push(column.value(i));
+                let note = format!("line\n\"{}\"\nend", column.value(i));
+                assert_eq!(note, notes.value(i));
+            }
+        }
+        assert_eq!((0..100).collect::<Vec<_>>(), ids);
+        Ok(())
+    }
+
+    #[test]
+    fn split_files_with_quotes_in_comments() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "ballista_csv_comment_splits_test-{}.csv",
+            std::process::id()
+        ));
+        let mut data = b"id,note\n".to_vec();
+        for i in 0..100 {
+            data.extend(format!("# a comment with a \" quote\n{},\"a\nb\"\n", i).as_bytes());
+        }
+        File::create(&path)?.write_all(&data)?;
+
+        let files = vec![path.to_str().unwrap().to_owned()];
+        let options = CsvReadOptions::new().with_comment(b'#').with_partitions(8);
+        let splits = csv_splits(&files, &options)?;
+        assert_eq!(8, splits.len());
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("note", DataType::Utf8, false),
+        ]);
+        let mut ids = vec![];
+        for batch in read_splits(&splits, &schema, &options)? {
+            let column = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .unwrap();
+            ids.extend((0..batch.num_rows()).map(|i| column.value(i)));
+        }
+        assert_eq!((0..100).collect::<Vec<_>>(), ids);
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+//! Delta Lake tables.
+//!
+//! The parquet files in a snapshot of the table are determined by replaying the add and
+//! remove actions in the transaction log, starting from the latest checkpoint. Only
+//! single-part checkpoints are supported.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::datasource::object_store::ObjectStoreRegistry;
+use crate::error::{ballista_error, BallistaError, Result};
+
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::Field;
+use serde_json::Value;
+
+/// Determine the parquet files in a version of a Delta table, defaulting to the latest
+/// version
+pub fn snapshot_files(
+    object_stores: &ObjectStoreRegistry,
+    path: &str,
+    version: Option<u64>,
+) -> Result<Vec<String>> {
+    let path = path.trim_end_matches('/');
+    let log_dir = format!("{}/_delta_log", path);
+    let log_files = object_stores.get(&log_dir)?.list(&log_dir)?;
+
+    let mut commits = vec![];
+    let mut checkpoints = vec![];
+    for file in &log_files {
+        let name = file.rsplit('/').next().unwrap_or_default();
+        if let Some(v) = parse_log_version(name, ".json") {
+            commits.push((v, file.clone()));
+        } else if let Some(v) = parse_log_version(name, ".checkpoint.parquet") {
+            checkpoints.push((v, file.clone()));
+        }
+    }
+    commits.sort();
+    checkpoints.sort();
+
+    let latest = commits
+        .iter()
+        .chain(checkpoints.iter())
+        .map(|(v, _)| *v)
+        .max()
+        .ok_or_else(|| ballista_error(&format!("{} is not a Delta table", path)))?;
+    let version = version.unwrap_or(latest);
+    if version > latest {
+        return Err(ballista_error(&format!(
+            "Delta table {} has no version {}, the latest version is {}",
+            path, version, latest
+        )));
+    }
+
+    let mut files = BTreeSet::new();
+    let mut next_version = 0;
+    if let Some((v, checkpoint)) = checkpoints.iter().rev().find(|(v, _)| *v <= version) {
+        let local = object_stores.stage_files(&[checkpoint.clone()])?.remove(0);
+        read_checkpoint(&local, &mut files)?;
+        next_version = v + 1;
+    }
+    for (v, commit) in commits
+        .iter()
+        .filter(|(v, _)| *v >= next_version && *v <= version)
+    {
+        if *v != next_version {
+            return Err(ballista_error(&format!(
+                "Version {} of Delta table {} is no longer available",
+                version, path
+            )));
+        }
+        let local = object_stores.stage_files(&[commit.clone()])?.remove(0);
+        read_commit(&local, &mut files)?;
+        next_version += 1;
+    }
+    if next_version != version + 1 {
+        return Err(ballista_error(&format!(
+            "Version {} of Delta table {} is no longer available",
+            version, path
+        )));
+    }
+
+    Ok(files
+        .into_iter()
+        .map(|f| {
+            if f.contains("://") || f.starts_with('/') {
+                f
+            } else {
+                format!("{}/{}", path, f)
+            }
+        })
+        .collect())
+}
+
+/// Parse the version of a log file such as `00000000000000000010.json`
+fn parse_log_version(name: &str, suffix: &str) -> Option<u64> {
+    if !name.ends_with(suffix) {
+        return None;
+    }
+    let version = &name[..name.len() - suffix.len()];
+    if version.len() == 20 && version.chars().all(|c| c.is_ascii_digit()) {
+        version.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Apply the actions in a commit file, with one JSON action per line
+fn read_commit(path: &str, files: &mut BTreeSet<String>) -> Result<()> {
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let action: Value = serde_json::from_str(&line).map_err(delta_error)?;
+        if let Some(file) = action["add"]["path"].as_str() {
+            files.insert(percent_decode(file));
+        } else if let Some(file) = action["remove"]["path"].as_str() {
+            files.remove(&percent_decode(file));
+        }
+    }
+    Ok(())
+}
+
+/// Read the files that were added in a checkpoint, which contains one action per row
+fn read_checkpoint(path: &str, files: &mut BTreeSet<String>) -> Result<()> {
+    let reader = SerializedFileReader::new(File::open(path)?).map_err(delta_error)?;
+    for row in reader.get_row_iter(None).map_err(delta_error)? {
+        for (name, field) in row.get_column_iter() {
+            if let ("add", Field::Group(add)) = (name.as_str(), field) {
+                for (name, field) in add.get_column_iter() {
+                    if let ("path", Field::Str(file)) = (name.as_str(), field) {
+                        files.insert(percent_decode(file));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Paths in the transaction log are URL-encoded
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn delta_error<E: std::fmt::Debug>(e: E) -> BallistaError {
+    BallistaError::General(format!("Delta error: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn replay_log() -> Result<()> {
+        let dir = std::env::temp_dir().join("ballista_delta_test");
+        let log_dir = dir.join("_delta_log");
+        fs::create_dir_all(&log_dir)?;
+        fs::write(
+            log_dir.join("00000000000000000000.json"),
+            "{\"protocol\":{\"minReaderVersion\":1}}\n\
+             {\"add\":{\"path\":\"part-0.parquet\"}}\n\
+             {\"add\":{\"path\":\"day=2020-01-01%2000%3A00/part-1.parquet\"}}\n",
+        )?;
+        fs::write(
+            log_dir.join("00000000000000000001.json"),
+            "{\"remove\":{\"path\":\"part-0.parquet\"}}\n\
+             {\"add\":{\"path\":\"part-2.parquet\"}}\n",
+        )?;
+
+        let object_stores = ObjectStoreRegistry::new(&HashMap::new());
+        let path = dir.to_str().unwrap();
+        assert_eq!(
+            vec![
+                format!("{}/day=2020-01-01 00:00/part-1.parquet", path),
+                format!("{}/part-2.parquet", path),
+            ],
+            snapshot_files(&object_stores, path, None)?
+        );
+        assert_eq!(
+            vec![
+                format!("{}/day=2020-01-01 00:00/part-1.parquet", path),
+                format!("{}/part-0.parquet", path),
+            ],
+            snapshot_files(&object_stores, path, Some(0))?
+        );
+        assert!(snapshot_files(&object_stores, path, Some(2)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+//! Google Cloud Storage support using the JSON API (requires the `gcs` feature).
+//!
+//! Requests use the same retry policy as the other object stores and are authorized with
+//! an OAuth access token, either configured directly or obtained using a service account
+//! key.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::datasource::object_store::{read_partial_body, ObjectStore};
+use crate::datasource::with_retry;
+use crate::error::{ballista_error, BallistaError, Result};
+
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::header::{AUTHORIZATION, RANGE};
+use serde_json::{json, Value};
+
+pub const GCS_SERVICE_ACCOUNT_KEY: &str = "ballista.gcs.serviceAccountKey";
+pub const GCS_ACCESS_TOKEN: &str = "ballista.gcs.accessToken";
+pub const GCS_MAX_RETRIES: &str = "ballista.gcs.maxRetries";
+
+const API_URL: &str = "https://storage.googleapis.com/storage/v1";
+const UPLOAD_URL: &str = "https://storage.googleapis.com/upload/storage/v1";
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// GCS connection settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcsConfig {
+    /// Path to a service account JSON key file, defaulting to the file referenced by
+    /// `GOOGLE_APPLICATION_CREDENTIALS`
+    pub service_account_key: Option<String>,
+    /// OAuth access token to use instead of a service account
+    pub access_token: Option<String>,
+    pub max_retries: usize,
+}
+
+impl GcsConfig {
+    /// Read the GCS configuration from the Context settings
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self> {
+        let max_retries = match settings.get(GCS_MAX_RETRIES) {
+            Some(n) => n.parse::<usize>().map_err(|_| {
+                ballista_error(&format!("Invalid value for {}: {}", GCS_MAX_RETRIES, n))
+            })?,
+            None => 3,
+        };
+        Ok(Self {
+            service_account_key: settings
+                .get(GCS_SERVICE_ACCOUNT_KEY)
+                .cloned()
+                .or_else(|| std::env::var("GOOGLE_APPLICATION_CREDENTIALS").ok()),
+            access_token: settings.get(GCS_ACCESS_TOKEN).cloned(),
+            max_retries,
+        })
+    }
+
+    /// Obtain an access token, exchanging a signed service account assertion if needed
+    fn access_token(&self) -> Result<String> {
+        if let Some(token) = &self.access_token {
+            return Ok(token.clone());
+        }
+        let key_path = self.service_account_key.as_ref().ok_or_else(|| {
+            ballista_error(&format!(
+                "GCS credentials are not configured, set {} or {}",
+                GCS_SERVICE_ACCOUNT_KEY, GCS_ACCESS_TOKEN
+            ))
+        })?;
+        let mut key = String::new();
+        File::open(key_path)?.read_to_string(&mut key)?;
+        let key: Value = serde_json::from_str(&key).map_err(gcs_error)?;
+        let token_uri = key["token_uri"]
+            .as_str()
+            .unwrap_or("https://oauth2.googleapis.com/token");
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(gcs_error)?
+            .as_secs();
+        let claims = json!({
+            "iss": key["client_email"],
+            "scope": SCOPE,
+            "aud": token_uri,
+            "iat": now,
+            "exp": now + 3600,
+        });
+        let private_key = key["private_key"]
+            .as_str()
+            .ok_or_else(|| ballista_error("Service account key has no private_key"))?;
+        let assertion = jsonwebtoken::encode(
+            &Header::new(Algorithm::RS256),
+            &claims,
+            &EncodingKey::from_rsa_pem(private_key.as_bytes()).map_err(gcs_error)?,
+        )
+        .map_err(gcs_error)?;
+
+        let params = [
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", assertion.as_str()),
+        ];
+        let response: Value = reqwest::Client::new()
+            .post(token_uri)
+            .form(&params)
+            .send()?
+            .error_for_status()?
+            .json()?;
+        response["access_token"]
+            .as_str()
+            .map(|s| s.to_owned())
+            .ok_or_else(|| ballista_error("Token response has no access_token"))
+    }
+}
+
+/// Determine whether a path refers to a GCS object
+pub fn is_gcs_path(path: &str) -> bool {
+    path.starts_with("gs://")
+}
+
+/// Split a `gs://bucket/name` URL into the bucket and object name
+pub fn parse_gcs_path(path: &str) -> Result<(String, String)> {
+    if !is_gcs_path(path) {
+        return Err(ballista_error(&format!("Not a GCS path: {}", path)));
+    }
+    let mut parts = path["gs://".len()..].splitn(2, '/');
+    let bucket = parts.next().unwrap_or_default();
+    if bucket.is_empty() {
+        return Err(ballista_error(&format!("GCS path has no bucket: {}", path)));
+    }
+    Ok((
+        bucket.to_owned(),
+        parts.next().unwrap_or_default().to_owned(),
+    ))
+}
+
+/// List the objects under a prefix, returning their `gs://` URLs
+pub fn list_objects(path: &str, config: &GcsConfig) -> Result<Vec<String>> {
+    let (bucket, prefix) = parse_gcs_path(path)?;
+    let token = config.access_token()?;
+    let client = reqwest::Client::new();
+
+    let mut objects = vec![];
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut url = format!(
+            "{}/b/{}/o?prefix={}",
+            API_URL,
+            bucket,
+            percent_encode(&prefix)
+        );
+        if let Some(page_token) = &page_token {
+            url.push_str(&format!("&pageToken={}", percent_encode(page_token)));
+        }
+        let response: Value = with_retry(config.max_retries, || {
+            Ok(client
+                .get(&url)
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .send()?
+                .error_for_status()?
+                .json()?)
+        })?;
+        for item in response["items"].as_array().cloned().unwrap_or_default() {
+            if let Some(name) = item["name"].as_str() {
+                if !name.ends_with('/') {
+                    objects.push(format!("gs://{}/{}", bucket, name));
+                }
+            }
+        }
+        match response["nextPageToken"].as_str() {
+            Some(token) => page_token = Some(token.to_owned()),
+            None => break,
+        }
+    }
+    objects.sort();
+    Ok(objects)
+}
+
+/// Object store for Google Cloud Storage
+#[derive(Debug)]
+pub struct GcsStore {
+    config: GcsConfig,
+    /// Access token and the time that it expires
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl GcsStore {
+    pub fn new(config: GcsConfig) -> Self {
+        Self {
+            config,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Get an access token, reusing the previous token until shortly before it expires
+    fn token(&self) -> Result<String> {
+        let mut token = self
+            .token
+            .lock()
+            .map_err(|_| ballista_error("GCS token lock poisoned"))?;
+        match token.as_ref() {
+            Some((value, expires)) if Instant::now() < *expires => Ok(value.clone()),
+            _ => {
+                let value = self.config.access_token()?;
+                *token = Some((value.clone(), Instant::now() + Duration::from_secs(3000)));
+                Ok(value)
+            }
+        }
+    }
+
+    fn object_url(path: &str) -> Result<String> {
+        let (bucket, name) = parse_gcs_path(path)?;
+        Ok(format!(
+            "{}/b/{}/o/{}",
+            API_URL,
+            bucket,
+            percent_encode(&name)
+        ))
+    }
+}
+
+impl ObjectStore for GcsStore {
+    fn list(&self, path: &str) -> Result<Vec<String>> {
+        list_objects(path, &self.config)
+    }
+
+    fn size(&self, path: &str) -> Result<u64> {
+        let url = Self::object_url(path)?;
+        let token = self.token()?;
+        let client = reqwest::Client::new();
+        let metadata: Value = with_retry(self.config.max_retries, || {
+            Ok(client
+                .get(&url)
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .send()?
+                .error_for_status()?
+                .json()?)
+        })?;
+        // the JSON API returns the size as a string
+        metadata["size"]
+            .as_str()
+            .and_then(|s| s.parse::<u64>().ok())
+            .ok_or_else(|| ballista_error(&format!("Unable to determine the size of {}", path)))
+    }
+
+    fn read_range(&self, path: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+        let url = format!("{}?alt=media", Self::object_url(path)?);
+        let token = self.token()?;
+        let client = reqwest::Client::new();
+        with_retry(self.config.max_retries, || {
+            let mut response = client
+                .get(&url)
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .header(RANGE, format!("bytes={}-{}", start, start + len - 1))
+                .send()?
+                .error_for_status()?;
+            read_partial_body(&mut response)
+        })
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        let (bucket, name) = parse_gcs_path(path)?;
+        let url = format!(
+            "{}/b/{}/o?uploadType=media&name={}",
+            UPLOAD_URL,
+            bucket,
+            percent_encode(&name)
+        );
+        let token = self.token()?;
+        let client = reqwest::Client::new();
+        with_retry(self.config.max_retries, || {
+            client
+                .post(&url)
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .body(data.to_vec())
+                .send()?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Percent-encode an object name for use in a URL path or query parameter
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn gcs_error<E: std::fmt::Debug>(e: E) -> BallistaError {
+    BallistaError::General(format!("GCS error: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_paths() -> Result<()> {
+        assert_eq!(
+            ("bucket".to_owned(), "a/b.csv".to_owned()),
+            parse_gcs_path("gs://bucket/a/b.csv")?
+        );
+        assert!(parse_gcs_path("gs:///a").is_err());
+        assert_eq!("a%2Fb%20c.csv", percent_encode("a/b c.csv"));
+        Ok(())
+    }
+}
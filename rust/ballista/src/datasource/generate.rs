@@ -0,0 +1,554 @@
+//! Synthetic data sources for testing and benchmarking pipelines.
+//!
+//! A `RateTable` produces rows at a fixed rate, read with `Context::read_rate`, and is an
+//! unbounded source of streaming queries unless it is given a maximum number of rows. A
+//! `RandomTable` is a bounded table of a number of rows, read with `Context::read_random`,
+//! which can be scanned by batch queries or joined with the input of streaming queries.
+//!
+//! The values of each column are generated from its type. Date and timestamp columns have
+//! the time that a row of a rate table was due, or a time in the day before a random table
+//! was created. Integer columns have values below the cardinality of the options, so that
+//! they can be grouped and joined on, string columns have values such as `value-42` with the
+//! same cardinality, floating point columns have values between 0 and 1, and nullable
+//! columns have nulls in the fraction of rows given by the options. The values come from a
+//! generator seeded by the options, so a random table has the same rows for the same seed.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, DateUnit, Schema, SchemaRef, TimeUnit};
+use crate::arrow::error::Result as ArrowResult;
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datafusion::datasource::TableProvider;
+use crate::datafusion::error::Result as DataFusionResult;
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::ipc::ProjectedReader;
+use crate::datasource::DEFAULT_BATCH_SIZE;
+use crate::error::{BallistaError, Result};
+
+/// The span of the times of the rows of a random table
+const RANDOM_TIME_SPAN_MS: u64 = 86_400_000;
+
+/// The most time that a batch of a rate table waits for its rows to be due
+const MAX_RATE_BATCH_WAIT: Duration = Duration::from_millis(100);
+
+/// Options for generating synthetic rows
+#[derive(Debug, Clone)]
+pub struct GeneratorOptions {
+    /// Seed of the generator of the values
+    pub seed: u64,
+    /// Number of distinct values of integer and string columns
+    pub cardinality: u64,
+    /// Fraction of the values of nullable columns that are null
+    pub null_fraction: f64,
+    /// Maximum number of rows in a batch
+    pub batch_size: usize,
+    /// Number of partitions that the rows are spread across
+    pub partitions: usize,
+    /// Number of rows after which a rate table ends, so that batch queries can read it
+    pub max_rows: Option<usize>,
+}
+
+impl GeneratorOptions {
+    pub fn new() -> Self {
+        Self {
+            seed: 0,
+            cardinality: 1000,
+            null_fraction: 0.0,
+            batch_size: DEFAULT_BATCH_SIZE,
+            partitions: 1,
+            max_rows: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_cardinality(mut self, cardinality: u64) -> Self {
+        self.cardinality = cardinality;
+        self
+    }
+
+    pub fn with_null_fraction(mut self, null_fraction: f64) -> Self {
+        self.null_fraction = null_fraction;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn with_partitions(mut self, partitions: usize) -> Self {
+        self.partitions = partitions;
+        self
+    }
+
+    pub fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = Some(max_rows);
+        self
+    }
+
+    fn validate(&self, schema: &Schema) -> Result<()> {
+        if self.cardinality == 0 || self.batch_size == 0 || self.partitions == 0 {
+            return Err(BallistaError::General(
+                "The cardinality, batch size and partitions of generated rows must be positive"
+                    .to_owned(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.null_fraction) {
+            return Err(BallistaError::General(format!(
+                "Invalid fraction of null values: {}",
+                self.null_fraction
+            )));
+        }
+        for field in schema.fields() {
+            if !can_generate(field.data_type()) {
+                return Err(BallistaError::NotImplemented(format!(
+                    "Cannot generate values of type {:?} for column {}",
+                    field.data_type(),
+                    field.name()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A table whose rows are generated at a fixed rate, spread evenly across its partitions
+pub struct RateTable {
+    schema: SchemaRef,
+    rows_per_second: usize,
+    options: GeneratorOptions,
+}
+
+impl RateTable {
+    pub fn try_new(
+        rows_per_second: usize,
+        schema: SchemaRef,
+        options: GeneratorOptions,
+    ) -> Result<Self> {
+        if rows_per_second < options.partitions {
+            return Err(BallistaError::General(
+                "Each partition of a rate table must produce at least one row per second"
+                    .to_owned(),
+            ));
+        }
+        options.validate(&schema)?;
+        Ok(Self {
+            schema,
+            rows_per_second,
+            options,
+        })
+    }
+
+    /// Whether the table produces rows until its queries stop
+    pub fn is_unbounded(&self) -> bool {
+        self.options.max_rows.is_none()
+    }
+}
+
+impl TableProvider for RateTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let partitions = self.options.partitions;
+        Ok((0..partitions)
+            .map(|i| {
+                Arc::new(GeneratedPartition {
+                    schema: self.schema.clone(),
+                    projection: projection.clone(),
+                    options: self.options.clone(),
+                    index: i,
+                    rows: self.options.max_rows.map(|rows| share(rows, partitions, i)),
+                    rate: Some(share(self.rows_per_second, partitions, i)),
+                    time_base: 0,
+                }) as Arc<dyn Partition>
+            })
+            .collect())
+    }
+}
+
+/// A table of a number of random rows, spread evenly across its partitions. Each scan of
+/// the table produces the same rows.
+pub struct RandomTable {
+    schema: SchemaRef,
+    rows: usize,
+    options: GeneratorOptions,
+    /// The time that the table was created, in milliseconds since the epoch
+    created: i64,
+}
+
+impl RandomTable {
+    pub fn try_new(rows: usize, schema: SchemaRef, options: GeneratorOptions) -> Result<Self> {
+        options.validate(&schema)?;
+        Ok(Self {
+            schema,
+            rows,
+            options,
+            created: now_millis(),
+        })
+    }
+}
+
+impl TableProvider for RandomTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let partitions = self.options.partitions;
+        Ok((0..partitions)
+            .map(|i| {
+                Arc::new(GeneratedPartition {
+                    schema: self.schema.clone(),
+                    projection: projection.clone(),
+                    options: self.options.clone(),
+                    index: i,
+                    rows: Some(share(self.rows, partitions, i)),
+                    rate: None,
+                    time_base: self.created,
+                }) as Arc<dyn Partition>
+            })
+            .collect())
+    }
+}
+
+/// A partition of a `RateTable` or a `RandomTable`
+struct GeneratedPartition {
+    schema: SchemaRef,
+    projection: Option<Vec<usize>>,
+    options: GeneratorOptions,
+    index: usize,
+    /// The number of rows of the partition, if it ends
+    rows: Option<usize>,
+    /// The rows per second of a partition of a rate table
+    rate: Option<usize>,
+    /// The time that the rows of a random table precede
+    time_base: i64,
+}
+
+impl Partition for GeneratedPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let reader = GeneratedReader {
+            schema: self.schema.clone(),
+            options: self.options.clone(),
+            random: Random::new(self.options.seed, self.index),
+            remaining: self.rows,
+            rate: self.rate,
+            time_base: self.time_base,
+            started: Instant::now(),
+            started_millis: now_millis(),
+            produced: 0,
+        };
+        let projection = self
+            .projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let projected_schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            Box::new(reader),
+            projection,
+            projected_schema,
+        ))))
+    }
+}
+
+/// Generates the batches of a partition, waiting for the rows of a rate table to be due
+struct GeneratedReader {
+    schema: SchemaRef,
+    options: GeneratorOptions,
+    random: Random,
+    remaining: Option<usize>,
+    rate: Option<usize>,
+    time_base: i64,
+    started: Instant,
+    started_millis: i64,
+    produced: usize,
+}
+
+impl GeneratedReader {
+    /// Wait until the next rows of a rate table are due, returning how many are due along
+    /// with the time that the first of them was due
+    fn wait_for_rows(&self, rate: usize, max_rows: usize) -> (usize, i64) {
+        let due_at = |rows: usize| Duration::from_secs_f64(rows as f64 / rate as f64);
+        // a batch is produced once it is full or it has waited long enough
+        let wait_rows = ((rate as f64 * MAX_RATE_BATCH_WAIT.as_secs_f64()) as usize).max(1);
+        let rows = max_rows.min(wait_rows);
+        let due = due_at(self.produced + rows);
+        let elapsed = self.started.elapsed();
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+        }
+        let first = self.started_millis + due_at(self.produced).as_millis() as i64;
+        (rows, first)
+    }
+
+    /// The time of each row of a batch in milliseconds since the epoch
+    fn row_times(&mut self, rows: usize, first: i64) -> Vec<i64> {
+        match self.rate {
+            Some(rate) => (0..rows)
+                .map(|i| first + (i as u64 * 1000 / rate as u64) as i64)
+                .collect(),
+            None => (0..rows)
+                .map(|_| self.time_base - self.random.below(RANDOM_TIME_SPAN_MS) as i64)
+                .collect(),
+        }
+    }
+}
+
+impl RecordBatchReader for GeneratedReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        let max_rows = match self.remaining {
+            Some(0) => return Ok(None),
+            Some(remaining) => remaining.min(self.options.batch_size),
+            None => self.options.batch_size,
+        };
+        let (rows, first) = match self.rate {
+            Some(rate) => self.wait_for_rows(rate, max_rows),
+            None => (max_rows, 0),
+        };
+        let times = self.row_times(rows, first);
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let null_fraction = if field.is_nullable() {
+                    self.options.null_fraction
+                } else {
+                    0.0
+                };
+                generate_column(
+                    field.data_type(),
+                    &times,
+                    &mut self.random,
+                    self.options.cardinality,
+                    null_fraction,
+                )
+            })
+            .collect::<ArrowResult<Vec<_>>>()?;
+        self.produced += rows;
+        self.remaining = self.remaining.map(|remaining| remaining - rows);
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), columns)?))
+    }
+}
+
+/// Whether values of a type can be generated
+fn can_generate(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Boolean
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::Utf8
+            | DataType::Date32(DateUnit::Day)
+            | DataType::Date64(DateUnit::Millisecond)
+            | DataType::Timestamp(_, _)
+    )
+}
+
+/// Generate a column of a batch, with a value for each row time
+fn generate_column(
+    data_type: &DataType,
+    times: &[i64],
+    random: &mut Random,
+    cardinality: u64,
+    null_fraction: f64,
+) -> ArrowResult<ArrayRef> {
+    macro_rules! values {
+        ($builder:ident, |$time:pat| $value:expr) => {{
+            let mut builder = $builder::new(times.len());
+            for time in times {
+                if random.next_f64() < null_fraction {
+                    builder.append_null()?;
+                } else {
+                    let $time = *time;
+                    builder.append_value($value)?;
+                }
+            }
+            Ok(Arc::new(builder.finish()) as ArrayRef)
+        }};
+    }
+
+    // a value below the cardinality, and below the given bound of the type of the column
+    let n = |random: &mut Random, bound: u64| random.below(cardinality.min(bound));
+    match data_type {
+        DataType::Boolean => values!(BooleanBuilder, |_| random.next_u64() % 2 == 0),
+        DataType::Int8 => values!(Int8Builder, |_| n(random, 1 << 7) as i8),
+        DataType::Int16 => values!(Int16Builder, |_| n(random, 1 << 15) as i16),
+        DataType::Int32 => values!(Int32Builder, |_| n(random, 1 << 31) as i32),
+        DataType::Int64 => values!(Int64Builder, |_| n(random, 1 << 63) as i64),
+        DataType::UInt8 => values!(UInt8Builder, |_| n(random, 1 << 8) as u8),
+        DataType::UInt16 => values!(UInt16Builder, |_| n(random, 1 << 16) as u16),
+        DataType::UInt32 => values!(UInt32Builder, |_| n(random, 1 << 32) as u32),
+        DataType::UInt64 => values!(UInt64Builder, |_| n(random, std::u64::MAX)),
+        DataType::Float32 => values!(Float32Builder, |_| random.next_f64() as f32),
+        DataType::Float64 => values!(Float64Builder, |_| random.next_f64()),
+        DataType::Utf8 => values!(StringBuilder, |_| format!(
+            "value-{}",
+            n(random, std::u64::MAX)
+        )),
+        DataType::Date32(_) => values!(Date32Builder, |t| (t / 86_400_000) as i32),
+        DataType::Date64(_) => values!(Date64Builder, |t| t),
+        DataType::Timestamp(TimeUnit::Second, _) => values!(TimestampSecondBuilder, |t| t / 1000),
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            values!(TimestampMillisecondBuilder, |t| t)
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            values!(TimestampMicrosecondBuilder, |t| t * 1000)
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            values!(TimestampNanosecondBuilder, |t| t * 1_000_000)
+        }
+        // the types are checked when the table is created
+        other => unreachable!("Cannot generate values of type {:?}", other),
+    }
+}
+
+/// The share of a total of the partition with the given index
+fn share(total: usize, partitions: usize, index: usize) -> usize {
+    total / partitions + if index < total % partitions { 1 } else { 0 }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// A SplitMix64 generator, which is fast and good enough for synthetic data
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    /// Seed a generator for a partition, so that each partition has different values
+    fn new(seed: u64, partition: usize) -> Self {
+        Self {
+            state: seed ^ (partition as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A value between 0 and 1
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::Field;
+
+    fn collect(table: &dyn TableProvider) -> Result<Vec<RecordBatch>> {
+        let mut batches = vec![];
+        for partition in table.scan(&None, DEFAULT_BATCH_SIZE)? {
+            let reader = partition.execute()?;
+            let mut reader = reader.lock().unwrap();
+            while let Some(batch) = reader.next_batch()? {
+                batches.push(batch);
+            }
+        }
+        Ok(batches)
+    }
+
+    #[test]
+    fn generate_the_same_random_rows_for_a_seed() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+        ]));
+        let options = GeneratorOptions::new()
+            .with_seed(7)
+            .with_cardinality(10)
+            .with_null_fraction(0.5)
+            .with_batch_size(100)
+            .with_partitions(3);
+        let table = RandomTable::try_new(1000, schema, options)?;
+        let batches = collect(&table)?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1000);
+        let ids = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        assert!((0..ids.len()).all(|i| (0..10).contains(&ids.value(i))));
+        let nulls: usize = batches.iter().map(|b| b.column(1).null_count()).sum();
+        assert!(nulls > 400 && nulls < 600);
+
+        let again = collect(&table)?;
+        assert_eq!(
+            format!("{:?}", batches[0].column(1)),
+            format!("{:?}", again[0].column(1))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn produce_rows_at_a_rate() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int64, false)]));
+        let options = GeneratorOptions::new().with_max_rows(50);
+        let table = RateTable::try_new(500, schema, options)?;
+        let start = Instant::now();
+        let rows: usize = collect(&table)?.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 50);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        Ok(())
+    }
+}
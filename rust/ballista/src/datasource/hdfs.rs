@@ -0,0 +1,241 @@
+//! HDFS support using the WebHDFS REST API.
+//!
+//! The WebHDFS address of the namenode defaults to port 9870 on the host in the `hdfs://`
+//! URL and can be overridden through the Context settings.
+
+use std::collections::HashMap;
+
+use crate::datasource::object_store::{read_partial_body, ObjectStore};
+use crate::error::{ballista_error, Result};
+
+use reqwest::header::LOCATION;
+use reqwest::RedirectPolicy;
+use serde_json::Value;
+
+pub const HDFS_NAMENODE: &str = "ballista.hdfs.namenode";
+pub const HDFS_USER: &str = "ballista.hdfs.user";
+
+const DEFAULT_WEBHDFS_PORT: u16 = 9870;
+
+/// HDFS connection settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdfsConfig {
+    /// WebHDFS base URL of the namenode, such as `http://namenode:9870`
+    pub namenode: Option<String>,
+    /// User to access HDFS as
+    pub user: Option<String>,
+}
+
+impl HdfsConfig {
+    /// Read the HDFS configuration from the Context settings
+    pub fn from_settings(settings: &HashMap<String, String>) -> Self {
+        Self {
+            namenode: settings.get(HDFS_NAMENODE).cloned(),
+            user: settings.get(HDFS_USER).cloned(),
+        }
+    }
+
+    /// Build the WebHDFS URL for an operation on a path
+    fn url(&self, authority: &str, path: &str, op: &str) -> String {
+        let base = match &self.namenode {
+            Some(namenode) => namenode.trim_end_matches('/').to_owned(),
+            None => {
+                let host = authority.split(':').next().unwrap_or(authority);
+                format!("http://{}:{}", host, DEFAULT_WEBHDFS_PORT)
+            }
+        };
+        let mut url = format!("{}/webhdfs/v1{}?op={}", base, path, op);
+        if let Some(user) = &self.user {
+            url.push_str(&format!("&user.name={}", user));
+        }
+        url
+    }
+}
+
+/// Determine whether a path refers to a file in HDFS
+pub fn is_hdfs_path(path: &str) -> bool {
+    path.starts_with("hdfs://")
+}
+
+/// Split an `hdfs://namenode:port/path` URL into the authority and the absolute path
+pub fn parse_hdfs_path(path: &str) -> Result<(String, String)> {
+    if !is_hdfs_path(path) {
+        return Err(ballista_error(&format!("Not an HDFS path: {}", path)));
+    }
+    let rest = &path["hdfs://".len()..];
+    match rest.find('/') {
+        Some(i) => Ok((rest[..i].to_owned(), rest[i..].to_owned())),
+        None => Ok((rest.to_owned(), "/".to_owned())),
+    }
+}
+
+/// List the files in a directory and its subdirectories, returning their `hdfs://` URLs.
+/// A path that refers to a file is returned unchanged.
+pub fn list_files(path: &str, config: &HdfsConfig) -> Result<Vec<String>> {
+    let (authority, dir) = parse_hdfs_path(path)?;
+    let client = reqwest::Client::new();
+    let mut files = vec![];
+    let mut dirs = vec![dir];
+    while let Some(dir) = dirs.pop() {
+        let response: Value = client
+            .get(&config.url(&authority, &dir, "LISTSTATUS"))
+            .send()?
+            .error_for_status()?
+            .json()?;
+        let statuses = response["FileStatuses"]["FileStatus"]
+            .as_array()
+            .ok_or_else(|| ballista_error(&format!("Invalid LISTSTATUS response for {}", dir)))?;
+        for status in statuses {
+            let suffix = status["pathSuffix"].as_str().unwrap_or_default();
+            // listing a file returns a single status with an empty suffix
+            let child = if suffix.is_empty() {
+                dir.clone()
+            } else {
+                format!("{}/{}", dir.trim_end_matches('/'), suffix)
+            };
+            match status["type"].as_str() {
+                Some("DIRECTORY") => dirs.push(child),
+                _ => files.push(format!("hdfs://{}{}", authority, child)),
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Object store for HDFS
+#[derive(Debug)]
+pub struct HdfsStore {
+    config: HdfsConfig,
+}
+
+impl HdfsStore {
+    pub fn new(config: HdfsConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl ObjectStore for HdfsStore {
+    fn list(&self, path: &str) -> Result<Vec<String>> {
+        list_files(path, &self.config)
+    }
+
+    fn size(&self, path: &str) -> Result<u64> {
+        let (authority, file_path) = parse_hdfs_path(path)?;
+        let status: Value = reqwest::Client::new()
+            .get(&self.config.url(&authority, &file_path, "GETFILESTATUS"))
+            .send()?
+            .error_for_status()?
+            .json()?;
+        status["FileStatus"]["length"]
+            .as_u64()
+            .ok_or_else(|| ballista_error(&format!("Unable to determine the size of {}", path)))
+    }
+
+    fn read_range(&self, path: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+        let (authority, file_path) = parse_hdfs_path(path)?;
+        let url = format!(
+            "{}&offset={}&length={}",
+            self.config.url(&authority, &file_path, "OPEN"),
+            start,
+            len
+        );
+        // the namenode redirects OPEN requests to a datanode
+        let mut response = reqwest::Client::new()
+            .get(&url)
+            .send()?
+            .error_for_status()?;
+        read_partial_body(&mut response)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        let (authority, file_path) = parse_hdfs_path(path)?;
+        let url = format!(
+            "{}&overwrite=true",
+            self.config.url(&authority, &file_path, "CREATE")
+        );
+        // the namenode responds with the location of the datanode to send the data to
+        let client = reqwest::Client::builder()
+            .redirect(RedirectPolicy::none())
+            .build()?;
+        let response = client.put(&url).send()?.error_for_status()?;
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| ballista_error("WebHDFS CREATE response has no location"))?;
+        client
+            .put(location)
+            .body(data.to_vec())
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn locations(&self, path: &str) -> Result<Vec<String>> {
+        let (authority, file_path) = parse_hdfs_path(path)?;
+        let response: Value = reqwest::Client::new()
+            .get(
+                &self
+                    .config
+                    .url(&authority, &file_path, "GETFILEBLOCKLOCATIONS"),
+            )
+            .send()?
+            .error_for_status()?
+            .json()?;
+        Ok(block_hosts(&response))
+    }
+}
+
+/// The datanodes in a GETFILEBLOCKLOCATIONS response, ordered by the number of blocks of
+/// the file that they store
+fn block_hosts(response: &Value) -> Vec<String> {
+    let mut blocks: HashMap<&str, usize> = HashMap::new();
+    let locations = response["BlockLocations"]["BlockLocation"].as_array();
+    for location in locations.into_iter().flatten() {
+        for host in location["hosts"].as_array().into_iter().flatten() {
+            if let Some(host) = host.as_str() {
+                *blocks.entry(host).or_default() += 1;
+            }
+        }
+    }
+    let mut hosts: Vec<(&str, usize)> = blocks.into_iter().collect();
+    hosts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    hosts.into_iter().map(|(host, _)| host.to_owned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webhdfs_urls() -> Result<()> {
+        let (authority, path) = parse_hdfs_path("hdfs://namenode:8020/data/t.parquet")?;
+        assert_eq!("namenode:8020", authority);
+        assert_eq!("/data/t.parquet", path);
+
+        let mut config = HdfsConfig::from_settings(&HashMap::new());
+        assert_eq!(
+            "http://namenode:9870/webhdfs/v1/data/t.parquet?op=OPEN",
+            config.url(&authority, &path, "OPEN")
+        );
+
+        config.namenode = Some("http://nn.example.com:50070/".to_owned());
+        config.user = Some("ballista".to_owned());
+        assert_eq!(
+            "http://nn.example.com:50070/webhdfs/v1/data?op=LISTSTATUS&user.name=ballista",
+            config.url(&authority, "/data", "LISTSTATUS")
+        );
+
+        let response = serde_json::json!({
+            "BlockLocations": {
+                "BlockLocation": [
+                    { "hosts": ["dn2", "dn1"], "offset": 0 },
+                    { "hosts": ["dn3", "dn2"], "offset": 134217728 },
+                ]
+            }
+        });
+        assert_eq!(vec!["dn2", "dn1", "dn3"], block_hosts(&response));
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+//! Read-only object store for files referenced by `http://` and `https://` URLs. Files
+//! are read with HTTP range requests, so the server must support the `Range` header.
+
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, RANGE};
+use reqwest::StatusCode;
+
+use crate::datasource::object_store::{read_partial_body, ObjectStore};
+use crate::datasource::with_retry;
+use crate::error::{ballista_error, BallistaError, Result};
+
+const MAX_RETRIES: usize = 3;
+
+/// Object store for files served over HTTP
+#[derive(Debug, Default)]
+pub struct HttpStore {
+    client: reqwest::Client,
+}
+
+impl HttpStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ObjectStore for HttpStore {
+    /// HTTP servers cannot be listed, so a URL always refers to a single file
+    fn list(&self, path: &str) -> Result<Vec<String>> {
+        Ok(vec![path.to_owned()])
+    }
+
+    fn size(&self, path: &str) -> Result<u64> {
+        let response = with_retry(MAX_RETRIES, || {
+            Ok(self.client.head(path).send()?.error_for_status()?)
+        })?;
+        if response
+            .headers()
+            .get(ACCEPT_RANGES)
+            .map(|v| v == "none")
+            .unwrap_or(false)
+        {
+            return Err(ballista_error(&format!(
+                "{} does not support range requests",
+                path
+            )));
+        }
+        response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| ballista_error(&format!("Unable to determine the size of {}", path)))
+    }
+
+    fn read_range(&self, path: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+        with_retry(MAX_RETRIES, || {
+            let mut response = self
+                .client
+                .get(path)
+                .header(RANGE, format!("bytes={}-{}", start, start + len - 1))
+                .send()?
+                .error_for_status()?;
+            // a server that ignores the range header would return the whole file
+            if response.status() != StatusCode::PARTIAL_CONTENT && start > 0 {
+                return Err(ballista_error(&format!(
+                    "{} does not support range requests",
+                    path
+                )));
+            }
+            let mut buf = read_partial_body(&mut response)?;
+            buf.truncate(len as usize);
+            Ok(buf)
+        })
+    }
+
+    fn write(&self, path: &str, _data: &[u8]) -> Result<()> {
+        Err(BallistaError::NotImplemented(format!(
+            "Writing to HTTP URLs is not supported: {}",
+            path
+        )))
+    }
+}
@@ -0,0 +1,330 @@
+//! Apache Iceberg tables.
+//!
+//! The data files in the current snapshot are found by reading the table metadata, the
+//! snapshot's manifest list, and the manifests. Manifests record the partition values of
+//! each data file, so files with identity partitions that cannot match a filter are skipped
+//! without reading them. Only tables with parquet data files and without delete files are
+//! supported.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::datasource::object_store::ObjectStoreRegistry;
+use crate::datasource::partitioned::matches_partition;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::logicalplan::Expr;
+
+use avro_rs::types::Value as AvroValue;
+use avro_rs::Reader;
+use serde_json::Value;
+
+const STATUS_DELETED: i32 = 2;
+
+/// Determine the data files in the current snapshot of an Iceberg table, skipping files
+/// whose partition values cannot match the filter
+pub fn plan_files(
+    object_stores: &ObjectStoreRegistry,
+    path: &str,
+    filter: Option<&Expr>,
+) -> Result<Vec<String>> {
+    let metadata = read_json(object_stores, &metadata_file(object_stores, path)?)?;
+
+    let snapshot_id = match metadata["current-snapshot-id"].as_i64() {
+        Some(id) if id >= 0 => id,
+        _ => return Ok(vec![]),
+    };
+    let snapshot = metadata["snapshots"]
+        .as_array()
+        .and_then(|snapshots| {
+            snapshots
+                .iter()
+                .find(|s| s["snapshot-id"].as_i64() == Some(snapshot_id))
+        })
+        .ok_or_else(|| ballista_error(&format!("Iceberg snapshot {} not found", snapshot_id)))?;
+
+    // format version 1 tables may list the manifests in the snapshot itself
+    let manifests: Vec<String> = match snapshot["manifest-list"].as_str() {
+        Some(manifest_list) => read_avro(object_stores, manifest_list)?
+            .iter()
+            .filter_map(|record| match get(record, "manifest_path") {
+                Some(AvroValue::String(path)) => Some(path.clone()),
+                _ => None,
+            })
+            .collect(),
+        None => snapshot["manifests"]
+            .as_array()
+            .map(|m| {
+                m.iter()
+                    .filter_map(|p| p.as_str().map(|s| s.to_owned()))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+
+    let (schema, partition_columns) = partition_columns(&metadata);
+    let mut files = vec![];
+    for manifest in manifests {
+        for entry in read_avro(object_stores, &manifest)? {
+            if let Some(AvroValue::Int(STATUS_DELETED)) = get(&entry, "status") {
+                continue;
+            }
+            let data_file = match get(&entry, "data_file") {
+                Some(AvroValue::Record(data_file)) => data_file,
+                _ => return Err(ballista_error(&format!("Invalid manifest {}", manifest))),
+            };
+            if let Some(AvroValue::Int(content)) = get(data_file, "content") {
+                if *content != 0 {
+                    return Err(BallistaError::NotImplemented(format!(
+                        "Iceberg delete files are not supported: {}",
+                        path
+                    )));
+                }
+            }
+            match get(data_file, "file_format") {
+                Some(AvroValue::String(format)) if format.eq_ignore_ascii_case("parquet") => {}
+                other => {
+                    return Err(BallistaError::NotImplemented(format!(
+                        "Iceberg data file format {:?} is not supported",
+                        other
+                    )))
+                }
+            }
+            let file = match get(data_file, "file_path") {
+                Some(AvroValue::String(file)) => normalize_path(file),
+                _ => return Err(ballista_error(&format!("Invalid manifest {}", manifest))),
+            };
+
+            if let Some(filter) = filter {
+                let values = match get(data_file, "partition") {
+                    Some(AvroValue::Record(partition)) => {
+                        partition_values(partition, &partition_columns)
+                    }
+                    _ => vec![],
+                };
+                let columns: Vec<String> = values.iter().map(|(k, _)| k.clone()).collect();
+                if !matches_partition(filter, &columns, &schema, &values) {
+                    continue;
+                }
+            }
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+/// Find the metadata file for the current version of a table. The path may also refer to
+/// a metadata file directly.
+fn metadata_file(object_stores: &ObjectStoreRegistry, path: &str) -> Result<String> {
+    let path = path.trim_end_matches('/');
+    if path.ends_with(".metadata.json") {
+        return Ok(path.to_owned());
+    }
+    let metadata_dir = format!("{}/metadata", path);
+
+    // tables created by the Hadoop catalog record the current version in a hint file
+    let hint = format!("{}/version-hint.text", metadata_dir);
+    if let Ok(local) = object_stores.stage_files(&[hint]) {
+        let mut version = String::new();
+        if File::open(&local[0])
+            .and_then(|mut f| f.read_to_string(&mut version))
+            .is_ok()
+        {
+            return Ok(format!(
+                "{}/v{}.metadata.json",
+                metadata_dir,
+                version.trim()
+            ));
+        }
+    }
+
+    // otherwise use the metadata file with the highest version
+    object_stores
+        .get(&metadata_dir)?
+        .list(&metadata_dir)?
+        .into_iter()
+        .filter(|f| f.ends_with(".metadata.json"))
+        .max_by_key(|f| metadata_version(f))
+        .ok_or_else(|| ballista_error(&format!("{} is not an Iceberg table", path)))
+}
+
+/// Parse the version from a metadata file name such as `v3.metadata.json` or
+/// `00003-<uuid>.metadata.json`
+fn metadata_version(file: &str) -> u64 {
+    let name = file.rsplit('/').next().unwrap_or_default();
+    let name = name.trim_start_matches('v');
+    let digits: String = name.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().unwrap_or(0)
+}
+
+/// Determine the names of the table columns and the identity partition fields, which are
+/// returned as pairs of partition field name and source column name
+fn partition_columns(metadata: &Value) -> (Schema, Vec<(String, String)>) {
+    let schema = match metadata["schemas"].as_array() {
+        Some(schemas) => schemas
+            .iter()
+            .find(|s| s["schema-id"] == metadata["current-schema-id"])
+            .cloned()
+            .unwrap_or_default(),
+        None => metadata["schema"].clone(),
+    };
+    let fields = schema["fields"].as_array().cloned().unwrap_or_default();
+    let names: HashMap<i64, String> = fields
+        .iter()
+        .filter_map(|f| Some((f["id"].as_i64()?, f["name"].as_str()?.to_owned())))
+        .collect();
+
+    // the column names are only used to resolve column references in filters
+    let schema = Schema::new(
+        fields
+            .iter()
+            .filter_map(|f| f["name"].as_str())
+            .map(|name| Field::new(name, DataType::Utf8, true))
+            .collect(),
+    );
+
+    let specs: Vec<Value> = match metadata["partition-specs"].as_array() {
+        Some(specs) => specs
+            .iter()
+            .filter_map(|s| s["fields"].as_array())
+            .flatten()
+            .cloned()
+            .collect(),
+        None => metadata["partition-spec"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default(),
+    };
+    let columns = specs
+        .iter()
+        .filter(|f| f["transform"].as_str() == Some("identity"))
+        .filter_map(|f| {
+            let source = names.get(&f["source-id"].as_i64()?)?;
+            Some((f["name"].as_str()?.to_owned(), source.clone()))
+        })
+        .collect();
+    (schema, columns)
+}
+
+/// Convert the values of the identity partition fields of a data file to pairs of column
+/// name and value. Values of other types are not used for pruning.
+fn partition_values(
+    partition: &[(String, AvroValue)],
+    partition_columns: &[(String, String)],
+) -> Vec<(String, String)> {
+    partition
+        .iter()
+        .filter_map(|(name, value)| {
+            let (_, column) = partition_columns.iter().find(|(n, _)| n == name)?;
+            let value = match value {
+                AvroValue::Union(v) => v.as_ref(),
+                v => v,
+            };
+            let value = match value {
+                AvroValue::String(v) => v.clone(),
+                AvroValue::Int(v) => v.to_string(),
+                AvroValue::Long(v) => v.to_string(),
+                AvroValue::Float(v) => v.to_string(),
+                AvroValue::Double(v) => v.to_string(),
+                AvroValue::Boolean(v) => v.to_string(),
+                _ => return None,
+            };
+            Some((column.clone(), value))
+        })
+        .collect()
+}
+
+/// Metadata written by Hadoop file systems uses `file:/path` URIs for local files
+fn normalize_path(path: &str) -> String {
+    if path.starts_with("file:") && !path.starts_with("file://") {
+        path["file:".len()..].to_owned()
+    } else {
+        path.to_owned()
+    }
+}
+
+fn get<'a>(record: &'a [(String, AvroValue)], name: &str) -> Option<&'a AvroValue> {
+    record.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+}
+
+fn read_json(object_stores: &ObjectStoreRegistry, path: &str) -> Result<Value> {
+    let local = object_stores
+        .stage_files(&[normalize_path(path)])?
+        .remove(0);
+    let mut json = String::new();
+    File::open(local)?.read_to_string(&mut json)?;
+    serde_json::from_str(&json).map_err(iceberg_error)
+}
+
+fn read_avro(
+    object_stores: &ObjectStoreRegistry,
+    path: &str,
+) -> Result<Vec<Vec<(String, AvroValue)>>> {
+    let local = object_stores
+        .stage_files(&[normalize_path(path)])?
+        .remove(0);
+    let reader = Reader::new(File::open(local)?).map_err(iceberg_error)?;
+    let mut records = vec![];
+    for value in reader {
+        match value.map_err(iceberg_error)? {
+            AvroValue::Record(fields) => records.push(fields),
+            other => {
+                return Err(ballista_error(&format!(
+                    "Expected a record in {} but found {:?}",
+                    path, other
+                )))
+            }
+        }
+    }
+    Ok(records)
+}
+
+fn iceberg_error<E: std::fmt::Debug>(e: E) -> BallistaError {
+    BallistaError::General(format!("Iceberg error: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn identity_partitions() {
+        let metadata = json!({
+            "format-version": 1,
+            "schema": {"type": "struct", "fields": [
+                {"id": 1, "name": "id", "type": "long"},
+                {"id": 2, "name": "category", "type": "string"},
+                {"id": 3, "name": "ts", "type": "timestamp"}
+            ]},
+            "partition-spec": [
+                {"name": "category", "transform": "identity", "source-id": 2, "field-id": 1000},
+                {"name": "ts_day", "transform": "day", "source-id": 3, "field-id": 1001}
+            ]
+        });
+        let (schema, columns) = partition_columns(&metadata);
+        assert_eq!(3, schema.fields().len());
+        assert_eq!(
+            vec![("category".to_owned(), "category".to_owned())],
+            columns
+        );
+
+        let partition = vec![
+            (
+                "category".to_owned(),
+                AvroValue::Union(Box::new(AvroValue::String("books".to_owned()))),
+            ),
+            ("ts_day".to_owned(), AvroValue::Int(18262)),
+        ];
+        assert_eq!(
+            vec![("category".to_owned(), "books".to_owned())],
+            partition_values(&partition, &columns)
+        );
+        assert_eq!(4, metadata_version("/t/metadata/00004-1a2b.metadata.json"));
+        assert_eq!(
+            "/tmp/t/data/a.parquet",
+            normalize_path("file:/tmp/t/data/a.parquet")
+        );
+    }
+}
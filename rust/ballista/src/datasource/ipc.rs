@@ -0,0 +1,159 @@
+//! Arrow IPC data source, supporting both the file (Feather v2) and streaming formats.
+//! Files are read as they are scanned rather than loaded into memory first.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::datatypes::{Schema, SchemaRef};
+use crate::arrow::error::Result as ArrowResult;
+use crate::arrow::ipc::reader::StreamReader;
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datafusion::datasource::TableProvider;
+use crate::datafusion::error::{ExecutionError, Result as DataFusionResult};
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::dictionary::DictionaryFileReader;
+use crate::error::Result;
+
+/// Magic bytes at the start of files in the Arrow IPC file format
+const ARROW_MAGIC: [u8; 6] = *b"ARROW1";
+
+/// Open an Arrow IPC file, detecting whether it uses the file or the stream format. Files
+/// in the file format may have sidecar files with the dictionaries of their columns.
+pub(crate) fn open(path: &str) -> Result<Box<dyn RecordBatchReader + Send + Sync>> {
+    let mut magic = [0_u8; 6];
+    let is_file_format = match File::open(path)?.read_exact(&mut magic) {
+        Ok(_) => magic == ARROW_MAGIC,
+        Err(_) => false,
+    };
+
+    if is_file_format {
+        Ok(Box::new(DictionaryFileReader::try_new(Path::new(path))?))
+    } else {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(Box::new(StreamReader::try_new(reader)?))
+    }
+}
+
+/// Read the schema from an Arrow IPC file
+pub fn ipc_schema(path: &str) -> Result<Schema> {
+    let mut reader = open(path)?;
+    Ok(reader.schema().as_ref().clone())
+}
+
+/// Table that scans Arrow IPC files, with a partition for each file
+pub struct IpcTable {
+    files: Vec<String>,
+    schema: SchemaRef,
+}
+
+impl IpcTable {
+    /// Create a table for files that have the given schema
+    pub fn new(files: Vec<String>, schema: SchemaRef) -> Self {
+        Self { files, schema }
+    }
+}
+
+impl TableProvider for IpcTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok(self
+            .files
+            .iter()
+            .map(|file| {
+                Arc::new(IpcPartition {
+                    path: file.clone(),
+                    projection: projection.clone(),
+                    schema: schema.clone(),
+                }) as Arc<dyn Partition>
+            })
+            .collect())
+    }
+}
+
+/// A file of an `IpcTable`
+struct IpcPartition {
+    path: String,
+    projection: Vec<usize>,
+    /// The schema of the projected columns
+    schema: SchemaRef,
+}
+
+impl Partition for IpcPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let reader = open(&self.path).map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            reader,
+            self.projection.clone(),
+            self.schema.clone(),
+        ))))
+    }
+}
+
+/// Reads the projected columns of the batches of another reader
+pub(crate) struct ProjectedReader {
+    reader: Box<dyn RecordBatchReader + Send + Sync>,
+    projection: Vec<usize>,
+    schema: SchemaRef,
+}
+
+impl ProjectedReader {
+    /// Create a reader of the given columns, where the schema is the schema of the columns
+    pub(crate) fn new(
+        reader: Box<dyn RecordBatchReader + Send + Sync>,
+        projection: Vec<usize>,
+        schema: SchemaRef,
+    ) -> Self {
+        Self {
+            reader,
+            projection,
+            schema,
+        }
+    }
+}
+
+impl RecordBatchReader for ProjectedReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        match self.reader.next_batch()? {
+            Some(batch) => Ok(Some(RecordBatch::try_new(
+                self.schema.clone(),
+                self.projection
+                    .iter()
+                    .map(|i| batch.column(*i).clone())
+                    .collect(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Read all record batches from an Arrow IPC file
+pub fn read_ipc_batches(path: &str) -> Result<Vec<RecordBatch>> {
+    let mut reader = open(path)?;
+    let mut batches = vec![];
+    while let Some(batch) = reader.next_batch()? {
+        batches.push(batch);
+    }
+    Ok(batches)
+}
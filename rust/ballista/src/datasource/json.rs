@@ -0,0 +1,139 @@
+//! Newline-delimited JSON (NDJSON) data source
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use crate::arrow::datatypes::Schema;
+use crate::arrow::json;
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::Result;
+
+/// Default number of records to sample when inferring the schema of a JSON file
+pub const DEFAULT_SCHEMA_INFER_MAX_RECORDS: usize = 1000;
+
+/// Options for reading newline-delimited JSON files
+#[derive(Debug, Clone)]
+pub struct JsonReadOptions {
+    /// Optional schema. When not provided, the schema is inferred from the file.
+    pub schema: Option<Schema>,
+    /// Maximum number of records to read when inferring the schema
+    pub schema_infer_max_records: usize,
+}
+
+impl Default for JsonReadOptions {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            schema_infer_max_records: DEFAULT_SCHEMA_INFER_MAX_RECORDS,
+        }
+    }
+}
+
+impl JsonReadOptions {
+    /// Create options that infer the schema from the file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the given schema rather than inferring one
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Set the maximum number of records to read when inferring the schema
+    pub fn with_schema_infer_max_records(mut self, max_records: usize) -> Self {
+        self.schema_infer_max_records = max_records;
+        self
+    }
+}
+
+/// Determine the schema of a JSON file, either from the options or by sampling the file
+pub fn json_schema(path: &str, options: &JsonReadOptions) -> Result<Schema> {
+    match &options.schema {
+        Some(schema) => Ok(schema.clone()),
+        None => {
+            let mut reader = BufReader::new(File::open(path)?);
+            let schema = json::reader::infer_json_schema(
+                &mut reader,
+                Some(options.schema_infer_max_records),
+            )?;
+            Ok(schema.as_ref().clone())
+        }
+    }
+}
+
+/// Read a newline-delimited JSON file into record batches
+pub fn read_json_batches(
+    path: &str,
+    schema: &Schema,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let mut reader = json::ReaderBuilder::new()
+        .with_schema(Arc::new(schema.clone()))
+        .with_batch_size(batch_size)
+        .build(File::open(path)?)?;
+
+    let mut batches = vec![];
+    while let Some(batch) = reader.next()? {
+        batches.push(batch);
+    }
+    Ok(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn infer_schema_and_read() -> Result<()> {
+        let path = std::env::temp_dir().join("ballista_json_test.json");
+        let mut file = File::create(&path)?;
+        file.write_all(b"{\"a\": 1, \"b\": \"x\"}\n{\"a\": 2, \"b\": \"y\"}\n{\"a\": 3}\n")?;
+        let path = path.to_str().unwrap();
+
+        let schema = json_schema(path, &JsonReadOptions::new())?;
+        assert_eq!(2, schema.fields().len());
+        assert!(schema.field_with_name("a").is_ok());
+
+        let batches = read_json_batches(path, &schema, 2)?;
+        assert_eq!(2, batches.len());
+        assert_eq!(3, batches.iter().map(|b| b.num_rows()).sum::<usize>());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_files_as_partitions() -> Result<()> {
+        use crate::arrow::record_batch::RecordBatchReader;
+        use crate::datafusion::datasource::TableProvider;
+        use crate::datasource::FileTable;
+
+        let mut files = vec![];
+        for i in 0..2 {
+            let path = std::env::temp_dir().join(format!(
+                "ballista_json_table_test-{}-{}.json",
+                std::process::id(),
+                i
+            ));
+            File::create(&path)?.write_all(b"{\"a\": 1, \"b\": \"x\"}\n{\"a\": 2}\n")?;
+            files.push(path.to_str().unwrap().to_owned());
+        }
+        let schema = Arc::new(json_schema(&files[0], &JsonReadOptions::new())?);
+        let table = FileTable::new(files, schema, read_json_batches);
+
+        // each file is read by its own partition when the partition is executed
+        let partitions = table.scan(&Some(vec![1]), 1024)?;
+        assert_eq!(2, partitions.len());
+        for partition in partitions {
+            let reader = partition.execute()?;
+            let mut reader = reader.lock().unwrap();
+            let batch = reader.next_batch()?.unwrap();
+            assert_eq!(1, batch.num_columns());
+            assert_eq!(2, batch.num_rows());
+            assert!(reader.next_batch()?.is_none());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,632 @@
+//! Kafka data source (requires the `kafka` feature).
+//!
+//! A Kafka topic is an unbounded table that streaming contexts read with
+//! `Context::read_kafka`. Each partition of the topic is a partition of the scan, so the
+//! partitions are consumed in parallel, and the payload of each message is a row that is
+//! decoded as JSON, Avro or CSV. JSON and CSV payloads are decoded with the schema given in
+//! the options, and Avro payloads are datums of the writer schema given in the options,
+//! without the header of an Avro file.
+//!
+//! Offsets are managed by the consumer group of the options. By default a scan starts from
+//! the offsets that the group committed, or from the earliest offsets of partitions that it
+//! has not consumed, and commits the offset of each batch once the batch has been passed to
+//! the query, so messages are consumed at least once when a query is restarted. Windowed
+//! queries that restore a checkpoint resume each partition from the offset of the
+//! checkpoint instead.
+//!
+//! The results of streaming queries are published to a topic with a `KafkaSink`, which
+//! produces a message with a JSON object for each row, with timestamps rendered in the
+//! timezone of the sink, such as `2020-05-14T14:30:00+02:00`. A flush waits for the brokers to
+//! acknowledge every message, and produces all of them again if any was not acknowledged.
+//!
+//! Streaming queries only run in local contexts for now, so the partitions are consumed by
+//! the threads of the client rather than spread across executors. Scans are planned with a
+//! partition for each Kafka partition so that they can be distributed once clusters run
+//! streaming queries.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, Schema, SchemaRef, TimeUnit};
+use crate::arrow::error::{ArrowError, Result as ArrowResult};
+use crate::arrow::json;
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datafusion::datasource::TableProvider;
+use crate::datafusion::error::{ExecutionError, Result as DataFusionResult};
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::avro::{avro_error, build_batch, to_arrow_schema};
+use crate::datasource::csv::{read_csv, CsvReadOptions};
+use crate::datasource::ipc::ProjectedReader;
+use crate::datasource::DEFAULT_BATCH_SIZE;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::streaming::{record_source_offset, resume_offset, StreamSink};
+use crate::timezone::format_timestamp;
+
+use avro_rs::types::Value;
+use avro_rs::{from_avro_datum, Schema as AvroSchema};
+use chrono::{Duration as ChronoDuration, FixedOffset, NaiveDate};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::{KafkaError, RDKafkaError};
+use rdkafka::message::Message;
+use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, ProducerContext};
+use rdkafka::topic_partition_list::{Offset, TopicPartitionList};
+use rdkafka::ClientContext;
+use serde_json::{Map, Value as JsonValue};
+
+/// How long to wait for the metadata of a topic
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long each poll of a consumer waits for a message
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a flush of a sink waits for the brokers to acknowledge its messages
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The encoding of the payloads of a topic
+#[derive(Debug, Clone)]
+pub enum KafkaFormat {
+    /// A JSON object per message
+    Json,
+    /// An Avro datum of the given writer schema per message, in the Avro JSON schema format
+    Avro(String),
+    /// A CSV record per message, without a header
+    Csv(CsvReadOptions),
+}
+
+/// Where a scan starts consuming the partitions of a topic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KafkaOffset {
+    /// The offsets committed by the consumer group, or the earliest offsets of partitions
+    /// that the group has not committed offsets for
+    Committed,
+    /// The first message of each partition
+    Earliest,
+    /// The messages produced after the scan starts
+    Latest,
+}
+
+/// Options for reading Kafka topics
+#[derive(Debug, Clone)]
+pub struct KafkaReadOptions {
+    /// Encoding of the payloads
+    pub format: KafkaFormat,
+    /// Schema of the payloads, which is required for JSON and CSV payloads. The schema of
+    /// Avro payloads is derived from their writer schema.
+    pub schema: Option<Schema>,
+    /// Consumer group whose offsets are used and committed
+    pub group_id: String,
+    /// Where consumption starts
+    pub start: KafkaOffset,
+    /// Whether the offsets of the batches are committed to the consumer group
+    pub commit_offsets: bool,
+    /// Maximum number of messages in a batch
+    pub batch_size: usize,
+    /// Maximum time to wait for a batch to fill before it is produced with the messages
+    /// that have arrived
+    pub max_batch_wait: Duration,
+    /// Other settings of the consumers, such as `security.protocol`
+    pub settings: HashMap<String, String>,
+}
+
+impl KafkaReadOptions {
+    /// Create options for payloads of the given format that are consumed by a group
+    pub fn new(format: KafkaFormat, group_id: &str) -> Self {
+        Self {
+            format,
+            schema: None,
+            group_id: group_id.to_owned(),
+            start: KafkaOffset::Committed,
+            commit_offsets: true,
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_batch_wait: Duration::from_millis(500),
+            settings: HashMap::new(),
+        }
+    }
+
+    /// Decode the payloads with the given schema
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Start consuming from the given offsets
+    pub fn with_start(mut self, start: KafkaOffset) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Set whether the offsets of the batches are committed
+    pub fn with_commit_offsets(mut self, commit_offsets: bool) -> Self {
+        self.commit_offsets = commit_offsets;
+        self
+    }
+
+    /// Set the maximum number of messages in a batch
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Set the maximum time to wait for a batch to fill
+    pub fn with_max_batch_wait(mut self, max_batch_wait: Duration) -> Self {
+        self.max_batch_wait = max_batch_wait;
+        self
+    }
+
+    /// Set a setting of the consumers
+    pub fn with_setting(mut self, key: &str, value: &str) -> Self {
+        self.settings.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// The schema of the rows of the topic
+    pub fn payload_schema(&self) -> Result<Schema> {
+        match (&self.format, &self.schema) {
+            (_, Some(schema)) => Ok(schema.clone()),
+            (KafkaFormat::Avro(schema), None) => {
+                to_arrow_schema(&AvroSchema::parse_str(schema).map_err(avro_error)?)
+            }
+            (format, None) => Err(BallistaError::General(format!(
+                "Kafka topics with {:?} payloads must be read with a schema",
+                format
+            ))),
+        }
+    }
+
+    fn consumer(&self, brokers: &str) -> Result<BaseConsumer> {
+        let mut config = ClientConfig::new();
+        for (key, value) in &self.settings {
+            config.set(key, value);
+        }
+        config
+            .set("bootstrap.servers", brokers)
+            .set("group.id", &self.group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest");
+        config.create().map_err(kafka_error)
+    }
+}
+
+/// An unbounded table of the messages of a Kafka topic, with a partition for each
+/// partition of the topic
+pub struct KafkaTable {
+    brokers: String,
+    topic: String,
+    partitions: Vec<i32>,
+    schema: SchemaRef,
+    options: KafkaReadOptions,
+}
+
+impl KafkaTable {
+    /// Create a table for a topic, fetching its partitions from the brokers
+    pub fn try_new(brokers: &str, topic: &str, options: KafkaReadOptions) -> Result<Self> {
+        let schema = Arc::new(options.payload_schema()?);
+        let consumer = options.consumer(brokers)?;
+        let metadata = consumer
+            .fetch_metadata(Some(topic), METADATA_TIMEOUT)
+            .map_err(kafka_error)?;
+        let partitions: Vec<i32> = metadata
+            .topics()
+            .iter()
+            .filter(|t| t.name() == topic)
+            .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+            .collect();
+        if partitions.is_empty() {
+            return Err(BallistaError::General(format!(
+                "Kafka topic {} has no partitions",
+                topic
+            )));
+        }
+        Ok(Self {
+            brokers: brokers.to_owned(),
+            topic: topic.to_owned(),
+            partitions,
+            schema,
+            options,
+        })
+    }
+}
+
+impl TableProvider for KafkaTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let projected_schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok(self
+            .partitions
+            .iter()
+            .map(|partition| {
+                Arc::new(KafkaPartition {
+                    brokers: self.brokers.clone(),
+                    topic: self.topic.clone(),
+                    partition: *partition,
+                    schema: self.schema.clone(),
+                    options: self.options.clone(),
+                    projection: projection.clone(),
+                    projected_schema: projected_schema.clone(),
+                }) as Arc<dyn Partition>
+            })
+            .collect())
+    }
+}
+
+/// A partition of a `KafkaTable`
+struct KafkaPartition {
+    brokers: String,
+    topic: String,
+    partition: i32,
+    schema: SchemaRef,
+    options: KafkaReadOptions,
+    projection: Vec<usize>,
+    projected_schema: SchemaRef,
+}
+
+impl Partition for KafkaPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let reader =
+            KafkaReader::try_new(self).map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            Box::new(reader),
+            self.projection.clone(),
+            self.projected_schema.clone(),
+        ))))
+    }
+}
+
+/// Consumes a partition of a topic, producing a batch whenever it has `batch_size`
+/// messages or it has waited `max_batch_wait` for the batch to fill
+struct KafkaReader {
+    consumer: BaseConsumer,
+    topic: String,
+    partition: i32,
+    schema: SchemaRef,
+    options: KafkaReadOptions,
+    avro_schema: Option<AvroSchema>,
+    /// Batches decoded from the payloads of the last messages that were consumed, with the
+    /// offset after the messages on the last of them
+    pending: VecDeque<(RecordBatch, Option<i64>)>,
+}
+
+impl KafkaReader {
+    fn try_new(partition: &KafkaPartition) -> Result<Self> {
+        let options = &partition.options;
+        let consumer = options.consumer(&partition.brokers)?;
+        let offset = match resume_offset(&source_name(&partition.topic), partition.partition) {
+            Some(offset) => Offset::Offset(offset),
+            None => match options.start {
+                KafkaOffset::Committed => Offset::Stored,
+                KafkaOffset::Earliest => Offset::Beginning,
+                KafkaOffset::Latest => Offset::End,
+            },
+        };
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition_offset(&partition.topic, partition.partition, offset);
+        consumer.assign(&assignment).map_err(kafka_error)?;
+        let avro_schema = match &options.format {
+            KafkaFormat::Avro(schema) => Some(AvroSchema::parse_str(schema).map_err(avro_error)?),
+            _ => None,
+        };
+        Ok(Self {
+            consumer,
+            topic: partition.topic.clone(),
+            partition: partition.partition,
+            schema: partition.schema.clone(),
+            options: options.clone(),
+            avro_schema,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Consume the next messages, returning their payloads and the offset after the last
+    /// message
+    fn consume(&mut self) -> Result<(Vec<Vec<u8>>, i64)> {
+        let mut payloads = vec![];
+        let mut next_offset = 0;
+        let mut first_received = None;
+        while payloads.len() < self.options.batch_size {
+            if let Some(first) = first_received {
+                if Instant::now().duration_since(first) >= self.options.max_batch_wait {
+                    break;
+                }
+            }
+            if let Some(message) = self.consumer.poll(POLL_INTERVAL) {
+                let message = message.map_err(kafka_error)?;
+                // messages without a payload, such as tombstones, have no row
+                if let Some(payload) = message.payload() {
+                    payloads.push(payload.to_vec());
+                }
+                next_offset = message.offset() + 1;
+                first_received.get_or_insert_with(Instant::now);
+            }
+        }
+        Ok((payloads, next_offset))
+    }
+
+    /// Decode the payloads of messages into batches
+    fn decode(&self, payloads: &[Vec<u8>]) -> Result<Vec<RecordBatch>> {
+        match &self.options.format {
+            KafkaFormat::Json => {
+                let mut reader = json::ReaderBuilder::new()
+                    .with_schema(self.schema.clone())
+                    .with_batch_size(payloads.len())
+                    .build(Cursor::new(payloads.join(&b'\n')))?;
+                let mut batches = vec![];
+                while let Some(batch) = reader.next()? {
+                    batches.push(batch);
+                }
+                Ok(batches)
+            }
+            KafkaFormat::Csv(options) => read_csv(
+                &self.topic,
+                Cursor::new(payloads.join(&b'\n')),
+                &self.schema,
+                false,
+                options,
+                payloads.len(),
+            ),
+            KafkaFormat::Avro(_) => {
+                // the unwrap is safe because the schema is parsed for Avro payloads
+                let avro_schema = self.avro_schema.as_ref().unwrap();
+                let rows = payloads
+                    .iter()
+                    .map(|payload| {
+                        match from_avro_datum(avro_schema, &mut payload.as_slice(), None)
+                            .map_err(avro_error)?
+                        {
+                            Value::Record(fields) => Ok(fields),
+                            other => Err(BallistaError::General(format!(
+                                "Expected Avro record but found {:?}",
+                                other
+                            ))),
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(vec![build_batch(&self.schema, &rows)?])
+            }
+        }
+    }
+
+    /// Commit the offset after the messages of a batch
+    fn commit(&self, next_offset: i64) -> Result<()> {
+        let mut offsets = TopicPartitionList::new();
+        offsets.add_partition_offset(&self.topic, self.partition, Offset::Offset(next_offset));
+        self.consumer
+            .commit(&offsets, CommitMode::Async)
+            .map_err(kafka_error)
+    }
+}
+
+impl RecordBatchReader for KafkaReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        let to_arrow = |e: BallistaError| ArrowError::ComputeError(format!("{:?}", e));
+        while self.pending.is_empty() {
+            let (payloads, next_offset) = self.consume().map_err(to_arrow)?;
+            if payloads.is_empty() {
+                continue;
+            }
+            let batches = self.decode(&payloads).map_err(to_arrow)?;
+            let last = batches.len();
+            self.pending.extend(
+                batches
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, batch)| (batch, Some(next_offset).filter(|_| i + 1 == last))),
+            );
+            if self.options.commit_offsets {
+                self.commit(next_offset).map_err(to_arrow)?;
+            }
+        }
+        Ok(self.pending.pop_front().map(|(batch, next_offset)| {
+            if let Some(offset) = next_offset {
+                record_source_offset(&source_name(&self.topic), self.partition, offset);
+            }
+            batch
+        }))
+    }
+}
+
+/// The name of a topic in the offsets of streaming checkpoints
+fn source_name(topic: &str) -> String {
+    format!("kafka.{}", topic)
+}
+
+/// Publishes the results of a streaming query to a Kafka topic, with a message for each row
+/// whose payload is a JSON object of the columns of the row
+pub struct KafkaSink {
+    producer: BaseProducer<DeliveryContext>,
+    topic: String,
+    payloads: Vec<Vec<u8>>,
+    /// Number of messages of the current flush that the brokers failed to acknowledge
+    failed: Arc<AtomicUsize>,
+    /// Timezone that timestamps are rendered in
+    timezone: FixedOffset,
+}
+
+impl KafkaSink {
+    /// Create a sink for a topic, with other settings of the producer such as
+    /// `security.protocol`
+    pub fn try_new(brokers: &str, topic: &str, settings: &HashMap<String, String>) -> Result<Self> {
+        let mut config = ClientConfig::new();
+        for (key, value) in settings {
+            config.set(key, value);
+        }
+        config.set("bootstrap.servers", brokers).set("acks", "all");
+        let failed = Arc::new(AtomicUsize::new(0));
+        let context = DeliveryContext {
+            failed: failed.clone(),
+        };
+        Ok(Self {
+            producer: config.create_with_context(context).map_err(kafka_error)?,
+            topic: topic.to_owned(),
+            payloads: vec![],
+            failed,
+            timezone: FixedOffset::east(0),
+        })
+    }
+
+    /// Render timestamps in a timezone rather than UTC
+    pub fn with_timezone(mut self, timezone: FixedOffset) -> Self {
+        self.timezone = timezone;
+        self
+    }
+}
+
+impl StreamSink for KafkaSink {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        self.payloads.extend(json_payloads(batch, &self.timezone)?);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.failed.store(0, Ordering::SeqCst);
+        for payload in &self.payloads {
+            loop {
+                let record = BaseRecord::<(), Vec<u8>>::to(&self.topic).payload(payload);
+                match self.producer.send(record) {
+                    Ok(()) => break,
+                    // wait for the queue of the producer to drain
+                    Err((KafkaError::MessageProduction(RDKafkaError::QueueFull), _)) => {
+                        self.producer.poll(POLL_INTERVAL);
+                    }
+                    Err((e, _)) => return Err(kafka_error(e)),
+                }
+            }
+        }
+        self.producer.flush(FLUSH_TIMEOUT);
+        if self.producer.in_flight_count() > 0 {
+            return Err(ballista_error(
+                "Timed out waiting for Kafka to acknowledge the results",
+            ));
+        }
+        let failed = self.failed.load(Ordering::SeqCst);
+        if failed > 0 {
+            return Err(BallistaError::General(format!(
+                "Kafka failed to acknowledge {} of {} results",
+                failed,
+                self.payloads.len()
+            )));
+        }
+        self.payloads.clear();
+        Ok(())
+    }
+}
+
+/// Counts the messages that the brokers fail to acknowledge
+struct DeliveryContext {
+    failed: Arc<AtomicUsize>,
+}
+
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult, _: Self::DeliveryOpaque) {
+        if result.is_err() {
+            self.failed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// The JSON object of each row of a batch
+fn json_payloads(batch: &RecordBatch, timezone: &FixedOffset) -> Result<Vec<Vec<u8>>> {
+    let mut rows = vec![Map::new(); batch.num_rows()];
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        for (row, value) in rows.iter_mut().zip(json_values(column, timezone)?) {
+            row.insert(field.name().clone(), value);
+        }
+    }
+    rows.into_iter()
+        .map(|row| serde_json::to_vec(&JsonValue::Object(row)).map_err(BallistaError::from))
+        .collect()
+}
+
+/// The JSON value of each row of a column, with dates and timestamps as strings
+fn json_values(column: &ArrayRef, timezone: &FixedOffset) -> Result<Vec<JsonValue>> {
+    macro_rules! values {
+        ($ARRAY:ty) => {
+            values!($ARRAY, JsonValue::from)
+        };
+        ($ARRAY:ty, $TO_JSON:expr) => {{
+            let array = column.as_any().downcast_ref::<$ARRAY>().unwrap();
+            Ok((0..array.len())
+                .map(|i| {
+                    if array.is_null(i) {
+                        JsonValue::Null
+                    } else {
+                        $TO_JSON(array.value(i))
+                    }
+                })
+                .collect())
+        }};
+    }
+    let epoch = NaiveDate::from_ymd(1970, 1, 1);
+    let date = |date: Option<NaiveDate>| match date {
+        Some(date) => JsonValue::from(date.format("%Y-%m-%d").to_string()),
+        None => JsonValue::Null,
+    };
+    let timestamp = |value, unit| match format_timestamp(value, unit, timezone) {
+        Some(timestamp) => JsonValue::from(timestamp),
+        None => JsonValue::Null,
+    };
+    match column.data_type() {
+        DataType::Boolean => values!(BooleanArray),
+        DataType::Int8 => values!(Int8Array),
+        DataType::Int16 => values!(Int16Array),
+        DataType::Int32 => values!(Int32Array),
+        DataType::Int64 => values!(Int64Array),
+        DataType::UInt8 => values!(UInt8Array),
+        DataType::UInt16 => values!(UInt16Array),
+        DataType::UInt32 => values!(UInt32Array),
+        DataType::UInt64 => values!(UInt64Array),
+        DataType::Float32 => values!(Float32Array),
+        DataType::Float64 => values!(Float64Array),
+        DataType::Utf8 => values!(StringArray),
+        DataType::Date32(_) => values!(Date32Array, |v| date(
+            epoch.checked_add_signed(ChronoDuration::days(v as i64))
+        )),
+        DataType::Date64(_) => values!(Date64Array, |v| date(
+            epoch.checked_add_signed(ChronoDuration::milliseconds(v))
+        )),
+        DataType::Timestamp(unit, _) => {
+            let to_json = |v| timestamp(v, unit);
+            match unit {
+                TimeUnit::Second => values!(TimestampSecondArray, to_json),
+                TimeUnit::Millisecond => values!(TimestampMillisecondArray, to_json),
+                TimeUnit::Microsecond => values!(TimestampMicrosecondArray, to_json),
+                TimeUnit::Nanosecond => values!(TimestampNanosecondArray, to_json),
+            }
+        }
+        other => Err(BallistaError::NotImplemented(format!(
+            "Publishing columns of type {:?} to Kafka",
+            other
+        ))),
+    }
+}
+
+fn kafka_error<E: std::fmt::Debug>(e: E) -> BallistaError {
+    ballista_error(&format!("Kafka error: {:?}", e))
+}
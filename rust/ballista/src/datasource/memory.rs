@@ -0,0 +1,158 @@
+//! Named in-memory tables with an explicit partitioning.
+//!
+//! The batches of a `MemoryScan` are serialized into its plan and scanned as a single
+//! partition. A memory table is instead registered by name with
+//! `ExecutionContext::register_memory_table`, and each scan of it has a partition for each
+//! of its partitions, so that local queries read them in parallel. Plans refer to the table
+//! by name, so remote executors can read it once it has been pinned on them with
+//! `ExecutionContext::pin_table`, which uploads it once for any number of queries.
+//!
+//! Tables are pinned with Flight `DoPut`. The first message is the schema of the table, with
+//! a descriptor whose path is the name of the table. It is followed by the dictionary and
+//! batch messages of each partition, each partition ending with the same marker as the
+//! partitions of results (see `stream`). Executors keep pinned tables until they are
+//! replaced or the executor stops.
+
+use std::sync::Arc;
+
+use crate::arrow::datatypes::SchemaRef;
+use crate::arrow::record_batch::RecordBatch;
+use crate::datafusion::datasource::{MemTable, TableProvider};
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::dictionary::{dictionary_flight_data, DictionaryDecoder, DictionaryEncoder};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::stream::{is_partition_end, partition_end_flight_data};
+
+use flight::{flight_descriptor::DescriptorType, FlightData, FlightDescriptor};
+
+/// Batches held in memory, in partitions
+#[derive(Debug, Clone)]
+pub struct MemoryTable {
+    schema: SchemaRef,
+    partitions: Vec<Vec<RecordBatch>>,
+}
+
+impl MemoryTable {
+    /// Create a table from its partitions, whose batches must all have the given schema
+    pub fn try_new(schema: SchemaRef, partitions: Vec<Vec<RecordBatch>>) -> Result<Self> {
+        for (i, partition) in partitions.iter().enumerate() {
+            if partition.iter().any(|batch| batch.schema() != schema) {
+                return Err(BallistaError::General(format!(
+                    "Partition {} has batches with a different schema than the table",
+                    i
+                )));
+            }
+        }
+        Ok(Self { schema, partitions })
+    }
+
+    /// The batches of each partition
+    pub fn partitions(&self) -> &[Vec<RecordBatch>] {
+        &self.partitions
+    }
+}
+
+impl TableProvider for MemoryTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        batch_size: usize,
+    ) -> crate::datafusion::error::Result<Vec<Arc<dyn Partition>>> {
+        MemTable::new(self.schema.clone(), self.partitions.clone())?.scan(projection, batch_size)
+    }
+}
+
+/// The messages that the client sends to pin a table on an executor
+pub fn pin_messages(name: &str, table: &MemoryTable) -> Result<Vec<FlightData>> {
+    let mut encoder = DictionaryEncoder::new(table.schema.clone());
+    let mut schema = encoder.schema_flight_data()?;
+    schema.flight_descriptor = Some(FlightDescriptor {
+        r#type: DescriptorType::Path as i32,
+        cmd: vec![],
+        path: vec![name.to_owned()],
+    });
+    let mut messages = vec![schema];
+    for partition in &table.partitions {
+        for batch in partition {
+            let (keys, changed) = encoder.encode(batch)?;
+            for (column, values) in &changed {
+                messages.push(dictionary_flight_data(*column, values)?);
+            }
+            messages.push(FlightData::from(&keys));
+        }
+        messages.push(partition_end_flight_data());
+    }
+    Ok(messages)
+}
+
+/// The name and the table of the messages that pin a table
+pub fn receive_pinned(messages: &[FlightData]) -> Result<(String, MemoryTable)> {
+    let first = messages
+        .first()
+        .ok_or_else(|| ballista_error("A pinned table must start with its schema"))?;
+    let name = match &first.flight_descriptor {
+        Some(descriptor)
+            if descriptor.r#type == DescriptorType::Path as i32 && descriptor.path.len() == 1 =>
+        {
+            descriptor.path[0].clone()
+        }
+        _ => {
+            return Err(ballista_error(
+                "The schema of a pinned table must describe the name of the table",
+            ))
+        }
+    };
+    let mut decoder = DictionaryDecoder::from_flight_data(first)?;
+    let mut partitions = vec![];
+    let mut partition = vec![];
+    for data in &messages[1..] {
+        if is_partition_end(data) {
+            partitions.push(std::mem::replace(&mut partition, vec![]));
+        } else if let Some(batch) = decoder.decode_flight_data(data)? {
+            partition.push(batch);
+        }
+    }
+    if !partition.is_empty() {
+        return Err(ballista_error(
+            "The last partition of a pinned table must be ended",
+        ));
+    }
+    Ok((name, MemoryTable::try_new(decoder.schema(), partitions)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+
+    #[test]
+    fn pin_partitioned_table() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = |values: Vec<i32>| {
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(values))])
+        };
+        let partitions = vec![
+            vec![batch(vec![1, 2])?, batch(vec![3])?],
+            vec![],
+            vec![batch(vec![4])?],
+        ];
+        let table = MemoryTable::try_new(schema.clone(), partitions)?;
+        assert_eq!(3, table.scan(&None, 1024)?.len());
+
+        let messages = pin_messages("lookup", &table)?;
+        let (name, pinned) = receive_pinned(&messages)?;
+        assert_eq!("lookup", name);
+        let rows: Vec<usize> = pinned
+            .partitions()
+            .iter()
+            .map(|partition| partition.iter().map(|b| b.num_rows()).sum())
+            .collect();
+        assert_eq!(vec![3, 0, 1], rows);
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+//! Ballista data sources
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::arrow::array::*;
+use crate::arrow::compute::cast;
+use crate::arrow::datatypes::{DataType, Schema, SchemaRef};
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datafusion::datasource::TableProvider;
+use crate::datafusion::error::{ExecutionError, Result as DataFusionResult};
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::ipc::ProjectedReader;
+use crate::datasource::object_store::ObjectStoreRegistry;
+use crate::error::{BallistaError, Result};
+use crate::physical_plan::memory::MemoryReader;
+
+pub use crate::file_scan::expand_path;
+
+pub mod avro;
+#[cfg(feature = "azure")]
+pub mod azure;
+pub mod csv;
+pub mod delta;
+#[cfg(feature = "gcs")]
+pub mod gcs;
+pub mod generate;
+pub mod hdfs;
+pub mod http;
+pub mod iceberg;
+pub mod ipc;
+pub mod json;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod memory;
+pub mod object_store;
+#[cfg(feature = "orc")]
+pub mod orc;
+pub mod parquet;
+pub mod partitioned;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod sql;
+pub mod table;
+pub mod write;
+
+/// Default number of rows per batch for data sources that are read into memory
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// Determine whether a path refers to an object in a remote object store
+pub fn is_remote_path(path: &str) -> bool {
+    object_store::scheme(path) != "file"
+}
+
+/// The schema of the files of a type at a path, as a scan of the path reads them. Remote
+/// paths are read through the object stores. The schemas of CSV files are not stored in
+/// the files, so they are inferred from the first file, which must have a header and the
+/// default dialect.
+pub fn file_schema(
+    path: &str,
+    file_type: &str,
+    object_stores: &ObjectStoreRegistry,
+) -> Result<Schema> {
+    let local_path =
+        || -> Result<String> { Ok(object_stores.stage_files(&[path.to_owned()])?.remove(0)) };
+    match file_type {
+        "parquet" => Ok(parquet::parquet_dataset(&[path], object_stores)?.schema),
+        "csv" => {
+            let files = object_stores.get(path)?.list(path)?;
+            let first = files
+                .first()
+                .ok_or_else(|| BallistaError::General(format!("No files found at {}", path)))?;
+            let local_path = object_stores.stage_files(&[first.clone()])?.remove(0);
+            let options = csv::CsvReadOptions::default();
+            csv::infer_csv_schema(&local_path, true, &options, csv::CSV_SCHEMA_INFER_RECORDS)
+        }
+        "json" => json::json_schema(&local_path()?, &json::JsonReadOptions::default()),
+        "avro" => avro::avro_schema(&local_path()?),
+        "ipc" => ipc::ipc_schema(&local_path()?),
+        #[cfg(feature = "orc")]
+        "orc" => orc::orc_schema(&local_path()?),
+        other => Err(BallistaError::NotImplemented(format!(
+            "Fetching the schema of {} files",
+            other
+        ))),
+    }
+}
+
+/// Delay before retrying a failed object store request, doubling with every attempt
+pub fn backoff(attempt: usize) -> Duration {
+    Duration::from_millis(100 << attempt.min(10))
+}
+
+/// Retry a blocking object store request with exponential backoff
+pub fn with_retry<T, F>(max_retries: usize, f: F) -> Result<T>
+where
+    F: Fn() -> Result<T>,
+{
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= max_retries => return Err(e),
+            Err(_) => {
+                std::thread::sleep(backoff(attempt));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Reads a file into batches that conform to a schema, with the given number of rows per
+/// batch
+pub type ReadFile = fn(&str, &Schema, usize) -> Result<Vec<RecordBatch>>;
+
+/// Table of files that are read as they are scanned, with a partition for each file, for
+/// formats that are read a file at a time
+pub struct FileTable {
+    files: Vec<String>,
+    schema: SchemaRef,
+    read: ReadFile,
+}
+
+impl FileTable {
+    /// Create a table for files that are read with the given schema
+    pub fn new(files: Vec<String>, schema: SchemaRef, read: ReadFile) -> Self {
+        Self {
+            files,
+            schema,
+            read,
+        }
+    }
+}
+
+impl TableProvider for FileTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let projected_schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok(self
+            .files
+            .iter()
+            .map(|file| {
+                Arc::new(FilePartition {
+                    path: file.clone(),
+                    schema: self.schema.clone(),
+                    read: self.read,
+                    batch_size,
+                    projection: projection.clone(),
+                    projected_schema: projected_schema.clone(),
+                }) as Arc<dyn Partition>
+            })
+            .collect())
+    }
+}
+
+/// A file of a `FileTable`
+struct FilePartition {
+    path: String,
+    schema: SchemaRef,
+    read: ReadFile,
+    batch_size: usize,
+    projection: Vec<usize>,
+    /// The schema of the projected columns
+    projected_schema: SchemaRef,
+}
+
+impl Partition for FilePartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let batches = (self.read)(&self.path, &self.schema, self.batch_size)
+            .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+        let reader = MemoryReader::new(self.schema.clone(), batches);
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            Box::new(reader),
+            self.projection.clone(),
+            self.projected_schema.clone(),
+        ))))
+    }
+}
+
+/// Adapt a batch to a wider schema by matching columns by name, casting columns to the
+/// target type where needed and filling in missing columns with nulls
+pub fn adapt_batch(batch: &RecordBatch, schema: &Arc<Schema>) -> Result<RecordBatch> {
+    let batch_schema = batch.schema();
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| match batch_schema.index_of(field.name()) {
+            Ok(i) => {
+                let column = batch.column(i);
+                if column.data_type() == field.data_type() {
+                    Ok(column.clone())
+                } else {
+                    Ok(cast(column, field.data_type())?)
+                }
+            }
+            Err(_) => new_null_array(field.data_type(), batch.num_rows()),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+macro_rules! null_array {
+    ($BUILDER:ident, $LEN:expr) => {{
+        let mut builder = $BUILDER::new($LEN);
+        for _ in 0..$LEN {
+            builder.append_null()?;
+        }
+        Ok(Arc::new(builder.finish()) as ArrayRef)
+    }};
+}
+
+/// Create an array of the given type where every value is null
+pub fn new_null_array(data_type: &DataType, len: usize) -> Result<ArrayRef> {
+    match data_type {
+        DataType::Boolean => null_array!(BooleanBuilder, len),
+        DataType::Int8 => null_array!(Int8Builder, len),
+        DataType::Int16 => null_array!(Int16Builder, len),
+        DataType::Int32 => null_array!(Int32Builder, len),
+        DataType::Int64 => null_array!(Int64Builder, len),
+        DataType::UInt8 => null_array!(UInt8Builder, len),
+        DataType::UInt16 => null_array!(UInt16Builder, len),
+        DataType::UInt32 => null_array!(UInt32Builder, len),
+        DataType::UInt64 => null_array!(UInt64Builder, len),
+        DataType::Float32 => null_array!(Float32Builder, len),
+        DataType::Float64 => null_array!(Float64Builder, len),
+        DataType::Utf8 => null_array!(StringBuilder, len),
+        DataType::Binary => null_array!(BinaryBuilder, len),
+        other => Err(BallistaError::NotImplemented(format!(
+            "Cannot create null array of type {:?}",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,518 @@
+//! Pluggable object store abstraction.
+//!
+//! Object stores are registered by URI scheme. Remote objects are downloaded into a local
+//! cache directory using parallel range reads so that the file readers can be used
+//! unchanged. Paths without a scheme (or with the `file` scheme) use the local file system.
+//!
+//! Each range read of a download is retried with exponential backoff when it fails, as
+//! configured by `ballista.objectStore.readMaxRetries` and
+//! `ballista.objectStore.readBackoffMs`. A read that returns fewer bytes than requested,
+//! such as a response whose body failed part way through, is resumed from the first byte
+//! that it did not return rather than read again from the start, so a transient failure
+//! does not fail the scan that reads the object.
+//!
+//! The range reads run on the I/O pool of the registry, which has a thread for each
+//! partition of a query that may execute at the same time (see `pool::parallelism`), so a
+//! large object does not start a thread for each of its parts.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::datasource::hdfs::{HdfsConfig, HdfsStore};
+use crate::datasource::http::HttpStore;
+use crate::datasource::parquet::FooterCache;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::pool::{self, ExecutionPool};
+
+/// Objects are downloaded in parts of this size
+pub const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Setting with the number of times a failed read of a part of a remote object is retried
+pub const READ_MAX_RETRIES: &str = "ballista.objectStore.readMaxRetries";
+
+/// Setting with the delay in milliseconds before the first retry of a read, which doubles
+/// with every retry
+pub const READ_BACKOFF_MS: &str = "ballista.objectStore.readBackoffMs";
+
+pub const DEFAULT_READ_MAX_RETRIES: usize = 3;
+
+pub const DEFAULT_READ_BACKOFF_MS: u64 = 100;
+
+/// Longest delay between the retries of a read
+const MAX_READ_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Storage backend that can list, read and write objects
+pub trait ObjectStore: fmt::Debug + Send + Sync {
+    /// List the objects in a directory or under a prefix and its subdirectories. A path that
+    /// refers to a single object is returned unchanged.
+    fn list(&self, path: &str) -> Result<Vec<String>>;
+
+    /// Size of an object in bytes
+    fn size(&self, path: &str) -> Result<u64>;
+
+    /// Read `len` bytes starting at `start`
+    fn read_range(&self, path: &str, start: u64, len: u64) -> Result<Vec<u8>>;
+
+    /// Create or replace an object
+    fn write(&self, path: &str, data: &[u8]) -> Result<()>;
+
+    /// Hosts that store the object, which executors on those hosts can read it from without
+    /// transferring it over the network. Stores that do not track where objects are stored
+    /// return no hosts.
+    fn locations(&self, _path: &str) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+}
+
+/// How the range reads of downloads are retried
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadRetryPolicy {
+    /// The number of times a read is retried after it fails without returning any bytes
+    pub max_retries: usize,
+    /// The delay before the first retry, which doubles with every retry
+    pub backoff: Duration,
+}
+
+impl ReadRetryPolicy {
+    /// The policy configured by the Context settings, where invalid values are ignored
+    pub fn from_settings(settings: &HashMap<String, String>) -> Self {
+        let max_retries = settings
+            .get(READ_MAX_RETRIES)
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_READ_MAX_RETRIES);
+        let backoff_ms = settings
+            .get(READ_BACKOFF_MS)
+            .and_then(|n| n.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_READ_BACKOFF_MS);
+        Self {
+            max_retries,
+            backoff: Duration::from_millis(backoff_ms),
+        }
+    }
+
+    /// The delay before a retry
+    fn delay(&self, attempt: usize) -> Duration {
+        self.backoff
+            .checked_mul(1 << attempt.min(16))
+            .unwrap_or(MAX_READ_BACKOFF)
+            .min(MAX_READ_BACKOFF)
+    }
+}
+
+impl Default for ReadRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_READ_MAX_RETRIES,
+            backoff: Duration::from_millis(DEFAULT_READ_BACKOFF_MS),
+        }
+    }
+}
+
+/// Read `len` bytes of an object starting at `start`, resuming reads that return fewer bytes
+/// than requested and retrying reads that fail. Reads that make progress do not count as
+/// retries.
+pub fn read_range_fully(
+    store: &dyn ObjectStore,
+    path: &str,
+    start: u64,
+    len: u64,
+    policy: &ReadRetryPolicy,
+) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(len as usize);
+    let mut attempt = 0;
+    while (buf.len() as u64) < len {
+        let offset = start + buf.len() as u64;
+        let remaining = len - buf.len() as u64;
+        let error = match store.read_range(path, offset, remaining) {
+            Ok(part) if !part.is_empty() => {
+                buf.extend_from_slice(&part[..part.len().min(remaining as usize)]);
+                attempt = 0;
+                continue;
+            }
+            Ok(_) => ballista_error(&format!(
+                "Read of {} returned no bytes at offset {}",
+                path, offset
+            )),
+            Err(e) => e,
+        };
+        if attempt >= policy.max_retries {
+            return Err(BallistaError::General(format!(
+                "Failed to read bytes {}..{} of {} after {} retries: {}",
+                offset,
+                start + len,
+                path,
+                attempt,
+                error
+            )));
+        }
+        thread::sleep(policy.delay(attempt));
+        attempt += 1;
+    }
+    Ok(buf)
+}
+
+/// Read the body of the response to a range read. A body that fails part way through is
+/// returned as a short read, so that `read_range_fully` resumes it where it stopped.
+pub(crate) fn read_partial_body(response: &mut reqwest::Response) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    match response.copy_to(&mut buf) {
+        Ok(_) => Ok(buf),
+        Err(_) if !buf.is_empty() => Ok(buf),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Download an object into the local cache directory using range reads on a pool, returning
+/// the local path. Objects that were already downloaded and have the same size are not
+/// downloaded again.
+pub fn download(
+    store: Arc<dyn ObjectStore>,
+    path: &str,
+    policy: &ReadRetryPolicy,
+    pool: &ExecutionPool,
+) -> Result<String> {
+    let local_path = cache_path(path);
+    let len = store.size(path)?;
+    if let Ok(metadata) = fs::metadata(&local_path) {
+        if metadata.len() == len {
+            return Ok(local_path.to_string_lossy().to_string());
+        }
+    }
+
+    let handles: Vec<_> = (0..len)
+        .step_by(PART_SIZE as usize)
+        .map(|start| {
+            let store = store.clone();
+            let path = path.to_owned();
+            let policy = *policy;
+            pool.spawn(move || {
+                read_range_fully(
+                    store.as_ref(),
+                    &path,
+                    start,
+                    PART_SIZE.min(len - start),
+                    &policy,
+                )
+            })
+        })
+        .collect();
+
+    if let Some(parent) = local_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(&local_path)?;
+    for handle in handles {
+        let part = handle
+            .wait()
+            .map_err(|_| ballista_error(&format!("Download of {} panicked", path)))??;
+        file.write_all(&part)?;
+    }
+    Ok(local_path.to_string_lossy().to_string())
+}
+
+/// Local directory that remote objects are cached in
+pub fn cache_dir() -> PathBuf {
+    std::env::temp_dir().join("ballista-cache")
+}
+
+/// Local path that a remote object is cached at
+fn cache_path(path: &str) -> PathBuf {
+    let (scheme, rest) = match path.find("://") {
+        Some(i) => (&path[..i], &path[i + 3..]),
+        None => ("file", path),
+    };
+    cache_dir()
+        .join(scheme)
+        .join(rest.replace(':', "_").replace('@', "_"))
+}
+
+/// Get the URI scheme of a path, which is `file` for paths without a scheme
+pub fn scheme(path: &str) -> &str {
+    match path.find("://") {
+        Some(i) => &path[..i],
+        None => "file",
+    }
+}
+
+/// Object store for the local file system
+#[derive(Debug, Default)]
+pub struct LocalFileSystem {}
+
+impl LocalFileSystem {
+    pub(crate) fn local_path(path: &str) -> &str {
+        if path.starts_with("file://") {
+            &path["file://".len()..]
+        } else {
+            path
+        }
+    }
+}
+
+impl ObjectStore for LocalFileSystem {
+    fn list(&self, path: &str) -> Result<Vec<String>> {
+        fn collect(path: &Path, files: &mut Vec<String>) -> Result<()> {
+            if path.is_dir() {
+                for entry in fs::read_dir(path)? {
+                    collect(&entry?.path(), files)?;
+                }
+            } else {
+                files.push(path.to_string_lossy().to_string());
+            }
+            Ok(())
+        }
+        let mut files = vec![];
+        collect(Path::new(Self::local_path(path)), &mut files)?;
+        files.sort();
+        Ok(files)
+    }
+
+    fn size(&self, path: &str) -> Result<u64> {
+        Ok(fs::metadata(Self::local_path(path))?.len())
+    }
+
+    fn read_range(&self, path: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+        let mut file = File::open(Self::local_path(path))?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![];
+        file.take(len).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        Ok(fs::write(Self::local_path(path), data)?)
+    }
+}
+
+/// Object stores registered by URI scheme. Schemes without a registered store fall back
+/// to the built-in stores, which are configured from the Context settings. The registry
+/// also caches the footers of the parquet files that are read through it.
+pub struct ObjectStoreRegistry {
+    settings: HashMap<String, String>,
+    stores: RwLock<HashMap<String, Arc<dyn ObjectStore>>>,
+    footers: FooterCache,
+    retry: ReadRetryPolicy,
+    io_pool: Mutex<Option<Arc<ExecutionPool>>>,
+}
+
+impl ObjectStoreRegistry {
+    /// Create a registry where the built-in stores use the given settings
+    pub fn new(settings: &HashMap<String, String>) -> Self {
+        Self {
+            settings: settings.clone(),
+            stores: RwLock::new(HashMap::new()),
+            footers: FooterCache::from_settings(settings),
+            retry: ReadRetryPolicy::from_settings(settings),
+            io_pool: Mutex::new(None),
+        }
+    }
+
+    /// The pool that the blocking reads of remote objects and databases run on, which is
+    /// started when it is first used
+    pub fn io_pool(&self) -> Arc<ExecutionPool> {
+        self.io_pool
+            .lock()
+            .expect("object store I/O pool lock poisoned")
+            .get_or_insert_with(|| Arc::new(ExecutionPool::new(pool::parallelism(&self.settings))))
+            .clone()
+    }
+
+    /// The value of one of the settings that the registry was created with
+    pub fn setting(&self, name: &str) -> Option<&String> {
+        self.settings.get(name)
+    }
+
+    /// The parsed footers of the local and staged parquet files that have been read
+    pub fn footers(&self) -> &FooterCache {
+        &self.footers
+    }
+
+    /// Register an object store for a URI scheme, replacing any existing store
+    pub fn register(&self, scheme: &str, store: Arc<dyn ObjectStore>) {
+        self.stores
+            .write()
+            .expect("object store registry lock poisoned")
+            .insert(scheme.to_owned(), store);
+    }
+
+    /// Get the object store for a path
+    pub fn get(&self, path: &str) -> Result<Arc<dyn ObjectStore>> {
+        let scheme = scheme(path);
+        if let Some(store) = self
+            .stores
+            .read()
+            .expect("object store registry lock poisoned")
+            .get(scheme)
+        {
+            return Ok(store.clone());
+        }
+        self.builtin(scheme)
+    }
+
+    fn builtin(&self, scheme: &str) -> Result<Arc<dyn ObjectStore>> {
+        match scheme {
+            "file" => Ok(Arc::new(LocalFileSystem::default())),
+            "hdfs" => Ok(Arc::new(HdfsStore::new(HdfsConfig::from_settings(
+                &self.settings,
+            )))),
+            "http" | "https" => Ok(Arc::new(HttpStore::new())),
+            #[cfg(feature = "s3")]
+            "s3" => Ok(Arc::new(crate::datasource::s3::S3Store::new(
+                crate::datasource::s3::S3Config::from_settings(&self.settings)?,
+            ))),
+            #[cfg(feature = "gcs")]
+            "gs" => Ok(Arc::new(crate::datasource::gcs::GcsStore::new(
+                crate::datasource::gcs::GcsConfig::from_settings(&self.settings)?,
+            ))),
+            #[cfg(feature = "azure")]
+            "wasb" | "wasbs" | "abfs" | "abfss" => {
+                Ok(Arc::new(crate::datasource::azure::AzureStore::new(
+                    crate::datasource::azure::AzureConfig::from_settings(&self.settings)?,
+                )))
+            }
+            other => Err(BallistaError::NotImplemented(format!(
+                "No object store is registered for the {} scheme (the built-in S3, GCS and \
+                 Azure stores require the s3, gcs and azure features)",
+                other
+            ))),
+        }
+    }
+
+    /// Make files available on the local file system, downloading remote objects into the
+    /// local cache. Local files are always read directly.
+    pub fn stage_files(&self, files: &[String]) -> Result<Vec<String>> {
+        files
+            .iter()
+            .map(|file| {
+                if scheme(file) == "file" {
+                    Ok(LocalFileSystem::local_path(file).to_owned())
+                } else {
+                    download(self.get(file)?, file, &self.retry, &self.io_pool())
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for ObjectStoreRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stores = self
+            .stores
+            .read()
+            .expect("object store registry lock poisoned");
+        let mut schemes: Vec<&String> = stores.keys().collect();
+        schemes.sort();
+        f.debug_struct("ObjectStoreRegistry")
+            .field("schemes", &schemes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct MemoryStore {
+        data: Vec<u8>,
+    }
+
+    impl ObjectStore for MemoryStore {
+        fn list(&self, path: &str) -> Result<Vec<String>> {
+            Ok(vec![path.to_owned()])
+        }
+
+        fn size(&self, _path: &str) -> Result<u64> {
+            Ok(self.data.len() as u64)
+        }
+
+        fn read_range(&self, _path: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+            Ok(self.data[start as usize..(start + len) as usize].to_vec())
+        }
+
+        fn write(&self, _path: &str, _data: &[u8]) -> Result<()> {
+            Err(BallistaError::NotImplemented("write".to_owned()))
+        }
+    }
+
+    #[test]
+    fn register_custom_store() -> Result<()> {
+        let registry = ObjectStoreRegistry::new(&HashMap::new());
+        assert!(registry.get("mem://bucket/a.csv").is_err());
+
+        registry.register(
+            "mem",
+            Arc::new(MemoryStore {
+                data: b"a,b\n1,2\n".to_vec(),
+            }),
+        );
+        let files = registry.stage_files(&["mem://bucket/a.csv".to_owned()])?;
+        assert_eq!(b"a,b\n1,2\n".to_vec(), fs::read(&files[0])?);
+
+        let local = registry.stage_files(&["/tmp/b.csv".to_owned()])?;
+        assert_eq!("/tmp/b.csv", local[0]);
+        Ok(())
+    }
+
+    /// Fails every other read and returns at most three bytes from the others
+    #[derive(Debug)]
+    struct FlakyStore {
+        data: Vec<u8>,
+        reads: std::sync::atomic::AtomicUsize,
+    }
+
+    impl ObjectStore for FlakyStore {
+        fn list(&self, path: &str) -> Result<Vec<String>> {
+            Ok(vec![path.to_owned()])
+        }
+
+        fn size(&self, _path: &str) -> Result<u64> {
+            Ok(self.data.len() as u64)
+        }
+
+        fn read_range(&self, _path: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+            let reads = self.reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if reads % 2 == 0 {
+                return Err(ballista_error("connection reset"));
+            }
+            let end = (start + len.min(3)) as usize;
+            Ok(self.data[start as usize..end].to_vec())
+        }
+
+        fn write(&self, _path: &str, _data: &[u8]) -> Result<()> {
+            Err(BallistaError::NotImplemented("write".to_owned()))
+        }
+    }
+
+    #[test]
+    fn resume_and_retry_reads() -> Result<()> {
+        let store = FlakyStore {
+            data: b"0123456789".to_vec(),
+            reads: Default::default(),
+        };
+        let policy = ReadRetryPolicy {
+            max_retries: 1,
+            backoff: Duration::from_millis(0),
+        };
+        let part = read_range_fully(&store, "flaky://a", 1, 8, &policy)?;
+        assert_eq!(b"12345678".to_vec(), part);
+
+        let policy = ReadRetryPolicy {
+            max_retries: 0,
+            ..policy
+        };
+        let err = read_range_fully(&store, "flaky://a", 0, 4, &policy).unwrap_err();
+        assert!(err.to_string().contains("after 0 retries"));
+
+        let mut settings = HashMap::new();
+        settings.insert(READ_MAX_RETRIES.to_owned(), "5".to_owned());
+        assert_eq!(5, ReadRetryPolicy::from_settings(&settings).max_retries);
+        assert_eq!(policy.delay(0), Duration::from_millis(0));
+        assert_eq!(ReadRetryPolicy::default().delay(40), MAX_READ_BACKOFF);
+        Ok(())
+    }
+}
@@ -0,0 +1,1048 @@
+//! ORC data source (requires the `orc` feature).
+//!
+//! The schema is read from the ORC file footer and mapped to Arrow types. Files are read a
+//! stripe at a time, decoding the streams of each top-level column of the stripe:
+//!
+//! - booleans and bytes are run length encoded bytes, and nulls are a `PRESENT` stream of
+//!   booleans
+//! - integers, dates and the lengths of strings are run length encoded integers of version 1
+//!   or 2, depending on the encoding of the column
+//! - strings are either direct, with the bytes of each value, or indexes into a dictionary
+//!   of the distinct values of the stripe
+//! - timestamps are seconds since the ORC epoch of 2015-01-01 with separate nanoseconds,
+//!   read as UTC
+//!
+//! Streams are either uncompressed or compressed with zlib. Nested columns and decimals are
+//! not supported.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::iter;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::datasource::adapt_batch;
+use crate::error::{ballista_error, BallistaError, Result};
+
+use flate2::read::DeflateDecoder;
+use prost::Message;
+
+/// Subset of the ORC `PostScript` message that is needed to locate the footer
+#[derive(Clone, PartialEq, Message)]
+struct PostScript {
+    #[prost(uint64, optional, tag = "1")]
+    footer_length: Option<u64>,
+    #[prost(int32, optional, tag = "2")]
+    compression: Option<i32>,
+    #[prost(string, optional, tag = "8000")]
+    magic: Option<String>,
+}
+
+/// Subset of the ORC `Footer` message that describes the file schema and its stripes
+#[derive(Clone, PartialEq, Message)]
+struct Footer {
+    #[prost(message, repeated, tag = "3")]
+    stripes: Vec<StripeInformation>,
+    #[prost(message, repeated, tag = "4")]
+    types: Vec<OrcType>,
+    #[prost(uint64, optional, tag = "6")]
+    number_of_rows: Option<u64>,
+}
+
+/// The location of a stripe in the file, which is its index streams, its data streams and
+/// its footer
+#[derive(Clone, PartialEq, Message)]
+struct StripeInformation {
+    #[prost(uint64, optional, tag = "1")]
+    offset: Option<u64>,
+    #[prost(uint64, optional, tag = "2")]
+    index_length: Option<u64>,
+    #[prost(uint64, optional, tag = "3")]
+    data_length: Option<u64>,
+    #[prost(uint64, optional, tag = "4")]
+    footer_length: Option<u64>,
+    #[prost(uint64, optional, tag = "5")]
+    number_of_rows: Option<u64>,
+}
+
+/// The streams of a stripe, in the order that they are stored in, and the encodings of its
+/// columns
+#[derive(Clone, PartialEq, Message)]
+struct StripeFooter {
+    #[prost(message, repeated, tag = "1")]
+    streams: Vec<Stream>,
+    #[prost(message, repeated, tag = "2")]
+    columns: Vec<ColumnEncoding>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Stream {
+    #[prost(int32, optional, tag = "1")]
+    kind: Option<i32>,
+    #[prost(uint32, optional, tag = "2")]
+    column: Option<u32>,
+    #[prost(uint64, optional, tag = "3")]
+    length: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ColumnEncoding {
+    #[prost(int32, optional, tag = "1")]
+    kind: Option<i32>,
+    #[prost(uint32, optional, tag = "2")]
+    dictionary_size: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct OrcType {
+    #[prost(int32, optional, tag = "1")]
+    kind: Option<i32>,
+    #[prost(uint32, repeated, tag = "2")]
+    subtypes: Vec<u32>,
+    #[prost(string, repeated, tag = "3")]
+    field_names: Vec<String>,
+}
+
+const COMPRESSION_NONE: i32 = 0;
+const COMPRESSION_ZLIB: i32 = 1;
+
+const STREAM_PRESENT: i32 = 0;
+const STREAM_DATA: i32 = 1;
+const STREAM_LENGTH: i32 = 2;
+const STREAM_DICTIONARY_DATA: i32 = 3;
+const STREAM_SECONDARY: i32 = 5;
+
+const ENCODING_DICTIONARY: i32 = 1;
+const ENCODING_DIRECT_V2: i32 = 2;
+const ENCODING_DICTIONARY_V2: i32 = 3;
+
+/// Seconds from the Unix epoch to the ORC epoch of 2015-01-01, which timestamps are
+/// relative to
+const ORC_EPOCH_SECONDS: i64 = 1_420_070_400;
+
+/// Read the Arrow schema from the footer of an ORC file
+pub fn orc_schema(path: &str) -> Result<Schema> {
+    let (footer, _) = read_footer(path)?;
+    footer_schema(&footer)
+}
+
+/// Read the stripes of an ORC file as batches of the given schema, whose columns are
+/// matched to the columns of the file by name
+pub fn read_orc_batches(
+    path: &str,
+    schema: &Schema,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let (footer, compression) = read_footer(path)?;
+    let file_schema = Arc::new(footer_schema(&footer)?);
+    let schema = Arc::new(schema.clone());
+    let mut file = File::open(path)?;
+    let mut batches = vec![];
+    for stripe in &footer.stripes {
+        let columns = read_stripe(&mut file, stripe, &footer, compression)?;
+        let num_rows = stripe.number_of_rows.unwrap_or_default() as usize;
+        let mut rows = 0;
+        while rows < num_rows {
+            let len = batch_size.min(num_rows - rows);
+            let arrays = columns
+                .iter()
+                .zip(file_schema.fields())
+                .map(|(values, field)| values.array(rows..rows + len, field.data_type()))
+                .collect::<Result<Vec<_>>>()?;
+            let batch = RecordBatch::try_new(file_schema.clone(), arrays)?;
+            batches.push(adapt_batch(&batch, &schema)?);
+            rows += len;
+        }
+    }
+    Ok(batches)
+}
+
+/// The Arrow schema of the top-level columns of an ORC file
+fn footer_schema(footer: &Footer) -> Result<Schema> {
+    let root = footer
+        .types
+        .get(0)
+        .ok_or_else(|| ballista_error("ORC footer does not contain a root type"))?;
+
+    let fields = root
+        .field_names
+        .iter()
+        .zip(root.subtypes.iter())
+        .map(|(name, i)| {
+            let orc_type = footer
+                .types
+                .get(*i as usize)
+                .ok_or_else(|| ballista_error(&format!("ORC footer is missing type {}", i)))?;
+            Ok(Field::new(name, to_arrow_type(orc_type)?, true))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Schema::new(fields))
+}
+
+/// Map an ORC type to the equivalent Arrow type
+fn to_arrow_type(orc_type: &OrcType) -> Result<DataType> {
+    match orc_type.kind.unwrap_or_default() {
+        0 => Ok(DataType::Boolean),
+        1 => Ok(DataType::Int8),
+        2 => Ok(DataType::Int16),
+        3 => Ok(DataType::Int32),
+        4 => Ok(DataType::Int64),
+        5 => Ok(DataType::Float32),
+        6 => Ok(DataType::Float64),
+        7 | 16 | 17 => Ok(DataType::Utf8),
+        8 => Ok(DataType::Binary),
+        9 => Ok(DataType::Timestamp(TimeUnit::Nanosecond, None)),
+        15 => Ok(DataType::Date32(DateUnit::Day)),
+        other => Err(BallistaError::NotImplemented(format!(
+            "ORC type kind {}",
+            other
+        ))),
+    }
+}
+
+/// Read the footer of an ORC file and the kind of compression of its streams
+fn read_footer(path: &str) -> Result<(Footer, i32)> {
+    let mut file = File::open(path)?;
+    let file_len = file.seek(SeekFrom::End(0))?;
+    if file_len < 4 {
+        return Err(ballista_error(&format!("{} is not an ORC file", path)));
+    }
+
+    // the last byte of the file holds the length of the postscript
+    let mut ps_len = [0_u8; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut ps_len)?;
+    let ps_len = ps_len[0] as u64;
+    if ps_len + 1 > file_len {
+        return Err(ballista_error(&format!("{} is not an ORC file", path)));
+    }
+
+    let mut buf = vec![0_u8; ps_len as usize];
+    file.seek(SeekFrom::Start(file_len - 1 - ps_len))?;
+    file.read_exact(&mut buf)?;
+    let postscript = PostScript::decode(buf.as_slice()).map_err(orc_error)?;
+    if postscript.magic.as_deref() != Some("ORC") {
+        return Err(ballista_error(&format!("{} is not an ORC file", path)));
+    }
+
+    let footer_len = postscript.footer_length.unwrap_or_default();
+    let footer_start = (file_len - 1 - ps_len)
+        .checked_sub(footer_len)
+        .ok_or_else(|| ballista_error(&format!("{} has an invalid ORC footer length", path)))?;
+    let mut buf = vec![0_u8; footer_len as usize];
+    file.seek(SeekFrom::Start(footer_start))?;
+    file.read_exact(&mut buf)?;
+
+    let compression = postscript.compression.unwrap_or(COMPRESSION_NONE);
+    let buf = decompress(&buf, compression)?;
+    Ok((
+        Footer::decode(buf.as_slice()).map_err(orc_error)?,
+        compression,
+    ))
+}
+
+/// Read the top-level columns of a stripe of an ORC file
+fn read_stripe(
+    file: &mut File,
+    stripe: &StripeInformation,
+    footer: &Footer,
+    compression: i32,
+) -> Result<Vec<ColumnValues>> {
+    let index_len = stripe.index_length.unwrap_or_default() as usize;
+    let data_len = stripe.data_length.unwrap_or_default() as usize;
+    let footer_len = stripe.footer_length.unwrap_or_default() as usize;
+    let mut buf = vec![0_u8; index_len + data_len + footer_len];
+    file.seek(SeekFrom::Start(stripe.offset.unwrap_or_default()))?;
+    file.read_exact(&mut buf)?;
+    let stripe_footer = decompress(&buf[index_len + data_len..], compression)?;
+    let stripe_footer = StripeFooter::decode(stripe_footer.as_slice()).map_err(orc_error)?;
+
+    // the streams are stored one after the other, starting with the index streams, which
+    // are not read
+    let mut streams = HashMap::new();
+    let mut start = 0;
+    for stream in &stripe_footer.streams {
+        let len = stream.length.unwrap_or_default() as usize;
+        let data = buf
+            .get(start..start + len)
+            .ok_or_else(|| ballista_error("Truncated ORC stripe"))?;
+        let kind = stream.kind.unwrap_or_default();
+        if kind <= STREAM_SECONDARY {
+            let column = stream.column.unwrap_or_default();
+            streams.insert((column, kind), decompress(data, compression)?);
+        }
+        start += len;
+    }
+
+    let num_rows = stripe.number_of_rows.unwrap_or_default() as usize;
+    footer.types[0]
+        .subtypes
+        .iter()
+        .map(|column| {
+            let orc_type = footer
+                .types
+                .get(*column as usize)
+                .ok_or_else(|| ballista_error(&format!("ORC footer is missing type {}", column)))?;
+            let encoding = stripe_footer.columns.get(*column as usize);
+            let stream = |kind: i32| {
+                streams
+                    .get(&(*column, kind))
+                    .map_or(&[][..], |s| s.as_slice())
+            };
+            read_column(orc_type, encoding, stream, num_rows)
+        })
+        .collect()
+}
+
+/// The values of a column of a stripe, with `None` for nulls
+enum ColumnValues {
+    Boolean(Vec<Option<bool>>),
+    Int(Vec<Option<i64>>),
+    Float(Vec<Option<f64>>),
+    Bytes(Vec<Option<Vec<u8>>>),
+}
+
+macro_rules! primitive_array {
+    ($ARRAY:ident, $NATIVE:ty, $VALUES:expr) => {{
+        let values: Vec<Option<$NATIVE>> =
+            $VALUES.iter().map(|v| v.map(|v| v as $NATIVE)).collect();
+        Arc::new($ARRAY::from(values)) as ArrayRef
+    }};
+}
+
+impl ColumnValues {
+    /// An array of the values of a range of rows, of the Arrow type of the column
+    fn array(&self, rows: Range<usize>, data_type: &DataType) -> Result<ArrayRef> {
+        Ok(match (self, data_type) {
+            (ColumnValues::Boolean(values), DataType::Boolean) => {
+                Arc::new(BooleanArray::from(values[rows].to_vec()))
+            }
+            (ColumnValues::Int(values), DataType::Int8) => {
+                primitive_array!(Int8Array, i8, values[rows])
+            }
+            (ColumnValues::Int(values), DataType::Int16) => {
+                primitive_array!(Int16Array, i16, values[rows])
+            }
+            (ColumnValues::Int(values), DataType::Int32) => {
+                primitive_array!(Int32Array, i32, values[rows])
+            }
+            (ColumnValues::Int(values), DataType::Int64) => {
+                primitive_array!(Int64Array, i64, values[rows])
+            }
+            (ColumnValues::Int(values), DataType::Date32(_)) => {
+                primitive_array!(Date32Array, i32, values[rows])
+            }
+            (ColumnValues::Int(values), DataType::Timestamp(TimeUnit::Nanosecond, None)) => {
+                primitive_array!(TimestampNanosecondArray, i64, values[rows])
+            }
+            (ColumnValues::Float(values), DataType::Float32) => {
+                primitive_array!(Float32Array, f32, values[rows])
+            }
+            (ColumnValues::Float(values), DataType::Float64) => {
+                Arc::new(Float64Array::from(values[rows].to_vec()))
+            }
+            (ColumnValues::Bytes(values), DataType::Utf8) => {
+                let mut builder = StringBuilder::new(rows.len());
+                for value in &values[rows] {
+                    match value {
+                        Some(value) => {
+                            builder.append_value(std::str::from_utf8(value).map_err(|_| {
+                                ballista_error("ORC string column is not valid UTF-8")
+                            })?)?
+                        }
+                        None => builder.append_null()?,
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            (ColumnValues::Bytes(values), DataType::Binary) => {
+                let mut builder = BinaryBuilder::new(rows.len());
+                for value in &values[rows] {
+                    match value {
+                        Some(value) => builder.append_value(value)?,
+                        None => builder.append_null()?,
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            (_, other) => {
+                return Err(ballista_error(&format!(
+                    "Cannot read ORC column of type {:?}",
+                    other
+                )))
+            }
+        })
+    }
+}
+
+/// Read the values of a column of a stripe from its streams
+fn read_column<'a>(
+    orc_type: &OrcType,
+    encoding: Option<&ColumnEncoding>,
+    stream: impl Fn(i32) -> &'a [u8],
+    num_rows: usize,
+) -> Result<ColumnValues> {
+    // nulls are only stored in the present stream, which is missing when there are none
+    let present = stream(STREAM_PRESENT);
+    let present = if present.is_empty() {
+        None
+    } else {
+        Some(read_booleans(present, num_rows)?)
+    };
+    let count = present
+        .as_ref()
+        .map_or(num_rows, |p| p.iter().filter(|p| **p).count());
+    let encoding_kind = encoding.and_then(|e| e.kind).unwrap_or_default();
+    let v2 = encoding_kind == ENCODING_DIRECT_V2 || encoding_kind == ENCODING_DICTIONARY_V2;
+    let data = stream(STREAM_DATA);
+
+    Ok(match orc_type.kind.unwrap_or_default() {
+        0 => ColumnValues::Boolean(with_nulls(read_booleans(data, count)?, &present)),
+        1 => {
+            let bytes = read_byte_runs(data, count)?;
+            let values = bytes.into_iter().map(|b| b as i8 as i64).collect();
+            ColumnValues::Int(with_nulls(values, &present))
+        }
+        2 | 3 | 4 | 15 => {
+            ColumnValues::Int(with_nulls(read_integers(data, count, true, v2)?, &present))
+        }
+        5 => {
+            let values = data
+                .chunks_exact(4)
+                .take(count)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                .collect::<Vec<_>>();
+            ColumnValues::Float(with_nulls(check_count(values, count)?, &present))
+        }
+        6 => {
+            let values = data
+                .chunks_exact(8)
+                .take(count)
+                .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+                .collect::<Vec<_>>();
+            ColumnValues::Float(with_nulls(check_count(values, count)?, &present))
+        }
+        7 | 8 | 16 | 17 => {
+            let values = if encoding_kind == ENCODING_DICTIONARY
+                || encoding_kind == ENCODING_DICTIONARY_V2
+            {
+                let size = encoding.and_then(|e| e.dictionary_size).unwrap_or_default() as usize;
+                let lengths = read_integers(stream(STREAM_LENGTH), size, false, v2)?;
+                let dictionary = split_bytes(stream(STREAM_DICTIONARY_DATA), &lengths)?;
+                read_integers(data, count, false, v2)?
+                    .into_iter()
+                    .map(|i| {
+                        dictionary
+                            .get(i as usize)
+                            .cloned()
+                            .ok_or_else(|| ballista_error("ORC dictionary index is out of range"))
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            } else {
+                let lengths = read_integers(stream(STREAM_LENGTH), count, false, v2)?;
+                split_bytes(data, &lengths)?
+            };
+            ColumnValues::Bytes(with_nulls(values, &present))
+        }
+        9 => {
+            let seconds = read_integers(data, count, true, v2)?;
+            let nanos = read_integers(stream(STREAM_SECONDARY), count, false, v2)?;
+            let values = seconds
+                .iter()
+                .zip(&nanos)
+                .map(|(seconds, nanos)| {
+                    (seconds + ORC_EPOCH_SECONDS) * 1_000_000_000 + decode_nanos(*nanos as u64)
+                })
+                .collect();
+            ColumnValues::Int(with_nulls(values, &present))
+        }
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Reading ORC columns of type kind {}",
+                other
+            )))
+        }
+    })
+}
+
+/// Spread the non-null values of a column over its rows, which are null where they are
+/// not present
+fn with_nulls<T>(values: Vec<T>, present: &Option<Vec<bool>>) -> Vec<Option<T>> {
+    match present {
+        Some(present) => {
+            let mut values = values.into_iter();
+            present
+                .iter()
+                .map(|p| if *p { values.next() } else { None })
+                .collect()
+        }
+        None => values.into_iter().map(Some).collect(),
+    }
+}
+
+/// The values of a stream of fixed width values, which has a value for each non-null row
+fn check_count<T>(values: Vec<T>, count: usize) -> Result<Vec<T>> {
+    if values.len() == count {
+        Ok(values)
+    } else {
+        Err(ballista_error("Truncated ORC stream"))
+    }
+}
+
+/// Split the bytes of a stream into values of the given lengths
+fn split_bytes(data: &[u8], lengths: &[i64]) -> Result<Vec<Vec<u8>>> {
+    let mut start = 0;
+    lengths
+        .iter()
+        .map(|len| {
+            let end = start + *len as usize;
+            let value = data
+                .get(start..end)
+                .ok_or_else(|| ballista_error("Truncated ORC stream"))?;
+            start = end;
+            Ok(value.to_vec())
+        })
+        .collect()
+}
+
+/// The nanoseconds of a timestamp, which are stored with the number of trailing decimal
+/// zeros, less one, in their lowest three bits
+fn decode_nanos(value: u64) -> i64 {
+    let zeros = value & 7;
+    let nanos = (value >> 3) as i64;
+    if zeros == 0 {
+        nanos
+    } else {
+        nanos * 10_i64.pow(zeros as u32 + 1)
+    }
+}
+
+/// A reader of the bytes of a decompressed stream
+struct StreamReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StreamReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| ballista_error("Truncated ORC stream"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    /// A base 128 varint, with the lowest seven bits first
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0_u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// An integer of the given number of bytes, with the highest byte first
+    fn big_endian(&mut self, bytes: usize) -> Result<u64> {
+        let mut value = 0_u64;
+        for _ in 0..bytes {
+            value = (value << 8) | self.byte()? as u64;
+        }
+        Ok(value)
+    }
+
+    /// Values of the given bit width packed with the highest bit first, up to the end of
+    /// the last byte of the values
+    fn unpack(&mut self, width: usize, count: usize) -> Result<Vec<u64>> {
+        let mut values = Vec::with_capacity(count);
+        let mut bit = 0;
+        for _ in 0..count {
+            let mut value = 0_u64;
+            for _ in 0..width {
+                let byte = *self
+                    .data
+                    .get(self.pos + bit / 8)
+                    .ok_or_else(|| ballista_error("Truncated ORC stream"))?;
+                value = (value << 1) | ((byte >> (7 - bit % 8)) & 1) as u64;
+                bit += 1;
+            }
+            values.push(value);
+        }
+        self.pos += (bit + 7) / 8;
+        Ok(values)
+    }
+}
+
+/// Read run length encoded bytes, which are runs of a byte repeated 3 to 130 times or
+/// literals of 1 to 128 bytes
+fn read_byte_runs(data: &[u8], count: usize) -> Result<Vec<u8>> {
+    let mut reader = StreamReader::new(data);
+    let mut values = Vec::with_capacity(count);
+    while values.len() < count {
+        let header = reader.byte()? as i8;
+        if header >= 0 {
+            let value = reader.byte()?;
+            values.extend(iter::repeat(value).take(header as usize + 3));
+        } else {
+            for _ in 0..-(header as i16) {
+                values.push(reader.byte()?);
+            }
+        }
+    }
+    values.truncate(count);
+    Ok(values)
+}
+
+/// Read run length encoded booleans, which are run length encoded bytes of eight booleans
+/// each, with the first in the highest bit
+fn read_booleans(data: &[u8], count: usize) -> Result<Vec<bool>> {
+    let bytes = read_byte_runs(data, (count + 7) / 8)?;
+    Ok((0..count)
+        .map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+        .collect())
+}
+
+/// Decode a zigzag encoded signed integer
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Read run length encoded integers of version 1 or 2. Unsigned integers are read as the
+/// bits of signed integers.
+fn read_integers(data: &[u8], count: usize, signed: bool, v2: bool) -> Result<Vec<i64>> {
+    let mut reader = StreamReader::new(data);
+    let mut values = Vec::with_capacity(count);
+    let decode = |value: u64| {
+        if signed {
+            unzigzag(value)
+        } else {
+            value as i64
+        }
+    };
+    while values.len() < count {
+        if v2 {
+            read_integer_run_v2(&mut reader, signed, &mut values)?;
+            continue;
+        }
+        // version 1 runs are a base and a delta repeated 3 to 130 times, or literals
+        let header = reader.byte()? as i8;
+        if header >= 0 {
+            let delta = reader.byte()? as i8 as i64;
+            let base = decode(reader.varint()?);
+            values.extend((0..header as i64 + 3).map(|i| base.wrapping_add(i * delta)));
+        } else {
+            for _ in 0..-(header as i16) {
+                values.push(decode(reader.varint()?));
+            }
+        }
+    }
+    values.truncate(count);
+    Ok(values)
+}
+
+/// Read a run of version 2 run length encoded integers, which is a short repeat of a
+/// value, directly bit packed values, bit packed values from a base with patches of their
+/// highest bits, or deltas from a base value
+fn read_integer_run_v2(
+    reader: &mut StreamReader,
+    signed: bool,
+    values: &mut Vec<i64>,
+) -> Result<()> {
+    let decode = |value: u64| {
+        if signed {
+            unzigzag(value)
+        } else {
+            value as i64
+        }
+    };
+    let header = reader.byte()?;
+    match header >> 6 {
+        0 => {
+            let width = ((header >> 3) & 7) as usize + 1;
+            let count = (header & 7) as usize + 3;
+            let value = decode(reader.big_endian(width)?);
+            values.extend(iter::repeat(value).take(count));
+        }
+        1 => {
+            let width = bit_width((header >> 1) & 0x1f);
+            let count = (((header & 1) as usize) << 8 | reader.byte()? as usize) + 1;
+            values.extend(reader.unpack(width, count)?.into_iter().map(decode));
+        }
+        2 => {
+            let width = bit_width((header >> 1) & 0x1f);
+            let count = (((header & 1) as usize) << 8 | reader.byte()? as usize) + 1;
+            let third = reader.byte()?;
+            let base_width = ((third >> 5) & 7) as usize + 1;
+            let patch_width = bit_width(third & 0x1f);
+            let fourth = reader.byte()?;
+            let gap_width = ((fourth >> 5) & 7) as usize + 1;
+            let patches = (fourth & 0x1f) as usize;
+            // the base is stored with its sign in the highest bit rather than zigzag encoded
+            let base = reader.big_endian(base_width)?;
+            let sign = 1_u64 << (base_width * 8 - 1);
+            let base = if base & sign != 0 {
+                -((base & !sign) as i64)
+            } else {
+                base as i64
+            };
+            let mut run = reader.unpack(width, count)?;
+            let entries = reader.unpack(closest_fixed_bits(patch_width + gap_width), patches)?;
+            let mut position = 0;
+            for entry in entries {
+                position += (entry >> patch_width) as usize;
+                let patch = entry & ((1_u64 << patch_width) - 1);
+                let value = run
+                    .get_mut(position)
+                    .ok_or_else(|| ballista_error("ORC patch is out of range"))?;
+                *value |= patch.checked_shl(width as u32).unwrap_or(0);
+            }
+            values.extend(run.into_iter().map(|v| base.wrapping_add(v as i64)));
+        }
+        _ => {
+            let encoded = (header >> 1) & 0x1f;
+            let width = if encoded == 0 { 0 } else { bit_width(encoded) };
+            let count = (((header & 1) as usize) << 8 | reader.byte()? as usize) + 1;
+            let base = decode(reader.varint()?);
+            let delta = unzigzag(reader.varint()?);
+            let mut value = base;
+            values.push(value);
+            if width == 0 {
+                // a fixed delta between all of the values
+                for _ in 1..count {
+                    value = value.wrapping_add(delta);
+                    values.push(value);
+                }
+            } else if count > 1 {
+                value = value.wrapping_add(delta);
+                values.push(value);
+                // the other deltas are magnitudes with the sign of the first delta
+                for magnitude in reader.unpack(width, count - 2)? {
+                    value = if delta < 0 {
+                        value.wrapping_sub(magnitude as i64)
+                    } else {
+                        value.wrapping_add(magnitude as i64)
+                    };
+                    values.push(value);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The bit width of a 5-bit encoded width of version 2 integer runs
+fn bit_width(encoded: u8) -> usize {
+    match encoded {
+        0..=23 => encoded as usize + 1,
+        24 => 26,
+        25 => 28,
+        26 => 30,
+        27 => 32,
+        28 => 40,
+        29 => 48,
+        30 => 56,
+        _ => 64,
+    }
+}
+
+/// The smallest bit width that can be encoded that is at least the given width
+fn closest_fixed_bits(width: usize) -> usize {
+    match width {
+        0..=24 => width.max(1),
+        25..=26 => 26,
+        27..=28 => 28,
+        29..=30 => 30,
+        31..=32 => 32,
+        33..=40 => 40,
+        41..=48 => 48,
+        49..=56 => 56,
+        _ => 64,
+    }
+}
+
+/// Decompress a stream, the footer or the footer of a stripe
+fn decompress(buf: &[u8], compression: i32) -> Result<Vec<u8>> {
+    match compression {
+        COMPRESSION_NONE => Ok(buf.to_vec()),
+        COMPRESSION_ZLIB => decompress_zlib(buf),
+        other => Err(BallistaError::NotImplemented(format!(
+            "ORC compression kind {}",
+            other
+        ))),
+    }
+}
+
+/// ORC splits compressed streams into chunks, each with a 3-byte header holding the chunk
+/// length and a flag indicating whether the chunk was stored uncompressed
+fn decompress_zlib(buf: &[u8]) -> Result<Vec<u8>> {
+    let mut output = vec![];
+    let mut offset = 0;
+    while offset + 3 <= buf.len() {
+        let header = buf[offset] as usize
+            | (buf[offset + 1] as usize) << 8
+            | (buf[offset + 2] as usize) << 16;
+        let is_original = header & 1 == 1;
+        let chunk_len = header >> 1;
+        offset += 3;
+        let chunk = buf
+            .get(offset..offset + chunk_len)
+            .ok_or_else(|| ballista_error("Truncated ORC compression chunk"))?;
+        if is_original {
+            output.extend_from_slice(chunk);
+        } else {
+            DeflateDecoder::new(chunk).read_to_end(&mut output)?;
+        }
+        offset += chunk_len;
+    }
+    Ok(output)
+}
+
+fn orc_error(e: prost::DecodeError) -> BallistaError {
+    BallistaError::General(format!("Invalid ORC metadata: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::path::Path;
+
+    fn encode(message: &impl Message) -> Vec<u8> {
+        let mut buf = vec![];
+        message.encode(&mut buf).unwrap();
+        buf
+    }
+
+    /// Compress a stream as a single chunk of zlib compressed data
+    fn compress(data: &[u8], compression: i32) -> Vec<u8> {
+        if compression == COMPRESSION_NONE {
+            return data.to_vec();
+        }
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        let chunk = encoder.finish().unwrap();
+        let header = chunk.len() << 1;
+        let mut output = vec![header as u8, (header >> 8) as u8, (header >> 16) as u8];
+        output.extend(chunk);
+        output
+    }
+
+    /// Write an ORC file with a single stripe of the given streams
+    fn write_orc(
+        path: &Path,
+        types: Vec<OrcType>,
+        columns: Vec<ColumnEncoding>,
+        streams: Vec<(u32, i32, Vec<u8>)>,
+        num_rows: u64,
+        compression: i32,
+    ) -> Result<()> {
+        let mut file = b"ORC".to_vec();
+        let mut stripe_footer = StripeFooter {
+            streams: vec![],
+            columns,
+        };
+        for (column, kind, data) in streams {
+            let data = compress(&data, compression);
+            stripe_footer.streams.push(Stream {
+                kind: Some(kind),
+                column: Some(column),
+                length: Some(data.len() as u64),
+            });
+            file.extend(data);
+        }
+        let data_length = file.len() as u64 - 3;
+        let stripe_footer = compress(&encode(&stripe_footer), compression);
+        file.extend(&stripe_footer);
+        let footer = Footer {
+            stripes: vec![StripeInformation {
+                offset: Some(3),
+                index_length: Some(0),
+                data_length: Some(data_length),
+                footer_length: Some(stripe_footer.len() as u64),
+                number_of_rows: Some(num_rows),
+            }],
+            types,
+            number_of_rows: Some(num_rows),
+        };
+        let footer = compress(&encode(&footer), compression);
+        file.extend(&footer);
+        let postscript = encode(&PostScript {
+            footer_length: Some(footer.len() as u64),
+            compression: Some(compression),
+            magic: Some("ORC".to_owned()),
+        });
+        file.extend(&postscript);
+        file.push(postscript.len() as u8);
+        std::fs::write(path, file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn decode_run_length_encodings() -> Result<()> {
+        // the examples of the ORC specification
+        let runs: Vec<(&[u8], Vec<i64>)> = vec![
+            (&[0x0a, 0x27, 0x10], vec![10000; 5]),
+            (
+                &[0x5e, 0x03, 0x5c, 0xa1, 0xab, 0x1e, 0xde, 0xad, 0xbe, 0xef],
+                vec![23713, 43806, 57005, 48879],
+            ),
+            (
+                &[
+                    0x8e, 0x13, 0x2b, 0x21, 0x07, 0xd0, 0x1e, 0x00, 0x14, 0x70, 0x28, 0x32, 0x3c,
+                    0x46, 0x50, 0x5a, 0x64, 0x6e, 0x78, 0x82, 0x8c, 0x96, 0xa0, 0xaa, 0xb4, 0xbe,
+                    0xfc, 0xe8,
+                ],
+                vec![
+                    2030, 2000, 2020, 1_000_000, 2040, 2050, 2060, 2070, 2080, 2090, 2100, 2110,
+                    2120, 2130, 2140, 2150, 2160, 2170, 2180, 2190,
+                ],
+            ),
+            (
+                &[0xc6, 0x09, 0x02, 0x02, 0x22, 0x42, 0x42, 0x46],
+                vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29],
+            ),
+        ];
+        for (data, expected) in runs {
+            assert_eq!(expected, read_integers(data, expected.len(), false, true)?);
+        }
+        assert_eq!(
+            vec![100; 100],
+            read_integers(&[0x61, 0x00, 0x64], 100, false, false)?
+        );
+        assert_eq!(
+            vec![2, 3, 6, 7, 11],
+            read_integers(&[0xfb, 0x02, 0x03, 0x06, 0x07, 0x0b], 5, false, false)?
+        );
+        assert_eq!(vec![0x44; 100], read_byte_runs(&[0x61, 0x44], 100)?);
+        assert_eq!(
+            vec![true, false, false, false, true, false],
+            read_booleans(&[0xff, 0x88], 6)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_stripes() -> Result<()> {
+        let primitive = |kind| OrcType {
+            kind: Some(kind),
+            subtypes: vec![],
+            field_names: vec![],
+        };
+        let names = ["id", "name", "flag", "city", "total"];
+        let types = vec![
+            OrcType {
+                kind: Some(12),
+                subtypes: vec![1, 2, 3, 4, 5],
+                field_names: names.iter().map(|n| n.to_string()).collect(),
+            },
+            primitive(3),
+            primitive(7),
+            primitive(0),
+            primitive(7),
+            primitive(4),
+        ];
+        let encoding = |kind, dictionary_size| ColumnEncoding {
+            kind: Some(kind),
+            dictionary_size,
+        };
+        let columns = vec![
+            encoding(0, None),
+            encoding(ENCODING_DIRECT_V2, None),
+            encoding(ENCODING_DICTIONARY_V2, Some(2)),
+            encoding(0, None),
+            encoding(0, None),
+            encoding(0, None),
+        ];
+        let streams = vec![
+            // a version 2 run of deltas of one from one
+            (1, STREAM_DATA, vec![0xc0, 0x04, 0x02, 0x02]),
+            // the third name is null, and the others are indexes into the dictionary
+            (2, STREAM_PRESENT, vec![0xff, 0xd8]),
+            (2, STREAM_DATA, vec![0x40, 0x03, 0x60]),
+            (2, STREAM_LENGTH, vec![0x42, 0x01, 0xf0]),
+            (2, STREAM_DICTIONARY_DATA, b"annbob".to_vec()),
+            (3, STREAM_DATA, vec![0xff, 0xb0]),
+            // a version 1 run of five lengths of two
+            (4, STREAM_DATA, b"nylasfdcla".to_vec()),
+            (4, STREAM_LENGTH, vec![0x02, 0x00, 0x02]),
+            // version 1 literals
+            (
+                5,
+                STREAM_DATA,
+                vec![0xfb, 0x01, 0x0a, 0xd8, 0x04, 0x0e, 0x00],
+            ),
+        ];
+
+        for compression in &[COMPRESSION_NONE, COMPRESSION_ZLIB] {
+            let path = std::env::temp_dir().join(format!(
+                "stripes-{}-{}.orc",
+                compression,
+                std::process::id()
+            ));
+            write_orc(
+                &path,
+                types.clone(),
+                columns.clone(),
+                streams.clone(),
+                5,
+                *compression,
+            )?;
+            let file = path.to_string_lossy();
+            let schema = orc_schema(&file)?;
+            let batches = read_orc_batches(&file, &schema, 2)?;
+            std::fs::remove_file(&path)?;
+
+            let rows: Vec<usize> = batches.iter().map(|b| b.num_rows()).collect();
+            assert_eq!(vec![2, 2, 1], rows);
+            let values = |column: usize| -> Vec<String> {
+                batches
+                    .iter()
+                    .flat_map(|batch| {
+                        let array = batch.column(column).clone();
+                        (0..array.len())
+                            .map(|i| {
+                                if array.is_null(i) {
+                                    return "null".to_owned();
+                                }
+                                let any = array.as_any();
+                                if let Some(a) = any.downcast_ref::<Int32Array>() {
+                                    a.value(i).to_string()
+                                } else if let Some(a) = any.downcast_ref::<Int64Array>() {
+                                    a.value(i).to_string()
+                                } else if let Some(a) = any.downcast_ref::<BooleanArray>() {
+                                    a.value(i).to_string()
+                                } else {
+                                    let a = any.downcast_ref::<StringArray>().unwrap();
+                                    a.value(i).to_owned()
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect()
+            };
+            assert_eq!(vec!["1", "2", "3", "4", "5"], values(0));
+            assert_eq!(vec!["ann", "bob", "null", "bob", "ann"], values(1));
+            assert_eq!(vec!["true", "false", "true", "true", "false"], values(2));
+            assert_eq!(vec!["ny", "la", "sf", "dc", "la"], values(3));
+            assert_eq!(vec!["-1", "5", "300", "7", "0"], values(4));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1877 @@
+//! Parquet data source supporting directories and lists of files with schema merging, and
+//! a writer for the output of queries.
+//!
+//! The schemas and statistics that queries are planned with are read from the footers of
+//! the files. Each context and executor keeps the footers that it has parsed in a
+//! `FooterCache`, keyed by the path, size and modification time of the file, so repeated
+//! queries over the same files do not read and parse their footers again.
+//!
+//! Arrow has no decimal type, so decimal columns are read as `Utf8` columns of the exact
+//! decimal text of their values, such as `-0.50` for a scale of 2, and their precision and
+//! scale are kept in the metadata of the schema (see `decimal_precision_and_scale`), which
+//! arithmetic and aggregates of decimals are computed with (see `decimal`). Decimal columns
+//! are read apart from the other columns of a file, whose batches they are then added to,
+//! and `Utf8` columns with a decimal precision and scale are written as decimals.
+//!
+//! Struct columns are read as struct arrays. The Arrow reader of this version of parquet
+//! cannot read list columns, so files with list columns are read with the column reader
+//! instead, building list arrays from the definition and repetition levels of the leaf
+//! columns of lists of primitive values. Struct columns and lists of structs or lists are
+//! not supported in files with list columns, which is an error rather than a panic within
+//! the reader.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::aggregate::to_array;
+use crate::arrow::array::*;
+use crate::arrow::buffer::Buffer;
+use crate::arrow::compute::kernels::cast::cast;
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, SchemaRef, ToByteSlice};
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datafusion::datasource::TableProvider;
+use crate::datafusion::error::{ExecutionError, Result as DataFusionResult};
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::ipc::ProjectedReader;
+use crate::datasource::object_store::ObjectStoreRegistry;
+use crate::datasource::partitioned::{add_partition_columns, discover_partition_columns, flip};
+use crate::datasource::{adapt_batch, expand_path, is_remote_path, DEFAULT_BATCH_SIZE};
+use crate::decimal::parse_decimal;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::join::KeyValue;
+use crate::logicalplan::{
+    decimal_to_f64, format_decimal, get_supertype, Expr, Operator, ScalarValue,
+};
+use crate::physical_plan::memory::MemoryReader;
+use crate::statistics::{ColumnStatistics, PlanStatistics, TableStatistics};
+
+use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+use parquet::column::reader::{ColumnReader, ColumnReaderImpl};
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::{ByteArray, DataType as ParquetDataType};
+use parquet::file::metadata::RowGroupMetaData;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::statistics::Statistics;
+use parquet::file::writer::{FileWriter, RowGroupWriter, SerializedFileWriter};
+use parquet::schema::types::{ColumnDescriptor, SchemaDescriptor, Type};
+
+pub use crate::decimal::{decimal_precision_and_scale, DECIMAL_METADATA_PREFIX};
+
+/// Find all parquet files in a path. The path may contain glob patterns, and directories
+/// are searched recursively for files with a `.parquet` extension.
+pub fn list_parquet_files(path: &str) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for p in expand_path(path)? {
+        collect_files(Path::new(&p), &mut files)?;
+    }
+    if files.is_empty() {
+        return Err(ballista_error(&format!(
+            "No parquet files found in {}",
+            path
+        )));
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files(path: &Path, files: &mut Vec<String>) -> Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry_path = entry?.path();
+            if entry_path.is_dir() {
+                collect_files(&entry_path, files)?;
+            } else if entry_path.extension().map(|e| e == "parquet") == Some(true) {
+                files.push(entry_path.to_string_lossy().to_string());
+            }
+        }
+    } else {
+        files.push(path.to_string_lossy().to_string());
+    }
+    Ok(())
+}
+
+/// Number of parquet footers that a context or executor keeps in memory
+pub const PARQUET_FOOTER_CACHE_SIZE: &str = "ballista.parquet.footerCacheSize";
+
+/// Number of footers that are cached when the cache size is not configured
+pub const DEFAULT_FOOTER_CACHE_SIZE: usize = 10_000;
+
+/// The schema and row group metadata of a parquet file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParquetFooter {
+    pub schema: Schema,
+    pub row_groups: Vec<RowGroupFooter>,
+}
+
+/// The number of rows and the column statistics of a row group
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowGroupFooter {
+    pub num_rows: i64,
+    pub columns: Vec<ColumnFooter>,
+}
+
+/// The statistics of a column chunk that queries are planned with
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnFooter {
+    pub name: String,
+    pub null_count: Option<u64>,
+    pub distinct_count: Option<u64>,
+    /// The minimum and maximum values, for the physical types whose statistics compare in
+    /// the same order as their values
+    range: Option<(StatValue, StatValue)>,
+}
+
+impl ParquetFooter {
+    /// Read and parse the footer of a parquet file
+    pub fn read(file: &str) -> Result<Self> {
+        let reader: Rc<dyn FileReader> = Rc::new(SerializedFileReader::new(File::open(file)?)?);
+        let row_groups = reader
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(RowGroupFooter::from_metadata)
+            .collect();
+        let schema = arrow_schema(&reader)?;
+        Ok(Self { schema, row_groups })
+    }
+}
+
+impl RowGroupFooter {
+    fn from_metadata(row_group: &RowGroupMetaData) -> Self {
+        let columns = row_group
+            .columns()
+            .iter()
+            .map(|chunk| {
+                let statistics = chunk.statistics();
+                let descr = chunk.column_descr();
+                let range = statistics.and_then(statistics_range);
+                ColumnFooter {
+                    name: descr.name().to_owned(),
+                    null_count: statistics.map(|s| s.null_count()),
+                    distinct_count: statistics.and_then(|s| s.distinct_count()),
+                    // the statistics of decimals are of their unscaled values
+                    range: match descr.logical_type() {
+                        LogicalType::DECIMAL => range.and_then(|(min, max)| {
+                            let scale = descr.type_scale() as usize;
+                            Some((min.unscaled(scale)?, max.unscaled(scale)?))
+                        }),
+                        _ => range,
+                    },
+                }
+            })
+            .collect();
+        Self {
+            num_rows: row_group.num_rows(),
+            columns,
+        }
+    }
+
+    /// The statistics of a column, which are missing for columns that are not in the file,
+    /// such as partition columns
+    pub fn column(&self, name: &str) -> Option<&ColumnFooter> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+}
+
+/// The version of a file that a cached footer was read from
+type FileVersion = (u64, Option<SystemTime>);
+
+/// Parsed footers of parquet files, keyed by path. A footer is read again when the size
+/// or modification time of its file changes, and the least recently read footers are
+/// evicted once the cache is full.
+#[derive(Debug)]
+pub struct FooterCache {
+    capacity: usize,
+    footers: Mutex<CachedFooters>,
+}
+
+#[derive(Debug, Default)]
+struct CachedFooters {
+    footers: HashMap<String, (FileVersion, Arc<ParquetFooter>)>,
+    /// Paths in the order that their footers were read, oldest first
+    order: VecDeque<String>,
+}
+
+impl FooterCache {
+    /// Create a cache that holds at most `capacity` footers
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            footers: Mutex::new(CachedFooters::default()),
+        }
+    }
+
+    /// Create a cache with the size in the `ballista.parquet.footerCacheSize` setting
+    pub fn from_settings(settings: &HashMap<String, String>) -> Self {
+        let capacity = settings
+            .get(PARQUET_FOOTER_CACHE_SIZE)
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_FOOTER_CACHE_SIZE);
+        Self::new(capacity)
+    }
+
+    /// The footer of a local parquet file, which is read if it is not cached or the file
+    /// has changed since it was read
+    pub fn footer(&self, file: &str) -> Result<Arc<ParquetFooter>> {
+        let metadata = fs::metadata(file)?;
+        let version = (metadata.len(), metadata.modified().ok());
+        if let Some((cached, footer)) = self.footers.lock().unwrap().footers.get(file) {
+            if *cached == version {
+                return Ok(footer.clone());
+            }
+        }
+
+        // the footer is parsed without holding the lock, so other files can be read at the
+        // same time
+        let footer = Arc::new(ParquetFooter::read(file).map_err(|e| e.in_file(file))?);
+        if self.capacity > 0 {
+            let mut cached = self.footers.lock().unwrap();
+            let entry = (version, footer.clone());
+            if cached.footers.insert(file.to_owned(), entry).is_none() {
+                cached.order.push_back(file.to_owned());
+            }
+            while cached.order.len() > self.capacity {
+                if let Some(oldest) = cached.order.pop_front() {
+                    cached.footers.remove(&oldest);
+                }
+            }
+        }
+        Ok(footer)
+    }
+
+    /// The number of footers in the cache
+    pub fn len(&self) -> usize {
+        self.footers.lock().unwrap().footers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for FooterCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_FOOTER_CACHE_SIZE)
+    }
+}
+
+/// Read the schema of a single parquet file
+pub fn parquet_file_schema(file: &str, footers: &FooterCache) -> Result<Schema> {
+    Ok(footers.footer(file)?.schema.clone())
+}
+
+/// Determine the merged schema of a list of parquet files
+pub fn parquet_schema(files: &[String], footers: &FooterCache) -> Result<Schema> {
+    let schemas = files
+        .iter()
+        .map(|f| parquet_file_schema(f, footers))
+        .collect::<Result<Vec<_>>>()?;
+    merge_schemas(&schemas)
+}
+
+/// The parquet files in a list of files and directories, which may be remote
+#[derive(Debug, Clone)]
+pub struct ParquetDataset {
+    pub files: Vec<String>,
+    /// The merged schema of the files, followed by the partition columns
+    pub schema: Schema,
+    /// The keys of a `key=value` directory layout, which are exposed as columns
+    pub partition_columns: Vec<String>,
+}
+
+/// Find the parquet files in a list of files and directories and determine their schema.
+/// Remote paths are listed and read through the object stores.
+pub fn parquet_dataset(
+    paths: &[&str],
+    object_stores: &ObjectStoreRegistry,
+) -> Result<ParquetDataset> {
+    let mut files = vec![];
+    for path in paths {
+        if is_remote_path(path) {
+            files.extend(
+                object_stores
+                    .get(path)?
+                    .list(path)?
+                    .into_iter()
+                    .filter(|f| f.ends_with(".parquet")),
+            );
+        } else {
+            files.extend(list_parquet_files(path)?);
+        }
+    }
+    let staged = object_stores.stage_files(&files)?;
+    let mut fields = parquet_schema(&staged, object_stores.footers())?
+        .fields()
+        .clone();
+
+    let partition_fields = discover_partition_columns(&files);
+    let partition_columns = partition_fields.iter().map(|f| f.name().clone()).collect();
+    fields.extend(partition_fields);
+    Ok(ParquetDataset {
+        files,
+        schema: Schema::new(fields),
+        partition_columns,
+    })
+}
+
+/// The statistics of a table of parquet files from the metadata of the files. The number
+/// of distinct values of a column is the largest number in any row group, and is only known
+/// when the files record it for every row group.
+pub fn parquet_statistics(
+    files: &[String],
+    schema: &Schema,
+    footers: &FooterCache,
+) -> Result<PlanStatistics> {
+    let mut num_rows = 0;
+    let mut distinct_counts: Vec<Option<f64>> = vec![Some(0.0); schema.fields().len()];
+    for file in files {
+        for row_group in &footers.footer(file)?.row_groups {
+            num_rows += row_group.num_rows;
+            for (i, field) in schema.fields().iter().enumerate() {
+                let distinct = row_group
+                    .column(field.name())
+                    .and_then(|c| c.distinct_count);
+                distinct_counts[i] = match (distinct_counts[i], distinct) {
+                    (Some(max), Some(distinct)) => Some(max.max(distinct as f64)),
+                    _ => None,
+                };
+            }
+        }
+    }
+    Ok(PlanStatistics {
+        num_rows: num_rows as f64,
+        distinct_counts,
+    })
+}
+
+/// The row counts, null counts and value ranges of a table of parquet files from the
+/// metadata of the files. The null count and range of a column are only known when every
+/// row group records them, so they are not known for columns that are missing from some of
+/// the files.
+pub fn parquet_table_statistics(
+    files: &[String],
+    schema: &Schema,
+    footers: &FooterCache,
+) -> Result<TableStatistics> {
+    let mut statistics = TableStatistics::empty(schema.fields().len());
+    let mut ranges: Vec<Option<(StatValue, StatValue)>> = vec![None; schema.fields().len()];
+    let mut known = vec![true; schema.fields().len()];
+    for file in files {
+        for row_group in &footers.footer(file)?.row_groups {
+            statistics.num_rows += row_group.num_rows as u64;
+            for (i, field) in schema.fields().iter().enumerate() {
+                let column = &mut statistics.columns[i];
+                let nulls = row_group.column(field.name()).and_then(|c| c.null_count);
+                column.null_count = match (column.null_count, nulls) {
+                    (Some(total), Some(nulls)) => Some(total + nulls),
+                    _ => None,
+                };
+                let pick = |a: StatValue, b: StatValue, wanted: Ordering| {
+                    if a.compare(&b) == Some(wanted) {
+                        a
+                    } else {
+                        b
+                    }
+                };
+                ranges[i] = match (ranges[i].take(), column_range(row_group, schema, i)) {
+                    (range, None) => {
+                        known[i] = false;
+                        range
+                    }
+                    (Some((min, max)), Some((lo, hi))) => Some((
+                        pick(lo, min, Ordering::Less),
+                        pick(hi, max, Ordering::Greater),
+                    )),
+                    (None, range) => range,
+                };
+            }
+        }
+    }
+    for (i, field) in schema.fields().iter().enumerate() {
+        if let (true, Some((min, max))) = (known[i], &ranges[i]) {
+            statistics.columns[i] = ColumnStatistics {
+                min: min.to_scalar(field.data_type()),
+                max: max.to_scalar(field.data_type()),
+                ..statistics.columns[i].clone()
+            };
+        }
+    }
+    Ok(statistics)
+}
+
+/// Merge schemas by field name. Fields are ordered by first appearance, fields with
+/// different types are promoted to a common supertype, and fields that are missing from
+/// some of the schemas become nullable.
+pub fn merge_schemas(schemas: &[Schema]) -> Result<Schema> {
+    let mut fields: Vec<Field> = vec![];
+    for schema in schemas {
+        for field in schema.fields() {
+            match fields.iter().position(|f| f.name() == field.name()) {
+                Some(i) => {
+                    let existing = &fields[i];
+                    let data_type = if existing.data_type() == field.data_type() {
+                        existing.data_type().clone()
+                    } else {
+                        get_supertype(existing.data_type(), field.data_type()).map_err(|_| {
+                            BallistaError::General(format!(
+                                "Incompatible types {:?} and {:?} for field '{}'",
+                                existing.data_type(),
+                                field.data_type(),
+                                field.name()
+                            ))
+                        })?
+                    };
+                    let nullable = existing.is_nullable() || field.is_nullable();
+                    fields[i] = Field::new(field.name(), data_type, nullable);
+                }
+                None => fields.push(field.clone()),
+            }
+        }
+    }
+
+    // fields that do not appear in every schema will contain nulls
+    let fields = fields
+        .iter()
+        .map(|f| {
+            let in_all = schemas
+                .iter()
+                .all(|s| s.fields().iter().any(|sf| sf.name() == f.name()));
+            Field::new(f.name(), f.data_type().clone(), f.is_nullable() || !in_all)
+        })
+        .collect();
+    // the decimal precision and scale of each column are kept
+    let mut metadata = HashMap::new();
+    for schema in schemas {
+        metadata.extend(schema.metadata().clone());
+    }
+    Ok(Schema::new_with_metadata(fields, metadata))
+}
+
+/// A row group of a parquet file, which parallel scans read as an independent partition
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowGroupSplit {
+    pub file: String,
+    pub row_group: usize,
+}
+
+/// The row groups of the files that may contain rows matching the filters, in file order
+pub fn row_group_splits(
+    files: &[String],
+    schema: &Schema,
+    filters: &[Expr],
+    footers: &FooterCache,
+) -> Result<Vec<RowGroupSplit>> {
+    let mut splits = vec![];
+    for file in files {
+        for (i, row_group) in footers.footer(file)?.row_groups.iter().enumerate() {
+            if filters
+                .iter()
+                .all(|filter| row_group_may_match(row_group, schema, filter))
+            {
+                splits.push(RowGroupSplit {
+                    file: file.clone(),
+                    row_group: i,
+                });
+            }
+        }
+    }
+    Ok(splits)
+}
+
+/// A table of parquet row groups that are read as they are scanned, with a partition for
+/// each row group, so that the row groups are read in parallel by the threads that execute
+/// the partitions of queries
+pub struct RowGroupTable {
+    splits: Vec<RowGroupSplit>,
+    /// The merged schema of the files, with the partition columns
+    schema: SchemaRef,
+    partition_columns: Vec<String>,
+}
+
+impl RowGroupTable {
+    /// Create a table for row groups of files whose merged schema is the given schema, where
+    /// the values of the partition columns are taken from the paths of the files
+    pub fn new(
+        splits: Vec<RowGroupSplit>,
+        schema: SchemaRef,
+        partition_columns: Vec<String>,
+    ) -> Self {
+        Self {
+            splits,
+            schema,
+            partition_columns,
+        }
+    }
+}
+
+impl TableProvider for RowGroupTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let projected_schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok(self
+            .splits
+            .iter()
+            .map(|split| {
+                Arc::new(RowGroupPartition {
+                    split: split.clone(),
+                    schema: self.schema.clone(),
+                    partition_columns: self.partition_columns.clone(),
+                    batch_size,
+                    projection: projection.clone(),
+                    projected_schema: projected_schema.clone(),
+                }) as Arc<dyn Partition>
+            })
+            .collect())
+    }
+}
+
+/// A row group of a `RowGroupTable`
+struct RowGroupPartition {
+    split: RowGroupSplit,
+    schema: SchemaRef,
+    partition_columns: Vec<String>,
+    batch_size: usize,
+    projection: Vec<usize>,
+    /// The schema of the projected columns
+    projected_schema: SchemaRef,
+}
+
+impl Partition for RowGroupPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let batches = read_row_group(
+            &self.split,
+            &self.schema,
+            &self.partition_columns,
+            self.batch_size,
+        )
+        .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+        let reader = MemoryReader::new(self.schema.clone(), batches);
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            Box::new(reader),
+            self.projection.clone(),
+            self.projected_schema.clone(),
+        ))))
+    }
+}
+
+/// Read a row group into record batches that conform to the given (merged) schema, with the
+/// values of the partition columns taken from the path of its file
+pub fn read_row_group(
+    split: &RowGroupSplit,
+    schema: &SchemaRef,
+    partition_columns: &[String],
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let row_group = split.row_group;
+    read_file(
+        &split.file,
+        schema,
+        &|index| index == row_group,
+        None,
+        batch_size,
+    )
+    .and_then(|batches| {
+        batches
+            .iter()
+            .map(|batch| add_partition_columns(batch, schema, partition_columns, &split.file))
+            .collect()
+    })
+    .map_err(|e| e.in_file(&split.file))
+}
+
+/// Read a parquet file into record batches that conform to the given (merged) schema,
+/// skipping the row groups whose statistics show that none of their rows match the filters
+/// on the columns of the schema, and stopping once at least `limit` rows have been read
+pub fn read_parquet_batches(
+    file: &str,
+    schema: &Schema,
+    filters: &[Expr],
+    limit: Option<usize>,
+    batch_size: usize,
+    footers: &FooterCache,
+) -> Result<Vec<RecordBatch>> {
+    let footer = footers.footer(file)?;
+    let keep: Vec<bool> = footer
+        .row_groups
+        .iter()
+        .map(|row_group| {
+            filters
+                .iter()
+                .all(|filter| row_group_may_match(row_group, schema, filter))
+        })
+        .collect();
+    read_file(file, schema, &|i| keep[i], limit, batch_size).map_err(|e| e.in_file(file))
+}
+
+/// Read the row groups of a parquet file whose indexes the predicate keeps, stopping once
+/// at least `limit` rows have been read
+fn read_file(
+    file: &str,
+    schema: &Schema,
+    keep: &dyn Fn(usize) -> bool,
+    limit: Option<usize>,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let mut file_reader = SerializedFileReader::new(File::open(file)?)?;
+    file_reader.filter_row_groups(&|_, i| keep(i));
+    let file_reader: Rc<dyn FileReader> = Rc::new(file_reader);
+    if list_column(file_reader.metadata().file_metadata().schema_descr()).is_some() {
+        return read_file_with_lists(file_reader, schema, limit, batch_size);
+    }
+    let decimals = decimal_columns(file_reader.metadata().file_metadata().schema_descr());
+    if !decimals.is_empty() {
+        return read_file_with_decimals(file_reader, &decimals, schema, limit, batch_size);
+    }
+    let mut arrow_reader = ParquetFileArrowReader::new(file_reader);
+    let mut batch_reader = arrow_reader.get_record_reader(batch_size)?;
+
+    let schema = Arc::new(schema.clone());
+    let mut batches = vec![];
+    let mut rows = 0;
+    while limit.map(|n| rows < n).unwrap_or(true) {
+        match batch_reader.next_batch()? {
+            Some(batch) => {
+                rows += batch.num_rows();
+                batches.push(adapt_batch(&batch, &schema)?);
+            }
+            None => break,
+        }
+    }
+    Ok(batches)
+}
+
+/// Read the row groups of a parquet file with decimal columns. The decimal columns are read
+/// as decimal text apart from the other columns, which are read by the Arrow reader.
+fn read_file_with_decimals(
+    file_reader: Rc<dyn FileReader>,
+    decimals: &[usize],
+    schema: &Schema,
+    limit: Option<usize>,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let file_schema = Arc::new(arrow_schema(&file_reader)?);
+    let decimal_values = decimals
+        .iter()
+        .map(|i| read_decimal_column(file_reader.as_ref(), *i))
+        .collect::<Result<Vec<_>>>()?;
+    let num_rows = decimal_values[0].len();
+    let others: Vec<usize> = (0..file_schema.fields().len())
+        .filter(|i| !decimals.contains(i))
+        .collect();
+    let mut batch_reader = if others.is_empty() {
+        None
+    } else {
+        let mut arrow_reader = ParquetFileArrowReader::new(file_reader.clone());
+        Some(arrow_reader.get_record_reader_by_columns(others, batch_size)?)
+    };
+
+    let schema = Arc::new(schema.clone());
+    let mut batches = vec![];
+    let mut rows = 0;
+    while rows < num_rows && limit.map(|n| rows < n).unwrap_or(true) {
+        let mut columns = match &mut batch_reader {
+            Some(reader) => match reader.next_batch()? {
+                Some(batch) => batch.columns().to_vec(),
+                None => break,
+            },
+            None => vec![],
+        };
+        let len = columns
+            .first()
+            .map_or(batch_size.min(num_rows - rows), |c| c.len());
+        // the decimal columns are inserted in the order of their indexes
+        for (column, values) in decimals.iter().zip(&decimal_values) {
+            let values: Vec<Option<&str>> = values[rows..rows + len]
+                .iter()
+                .map(|v| v.as_ref().map(|v| v.as_str()))
+                .collect();
+            columns.insert(*column, Arc::new(StringArray::from(values)));
+        }
+        let batch = RecordBatch::try_new(file_schema.clone(), columns)?;
+        rows += len;
+        batches.push(adapt_batch(&batch, &schema)?);
+    }
+    Ok(batches)
+}
+
+/// Read the row groups of a parquet file with list columns, which the Arrow reader of this
+/// version of parquet cannot read. Every column is read with the column reader instead:
+/// lists of primitive values are read as list arrays, decimal columns as decimal text and
+/// the other primitive columns as arrays of their Arrow types. Files with struct columns or
+/// lists of structs or lists cannot be read yet.
+fn read_file_with_lists(
+    file_reader: Rc<dyn FileReader>,
+    schema: &Schema,
+    limit: Option<usize>,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let file_schema = Arc::new(arrow_schema(&file_reader)?);
+    let metadata = file_reader.metadata();
+    let descr = metadata.file_metadata().schema_descr();
+    let decimals = decimal_columns(descr);
+    let mut values = vec![];
+    // a file that is read here has one leaf column for each of its columns, so the index
+    // of a column is the index of its leaf
+    for (i, field) in descr.root_schema().get_fields().iter().enumerate() {
+        let column = descr.column(i);
+        let data_type = file_schema.field(i).data_type();
+        if decimals.contains(&i) {
+            let decimals = read_decimal_column(file_reader.as_ref(), i)?;
+            values.push(LeafValues::Values(
+                decimals
+                    .into_iter()
+                    .map(|v| v.map(KeyValue::Utf8))
+                    .collect(),
+            ));
+            continue;
+        }
+        let supported = match data_type {
+            DataType::List(item_type) => {
+                list_item(field).is_some() && list_item_type(&column) == Some(*item_type.clone())
+            }
+            _ => field.is_primitive() && column.max_rep_level() == 0,
+        };
+        if !supported {
+            return Err(ballista_error(&format!(
+                "Cannot read column {} of a parquet file with list columns, only primitive \
+                 columns and lists of primitive values are supported",
+                field.name()
+            )));
+        }
+        let (levels, leaf_values) = read_leaf_column(file_reader.as_ref(), i)?;
+        let max_def_level = column.max_def_level();
+        values.push(match data_type {
+            DataType::List(_) => {
+                // lists are null below the definition level of their column and empty at it
+                let info = field.get_basic_info();
+                let optional = info.has_repetition() && info.repetition() == Repetition::OPTIONAL;
+                let empty_level = if optional { 1 } else { 0 };
+                LeafValues::Lists(list_values(
+                    &levels,
+                    leaf_values,
+                    max_def_level,
+                    empty_level,
+                ))
+            }
+            _ => LeafValues::Values(flat_values(&levels, leaf_values, max_def_level)),
+        });
+    }
+    let num_rows = values.first().map_or(0, |v| v.len());
+
+    let schema = Arc::new(schema.clone());
+    let mut batches = vec![];
+    let mut rows = 0;
+    while rows < num_rows && limit.map(|n| rows < n).unwrap_or(true) {
+        let len = batch_size.min(num_rows - rows);
+        let columns = values
+            .iter()
+            .zip(file_schema.fields())
+            .map(|(values, field)| values.array(rows..rows + len, field.data_type()))
+            .collect::<Result<Vec<_>>>()?;
+        let batch = RecordBatch::try_new(file_schema.clone(), columns)?;
+        rows += len;
+        batches.push(adapt_batch(&batch, &schema)?);
+    }
+    Ok(batches)
+}
+
+/// The values of a leaf column of a parquet file for each row of the file, which are lists
+/// of values for list columns
+enum LeafValues {
+    Values(Vec<Option<KeyValue>>),
+    Lists(Vec<Option<Vec<Option<KeyValue>>>>),
+}
+
+impl LeafValues {
+    fn len(&self) -> usize {
+        match self {
+            LeafValues::Values(values) => values.len(),
+            LeafValues::Lists(lists) => lists.len(),
+        }
+    }
+
+    /// An array of the values of a range of rows, of the given Arrow type
+    fn array(&self, rows: Range<usize>, data_type: &DataType) -> Result<ArrayRef> {
+        match (self, data_type) {
+            (LeafValues::Values(values), _) => leaf_array(&values[rows], data_type),
+            (LeafValues::Lists(lists), DataType::List(item_type)) => {
+                let lists = &lists[rows];
+                let mut offsets = vec![0i32];
+                let mut valid = BooleanBufferBuilder::new(lists.len());
+                let mut items = vec![];
+                for list in lists {
+                    if let Some(list) = list {
+                        items.extend(list.iter().cloned());
+                    }
+                    valid.append(list.is_some())?;
+                    offsets.push(items.len() as i32);
+                }
+                let items = leaf_array(&items, item_type)?;
+                Ok(Arc::new(ListArray::from(
+                    ArrayData::builder(data_type.clone())
+                        .len(lists.len())
+                        .add_buffer(Buffer::from(offsets.to_byte_slice()))
+                        .null_bit_buffer(valid.finish())
+                        .add_child_data(items.data())
+                        .build(),
+                )))
+            }
+            (LeafValues::Lists(_), _) => Err(ballista_error(&format!(
+                "Cannot read lists as a column of type {:?}",
+                data_type
+            ))),
+        }
+    }
+}
+
+/// An array of values of a leaf column, of the given Arrow type. Dates and times are cast
+/// from the integers that they are stored as.
+fn leaf_array(values: &[Option<KeyValue>], data_type: &DataType) -> Result<ArrayRef> {
+    let values: Vec<Option<&KeyValue>> = values.iter().map(|v| v.as_ref()).collect();
+    match data_type {
+        DataType::Date32(_) | DataType::Time32(_) => {
+            Ok(cast(&to_array(&values, &DataType::Int32)?, data_type)?)
+        }
+        DataType::Date64(_) | DataType::Time64(_) | DataType::Timestamp(_, _) => {
+            Ok(cast(&to_array(&values, &DataType::Int64)?, data_type)?)
+        }
+        _ => to_array(&values, data_type),
+    }
+}
+
+/// The value of each row of a leaf column that is not repeated, from its definition levels
+/// and its non-null values
+fn flat_values<V>(levels: &[(i16, i16)], values: Vec<V>, max_def_level: i16) -> Vec<Option<V>> {
+    let mut values = values.into_iter();
+    levels
+        .iter()
+        .map(|(def, _)| {
+            if *def == max_def_level {
+                values.next()
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The list of each row of the leaf column of a list, from its definition and repetition
+/// levels and its non-null values. A repetition level of zero starts the list of the next
+/// row, which is null below the definition level of empty lists. Each level above it is an
+/// item, which is null below the maximum definition level.
+fn list_values<V>(
+    levels: &[(i16, i16)],
+    values: Vec<V>,
+    max_def_level: i16,
+    empty_level: i16,
+) -> Vec<Option<Vec<Option<V>>>> {
+    let mut values = values.into_iter();
+    let mut lists = vec![];
+    for (def, rep) in levels {
+        if *rep == 0 {
+            lists.push(if *def < empty_level {
+                None
+            } else {
+                Some(vec![])
+            });
+        }
+        if *def > empty_level {
+            let item = if *def == max_def_level {
+                values.next()
+            } else {
+                None
+            };
+            if let Some(Some(items)) = lists.last_mut() {
+                items.push(item);
+            }
+        }
+    }
+    lists
+}
+
+/// The primitive item type of a list column of a parquet schema, which is either a list
+/// annotated group of a repeated group of the item, the legacy list annotated group of a
+/// repeated primitive item, or a repeated primitive column itself
+fn list_item(field: &Type) -> Option<&Type> {
+    let repeated = |field: &Type| {
+        let info = field.get_basic_info();
+        info.has_repetition() && info.repetition() == Repetition::REPEATED
+    };
+    if field.is_primitive() {
+        return if repeated(field) { Some(field) } else { None };
+    }
+    if field.get_basic_info().logical_type() != LogicalType::LIST {
+        return None;
+    }
+    match field.get_fields() {
+        [list] if repeated(list) && list.is_primitive() => Some(list),
+        [list] if repeated(list) => match list.get_fields() {
+            [item] if item.is_primitive() => Some(item),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The Arrow type of the items of a list column, for the item types that are read
+fn list_item_type(column: &ColumnDescriptor) -> Option<DataType> {
+    Some(match (column.physical_type(), column.logical_type()) {
+        (PhysicalType::BOOLEAN, _) => DataType::Boolean,
+        (PhysicalType::INT32, LogicalType::NONE) | (PhysicalType::INT32, LogicalType::INT_32) => {
+            DataType::Int32
+        }
+        (PhysicalType::INT64, LogicalType::NONE) | (PhysicalType::INT64, LogicalType::INT_64) => {
+            DataType::Int64
+        }
+        (PhysicalType::FLOAT, _) => DataType::Float32,
+        (PhysicalType::DOUBLE, _) => DataType::Float64,
+        (PhysicalType::BYTE_ARRAY, LogicalType::UTF8) => DataType::Utf8,
+        _ => return None,
+    })
+}
+
+/// The Arrow schema of a parquet file, in which decimal columns are `Utf8` columns whose
+/// precision and scale are in the metadata of the schema, and lists of primitive values have
+/// the item types that they are read with
+fn arrow_schema(file_reader: &Rc<dyn FileReader>) -> Result<Schema> {
+    let mut arrow_reader = ParquetFileArrowReader::new(file_reader.clone());
+    let metadata = file_reader.metadata();
+    let descr = metadata.file_metadata().schema_descr();
+    let decimals = decimal_columns(descr);
+    let root_fields = descr.root_schema().get_fields();
+    // columns are only mapped one by one when each of them has exactly one leaf
+    let flat = root_fields
+        .iter()
+        .all(|f| f.is_primitive() || list_item(f).is_some());
+    if !flat || (decimals.is_empty() && list_column(descr).is_none()) {
+        return Ok(arrow_reader.get_schema()?);
+    }
+    let mut fields = vec![];
+    let mut metadata = HashMap::new();
+    for (i, root_field) in root_fields.iter().enumerate() {
+        let item_type = list_item(root_field).and_then(|_| list_item_type(&descr.column(i)));
+        if let Some(item_type) = item_type {
+            let info = root_field.get_basic_info();
+            let nullable = info.has_repetition() && info.repetition() == Repetition::OPTIONAL;
+            let data_type = DataType::List(Box::new(item_type));
+            fields.push(Field::new(root_field.name(), data_type, nullable));
+        } else if decimals.contains(&i) {
+            let column = descr.column(i);
+            let nullable = column.max_def_level() > 0;
+            fields.push(Field::new(column.name(), DataType::Utf8, nullable));
+            metadata.insert(
+                format!("{}{}", DECIMAL_METADATA_PREFIX, column.name()),
+                format!("{},{}", column.type_precision(), column.type_scale()),
+            );
+        } else {
+            fields.push(
+                arrow_reader
+                    .get_schema_by_columns(vec![i])?
+                    .field(0)
+                    .clone(),
+            );
+        }
+    }
+    Ok(Schema::new_with_metadata(fields, metadata))
+}
+
+/// The indexes of the decimal columns of a parquet schema. Only files without struct
+/// columns are read with decimal columns, so the indexes of their leaves are the indexes of
+/// their Arrow columns.
+fn decimal_columns(descr: &SchemaDescriptor) -> Vec<usize> {
+    if !descr
+        .root_schema()
+        .get_fields()
+        .iter()
+        .all(|f| f.is_primitive() || list_item(f).is_some())
+    {
+        return vec![];
+    }
+    (0..descr.num_columns())
+        .filter(|i| {
+            let column = descr.column(*i);
+            column.logical_type() == LogicalType::DECIMAL && column.max_rep_level() == 0
+        })
+        .collect()
+}
+
+/// The name of the first column of a parquet schema that is or contains a list
+fn list_column(descr: &SchemaDescriptor) -> Option<String> {
+    fn is_list(field: &Type) -> bool {
+        let info = field.get_basic_info();
+        if info.logical_type() == LogicalType::LIST
+            || (info.has_repetition() && info.repetition() == Repetition::REPEATED)
+        {
+            return true;
+        }
+        !field.is_primitive() && field.get_fields().iter().any(|f| is_list(f))
+    }
+    descr
+        .root_schema()
+        .get_fields()
+        .iter()
+        .find(|f| is_list(f))
+        .map(|f| f.name().to_owned())
+}
+
+/// Read the values of a decimal column of the row groups of a file as decimal text
+fn read_decimal_column(file_reader: &dyn FileReader, column: usize) -> Result<Vec<Option<String>>> {
+    let metadata = file_reader.metadata();
+    let descr = metadata.file_metadata().schema_descr().column(column);
+    let mut levels = vec![];
+    let mut values = vec![];
+    for i in 0..file_reader.num_row_groups() {
+        let row_group = file_reader.get_row_group(i)?;
+        match row_group.get_column_reader(column)? {
+            ColumnReader::Int32ColumnReader(mut reader) => {
+                read_levels(&mut reader, |v| *v as i128, &mut levels, &mut values)?
+            }
+            ColumnReader::Int64ColumnReader(mut reader) => {
+                read_levels(&mut reader, |v| *v as i128, &mut levels, &mut values)?
+            }
+            ColumnReader::ByteArrayColumnReader(mut reader) => read_levels(
+                &mut reader,
+                |v| unscaled_from_bytes(v.data()),
+                &mut levels,
+                &mut values,
+            )?,
+            ColumnReader::FixedLenByteArrayColumnReader(mut reader) => read_levels(
+                &mut reader,
+                |v| unscaled_from_bytes(v.data()),
+                &mut levels,
+                &mut values,
+            )?,
+            _ => {
+                return Err(ballista_error(&format!(
+                    "Decimal column {} has an unsupported physical type",
+                    descr.name()
+                )))
+            }
+        }
+    }
+    let scale = descr.type_scale() as usize;
+    Ok(flat_values(&levels, values, descr.max_def_level())
+        .into_iter()
+        .map(|v| v.map(|v| format_decimal(v, scale)))
+        .collect())
+}
+
+/// Read the definition and repetition levels of a leaf column of the row groups of a file,
+/// and its non-null values as the values that rows are compared and built with
+fn read_leaf_column(
+    file_reader: &dyn FileReader,
+    column: usize,
+) -> Result<(Vec<(i16, i16)>, Vec<KeyValue>)> {
+    let metadata = file_reader.metadata();
+    let descr = metadata.file_metadata().schema_descr().column(column);
+    // unsigned integers are stored as the bits of signed integers of the same width
+    let unsigned = match descr.logical_type() {
+        LogicalType::UINT_8
+        | LogicalType::UINT_16
+        | LogicalType::UINT_32
+        | LogicalType::UINT_64 => true,
+        _ => false,
+    };
+    let mut levels = vec![];
+    let mut values = vec![];
+    for i in 0..file_reader.num_row_groups() {
+        let row_group = file_reader.get_row_group(i)?;
+        match row_group.get_column_reader(column)? {
+            ColumnReader::BoolColumnReader(mut reader) => read_levels(
+                &mut reader,
+                |v| KeyValue::Boolean(*v),
+                &mut levels,
+                &mut values,
+            )?,
+            ColumnReader::Int32ColumnReader(mut reader) if unsigned => read_levels(
+                &mut reader,
+                |v| KeyValue::UInt(*v as u32 as u64),
+                &mut levels,
+                &mut values,
+            )?,
+            ColumnReader::Int32ColumnReader(mut reader) => read_levels(
+                &mut reader,
+                |v| KeyValue::Int(*v as i64),
+                &mut levels,
+                &mut values,
+            )?,
+            ColumnReader::Int64ColumnReader(mut reader) if unsigned => read_levels(
+                &mut reader,
+                |v| KeyValue::UInt(*v as u64),
+                &mut levels,
+                &mut values,
+            )?,
+            ColumnReader::Int64ColumnReader(mut reader) => {
+                read_levels(&mut reader, |v| KeyValue::Int(*v), &mut levels, &mut values)?
+            }
+            ColumnReader::FloatColumnReader(mut reader) => read_levels(
+                &mut reader,
+                |v| KeyValue::Float((*v as f64).to_bits()),
+                &mut levels,
+                &mut values,
+            )?,
+            ColumnReader::DoubleColumnReader(mut reader) => read_levels(
+                &mut reader,
+                |v| KeyValue::Float(v.to_bits()),
+                &mut levels,
+                &mut values,
+            )?,
+            ColumnReader::ByteArrayColumnReader(mut reader) => read_levels(
+                &mut reader,
+                |v| KeyValue::Utf8(String::from_utf8_lossy(v.data()).into_owned()),
+                &mut levels,
+                &mut values,
+            )?,
+            _ => {
+                return Err(ballista_error(&format!(
+                    "Column {} of a parquet file with list columns has an unsupported \
+                     physical type",
+                    descr.name()
+                )))
+            }
+        }
+    }
+    Ok((levels, values))
+}
+
+/// Read the definition and repetition levels of a column chunk, and its non-null values
+fn read_levels<T: ParquetDataType, V>(
+    reader: &mut ColumnReaderImpl<T>,
+    value: impl Fn(&T::T) -> V,
+    levels: &mut Vec<(i16, i16)>,
+    values: &mut Vec<V>,
+) -> Result<()> {
+    let mut def_levels = vec![0; DEFAULT_BATCH_SIZE];
+    let mut rep_levels = vec![0; DEFAULT_BATCH_SIZE];
+    let mut buffer = vec![T::T::default(); DEFAULT_BATCH_SIZE];
+    loop {
+        let (read, count) = reader.read_batch(
+            DEFAULT_BATCH_SIZE,
+            Some(&mut def_levels),
+            Some(&mut rep_levels),
+            &mut buffer,
+        )?;
+        if count == 0 {
+            return Ok(());
+        }
+        // the levels of required and unrepeated columns are not read, and are all zero
+        values.extend(buffer[..read].iter().map(&value));
+        levels.extend(
+            def_levels[..count]
+                .iter()
+                .cloned()
+                .zip(rep_levels[..count].iter().cloned()),
+        );
+    }
+}
+
+/// The unscaled value of a decimal stored as a big-endian two's complement integer
+fn unscaled_from_bytes(bytes: &[u8]) -> i128 {
+    let negative = bytes.first().map_or(false, |b| b & 0x80 != 0);
+    bytes
+        .iter()
+        .fold(if negative { -1 } else { 0 }, |value, b| {
+            (value << 8) | *b as i128
+        })
+}
+
+/// Whether any of the rows of a row group may match a filter on the columns of the schema,
+/// judging by the minimum and maximum values of the columns of the row group. Filters that
+/// cannot be judged from the statistics are assumed to match.
+pub fn row_group_may_match(row_group: &RowGroupFooter, schema: &Schema, filter: &Expr) -> bool {
+    match filter {
+        Expr::BinaryExpr { left, op, right } => match (op, left.as_ref(), right.as_ref()) {
+            (Operator::And, _, _) => {
+                row_group_may_match(row_group, schema, left)
+                    && row_group_may_match(row_group, schema, right)
+            }
+            (Operator::Or, _, _) => {
+                row_group_may_match(row_group, schema, left)
+                    || row_group_may_match(row_group, schema, right)
+            }
+            (op, Expr::Column(i), Expr::Literal(value)) => {
+                column_may_match(row_group, schema, *i, op, value)
+            }
+            (op, Expr::Literal(value), Expr::Column(i)) => match flip(op) {
+                Some(op) => column_may_match(row_group, schema, *i, &op, value),
+                None => true,
+            },
+            _ => true,
+        },
+        _ => true,
+    }
+}
+
+fn column_may_match(
+    row_group: &RowGroupFooter,
+    schema: &Schema,
+    column: usize,
+    op: &Operator,
+    value: &ScalarValue,
+) -> bool {
+    let (min, max, value) = match (
+        column_range(row_group, schema, column),
+        StatValue::from_scalar(value),
+    ) {
+        (Some((min, max)), Some(value)) => (min, max, value),
+        _ => return true,
+    };
+    // comparisons that cannot be made, such as between strings and numbers, may match
+    let holds = |a: &StatValue, b: &StatValue, expected: &[Ordering]| match a.compare(b) {
+        Some(ordering) => expected.contains(&ordering),
+        None => true,
+    };
+    match op {
+        Operator::Eq => {
+            holds(&min, &value, &[Ordering::Less, Ordering::Equal])
+                && holds(&max, &value, &[Ordering::Greater, Ordering::Equal])
+        }
+        Operator::NotEq => {
+            !(min.compare(&value) == Some(Ordering::Equal)
+                && max.compare(&value) == Some(Ordering::Equal))
+        }
+        Operator::Lt => holds(&min, &value, &[Ordering::Less]),
+        Operator::LtEq => holds(&min, &value, &[Ordering::Less, Ordering::Equal]),
+        Operator::Gt => holds(&max, &value, &[Ordering::Greater]),
+        Operator::GtEq => holds(&max, &value, &[Ordering::Greater, Ordering::Equal]),
+        _ => true,
+    }
+}
+
+/// The minimum and maximum values of a column in a row group, for the types whose
+/// statistics compare in the same order as their values
+fn column_range(
+    row_group: &RowGroupFooter,
+    schema: &Schema,
+    column: usize,
+) -> Option<(StatValue, StatValue)> {
+    let field = schema.fields().get(column)?;
+    match field.data_type() {
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::Utf8 => {}
+        _ => return None,
+    }
+    // columns that are missing from the file, such as partition columns, have no statistics
+    row_group.column(field.name())?.range.clone()
+}
+
+/// The minimum and maximum values in the statistics of a column chunk, for the physical
+/// types whose statistics compare in the same order as their values
+fn statistics_range(statistics: &Statistics) -> Option<(StatValue, StatValue)> {
+    if !statistics.has_min_max_set() {
+        return None;
+    }
+    match statistics {
+        Statistics::Int32(s) => Some((
+            StatValue::Int(*s.min() as i64),
+            StatValue::Int(*s.max() as i64),
+        )),
+        Statistics::Int64(s) => Some((StatValue::Int(*s.min()), StatValue::Int(*s.max()))),
+        Statistics::Float(s) => Some((
+            StatValue::Float(*s.min() as f64),
+            StatValue::Float(*s.max() as f64),
+        )),
+        Statistics::Double(s) => Some((StatValue::Float(*s.min()), StatValue::Float(*s.max()))),
+        Statistics::ByteArray(s) => Some((
+            StatValue::Text(s.min().as_utf8().ok()?.to_owned()),
+            StatValue::Text(s.max().as_utf8().ok()?.to_owned()),
+        )),
+        _ => None,
+    }
+}
+
+/// A value of a column statistic or of a literal that it is compared with
+#[derive(Debug, Clone, PartialEq)]
+enum StatValue {
+    Int(i64),
+    Float(f64),
+    Text(String),
+    /// A decimal as its unscaled value and its scale, which is compared exactly
+    Decimal(i128, usize),
+}
+
+impl StatValue {
+    fn from_scalar(value: &ScalarValue) -> Option<Self> {
+        match value {
+            ScalarValue::Int8(v) => Some(StatValue::Int(*v as i64)),
+            ScalarValue::Int16(v) => Some(StatValue::Int(*v as i64)),
+            ScalarValue::Int32(v) => Some(StatValue::Int(*v as i64)),
+            ScalarValue::Int64(v) => Some(StatValue::Int(*v)),
+            ScalarValue::UInt8(v) => Some(StatValue::Int(*v as i64)),
+            ScalarValue::UInt16(v) => Some(StatValue::Int(*v as i64)),
+            ScalarValue::UInt32(v) => Some(StatValue::Int(*v as i64)),
+            ScalarValue::Float32(v) => Some(StatValue::Float(*v as f64)),
+            ScalarValue::Float64(v) => Some(StatValue::Float(*v)),
+            ScalarValue::Utf8(v) => Some(StatValue::Text(v.clone())),
+            ScalarValue::Decimal(v, _, scale) => Some(StatValue::Decimal(*v, *scale)),
+            _ => None,
+        }
+    }
+
+    /// The value of a decimal statistic, which is of the unscaled integer
+    fn unscaled(&self, scale: usize) -> Option<Self> {
+        match self {
+            StatValue::Int(v) => Some(StatValue::Decimal(*v as i128, scale)),
+            _ => None,
+        }
+    }
+
+    /// The value as a literal of the type of a column
+    fn to_scalar(&self, data_type: &DataType) -> Option<ScalarValue> {
+        match (self, data_type) {
+            (StatValue::Int(v), DataType::Int8) => Some(ScalarValue::Int8(*v as i8)),
+            (StatValue::Int(v), DataType::Int16) => Some(ScalarValue::Int16(*v as i16)),
+            (StatValue::Int(v), DataType::Int32) => Some(ScalarValue::Int32(*v as i32)),
+            (StatValue::Int(v), DataType::Int64) => Some(ScalarValue::Int64(*v)),
+            (StatValue::Float(v), DataType::Float32) => Some(ScalarValue::Float32(*v as f32)),
+            (StatValue::Float(v), DataType::Float64) => Some(ScalarValue::Float64(*v)),
+            (StatValue::Text(v), DataType::Utf8) => Some(ScalarValue::Utf8(v.clone())),
+            _ => None,
+        }
+    }
+
+    fn compare(&self, other: &StatValue) -> Option<Ordering> {
+        let decimal = |v: i128, scale: usize| ScalarValue::Decimal(v, 0, scale);
+        match (self, other) {
+            (StatValue::Decimal(a, a_scale), StatValue::Decimal(b, b_scale)) => {
+                decimal(*a, *a_scale).compare(&decimal(*b, *b_scale))
+            }
+            (StatValue::Decimal(a, scale), StatValue::Int(b)) => {
+                decimal(*a, *scale).compare(&decimal(*b as i128, 0))
+            }
+            (StatValue::Int(a), StatValue::Decimal(b, scale)) => {
+                decimal(*a as i128, 0).compare(&decimal(*b, *scale))
+            }
+            (StatValue::Decimal(a, scale), StatValue::Float(b)) => {
+                decimal_to_f64(*a, *scale).partial_cmp(b)
+            }
+            (StatValue::Float(a), StatValue::Decimal(b, scale)) => {
+                a.partial_cmp(&decimal_to_f64(*b, *scale))
+            }
+            (StatValue::Int(a), StatValue::Int(b)) => Some(a.cmp(b)),
+            (StatValue::Int(a), StatValue::Float(b)) => (*a as f64).partial_cmp(b),
+            (StatValue::Float(a), StatValue::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (StatValue::Float(a), StatValue::Float(b)) => a.partial_cmp(b),
+            (StatValue::Text(a), StatValue::Text(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+/// Writes record batches to a Parquet file, with a row group for each batch
+pub struct ParquetFileWriter {
+    writer: SerializedFileWriter<File>,
+    /// The scale of each decimal column
+    scales: Vec<Option<usize>>,
+    num_rows: usize,
+}
+
+impl ParquetFileWriter {
+    /// Create a Parquet file with the given schema. All columns are written as optional
+    /// columns, since batches of non-nullable fields may still contain nulls.
+    pub fn try_new(path: &Path, schema: &Schema) -> Result<Self> {
+        let decimals: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|field| decimal_precision_and_scale(schema, field.name()))
+            .collect();
+        let mut fields = schema
+            .fields()
+            .iter()
+            .zip(&decimals)
+            .map(|(field, decimal)| Ok(Rc::new(parquet_type(field, *decimal)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let parquet_schema = Type::group_type_builder("schema")
+            .with_fields(&mut fields)
+            .build()?;
+        let writer = SerializedFileWriter::new(
+            File::create(path)?,
+            Rc::new(parquet_schema),
+            Rc::new(WriterProperties::builder().build()),
+        )?;
+        Ok(Self {
+            writer,
+            scales: decimals.iter().map(|d| d.map(|(_, scale)| scale)).collect(),
+            num_rows: 0,
+        })
+    }
+
+    /// Append a batch to the file as a row group
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        let mut row_group = self.writer.next_row_group()?;
+        for (column, scale) in batch.columns().iter().zip(&self.scales) {
+            let mut writer = row_group
+                .next_column()?
+                .ok_or_else(|| ballista_error("Batch has more columns than the Parquet schema"))?;
+            match scale {
+                Some(scale) => write_decimal_column(&mut writer, column.as_ref(), *scale)?,
+                None => write_column(&mut writer, column.as_ref())?,
+            }
+            row_group.close_column(writer)?;
+        }
+        self.writer.close_row_group(row_group)?;
+        self.num_rows += batch.num_rows();
+        Ok(())
+    }
+
+    /// Finish writing the file, returning the number of rows written
+    pub fn close(mut self) -> Result<usize> {
+        self.writer.close()?;
+        Ok(self.num_rows)
+    }
+}
+
+/// The Parquet type of an Arrow field, which is a decimal when it has a precision and scale
+fn parquet_type(field: &Field, decimal: Option<(usize, usize)>) -> Result<Type> {
+    if let Some((precision, scale)) = decimal {
+        return Type::primitive_type_builder(field.name(), PhysicalType::BYTE_ARRAY)
+            .with_repetition(Repetition::OPTIONAL)
+            .with_logical_type(LogicalType::DECIMAL)
+            .with_precision(precision as i32)
+            .with_scale(scale as i32)
+            .build()
+            .map_err(BallistaError::from);
+    }
+    let (physical_type, logical_type) = match field.data_type() {
+        DataType::Boolean => (PhysicalType::BOOLEAN, LogicalType::NONE),
+        DataType::Int8 => (PhysicalType::INT32, LogicalType::INT_8),
+        DataType::Int16 => (PhysicalType::INT32, LogicalType::INT_16),
+        DataType::Int32 => (PhysicalType::INT32, LogicalType::NONE),
+        DataType::Int64 => (PhysicalType::INT64, LogicalType::NONE),
+        DataType::UInt8 => (PhysicalType::INT32, LogicalType::UINT_8),
+        DataType::UInt16 => (PhysicalType::INT32, LogicalType::UINT_16),
+        DataType::UInt32 => (PhysicalType::INT32, LogicalType::UINT_32),
+        DataType::UInt64 => (PhysicalType::INT64, LogicalType::UINT_64),
+        DataType::Float32 => (PhysicalType::FLOAT, LogicalType::NONE),
+        DataType::Float64 => (PhysicalType::DOUBLE, LogicalType::NONE),
+        DataType::Date32(DateUnit::Day) => (PhysicalType::INT32, LogicalType::DATE),
+        DataType::Utf8 => (PhysicalType::BYTE_ARRAY, LogicalType::UTF8),
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Writing {:?} columns to Parquet is not supported",
+                other
+            )))
+        }
+    };
+    Type::primitive_type_builder(field.name(), physical_type)
+        .with_repetition(Repetition::OPTIONAL)
+        .with_logical_type(logical_type)
+        .build()
+        .map_err(BallistaError::from)
+}
+
+macro_rules! write_values {
+    ($writer:expr, $array:expr, $array_type:ident, $def_levels:expr, $value:expr) => {{
+        let array = $array.as_any().downcast_ref::<$array_type>().unwrap();
+        let values: Vec<_> = (0..array.len())
+            .filter(|i| !array.is_null(*i))
+            .map(|i| $value(array.value(i)))
+            .collect();
+        $writer.write_batch(&values, Some($def_levels), None)?;
+    }};
+}
+
+/// Write the values of an array to a column, where null values are only recorded in the
+/// definition levels
+fn write_column(writer: &mut ColumnWriter, array: &dyn Array) -> Result<()> {
+    let def_levels: Vec<i16> = (0..array.len())
+        .map(|i| if array.is_null(i) { 0 } else { 1 })
+        .collect();
+    let def_levels = def_levels.as_slice();
+    match (writer, array.data_type()) {
+        (ColumnWriter::BoolColumnWriter(w), DataType::Boolean) => {
+            write_values!(w, array, BooleanArray, def_levels, |v| v)
+        }
+        (ColumnWriter::Int32ColumnWriter(w), DataType::Int8) => {
+            write_values!(w, array, Int8Array, def_levels, i32::from)
+        }
+        (ColumnWriter::Int32ColumnWriter(w), DataType::Int16) => {
+            write_values!(w, array, Int16Array, def_levels, i32::from)
+        }
+        (ColumnWriter::Int32ColumnWriter(w), DataType::Int32) => {
+            write_values!(w, array, Int32Array, def_levels, |v| v)
+        }
+        (ColumnWriter::Int32ColumnWriter(w), DataType::UInt8) => {
+            write_values!(w, array, UInt8Array, def_levels, i32::from)
+        }
+        (ColumnWriter::Int32ColumnWriter(w), DataType::UInt16) => {
+            write_values!(w, array, UInt16Array, def_levels, i32::from)
+        }
+        // unsigned values are stored with the same bits as signed values
+        (ColumnWriter::Int32ColumnWriter(w), DataType::UInt32) => {
+            write_values!(w, array, UInt32Array, def_levels, |v: u32| v as i32)
+        }
+        (ColumnWriter::Int32ColumnWriter(w), DataType::Date32(DateUnit::Day)) => {
+            write_values!(w, array, Date32Array, def_levels, |v| v)
+        }
+        (ColumnWriter::Int64ColumnWriter(w), DataType::Int64) => {
+            write_values!(w, array, Int64Array, def_levels, |v| v)
+        }
+        (ColumnWriter::Int64ColumnWriter(w), DataType::UInt64) => {
+            write_values!(w, array, UInt64Array, def_levels, |v: u64| v as i64)
+        }
+        (ColumnWriter::FloatColumnWriter(w), DataType::Float32) => {
+            write_values!(w, array, Float32Array, def_levels, |v| v)
+        }
+        (ColumnWriter::DoubleColumnWriter(w), DataType::Float64) => {
+            write_values!(w, array, Float64Array, def_levels, |v| v)
+        }
+        (ColumnWriter::ByteArrayColumnWriter(w), DataType::Utf8) => {
+            write_values!(w, array, StringArray, def_levels, ByteArray::from)
+        }
+        (_, data_type) => {
+            return Err(ballista_error(&format!(
+                "Column of type {:?} does not match the Parquet schema",
+                data_type
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Write the decimal text of a `Utf8` column as the unscaled values of a decimal column
+fn write_decimal_column(writer: &mut ColumnWriter, array: &dyn Array, scale: usize) -> Result<()> {
+    match (writer, array.as_any().downcast_ref::<StringArray>()) {
+        (ColumnWriter::ByteArrayColumnWriter(w), Some(array)) => {
+            let mut values = vec![];
+            let mut def_levels = vec![];
+            for i in 0..array.len() {
+                if array.is_null(i) {
+                    def_levels.push(0);
+                    continue;
+                }
+                let value = parse_decimal(array.value(i), scale).ok_or_else(|| {
+                    ballista_error(&format!(
+                        "'{}' is not a decimal with a scale of {}",
+                        array.value(i),
+                        scale
+                    ))
+                })?;
+                // decimals are stored as big-endian two's complement integers
+                values.push(ByteArray::from(value.to_be_bytes().to_vec()));
+                def_levels.push(1);
+            }
+            w.write_batch(&values, Some(&def_levels), None)?;
+            Ok(())
+        }
+        _ => Err(ballista_error(
+            "Decimal columns must be Utf8 columns of decimal text",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::schema::parser::parse_message_type;
+
+    #[test]
+    fn merge_compatible_schemas() -> Result<()> {
+        let a = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, false),
+        ]);
+        let b = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("amount", DataType::Float64, false),
+        ]);
+
+        let merged = merge_schemas(&[a, b])?;
+        assert_eq!(3, merged.fields().len());
+        assert_eq!(&DataType::Int64, merged.field(0).data_type());
+        assert!(!merged.field(0).is_nullable());
+        assert!(merged.field(1).is_nullable());
+        assert_eq!("amount", merged.field(2).name());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_incompatible_schemas() {
+        let a = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let b = Schema::new(vec![Field::new("id", DataType::Boolean, false)]);
+        assert!(merge_schemas(&[a, b]).is_err());
+    }
+
+    #[test]
+    fn write_and_read_parquet() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, true),
+            Field::new("name", DataType::Utf8, false),
+            Field::new("amount", DataType::Float64, false),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema.clone()),
+            vec![
+                Arc::new(Int32Array::from(vec![Some(1), None, Some(3)])),
+                Arc::new(StringArray::from(vec!["a", "b", "c"])),
+                Arc::new(Float64Array::from(vec![1.5, 2.5, 3.5])),
+            ],
+        )?;
+        let path = std::env::temp_dir().join(format!("write-{}.parquet", std::process::id()));
+        let mut writer = ParquetFileWriter::try_new(&path, &schema)?;
+        writer.write(&batch)?;
+        writer.write(&batch)?;
+        assert_eq!(6, writer.close()?);
+
+        let file = path.to_string_lossy();
+        let batches =
+            read_parquet_batches(&file, &schema, &[], None, 1024, &FooterCache::default())?;
+        fs::remove_file(&path)?;
+        assert_eq!(6, batches.iter().map(|b| b.num_rows()).sum::<usize>());
+        let ids = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        assert!(ids.is_null(1));
+        assert_eq!(3, ids.value(2));
+        let names = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!("c", names.value(2));
+        Ok(())
+    }
+
+    #[test]
+    fn write_and_read_exact_decimals() -> Result<()> {
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            format!("{}price", DECIMAL_METADATA_PREFIX),
+            "20,2".to_owned(),
+        );
+        let schema =
+            Schema::new_with_metadata(vec![Field::new("price", DataType::Utf8, true)], metadata);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema.clone()),
+            vec![Arc::new(StringArray::from(vec![
+                Some("12345678901234567.89"),
+                None,
+                Some("-0.50"),
+            ]))],
+        )?;
+        let path = std::env::temp_dir().join(format!("decimals-{}.parquet", std::process::id()));
+        let mut writer = ParquetFileWriter::try_new(&path, &schema)?;
+        writer.write(&batch)?;
+        writer.close()?;
+
+        let file = path.to_string_lossy();
+        let footers = FooterCache::default();
+        let read_schema = parquet_file_schema(&file, &footers)?;
+        let batches = read_parquet_batches(&file, &schema, &[], None, 1024, &footers)?;
+        fs::remove_file(&path)?;
+        assert_eq!(schema, read_schema);
+        assert_eq!(
+            Some((20, 2)),
+            decimal_precision_and_scale(&read_schema, "price")
+        );
+        let prices = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!("12345678901234567.89", prices.value(0));
+        assert!(prices.is_null(1));
+        assert_eq!("-0.50", prices.value(2));
+
+        // decimal statistics are compared exactly rather than as doubles
+        let max = StatValue::Decimal(1_234_567_890_123_456_789, 2);
+        let value = StatValue::from_scalar(&ScalarValue::Decimal(1_234_567_890_123_456_788, 19, 2));
+        assert_eq!(Some(Ordering::Greater), max.compare(&value.unwrap()));
+        Ok(())
+    }
+
+    #[test]
+    fn read_list_columns() -> Result<()> {
+        let message = "
+            message schema {
+                REQUIRED INT64 id;
+                OPTIONAL GROUP tags (LIST) {
+                    REPEATED GROUP list {
+                        OPTIONAL BYTE_ARRAY element (UTF8);
+                    }
+                }
+                REPEATED INT32 scores;
+            }
+        ";
+        let path = std::env::temp_dir().join(format!("lists-{}.parquet", std::process::id()));
+        let mut writer = SerializedFileWriter::new(
+            File::create(&path)?,
+            Rc::new(parse_message_type(message)?),
+            Rc::new(WriterProperties::builder().build()),
+        )?;
+        // [1, ["a", null], [7, 8]], [2, null, []], [3, [], [9]]
+        let mut row_group = writer.next_row_group()?;
+        while let Some(mut column) = row_group.next_column()? {
+            match &mut column {
+                ColumnWriter::Int64ColumnWriter(writer) => {
+                    writer.write_batch(&[1, 2, 3], None, None)?;
+                }
+                ColumnWriter::ByteArrayColumnWriter(writer) => {
+                    writer.write_batch(
+                        &[ByteArray::from("a")],
+                        Some(&[3, 2, 0, 1]),
+                        Some(&[0, 1, 0, 0]),
+                    )?;
+                }
+                ColumnWriter::Int32ColumnWriter(writer) => {
+                    writer.write_batch(&[7, 8, 9], Some(&[1, 1, 0, 1]), Some(&[0, 1, 0, 0]))?;
+                }
+                _ => unreachable!(),
+            }
+            row_group.close_column(column)?;
+        }
+        writer.close_row_group(row_group)?;
+        writer.close()?;
+
+        let file = path.to_string_lossy();
+        let footers = FooterCache::default();
+        let schema = parquet_file_schema(&file, &footers)?;
+        let batches = read_parquet_batches(&file, &schema, &[], None, 1024, &footers)?;
+        fs::remove_file(&path)?;
+        assert_eq!(
+            &DataType::List(Box::new(DataType::Utf8)),
+            schema.field(1).data_type()
+        );
+        assert!(schema.field(1).is_nullable());
+        assert!(!schema.field(2).is_nullable());
+        let tags = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<ListArray>()
+            .unwrap();
+        let first = tags.value(0);
+        let first = first.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(
+            (2, "a", true),
+            (first.len(), first.value(0), first.is_null(1))
+        );
+        assert!(tags.is_null(1));
+        assert_eq!((false, 0), (tags.is_null(2), tags.value_length(2)));
+        let scores = batches[0]
+            .column(2)
+            .as_any()
+            .downcast_ref::<ListArray>()
+            .unwrap();
+        let lengths: Vec<i32> = (0..3).map(|i| scores.value_length(i)).collect();
+        assert_eq!(vec![2, 0, 1], lengths);
+        let values = scores.values();
+        let values = values.as_any().downcast_ref::<Int32Array>().unwrap();
+        assert_eq!(vec![7, 8, 9], values.value_slice(0, 3).to_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn skip_row_groups() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int64, false)]);
+        let path = std::env::temp_dir().join(format!("row-groups-{}.parquet", std::process::id()));
+        let mut writer = ParquetFileWriter::try_new(&path, &schema)?;
+        for ids in &[vec![1, 2, 3], vec![10, 11, 12]] {
+            let batch = RecordBatch::try_new(
+                Arc::new(schema.clone()),
+                vec![Arc::new(Int64Array::from(ids.clone()))],
+            )?;
+            writer.write(&batch)?;
+        }
+        writer.close()?;
+
+        let file = path.to_string_lossy();
+        let footers = FooterCache::new(1);
+        let rows = |filter: Expr| -> Result<usize> {
+            let batches = read_parquet_batches(&file, &schema, &[filter], None, 1024, &footers)?;
+            Ok(batches.iter().map(|b| b.num_rows()).sum())
+        };
+        let id = Expr::Column(0);
+        let result = (
+            rows(id.gt(&Expr::Literal(ScalarValue::Int64(5))))?,
+            rows(Expr::Literal(ScalarValue::Int64(2)).eq(&id))?,
+            rows(id.gt(&Expr::Literal(ScalarValue::Int64(20))))?,
+        );
+        let statistics = parquet_table_statistics(&[file.to_string()], &schema, &footers)?;
+        let filter = id.gt(&Expr::Literal(ScalarValue::Int64(5)));
+        let splits = row_group_splits(&[file.to_string()], &schema, &[], &footers)?;
+        let filtered = row_group_splits(&[file.to_string()], &schema, &[filter], &footers)?;
+        // each row group is read by a partition of the table as it is executed
+        let table = RowGroupTable::new(splits.clone(), Arc::new(schema.clone()), vec![]);
+        let mut read = vec![];
+        for partition in table.scan(&None, 1024)? {
+            let reader = partition.execute()?;
+            let mut reader = reader.lock().unwrap();
+            let mut batches = vec![];
+            while let Some(batch) = reader.next_batch()? {
+                batches.push(batch);
+            }
+            read.push(batches);
+        }
+        let cached = footers.footer(&file)?;
+
+        // the footer is read again once the file changes
+        let mut writer = ParquetFileWriter::try_new(&path, &schema)?;
+        let batch = RecordBatch::try_new(
+            Arc::new(schema.clone()),
+            vec![Arc::new(Int64Array::from(vec![1, 2, 3, 4]))],
+        )?;
+        writer.write(&batch)?;
+        writer.close()?;
+        let rewritten = footers.footer(&file)?;
+        fs::remove_file(&path)?;
+        assert_eq!(1, footers.len());
+        assert_eq!(2, cached.row_groups.len());
+        assert_eq!(
+            vec![4],
+            rewritten
+                .row_groups
+                .iter()
+                .map(|r| r.num_rows)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!((3, 3, 0), result);
+        assert_eq!(2, splits.len());
+        assert_eq!(vec![splits[1].clone()], filtered);
+        let ids: Vec<Vec<i64>> = read
+            .iter()
+            .map(|batches| {
+                let ids = batches[0].column(0).as_any().downcast_ref::<Int64Array>();
+                let ids = ids.unwrap();
+                (0..ids.len()).map(|i| ids.value(i)).collect()
+            })
+            .collect();
+        assert_eq!(vec![vec![1, 2, 3], vec![10, 11, 12]], ids);
+        assert_eq!(6, statistics.num_rows);
+        assert_eq!(Some(ScalarValue::Int64(1)), statistics.columns[0].min);
+        assert_eq!(Some(ScalarValue::Int64(12)), statistics.columns[0].max);
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+//! Support for Hive-style partitioned datasets, where files are laid out in directories
+//! named `key=value`. The partition keys are exposed as virtual columns and filters on
+//! these columns are used to skip entire directories before any file is read.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{BallistaError, Result};
+use crate::logicalplan::{decimal_to_f64, lit_date, Expr, Operator, ScalarValue};
+
+/// Extract the `key=value` partition values from the directories in a file path
+pub fn partition_values(file: &str) -> Vec<(String, String)> {
+    let mut values = vec![];
+    if let Some(parent) = Path::new(file).parent() {
+        for component in parent.components() {
+            let component = component.as_os_str().to_string_lossy();
+            let parts: Vec<&str> = component.splitn(2, '=').collect();
+            if parts.len() == 2 && !parts[0].is_empty() {
+                values.push((parts[0].to_owned(), parts[1].to_owned()));
+            }
+        }
+    }
+    values
+}
+
+/// Determine the partition columns for a list of files. Files are only treated as
+/// partitioned when every file has the same partition keys, in the same order. Partition
+/// columns are typed as Int64 when every value is an integer, and Utf8 otherwise.
+pub fn discover_partition_columns(files: &[String]) -> Vec<Field> {
+    let all_values: Vec<Vec<(String, String)>> =
+        files.iter().map(|f| partition_values(f)).collect();
+    let keys: Vec<String> = match all_values.first() {
+        Some(values) => values.iter().map(|(k, _)| k.clone()).collect(),
+        None => return vec![],
+    };
+    let consistent = all_values
+        .iter()
+        .all(|values| values.iter().map(|(k, _)| k).eq(keys.iter()));
+    if !consistent {
+        return vec![];
+    }
+
+    keys.iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let is_int = all_values
+                .iter()
+                .all(|values| values[i].1.parse::<i64>().is_ok());
+            let data_type = if is_int {
+                DataType::Int64
+            } else {
+                DataType::Utf8
+            };
+            Field::new(key, data_type, false)
+        })
+        .collect()
+}
+
+/// Replace the partition columns in a batch with the values from the file path
+pub fn add_partition_columns(
+    batch: &RecordBatch,
+    schema: &Arc<Schema>,
+    partition_columns: &[String],
+    file: &str,
+) -> Result<RecordBatch> {
+    let values = partition_values(file);
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            if partition_columns.contains(field.name()) {
+                let value = values
+                    .iter()
+                    .find(|(k, _)| k == field.name())
+                    .map(|(_, v)| v.as_str())
+                    .ok_or_else(|| {
+                        BallistaError::General(format!(
+                            "File {} has no value for partition column {}",
+                            file,
+                            field.name()
+                        ))
+                    })?;
+                constant_array(field.data_type(), value, batch.num_rows())
+            } else {
+                Ok(batch.column(i).clone())
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn constant_array(data_type: &DataType, value: &str, len: usize) -> Result<ArrayRef> {
+    match data_type {
+        DataType::Int64 => {
+            let v = value.parse::<i64>().map_err(|_| {
+                BallistaError::General(format!("Invalid partition value {}", value))
+            })?;
+            let mut builder = Int64Builder::new(len);
+            for _ in 0..len {
+                builder.append_value(v)?;
+            }
+            Ok(Arc::new(builder.finish()) as ArrayRef)
+        }
+        _ => {
+            let mut builder = StringBuilder::new(len);
+            for _ in 0..len {
+                builder.append_value(value)?;
+            }
+            Ok(Arc::new(builder.finish()) as ArrayRef)
+        }
+    }
+}
+
+/// Remove files whose partition values cannot match the filter expression
+pub fn prune_files(
+    files: &[String],
+    partition_columns: &[String],
+    schema: &Schema,
+    expr: &Expr,
+) -> Vec<String> {
+    files
+        .iter()
+        .filter(|file| matches_partition(expr, partition_columns, schema, &partition_values(file)))
+        .cloned()
+        .collect()
+}
+
+/// Determine whether a filter expression can match rows with the given partition values
+pub fn matches_partition(
+    expr: &Expr,
+    partition_columns: &[String],
+    schema: &Schema,
+    values: &[(String, String)],
+) -> bool {
+    evaluate(expr, partition_columns, schema, values) != Some(false)
+}
+
+/// Evaluate a filter against the partition values of a file, returning `None` when the
+/// result depends on columns that are not partition columns
+fn evaluate(
+    expr: &Expr,
+    partition_columns: &[String],
+    schema: &Schema,
+    values: &[(String, String)],
+) -> Option<bool> {
+    match expr {
+        Expr::BinaryExpr { left, op, right } => match op {
+            Operator::And => {
+                let l = evaluate(left, partition_columns, schema, values);
+                let r = evaluate(right, partition_columns, schema, values);
+                match (l, r) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }
+            }
+            Operator::Or => {
+                let l = evaluate(left, partition_columns, schema, values);
+                let r = evaluate(right, partition_columns, schema, values);
+                match (l, r) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }
+            }
+            _ => {
+                let column = |e: &Expr| -> Option<String> {
+                    let name = match e {
+                        Expr::UnresolvedColumn(name) => name.clone(),
+                        Expr::Column(i) if *i < schema.fields().len() => {
+                            schema.field(*i).name().clone()
+                        }
+                        _ => return None,
+                    };
+                    if partition_columns.contains(&name) {
+                        Some(name)
+                    } else {
+                        None
+                    }
+                };
+                let (name, literal, op) = match (left.as_ref(), right.as_ref()) {
+                    (l, Expr::Literal(v)) => (column(l)?, v, op.clone()),
+                    (Expr::Literal(v), r) => (column(r)?, v, flip(op)?),
+                    _ => return None,
+                };
+                let value = &values.iter().find(|(k, _)| *k == name)?.1;
+                compare(value, &op, literal)
+            }
+        },
+        Expr::Not(expr) => evaluate(expr, partition_columns, schema, values).map(|b| !b),
+        _ => None,
+    }
+}
+
+/// Flip a comparison operator so that the literal can be moved to the right-hand side
+pub(crate) fn flip(op: &Operator) -> Option<Operator> {
+    match op {
+        Operator::Eq => Some(Operator::Eq),
+        Operator::NotEq => Some(Operator::NotEq),
+        Operator::Lt => Some(Operator::Gt),
+        Operator::LtEq => Some(Operator::GtEq),
+        Operator::Gt => Some(Operator::Lt),
+        Operator::GtEq => Some(Operator::LtEq),
+        _ => None,
+    }
+}
+
+fn compare(value: &str, op: &Operator, literal: &ScalarValue) -> Option<bool> {
+    let ordering = match literal {
+        ScalarValue::Utf8(s) => value.cmp(s.as_str()),
+        ScalarValue::Int8(_)
+        | ScalarValue::Int16(_)
+        | ScalarValue::Int32(_)
+        | ScalarValue::Int64(_)
+        | ScalarValue::UInt8(_)
+        | ScalarValue::UInt16(_)
+        | ScalarValue::UInt32(_)
+        | ScalarValue::UInt64(_)
+        | ScalarValue::Float32(_)
+        | ScalarValue::Float64(_)
+        | ScalarValue::Decimal(..) => {
+            let value = value.parse::<f64>().ok()?;
+            let literal = scalar_to_f64(literal)?;
+            value.partial_cmp(&literal)?
+        }
+        ScalarValue::Date32(_) => match lit_date(value).ok()? {
+            Expr::Literal(date) => date.compare(literal)?,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    use std::cmp::Ordering::*;
+    match op {
+        Operator::Eq => Some(ordering == Equal),
+        Operator::NotEq => Some(ordering != Equal),
+        Operator::Lt => Some(ordering == Less),
+        Operator::LtEq => Some(ordering != Greater),
+        Operator::Gt => Some(ordering == Greater),
+        Operator::GtEq => Some(ordering != Less),
+        _ => None,
+    }
+}
+
+fn scalar_to_f64(value: &ScalarValue) -> Option<f64> {
+    match value {
+        ScalarValue::Int8(v) => Some(*v as f64),
+        ScalarValue::Int16(v) => Some(*v as f64),
+        ScalarValue::Int32(v) => Some(*v as f64),
+        ScalarValue::Int64(v) => Some(*v as f64),
+        ScalarValue::UInt8(v) => Some(*v as f64),
+        ScalarValue::UInt16(v) => Some(*v as f64),
+        ScalarValue::UInt32(v) => Some(*v as f64),
+        ScalarValue::UInt64(v) => Some(*v as f64),
+        ScalarValue::Float32(v) => Some(*v as f64),
+        ScalarValue::Float64(v) => Some(*v),
+        ScalarValue::Decimal(v, _, scale) => Some(decimal_to_f64(*v, *scale)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logicalplan::col;
+
+    #[test]
+    fn discover_and_prune() {
+        let files = vec![
+            "/data/year=2019/month=12/part-0.parquet".to_owned(),
+            "/data/year=2020/month=01/part-0.parquet".to_owned(),
+            "/data/year=2020/month=02/part-0.parquet".to_owned(),
+        ];
+
+        let columns = discover_partition_columns(&files);
+        assert_eq!(2, columns.len());
+        assert_eq!("year", columns[0].name());
+        assert_eq!(&DataType::Int64, columns[0].data_type());
+
+        let schema = Schema::new(columns);
+        let partition_columns = vec!["year".to_owned(), "month".to_owned()];
+        let filter = col("year")
+            .eq(&Expr::Literal(ScalarValue::Int64(2020)))
+            .and(&col("month").gt(&Expr::Literal(ScalarValue::Int64(1))));
+        let pruned = prune_files(&files, &partition_columns, &schema, &filter);
+        assert_eq!(vec![files[2].clone()], pruned);
+    }
+}
@@ -0,0 +1,365 @@
+//! S3 object store support (requires the `s3` feature).
+//!
+//! Credentials and the endpoint (for S3-compatible stores such as MinIO) are configured
+//! through the Context settings, falling back to the standard AWS environment variables and
+//! credential files.
+//!
+//! Requests are made from synchronous code, so they run on a runtime of their own with a
+//! fixed number of threads and the caller waits for their results. The number of requests
+//! that are in flight is bounded by the pools that the callers run on.
+
+use std::collections::HashMap;
+use std::sync::{mpsc, Mutex};
+
+use crate::datasource::backoff;
+use crate::datasource::object_store::{ObjectStore, PART_SIZE};
+use crate::error::{ballista_error, BallistaError, Result};
+
+use lazy_static::lazy_static;
+use rusoto_core::credential::StaticProvider;
+use rusoto_core::{HttpClient, Region};
+use rusoto_s3::{
+    CompleteMultipartUploadRequest, CompletedMultipartUpload, CompletedPart,
+    CreateMultipartUploadRequest, GetObjectRequest, HeadObjectRequest, ListObjectsV2Request,
+    S3Client, UploadPartRequest, S3,
+};
+use tokio::io::AsyncReadExt;
+use tokio::runtime::Runtime;
+
+pub const S3_ACCESS_KEY_ID: &str = "ballista.s3.accessKeyId";
+pub const S3_SECRET_ACCESS_KEY: &str = "ballista.s3.secretAccessKey";
+pub const S3_REGION: &str = "ballista.s3.region";
+pub const S3_ENDPOINT: &str = "ballista.s3.endpoint";
+pub const S3_MAX_RETRIES: &str = "ballista.s3.maxRetries";
+
+/// Number of threads of the runtime that S3 requests run on, which only wait for I/O
+const S3_RUNTIME_THREADS: usize = 2;
+
+lazy_static! {
+    static ref S3_RUNTIME: Mutex<Runtime> = Mutex::new(
+        tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .core_threads(S3_RUNTIME_THREADS)
+            .thread_name("ballista-s3")
+            .enable_all()
+            .build()
+            .expect("Failed to start the S3 request runtime")
+    );
+}
+
+/// S3 connection settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Config {
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub region: String,
+    /// Custom endpoint for S3-compatible object stores
+    pub endpoint: Option<String>,
+    pub max_retries: usize,
+}
+
+impl S3Config {
+    /// Read the S3 configuration from the Context settings
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self> {
+        let max_retries = match settings.get(S3_MAX_RETRIES) {
+            Some(n) => n.parse::<usize>().map_err(|_| {
+                ballista_error(&format!("Invalid value for {}: {}", S3_MAX_RETRIES, n))
+            })?,
+            None => 3,
+        };
+        Ok(Self {
+            access_key_id: settings.get(S3_ACCESS_KEY_ID).cloned(),
+            secret_access_key: settings.get(S3_SECRET_ACCESS_KEY).cloned(),
+            region: settings
+                .get(S3_REGION)
+                .cloned()
+                .unwrap_or_else(|| "us-east-1".to_owned()),
+            endpoint: settings.get(S3_ENDPOINT).cloned(),
+            max_retries,
+        })
+    }
+
+    fn client(&self) -> Result<S3Client> {
+        let region = match &self.endpoint {
+            Some(endpoint) => Region::Custom {
+                name: self.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => self
+                .region
+                .parse::<Region>()
+                .map_err(|e| ballista_error(&format!("Invalid S3 region: {:?}", e)))?,
+        };
+        match (&self.access_key_id, &self.secret_access_key) {
+            (Some(key), Some(secret)) => {
+                let dispatcher = HttpClient::new().map_err(s3_error)?;
+                let credentials = StaticProvider::new_minimal(key.clone(), secret.clone());
+                Ok(S3Client::new_with(dispatcher, credentials, region))
+            }
+            _ => Ok(S3Client::new(region)),
+        }
+    }
+}
+
+/// Determine whether a path refers to an S3 object
+pub fn is_s3_path(path: &str) -> bool {
+    path.starts_with("s3://")
+}
+
+/// Split an `s3://bucket/key` URL into the bucket and key
+pub fn parse_s3_path(path: &str) -> Result<(String, String)> {
+    if !is_s3_path(path) {
+        return Err(ballista_error(&format!("Not an S3 path: {}", path)));
+    }
+    let mut parts = path["s3://".len()..].splitn(2, '/');
+    let bucket = parts.next().unwrap_or_default();
+    if bucket.is_empty() {
+        return Err(ballista_error(&format!("S3 path has no bucket: {}", path)));
+    }
+    Ok((
+        bucket.to_owned(),
+        parts.next().unwrap_or_default().to_owned(),
+    ))
+}
+
+/// List the objects under a prefix, returning their `s3://` URLs
+pub fn list_objects(path: &str, config: &S3Config) -> Result<Vec<String>> {
+    let (bucket, prefix) = parse_s3_path(path)?;
+    let client = config.client()?;
+    let max_retries = config.max_retries;
+    block_on(async move {
+        let mut objects = vec![];
+        let mut continuation_token = None;
+        loop {
+            let request = ListObjectsV2Request {
+                bucket: bucket.clone(),
+                prefix: Some(prefix.clone()),
+                continuation_token: continuation_token.clone(),
+                ..Default::default()
+            };
+            let output =
+                with_retry(max_retries, || client.list_objects_v2(request.clone())).await?;
+            for object in output.contents.unwrap_or_default() {
+                if let Some(key) = object.key {
+                    if !key.ends_with('/') {
+                        objects.push(format!("s3://{}/{}", bucket, key));
+                    }
+                }
+            }
+            match output.next_continuation_token {
+                Some(token) if output.is_truncated == Some(true) => {
+                    continuation_token = Some(token)
+                }
+                _ => break,
+            }
+        }
+        objects.sort();
+        Ok(objects)
+    })
+}
+
+/// Object store for S3 and S3-compatible stores
+#[derive(Debug)]
+pub struct S3Store {
+    config: S3Config,
+}
+
+impl S3Store {
+    pub fn new(config: S3Config) -> Self {
+        Self { config }
+    }
+}
+
+impl ObjectStore for S3Store {
+    fn list(&self, path: &str) -> Result<Vec<String>> {
+        list_objects(path, &self.config)
+    }
+
+    fn size(&self, path: &str) -> Result<u64> {
+        let (bucket, key) = parse_s3_path(path)?;
+        let client = self.config.client()?;
+        let max_retries = self.config.max_retries;
+        block_on(async move {
+            let head = with_retry(max_retries, || {
+                client.head_object(HeadObjectRequest {
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    ..Default::default()
+                })
+            })
+            .await?;
+            Ok(head.content_length.unwrap_or_default() as u64)
+        })
+    }
+
+    fn read_range(&self, path: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+        let (bucket, key) = parse_s3_path(path)?;
+        let client = self.config.client()?;
+        let max_retries = self.config.max_retries;
+        let range = format!("bytes={}-{}", start, start + len - 1);
+        block_on(async move {
+            with_retry(max_retries, || async {
+                let output = client
+                    .get_object(GetObjectRequest {
+                        bucket: bucket.clone(),
+                        key: key.clone(),
+                        range: Some(range.clone()),
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(|e| format!("{:?}", e))?;
+                let mut buf = vec![];
+                if let Some(body) = output.body {
+                    // a body that fails part way through is returned as a short read, which
+                    // the download resumes where it stopped
+                    let read = body.into_async_read().read_to_end(&mut buf).await;
+                    if let Err(e) = read {
+                        if buf.is_empty() {
+                            return Err(format!("{:?}", e));
+                        }
+                    }
+                }
+                Ok::<_, String>(buf)
+            })
+            .await
+        })
+    }
+
+    /// Objects are written with a multipart upload
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        let (bucket, key) = parse_s3_path(path)?;
+        let client = self.config.client()?;
+        let max_retries = self.config.max_retries;
+        let parts: Vec<Vec<u8>> = if data.is_empty() {
+            vec![vec![]]
+        } else {
+            data.chunks(PART_SIZE as usize)
+                .map(|c| c.to_vec())
+                .collect()
+        };
+
+        block_on(async move {
+            let upload = with_retry(max_retries, || {
+                client.create_multipart_upload(CreateMultipartUploadRequest {
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    ..Default::default()
+                })
+            })
+            .await?;
+            let upload_id = upload
+                .upload_id
+                .ok_or_else(|| ballista_error("S3 did not return a multipart upload id"))?;
+
+            let mut completed = vec![];
+            for (i, part) in parts.iter().enumerate() {
+                let part_number = i as i64 + 1;
+                let output = with_retry(max_retries, || {
+                    client.upload_part(UploadPartRequest {
+                        bucket: bucket.clone(),
+                        key: key.clone(),
+                        upload_id: upload_id.clone(),
+                        part_number,
+                        body: Some(part.clone().into()),
+                        ..Default::default()
+                    })
+                })
+                .await?;
+                completed.push(CompletedPart {
+                    e_tag: output.e_tag,
+                    part_number: Some(part_number),
+                });
+            }
+
+            with_retry(max_retries, || {
+                client.complete_multipart_upload(CompleteMultipartUploadRequest {
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    upload_id: upload_id.clone(),
+                    multipart_upload: Some(CompletedMultipartUpload {
+                        parts: Some(completed.clone()),
+                    }),
+                    ..Default::default()
+                })
+            })
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+/// Retry a request with exponential backoff
+async fn with_retry<T, E, F, Fut>(max_retries: usize, f: F) -> Result<T>
+where
+    E: std::fmt::Debug,
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = std::result::Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= max_retries => return Err(s3_error(e)),
+            Err(_) => {
+                tokio::time::delay_for(backoff(attempt)).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Run a future to completion from synchronous code. The future runs on the shared S3
+/// runtime rather than the caller's, because this may be called from within an async
+/// context, where another runtime cannot be started.
+fn block_on<F, T>(future: F) -> Result<T>
+where
+    F: std::future::Future<Output = Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let handle = S3_RUNTIME
+        .lock()
+        .expect("S3 runtime lock poisoned")
+        .handle()
+        .clone();
+    handle.spawn(async move {
+        let _ = tx.send(future.await);
+    });
+    rx.recv()
+        .map_err(|_| ballista_error("S3 request panicked"))?
+}
+
+fn s3_error<E: std::fmt::Debug>(e: E) -> BallistaError {
+    BallistaError::General(format!("S3 error: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_paths() -> Result<()> {
+        assert_eq!(
+            ("bucket".to_owned(), "a/b.parquet".to_owned()),
+            parse_s3_path("s3://bucket/a/b.parquet")?
+        );
+        assert_eq!(
+            ("bucket".to_owned(), "".to_owned()),
+            parse_s3_path("s3://bucket")?
+        );
+        assert!(parse_s3_path("s3:///key").is_err());
+        assert!(parse_s3_path("/tmp/file.csv").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_from_settings() -> Result<()> {
+        let mut settings = HashMap::new();
+        settings.insert(S3_ENDPOINT.to_owned(), "http://localhost:9000".to_owned());
+        let config = S3Config::from_settings(&settings)?;
+        assert_eq!("us-east-1", config.region);
+        assert_eq!(Some("http://localhost:9000".to_owned()), config.endpoint);
+        assert_eq!(3, config.max_retries);
+        Ok(())
+    }
+}
@@ -0,0 +1,555 @@
+//! Tables in PostgreSQL and MySQL databases (requires the `postgres` or `mysql` feature).
+//!
+//! A table is scanned with a single `SELECT` query. Filters that can be expressed in SQL
+//! are added to the query so that the database only returns the matching rows. Columns
+//! with types that have no direct Arrow equivalent are cast to text by the query.
+//!
+//! The synchronous PostgreSQL client starts a runtime of its own, so its queries run on the
+//! I/O pool of the context (see `ObjectStoreRegistry::io_pool`) rather than the caller's
+//! thread, which may be running a runtime already.
+
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::logicalplan::{format_decimal, Expr, Operator, ScalarValue};
+use crate::pool::ExecutionPool;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+/// SQL dialect of a database, determined from the connection string
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SqlDialect {
+    Postgres,
+    MySql,
+}
+
+impl SqlDialect {
+    /// Determine the dialect from a `postgres://`, `postgresql://` or `mysql://` URL
+    pub fn from_connection_string(conn_str: &str) -> Result<Self> {
+        if conn_str.starts_with("postgres://") || conn_str.starts_with("postgresql://") {
+            Ok(SqlDialect::Postgres)
+        } else if conn_str.starts_with("mysql://") {
+            Ok(SqlDialect::MySql)
+        } else {
+            Err(ballista_error(&format!(
+                "Unsupported database connection string: {}",
+                conn_str
+            )))
+        }
+    }
+
+    /// The operator that compares values that are equal or both null
+    pub(crate) fn not_distinct_operator(&self) -> &'static str {
+        match self {
+            SqlDialect::Postgres => "IS NOT DISTINCT FROM",
+            SqlDialect::MySql => "<=>",
+        }
+    }
+
+    pub(crate) fn quote_identifier(&self, name: &str) -> String {
+        match self {
+            SqlDialect::Postgres => format!("\"{}\"", name.replace('"', "\"\"")),
+            SqlDialect::MySql => format!("`{}`", name.replace('`', "``")),
+        }
+    }
+
+    fn quote_string(&self, value: &str) -> String {
+        match self {
+            SqlDialect::Postgres => format!("'{}'", value.replace('\'', "''")),
+            SqlDialect::MySql => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''")),
+        }
+    }
+
+    /// Quote a table name that may be qualified with a schema
+    pub(crate) fn quote_table(&self, table: &str) -> String {
+        table
+            .split('.')
+            .map(|part| self.quote_identifier(part))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// A literal value in SQL, or `None` if the value cannot be written as a literal
+    pub(crate) fn literal(&self, value: &ScalarValue) -> Option<String> {
+        match value {
+            ScalarValue::Boolean(v) => Some(v.to_string().to_uppercase()),
+            ScalarValue::Int8(v) => Some(v.to_string()),
+            ScalarValue::Int16(v) => Some(v.to_string()),
+            ScalarValue::Int32(v) => Some(v.to_string()),
+            ScalarValue::Int64(v) => Some(v.to_string()),
+            ScalarValue::UInt8(v) => Some(v.to_string()),
+            ScalarValue::UInt16(v) => Some(v.to_string()),
+            ScalarValue::UInt32(v) => Some(v.to_string()),
+            ScalarValue::UInt64(v) => Some(v.to_string()),
+            ScalarValue::Float32(v) if v.is_finite() => Some(v.to_string()),
+            ScalarValue::Float64(v) if v.is_finite() => Some(v.to_string()),
+            ScalarValue::Utf8(v) => Some(self.quote_string(v)),
+            ScalarValue::Decimal(v, _, scale) => Some(format_decimal(*v, *scale)),
+            ScalarValue::Date32(v) => {
+                let date = NaiveDate::from_ymd(1970, 1, 1) + Duration::days(*v as i64);
+                Some(format!("DATE '{}'", date.format("%Y-%m-%d")))
+            }
+            ScalarValue::Date64(v) => {
+                let date = NaiveDate::from_ymd(1970, 1, 1) + Duration::milliseconds(*v);
+                Some(format!("DATE '{}'", date.format("%Y-%m-%d")))
+            }
+            ScalarValue::Timestamp(v, unit) => {
+                let nanos = match unit {
+                    TimeUnit::Second => v.checked_mul(1_000_000_000)?,
+                    TimeUnit::Millisecond => v.checked_mul(1_000_000)?,
+                    TimeUnit::Microsecond => v.checked_mul(1_000)?,
+                    TimeUnit::Nanosecond => *v,
+                };
+                let timestamp = NaiveDateTime::from_timestamp(
+                    nanos.div_euclid(1_000_000_000),
+                    nanos.rem_euclid(1_000_000_000) as u32,
+                );
+                Some(format!(
+                    "TIMESTAMP '{}'",
+                    timestamp.format("%Y-%m-%d %H:%M:%S%.f")
+                ))
+            }
+            ScalarValue::Binary(v) => {
+                let hex: String = v.iter().map(|b| format!("{:02x}", b)).collect();
+                match self {
+                    SqlDialect::Postgres => Some(format!("'\\x{}'::BYTEA", hex)),
+                    SqlDialect::MySql => Some(format!("X'{}'", hex)),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The SQL type that values are cast to for an Arrow type, or `None` if the database
+    /// cannot cast values to the type
+    pub(crate) fn cast_type(&self, data_type: &DataType) -> Option<&'static str> {
+        match (self, data_type) {
+            (SqlDialect::Postgres, DataType::Boolean) => Some("BOOLEAN"),
+            (SqlDialect::Postgres, DataType::Int16) => Some("SMALLINT"),
+            (SqlDialect::Postgres, DataType::Int32) => Some("INTEGER"),
+            (SqlDialect::Postgres, DataType::Int64) => Some("BIGINT"),
+            (SqlDialect::Postgres, DataType::Float32) => Some("REAL"),
+            (SqlDialect::Postgres, DataType::Float64) => Some("DOUBLE PRECISION"),
+            (SqlDialect::Postgres, DataType::Utf8) => Some("TEXT"),
+            (SqlDialect::MySql, DataType::Int8)
+            | (SqlDialect::MySql, DataType::Int16)
+            | (SqlDialect::MySql, DataType::Int32)
+            | (SqlDialect::MySql, DataType::Int64) => Some("SIGNED"),
+            (SqlDialect::MySql, DataType::Float64) => Some("DOUBLE"),
+            (SqlDialect::MySql, DataType::Utf8) => Some("CHAR"),
+            _ => None,
+        }
+    }
+
+    /// Convert a type name from `information_schema.columns` to an Arrow type
+    fn arrow_type(&self, sql_type: &str) -> DataType {
+        match (self, sql_type.to_lowercase().as_str()) {
+            (SqlDialect::MySql, "tinyint") => DataType::Int8,
+            (_, "smallint") => DataType::Int16,
+            (SqlDialect::MySql, "mediumint") | (_, "integer") | (_, "int") => DataType::Int32,
+            (_, "bigint") => DataType::Int64,
+            (_, "real") | (SqlDialect::MySql, "float") => DataType::Float32,
+            (_, "double precision") | (_, "double") | (_, "numeric") | (_, "decimal") => {
+                DataType::Float64
+            }
+            (SqlDialect::Postgres, "boolean") => DataType::Boolean,
+            _ => DataType::Utf8,
+        }
+    }
+
+    /// Select a column, casting it to the type that is read into Arrow
+    fn select_column(&self, field: &Field) -> String {
+        let name = self.quote_identifier(field.name());
+        match (self, field.data_type()) {
+            (SqlDialect::Postgres, DataType::Float64) => {
+                format!("CAST({} AS DOUBLE PRECISION) AS {}", name, name)
+            }
+            (SqlDialect::Postgres, DataType::Utf8) => format!("CAST({} AS TEXT) AS {}", name, name),
+            (SqlDialect::MySql, DataType::Float64) => {
+                format!("CAST({} AS DOUBLE) AS {}", name, name)
+            }
+            (SqlDialect::MySql, DataType::Utf8) => format!("CAST({} AS CHAR) AS {}", name, name),
+            _ => name,
+        }
+    }
+}
+
+/// Value read from a database row
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Int(i64),
+    Float(f64),
+    Boolean(bool),
+    Utf8(String),
+}
+
+/// Build the query that scans a table
+pub fn select_query(dialect: SqlDialect, table: &str, schema: &Schema) -> String {
+    let columns: Vec<String> = schema
+        .fields()
+        .iter()
+        .map(|f| dialect.select_column(f))
+        .collect();
+    format!(
+        "SELECT {} FROM {}",
+        columns.join(", "),
+        dialect.quote_table(table)
+    )
+}
+
+/// Add a filter to a scan query. The parts of a conjunction that cannot be expressed in
+/// SQL are left out, so the filter must still be applied to the rows that are returned.
+pub fn push_down_filter(dialect: SqlDialect, query: &str, schema: &Schema, expr: &Expr) -> String {
+    let predicates: Vec<String> = conjuncts(expr)
+        .iter()
+        .filter_map(|e| to_sql(dialect, schema, e))
+        .collect();
+    if predicates.is_empty() {
+        return query.to_owned();
+    }
+    let keyword = if query.contains(" WHERE ") {
+        "AND"
+    } else {
+        "WHERE"
+    };
+    format!("{} {} {}", query, keyword, predicates.join(" AND "))
+}
+
+/// The expressions that are combined with `AND` in an expression
+pub(crate) fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => {
+            let mut exprs = conjuncts(left);
+            exprs.extend(conjuncts(right));
+            exprs
+        }
+        other => vec![other],
+    }
+}
+
+/// Translate an expression to SQL, returning `None` if it cannot be translated
+fn to_sql(dialect: SqlDialect, schema: &Schema, expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Column(i) if *i < schema.fields().len() => {
+            Some(dialect.quote_identifier(schema.field(*i).name()))
+        }
+        Expr::UnresolvedColumn(name) if schema.index_of(name).is_ok() => {
+            Some(dialect.quote_identifier(name))
+        }
+        Expr::Literal(value) => dialect.literal(value),
+        Expr::BinaryExpr { left, op, right } => {
+            let op = match op {
+                Operator::Eq => "=",
+                Operator::NotEq => "<>",
+                Operator::Lt => "<",
+                Operator::LtEq => "<=",
+                Operator::Gt => ">",
+                Operator::GtEq => ">=",
+                Operator::And => "AND",
+                Operator::Or => "OR",
+                Operator::Like => "LIKE",
+                Operator::NotLike => "NOT LIKE",
+                Operator::IsNotDistinctFrom => dialect.not_distinct_operator(),
+                _ => return None,
+            };
+            Some(format!(
+                "({} {} {})",
+                to_sql(dialect, schema, left)?,
+                op,
+                to_sql(dialect, schema, right)?
+            ))
+        }
+        Expr::Not(e) => Some(format!("(NOT {})", to_sql(dialect, schema, e)?)),
+        Expr::IsNull(e) => Some(format!("({} IS NULL)", to_sql(dialect, schema, e)?)),
+        Expr::IsNotNull(e) => Some(format!("({} IS NOT NULL)", to_sql(dialect, schema, e)?)),
+        _ => None,
+    }
+}
+
+/// Determine the schema of a table from `information_schema.columns`
+pub fn sql_schema(conn_str: &str, table: &str, pool: &ExecutionPool) -> Result<Schema> {
+    let dialect = SqlDialect::from_connection_string(conn_str)?;
+    let (table_schema, table_name) = match table.rfind('.') {
+        Some(i) => (Some(&table[..i]), &table[i + 1..]),
+        None => (None, table),
+    };
+    let table_schema = match (table_schema, dialect) {
+        (Some(s), _) => dialect.quote_string(s),
+        (None, SqlDialect::Postgres) => "current_schema()".to_owned(),
+        (None, SqlDialect::MySql) => "DATABASE()".to_owned(),
+    };
+    let query = format!(
+        "SELECT CAST(column_name AS {t}), CAST(data_type AS {t}), CAST(is_nullable AS {t}) \
+         FROM information_schema.columns WHERE table_schema = {} AND table_name = {} \
+         ORDER BY ordinal_position",
+        table_schema,
+        dialect.quote_string(table_name),
+        t = if dialect == SqlDialect::Postgres {
+            "TEXT"
+        } else {
+            "CHAR"
+        }
+    );
+    let columns = [DataType::Utf8, DataType::Utf8, DataType::Utf8];
+    let rows = query_rows(conn_str, &query, &columns, pool)?;
+    if rows.is_empty() {
+        return Err(ballista_error(&format!("Table {} not found", table)));
+    }
+    let fields = rows
+        .iter()
+        .map(|row| match row.as_slice() {
+            [Some(SqlValue::Utf8(name)), Some(SqlValue::Utf8(sql_type)), nullable] => {
+                let nullable = nullable != &Some(SqlValue::Utf8("NO".to_owned()));
+                Ok(Field::new(name, dialect.arrow_type(sql_type), nullable))
+            }
+            _ => Err(ballista_error(&format!(
+                "Invalid column metadata for table {}",
+                table
+            ))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Schema::new(fields))
+}
+
+/// Execute a scan query and read the rows into batches
+pub fn read_sql_batches(
+    conn_str: &str,
+    query: &str,
+    schema: &Schema,
+    batch_size: usize,
+    pool: &ExecutionPool,
+) -> Result<Vec<RecordBatch>> {
+    let data_types: Vec<DataType> = schema
+        .fields()
+        .iter()
+        .map(|f| f.data_type().clone())
+        .collect();
+    let rows = query_rows(conn_str, query, &data_types, pool)?;
+    let schema = Arc::new(schema.clone());
+    rows.chunks(batch_size.max(1))
+        .map(|chunk| {
+            let columns = data_types
+                .iter()
+                .enumerate()
+                .map(|(i, data_type)| build_array(data_type, chunk, i))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(RecordBatch::try_new(schema.clone(), columns)?)
+        })
+        .collect()
+}
+
+fn build_array(data_type: &DataType, rows: &[Vec<Option<SqlValue>>], i: usize) -> Result<ArrayRef> {
+    macro_rules! build {
+        ($BUILDER:ident, $VARIANT:ident, $T:ty) => {{
+            let mut builder = $BUILDER::new(rows.len());
+            for row in rows {
+                match &row[i] {
+                    Some(SqlValue::$VARIANT(v)) => builder.append_value(v.clone() as $T)?,
+                    None => builder.append_null()?,
+                    other => {
+                        return Err(ballista_error(&format!(
+                            "Unexpected value {:?} for {:?} column",
+                            other, data_type
+                        )))
+                    }
+                }
+            }
+            Ok(Arc::new(builder.finish()) as ArrayRef)
+        }};
+    }
+    match data_type {
+        DataType::Int8 => build!(Int8Builder, Int, i8),
+        DataType::Int16 => build!(Int16Builder, Int, i16),
+        DataType::Int32 => build!(Int32Builder, Int, i32),
+        DataType::Int64 => build!(Int64Builder, Int, i64),
+        DataType::Float32 => build!(Float32Builder, Float, f32),
+        DataType::Float64 => build!(Float64Builder, Float, f64),
+        DataType::Boolean => build!(BooleanBuilder, Boolean, bool),
+        DataType::Utf8 => {
+            let mut builder = StringBuilder::new(rows.len());
+            for row in rows {
+                match &row[i] {
+                    Some(SqlValue::Utf8(v)) => builder.append_value(v)?,
+                    None => builder.append_null()?,
+                    other => {
+                        return Err(ballista_error(&format!(
+                            "Unexpected value {:?} for Utf8 column",
+                            other
+                        )))
+                    }
+                }
+            }
+            Ok(Arc::new(builder.finish()) as ArrayRef)
+        }
+        other => Err(BallistaError::NotImplemented(format!(
+            "Reading {:?} columns from a database is not supported",
+            other
+        ))),
+    }
+}
+
+/// Execute a query, reading each column as the given type
+fn query_rows(
+    conn_str: &str,
+    query: &str,
+    data_types: &[DataType],
+    #[allow(unused_variables)] pool: &ExecutionPool,
+) -> Result<Vec<Vec<Option<SqlValue>>>> {
+    match SqlDialect::from_connection_string(conn_str)? {
+        #[cfg(feature = "postgres")]
+        SqlDialect::Postgres => postgres_rows(conn_str, query, data_types, pool),
+        #[cfg(feature = "mysql")]
+        SqlDialect::MySql => mysql_rows(conn_str, query, data_types),
+        #[allow(unreachable_patterns)]
+        other => Err(BallistaError::NotImplemented(format!(
+            "Reading from {:?} requires the {} feature",
+            other,
+            if other == SqlDialect::Postgres {
+                "postgres"
+            } else {
+                "mysql"
+            }
+        ))),
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn postgres_rows(
+    conn_str: &str,
+    query: &str,
+    data_types: &[DataType],
+    pool: &ExecutionPool,
+) -> Result<Vec<Vec<Option<SqlValue>>>> {
+    // the synchronous client starts its own runtime, which is not possible on a thread
+    // that is already running one
+    let conn_str = conn_str.to_owned();
+    let query = query.to_owned();
+    let data_types = data_types.to_vec();
+    pool.spawn(move || {
+        let mut client =
+            postgres::Client::connect(&conn_str, postgres::NoTls).map_err(sql_error)?;
+        let rows = client.query(query.as_str(), &[]).map_err(sql_error)?;
+        rows.iter()
+            .map(|row| {
+                data_types
+                    .iter()
+                    .enumerate()
+                    .map(|(i, data_type)| {
+                        let value = match data_type {
+                            DataType::Int8 | DataType::Int16 => row
+                                .try_get::<_, Option<i16>>(i)
+                                .map(|v| v.map(|v| SqlValue::Int(v as i64))),
+                            DataType::Int32 => row
+                                .try_get::<_, Option<i32>>(i)
+                                .map(|v| v.map(|v| SqlValue::Int(v as i64))),
+                            DataType::Int64 => row
+                                .try_get::<_, Option<i64>>(i)
+                                .map(|v| v.map(SqlValue::Int)),
+                            DataType::Float32 => row
+                                .try_get::<_, Option<f32>>(i)
+                                .map(|v| v.map(|v| SqlValue::Float(v as f64))),
+                            DataType::Float64 => row
+                                .try_get::<_, Option<f64>>(i)
+                                .map(|v| v.map(SqlValue::Float)),
+                            DataType::Boolean => row
+                                .try_get::<_, Option<bool>>(i)
+                                .map(|v| v.map(SqlValue::Boolean)),
+                            _ => row
+                                .try_get::<_, Option<String>>(i)
+                                .map(|v| v.map(SqlValue::Utf8)),
+                        };
+                        value.map_err(sql_error)
+                    })
+                    .collect()
+            })
+            .collect()
+    })
+    .wait()
+    .map_err(|_| ballista_error("PostgreSQL query panicked"))?
+}
+
+#[cfg(feature = "mysql")]
+fn mysql_rows(
+    conn_str: &str,
+    query: &str,
+    data_types: &[DataType],
+) -> Result<Vec<Vec<Option<SqlValue>>>> {
+    use mysql::{from_value_opt, Value};
+
+    let mut conn = mysql::Conn::new(conn_str).map_err(sql_error)?;
+    let mut rows = vec![];
+    for row in conn.query(query).map_err(sql_error)? {
+        let row = row.map_err(sql_error)?;
+        let values = data_types
+            .iter()
+            .enumerate()
+            .map(|(i, data_type)| {
+                let value = match row.as_ref(i) {
+                    None | Some(Value::NULL) => return Ok(None),
+                    Some(value) => value.clone(),
+                };
+                let value = match data_type {
+                    DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => {
+                        from_value_opt::<i64>(value).map(SqlValue::Int)
+                    }
+                    DataType::Float32 | DataType::Float64 => {
+                        from_value_opt::<f64>(value).map(SqlValue::Float)
+                    }
+                    DataType::Boolean => from_value_opt::<bool>(value).map(SqlValue::Boolean),
+                    _ => from_value_opt::<String>(value).map(SqlValue::Utf8),
+                };
+                value.map(Some).map_err(sql_error)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        rows.push(values);
+    }
+    Ok(rows)
+}
+
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+fn sql_error<E: std::fmt::Debug>(e: E) -> BallistaError {
+    BallistaError::General(format!("SQL error: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logicalplan::col;
+
+    #[test]
+    fn generate_queries() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("price", DataType::Float64, true),
+        ]);
+        let query = select_query(SqlDialect::Postgres, "public.items", &schema);
+        assert_eq!(
+            "SELECT \"id\", CAST(\"name\" AS TEXT) AS \"name\", \
+             CAST(\"price\" AS DOUBLE PRECISION) AS \"price\" FROM \"public\".\"items\"",
+            query
+        );
+
+        // the comparison of two arithmetic expressions cannot be pushed down
+        let filter = col("name")
+            .eq(&Expr::Literal(ScalarValue::Utf8("O'Brien".to_owned())))
+            .and(&Expr::BinaryExpr {
+                left: Box::new(col("id")),
+                op: Operator::Modulus,
+                right: Box::new(Expr::Literal(ScalarValue::Int64(2))),
+            })
+            .and(&col("id").gt(&Expr::Literal(ScalarValue::Int64(10))));
+        let query = push_down_filter(SqlDialect::MySql, "SELECT `id` FROM `t`", &schema, &filter);
+        assert_eq!(
+            "SELECT `id` FROM `t` WHERE (`name` = 'O''Brien') AND (`id` > 10)",
+            query
+        );
+    }
+}
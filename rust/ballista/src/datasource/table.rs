@@ -0,0 +1,280 @@
+//! Named tables backed by custom DataFusion `TableProvider` implementations.
+//!
+//! Plans refer to registered tables by name, so a plan that is sent to a remote executor
+//! can only be executed if the executor has registered a table with the same name. The
+//! registry also stores the statistics that have been collected for each table, and which
+//! tables are unbounded sources of streaming queries. Memory tables (see
+//! `datasource::memory`) are kept so that they can be pinned on executors.
+//!
+//! Views are named plans, which SQL queries can read like tables. The plan of a view
+//! replaces its name in the plans of the queries that read it, so that the queries read
+//! the sources of the view rather than a table that executors would need to register.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::arrow::array::StringArray;
+use crate::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use crate::arrow::record_batch::RecordBatch;
+use crate::datafusion::datasource::{MemTable, TableProvider};
+use crate::datafusion::execution::context::ExecutionContext;
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::memory::MemoryTable;
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::{col_index, LogicalPlan, LogicalPlanBuilder};
+use crate::statistics::TableStatistics;
+use crate::translate::from_datafusion_plan;
+
+/// Table provider that can be shared between contexts
+pub type SharedTableProvider = Arc<dyn TableProvider + Send + Sync>;
+
+/// Tables registered by name
+#[derive(Default)]
+pub struct TableRegistry {
+    tables: RwLock<HashMap<String, SharedTableProvider>>,
+    statistics: RwLock<HashMap<String, TableStatistics>>,
+    unbounded: RwLock<HashSet<String>>,
+    memory: RwLock<HashMap<String, Arc<MemoryTable>>>,
+    views: RwLock<HashMap<String, LogicalPlan>>,
+}
+
+impl TableRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a table, replacing any existing table with the same name and discarding
+    /// its statistics
+    pub fn register(&self, name: &str, provider: SharedTableProvider) {
+        self.tables
+            .write()
+            .expect("table registry lock poisoned")
+            .insert(name.to_owned(), provider);
+        self.statistics
+            .write()
+            .expect("table registry lock poisoned")
+            .remove(name);
+        self.unbounded
+            .write()
+            .expect("table registry lock poisoned")
+            .remove(name);
+        self.memory
+            .write()
+            .expect("table registry lock poisoned")
+            .remove(name);
+    }
+
+    /// Register a table of batches held in memory
+    pub fn register_memory(&self, name: &str, table: MemoryTable) {
+        let table = Arc::new(table);
+        self.register(name, table.clone());
+        self.memory
+            .write()
+            .expect("table registry lock poisoned")
+            .insert(name.to_owned(), table);
+    }
+
+    /// Get a registered memory table
+    pub fn memory_table(&self, name: &str) -> Option<Arc<MemoryTable>> {
+        self.memory
+            .read()
+            .expect("table registry lock poisoned")
+            .get(name)
+            .cloned()
+    }
+
+    /// Register a table that is an unbounded source of streaming queries
+    pub fn register_unbounded(&self, name: &str, provider: SharedTableProvider) {
+        self.register(name, provider);
+        self.unbounded
+            .write()
+            .expect("table registry lock poisoned")
+            .insert(name.to_owned());
+    }
+
+    /// Whether a registered table is an unbounded source
+    pub fn is_unbounded(&self, name: &str) -> bool {
+        self.unbounded
+            .read()
+            .expect("table registry lock poisoned")
+            .contains(name)
+    }
+
+    /// Store the statistics of a registered table
+    pub fn set_statistics(&self, name: &str, statistics: TableStatistics) -> Result<()> {
+        self.get(name)?;
+        self.statistics
+            .write()
+            .expect("table registry lock poisoned")
+            .insert(name.to_owned(), statistics);
+        Ok(())
+    }
+
+    /// The statistics of a table, if they have been collected
+    pub fn statistics(&self, name: &str) -> Option<TableStatistics> {
+        self.statistics
+            .read()
+            .expect("table registry lock poisoned")
+            .get(name)
+            .cloned()
+    }
+
+    /// Get a registered table
+    pub fn get(&self, name: &str) -> Result<SharedTableProvider> {
+        self.tables
+            .read()
+            .expect("table registry lock poisoned")
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ballista_error(&format!("No table is registered as '{}'", name)))
+    }
+
+    /// The names of the registered tables, in order
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .tables
+            .read()
+            .expect("table registry lock poisoned")
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Register a plan as a view, replacing any existing view with the same name. Views
+    /// are read instead of tables with the same name.
+    pub fn register_view(&self, name: &str, plan: &LogicalPlan) {
+        self.views
+            .write()
+            .expect("table registry lock poisoned")
+            .insert(name.to_owned(), plan.clone());
+    }
+
+    /// Get the plan of a registered view
+    pub fn view(&self, name: &str) -> Option<LogicalPlan> {
+        self.views
+            .read()
+            .expect("table registry lock poisoned")
+            .get(name)
+            .cloned()
+    }
+
+    /// The names of the registered views, in order
+    pub fn view_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .views
+            .read()
+            .expect("table registry lock poisoned")
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Plan a SQL query with DataFusion's SQL planner against the registered tables and
+    /// views
+    pub fn sql_plan(&self, sql: &str) -> Result<LogicalPlan> {
+        let mut ctx = ExecutionContext::new();
+        self.register_with(&mut ctx);
+        let views = self
+            .views
+            .read()
+            .expect("table registry lock poisoned")
+            .clone();
+        // views are planned as empty tables with their schema and then replaced
+        for (name, plan) in &views {
+            let schema = Arc::new(plan.schema().clone());
+            ctx.register_table(name, Box::new(MemTable::new(schema, vec![])?));
+        }
+        let plan = ctx.create_logical_plan(sql)?;
+        let plan = from_datafusion_plan(&ctx.optimize(&plan)?)?;
+        plan.transform_up(|plan| match plan {
+            LogicalPlan::TableScan {
+                ref table_name,
+                ref projection,
+                ..
+            } if views.contains_key(table_name) => {
+                let view = &views[table_name];
+                match projection {
+                    Some(projection) => Ok(LogicalPlanBuilder::from(view)
+                        .project(projection.iter().map(|i| col_index(*i)).collect())?
+                        .build()?),
+                    None => Ok(view.clone()),
+                }
+            }
+            other => Ok(other),
+        })
+    }
+
+    /// Register all of the tables with a DataFusion context
+    pub fn register_with(&self, ctx: &mut ExecutionContext) {
+        for (name, provider) in self
+            .tables
+            .read()
+            .expect("table registry lock poisoned")
+            .iter()
+        {
+            ctx.register_table(name, Box::new(RegisteredTable(provider.clone())));
+        }
+    }
+}
+
+/// The batch of table names that executors return for `ListTables` actions
+pub fn table_names_to_batch(names: &[String]) -> Result<RecordBatch> {
+    let schema = Schema::new(vec![Field::new("name", DataType::Utf8, false)]);
+    let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![Arc::new(StringArray::from(names))],
+    )?)
+}
+
+impl fmt::Debug for TableRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tables = self.tables.read().expect("table registry lock poisoned");
+        let mut names: Vec<&String> = tables.keys().collect();
+        names.sort();
+        f.debug_struct("TableRegistry")
+            .field("tables", &names)
+            .finish()
+    }
+}
+
+/// DataFusion takes ownership of the providers that are registered with it, so registered
+/// tables are wrapped to allow them to be registered with more than one context
+struct RegisteredTable(SharedTableProvider);
+
+impl TableProvider for RegisteredTable {
+    fn schema(&self) -> SchemaRef {
+        self.0.schema()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        batch_size: usize,
+    ) -> crate::datafusion::error::Result<Vec<Arc<dyn Partition>>> {
+        self.0.scan(projection, batch_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafusion::datasource::MemTable;
+
+    #[test]
+    fn register_and_get() -> Result<()> {
+        let registry = TableRegistry::new();
+        assert!(registry.get("t").is_err());
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        registry.register("t", Arc::new(MemTable::new(schema.clone(), vec![])?));
+        assert_eq!(schema, registry.get("t")?.schema());
+        assert_eq!(vec!["t".to_owned()], registry.names());
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+//! Writing the results of queries to object stores.
+//!
+//! Distributed writes produce a file for each task of the last stage of a query, written by
+//! the executor that ran the task directly to the object store, so that the results do not
+//! pass through the client. The client receives a manifest of the files that were written.
+//!
+//! Streaming queries append their results to a directory with a `ParquetStreamSink`, which
+//! writes a file for each flush into a subdirectory named after the time of the flush.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::arrow::array::{StringArray, UInt64Array};
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::cancel::CancellationToken;
+use crate::datafusion::execution::physical_plan::memory::MemoryExec;
+use crate::datafusion::execution::physical_plan::{ExecutionPlan, Partition};
+use crate::datasource::object_store::{self, LocalFileSystem, ObjectStoreRegistry};
+use crate::datasource::parquet::ParquetFileWriter;
+use crate::error::{ballista_error, Result};
+use crate::streaming::StreamSink;
+
+use chrono::Utc;
+
+/// Used to name the local files that are written before they are uploaded
+static NEXT_STAGING_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// A file written by a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputFile {
+    pub path: String,
+    pub num_rows: usize,
+    /// Size of the file in bytes
+    pub size: u64,
+}
+
+/// The files written by a query, ordered by partition
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WriteManifest {
+    pub files: Vec<OutputFile>,
+}
+
+impl WriteManifest {
+    /// Schema of the batches that manifests are sent to clients as
+    pub fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("num_rows", DataType::UInt64, false),
+            Field::new("size", DataType::UInt64, false),
+        ])
+    }
+
+    /// Total number of rows written
+    pub fn num_rows(&self) -> usize {
+        self.files.iter().map(|file| file.num_rows).sum()
+    }
+
+    pub fn to_batch(&self) -> Result<RecordBatch> {
+        let paths: Vec<&str> = self.files.iter().map(|file| file.path.as_str()).collect();
+        let num_rows: Vec<u64> = self.files.iter().map(|file| file.num_rows as u64).collect();
+        let sizes: Vec<u64> = self.files.iter().map(|file| file.size).collect();
+        Ok(RecordBatch::try_new(
+            Arc::new(Self::schema()),
+            vec![
+                Arc::new(StringArray::from(paths)),
+                Arc::new(UInt64Array::from(num_rows)),
+                Arc::new(UInt64Array::from(sizes)),
+            ],
+        )?)
+    }
+
+    /// Read a manifest from the batches returned for a write
+    pub fn from_batches(batches: &[RecordBatch]) -> Result<Self> {
+        let mut files = vec![];
+        for batch in batches {
+            if batch.schema().as_ref() != &Self::schema() {
+                return Err(ballista_error(&format!(
+                    "Unexpected schema for write manifest: {:?}",
+                    batch.schema()
+                )));
+            }
+            let column = |i: usize| batch.column(i).as_any();
+            let paths = column(0).downcast_ref::<StringArray>().unwrap();
+            let num_rows = column(1).downcast_ref::<UInt64Array>().unwrap();
+            let sizes = column(2).downcast_ref::<UInt64Array>().unwrap();
+            for row in 0..batch.num_rows() {
+                files.push(OutputFile {
+                    path: paths.value(row).to_owned(),
+                    num_rows: num_rows.value(row) as usize,
+                    size: sizes.value(row),
+                });
+            }
+        }
+        Ok(Self { files })
+    }
+}
+
+/// The path of the file written for a partition of the output in a directory
+pub fn output_file_path(dir: &str, partition: usize) -> String {
+    format!(
+        "{}/part-{:05}.parquet",
+        dir.trim_end_matches('/'),
+        partition
+    )
+}
+
+/// Execute partitions and write their output to a single Parquet file through the object
+/// store for the path. Files in remote object stores are written locally and then uploaded,
+/// replacing any existing file, so tasks that are retried write the same file again.
+pub fn write_parquet_partitions(
+    object_stores: &ObjectStoreRegistry,
+    path: &str,
+    schema: &Schema,
+    partitions: &[Arc<dyn Partition>],
+    token: &CancellationToken,
+) -> Result<OutputFile> {
+    let is_local = object_store::scheme(path) == "file";
+    let local_path = if is_local {
+        PathBuf::from(LocalFileSystem::local_path(path))
+    } else {
+        std::env::temp_dir().join("ballista-write").join(format!(
+            "{}-{}.parquet",
+            std::process::id(),
+            NEXT_STAGING_FILE.fetch_add(1, Ordering::SeqCst)
+        ))
+    };
+    if let Some(parent) = local_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut writer = ParquetFileWriter::try_new(&local_path, schema)?;
+    for partition in partitions {
+        let reader = partition.execute()?;
+        let mut reader = reader.lock().unwrap();
+        while let Some(batch) = reader.next_batch()? {
+            token.check()?;
+            writer.write(&batch)?;
+        }
+    }
+    let num_rows = writer.close()?;
+    let size = fs::metadata(&local_path)?.len();
+
+    if !is_local {
+        let result = fs::read(&local_path)
+            .map_err(|e| e.into())
+            .and_then(|data| object_stores.get(path)?.write(path, &data));
+        fs::remove_file(&local_path)?;
+        result?;
+    }
+    Ok(OutputFile {
+        path: path.to_owned(),
+        num_rows,
+        size,
+    })
+}
+
+/// Default layout of the subdirectories of a `ParquetStreamSink`, as a `chrono` format of
+/// the time of each flush in UTC
+pub const DEFAULT_DIRECTORY_FORMAT: &str = "date=%Y-%m-%d/hour=%H";
+
+/// Appends the results of a streaming query to a directory, writing the batches of each
+/// flush to a new Parquet file in the subdirectory for the time of the flush. A flush that
+/// is retried writes the same file again, so a file is only published once.
+pub struct ParquetStreamSink {
+    object_stores: ObjectStoreRegistry,
+    dir: String,
+    directory_format: String,
+    batches: Vec<RecordBatch>,
+    /// The path of the file of the buffered batches, once a flush has been attempted
+    path: Option<String>,
+    next_file: usize,
+}
+
+impl ParquetStreamSink {
+    pub fn new(object_stores: ObjectStoreRegistry, dir: &str) -> Self {
+        Self {
+            object_stores,
+            dir: dir.trim_end_matches('/').to_owned(),
+            directory_format: DEFAULT_DIRECTORY_FORMAT.to_owned(),
+            batches: vec![],
+            path: None,
+            next_file: 0,
+        }
+    }
+
+    /// Lay out the subdirectories with a `chrono` format of the time of each flush
+    pub fn with_directory_format(mut self, directory_format: &str) -> Self {
+        self.directory_format = directory_format.to_owned();
+        self
+    }
+
+    /// The path of the next file, which is unique to the process and flush
+    fn next_path(&mut self) -> String {
+        let now = Utc::now();
+        let path = format!(
+            "{}/{}/part-{}-{}-{:05}.parquet",
+            self.dir,
+            now.format(&self.directory_format),
+            now.timestamp_millis(),
+            std::process::id(),
+            self.next_file
+        );
+        self.next_file += 1;
+        path
+    }
+}
+
+impl StreamSink for ParquetStreamSink {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        self.batches.push(batch.clone());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.batches.is_empty() {
+            return Ok(());
+        }
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => {
+                let path = self.next_path();
+                self.path = Some(path.clone());
+                path
+            }
+        };
+        let schema = self.batches[0].schema();
+        let exec = MemoryExec::try_new(&vec![self.batches.clone()], schema.clone(), None)?;
+        write_parquet_partitions(
+            &self.object_stores,
+            &path,
+            &schema,
+            &exec.partitions()?,
+            &CancellationToken::new(),
+        )?;
+        self.batches.clear();
+        self.path = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use std::collections::HashMap;
+
+    #[test]
+    fn write_partitions_with_manifest() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+        let exec = MemoryExec::try_new(
+            &vec![vec![batch.clone()], vec![batch]],
+            schema.clone(),
+            None,
+        )?;
+
+        let dir = std::env::temp_dir().join(format!("ballista-write-test-{}", std::process::id()));
+        let path = output_file_path(&dir.to_string_lossy(), 3);
+        assert!(path.ends_with("/part-00003.parquet"));
+        let object_stores = ObjectStoreRegistry::new(&HashMap::new());
+        let file = write_parquet_partitions(
+            &object_stores,
+            &path,
+            &schema,
+            &exec.partitions()?,
+            &CancellationToken::new(),
+        )?;
+        fs::remove_dir_all(&dir)?;
+        assert_eq!(6, file.num_rows);
+        assert!(file.size > 0);
+
+        let manifest = WriteManifest { files: vec![file] };
+        assert_eq!(6, manifest.num_rows());
+        assert_eq!(
+            manifest,
+            WriteManifest::from_batches(&[manifest.to_batch()?])?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn append_a_file_for_each_flush() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+        let dir = std::env::temp_dir().join(format!("ballista-sink-test-{}", std::process::id()));
+        let object_stores = ObjectStoreRegistry::new(&HashMap::new());
+        let mut sink = ParquetStreamSink::new(object_stores, &dir.to_string_lossy())
+            .with_directory_format("year=%Y");
+        sink.flush()?;
+        sink.write(&batch)?;
+        sink.flush()?;
+        sink.write(&batch)?;
+        sink.flush()?;
+
+        let year = Utc::now().format("year=%Y").to_string();
+        let files = fs::read_dir(dir.join(year))?.count();
+        fs::remove_dir_all(&dir)?;
+        assert_eq!(2, files);
+        Ok(())
+    }
+}
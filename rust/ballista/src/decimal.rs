@@ -0,0 +1,427 @@
+//! Exact decimal arithmetic.
+//!
+//! Arrow has no decimal type, so decimal columns are `Utf8` columns of the exact text of
+//! their values, whose precision and scale are kept in the metadata of the schema (see
+//! `decimal_precision_and_scale`). DataFusion cannot compute with them, so sums, differences and
+//! products of decimal columns, with each other and with integer columns and literals, are
+//! evaluated here on their unscaled `i128` values, and `SUM`, `MIN`, `MAX` and `AVG` of
+//! decimals are computed by the hash aggregate (see `aggregate`).
+//!
+//! Results have the precision and scale that SQL gives them. Sums and differences of
+//! `DECIMAL(p1, s1)` and `DECIMAL(p2, s2)` have a scale of `max(s1, s2)` and a precision of
+//! `max(s1, s2) + max(p1 - s1, p2 - s2) + 1`, and products have a precision of `p1 + p2 + 1`
+//! and a scale of `s1 + s2`. `SUM` of `DECIMAL(p, s)` is a `DECIMAL(p + 10, s)` and `AVG` a
+//! `DECIMAL(p + 4, s + 4)`, rounded half away from zero. Arithmetic whose result would need
+//! more than 38 digits is left to DataFusion, and values that overflow are errors.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::arrow::array::{Array, ArrayRef, Int64Array, StringArray};
+use crate::arrow::compute::kernels::cast::cast;
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::{
+    format_decimal, lit_decimal, rescale_decimal, Expr, Operator, ScalarValue,
+};
+
+/// The prefix of the schema metadata keys that hold the precision and scale of decimal
+/// columns, as `<precision>,<scale>` under the key of the prefix and the column name
+pub const DECIMAL_METADATA_PREFIX: &str = "ballista.decimal.";
+
+/// The precision and scale of a `Utf8` column of a schema that holds decimals
+pub fn decimal_precision_and_scale(schema: &Schema, column: &str) -> Option<(usize, usize)> {
+    let value = schema
+        .metadata()
+        .get(&format!("{}{}", DECIMAL_METADATA_PREFIX, column))?;
+    let mut parts = value.splitn(2, ',');
+    let precision = parts.next()?.parse().ok()?;
+    let scale = parts.next()?.parse().ok()?;
+    Some((precision, scale))
+}
+
+/// The largest precision of a decimal, which is the number of digits that an `i128` holds
+pub const MAX_DECIMAL_PRECISION: usize = 38;
+
+/// The precision and scale of the result of an arithmetic operator on decimals of the given
+/// precisions and scales, or `None` if the operator is not supported or the result would
+/// exceed the maximum precision
+pub fn result_type(
+    op: &Operator,
+    (p1, s1): (usize, usize),
+    (p2, s2): (usize, usize),
+) -> Option<(usize, usize)> {
+    let (precision, scale) = match op {
+        Operator::Plus | Operator::Minus => {
+            let scale = s1.max(s2);
+            let integer_digits = p1.saturating_sub(s1).max(p2.saturating_sub(s2));
+            (integer_digits + scale + 1, scale)
+        }
+        Operator::Multiply => (p1 + p2 + 1, s1 + s2),
+        _ => return None,
+    };
+    if precision > MAX_DECIMAL_PRECISION {
+        None
+    } else {
+        Some((precision, scale))
+    }
+}
+
+/// Apply an arithmetic operator to two unscaled decimal values of the given scales, giving
+/// the unscaled value of the result at the scale of `result_type`, or `None` on overflow
+pub fn apply(a: i128, s1: usize, op: &Operator, b: i128, s2: usize) -> Option<i128> {
+    match op {
+        Operator::Plus | Operator::Minus => {
+            let scale = s1.max(s2);
+            let a = rescale_decimal(a, s1, scale)?;
+            let b = rescale_decimal(b, s2, scale)?;
+            match op {
+                Operator::Plus => a.checked_add(b),
+                _ => a.checked_sub(b),
+            }
+        }
+        Operator::Multiply => a.checked_mul(b),
+        _ => None,
+    }
+}
+
+/// The precision and scale of a `SUM` of decimals
+pub fn sum_type((precision, scale): (usize, usize)) -> (usize, usize) {
+    ((precision + 10).min(MAX_DECIMAL_PRECISION), scale)
+}
+
+/// The precision and scale of an `AVG` of decimals
+pub fn avg_type((precision, scale): (usize, usize)) -> (usize, usize) {
+    ((precision + 4).min(MAX_DECIMAL_PRECISION), scale + 4)
+}
+
+/// The unscaled value of the average of decimals of a scale, at the scale of `avg_type`,
+/// or `None` on overflow
+pub fn average(sum: i128, count: u64, scale: usize) -> Option<i128> {
+    let (_, avg_scale) = avg_type((0, scale));
+    let sum = rescale_decimal(sum, scale, avg_scale)?;
+    let count = count as i128;
+    // rounded half away from zero
+    let (quotient, remainder) = (sum / count, sum % count);
+    if remainder.abs() * 2 >= count {
+        Some(quotient + sum.signum())
+    } else {
+        Some(quotient)
+    }
+}
+
+/// The unscaled value of the text of a decimal, such as `-0.5`, at the given scale, or
+/// `None` if the text is not a decimal with at most that many fractional digits
+pub fn parse_decimal(text: &str, scale: usize) -> Option<i128> {
+    match lit_decimal(text) {
+        Ok(Expr::Literal(ScalarValue::Decimal(value, _, s))) if s <= scale => {
+            rescale_decimal(value, s, scale)
+        }
+        _ => None,
+    }
+}
+
+/// The precision and scale of an expression whose values are decimals, which are decimal
+/// columns and arithmetic on them, or `None` if its values are not decimals
+pub fn decimal_type(expr: &Expr, schema: &Schema) -> Option<(usize, usize)> {
+    match expr {
+        Expr::Alias(expr, _) => decimal_type(expr, schema),
+        Expr::Column(i) => column_type(schema.fields().get(*i)?.name(), schema),
+        Expr::UnresolvedColumn(name) => column_type(name, schema),
+        Expr::BinaryExpr { left, op, right } => {
+            let (left, right) = match (decimal_type(left, schema), decimal_type(right, schema)) {
+                (None, None) => return None,
+                (l, r) => (
+                    l.or_else(|| operand_type(left, schema))?,
+                    r.or_else(|| operand_type(right, schema))?,
+                ),
+            };
+            result_type(op, left, right)
+        }
+        _ => None,
+    }
+}
+
+/// Whether an expression is arithmetic on decimals, which is evaluated here
+pub fn is_decimal_arithmetic(expr: &Expr, schema: &Schema) -> bool {
+    match expr {
+        Expr::BinaryExpr { .. } => decimal_type(expr, schema).is_some(),
+        _ => false,
+    }
+}
+
+/// The precision and scale of an aggregate of decimals, or `None` if it is not one
+pub fn aggregate_type(expr: &Expr, schema: &Schema) -> Option<(usize, usize)> {
+    match expr {
+        Expr::Alias(expr, _) => aggregate_type(expr, schema),
+        Expr::AggregateFunction { name, args, .. } if args.len() == 1 => {
+            let arg = decimal_type(&args[0], schema)?;
+            match name.to_uppercase().as_str() {
+                "SUM" => Some(sum_type(arg)),
+                "AVG" => Some(avg_type(arg)),
+                "MIN" | "MAX" => Some(arg),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// A schema of the fields of expressions of an input, with the precision and scale of the
+/// expressions of decimals in its metadata
+pub fn schema_with_decimals(fields: Vec<Field>, exprs: &[Expr], input_schema: &Schema) -> Schema {
+    let mut metadata = HashMap::new();
+    for (field, expr) in fields.iter().zip(exprs) {
+        let decimal =
+            decimal_type(expr, input_schema).or_else(|| aggregate_type(expr, input_schema));
+        if let Some((precision, scale)) = decimal {
+            metadata.insert(
+                format!("{}{}", DECIMAL_METADATA_PREFIX, field.name()),
+                format!("{},{}", precision, scale),
+            );
+        }
+    }
+    Schema::new_with_metadata(fields, metadata)
+}
+
+/// The metadata of a schema with the columns of several schemas, such as the schema of a
+/// join, which keeps the precision and scale of the decimal columns of each
+pub fn merged_metadata(schemas: &[&Schema]) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    for schema in schemas {
+        metadata.extend(schema.metadata().clone());
+    }
+    metadata
+}
+
+/// Evaluate arithmetic on decimals over a batch of an input with the given schema, giving
+/// the decimal text of the results
+pub fn evaluate(expr: &Expr, batch: &RecordBatch, schema: &Schema) -> Result<ArrayRef> {
+    let (values, scale) = unscaled_values(expr, batch, schema)?;
+    let text: Vec<Option<String>> = values
+        .into_iter()
+        .map(|v| v.map(|v| format_decimal(v, scale)))
+        .collect();
+    let text: Vec<Option<&str>> = text
+        .iter()
+        .map(|v| v.as_ref().map(|v| v.as_str()))
+        .collect();
+    Ok(Arc::new(StringArray::from(text)))
+}
+
+/// The unscaled values of an expression over a batch, and their scale
+fn unscaled_values(
+    expr: &Expr,
+    batch: &RecordBatch,
+    schema: &Schema,
+) -> Result<(Vec<Option<i128>>, usize)> {
+    let rows = batch.num_rows();
+    match expr {
+        Expr::Alias(expr, _) => unscaled_values(expr, batch, schema),
+        Expr::Literal(ScalarValue::Decimal(value, _, scale)) => {
+            Ok((vec![Some(*value); rows], *scale))
+        }
+        Expr::Literal(value) if integer_literal(value).is_some() => {
+            Ok((vec![integer_literal(value); rows], 0))
+        }
+        Expr::Column(i) => column_values(*i, batch, schema),
+        Expr::UnresolvedColumn(name) => column_values(schema.index_of(name)?, batch, schema),
+        Expr::BinaryExpr { left, op, right } => {
+            let (left, s1) = unscaled_values(left, batch, schema)?;
+            let (right, s2) = unscaled_values(right, batch, schema)?;
+            let values = left
+                .into_iter()
+                .zip(right)
+                .map(|values| match values {
+                    (Some(a), Some(b)) => apply(a, s1, op, b, s2)
+                        .map(Some)
+                        .ok_or_else(|| ballista_error(&format!("Decimal overflow in {:?}", expr))),
+                    _ => Ok(None),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let scale = match op {
+                Operator::Multiply => s1 + s2,
+                _ => s1.max(s2),
+            };
+            Ok((values, scale))
+        }
+        other => Err(ballista_error(&format!(
+            "Cannot evaluate {:?} as a decimal",
+            other
+        ))),
+    }
+}
+
+/// The unscaled values of a decimal or integer column and their scale
+fn column_values(
+    column: usize,
+    batch: &RecordBatch,
+    schema: &Schema,
+) -> Result<(Vec<Option<i128>>, usize)> {
+    let array = batch.column(column);
+    if let Some((_, scale)) = column_type(schema.field(column).name(), schema) {
+        let text = array
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| ballista_error("Decimal columns must be Utf8 columns"))?;
+        let values = (0..text.len())
+            .map(|i| {
+                if text.is_null(i) {
+                    return Ok(None);
+                }
+                parse_decimal(text.value(i), scale)
+                    .map(Some)
+                    .ok_or_else(|| {
+                        ballista_error(&format!(
+                            "'{}' is not a decimal with a scale of {}",
+                            text.value(i),
+                            scale
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        return Ok((values, scale));
+    }
+    let integers = cast(array, &DataType::Int64)?;
+    let integers = integers
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .ok_or_else(|| ballista_error("Integer columns must cast to Int64"))?;
+    let values = (0..integers.len())
+        .map(|i| {
+            if integers.is_null(i) {
+                None
+            } else {
+                Some(integers.value(i) as i128)
+            }
+        })
+        .collect();
+    Ok((values, 0))
+}
+
+/// The precision and scale of a column that holds decimals
+fn column_type(name: &str, schema: &Schema) -> Option<(usize, usize)> {
+    match schema.field_with_name(name) {
+        Ok(field) if *field.data_type() == DataType::Utf8 => {
+            decimal_precision_and_scale(schema, name)
+        }
+        _ => None,
+    }
+}
+
+/// The precision and scale of an operand of arithmetic with decimals that is not itself a
+/// decimal, which are decimal and integer literals and integer columns
+fn operand_type(expr: &Expr, schema: &Schema) -> Option<(usize, usize)> {
+    match expr {
+        Expr::Alias(expr, _) => operand_type(expr, schema),
+        Expr::Literal(ScalarValue::Decimal(_, precision, scale)) => Some((*precision, *scale)),
+        Expr::Literal(value) => {
+            let digits = integer_literal(value)?.abs().to_string().len();
+            Some((digits, 0))
+        }
+        Expr::Column(_) | Expr::UnresolvedColumn(_) => {
+            let precision = match expr.get_type(schema).ok()? {
+                DataType::Int8 | DataType::UInt8 => 3,
+                DataType::Int16 | DataType::UInt16 => 5,
+                DataType::Int32 | DataType::UInt32 => 10,
+                DataType::Int64 => 19,
+                DataType::UInt64 => 20,
+                _ => return None,
+            };
+            Some((precision, 0))
+        }
+        _ => None,
+    }
+}
+
+fn integer_literal(value: &ScalarValue) -> Option<i128> {
+    Some(match value {
+        ScalarValue::Int8(v) => *v as i128,
+        ScalarValue::Int16(v) => *v as i128,
+        ScalarValue::Int32(v) => *v as i128,
+        ScalarValue::Int64(v) => *v as i128,
+        ScalarValue::UInt8(v) => *v as i128,
+        ScalarValue::UInt16(v) => *v as i128,
+        ScalarValue::UInt32(v) => *v as i128,
+        ScalarValue::UInt64(v) => *v as i128,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::logicalplan::col;
+
+    fn decimal_schema() -> Schema {
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            format!("{}price", DECIMAL_METADATA_PREFIX),
+            "10,2".to_owned(),
+        );
+        metadata.insert(
+            format!("{}discount", DECIMAL_METADATA_PREFIX),
+            "3,2".to_owned(),
+        );
+        Schema::new_with_metadata(
+            vec![
+                Field::new("price", DataType::Utf8, true),
+                Field::new("discount", DataType::Utf8, true),
+                Field::new("quantity", DataType::Int32, true),
+            ],
+            metadata,
+        )
+    }
+
+    #[test]
+    fn precision_and_scale_of_arithmetic() {
+        let schema = decimal_schema();
+        let plus = col("price").plus(&col("discount"));
+        assert_eq!(decimal_type(&plus, &schema), Some((11, 2)));
+        let times = col("price").multiply(&col("quantity"));
+        assert_eq!(decimal_type(&times, &schema), Some((21, 2)));
+        let discounted = col("price").multiply(&lit_decimal("1").unwrap().minus(&col("discount")));
+        assert_eq!(decimal_type(&discounted, &schema), Some((15, 4)));
+        assert!(!is_decimal_arithmetic(
+            &col("quantity").plus(&col("quantity")),
+            &schema
+        ));
+        assert_eq!(sum_type((15, 4)), (25, 4));
+        assert_eq!(avg_type((10, 2)), (14, 6));
+    }
+
+    #[test]
+    fn evaluate_exactly() -> Result<()> {
+        let schema = decimal_schema();
+        let batch = RecordBatch::try_new(
+            Arc::new(schema.clone()),
+            vec![
+                Arc::new(StringArray::from(vec![
+                    Some("12345678901234567.89"),
+                    Some("0.10"),
+                    None,
+                ])),
+                Arc::new(StringArray::from(vec![
+                    Some("0.05"),
+                    Some("0.20"),
+                    Some("0.01"),
+                ])),
+                Arc::new(crate::arrow::array::Int32Array::from(vec![3, 3, 1])),
+            ],
+        )?;
+        let expr = col("price")
+            .multiply(&lit_decimal("1")?.minus(&col("discount")))
+            .multiply(&col("quantity"));
+        let values = evaluate(&expr, &batch, &schema)?;
+        let values = values.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(values.value(0), "35185184868518518.4865");
+        assert_eq!(values.value(1), "0.2400");
+        assert!(values.is_null(2));
+
+        assert_eq!(average(1000, 3, 2), Some(3_333_333));
+        assert_eq!(average(-1000, 6, 2), Some(-1_666_667));
+        Ok(())
+    }
+}
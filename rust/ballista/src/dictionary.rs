@@ -0,0 +1,597 @@
+//! Dictionary-encoded columns.
+//!
+//! String-heavy datasets use far less memory when their columns are dictionary encoded, so
+//! dictionary columns are kept encoded as they flow through joins, aggregates, sorts and
+//! shuffles rather than being decoded into their values. The Arrow IPC writer does not
+//! write dictionary batches, so dictionary columns are transported as batches of their keys,
+//! with the values of each dictionary sent separately whenever they change. Flight streams
+//! send the values in messages that precede the batch that uses them, and IPC files keep the
+//! values of each dictionary column in a sidecar file next to the file of keys, so files
+//! without dictionary columns are plain Arrow IPC files.
+
+use std::convert::{TryFrom, TryInto};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::compute::kernels::cast::cast;
+use crate::arrow::compute::kernels::concat::concat as concat_arrays;
+use crate::arrow::compute::kernels::take::take as take_array;
+use crate::arrow::datatypes::*;
+use crate::arrow::error::{ArrowError, Result as ArrowResult};
+use crate::arrow::flight::flight_data_to_batch;
+use crate::arrow::ipc::reader::FileReader;
+use crate::arrow::ipc::writer::FileWriter;
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::error::{ballista_error, Result};
+use crate::protobuf;
+
+use flight::{flight_descriptor::DescriptorType, FlightData, FlightDescriptor};
+use prost::Message;
+
+/// First element of the descriptor path of the Flight messages that carry dictionaries
+const DICTIONARY_PATH: &str = "ballista.dictionary";
+
+/// The keys of a dictionary column, or the column itself if it is not dictionary encoded
+pub fn keys(column: &ArrayRef) -> ArrayRef {
+    let data = column.data();
+    match data.data_type() {
+        DataType::Dictionary(key_type, _) => make_array(Arc::new(ArrayData::new(
+            key_type.as_ref().clone(),
+            data.len(),
+            Some(data.null_count()),
+            data.null_buffer().cloned(),
+            data.offset(),
+            data.buffers().to_vec(),
+            vec![],
+        ))),
+        _ => column.clone(),
+    }
+}
+
+/// The values of the dictionary of a column, which must be dictionary encoded
+pub fn values(column: &ArrayRef) -> ArrayRef {
+    make_array(column.data().child_data()[0].clone())
+}
+
+/// A dictionary column with the given keys and dictionary values
+pub fn from_parts(keys: &ArrayRef, values: &ArrayRef) -> ArrayRef {
+    let data = keys.data();
+    make_array(Arc::new(ArrayData::new(
+        DataType::Dictionary(
+            Box::new(keys.data_type().clone()),
+            Box::new(values.data_type().clone()),
+        ),
+        data.len(),
+        Some(data.null_count()),
+        data.null_buffer().cloned(),
+        data.offset(),
+        data.buffers().to_vec(),
+        vec![values.data()],
+    )))
+}
+
+/// The index into the dictionary values of each row of a dictionary column, or `None` for
+/// null rows
+pub fn key_indices(column: &ArrayRef) -> Result<Vec<Option<usize>>> {
+    let keys = cast(&keys(column), &DataType::UInt32)?;
+    let keys = keys.as_any().downcast_ref::<UInt32Array>().unwrap();
+    Ok((0..keys.len())
+        .map(|row| {
+            if keys.is_null(row) {
+                None
+            } else {
+                Some(keys.value(row) as usize)
+            }
+        })
+        .collect())
+}
+
+macro_rules! encode_strings {
+    ($array:expr, $key_type:ident) => {{
+        let keys = PrimitiveBuilder::<$key_type>::new($array.len());
+        let values = StringBuilder::new($array.len());
+        let mut builder = StringDictionaryBuilder::new(keys, values);
+        for row in 0..$array.len() {
+            if $array.is_null(row) {
+                builder.append_null()?;
+            } else {
+                builder.append($array.value(row))?;
+            }
+        }
+        Arc::new(builder.finish()) as ArrayRef
+    }};
+}
+
+/// Dictionary encode a string column with keys of the given type
+pub fn encode(column: &ArrayRef, key_type: &DataType) -> Result<ArrayRef> {
+    let array = column
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .ok_or_else(|| {
+            ballista_error(&format!(
+                "Cannot dictionary encode a column of type {:?}",
+                column.data_type()
+            ))
+        })?;
+    let encoded = match key_type {
+        DataType::Int8 => encode_strings!(array, Int8Type),
+        DataType::Int16 => encode_strings!(array, Int16Type),
+        DataType::Int32 => encode_strings!(array, Int32Type),
+        DataType::Int64 => encode_strings!(array, Int64Type),
+        DataType::UInt8 => encode_strings!(array, UInt8Type),
+        DataType::UInt16 => encode_strings!(array, UInt16Type),
+        DataType::UInt32 => encode_strings!(array, UInt32Type),
+        DataType::UInt64 => encode_strings!(array, UInt64Type),
+        other => {
+            return Err(ballista_error(&format!(
+                "Invalid dictionary key type {:?}",
+                other
+            )))
+        }
+    };
+    Ok(encoded)
+}
+
+/// Take the given rows of a column, keeping dictionary columns encoded with the same
+/// dictionary
+pub fn take(column: &ArrayRef, indices: &UInt32Array) -> Result<ArrayRef> {
+    match column.data_type() {
+        DataType::Dictionary(_, _) => {
+            let keys = take_array(&keys(column), indices, None)?;
+            Ok(from_parts(&keys, &values(column)))
+        }
+        _ => Ok(take_array(column, indices, None)?),
+    }
+}
+
+/// Concatenate columns of the same type. Dictionary columns that share a dictionary keep
+/// it, and the dictionaries of other dictionary columns are concatenated.
+pub fn concat(columns: &[ArrayRef]) -> Result<ArrayRef> {
+    let key_type = match columns.first().map(|column| column.data_type()) {
+        Some(DataType::Dictionary(key_type, _)) => key_type.as_ref().clone(),
+        _ => return Ok(concat_arrays(columns)?),
+    };
+    let keys: Vec<ArrayRef> = columns.iter().map(keys).collect();
+    let first = columns[0].data().child_data()[0].clone();
+    if columns
+        .iter()
+        .all(|column| Arc::ptr_eq(&column.data().child_data()[0], &first))
+    {
+        return Ok(from_parts(&concat_arrays(&keys)?, &values(&columns[0])));
+    }
+
+    // the keys of each column are offset by the length of the dictionaries before it
+    let mut offset = 0;
+    let mut offset_keys = Vec::with_capacity(columns.len());
+    let mut dictionaries = Vec::with_capacity(columns.len());
+    for column in columns {
+        let dictionary = values(column);
+        let shifted: Vec<Option<u64>> = key_indices(column)?
+            .into_iter()
+            .map(|key| key.map(|key| (key + offset) as u64))
+            .collect();
+        offset_keys.push(cast(
+            &(Arc::new(UInt64Array::from(shifted)) as ArrayRef),
+            &key_type,
+        )?);
+        offset += dictionary.len();
+        dictionaries.push(dictionary);
+    }
+    Ok(from_parts(
+        &concat_arrays(&offset_keys)?,
+        &concat_arrays(&dictionaries)?,
+    ))
+}
+
+/// The schema with each dictionary column replaced by its keys, which is how batches are
+/// transported
+pub fn transport_schema(schema: &Schema) -> Schema {
+    Schema::new(
+        schema
+            .fields()
+            .iter()
+            .map(|field| match field.data_type() {
+                DataType::Dictionary(key_type, _) => {
+                    Field::new(field.name(), key_type.as_ref().clone(), field.is_nullable())
+                }
+                _ => field.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// Schema of the batches that carry the values of a dictionary
+fn values_schema(value_type: &DataType) -> SchemaRef {
+    Arc::new(Schema::new(vec![Field::new(
+        "values",
+        value_type.clone(),
+        true,
+    )]))
+}
+
+/// Splits batches into batches of keys and the dictionaries that have changed since the
+/// previous batch
+pub struct DictionaryEncoder {
+    schema: SchemaRef,
+    transport: SchemaRef,
+    sent: Vec<Option<ArrayDataRef>>,
+}
+
+impl DictionaryEncoder {
+    pub fn new(schema: SchemaRef) -> Self {
+        let transport = Arc::new(transport_schema(&schema));
+        let sent = vec![None; schema.fields().len()];
+        Self {
+            schema,
+            transport,
+            sent,
+        }
+    }
+
+    /// Whether the schema has any dictionary columns
+    pub fn has_dictionaries(&self) -> bool {
+        self.schema != self.transport
+    }
+
+    /// The schema of the batches of keys
+    pub fn transport_schema(&self) -> SchemaRef {
+        self.transport.clone()
+    }
+
+    /// Split a batch into its batch of keys and the values of each dictionary that changed,
+    /// by column index
+    pub fn encode(&mut self, batch: &RecordBatch) -> Result<(RecordBatch, Vec<(usize, ArrayRef)>)> {
+        if !self.has_dictionaries() {
+            return Ok((batch.clone(), vec![]));
+        }
+        let mut changed = vec![];
+        let mut columns = Vec::with_capacity(batch.num_columns());
+        for (i, column) in batch.columns().iter().enumerate() {
+            if let DataType::Dictionary(_, _) = column.data_type() {
+                let dictionary = column.data().child_data()[0].clone();
+                let unchanged = match &self.sent[i] {
+                    Some(sent) => Arc::ptr_eq(sent, &dictionary),
+                    None => false,
+                };
+                if !unchanged {
+                    changed.push((i, values(column)));
+                    self.sent[i] = Some(dictionary);
+                }
+                columns.push(keys(column));
+            } else {
+                columns.push(column.clone());
+            }
+        }
+        Ok((
+            RecordBatch::try_new(self.transport.clone(), columns)?,
+            changed,
+        ))
+    }
+
+    /// The Flight message with the schema of the stream. The schema of the message is the
+    /// transport schema, and the descriptor carries the full schema when it has
+    /// dictionary columns.
+    pub fn schema_flight_data(&self) -> Result<FlightData> {
+        let mut data = FlightData::from(self.transport.as_ref());
+        if self.has_dictionaries() {
+            let schema: protobuf::Schema = self.schema.as_ref().clone().try_into()?;
+            let mut cmd = vec![];
+            schema.encode(&mut cmd)?;
+            data.flight_descriptor = Some(FlightDescriptor {
+                r#type: DescriptorType::Cmd as i32,
+                cmd,
+                path: vec![],
+            });
+        }
+        Ok(data)
+    }
+}
+
+/// The Flight message with the values of the dictionary of a column
+pub fn dictionary_flight_data(column: usize, values: &ArrayRef) -> Result<FlightData> {
+    let batch = RecordBatch::try_new(values_schema(values.data_type()), vec![values.clone()])?;
+    let mut data = FlightData::from(&batch);
+    data.flight_descriptor = Some(FlightDescriptor {
+        r#type: DescriptorType::Path as i32,
+        cmd: vec![],
+        path: vec![DICTIONARY_PATH.to_owned(), column.to_string()],
+    });
+    Ok(data)
+}
+
+/// Rebuilds batches with dictionary columns from batches of keys and the dictionaries that
+/// were received before them
+pub struct DictionaryDecoder {
+    schema: SchemaRef,
+    transport: SchemaRef,
+    dictionaries: Vec<Option<ArrayRef>>,
+}
+
+impl DictionaryDecoder {
+    pub fn new(schema: SchemaRef) -> Self {
+        let transport = Arc::new(transport_schema(&schema));
+        let dictionaries = vec![None; schema.fields().len()];
+        Self {
+            schema,
+            transport,
+            dictionaries,
+        }
+    }
+
+    /// Create a decoder from the schema message of a Flight stream
+    pub fn from_flight_data(data: &FlightData) -> Result<Self> {
+        let transport = Schema::try_from(data)?;
+        let schema = match &data.flight_descriptor {
+            Some(descriptor) if !descriptor.cmd.is_empty() => {
+                let schema = protobuf::Schema::decode(&mut Cursor::new(&descriptor.cmd))?;
+                schema.try_into()?
+            }
+            _ => transport,
+        };
+        Ok(Self::new(Arc::new(schema)))
+    }
+
+    /// The schema of the decoded batches
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Use new values for the dictionary of a column
+    pub fn set_dictionary(&mut self, column: usize, values: ArrayRef) {
+        self.dictionaries[column] = Some(values);
+    }
+
+    /// Decode a Flight message, returning `None` for messages that carry a dictionary
+    pub fn decode_flight_data(&mut self, data: &FlightData) -> Result<Option<RecordBatch>> {
+        let path = data.flight_descriptor.as_ref().map(|d| d.path.as_slice());
+        if let Some([kind, column]) = path {
+            if kind == DICTIONARY_PATH {
+                let column = self.column_index(column)?;
+                let value_type = match self.schema.field(column).data_type() {
+                    DataType::Dictionary(_, value_type) => value_type.as_ref().clone(),
+                    _ => return Err(ballista_error("Dictionary for a column without one")),
+                };
+                // the unwrap is infallible and thus safe
+                let batch = flight_data_to_batch(data, values_schema(&value_type))?.unwrap();
+                self.set_dictionary(column, batch.column(0).clone());
+                return Ok(None);
+            }
+        }
+        // the unwrap is infallible and thus safe
+        let batch = flight_data_to_batch(data, self.transport.clone())?.unwrap();
+        self.decode(&batch).map(Some)
+    }
+
+    /// Rebuild a batch from a batch of keys
+    pub fn decode(&self, batch: &RecordBatch) -> Result<RecordBatch> {
+        if self.schema == self.transport {
+            return Ok(batch.clone());
+        }
+        let mut columns = Vec::with_capacity(batch.num_columns());
+        for (i, column) in batch.columns().iter().enumerate() {
+            match &self.dictionaries[i] {
+                Some(values) => columns.push(from_parts(column, values)),
+                None if column.data_type() == self.schema.field(i).data_type() => {
+                    columns.push(column.clone())
+                }
+                None => {
+                    return Err(ballista_error(&format!(
+                        "Missing dictionary for column {}",
+                        self.schema.field(i).name()
+                    )))
+                }
+            }
+        }
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+    }
+
+    fn column_index(&self, column: &str) -> Result<usize> {
+        column
+            .parse::<usize>()
+            .ok()
+            .filter(|column| *column < self.dictionaries.len())
+            .ok_or_else(|| ballista_error(&format!("Invalid dictionary column {}", column)))
+    }
+}
+
+/// Path of the sidecar file with the dictionaries of a column of an IPC file
+fn dictionary_path(path: &Path, column: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".dict-{}", column));
+    PathBuf::from(name)
+}
+
+/// Writes batches to an Arrow IPC file, with the dictionaries of each dictionary column in
+/// a sidecar file that has a batch for every batch of the main file. Empty batches in the
+/// sidecar files mean that the dictionary did not change.
+pub struct DictionaryFileWriter {
+    encoder: DictionaryEncoder,
+    writer: FileWriter<BufWriter<File>>,
+    dictionaries: Vec<(usize, FileWriter<BufWriter<File>>)>,
+}
+
+impl DictionaryFileWriter {
+    pub fn try_new(path: &Path, schema: SchemaRef) -> Result<Self> {
+        let encoder = DictionaryEncoder::new(schema.clone());
+        let writer = FileWriter::try_new(
+            BufWriter::new(File::create(path)?),
+            &encoder.transport_schema(),
+        )?;
+        let mut dictionaries = vec![];
+        for (i, field) in schema.fields().iter().enumerate() {
+            if let DataType::Dictionary(_, value_type) = field.data_type() {
+                let file = File::create(dictionary_path(path, i))?;
+                let writer = FileWriter::try_new(BufWriter::new(file), &values_schema(value_type))?;
+                dictionaries.push((i, writer));
+            }
+        }
+        Ok(Self {
+            encoder,
+            writer,
+            dictionaries,
+        })
+    }
+
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let (keys, changed) = self.encoder.encode(batch)?;
+        for (column, writer) in &mut self.dictionaries {
+            let values = changed
+                .iter()
+                .find(|(i, _)| i == column)
+                .map(|(_, values)| values.clone())
+                .unwrap_or_else(|| values(batch.column(*column)).slice(0, 0));
+            let schema = values_schema(values.data_type());
+            writer.write(&RecordBatch::try_new(schema, vec![values])?)?;
+        }
+        self.writer.write(&keys)?;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        for (_, writer) in &mut self.dictionaries {
+            writer.finish()?;
+        }
+        Ok(self.writer.finish()?)
+    }
+}
+
+/// Reads the batches of an Arrow IPC file that may have sidecar files with dictionaries
+pub struct DictionaryFileReader {
+    decoder: DictionaryDecoder,
+    reader: FileReader<BufReader<File>>,
+    dictionaries: Vec<(usize, FileReader<BufReader<File>>)>,
+}
+
+impl DictionaryFileReader {
+    pub fn try_new(path: &Path) -> Result<Self> {
+        let reader = FileReader::try_new(BufReader::new(File::open(path)?))?;
+        let transport = reader.schema();
+        let mut fields = Vec::with_capacity(transport.fields().len());
+        let mut dictionaries = vec![];
+        for (i, field) in transport.fields().iter().enumerate() {
+            let dictionary_path = dictionary_path(path, i);
+            if dictionary_path.exists() {
+                let dictionary = FileReader::try_new(BufReader::new(File::open(dictionary_path)?))?;
+                let value_type = dictionary.schema().field(0).data_type().clone();
+                fields.push(Field::new(
+                    field.name(),
+                    DataType::Dictionary(Box::new(field.data_type().clone()), Box::new(value_type)),
+                    field.is_nullable(),
+                ));
+                dictionaries.push((i, dictionary));
+            } else {
+                fields.push(field.clone());
+            }
+        }
+        Ok(Self {
+            decoder: DictionaryDecoder::new(Arc::new(Schema::new(fields))),
+            reader,
+            dictionaries,
+        })
+    }
+
+    fn read_batch(&mut self) -> Result<Option<RecordBatch>> {
+        for (column, reader) in &mut self.dictionaries {
+            match reader.next_batch()? {
+                Some(values) if values.num_rows() > 0 => {
+                    self.decoder
+                        .set_dictionary(*column, values.column(0).clone());
+                }
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+        match self.reader.next_batch()? {
+            Some(batch) => self.decoder.decode(&batch).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl RecordBatchReader for DictionaryFileReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.decoder.schema()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        self.read_batch()
+            .map_err(|e| ArrowError::IoError(format!("{:?}", e)))
+    }
+}
+
+/// Remove an IPC file and the sidecar files with its dictionaries
+pub fn remove_file(path: &Path) -> Result<()> {
+    // the sidecar files are found by name, since the file may not have been written fully
+    if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+        let prefix = format!("{}.dict-", name.to_string_lossy());
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(column: &ArrayRef) -> Vec<Option<String>> {
+        let values = values(column);
+        let values = values.as_any().downcast_ref::<StringArray>().unwrap();
+        key_indices(column)
+            .unwrap()
+            .into_iter()
+            .map(|key| key.map(|key| values.value(key).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn transport_dictionaries() -> Result<()> {
+        let plain: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("a"),
+            None,
+            Some("b"),
+            Some("a"),
+        ]));
+        let column = encode(&plain, &DataType::Int32)?;
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "c",
+            column.data_type().clone(),
+            true,
+        )]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![column.clone()])?;
+
+        let mut encoder = DictionaryEncoder::new(schema.clone());
+        let mut decoder = DictionaryDecoder::from_flight_data(&encoder.schema_flight_data()?)?;
+        assert_eq!(schema, decoder.schema());
+        for _ in 0..2 {
+            let (keys, changed) = encoder.encode(&batch)?;
+            for (i, values) in changed {
+                let data = dictionary_flight_data(i, &values)?;
+                assert!(decoder.decode_flight_data(&data)?.is_none());
+            }
+            let decoded = decoder
+                .decode_flight_data(&FlightData::from(&keys))?
+                .unwrap();
+            assert_eq!(strings(&column), strings(decoded.column(0)));
+        }
+
+        let taken = take(&column, &UInt32Array::from(vec![3, 2]))?;
+        let concatenated = concat(&[taken, encode(&plain, &DataType::Int32)?])?;
+        assert_eq!(
+            vec![Some("a"), Some("b"), Some("a"), None, Some("b"), Some("a")],
+            strings(&concatenated)
+                .iter()
+                .map(|s| s.as_deref())
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
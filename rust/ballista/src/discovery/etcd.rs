@@ -0,0 +1,256 @@
+//! Executor discovery with etcd.
+//!
+//! Each executor is stored as a key under a common prefix, attached to a lease that is
+//! kept alive when the executor renews its registration, so that the keys of executors
+//! that stop without deregistering are removed once the lease expires. Requests are sent
+//! to the JSON gateway of the etcd v3 API, trying each configured endpoint in turn.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::discovery::{Discovery, ExecutorRegistration};
+use crate::error::{ballista_error, Result};
+
+use serde_json::{json, Value};
+
+pub const ETCD_ENDPOINTS: &str = "ballista.discovery.etcd.endpoints";
+pub const ETCD_PREFIX: &str = "ballista.discovery.etcd.prefix";
+pub const ETCD_LEASE_TTL_SECONDS: &str = "ballista.discovery.etcd.leaseTtlSeconds";
+
+/// Connection settings for etcd
+#[derive(Debug, Clone, PartialEq)]
+pub struct EtcdConfig {
+    /// URLs of the etcd members, such as `http://localhost:2379`
+    pub endpoints: Vec<String>,
+    /// Prefix of the keys that executors are registered under
+    pub prefix: String,
+    /// Time after which the registration of an executor that stops renewing it expires
+    pub lease_ttl: Duration,
+}
+
+impl Default for EtcdConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: vec!["http://localhost:2379".to_owned()],
+            prefix: "/ballista/executors/".to_owned(),
+            lease_ttl: Duration::from_secs(30),
+        }
+    }
+}
+
+impl EtcdConfig {
+    /// Read the etcd configuration from the Context settings. Endpoints are separated by
+    /// commas.
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            endpoints: match settings.get(ETCD_ENDPOINTS) {
+                Some(endpoints) => endpoints
+                    .split(',')
+                    .map(|e| e.trim().trim_end_matches('/').to_owned())
+                    .filter(|e| !e.is_empty())
+                    .collect(),
+                None => default.endpoints,
+            },
+            prefix: settings.get(ETCD_PREFIX).cloned().unwrap_or(default.prefix),
+            lease_ttl: match settings.get(ETCD_LEASE_TTL_SECONDS) {
+                Some(n) => n.parse::<u64>().map(Duration::from_secs).map_err(|_| {
+                    ballista_error(&format!(
+                        "Invalid value for {}: {}",
+                        ETCD_LEASE_TTL_SECONDS, n
+                    ))
+                })?,
+                None => default.lease_ttl,
+            },
+        })
+    }
+}
+
+/// Discovery service backed by etcd
+#[derive(Debug)]
+pub struct EtcdDiscovery {
+    config: EtcdConfig,
+    client: reqwest::Client,
+    /// The leases of the executors registered by this process, by executor id
+    leases: Mutex<HashMap<String, String>>,
+}
+
+impl EtcdDiscovery {
+    pub fn new(config: EtcdConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            leases: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn key(&self, executor_id: &str) -> String {
+        format!("{}{}", self.config.prefix, executor_id)
+    }
+
+    /// Send a request to the first endpoint that responds
+    fn post(&self, path: &str, body: &Value) -> Result<Value> {
+        let mut error = ballista_error("No etcd endpoints are configured");
+        for endpoint in &self.config.endpoints {
+            let url = format!("{}{}", endpoint, path);
+            let response = self
+                .client
+                .post(&url)
+                .json(body)
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|mut response| response.json::<Value>());
+            match response {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    error = ballista_error(&format!("etcd request to {} failed: {:?}", url, e))
+                }
+            }
+        }
+        Err(error)
+    }
+
+    /// Grant a lease that expires after the configured TTL
+    fn grant_lease(&self) -> Result<String> {
+        let response = self.post(
+            "/v3/lease/grant",
+            &json!({ "TTL": self.config.lease_ttl.as_secs() }),
+        )?;
+        int64(&response["ID"]).ok_or_else(|| ballista_error("etcd did not grant a lease"))
+    }
+
+    /// Keep a lease alive, returning false if it has already expired
+    fn keep_alive(&self, lease: &str) -> Result<bool> {
+        let response = self.post("/v3/lease/keepalive", &json!({ "ID": lease }))?;
+        Ok(int64(&response["result"]["TTL"])
+            .map(|ttl| ttl != "0")
+            .unwrap_or(false))
+    }
+}
+
+impl Discovery for EtcdDiscovery {
+    fn register(&self, executor: &ExecutorRegistration) -> Result<()> {
+        let existing = self.leases.lock().unwrap().get(&executor.id).cloned();
+        let lease = match existing {
+            Some(lease) if self.keep_alive(&lease)? => lease,
+            _ => {
+                let lease = self.grant_lease()?;
+                self.leases
+                    .lock()
+                    .unwrap()
+                    .insert(executor.id.clone(), lease.clone());
+                lease
+            }
+        };
+        self.post(
+            "/v3/kv/put",
+            &json!({
+                "key": base64::encode(self.key(&executor.id)),
+                "value": base64::encode(executor.to_json()),
+                "lease": lease,
+            }),
+        )?;
+        Ok(())
+    }
+
+    fn deregister(&self, executor_id: &str) -> Result<()> {
+        let lease = self.leases.lock().unwrap().remove(executor_id);
+        self.post(
+            "/v3/kv/deleterange",
+            &json!({ "key": base64::encode(self.key(executor_id)) }),
+        )?;
+        if let Some(lease) = lease {
+            self.post("/v3/lease/revoke", &json!({ "ID": lease }))?;
+        }
+        Ok(())
+    }
+
+    fn executors(&self) -> Result<Vec<ExecutorRegistration>> {
+        let response = self.post(
+            "/v3/kv/range",
+            &json!({
+                "key": base64::encode(&self.config.prefix),
+                "range_end": base64::encode(prefix_end(&self.config.prefix)),
+            }),
+        )?;
+        let kvs = match response["kvs"].as_array() {
+            Some(kvs) => kvs,
+            // etcd omits empty fields
+            None => return Ok(vec![]),
+        };
+        kvs.iter()
+            .map(|kv| {
+                let value = kv["value"]
+                    .as_str()
+                    .and_then(|v| base64::decode(v).ok())
+                    .and_then(|v| String::from_utf8(v).ok())
+                    .ok_or_else(|| ballista_error("Invalid executor registration in etcd"))?;
+                ExecutorRegistration::from_json(&value)
+            })
+            .collect()
+    }
+
+    fn refresh_interval(&self) -> Duration {
+        self.config.lease_ttl / 3
+    }
+}
+
+/// The end of the range of keys that start with the prefix, which is the prefix with its
+/// last byte incremented
+fn prefix_end(prefix: &str) -> Vec<u8> {
+    let mut end = prefix.as_bytes().to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // every key is in the range
+    vec![0]
+}
+
+/// The gateway encodes 64-bit integers as strings
+fn int64(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etcd_settings() -> Result<()> {
+        let mut settings = HashMap::new();
+        assert_eq!(EtcdConfig::default(), EtcdConfig::from_settings(&settings)?);
+
+        settings.insert(
+            ETCD_ENDPOINTS.to_owned(),
+            "http://etcd-0:2379/, http://etcd-1:2379".to_owned(),
+        );
+        settings.insert(ETCD_LEASE_TTL_SECONDS.to_owned(), "9".to_owned());
+        let config = EtcdConfig::from_settings(&settings)?;
+        assert_eq!(
+            vec![
+                "http://etcd-0:2379".to_owned(),
+                "http://etcd-1:2379".to_owned()
+            ],
+            config.endpoints
+        );
+        let discovery = EtcdDiscovery::new(config);
+        assert_eq!(Duration::from_secs(3), discovery.refresh_interval());
+        assert_eq!("/ballista/executors/e1", discovery.key("e1"));
+        assert_eq!(
+            b"/ballista/executors0".to_vec(),
+            prefix_end("/ballista/executors/")
+        );
+
+        settings.insert(ETCD_LEASE_TTL_SECONDS.to_owned(), "soon".to_owned());
+        assert!(EtcdConfig::from_settings(&settings).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+//! Executor discovery with the Kubernetes API.
+//!
+//! Executors are the pods in a namespace that match a label selector. Kubernetes already
+//! tracks which pods are running, so executors do not register themselves, and only pods
+//! that are ready are listed. Clients running in the cluster connect to the pod IP
+//! addresses directly.
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
+use crate::client::DEFAULT_PORT;
+use crate::cluster;
+use crate::discovery::{Discovery, ExecutorRegistration};
+use crate::error::{ballista_error, Result};
+
+use chrono::Utc;
+use k8s_openapi::api::core::v1::{ListNamespacedPodOptional, ListNamespacedPodResponse, Pod};
+
+pub const KUBERNETES_NAMESPACE: &str = "ballista.discovery.kubernetes.namespace";
+pub const KUBERNETES_LABEL_SELECTOR: &str = "ballista.discovery.kubernetes.labelSelector";
+pub const KUBERNETES_PORT: &str = "ballista.discovery.kubernetes.port";
+
+/// Annotation on executor pods with the number of tasks that the executor can run at the
+/// same time
+pub const CAPACITY_ANNOTATION: &str = "ballista.io/capacity";
+
+/// Annotation on executor pods with the newest version of the plan format that the
+/// executor decodes. Pods without the annotation are sent unversioned plans.
+pub const PLAN_VERSION_ANNOTATION: &str = "ballista.io/plan-version";
+
+/// The namespace of the pod that the process runs in, when it runs in a cluster
+const SERVICE_ACCOUNT_NAMESPACE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// Settings for listing executor pods
+#[derive(Debug, Clone, PartialEq)]
+pub struct KubernetesConfig {
+    /// The namespace of the executor pods
+    pub namespace: String,
+    /// Label selector that matches the executor pods, such as `app=ballista-executor`
+    pub label_selector: String,
+    /// The port that executors listen on, or `None` to use the first port of the first
+    /// container in each pod
+    pub port: Option<usize>,
+}
+
+impl Default for KubernetesConfig {
+    fn default() -> Self {
+        Self {
+            // the namespace of this pod, when the process runs in the cluster
+            namespace: fs::read_to_string(SERVICE_ACCOUNT_NAMESPACE)
+                .map(|namespace| namespace.trim().to_owned())
+                .unwrap_or_else(|_| "default".to_owned()),
+            label_selector: "app=ballista-executor".to_owned(),
+            port: None,
+        }
+    }
+}
+
+impl KubernetesConfig {
+    /// Read the Kubernetes configuration from the Context settings
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            namespace: settings
+                .get(KUBERNETES_NAMESPACE)
+                .cloned()
+                .unwrap_or(default.namespace),
+            label_selector: settings
+                .get(KUBERNETES_LABEL_SELECTOR)
+                .cloned()
+                .unwrap_or(default.label_selector),
+            port: match settings.get(KUBERNETES_PORT) {
+                Some(n) => Some(n.parse::<usize>().map_err(|_| {
+                    ballista_error(&format!("Invalid value for {}: {}", KUBERNETES_PORT, n))
+                })?),
+                None => default.port,
+            },
+        })
+    }
+}
+
+/// Discovery service that lists executor pods
+#[derive(Debug)]
+pub struct KubernetesDiscovery {
+    config: KubernetesConfig,
+}
+
+impl KubernetesDiscovery {
+    pub fn new(config: KubernetesConfig) -> Self {
+        Self { config }
+    }
+
+    /// The executor running in a pod, or `None` if the pod is not ready to accept requests
+    fn executor(&self, pod: &Pod) -> Option<ExecutorRegistration> {
+        let metadata = pod.metadata.as_ref()?;
+        let status = pod.status.as_ref()?;
+        let ready = status
+            .phase
+            .as_ref()
+            .map(|p| p == "Running")
+            .unwrap_or(false)
+            && status
+                .conditions
+                .as_ref()?
+                .iter()
+                .any(|c| c.type_ == "Ready" && c.status == "True");
+        if !ready {
+            return None;
+        }
+
+        let port = self.config.port.unwrap_or_else(|| {
+            pod.spec
+                .as_ref()
+                .and_then(|spec| spec.containers.first())
+                .and_then(|container| container.ports.as_ref())
+                .and_then(|ports| ports.first())
+                .map(|port| port.container_port as usize)
+                .unwrap_or(DEFAULT_PORT as usize)
+        });
+        let capacity = metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(CAPACITY_ANNOTATION))
+            .and_then(|capacity| capacity.parse::<usize>().ok())
+            .unwrap_or(1);
+        let plan_version = metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(PLAN_VERSION_ANNOTATION))
+            .and_then(|version| version.parse::<u32>().ok())
+            .unwrap_or(0);
+        Some(ExecutorRegistration {
+            id: metadata.name.clone()?,
+            host: status.pod_ip.clone()?,
+            port,
+            capacity,
+            // the pod is known to be ready now
+            last_heartbeat: Utc::now().timestamp_millis(),
+            plan_version,
+        })
+    }
+}
+
+impl Discovery for KubernetesDiscovery {
+    /// Pods are tracked by Kubernetes, so executors do not need to register
+    fn register(&self, _executor: &ExecutorRegistration) -> Result<()> {
+        Ok(())
+    }
+
+    fn deregister(&self, _executor_id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn executors(&self) -> Result<Vec<ExecutorRegistration>> {
+        let (request, response_body) = Pod::list_namespaced_pod(
+            &self.config.namespace,
+            ListNamespacedPodOptional {
+                label_selector: Some(&self.config.label_selector),
+                ..Default::default()
+            },
+        )?;
+        match cluster::execute(request, response_body)? {
+            ListNamespacedPodResponse::Ok(pod_list) => Ok(pod_list
+                .items
+                .iter()
+                .filter_map(|pod| self.executor(pod))
+                .collect()),
+            other => Err(ballista_error(&format!(
+                "Unexpected response from Kubernetes API: {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(30)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kubernetes_settings() -> Result<()> {
+        let mut settings = HashMap::new();
+        settings.insert(KUBERNETES_NAMESPACE.to_owned(), "analytics".to_owned());
+        settings.insert(
+            KUBERNETES_LABEL_SELECTOR.to_owned(),
+            "ballista-cluster=nightly".to_owned(),
+        );
+        let config = KubernetesConfig::from_settings(&settings)?;
+        assert_eq!("analytics", config.namespace);
+        assert_eq!("ballista-cluster=nightly", config.label_selector);
+        assert_eq!(None, config.port);
+
+        settings.insert(KUBERNETES_PORT.to_owned(), "http".to_owned());
+        assert!(KubernetesConfig::from_settings(&settings).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! Discovery of the executors in a cluster.
+//!
+//! Executors register themselves with a discovery service when they start and renew the
+//! registration while they are running, and clients and schedulers list the executors that
+//! are currently registered instead of being configured with their addresses. The backend
+//! is selected with the `ballista.discovery.backend` setting, which is `etcd` or
+//! `kubernetes`.
+
+pub mod etcd;
+pub mod kubernetes;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::discovery::etcd::{EtcdConfig, EtcdDiscovery};
+use crate::discovery::kubernetes::{KubernetesConfig, KubernetesDiscovery};
+use crate::error::{ballista_error, Result};
+use crate::scheduler::ExecutorMeta;
+use crate::serde::{check_plan_version, PLAN_VERSION};
+
+use chrono::Utc;
+use serde_json::{json, Value};
+
+pub const DISCOVERY_BACKEND: &str = "ballista.discovery.backend";
+
+/// An executor as registered with a discovery service
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutorRegistration {
+    /// Identifies the executor within the cluster
+    pub id: String,
+    pub host: String,
+    pub port: usize,
+    /// Number of tasks that the executor can run at the same time
+    pub capacity: usize,
+    /// When the registration was last renewed, in milliseconds since the Unix epoch
+    pub last_heartbeat: i64,
+    /// Newest version of the plan format that the executor decodes, which is 0 for
+    /// executors that were released before plans were versioned
+    pub plan_version: u32,
+}
+
+impl ExecutorRegistration {
+    /// Create a registration for an executor that runs one task at a time, identified by
+    /// its address
+    pub fn new(host: &str, port: usize) -> Self {
+        Self {
+            id: format!("{}:{}", host, port),
+            host: host.to_owned(),
+            port,
+            capacity: 1,
+            last_heartbeat: Utc::now().timestamp_millis(),
+            plan_version: PLAN_VERSION,
+        }
+    }
+
+    /// Encode the registration as the JSON that is stored by discovery services
+    pub fn to_json(&self) -> String {
+        json!({
+            "id": self.id,
+            "host": self.host,
+            "port": self.port,
+            "capacity": self.capacity,
+            "last_heartbeat": self.last_heartbeat,
+            "plan_version": self.plan_version,
+        })
+        .to_string()
+    }
+
+    /// Decode a registration that was encoded with `to_json`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| ballista_error(&format!("Invalid executor registration: {:?}", e)))?;
+        let string = |name: &str| {
+            value[name].as_str().map(|s| s.to_owned()).ok_or_else(|| {
+                ballista_error(&format!("Executor registration has no {}: {}", name, json))
+            })
+        };
+        let number = |name: &str| {
+            value[name].as_i64().ok_or_else(|| {
+                ballista_error(&format!("Executor registration has no {}: {}", name, json))
+            })
+        };
+        Ok(Self {
+            id: string("id")?,
+            host: string("host")?,
+            port: number("port")? as usize,
+            capacity: number("capacity")? as usize,
+            last_heartbeat: number("last_heartbeat")?,
+            plan_version: value["plan_version"].as_u64().unwrap_or(0) as u32,
+        })
+    }
+
+    /// The version of the plan format that actions sent to the executor are encoded with,
+    /// or `None` if the executor decodes none of the versions that can be encoded
+    pub fn negotiate_plan_version(&self, version: u32) -> Option<u32> {
+        let version = version.min(self.plan_version);
+        check_plan_version(version).ok().map(|_| version)
+    }
+
+    /// The executor that the scheduler assigns tasks to
+    pub fn executor_meta(&self) -> ExecutorMeta {
+        ExecutorMeta {
+            id: self.id.clone(),
+            host: self.host.clone(),
+            port: self.port,
+        }
+    }
+}
+
+/// A service that executors register with and that lists the executors in a cluster
+pub trait Discovery: Send + Sync + fmt::Debug {
+    /// Register an executor, or renew its registration if it is already registered. The
+    /// registration expires unless it is renewed within the refresh interval.
+    fn register(&self, executor: &ExecutorRegistration) -> Result<()>;
+
+    /// Remove the registration of an executor that is stopping
+    fn deregister(&self, executor_id: &str) -> Result<()>;
+
+    /// The executors whose registrations have not expired
+    fn executors(&self) -> Result<Vec<ExecutorRegistration>>;
+
+    /// How often executors renew their registration
+    fn refresh_interval(&self) -> Duration;
+}
+
+/// Create the discovery service configured in the Context settings, or `None` if no
+/// backend is configured
+pub fn from_settings(settings: &HashMap<String, String>) -> Result<Option<Arc<dyn Discovery>>> {
+    match settings.get(DISCOVERY_BACKEND).map(|s| s.as_str()) {
+        None => Ok(None),
+        Some("etcd") => Ok(Some(Arc::new(EtcdDiscovery::new(
+            EtcdConfig::from_settings(settings)?,
+        )))),
+        Some("kubernetes") => Ok(Some(Arc::new(KubernetesDiscovery::new(
+            KubernetesConfig::from_settings(settings)?,
+        )))),
+        Some(other) => Err(ballista_error(&format!(
+            "Unknown discovery backend: {}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registration_json() -> Result<()> {
+        let mut registration = ExecutorRegistration::new("executor-1", 50051);
+        registration.capacity = 4;
+        assert_eq!("executor-1:50051", registration.id);
+        let json = registration.to_json();
+        assert_eq!(registration, ExecutorRegistration::from_json(&json)?);
+        assert!(ExecutorRegistration::from_json("{\"id\": \"e1\"}").is_err());
+
+        let mut settings = HashMap::new();
+        assert!(from_settings(&settings)?.is_none());
+        settings.insert(DISCOVERY_BACKEND.to_owned(), "zookeeper".to_owned());
+        assert!(from_settings(&settings).is_err());
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
-//! Ballista error types
+//! Ballista error types.
+//!
+//! Errors of the libraries that Ballista uses are wrapped rather than formatted, so that
+//! callers can match on them and follow them with `Error::source`. Errors can be given the
+//! context that they occurred in, such as the operator, file, or executor, which wraps them
+//! in turn, and `root_cause` finds the error below any context.
 
 use std::error::Error;
 use std::fmt::{Display, Formatter};
@@ -6,8 +11,12 @@ use std::io;
 use std::result;
 
 use crate::arrow::error::ArrowError;
+#[cfg(feature = "local-execution")]
 use crate::datafusion::error::ExecutionError;
 
+#[cfg(feature = "local-execution")]
+use parquet::errors::ParquetError;
+
 pub type Result<T> = result::Result<T, BallistaError>;
 
 /// Ballista error
@@ -16,19 +25,111 @@ pub enum BallistaError {
     NotImplemented(String),
     General(String),
     ArrowError(ArrowError),
+    #[cfg(feature = "local-execution")]
     DataFusionError(ExecutionError),
     IoError(io::Error),
     ReqwestError(reqwest::Error),
     HttpError(http::Error),
     KubeAPIRequestError(k8s_openapi::RequestError),
     KubeAPIResponseError(k8s_openapi::ResponseError),
-    // TonicError(tonic::status::Status)
+    /// The query was cancelled
+    Cancelled,
+    /// Error status returned by an executor
+    TonicError(tonic::Status),
+    /// A plan was encoded with a version of the plan format that is not supported
+    #[cfg(feature = "plan")]
+    UnsupportedPlanVersion(u32),
+    #[cfg(feature = "local-execution")]
+    ParquetError(ParquetError),
+    JsonError(serde_json::Error),
+    ProtobufEncodeError(prost::EncodeError),
+    ProtobufDecodeError(prost::DecodeError),
+    /// An error executing an operator, with the plan fragment rooted at the operator
+    Operator {
+        operator: String,
+        plan: Option<String>,
+        source: Box<BallistaError>,
+    },
+    /// An error reading or writing a file
+    File {
+        path: String,
+        source: Box<BallistaError>,
+    },
+    /// An error returned by an executor, or in connecting to it
+    Executor {
+        executor: String,
+        source: Box<BallistaError>,
+    },
 }
 
 pub fn ballista_error(message: &str) -> BallistaError {
     BallistaError::General(message.to_owned())
 }
 
+impl BallistaError {
+    /// Give the error the context of the operator that it occurred in. Errors that already
+    /// have the context of an operator keep it, since it is the operator closest to the
+    /// cause.
+    pub fn in_operator(self, operator: &str, plan: Option<String>) -> Self {
+        if self.operator().is_some() {
+            return self;
+        }
+        BallistaError::Operator {
+            operator: operator.to_owned(),
+            plan,
+            source: Box::new(self),
+        }
+    }
+
+    /// Give the error the context of the file that was being read or written
+    pub fn in_file(self, path: &str) -> Self {
+        BallistaError::File {
+            path: path.to_owned(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Give the error the context of the executor that returned it
+    pub fn on_executor(self, executor: &str) -> Self {
+        BallistaError::Executor {
+            executor: executor.to_owned(),
+            source: Box::new(self),
+        }
+    }
+
+    /// The error below any context that it has been given
+    pub fn root_cause(&self) -> &BallistaError {
+        match self {
+            BallistaError::Operator { source, .. }
+            | BallistaError::File { source, .. }
+            | BallistaError::Executor { source, .. } => source.root_cause(),
+            e => e,
+        }
+    }
+
+    /// The operator that the error occurred in and the plan fragment rooted at it, if the
+    /// error has been given the context of an operator
+    pub fn operator(&self) -> Option<(&str, Option<&str>)> {
+        match self {
+            BallistaError::Operator { operator, plan, .. } => {
+                Some((operator, plan.as_ref().map(|p| p.as_str())))
+            }
+            BallistaError::File { source, .. } | BallistaError::Executor { source, .. } => {
+                source.operator()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the error is caused by the query being cancelled
+    pub fn is_cancelled(&self) -> bool {
+        match self.root_cause() {
+            BallistaError::Cancelled => true,
+            _ => false,
+        }
+    }
+}
+
 impl From<String> for BallistaError {
     fn from(e: String) -> Self {
         BallistaError::General(e)
@@ -41,12 +142,26 @@ impl From<ArrowError> for BallistaError {
     }
 }
 
+#[cfg(feature = "local-execution")]
 impl From<ExecutionError> for BallistaError {
     fn from(e: ExecutionError) -> Self {
         BallistaError::DataFusionError(e)
     }
 }
 
+/// Errors of plans that are translated to DataFusion plans, which keep their message
+#[cfg(feature = "local-execution")]
+impl From<BallistaError> for ExecutionError {
+    fn from(e: BallistaError) -> Self {
+        match e {
+            BallistaError::DataFusionError(e) => e,
+            BallistaError::General(message) => ExecutionError::General(message),
+            BallistaError::NotImplemented(message) => ExecutionError::NotImplemented(message),
+            other => ExecutionError::General(format!("{:?}", other)),
+        }
+    }
+}
+
 impl From<io::Error> for BallistaError {
     fn from(e: io::Error) -> Self {
         BallistaError::IoError(e)
@@ -77,11 +192,36 @@ impl From<k8s_openapi::ResponseError> for BallistaError {
     }
 }
 
-// impl From<tonic::status::Status> for BallistaError {
-//     fn from(e: tonic::status::Status) -> Self {
-//         BallistaError::TonicError(e)
-//     }
-// }
+impl From<tonic::Status> for BallistaError {
+    fn from(e: tonic::Status) -> Self {
+        BallistaError::TonicError(e)
+    }
+}
+
+#[cfg(feature = "local-execution")]
+impl From<ParquetError> for BallistaError {
+    fn from(e: ParquetError) -> Self {
+        BallistaError::ParquetError(e)
+    }
+}
+
+impl From<serde_json::Error> for BallistaError {
+    fn from(e: serde_json::Error) -> Self {
+        BallistaError::JsonError(e)
+    }
+}
+
+impl From<prost::EncodeError> for BallistaError {
+    fn from(e: prost::EncodeError) -> Self {
+        BallistaError::ProtobufEncodeError(e)
+    }
+}
+
+impl From<prost::DecodeError> for BallistaError {
+    fn from(e: prost::DecodeError) -> Self {
+        BallistaError::ProtobufDecodeError(e)
+    }
+}
 
 impl Display for BallistaError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -89,6 +229,7 @@ impl Display for BallistaError {
             BallistaError::NotImplemented(ref desc) => write!(f, "Not implemented: {}", desc),
             BallistaError::General(ref desc) => write!(f, "General error: {}", desc),
             BallistaError::ArrowError(ref desc) => write!(f, "Arrow error: {}", desc),
+            #[cfg(feature = "local-execution")]
             BallistaError::DataFusionError(ref desc) => write!(f, "DataFusion error: {:?}", desc),
             BallistaError::IoError(ref desc) => write!(f, "IO error: {}", desc),
             BallistaError::ReqwestError(ref desc) => write!(f, "Reqwest error: {}", desc),
@@ -99,8 +240,104 @@ impl Display for BallistaError {
             BallistaError::KubeAPIResponseError(ref desc) => {
                 write!(f, "KubeAPI response error: {}", desc)
             }
+            BallistaError::Cancelled => write!(f, "Query was cancelled"),
+            BallistaError::TonicError(ref desc) => write!(f, "Executor error: {}", desc),
+            #[cfg(feature = "plan")]
+            BallistaError::UnsupportedPlanVersion(version) => write!(
+                f,
+                "Plan version {} is not supported, versions {} to {} are supported",
+                version,
+                crate::serde::MIN_PLAN_VERSION,
+                crate::serde::PLAN_VERSION
+            ),
+            #[cfg(feature = "local-execution")]
+            BallistaError::ParquetError(ref desc) => write!(f, "Parquet error: {}", desc),
+            BallistaError::JsonError(ref desc) => write!(f, "JSON error: {}", desc),
+            BallistaError::ProtobufEncodeError(ref desc) => {
+                write!(f, "Protobuf encode error: {}", desc)
+            }
+            BallistaError::ProtobufDecodeError(ref desc) => {
+                write!(f, "Protobuf decode error: {}", desc)
+            }
+            BallistaError::Operator {
+                operator,
+                plan,
+                source,
+            } => {
+                write!(f, "{} failed: {}", operator, source)?;
+                match plan {
+                    Some(plan) => write!(f, "\nin plan:\n{}", plan.trim_end()),
+                    None => Ok(()),
+                }
+            }
+            BallistaError::File { path, source } => write!(f, "{}: {}", path, source),
+            BallistaError::Executor { executor, source } => {
+                write!(f, "Executor {}: {}", executor, source)
+            }
+        }
+    }
+}
+
+impl Error for BallistaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BallistaError::ArrowError(e) => Some(e),
+            // DataFusion errors are followed to the errors that they wrap
+            #[cfg(feature = "local-execution")]
+            BallistaError::DataFusionError(ExecutionError::ArrowError(e)) => Some(e),
+            #[cfg(feature = "local-execution")]
+            BallistaError::DataFusionError(ExecutionError::IoError(e)) => Some(e),
+            BallistaError::IoError(e) => Some(e),
+            BallistaError::ReqwestError(e) => Some(e),
+            BallistaError::HttpError(e) => Some(e),
+            BallistaError::KubeAPIRequestError(e) => Some(e),
+            BallistaError::KubeAPIResponseError(e) => Some(e),
+            BallistaError::TonicError(e) => Some(e),
+            #[cfg(feature = "local-execution")]
+            BallistaError::ParquetError(e) => Some(e),
+            BallistaError::JsonError(e) => Some(e),
+            BallistaError::ProtobufEncodeError(e) => Some(e),
+            BallistaError::ProtobufDecodeError(e) => Some(e),
+            BallistaError::Operator { source, .. }
+            | BallistaError::File { source, .. }
+            | BallistaError::Executor { source, .. } => Some(source.as_ref()),
+            _ => None,
         }
     }
 }
 
-impl Error for BallistaError {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_the_context_of_an_error() {
+        let io = io::Error::new(io::ErrorKind::NotFound, "missing");
+        let e = BallistaError::from(io)
+            .in_file("/data/a.csv")
+            .in_operator("CsvScan", Some("CsvScan: partitions=1\n".to_owned()))
+            .in_operator("Projection", None)
+            .on_executor("localhost:50051");
+        assert_eq!(
+            Some(("CsvScan", Some("CsvScan: partitions=1\n"))),
+            e.operator()
+        );
+        match e.root_cause() {
+            BallistaError::IoError(e) => assert_eq!(io::ErrorKind::NotFound, e.kind()),
+            other => panic!("unexpected cause {:?}", other),
+        }
+        let mut depth = 0;
+        let mut source = e.source();
+        while let Some(e) = source {
+            depth += 1;
+            source = e.source();
+        }
+        assert_eq!(4, depth);
+        assert_eq!(
+            "Executor localhost:50051: CsvScan failed: /data/a.csv: IO error: missing\n\
+             in plan:\nCsvScan: partitions=1",
+            e.to_string()
+        );
+        assert!(!e.is_cancelled());
+    }
+}
@@ -0,0 +1,234 @@
+//! Uploading client-side tables to an executor with Flight `DoExchange`.
+//!
+//! The batches of a `MemoryScan` are serialized into the plan, so they are limited by the
+//! size of a single message and are sent again every time the plan is. An exchange instead
+//! streams named tables to the executor along with the query that reads them, so that a
+//! small lookup table can be joined against the datasets of a cluster in one request.
+//!
+//! The first message of an exchange only has a descriptor whose command is the serialized
+//! action. Each uploaded table follows as a schema message that carries the name of the
+//! table as its `app_metadata`, followed by the dictionary and batch messages of its
+//! batches, and the client then closes its side of the stream. The executor scans the
+//! uploaded tables wherever the plan has a `TableScan` of their names, and streams the
+//! results back in the same form as `DoGet`.
+
+use std::collections::HashMap;
+
+use crate::arrow::datatypes::SchemaRef;
+use crate::arrow::record_batch::RecordBatch;
+use crate::dictionary::{dictionary_flight_data, DictionaryDecoder, DictionaryEncoder};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::logicalplan::LogicalPlan;
+
+use flight::{flight_descriptor::DescriptorType, FlightData, FlightDescriptor};
+
+/// A table uploaded with an exchange
+#[derive(Debug, Clone)]
+pub struct UploadedTable {
+    pub name: String,
+    pub schema: SchemaRef,
+    pub batches: Vec<RecordBatch>,
+}
+
+impl UploadedTable {
+    pub fn new(name: &str, schema: SchemaRef, batches: Vec<RecordBatch>) -> Self {
+        Self {
+            name: name.to_owned(),
+            schema,
+            batches,
+        }
+    }
+}
+
+/// The messages that the client sends for an exchange
+pub fn exchange_messages(action: &[u8], tables: &[UploadedTable]) -> Result<Vec<FlightData>> {
+    let mut messages = vec![FlightData {
+        flight_descriptor: Some(FlightDescriptor {
+            r#type: DescriptorType::Cmd as i32,
+            cmd: action.to_vec(),
+            path: vec![],
+        }),
+        ..Default::default()
+    }];
+    for table in tables {
+        let mut encoder = DictionaryEncoder::new(table.schema.clone());
+        let mut schema = encoder.schema_flight_data()?;
+        schema.app_metadata = table.name.as_bytes().to_vec();
+        messages.push(schema);
+        for batch in &table.batches {
+            let (keys, changed) = encoder.encode(batch)?;
+            for (column, values) in &changed {
+                messages.push(dictionary_flight_data(*column, values)?);
+            }
+            messages.push(FlightData::from(&keys));
+        }
+    }
+    Ok(messages)
+}
+
+/// The serialized action of the first message of an exchange
+pub fn exchange_action(data: &FlightData) -> Result<&[u8]> {
+    match &data.flight_descriptor {
+        Some(descriptor) if descriptor.r#type == DescriptorType::Cmd as i32 => Ok(&descriptor.cmd),
+        _ => Err(ballista_error(
+            "The first message of an exchange must describe the action",
+        )),
+    }
+}
+
+/// Receives the tables that are uploaded with an exchange
+#[derive(Default)]
+pub struct UploadReceiver {
+    tables: Vec<UploadedTable>,
+    decoder: Option<DictionaryDecoder>,
+}
+
+impl UploadReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive a message that follows the action
+    pub fn receive(&mut self, data: &FlightData) -> Result<()> {
+        if !data.app_metadata.is_empty() {
+            let name = String::from_utf8(data.app_metadata.clone())
+                .map_err(|_| ballista_error("Uploaded table names must be UTF-8"))?;
+            if self.tables.iter().any(|table| table.name == name) {
+                return Err(BallistaError::General(format!(
+                    "Table {} was uploaded more than once",
+                    name
+                )));
+            }
+            let decoder = DictionaryDecoder::from_flight_data(data)?;
+            self.tables
+                .push(UploadedTable::new(&name, decoder.schema(), vec![]));
+            self.decoder = Some(decoder);
+            return Ok(());
+        }
+        let decoder = self
+            .decoder
+            .as_mut()
+            .ok_or_else(|| ballista_error("Batches must follow the schema of their table"))?;
+        if let Some(batch) = decoder.decode_flight_data(data)? {
+            // the unwrap is safe because a table is pushed with each decoder
+            self.tables.last_mut().unwrap().batches.push(batch);
+        }
+        Ok(())
+    }
+
+    /// The uploaded tables, by name
+    pub fn finish(self) -> HashMap<String, UploadedTable> {
+        self.tables
+            .into_iter()
+            .map(|table| (table.name.clone(), table))
+            .collect()
+    }
+}
+
+/// Replace the scans of uploaded tables in a plan with scans of their batches. The schema of
+/// an uploaded table must be the schema that the plan was built with.
+pub fn scan_uploaded_tables(
+    plan: &LogicalPlan,
+    tables: &HashMap<String, UploadedTable>,
+) -> Result<LogicalPlan> {
+    if tables.is_empty() {
+        return Ok(plan.clone());
+    }
+    plan.transform_up(|plan| match &plan {
+        LogicalPlan::TableScan {
+            table_name,
+            schema,
+            projection,
+            projected_schema,
+        } => match tables.get(table_name) {
+            Some(table) if table.schema.as_ref() != schema => Err(BallistaError::General(format!(
+                "Uploaded table {} has a different schema",
+                table_name
+            ))),
+            Some(table) => {
+                let batches = table
+                    .batches
+                    .iter()
+                    .map(|batch| match projection {
+                        Some(projection) => {
+                            let columns = projection
+                                .iter()
+                                .map(|i| batch.column(*i).clone())
+                                .collect();
+                            let schema = SchemaRef::new(projected_schema.clone());
+                            Ok(RecordBatch::try_new(schema, columns)?)
+                        }
+                        None => Ok(batch.clone()),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(if batches.is_empty() {
+                    LogicalPlan::EmptyRelation {
+                        schema: projected_schema.clone(),
+                    }
+                } else {
+                    LogicalPlan::MemoryScan(batches)
+                })
+            }
+            None => Ok(plan),
+        },
+        _ => Ok(plan),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{Int32Array, StringArray};
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    #[test]
+    fn upload_and_scan_tables() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec!["a", "b"])),
+            ],
+        )?;
+        let tables = vec![
+            UploadedTable::new("lookup", schema.clone(), vec![batch]),
+            UploadedTable::new("empty", schema.clone(), vec![]),
+        ];
+        let messages = exchange_messages(b"action", &tables)?;
+        assert_eq!(exchange_action(&messages[0])?, b"action");
+
+        let mut receiver = UploadReceiver::new();
+        for message in &messages[1..] {
+            receiver.receive(message)?;
+        }
+        let uploaded = receiver.finish();
+        assert_eq!(uploaded["lookup"].batches[0].num_rows(), 2);
+
+        let scan = |table_name: &str| LogicalPlan::TableScan {
+            table_name: table_name.to_owned(),
+            schema: schema.as_ref().clone(),
+            projection: Some(vec![1]),
+            projected_schema: Schema::new(vec![schema.field(1).clone()]),
+        };
+        match scan_uploaded_tables(&scan("lookup"), &uploaded)? {
+            LogicalPlan::MemoryScan(batches) => {
+                assert_eq!(batches[0].num_columns(), 1);
+                assert_eq!(batches[0].schema().field(0).name(), "name");
+            }
+            other => panic!("Unexpected plan: {:?}", other),
+        }
+        match scan_uploaded_tables(&scan("empty"), &uploaded)? {
+            LogicalPlan::EmptyRelation { .. } => {}
+            other => panic!("Unexpected plan: {:?}", other),
+        }
+        match scan_uploaded_tables(&scan("other"), &uploaded)? {
+            LogicalPlan::TableScan { .. } => Ok(()),
+            other => panic!("Unexpected plan: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! Runtime metrics of the operators of a query.
+//!
+//! While a query runs, the rows that each operator produces, the time spent in it, the
+//! bytes that it reads and the number of times that it spills data to disk are recorded in
+//! a `MetricsCollector`. Most operators are executed by DataFusion in a single pipeline for
+//! each partition, so they are measured together as an `Execute` operator for each
+//! partition. Scans, joins, and shuffle reads and writes have metrics of their own, as do
+//! aggregates that are executed with a memory budget, which report how many times they
+//! spilled their groups to disk. The other operators hold their data in memory, and the
+//! memory of the batches that the measured operators produce is recorded with them.
+//!
+//! Clients ask executors for the metrics of a query with request metadata, and executors
+//! send them in a message after the last batch of the results. The scheduler collects the
+//! metrics of the tasks of distributed queries, labelled with the stage that ran them. The
+//! CPU time of partitions is only measured for queries that are profiled (see `profile`).
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "local-execution")]
+use std::time::Instant;
+
+#[cfg(feature = "local-execution")]
+use crate::arrow::datatypes::SchemaRef;
+#[cfg(feature = "local-execution")]
+use crate::arrow::error::Result as ArrowResult;
+#[cfg(feature = "local-execution")]
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+#[cfg(feature = "local-execution")]
+use crate::datafusion::error::Result as DataFusionResult;
+#[cfg(feature = "local-execution")]
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::error::{ballista_error, Result};
+#[cfg(feature = "local-execution")]
+use crate::memory::batch_memory_size;
+#[cfg(feature = "local-execution")]
+use crate::profile::thread_cpu_time;
+
+use flight::FlightData;
+use serde_json::{json, Value};
+use tonic::metadata::MetadataMap;
+use tonic::Request;
+
+/// Name of the operator that measures the execution of a partition of a physical plan
+pub const EXECUTE_OPERATOR: &str = "Execute";
+
+const METRICS_METADATA: &str = "x-ballista-metrics";
+
+/// Runtime metrics of an operator
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OperatorMetrics {
+    pub operator: String,
+    /// The stage of a distributed query that the operator ran in
+    pub stage: Option<usize>,
+    /// The partition that the operator ran for, for operators that run for each partition
+    pub partition: Option<usize>,
+    /// Rows produced by the operator
+    pub rows: u64,
+    /// Time spent in the operator, including the time spent in its inputs
+    pub elapsed: Duration,
+    /// Bytes read from files, from memory, or from other executors
+    pub bytes_read: u64,
+    /// Number of times the operator wrote data to disk because it did not fit in memory
+    pub spills: u64,
+    /// Bytes of memory held by the batches that the operator produced
+    pub memory: u64,
+    /// Batches produced by the operator
+    pub batches: u64,
+    /// Time spent producing the slowest batch
+    pub slowest_batch: Duration,
+    /// CPU time of the threads that ran the operator, which is only measured when the
+    /// query is profiled
+    pub cpu_time: Duration,
+}
+
+impl OperatorMetrics {
+    pub fn new(operator: &str) -> Self {
+        Self {
+            operator: operator.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "operator": self.operator,
+            "stage": self.stage,
+            "partition": self.partition,
+            "rows": self.rows,
+            "elapsed_ns": self.elapsed.as_nanos() as u64,
+            "bytes_read": self.bytes_read,
+            "spills": self.spills,
+            "memory_bytes": self.memory,
+            "batches": self.batches,
+            "slowest_batch_ns": self.slowest_batch.as_nanos() as u64,
+            "cpu_ns": self.cpu_time.as_nanos() as u64,
+        })
+    }
+
+    fn from_json(value: &Value) -> Result<Self> {
+        let number = |name: &str| {
+            value[name].as_u64().ok_or_else(|| {
+                ballista_error(&format!("Operator metrics have no {}: {}", name, value))
+            })
+        };
+        Ok(Self {
+            operator: value["operator"]
+                .as_str()
+                .ok_or_else(|| {
+                    ballista_error(&format!("Operator metrics have no name: {}", value))
+                })?
+                .to_owned(),
+            stage: value["stage"].as_u64().map(|n| n as usize),
+            partition: value["partition"].as_u64().map(|n| n as usize),
+            rows: number("rows")?,
+            elapsed: Duration::from_nanos(number("elapsed_ns")?),
+            bytes_read: number("bytes_read")?,
+            spills: number("spills")?,
+            // executors that predate profiling or memory metrics do not send these
+            memory: value["memory_bytes"].as_u64().unwrap_or(0),
+            batches: value["batches"].as_u64().unwrap_or(0),
+            slowest_batch: Duration::from_nanos(value["slowest_batch_ns"].as_u64().unwrap_or(0)),
+            cpu_time: Duration::from_nanos(value["cpu_ns"].as_u64().unwrap_or(0)),
+        })
+    }
+}
+
+/// The metrics of the operators of a query
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueryMetrics {
+    pub operators: Vec<OperatorMetrics>,
+}
+
+impl QueryMetrics {
+    /// The metrics of an operator summed across its partitions and stages
+    pub fn total(&self, operator: &str) -> OperatorMetrics {
+        let mut total = OperatorMetrics::new(operator);
+        for metrics in self.operators.iter().filter(|m| m.operator == operator) {
+            total.rows += metrics.rows;
+            total.elapsed += metrics.elapsed;
+            total.bytes_read += metrics.bytes_read;
+            total.spills += metrics.spills;
+            total.memory += metrics.memory;
+            total.batches += metrics.batches;
+            total.slowest_batch = total.slowest_batch.max(metrics.slowest_batch);
+            total.cpu_time += metrics.cpu_time;
+        }
+        total
+    }
+
+    /// The stages that the operators ran in, where `None` is for operators that did not
+    /// run in a stage of a distributed query
+    pub fn stages(&self) -> Vec<Option<usize>> {
+        let mut stages: Vec<Option<usize>> = self.operators.iter().map(|m| m.stage).collect();
+        stages.sort();
+        stages.dedup();
+        stages
+    }
+
+    /// The metrics of the operators that ran in a stage
+    pub fn stage(&self, stage: Option<usize>) -> QueryMetrics {
+        QueryMetrics {
+            operators: self
+                .operators
+                .iter()
+                .filter(|m| m.stage == stage)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Label the metrics as the metrics of a stage of a distributed query
+    pub fn with_stage(mut self, stage: usize) -> Self {
+        for metrics in &mut self.operators {
+            metrics.stage = Some(stage);
+        }
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        let operators: Vec<Value> = self.operators.iter().map(|m| m.to_json()).collect();
+        json!({ "operators": operators }).to_string()
+    }
+
+    /// Decode metrics that were encoded with `to_json`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| ballista_error(&format!("Invalid query metrics: {:?}", e)))?;
+        let operators = value["operators"]
+            .as_array()
+            .ok_or_else(|| ballista_error(&format!("Query metrics have no operators: {}", json)))?
+            .iter()
+            .map(OperatorMetrics::from_json)
+            .collect::<Result<_>>()?;
+        Ok(Self { operators })
+    }
+
+    /// Encode the metrics as the message that executors send after the results of a query
+    pub fn to_flight_data(&self) -> FlightData {
+        FlightData {
+            app_metadata: self.to_json().into_bytes(),
+            ..FlightData::default()
+        }
+    }
+
+    /// Decode the metrics sent after the results of a query, returning `None` for other
+    /// messages. Batch messages always have a header, while the metrics message does not.
+    pub fn from_flight_data(data: &FlightData) -> Option<Result<Self>> {
+        if data.data_header.is_empty() && !data.app_metadata.is_empty() {
+            Some(Self::from_json(&String::from_utf8_lossy(
+                &data.app_metadata,
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for QueryMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for metrics in &self.operators {
+            write!(f, "{}", metrics.operator)?;
+            if let Some(stage) = metrics.stage {
+                write!(f, " stage={}", stage)?;
+            }
+            if let Some(partition) = metrics.partition {
+                write!(f, " partition={}", partition)?;
+            }
+            writeln!(
+                f,
+                ": rows={} elapsed={:?} bytes_read={} spills={}",
+                metrics.rows, metrics.elapsed, metrics.bytes_read, metrics.spills
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the metrics of the operators of a query as they run
+#[derive(Debug, Clone, Default)]
+pub struct MetricsCollector {
+    operators: Arc<Mutex<Vec<OperatorMetrics>>>,
+    /// Whether the CPU time of partitions is measured
+    profile: bool,
+}
+
+impl MetricsCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect metrics that also measure the CPU time spent producing each batch
+    pub fn profiling() -> Self {
+        Self {
+            profile: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_profiling(&self) -> bool {
+        self.profile
+    }
+
+    pub fn record(&self, metrics: OperatorMetrics) {
+        self.operators.lock().unwrap().push(metrics);
+    }
+
+    /// Record the metrics of operators that ran elsewhere, such as in the tasks of a
+    /// distributed query
+    pub fn extend(&self, metrics: QueryMetrics) {
+        self.operators.lock().unwrap().extend(metrics.operators);
+    }
+
+    /// The metrics recorded so far
+    pub fn metrics(&self) -> QueryMetrics {
+        QueryMetrics {
+            operators: self.operators.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Measure the execution of each partition, recording the metrics when the partition is
+/// exhausted or its reader is dropped
+#[cfg(feature = "local-execution")]
+pub fn measure_partitions(
+    partitions: Vec<Arc<dyn Partition>>,
+    collector: &MetricsCollector,
+) -> Vec<Arc<dyn Partition>> {
+    partitions
+        .into_iter()
+        .enumerate()
+        .map(|(i, partition)| {
+            Arc::new(MeasuredPartition {
+                partition,
+                index: i,
+                collector: collector.clone(),
+            }) as Arc<dyn Partition>
+        })
+        .collect()
+}
+
+#[cfg(feature = "local-execution")]
+struct MeasuredPartition {
+    partition: Arc<dyn Partition>,
+    index: usize,
+    collector: MetricsCollector,
+}
+
+#[cfg(feature = "local-execution")]
+impl fmt::Debug for MeasuredPartition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MeasuredPartition")
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+#[cfg(feature = "local-execution")]
+impl Partition for MeasuredPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let start = Instant::now();
+        let reader = self.partition.execute()?;
+        let mut metrics = OperatorMetrics::new(EXECUTE_OPERATOR);
+        metrics.partition = Some(self.index);
+        metrics.elapsed = start.elapsed();
+        Ok(Arc::new(Mutex::new(MeasuredReader {
+            reader,
+            metrics: Some(metrics),
+            collector: self.collector.clone(),
+        })))
+    }
+}
+
+/// Counts the rows produced by a partition and the time spent producing them, which does
+/// not include the time that the consumer of the batches spends
+#[cfg(feature = "local-execution")]
+struct MeasuredReader {
+    reader: Arc<Mutex<dyn RecordBatchReader + Send + Sync>>,
+    /// The metrics of the partition, until they are recorded
+    metrics: Option<OperatorMetrics>,
+    collector: MetricsCollector,
+}
+
+#[cfg(feature = "local-execution")]
+impl MeasuredReader {
+    fn finish(&mut self) {
+        if let Some(metrics) = self.metrics.take() {
+            self.collector.record(metrics);
+        }
+    }
+}
+
+#[cfg(feature = "local-execution")]
+impl RecordBatchReader for MeasuredReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.reader.lock().unwrap().schema()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        let start = Instant::now();
+        let cpu_start = if self.collector.profile {
+            thread_cpu_time()
+        } else {
+            None
+        };
+        let batch = self.reader.lock().unwrap().next_batch()?;
+        if let Some(metrics) = &mut self.metrics {
+            let elapsed = start.elapsed();
+            metrics.elapsed += elapsed;
+            if let (Some(cpu_start), Some(cpu_end)) = (cpu_start, thread_cpu_time()) {
+                metrics.cpu_time += cpu_end.checked_sub(cpu_start).unwrap_or_default();
+            }
+            if let Some(batch) = &batch {
+                metrics.rows += batch.num_rows() as u64;
+                metrics.memory += batch_memory_size(batch) as u64;
+                metrics.batches += 1;
+                metrics.slowest_batch = metrics.slowest_batch.max(elapsed);
+            }
+        }
+        if batch.is_none() {
+            self.finish();
+        }
+        Ok(batch)
+    }
+}
+
+#[cfg(feature = "local-execution")]
+impl Drop for MeasuredReader {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Ask an executor to send the metrics of a query after its results
+pub fn request_metrics<T>(request: &mut Request<T>) {
+    request
+        .metadata_mut()
+        .insert(METRICS_METADATA, "true".parse().unwrap());
+}
+
+/// Whether a client asked for the metrics of a query
+pub fn metrics_requested(metadata: &MetadataMap) -> bool {
+    metadata
+        .get(METRICS_METADATA)
+        .map(|v| v.to_str() == Ok("true"))
+        .unwrap_or(false)
+}
+
+#[cfg(all(test, feature = "local-execution"))]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::datafusion::execution::physical_plan::memory::MemoryExec;
+    use crate::datafusion::execution::physical_plan::ExecutionPlan;
+
+    #[test]
+    fn measure_partition_execution() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+        let exec = MemoryExec::try_new(
+            &vec![vec![batch.clone(), batch.clone()], vec![batch]],
+            schema,
+            None,
+        )?;
+
+        let collector = MetricsCollector::new();
+        for partition in measure_partitions(exec.partitions()?, &collector) {
+            let reader = partition.execute()?;
+            let mut reader = reader.lock().unwrap();
+            while reader.next_batch()?.is_some() {}
+        }
+        let mut scan = OperatorMetrics::new("MemoryScan");
+        scan.bytes_read = 100;
+        collector.record(scan);
+
+        let metrics = collector.metrics().with_stage(1);
+        assert_eq!(3, metrics.operators.len());
+        assert_eq!(vec![Some(1)], metrics.stages());
+        assert!(metrics.stage(None).operators.is_empty());
+        assert_eq!(9, metrics.total(EXECUTE_OPERATOR).rows);
+        assert_eq!(3, metrics.total(EXECUTE_OPERATOR).batches);
+        assert_eq!(Some(1), metrics.operators[0].stage);
+        assert_eq!(100, metrics.total("MemoryScan").bytes_read);
+
+        let data = metrics.to_flight_data();
+        assert_eq!(metrics, QueryMetrics::from_flight_data(&data).unwrap()?);
+        assert!(QueryMetrics::from_flight_data(&FlightData::default()).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,1764 @@
+//! Executor that runs Ballista plans received over Arrow Flight.
+//!
+//! Clients submit serialized actions with `DoAction` and fetch the results
+//! with `DoGet`, or upload tables and run a query that reads them with `DoExchange`, as
+//! described in the `exchange` module. The server can be embedded in other applications,
+//! and in tests, by running `Server::serve` on a tokio runtime.
+//!
+//! Tasks of distributed queries write their output as shuffle partitions instead of
+//! returning it. Executors fetch the shuffle partitions that a task reads from the
+//! executors that wrote them into local files before the task is planned. Queries that
+//! write Parquet files write their output directly to the object store and return a
+//! manifest of the file.
+//!
+//! Each query reserves memory from the executor's memory tracker before it is executed. When
+//! the executor has a memory limit, queries wait for running queries to release memory and
+//! are rejected if it is not released in time, so that the scheduler can run them elsewhere.
+//!
+//! Planning and execution are recorded in `tracing` spans that carry the id of the query
+//! sent by the client and the job and stage of tasks.
+//!
+//! Executors keep metrics of the queries that they run, which can be scraped by Prometheus
+//! from the HTTP address configured with `Server::with_http_addr`, which also serves the
+//! status of the executor as JSON.
+//!
+//! A service with a scheduler accepts queries in the same way, but runs them across the
+//! executors registered with the scheduler.
+//!
+//! Executors also serve the Arrow Flight SQL protocol, so that Flight SQL drivers can run
+//! SQL queries and read the metadata of the registered tables, as described in the
+//! `flight_sql` module.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::arrow::datatypes::SchemaRef;
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::auth::{authenticate, Credentials};
+use crate::cancel::CancellationToken;
+use crate::client::{self, ClientConfig, ConnectionPool};
+use crate::compression::BatchCompression;
+use crate::datafusion::error::ExecutionError;
+use crate::datafusion::execution::context::ExecutionContext;
+use crate::datafusion::execution::physical_plan::memory::MemoryExec;
+use crate::datafusion::execution::physical_plan::{ExecutionPlan, Partition};
+use crate::datasource::file_schema;
+use crate::datasource::memory::receive_pinned;
+use crate::datasource::object_store::{self, LocalFileSystem, ObjectStoreRegistry};
+use crate::datasource::table::{table_names_to_batch, TableRegistry};
+use crate::datasource::write::{output_file_path, write_parquet_partitions, WriteManifest};
+use crate::dictionary::{dictionary_flight_data, DictionaryDecoder, DictionaryEncoder};
+use crate::discovery::{Discovery, ExecutorRegistration};
+use crate::error::BallistaError;
+use crate::exchange::{self, scan_uploaded_tables, UploadReceiver};
+use crate::execution_metrics::{
+    measure_partitions, metrics_requested, MetricsCollector, OperatorMetrics, EXECUTE_OPERATOR,
+};
+use crate::flight_sql;
+use crate::history::{self, QueryHistory, QueryRecord, QueryState};
+use crate::http::{self, HttpResponse};
+use crate::json;
+use crate::logicalplan::LogicalPlan;
+use crate::memory::{batch_memory_size, MemoryTracker, QueryMemory};
+use crate::metrics::{self, ExecutorMetrics};
+use crate::physical_plan::shuffle::write_partitions;
+use crate::physical_plan::{self, PhysicalPlanner};
+use crate::plan;
+use crate::pool::ExecutionPool;
+use crate::profile::profile_requested;
+use crate::result_cache::{PlanKey, ResultCache, ResultCollector};
+use crate::scheduler::queues::requested_queue;
+use crate::scheduler::Scheduler;
+use crate::serde::{decode_protobuf, PLAN_VERSION};
+use crate::shuffle::{
+    self, Partitioning, ShuffleDirs, ShufflePartitionId, ShuffleSummary, ShuffleWriter,
+};
+use crate::status::{self, DiskUsage, ExecutorStatusReport};
+use crate::stream::{partition_end_flight_data, partitions_requested};
+use crate::tls::{self, TlsConfig};
+use crate::trace;
+use crate::BALLISTA_VERSION;
+
+use chrono::Utc;
+use flight::{
+    flight_service_server::FlightService, flight_service_server::FlightServiceServer, Action,
+    ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PutResult, SchemaResult, Ticket,
+};
+use futures::executor::block_on;
+use futures::{future, Stream};
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc;
+use tonic::{Code, Request, Response, Status, Streaming};
+use tracing::Span;
+use tracing_futures::Instrument;
+
+pub use crate::client::DEFAULT_PORT;
+
+/// Executor server
+pub struct Server {
+    addr: SocketAddr,
+    service: BallistaFlightService,
+    tls: Option<TlsConfig>,
+    discovery: Option<(Arc<dyn Discovery>, ExecutorRegistration)>,
+    http_addr: Option<SocketAddr>,
+}
+
+impl Server {
+    /// Create a server that listens on the given address
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            service: BallistaFlightService::default(),
+            tls: None,
+            discovery: None,
+            http_addr: None,
+        }
+    }
+
+    /// Create a server that uses a custom service, such as one with custom tables
+    pub fn with_service(addr: SocketAddr, service: BallistaFlightService) -> Self {
+        Self {
+            addr,
+            service,
+            tls: None,
+            discovery: None,
+            http_addr: None,
+        }
+    }
+
+    /// Accept TLS connections using the given certificate, and require client certificates
+    /// signed by the CA if one is configured. The same settings are used when fetching
+    /// shuffle partitions from other executors.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.service.client_config.tls = Some(tls.clone());
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Require requests to be authenticated with one of the given credentials. The first
+    /// credentials are used when fetching shuffle partitions from other executors.
+    pub fn with_credentials(mut self, credentials: Vec<Credentials>) -> Self {
+        self.service.client_config.credentials = credentials.first().cloned();
+        self.service.credentials = Arc::new(credentials);
+        self
+    }
+
+    /// Write shuffle partitions to the given directory instead of the default directory
+    pub fn with_shuffle_dir(self, shuffle_dir: PathBuf) -> Self {
+        self.with_shuffle_dirs(vec![shuffle_dir])
+    }
+
+    /// Spread shuffle partitions across the given directories, such as a directory on each
+    /// disk of the executor
+    pub fn with_shuffle_dirs(mut self, shuffle_dirs: Vec<PathBuf>) -> Self {
+        self.service.shuffle_dirs = Arc::new(ShuffleDirs::new(shuffle_dirs));
+        self
+    }
+
+    /// Limit the memory that the queries running on the executor can reserve
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.service.memory = Arc::new(MemoryTracker::new(Some(bytes)));
+        self
+    }
+
+    /// The memory that each query reserves for its operators, in addition to the memory
+    /// needed for the batches in its plan
+    pub fn with_task_memory(mut self, bytes: usize) -> Self {
+        self.service.task_memory = bytes;
+        self
+    }
+
+    /// Run at most the given number of tasks at the same time, instead of one for each
+    /// CPU. Further tasks wait for a running task to complete.
+    pub fn with_task_slots(mut self, slots: usize) -> Self {
+        self.service.pool = Arc::new(ExecutionPool::new(slots));
+        self
+    }
+
+    /// The number of tasks that the executor runs at the same time, which it should
+    /// register as its capacity
+    pub fn task_slots(&self) -> usize {
+        self.service.pool.threads()
+    }
+
+    /// Keep the history of the queries run by the executor in the given history instead of
+    /// the default in-memory history
+    pub fn with_query_history(mut self, history: QueryHistory) -> Self {
+        self.service.history = Arc::new(history);
+        self
+    }
+
+    /// Cache up to `max_bytes` of the results of queries, so that identical queries over
+    /// files that have not changed are answered without executing them again. Cached
+    /// results expire after the time to live if one is given.
+    pub fn with_result_cache(mut self, max_bytes: usize, ttl: Option<Duration>) -> Self {
+        self.service.results = Arc::new(ResultCache::new(max_bytes, ttl));
+        self
+    }
+
+    /// Serve HTTP requests on the given address, with the metrics of the executor in the
+    /// Prometheus text format from `/metrics`, its status as JSON from `/status`, the
+    /// history of its queries as JSON from `/queries`, and health checks from `/health`
+    pub fn with_http_addr(mut self, addr: SocketAddr) -> Self {
+        self.http_addr = Some(addr);
+        self
+    }
+
+    /// Register the executor with a discovery service while the server is running, so that
+    /// clients and schedulers can find it
+    pub fn with_discovery(
+        mut self,
+        discovery: Arc<dyn Discovery>,
+        registration: ExecutorRegistration,
+    ) -> Self {
+        self.discovery = Some((discovery, registration));
+        self
+    }
+
+    /// The address that the server listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Serve requests until the server fails
+    pub async fn serve(self) -> Result<(), BallistaError> {
+        // the executor is registered before serving so that a misconfigured discovery
+        // service is reported, and the registration is renewed until the server stops
+        let stopped = CancellationToken::new();
+        if let Some((discovery, registration)) = &self.discovery {
+            register(discovery.clone(), registration.clone()).await?;
+            tokio::spawn(renew_registration(
+                discovery.clone(),
+                registration.clone(),
+                stopped.clone(),
+            ));
+        }
+
+        if let Some(addr) = self.http_addr {
+            let service = self.service.clone();
+            tokio::spawn(async move {
+                let served = http::serve(addr, move |path| match path {
+                    "/metrics" => HttpResponse::ok(
+                        metrics::PROMETHEUS_CONTENT_TYPE,
+                        service.metrics.render(&service.memory),
+                    ),
+                    "/status" => HttpResponse::ok("application/json", service.status().to_json()),
+                    "/queries" => HttpResponse::ok(
+                        "application/json",
+                        history::records_to_json(&service.history.records()),
+                    ),
+                    "/health" => HttpResponse::ok("text/plain", "OK\n".to_owned()),
+                    _ => HttpResponse::not_found(
+                        "Executors serve /metrics, /status, /queries and /health\n".to_owned(),
+                    ),
+                });
+                if let Err(e) = served.await {
+                    error!("HTTP server failed: {:?}", e);
+                }
+            });
+        }
+
+        let result = tls::server(self.tls.as_ref())?
+            .add_service(FlightServiceServer::new(self.service))
+            .serve(self.addr)
+            .await
+            .map_err(|e| BallistaError::General(format!("Executor failed: {:?}", e)));
+
+        stopped.cancel();
+        if let Some((discovery, registration)) = self.discovery {
+            let executor_id = registration.id;
+            let _ = tokio::task::spawn_blocking(move || discovery.deregister(&executor_id)).await;
+        }
+        result
+    }
+}
+
+/// Register an executor with a discovery service without blocking the runtime
+async fn register(
+    discovery: Arc<dyn Discovery>,
+    registration: ExecutorRegistration,
+) -> Result<(), BallistaError> {
+    tokio::task::spawn_blocking(move || discovery.register(&registration))
+        .await
+        .map_err(|e| BallistaError::General(format!("Registration failed: {:?}", e)))?
+}
+
+/// Renew the registration of an executor at the refresh interval of the discovery service
+/// until the token is cancelled. Failures are retried at the next interval.
+async fn renew_registration(
+    discovery: Arc<dyn Discovery>,
+    mut registration: ExecutorRegistration,
+    stopped: CancellationToken,
+) {
+    loop {
+        tokio::time::delay_for(discovery.refresh_interval()).await;
+        if stopped.is_cancelled() {
+            return;
+        }
+        registration.last_heartbeat = Utc::now().timestamp_millis();
+        if let Err(e) = register(discovery.clone(), registration.clone()).await {
+            warn!("Unable to renew executor registration: {:?}", e);
+        }
+    }
+}
+
+/// Number of batches that are buffered for each `DoGet` stream before execution waits for
+/// the client to receive them
+const STREAM_BUFFER_BATCHES: usize = 4;
+
+/// Memory reserved for the operators of each query unless configured with
+/// `Server::with_task_memory`
+pub const DEFAULT_TASK_MEMORY: usize = 64 * 1024 * 1024;
+
+/// How long queries wait for memory to be released before they are rejected
+const MEMORY_QUEUE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A planned query, which is executed when its results are fetched
+struct PlannedQuery {
+    /// The schema of the results sent to the client
+    schema: SchemaRef,
+    partitions: Vec<Arc<dyn Partition>>,
+    /// Where the output is written for tasks whose output is shuffled, in which case the
+    /// client receives the number of rows in each shuffle partition
+    shuffle: Option<ShuffleOutput>,
+    /// Where the output is written for queries that write Parquet files, in which case the
+    /// client receives the manifest of the file
+    parquet: Option<ParquetOutput>,
+    /// The memory reserved while the query is executed, in bytes
+    memory: usize,
+    /// The memory of the batches that were read into memory while the query was planned,
+    /// which is released when the query is dropped
+    inputs: QueryMemory,
+    /// Bytes of the local files and in-memory batches that the query scans
+    scanned_bytes: u64,
+    /// The span that the query is planned and executed in
+    span: Span,
+    /// The metrics of the operators of the query, which are sent to the client after the
+    /// results when it asks for them
+    operators: MetricsCollector,
+    /// The record of the query that is added to the history when it ends
+    record: QueryRecord,
+    /// When the query was received
+    received: Instant,
+    /// The key that the results are cached by once the query completes, for queries whose
+    /// results can be cached and were not already
+    cache_key: Option<PlanKey>,
+}
+
+struct ShuffleOutput {
+    job_id: String,
+    stage_id: usize,
+    map_partition: usize,
+    partitioning: Partitioning,
+    /// The schema of the output
+    schema: SchemaRef,
+}
+
+struct ParquetOutput {
+    /// The path of the file
+    path: String,
+    /// The schema of the output
+    schema: SchemaRef,
+}
+
+/// Submitted actions, keyed by the ticket used to fetch their results
+type Queries = HashMap<Vec<u8>, PlannedQuery>;
+
+/// Flight service that executes Ballista plans with DataFusion
+#[derive(Clone)]
+pub struct BallistaFlightService {
+    queries: Arc<Mutex<Queries>>,
+    running: Arc<Mutex<HashMap<Vec<u8>, CancellationToken>>>,
+    next_ticket: Arc<AtomicUsize>,
+    object_stores: Arc<ObjectStoreRegistry>,
+    tables: Arc<TableRegistry>,
+    credentials: Arc<Vec<Credentials>>,
+    shuffle_dirs: Arc<ShuffleDirs>,
+    /// Used to give the shuffle partitions fetched for each task their own directory
+    next_fetch: Arc<AtomicUsize>,
+    /// Connections to other executors, for fetching shuffle partitions
+    connections: Arc<ConnectionPool>,
+    client_config: ClientConfig,
+    /// Scheduler that runs queries across the executors of a cluster, when the service
+    /// runs the scheduler rather than executing queries itself
+    scheduler: Option<Arc<Scheduler>>,
+    memory: Arc<MemoryTracker>,
+    task_memory: usize,
+    /// Threads that execute tasks, one for each task slot. Shuffle partitions are served
+    /// outside of the pool, so that serving them never waits for a slot.
+    pool: Arc<ExecutionPool>,
+    metrics: Arc<ExecutorMetrics>,
+    /// When the service was created, in milliseconds since the Unix epoch
+    started: i64,
+    history: Arc<QueryHistory>,
+    /// Results of earlier queries, which are only cached when the cache is enabled
+    results: Arc<ResultCache>,
+}
+
+impl BallistaFlightService {
+    /// Create a service that reads remote paths through the given object stores and can
+    /// scan the given custom tables
+    pub fn new(object_stores: Arc<ObjectStoreRegistry>, tables: Arc<TableRegistry>) -> Self {
+        Self {
+            queries: Arc::new(Mutex::new(HashMap::new())),
+            running: Arc::new(Mutex::new(HashMap::new())),
+            next_ticket: Arc::new(AtomicUsize::new(0)),
+            object_stores,
+            tables,
+            credentials: Arc::new(vec![]),
+            shuffle_dirs: Arc::new(ShuffleDirs::default()),
+            next_fetch: Arc::new(AtomicUsize::new(0)),
+            connections: Arc::new(ConnectionPool::new()),
+            client_config: ClientConfig::default(),
+            scheduler: None,
+            memory: Arc::new(MemoryTracker::default()),
+            task_memory: DEFAULT_TASK_MEMORY,
+            pool: Arc::new(ExecutionPool::from_settings(&HashMap::new())),
+            metrics: Arc::new(ExecutorMetrics::new()),
+            started: Utc::now().timestamp_millis(),
+            history: Arc::new(QueryHistory::default()),
+            results: Arc::new(ResultCache::default()),
+        }
+    }
+
+    /// Run the queries that clients submit across the executors registered with the
+    /// scheduler, instead of executing them in this process
+    pub fn with_scheduler(mut self, scheduler: Arc<Scheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Record the queries run by the service in the given history
+    pub fn with_query_history(mut self, history: QueryHistory) -> Self {
+        self.history = Arc::new(history);
+        self
+    }
+
+    /// The metrics of the queries run by the service
+    pub fn metrics(&self) -> &ExecutorMetrics {
+        &self.metrics
+    }
+
+    /// The queries that have run on the service, oldest first
+    pub fn query_history(&self) -> Vec<QueryRecord> {
+        self.history.records()
+    }
+
+    /// The status of the executor. The shuffle and cache directories are read to find the
+    /// data that is stored in them, so this should not be called from async code.
+    pub fn status(&self) -> ExecutorStatusReport {
+        let (shuffle_jobs, shuffle_data) = status::shuffle_usage(&self.shuffle_dirs);
+        ExecutorStatusReport {
+            version: BALLISTA_VERSION.to_owned(),
+            started: self.started,
+            running_tasks: self.running.lock().unwrap().len(),
+            submitted_queries: self.queries.lock().unwrap().len(),
+            memory: self.memory.usage(),
+            shuffle_jobs,
+            shuffle_data,
+            object_cache: DiskUsage::of_dir(&object_store::cache_dir()),
+            plan_version: PLAN_VERSION,
+        }
+    }
+
+    /// Plan an action in its span, returning the result schema and the partitions to
+    /// execute, which record their metrics in the collector
+    fn plan(
+        &self,
+        action: &plan::Action,
+        fetched: &HashMap<ShufflePartitionId, PathBuf>,
+        span: Span,
+        operators: MetricsCollector,
+        record: QueryRecord,
+        received: Instant,
+    ) -> Result<PlannedQuery, Status> {
+        let _enter = span.enter();
+        match action {
+            plan::Action::Collect { plan } => {
+                let (schema, partitions, inputs) = self.plan_query(plan, fetched, &operators)?;
+                Ok(PlannedQuery {
+                    schema,
+                    partitions,
+                    shuffle: None,
+                    parquet: None,
+                    memory: self.query_memory(plan),
+                    inputs,
+                    scanned_bytes: scanned_bytes(plan),
+                    span: span.clone(),
+                    operators: operators.clone(),
+                    record: record.clone(),
+                    received,
+                    cache_key: None,
+                })
+            }
+            plan::Action::ShuffleWrite {
+                job_id,
+                stage_id,
+                partition,
+                plan,
+                partitioning,
+            } => {
+                let (schema, partitions, inputs) = self.plan_query(plan, fetched, &operators)?;
+                Ok(PlannedQuery {
+                    schema: Arc::new(shuffle::summary_schema()),
+                    partitions,
+                    shuffle: Some(ShuffleOutput {
+                        job_id: job_id.clone(),
+                        stage_id: *stage_id,
+                        map_partition: *partition,
+                        partitioning: partitioning.clone(),
+                        schema,
+                    }),
+                    parquet: None,
+                    memory: self.query_memory(plan),
+                    inputs,
+                    scanned_bytes: scanned_bytes(plan),
+                    span: span.clone(),
+                    operators: operators.clone(),
+                    record: record.clone(),
+                    received,
+                    cache_key: None,
+                })
+            }
+            plan::Action::WriteParquet {
+                plan,
+                path,
+                partition,
+            } => {
+                let (schema, partitions, inputs) = self.plan_query(plan, fetched, &operators)?;
+                Ok(PlannedQuery {
+                    schema: Arc::new(WriteManifest::schema()),
+                    partitions,
+                    shuffle: None,
+                    parquet: Some(ParquetOutput {
+                        path: output_file_path(path, *partition),
+                        schema,
+                    }),
+                    memory: self.query_memory(plan),
+                    inputs,
+                    scanned_bytes: scanned_bytes(plan),
+                    span: span.clone(),
+                    operators: operators.clone(),
+                    record: record.clone(),
+                    received,
+                    cache_key: None,
+                })
+            }
+            other => Err(Status::invalid_argument(format!(
+                "Invalid Ballista action: {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// The memory that a query reserves, which is the memory for its operators and the
+    /// batches that it scans from memory
+    fn query_memory(&self, plan: &LogicalPlan) -> usize {
+        fn scanned(plan: &LogicalPlan) -> usize {
+            match plan {
+                LogicalPlan::MemoryScan(batches) => batches.iter().map(batch_memory_size).sum(),
+                _ => plan.inputs().into_iter().map(scanned).sum(),
+            }
+        }
+        self.task_memory + scanned(plan)
+    }
+
+    fn plan_query(
+        &self,
+        logical_plan: &LogicalPlan,
+        fetched: &HashMap<ShufflePartitionId, PathBuf>,
+        operators: &MetricsCollector,
+    ) -> Result<(SchemaRef, Vec<Arc<dyn Partition>>, QueryMemory), Status> {
+        debug!("Logical plan: {:?}", logical_plan);
+
+        // create local execution context
+        let mut ctx = ExecutionContext::new();
+        self.tables.register_with(&mut ctx);
+
+        let inputs = QueryMemory::new(self.memory.clone());
+        let physical_plan = PhysicalPlanner::new(&self.object_stores, operators)
+            .with_memory(&inputs)
+            .with_fetched_shuffle(fetched)
+            .with_batch_size(1024 * 1024)
+            .create_physical_plan(&mut ctx, logical_plan)
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        debug!(
+            "Physical plan:\n{}",
+            physical_plan::format_plan(physical_plan.as_ref())
+        );
+
+        let partitions = physical_plan::partitions(&physical_plan);
+
+        let partitions = measure_partitions(trace::trace_partitions(partitions), operators);
+        Ok((physical_plan.schema(), partitions, inputs))
+    }
+
+    /// Execute a planned query, converting each row of its results to a JSON object
+    async fn collect_json(
+        &self,
+        planned: PlannedQuery,
+        ticket: Vec<u8>,
+    ) -> Result<Vec<flight::Result>, Status> {
+        let mut rx = self
+            .execute(
+                planned,
+                ticket,
+                BatchCompression::Uncompressed,
+                false,
+                false,
+            )
+            .await?;
+        let schema = rx
+            .recv()
+            .await
+            .ok_or_else(|| Status::internal("The results have no schema"))??;
+        let mut decoder = DictionaryDecoder::from_flight_data(&schema)
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        let mut results = vec![];
+        while let Some(data) = rx.recv().await {
+            let batch = decoder
+                .decode_flight_data(&data?)
+                .map_err(|e| Status::internal(format!("{:?}", e)))?;
+            if let Some(batch) = batch {
+                let rows = json::batch_to_json(&batch)
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                results.extend(rows.into_iter().map(|row| flight::Result {
+                    body: row.to_string().into_bytes(),
+                }));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Plan a Flight SQL command against the registered tables
+    fn flight_sql_plan(&self, command: &flight_sql::Command) -> Result<LogicalPlan, Status> {
+        command
+            .plan(&self.tables)
+            .map_err(|e| Status::invalid_argument(format!("{:?}", e)))
+    }
+
+    /// Perform the job management actions of a scheduler, returning `None` for other actions
+    fn job_action(&self, action: &Action) -> Result<Option<Vec<flight::Result>>, Status> {
+        let action_type = action.r#type.as_str();
+        if ![
+            plan::LIST_JOBS_ACTION_TYPE,
+            plan::JOB_STATUS_ACTION_TYPE,
+            plan::CANCEL_JOB_ACTION_TYPE,
+        ]
+        .contains(&action_type)
+        {
+            return Ok(None);
+        }
+        let scheduler = self
+            .scheduler
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Jobs are only managed by schedulers"))?;
+        let job_id = || {
+            let body = String::from_utf8_lossy(&action.body);
+            body.parse::<usize>()
+                .map_err(|_| Status::invalid_argument(format!("Invalid job id: {}", body)))
+        };
+
+        let jobs = match action_type {
+            plan::LIST_JOBS_ACTION_TYPE => scheduler.jobs(),
+            plan::JOB_STATUS_ACTION_TYPE => {
+                let job_id = job_id()?;
+                let job = scheduler
+                    .job(job_id)
+                    .ok_or_else(|| Status::not_found(format!("No such job: {}", job_id)))?;
+                vec![job]
+            }
+            _ => {
+                let job_id = job_id()?;
+                info!("do_action: cancel job {}", job_id);
+                if !scheduler.cancel_job(job_id) {
+                    return Err(Status::failed_precondition(format!(
+                        "Job {} is not running",
+                        job_id
+                    )));
+                }
+                vec![]
+            }
+        };
+        Ok(Some(
+            jobs.iter()
+                .map(|job| flight::Result {
+                    body: job.to_json().into_bytes(),
+                })
+                .collect(),
+        ))
+    }
+
+    /// Prepare an action to be planned. When the service runs a scheduler, queries are
+    /// executed across the cluster and the results are returned from memory, and otherwise
+    /// the shuffle partitions that the action reads are fetched from other executors,
+    /// returning the local files that they were fetched into. The metrics of the tasks and
+    /// of the fetches are recorded in the collector.
+    async fn prepare(
+        &self,
+        action: plan::Action,
+        queue: &str,
+        operators: &MetricsCollector,
+    ) -> Result<(plan::Action, HashMap<ShufflePartitionId, PathBuf>), Status> {
+        match (&self.scheduler, action) {
+            (Some(scheduler), plan::Action::Collect { plan }) => {
+                let batches = scheduler
+                    .execute_with_metrics(&plan, queue, operators)
+                    .await
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                let plan = if batches.is_empty() {
+                    LogicalPlan::EmptyRelation {
+                        schema: plan.schema().clone(),
+                    }
+                } else {
+                    LogicalPlan::MemoryScan(batches)
+                };
+                Ok((plan::Action::Collect { plan }, HashMap::new()))
+            }
+            (Some(scheduler), plan::Action::WriteParquet { plan, path, .. }) => {
+                let manifest = scheduler
+                    .write_parquet_with_metrics(&plan, &path, queue, operators)
+                    .await
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                let batch = manifest
+                    .to_batch()
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                let plan = LogicalPlan::MemoryScan(vec![batch]);
+                Ok((plan::Action::Collect { plan }, HashMap::new()))
+            }
+            (_, plan::Action::FetchSchema { path, file_type }) => {
+                let schema = if file_type == "table" {
+                    self.tables
+                        .get(&path)
+                        .map(|table| table.schema().as_ref().clone())
+                } else {
+                    let object_stores = self.object_stores.clone();
+                    tokio::task::spawn_blocking(move || {
+                        file_schema(&path, &file_type, &object_stores)
+                    })
+                    .await
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?
+                }
+                .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                let plan = LogicalPlan::EmptyRelation { schema };
+                Ok((plan::Action::Collect { plan }, HashMap::new()))
+            }
+            (_, plan::Action::ListTables) => {
+                let batch = table_names_to_batch(&self.tables.names())
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                let plan = LogicalPlan::MemoryScan(vec![batch]);
+                Ok((plan::Action::Collect { plan }, HashMap::new()))
+            }
+            (_, action) => self.fetch_shuffle_reads(action, operators).await,
+        }
+    }
+
+    /// Plan the results of a distributed query with a partition for the results of each task
+    /// of its last stage
+    fn plan_partitions(
+        &self,
+        plan: &LogicalPlan,
+        partitions: Vec<Vec<RecordBatch>>,
+        span: Span,
+        operators: MetricsCollector,
+        record: QueryRecord,
+        received: Instant,
+    ) -> Result<PlannedQuery, Status> {
+        let _enter = span.enter();
+        let schema = match partitions.iter().flatten().next() {
+            Some(batch) => batch.schema(),
+            None => Arc::new(plan.schema().clone()),
+        };
+        let size: usize = partitions.iter().flatten().map(batch_memory_size).sum();
+        let partitions = MemoryExec::try_new(&partitions, schema.clone(), None)
+            .and_then(|exec| exec.partitions())
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        let partitions = measure_partitions(trace::trace_partitions(partitions), &operators);
+        Ok(PlannedQuery {
+            schema,
+            partitions,
+            shuffle: None,
+            parquet: None,
+            memory: self.task_memory + size,
+            inputs: QueryMemory::new(self.memory.clone()),
+            scanned_bytes: size as u64,
+            span: span.clone(),
+            operators,
+            record,
+            received,
+            cache_key: None,
+        })
+    }
+
+    /// Prepare and plan an action in the span of the action, recording the query in the
+    /// history if it fails before it is executed. The partitions of the results of
+    /// distributed queries are kept when `partitioned` is set.
+    async fn prepare_and_plan(
+        &self,
+        action: plan::Action,
+        queue: &str,
+        query_id: Option<&str>,
+        profile: bool,
+        partitioned: bool,
+    ) -> Result<PlannedQuery, Status> {
+        let received = Instant::now();
+        let record = QueryRecord::new(query_id, &action);
+        let span = trace::action_span(&action, query_id);
+        let operators = if profile {
+            MetricsCollector::profiling()
+        } else {
+            MetricsCollector::new()
+        };
+        // cached results are scanned from memory instead of executing the query again. The
+        // cache does not keep the partitions of the results.
+        let cache_key = match &action {
+            plan::Action::Collect { plan } if !partitioned => {
+                self.results.key(plan, &self.object_stores)
+            }
+            _ => None,
+        };
+        let cached = match (&action, &cache_key) {
+            (plan::Action::Collect { plan }, Some(key)) => self.results.get(key).map(|batches| {
+                if batches.is_empty() {
+                    LogicalPlan::EmptyRelation {
+                        schema: plan.schema().clone(),
+                    }
+                } else {
+                    LogicalPlan::MemoryScan(batches)
+                }
+            }),
+            _ => None,
+        };
+        let planned = match (&self.scheduler, action) {
+            (Some(scheduler), plan::Action::Collect { plan }) if partitioned => {
+                let partitions = scheduler
+                    .execute_partitioned_with_metrics(&plan, queue, &operators)
+                    .instrument(span.clone())
+                    .await
+                    .map_err(|e| Status::internal(format!("{:?}", e)));
+                partitions.and_then(|partitions| {
+                    self.plan_partitions(
+                        &plan,
+                        partitions,
+                        span,
+                        operators,
+                        record.clone(),
+                        received,
+                    )
+                })
+            }
+            (_, action) => {
+                let prepared = match cached {
+                    Some(plan) => Ok((plan::Action::Collect { plan }, HashMap::new(), None)),
+                    None => self
+                        .prepare(action, queue, &operators)
+                        .instrument(span.clone())
+                        .await
+                        .map(|(action, fetched)| (action, fetched, cache_key)),
+                };
+                match prepared {
+                    Ok((action, fetched, cache_key)) => self
+                        .plan(&action, &fetched, span, operators, record.clone(), received)
+                        .map(|planned| PlannedQuery {
+                            cache_key,
+                            ..planned
+                        }),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        if let Err(e) = &planned {
+            self.history.record(QueryRecord {
+                duration: received.elapsed(),
+                state: query_state(e),
+                ..record
+            });
+        }
+        planned
+    }
+
+    /// Execute a planned query once its memory has been reserved, returning the channel of
+    /// the schema and results. The query can be cancelled with the ticket while it runs.
+    async fn execute(
+        &self,
+        planned: PlannedQuery,
+        ticket: Vec<u8>,
+        compression: BatchCompression,
+        send_metrics: bool,
+        send_partitions: bool,
+    ) -> Result<mpsc::Receiver<Result<FlightData, Status>>, Status> {
+        // the memory is released when execution completes. Queries that are rejected report
+        // that resources are exhausted, so they are retried on other executors.
+        let reservation = match self
+            .memory
+            .reserve(planned.memory, MEMORY_QUEUE_TIMEOUT)
+            .await
+        {
+            Ok(reservation) => reservation,
+            Err(e) => {
+                let status = Status::resource_exhausted(e.to_string());
+                self.history.record(QueryRecord {
+                    duration: planned.received.elapsed(),
+                    state: query_state(&status),
+                    ..planned.record
+                });
+                return Err(status);
+            }
+        };
+
+        // the first message sent is the schema, followed by the batches as they are
+        // produced. The channel is bounded so that execution waits for slow clients rather
+        // than buffering the results.
+        let (mut tx, rx) = mpsc::channel(STREAM_BUFFER_BATCHES);
+        let token = CancellationToken::new();
+        self.running
+            .lock()
+            .unwrap()
+            .insert(ticket.clone(), token.clone());
+        let running = self.running.clone();
+        let shuffle_dirs = self.shuffle_dirs.clone();
+        let object_stores = self.object_stores.clone();
+        let metrics = self.metrics.clone();
+        let history = self.history.clone();
+        let results = self.results.clone();
+        self.pool.spawn(move || {
+            let span = planned.span.clone();
+            let _enter = span.enter();
+            let start = Instant::now();
+            metrics.queries.inc();
+            metrics.bytes_scanned.inc_by(planned.scanned_bytes);
+            // queries end as cancelled when the client disconnects before all of the
+            // results are sent
+            let mut state = QueryState::Cancelled;
+            let mut rows = 0;
+            let mut encoder = DictionaryEncoder::new(planned.schema.clone());
+            let schema = encoder
+                .schema_flight_data()
+                .map_err(|e| Status::internal(format!("{:?}", e)));
+            if block_on(tx.send(schema)).is_ok() {
+                state = QueryState::Completed;
+                if let Some(output) = &planned.shuffle {
+                    let summary = write_shuffle(&planned.partitions, output, &shuffle_dirs, &token)
+                        .and_then(|summary| {
+                            let written = ShuffleSummary::from_batches(&[summary.clone()])
+                                .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                            rows = written.num_rows.iter().sum();
+                            let mut write = OperatorMetrics::new("ShuffleWrite");
+                            write.rows = rows;
+                            write.elapsed = start.elapsed();
+                            planned.operators.record(write);
+                            metrics
+                                .rows_output
+                                .inc_by(written.num_rows.iter().sum::<u64>());
+                            metrics.bytes_output.inc_by(written.total_bytes());
+                            metrics.shuffle_bytes_written.inc_by(written.total_bytes());
+                            compression
+                                .compress(FlightData::from(&summary))
+                                .map_err(|e| Status::internal(format!("{:?}", e)))
+                        });
+                    if let Err(e) = &summary {
+                        state = query_state(e);
+                    }
+                    let _ = block_on(tx.send(summary));
+                } else if let Some(output) = &planned.parquet {
+                    let manifest = write_parquet_partitions(
+                        &object_stores,
+                        &output.path,
+                        &output.schema,
+                        &planned.partitions,
+                        &token,
+                    )
+                    .and_then(|file| {
+                        rows = file.num_rows as u64;
+                        metrics.rows_output.inc_by(file.num_rows as u64);
+                        metrics.bytes_output.inc_by(file.size);
+                        WriteManifest { files: vec![file] }.to_batch()
+                    })
+                    .map_err(|e| match e.root_cause() {
+                        BallistaError::Cancelled => Status::cancelled("Query was cancelled"),
+                        _ => Status::internal(format!("{:?}", e)),
+                    })
+                    .and_then(|manifest| {
+                        compression
+                            .compress(FlightData::from(&manifest))
+                            .map_err(|e| Status::internal(format!("{:?}", e)))
+                    });
+                    if let Err(e) = &manifest {
+                        state = query_state(e);
+                    }
+                    let _ = block_on(tx.send(manifest));
+                } else {
+                    let mut collector = planned.cache_key.as_ref().map(|_| results.collector());
+                    for partition in &planned.partitions {
+                        let result = stream_partition(
+                            partition.as_ref(),
+                            &mut tx,
+                            &mut encoder,
+                            &token,
+                            compression,
+                            &metrics,
+                            collector.as_mut(),
+                        );
+                        // the end of each partition is marked when the client asks for it
+                        let result = result.map(|sent| {
+                            sent && (!send_partitions
+                                || block_on(tx.send(Ok(partition_end_flight_data()))).is_ok())
+                        });
+                        match result {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                state = QueryState::Cancelled;
+                                break;
+                            }
+                            Err(e) => {
+                                // the client may have disconnected, in which case the error
+                                // is not sent
+                                state = query_state(&e);
+                                let _ = block_on(tx.send(Err(e)));
+                                break;
+                            }
+                        }
+                    }
+                    rows = planned.operators.metrics().total(EXECUTE_OPERATOR).rows;
+                    if state == QueryState::Completed {
+                        let batches = collector.and_then(|collector| collector.finish());
+                        if let (Some(key), Some(batches)) = (&planned.cache_key, batches) {
+                            results.insert(key.clone(), &batches);
+                        }
+                    }
+                }
+                // the metrics follow the last batch, once the readers of the partitions
+                // have recorded them
+                if send_metrics && state == QueryState::Completed {
+                    let metrics = planned.operators.metrics().to_flight_data();
+                    let _ = block_on(tx.send(Ok(metrics)));
+                }
+            }
+            running.lock().unwrap().remove(&ticket);
+            drop(reservation);
+            if let QueryState::Failed { .. } = state {
+                metrics.failed_queries.inc();
+            }
+            metrics.task_duration.observe_duration(start.elapsed());
+            history.record(QueryRecord {
+                duration: planned.received.elapsed(),
+                rows,
+                state,
+                ..planned.record
+            });
+            debug!("Executed query");
+        });
+
+        Ok(rx)
+    }
+
+    /// Fetch the shuffle partitions that an action reads from other executors into local
+    /// files, returning the file of each partition. The files are removed with the other
+    /// shuffle files of the job.
+    async fn fetch_shuffle_reads(
+        &self,
+        action: plan::Action,
+        operators: &MetricsCollector,
+    ) -> Result<(plan::Action, HashMap<ShufflePartitionId, PathBuf>), Status> {
+        let locations = match &action {
+            plan::Action::Collect { plan }
+            | plan::Action::ShuffleWrite { plan, .. }
+            | plan::Action::WriteParquet { plan, .. } => shuffle::shuffle_locations(plan),
+            _ => vec![],
+        };
+        if locations.is_empty() {
+            return Ok((action, HashMap::new()));
+        }
+
+        let fetch = self.next_fetch.fetch_add(1, Ordering::SeqCst);
+        let fetched = future::try_join_all(locations.iter().map(|location| async move {
+            let path = self.shuffle_dirs.fetch_path(&location.partition_id, fetch);
+            let start = Instant::now();
+            let num_rows = client::fetch_shuffle_partition_to_file(
+                &self.connections,
+                location,
+                &path,
+                &self.client_config,
+            )
+            .await?;
+            let mut read = OperatorMetrics::new("ShuffleRead");
+            read.rows = num_rows as u64;
+            read.elapsed = start.elapsed();
+            if let Ok(file) = std::fs::metadata(&path) {
+                self.metrics.shuffle_bytes_fetched.inc_by(file.len());
+                read.bytes_read = file.len();
+            }
+            operators.record(read);
+            Ok::<_, BallistaError>((location.partition_id.clone(), path))
+        }))
+        .await
+        .map_err(|e| {
+            // the scheduler re-runs the tasks that wrote the partitions before retrying
+            Status::failed_precondition(format!("Unable to fetch shuffle partition: {:?}", e))
+        })?;
+        Ok((action, fetched.into_iter().collect()))
+    }
+
+    /// Stream a shuffle partition that was written by this executor
+    fn stream_shuffle_partition(
+        &self,
+        partition_id: &ShufflePartitionId,
+        compression: BatchCompression,
+    ) -> Result<mpsc::Receiver<Result<FlightData, Status>>, Status> {
+        let mut reader = shuffle::read_shuffle_partition(&self.shuffle_dirs, partition_id)
+            .map_err(|e| Status::not_found(format!("{:?}", e)))?;
+        let (mut tx, rx) = mpsc::channel(STREAM_BUFFER_BATCHES);
+        tokio::task::spawn_blocking(move || {
+            let mut encoder = DictionaryEncoder::new(reader.schema());
+            let schema = encoder
+                .schema_flight_data()
+                .map_err(|e| Status::internal(format!("{:?}", e)));
+            if block_on(tx.send(schema)).is_ok() {
+                let token = CancellationToken::new();
+                let result = stream_batches(
+                    &mut reader,
+                    &mut tx,
+                    &mut encoder,
+                    &token,
+                    compression,
+                    None,
+                    None,
+                );
+                if let Err(e) = result {
+                    let _ = block_on(tx.send(Err(e)));
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+impl Default for BallistaFlightService {
+    fn default() -> Self {
+        Self::new(
+            Arc::new(ObjectStoreRegistry::new(&HashMap::new())),
+            Arc::new(TableRegistry::new()),
+        )
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for BallistaFlightService {
+    type HandshakeStream =
+        Pin<Box<dyn Stream<Item = Result<HandshakeResponse, Status>> + Send + Sync + 'static>>;
+    type ListFlightsStream =
+        Pin<Box<dyn Stream<Item = Result<FlightInfo, Status>> + Send + Sync + 'static>>;
+    type DoGetStream =
+        Pin<Box<dyn Stream<Item = Result<FlightData, Status>> + Send + Sync + 'static>>;
+    type DoPutStream =
+        Pin<Box<dyn Stream<Item = Result<PutResult, Status>> + Send + Sync + 'static>>;
+    type DoActionStream =
+        Pin<Box<dyn Stream<Item = Result<flight::Result, Status>> + Send + Sync + 'static>>;
+    type ListActionsStream =
+        Pin<Box<dyn Stream<Item = Result<ActionType, Status>> + Send + Sync + 'static>>;
+    type DoExchangeStream =
+        Pin<Box<dyn Stream<Item = Result<FlightData, Status>> + Send + Sync + 'static>>;
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        authenticate(request.metadata(), &self.credentials)?;
+        let compression = BatchCompression::requested(request.metadata());
+        let queue = requested_queue(request.metadata());
+        let query_id = trace::requested_query_id(request.metadata());
+        let send_metrics = metrics_requested(request.metadata());
+        let profile = profile_requested(request.metadata());
+        let partitioned = partitions_requested(request.metadata());
+        let ticket = request.into_inner();
+
+        if let Some(partition_id) = ShufflePartitionId::from_ticket(&ticket.ticket) {
+            debug!("do_get: shuffle partition {:?}", partition_id);
+            let rx = self.stream_shuffle_partition(&partition_id, compression)?;
+            return Ok(Response::new(Box::pin(rx) as Self::DoGetStream));
+        }
+
+        // tickets returned by do_action refer to queries that have already been planned,
+        // otherwise the ticket is a serialized action, or a Flight SQL ticket that is planned
+        // as the query of an action, which is planned now
+        let stored = self.queries.lock().unwrap().remove(&ticket.ticket);
+        let planned = match stored {
+            Some(planned) => planned,
+            None => {
+                let action = match flight_sql::decode_command(&ticket.ticket) {
+                    Some(command) => {
+                        let command = command.map_err(|e| decode_err("ticket", e))?;
+                        let plan = self.flight_sql_plan(&command)?;
+                        plan::Action::Collect { plan }
+                    }
+                    None => decode_protobuf(&ticket.ticket.to_vec())
+                        .map_err(|e| decode_err("ticket", e))?,
+                };
+                debug!("do_get: {:?}", action);
+                self.prepare_and_plan(action, &queue, query_id.as_deref(), profile, partitioned)
+                    .await?
+            }
+        };
+
+        let rx = self
+            .execute(
+                planned,
+                ticket.ticket,
+                compression,
+                send_metrics,
+                partitioned,
+            )
+            .await?;
+        Ok(Response::new(Box::pin(rx) as Self::DoGetStream))
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        authenticate(request.metadata(), &self.credentials)?;
+        let command = flight_sql_command(&request.into_inner())?;
+        debug!("get_schema: {:?}", command);
+        let plan = self.flight_sql_plan(&command)?;
+        Ok(Response::new(SchemaResult::from(plan.schema())))
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        authenticate(request.metadata(), &self.credentials)?;
+        let descriptor = request.into_inner();
+        let command = flight_sql_command(&descriptor)?;
+        debug!("get_flight_info: {:?}", command);
+
+        // the results are fetched from this executor with a ticket for the command, which is
+        // planned again when it is fetched
+        let plan = self.flight_sql_plan(&command)?;
+        let ticket = command
+            .ticket()
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        Ok(Response::new(FlightInfo {
+            schema: flight_sql::schema_bytes(plan.schema()),
+            flight_descriptor: Some(descriptor),
+            endpoint: vec![FlightEndpoint {
+                ticket: Some(Ticket { ticket }),
+                location: vec![],
+            }],
+            total_records: -1,
+            total_bytes: -1,
+        }))
+    }
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("Not yet implemented"))
+    }
+
+    async fn do_put(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        authenticate(request.metadata(), &self.credentials)?;
+        let mut stream = request.into_inner();
+        let mut messages = vec![];
+        while let Some(data) = stream.message().await? {
+            messages.push(data);
+        }
+        let (name, table) = receive_pinned(&messages).map_err(|e| decode_err("table", e))?;
+        info!(
+            "do_put: pin table {} with {} partitions",
+            name,
+            table.partitions().len()
+        );
+        self.tables.register_memory(&name, table);
+        let output = futures::stream::iter(Vec::<Result<PutResult, Status>>::new());
+        Ok(Response::new(Box::pin(output) as Self::DoPutStream))
+    }
+
+    async fn do_action(
+        &self,
+        request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        authenticate(request.metadata(), &self.credentials)?;
+        let queue = requested_queue(request.metadata());
+        let query_id = trace::requested_query_id(request.metadata());
+        let profile = profile_requested(request.metadata());
+        let partitioned = partitions_requested(request.metadata());
+        let action = request.into_inner();
+        if action.r#type == plan::CANCEL_ACTION_TYPE {
+            // queries that have not been fetched yet are discarded, and running queries stop
+            // before their next batch
+            info!(
+                "do_action: cancel {:?}",
+                String::from_utf8_lossy(&action.body)
+            );
+            self.queries.lock().unwrap().remove(&action.body);
+            if let Some(token) = self.running.lock().unwrap().get(&action.body) {
+                token.cancel();
+            }
+            let output = futures::stream::iter(Vec::<Result<flight::Result, Status>>::new());
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
+        if action.r#type == plan::REMOVE_SHUFFLE_ACTION_TYPE {
+            let job_id = String::from_utf8_lossy(&action.body);
+            info!("do_action: remove shuffle partitions for job {}", job_id);
+            self.shuffle_dirs
+                .remove_job(&job_id)
+                .map_err(|e| Status::invalid_argument(format!("{:?}", e)))?;
+            let output = futures::stream::iter(Vec::<Result<flight::Result, Status>>::new());
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
+        if action.r#type == plan::EXECUTOR_STATUS_ACTION_TYPE {
+            let usage = self.memory.usage();
+            let output = futures::stream::iter(vec![Ok(flight::Result {
+                body: usage.to_json().into_bytes(),
+            })]);
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
+        if action.r#type == plan::QUERY_HISTORY_ACTION_TYPE {
+            let results: Vec<Result<flight::Result, Status>> = self
+                .history
+                .records()
+                .iter()
+                .map(|record| {
+                    Ok(flight::Result {
+                        body: record.to_json().into_bytes(),
+                    })
+                })
+                .collect();
+            let output = futures::stream::iter(results);
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
+        if let Some(results) = self.job_action(&action)? {
+            let output = futures::stream::iter(results.into_iter().map(Ok));
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
+        if action.r#type == flight_sql::CREATE_PREPARED_STATEMENT_ACTION_TYPE {
+            let body = flight_sql::create_prepared_statement(&action.body, &self.tables)
+                .map_err(|e| Status::invalid_argument(format!("{:?}", e)))?;
+            let output = futures::stream::iter(vec![Ok(flight::Result { body })]);
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
+        if action.r#type == flight_sql::CLOSE_PREPARED_STATEMENT_ACTION_TYPE {
+            flight_sql::close_prepared_statement(&action.body)
+                .map_err(|e| decode_err("action", e))?;
+            let output = futures::stream::iter(Vec::<Result<flight::Result, Status>>::new());
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
+        if action.r#type == plan::COLLECT_JSON_ACTION_TYPE {
+            let action = decode_protobuf(&action.body).map_err(|e| decode_err("action", e))?;
+            debug!("do_action: collect JSON {:?}", action);
+            let planned = self
+                .prepare_and_plan(action, &queue, query_id.as_deref(), profile, false)
+                .await?;
+            let ticket =
+                format!("json-{}", self.next_ticket.fetch_add(1, Ordering::SeqCst)).into_bytes();
+            let results = self.collect_json(planned, ticket).await?;
+            let output = futures::stream::iter(results.into_iter().map(Ok));
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
+        if action.r#type != plan::SUBMIT_ACTION_TYPE {
+            return Err(Status::invalid_argument(format!(
+                "Unknown action type: {}",
+                action.r#type
+            )));
+        }
+        let action = decode_protobuf(&action.body).map_err(|e| decode_err("action", e))?;
+        debug!("do_action: {:?}", action);
+
+        // the query is planned now so that errors are reported to the client, and executed
+        // when the results are fetched with do_get
+        let planned = self
+            .prepare_and_plan(action, &queue, query_id.as_deref(), profile, partitioned)
+            .await?;
+        let ticket =
+            format!("result-{}", self.next_ticket.fetch_add(1, Ordering::SeqCst)).into_bytes();
+        self.queries.lock().unwrap().insert(ticket.clone(), planned);
+
+        let output = futures::stream::iter(vec![Ok(flight::Result { body: ticket })]);
+        Ok(Response::new(Box::pin(output) as Self::DoActionStream))
+    }
+
+    async fn list_actions(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        authenticate(request.metadata(), &self.credentials)?;
+        let output = futures::stream::iter(vec![
+            Ok(ActionType {
+                r#type: plan::SUBMIT_ACTION_TYPE.to_owned(),
+                description:
+                    "Execute a serialized Ballista action, returning a ticket for the results"
+                        .to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: plan::CANCEL_ACTION_TYPE.to_owned(),
+                description: "Cancel the action that returned the given ticket".to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: plan::REMOVE_SHUFFLE_ACTION_TYPE.to_owned(),
+                description: "Remove the shuffle partitions written for the given job".to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: plan::EXECUTOR_STATUS_ACTION_TYPE.to_owned(),
+                description: "Get the memory used by the executor".to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: plan::LIST_JOBS_ACTION_TYPE.to_owned(),
+                description: "List the jobs of the scheduler".to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: plan::JOB_STATUS_ACTION_TYPE.to_owned(),
+                description: "Get the progress of the given job".to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: plan::CANCEL_JOB_ACTION_TYPE.to_owned(),
+                description: "Cancel the given job".to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: plan::QUERY_HISTORY_ACTION_TYPE.to_owned(),
+                description: "List the queries that have run, oldest first".to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: plan::COLLECT_JSON_ACTION_TYPE.to_owned(),
+                description: "Execute a serialized Ballista action, returning rows of JSON"
+                    .to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: flight_sql::CREATE_PREPARED_STATEMENT_ACTION_TYPE.to_owned(),
+                description: "Create a Flight SQL prepared statement".to_owned(),
+            }),
+            Ok(ActionType {
+                r#type: flight_sql::CLOSE_PREPARED_STATEMENT_ACTION_TYPE.to_owned(),
+                description: "Close a Flight SQL prepared statement".to_owned(),
+            }),
+        ]);
+        Ok(Response::new(Box::pin(output) as Self::ListActionsStream))
+    }
+
+    async fn do_exchange(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        authenticate(request.metadata(), &self.credentials)?;
+        let compression = BatchCompression::requested(request.metadata());
+        let queue = requested_queue(request.metadata());
+        let query_id = trace::requested_query_id(request.metadata());
+        let send_metrics = metrics_requested(request.metadata());
+        let profile = profile_requested(request.metadata());
+        let partitioned = partitions_requested(request.metadata());
+        let mut stream = request.into_inner();
+
+        // the action is sent first, followed by the uploaded tables
+        let first = stream
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("The exchange has no action"))?;
+        let action = exchange::exchange_action(&first)
+            .and_then(decode_protobuf)
+            .map_err(|e| decode_err("action", e))?;
+        let mut receiver = UploadReceiver::new();
+        while let Some(data) = stream.message().await? {
+            receiver
+                .receive(&data)
+                .map_err(|e| decode_err("uploaded table", e))?;
+        }
+        let tables = receiver.finish();
+        debug!("do_exchange: {:?} with tables {:?}", action, tables.keys());
+
+        let action = match action {
+            plan::Action::Collect { plan } => plan::Action::Collect {
+                plan: scan_uploaded_tables(&plan, &tables)
+                    .map_err(|e| Status::invalid_argument(format!("{:?}", e)))?,
+            },
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Only queries that collect their results can be exchanged",
+                ))
+            }
+        };
+        let planned = self
+            .prepare_and_plan(action, &queue, query_id.as_deref(), profile, partitioned)
+            .await?;
+        let ticket = format!(
+            "exchange-{}",
+            self.next_ticket.fetch_add(1, Ordering::SeqCst)
+        )
+        .into_bytes();
+        let rx = self
+            .execute(planned, ticket, compression, send_metrics, partitioned)
+            .await?;
+        Ok(Response::new(Box::pin(rx) as Self::DoExchangeStream))
+    }
+}
+
+/// Execute a partition, sending each batch to the client and to the collector of the
+/// results, if they are cached. Returns false if execution stopped early because the client
+/// disconnected or cancelled the query.
+fn stream_partition(
+    partition: &dyn Partition,
+    tx: &mut mpsc::Sender<Result<FlightData, Status>>,
+    encoder: &mut DictionaryEncoder,
+    token: &CancellationToken,
+    compression: BatchCompression,
+    metrics: &ExecutorMetrics,
+    collector: Option<&mut ResultCollector>,
+) -> Result<bool, Status> {
+    let reader = partition.execute().map_err(|e| to_tonic_err(&e))?;
+    let mut reader = reader.lock().unwrap();
+    stream_batches(
+        &mut *reader,
+        tx,
+        encoder,
+        token,
+        compression,
+        Some(metrics),
+        collector,
+    )
+}
+
+/// Execute the partitions of a task and write the output as shuffle partitions, returning
+/// the number of rows written to each shuffle partition
+fn write_shuffle(
+    partitions: &[Arc<dyn Partition>],
+    output: &ShuffleOutput,
+    shuffle_dirs: &ShuffleDirs,
+    token: &CancellationToken,
+) -> Result<RecordBatch, Status> {
+    let to_status = |e: BallistaError| match e.root_cause() {
+        BallistaError::Cancelled => Status::cancelled("Query was cancelled"),
+        _ => Status::internal(format!("{:?}", e)),
+    };
+    let writer = ShuffleWriter::try_new(
+        shuffle_dirs,
+        &output.job_id,
+        output.stage_id,
+        output.map_partition,
+        &output.partitioning,
+        &output.schema,
+    )
+    .map_err(to_status)?;
+    write_partitions(partitions, writer, token).map_err(to_status)
+}
+
+/// Send each batch from a reader to the client, preceded by the dictionaries that changed,
+/// counting the output of queries in the metrics. Returns false if the client disconnected
+/// or cancelled the query before all batches were sent.
+fn stream_batches(
+    reader: &mut dyn RecordBatchReader,
+    tx: &mut mpsc::Sender<Result<FlightData, Status>>,
+    encoder: &mut DictionaryEncoder,
+    token: &CancellationToken,
+    compression: BatchCompression,
+    metrics: Option<&ExecutorMetrics>,
+    mut collector: Option<&mut ResultCollector>,
+) -> Result<bool, Status> {
+    while let Some(batch) = reader
+        .next_batch()
+        .map_err(|e| Status::internal(format!("{:?}", e)))?
+    {
+        if token.is_cancelled() {
+            return Ok(false);
+        }
+        if let Some(metrics) = metrics {
+            metrics.rows_output.inc_by(batch.num_rows() as u64);
+            metrics
+                .bytes_output
+                .inc_by(batch_memory_size(&batch) as u64);
+        }
+        if let Some(collector) = collector.as_mut() {
+            collector.push(&batch);
+        }
+        let (keys, dictionaries) = encoder
+            .encode(&batch)
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        let mut messages = vec![];
+        for (column, values) in &dictionaries {
+            messages.push(dictionary_flight_data(*column, values));
+        }
+        messages.push(Ok(FlightData::from(&keys)));
+        for data in messages {
+            let data = data
+                .and_then(|data| compression.compress(data))
+                .map_err(|e| Status::internal(format!("{:?}", e)))?;
+            if block_on(tx.send(Ok(data))).is_err() {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Bytes of the local files and in-memory batches that a plan scans. The size of files in
+/// remote object stores is not known until they are read.
+fn scanned_bytes(plan: &LogicalPlan) -> u64 {
+    match plan {
+        LogicalPlan::MemoryScan(batches) => batches
+            .iter()
+            .map(|batch| batch_memory_size(batch) as u64)
+            .sum(),
+        LogicalPlan::FileScan {
+            files, file_type, ..
+        } if file_type != "sql" => files
+            .iter()
+            .filter(|file| object_store::scheme(file) == "file")
+            .filter_map(|file| std::fs::metadata(LocalFileSystem::local_path(file)).ok())
+            .map(|metadata| metadata.len())
+            .sum(),
+        _ => plan.inputs().into_iter().map(scanned_bytes).sum(),
+    }
+}
+
+/// How a query ended with an error
+fn query_state(status: &Status) -> QueryState {
+    match status.code() {
+        Code::Cancelled => QueryState::Cancelled,
+        _ => QueryState::Failed {
+            error: status.message().to_owned(),
+        },
+    }
+}
+
+fn to_tonic_err(e: &ExecutionError) -> Status {
+    Status::internal(format!("{:?}", e))
+}
+
+/// The error for a ticket or action that cannot be decoded. Actions encoded with a newer
+/// version of the plan format are reported with the versions that this executor supports.
+/// The Flight SQL command of a descriptor, which is the only kind of descriptor that
+/// executors describe
+fn flight_sql_command(descriptor: &FlightDescriptor) -> Result<flight_sql::Command, Status> {
+    match flight_sql::decode_command(&descriptor.cmd) {
+        Some(command) => command.map_err(|e| decode_err("Flight SQL command", e)),
+        None => Err(Status::invalid_argument(
+            "Descriptors must contain a Flight SQL command",
+        )),
+    }
+}
+
+fn decode_err(what: &str, e: BallistaError) -> Status {
+    match e.root_cause() {
+        BallistaError::UnsupportedPlanVersion(_) => Status::invalid_argument(e.to_string()),
+        _ => Status::invalid_argument(format!("Invalid {}: {:?}", what, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::client;
+    use crate::datafusion::datasource::MemTable;
+
+    #[tokio::test]
+    async fn execute_in_process() -> Result<(), BallistaError> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![1, 2, 3]))])?;
+
+        let server = Server::new("127.0.0.1:50151".parse().unwrap());
+        tokio::spawn(server.serve());
+        tokio::time::delay_for(std::time::Duration::from_millis(500)).await;
+
+        let action = plan::Action::Collect {
+            plan: LogicalPlan::MemoryScan(vec![batch]),
+        };
+        let batches = client::execute_action("127.0.0.1", 50151, action).await?;
+        assert_eq!(3, batches.iter().map(|b| b.num_rows()).sum::<usize>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_metadata_in_process() -> Result<(), BallistaError> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let tables = Arc::new(TableRegistry::new());
+        tables.register("t", Arc::new(MemTable::new(schema.clone(), vec![])?));
+        let object_stores = Arc::new(ObjectStoreRegistry::new(&HashMap::new()));
+        let service = BallistaFlightService::new(object_stores, tables);
+        let server = Server::with_service("127.0.0.1:50153".parse().unwrap(), service);
+        tokio::spawn(server.serve());
+        tokio::time::delay_for(std::time::Duration::from_millis(500)).await;
+
+        let pool = ConnectionPool::new();
+        let config = ClientConfig::default();
+        let tables = client::list_tables(&pool, "127.0.0.1", 50153, &config).await?;
+        assert_eq!(vec!["t".to_owned()], tables);
+        let fetched =
+            client::fetch_schema(&pool, "127.0.0.1", 50153, "t", "table", &config).await?;
+        assert_eq!(schema.as_ref(), &fetched);
+        assert!(
+            client::fetch_schema(&pool, "127.0.0.1", 50153, "u", "table", &config)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shuffle_in_process() -> Result<(), BallistaError> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3, 4, 5]))],
+        )?;
+
+        let shuffle_dir = std::env::temp_dir().join("ballista-shuffle-executor-test");
+        let server =
+            Server::new("127.0.0.1:50152".parse().unwrap()).with_shuffle_dir(shuffle_dir.clone());
+        tokio::spawn(server.serve());
+        tokio::time::delay_for(std::time::Duration::from_millis(500)).await;
+
+        let action = plan::Action::ShuffleWrite {
+            job_id: "job-1".to_owned(),
+            stage_id: 0,
+            partition: 0,
+            plan: LogicalPlan::MemoryScan(vec![batch]),
+            partitioning: Partitioning::Hash {
+                columns: vec![0],
+                partitions: 2,
+            },
+        };
+        let summary = client::execute_action("127.0.0.1", 50152, action).await?;
+        assert_eq!(2, summary[0].num_rows());
+
+        let pool = ConnectionPool::new();
+        let mut rows = 0;
+        for output_partition in 0..2 {
+            let location = shuffle::ShuffleLocation {
+                partition_id: ShufflePartitionId {
+                    job_id: "job-1".to_owned(),
+                    stage_id: 0,
+                    map_partition: 0,
+                    output_partition,
+                },
+                host: "127.0.0.1".to_owned(),
+                port: 50152,
+            };
+            let config = ClientConfig::default();
+            let batches = client::fetch_shuffle_partition(&pool, &location, &config).await?;
+            rows += batches.iter().map(|b| b.num_rows()).sum::<usize>();
+        }
+        assert_eq!(5, rows);
+
+        client::remove_shuffle(&pool, "127.0.0.1", 50152, "job-1", &ClientConfig::default())
+            .await?;
+        assert!(!shuffle_dir.join("job-1").exists());
+        Ok(())
+    }
+}
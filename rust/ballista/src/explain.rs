@@ -0,0 +1,263 @@
+//! Plans annotated with how they were executed.
+//!
+//! `DataFrame::explain_analyze` executes a query with the metrics of its operators collected
+//! (see `execution_metrics`) and describes each operator of the optimized plan, or of each
+//! stage of a distributed query, with the number of rows that the statistics estimate for it
+//! (see `statistics`) next to the rows, time and memory that were measured, so that the
+//! operators whose estimates are wrong stand out.
+//!
+//! Ballista measures the scans, joins, sorts, unions, aggregates that spill and extensions
+//! that it executes itself, and the shuffle reads of stages. Each of them records its
+//! metrics after the metrics of its inputs, so the metrics of an operator are the ones that
+//! were recorded with its name in the same position of the plan, combined across the tasks
+//! of its stage. The other operators run in the DataFusion pipeline of each partition, which
+//! is measured as a whole, so they only have estimates.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::datasource::table::TableRegistry;
+use crate::execution_metrics::{OperatorMetrics, QueryMetrics, EXECUTE_OPERATOR};
+use crate::logicalplan::LogicalPlan;
+use crate::statistics::estimate_statistics;
+
+/// An operator of a plan, with its estimated and measured rows
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyzedOperator {
+    /// The depth of the operator in the plan, which is 0 for the root
+    pub depth: usize,
+    /// The description of the operator, without its inputs
+    pub description: String,
+    /// The number of rows that the statistics estimate the operator produces, if known
+    pub estimated_rows: Option<f64>,
+    /// The metrics of the operator combined across the tasks that ran it, for operators
+    /// that are measured on their own
+    pub metrics: Option<OperatorMetrics>,
+}
+
+/// The operators of a plan that was executed, or of a stage of a distributed query
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyzedStage {
+    /// The stage of a distributed query, or `None` for the plan of a query that was not
+    /// split into stages
+    pub stage: Option<usize>,
+    /// The number of partitions that were executed
+    pub partitions: usize,
+    /// The execution of the partitions, combined
+    pub execute: OperatorMetrics,
+    /// The operators of the plan, with each operator followed by its inputs
+    pub operators: Vec<AnalyzedOperator>,
+}
+
+impl AnalyzedStage {
+    /// Annotate the operators of a plan with the metrics that were recorded for them in the
+    /// given stage
+    pub fn new(
+        stage: Option<usize>,
+        plan: &LogicalPlan,
+        metrics: &QueryMetrics,
+        tables: &TableRegistry,
+    ) -> Self {
+        let metrics = metrics.stage(stage);
+
+        // operators record their metrics after their inputs, so the position of each
+        // operator among the operators with the same name is found in that order
+        let mut measured = vec![];
+        measured_operators(plan, &mut measured);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut positions: HashMap<*const LogicalPlan, usize> = HashMap::new();
+        for plan in &measured {
+            let count = counts.entry(measured_name(plan)).or_insert(0);
+            positions.insert(*plan as *const LogicalPlan, *count);
+            *count += 1;
+        }
+        // the tasks of a stage each record the metrics of every operator in the same order
+        let mut operator_metrics: HashMap<(&str, usize), QueryMetrics> = HashMap::new();
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for m in &metrics.operators {
+            if let Some((&name, &count)) = counts.get_key_value(m.operator.as_str()) {
+                let index = seen.entry(name).or_insert(0);
+                operator_metrics
+                    .entry((name, *index % count))
+                    .or_default()
+                    .operators
+                    .push(m.clone());
+                *index += 1;
+            }
+        }
+
+        let mut operators = vec![];
+        let mut pending = vec![(0, plan)];
+        while let Some((depth, plan)) = pending.pop() {
+            let name = measured_name(plan);
+            let metrics = positions
+                .get(&(plan as *const LogicalPlan))
+                .and_then(|position| operator_metrics.get(&(name, *position)))
+                .map(|metrics| metrics.total(name));
+            operators.push(AnalyzedOperator {
+                depth,
+                description: plan.describe(),
+                estimated_rows: estimate_statistics(plan, tables).map(|s| s.num_rows),
+                metrics,
+            });
+            for input in plan.inputs().into_iter().rev() {
+                pending.push((depth + 1, input));
+            }
+        }
+
+        Self {
+            stage,
+            partitions: metrics
+                .operators
+                .iter()
+                .filter(|m| m.operator == EXECUTE_OPERATOR)
+                .count(),
+            execute: metrics.total(EXECUTE_OPERATOR),
+            operators,
+        }
+    }
+}
+
+/// The operators of a plan whose metrics are recorded, in the order that they record them
+fn measured_operators<'a>(plan: &'a LogicalPlan, measured: &mut Vec<&'a LogicalPlan>) {
+    for input in plan.inputs() {
+        measured_operators(input, measured);
+    }
+    measured.push(plan);
+}
+
+/// The name of the operator that the metrics of an operator are recorded with. The output of
+/// earlier stages is read by shuffle reads.
+fn measured_name(plan: &LogicalPlan) -> &'static str {
+    match plan {
+        LogicalPlan::StageOutput { .. } => "ShuffleRead",
+        other => other.operator_name(),
+    }
+}
+
+/// A query that was executed, with the operators of its plan annotated with how they ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyzedPlan {
+    /// Wall time of the query
+    pub elapsed: Duration,
+    /// Rows returned by the query
+    pub rows: u64,
+    /// The plan of the query, or the stages that it was split into
+    pub stages: Vec<AnalyzedStage>,
+}
+
+impl fmt::Display for AnalyzedPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Query took {:?} and returned {} rows",
+            self.elapsed, self.rows
+        )?;
+        for stage in &self.stages {
+            let indent = match stage.stage {
+                Some(id) => {
+                    writeln!(f, "Stage {}:", id)?;
+                    "  "
+                }
+                None => "",
+            };
+            writeln!(
+                f,
+                "{}Execute: partitions={} rows={} elapsed={:?} memory={}",
+                indent,
+                stage.partitions,
+                stage.execute.rows,
+                stage.execute.elapsed,
+                stage.execute.memory
+            )?;
+            for operator in &stage.operators {
+                write!(
+                    f,
+                    "{}{}{}",
+                    indent,
+                    "  ".repeat(operator.depth),
+                    operator.description
+                )?;
+                let mut annotations = vec![];
+                if let Some(rows) = operator.estimated_rows {
+                    annotations.push(format!("estimated_rows={:.0}", rows));
+                }
+                if let Some(m) = &operator.metrics {
+                    annotations.push(format!("rows={}", m.rows));
+                    annotations.push(format!("elapsed={:?}", m.elapsed));
+                    annotations.push(format!("memory={}", m.memory));
+                    if m.bytes_read > 0 {
+                        annotations.push(format!("bytes_read={}", m.bytes_read));
+                    }
+                    if m.spills > 0 {
+                        annotations.push(format!("spills={}", m.spills));
+                    }
+                }
+                if annotations.is_empty() {
+                    writeln!(f)?;
+                } else {
+                    writeln!(f, " [{}]", annotations.join(" "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::arrow::record_batch::RecordBatch;
+    use crate::error::Result;
+    use crate::logicalplan::LogicalPlanBuilder;
+    use std::sync::Arc;
+
+    #[test]
+    fn annotate_operators_with_metrics() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![1, 2, 3]))])?;
+        let scan = LogicalPlan::MemoryScan(vec![batch]);
+        let plan = LogicalPlanBuilder::from(&scan)
+            .union_by_name(&scan, false)?
+            .build()?;
+
+        // the scans of the union are recorded before the union, in the order of its inputs,
+        // by each of the two tasks
+        let mut metrics = QueryMetrics::default();
+        for _ in 0..2 {
+            for (operator, rows) in &[("MemoryScan", 3), ("MemoryScan", 4), ("Union", 7)] {
+                let mut m = OperatorMetrics::new(operator);
+                m.rows = *rows;
+                metrics.operators.push(m);
+            }
+            let mut execute = OperatorMetrics::new(EXECUTE_OPERATOR);
+            execute.rows = 7;
+            metrics.operators.push(execute);
+        }
+        let metrics = metrics.with_stage(0);
+
+        let stage = AnalyzedStage::new(Some(0), &plan, &metrics, &TableRegistry::new());
+        assert_eq!(2, stage.partitions);
+        assert_eq!(14, stage.execute.rows);
+        let rows: Vec<(usize, Option<u64>)> = stage
+            .operators
+            .iter()
+            .map(|o| (o.depth, o.metrics.as_ref().map(|m| m.rows)))
+            .collect();
+        assert_eq!(vec![(0, Some(14)), (1, Some(6)), (1, Some(8))], rows);
+        assert_eq!(Some(3.0), stage.operators[1].estimated_rows);
+
+        let analyzed = AnalyzedPlan {
+            elapsed: Duration::from_millis(5),
+            rows: 14,
+            stages: vec![stage],
+        };
+        let text = analyzed.to_string();
+        assert!(text.contains("Stage 0:\n  Execute: partitions=2 rows=14"));
+        assert!(text.contains("    MemoryScan [estimated_rows=3 rows=6 "));
+        Ok(())
+    }
+}
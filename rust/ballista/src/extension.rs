@@ -0,0 +1,220 @@
+//! User-defined logical operators.
+//!
+//! Projects that build on Ballista add operators that it does not implement, such as a
+//! geospatial join, as `LogicalPlan::Extension` nodes. A node describes its schema and
+//! inputs, so that the optimizer rewrites its inputs and the scheduler splits them into
+//! stages like those of any other operator, and it is executed on the batches of its inputs
+//! when the plan is translated. The optimizer treats a node as needing every column of its
+//! inputs, and the scheduler gathers each input into a single partition before the node
+//! executes.
+//!
+//! Nodes are serialized with their own `encode` hook and deserialized by the codec that is
+//! registered for their name, so every process that decodes plans, including the
+//! executors, must register the codecs of the extensions that it runs.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::arrow::datatypes::Schema;
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::LogicalPlan;
+use crate::window::{WatermarkCodec, WindowCodec, WATERMARK_NODE, WINDOW_NODE};
+
+use lazy_static::lazy_static;
+
+/// A custom operator of a logical plan
+pub trait UserDefinedLogicalNode: fmt::Debug + Send + Sync {
+    /// Name of the operator, which identifies the codec that decodes it
+    fn name(&self) -> &str;
+
+    /// The schema of the output
+    fn schema(&self) -> &Schema;
+
+    /// The inputs of the operator
+    fn inputs(&self) -> Vec<&LogicalPlan>;
+
+    /// Copy the node with different inputs, in the same order as returned by `inputs()`
+    fn with_new_inputs(&self, inputs: Vec<LogicalPlan>) -> Arc<dyn UserDefinedLogicalNode>;
+
+    /// Execute the operator on the batches of each of its inputs
+    fn execute(&self, inputs: &[Vec<RecordBatch>]) -> Result<Vec<RecordBatch>>;
+
+    /// Serialize the state of the node, other than its inputs
+    fn encode(&self) -> Result<Vec<u8>>;
+}
+
+/// Decodes the extension nodes of one operator
+pub trait ExtensionCodec: Send + Sync {
+    /// Decode a node from its serialized state and its decoded inputs
+    fn decode(
+        &self,
+        node: &[u8],
+        inputs: Vec<LogicalPlan>,
+    ) -> Result<Arc<dyn UserDefinedLogicalNode>>;
+}
+
+lazy_static! {
+    /// The codecs of the extensions that Ballista implements are always registered
+    static ref CODECS: RwLock<HashMap<String, Arc<dyn ExtensionCodec>>> = {
+        let mut codecs: HashMap<String, Arc<dyn ExtensionCodec>> = HashMap::new();
+        codecs.insert(WINDOW_NODE.to_owned(), Arc::new(WindowCodec));
+        codecs.insert(WATERMARK_NODE.to_owned(), Arc::new(WatermarkCodec));
+        RwLock::new(codecs)
+    };
+}
+
+/// Register the codec that decodes the extension nodes with the given name, replacing any
+/// codec that was registered for the name before
+pub fn register_extension_codec(name: &str, codec: Arc<dyn ExtensionCodec>) {
+    CODECS.write().unwrap().insert(name.to_owned(), codec);
+}
+
+/// Decode an extension node with the codec that is registered for its name
+pub fn decode_extension(
+    name: &str,
+    node: &[u8],
+    inputs: Vec<LogicalPlan>,
+) -> Result<Arc<dyn UserDefinedLogicalNode>> {
+    let codec = CODECS.read().unwrap().get(name).cloned();
+    match codec {
+        Some(codec) => codec.decode(node, inputs),
+        None => Err(ballista_error(&format!(
+            "No codec is registered for extension node '{}'",
+            name
+        ))),
+    }
+}
+
+#[cfg(all(test, feature = "local-execution"))]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field};
+    use crate::datafusion::execution::context::ExecutionContext;
+    use crate::logicalplan::{col, LogicalPlanBuilder};
+    use crate::optimizer::push_down_projections;
+    use crate::plan::Action;
+    use crate::serde::{decode_protobuf, encode_protobuf, PLAN_VERSION};
+    use crate::translate::translate_plan;
+
+    /// Keeps every nth row of its input
+    #[derive(Debug)]
+    struct EveryNth {
+        n: usize,
+        input: LogicalPlan,
+    }
+
+    impl UserDefinedLogicalNode for EveryNth {
+        fn name(&self) -> &str {
+            "every_nth"
+        }
+
+        fn schema(&self) -> &Schema {
+            self.input.schema()
+        }
+
+        fn inputs(&self) -> Vec<&LogicalPlan> {
+            vec![&self.input]
+        }
+
+        fn with_new_inputs(&self, mut inputs: Vec<LogicalPlan>) -> Arc<dyn UserDefinedLogicalNode> {
+            Arc::new(EveryNth {
+                n: self.n,
+                input: inputs.remove(0),
+            })
+        }
+
+        fn execute(&self, inputs: &[Vec<RecordBatch>]) -> Result<Vec<RecordBatch>> {
+            let mut values = vec![];
+            for batch in &inputs[0] {
+                let column = batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int32Array>()
+                    .unwrap();
+                values.extend((0..column.len()).map(|i| column.value(i)));
+            }
+            let values: Vec<i32> = values.into_iter().step_by(self.n).collect();
+            let schema = Arc::new(self.schema().clone());
+            Ok(vec![RecordBatch::try_new(
+                schema,
+                vec![Arc::new(Int32Array::from(values))],
+            )?])
+        }
+
+        fn encode(&self) -> Result<Vec<u8>> {
+            Ok(vec![self.n as u8])
+        }
+    }
+
+    struct EveryNthCodec;
+
+    impl ExtensionCodec for EveryNthCodec {
+        fn decode(
+            &self,
+            node: &[u8],
+            mut inputs: Vec<LogicalPlan>,
+        ) -> Result<Arc<dyn UserDefinedLogicalNode>> {
+            Ok(Arc::new(EveryNth {
+                n: node[0] as usize,
+                input: inputs.remove(0),
+            }))
+        }
+    }
+
+    #[test]
+    fn optimize_serialize_and_execute_extensions() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3, 4, 5]))],
+        )?;
+        let node = EveryNth {
+            n: 2,
+            input: LogicalPlan::MemoryScan(vec![batch]),
+        };
+        let plan = LogicalPlanBuilder::from(&LogicalPlan::Extension {
+            node: Arc::new(node),
+        })
+        .project(vec![col("a")])?
+        .build()?;
+        let plan = push_down_projections(&plan)?;
+
+        assert!(decode_protobuf(&encode_protobuf(
+            Action::Collect { plan: plan.clone() },
+            PLAN_VERSION
+        )?)
+        .is_err());
+        register_extension_codec("every_nth", Arc::new(EveryNthCodec));
+        let plan = match decode_protobuf(&encode_protobuf(Action::Collect { plan }, PLAN_VERSION)?)?
+        {
+            Action::Collect { plan } => plan,
+            other => panic!("unexpected action {:?}", other),
+        };
+        let plan: LogicalPlan =
+            serde_json::from_str(&serde_json::to_string(&plan).unwrap()).unwrap();
+        assert!(format!("{:?}", plan).contains("Extension: every_nth"));
+        // older executors cannot decode extension nodes
+        assert!(encode_protobuf(Action::Collect { plan: plan.clone() }, PLAN_VERSION - 1).is_err());
+
+        let mut ctx = ExecutionContext::new();
+        let plan = translate_plan(&mut ctx, &plan)?;
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan, 1024)?;
+        let batches = ctx.collect(plan.as_ref())?;
+        let column = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        assert_eq!(
+            vec![1, 3, 5],
+            (0..column.len())
+                .map(|i| column.value(i))
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+//! The paths and CSV options of the file scans of logical plans, which are part of the plan
+//! layer, while the readers that use them are in `datasource`.
+
+use crate::error::{ballista_error, BallistaError, Result};
+
+use serde::{Deserialize, Serialize};
+
+/// Expand a path containing glob patterns such as `data/2020-*/part-*.parquet` into the
+/// list of matching paths. Paths without patterns are returned unchanged.
+pub fn expand_path(path: &str) -> Result<Vec<String>> {
+    if !path.contains(&['*', '?', '['][..]) {
+        return Ok(vec![path.to_owned()]);
+    }
+    let mut paths = vec![];
+    for entry in glob::glob(path)
+        .map_err(|e| BallistaError::General(format!("Invalid glob pattern {}: {}", path, e)))?
+    {
+        let entry = entry.map_err(|e| BallistaError::General(format!("{}", e)))?;
+        paths.push(entry.to_string_lossy().to_string());
+    }
+    if paths.is_empty() {
+        return Err(BallistaError::General(format!(
+            "No files match the pattern {}",
+            path
+        )));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Compression codecs supported for CSV files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CsvCompression {
+    Uncompressed,
+    Gzip,
+    Bzip2,
+    Zstd,
+}
+
+impl CsvCompression {
+    /// Detect the compression codec from the file extension
+    pub fn from_path(path: &str) -> Self {
+        let path = path.to_lowercase();
+        if path.ends_with(".gz") || path.ends_with(".gzip") {
+            CsvCompression::Gzip
+        } else if path.ends_with(".bz2") {
+            CsvCompression::Bzip2
+        } else if path.ends_with(".zst") || path.ends_with(".zstd") {
+            CsvCompression::Zstd
+        } else {
+            CsvCompression::Uncompressed
+        }
+    }
+
+    /// Parse a codec name as produced by `name()`
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "none" => Ok(CsvCompression::Uncompressed),
+            "gzip" => Ok(CsvCompression::Gzip),
+            "bzip2" => Ok(CsvCompression::Bzip2),
+            "zstd" => Ok(CsvCompression::Zstd),
+            other => Err(ballista_error(&format!(
+                "Unsupported CSV compression '{}'",
+                other
+            ))),
+        }
+    }
+
+    /// The name of this codec
+    pub fn name(&self) -> &'static str {
+        match self {
+            CsvCompression::Uncompressed => "none",
+            CsvCompression::Gzip => "gzip",
+            CsvCompression::Bzip2 => "bzip2",
+            CsvCompression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Options that control how CSV files are parsed
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CsvReadOptions {
+    /// Field delimiter, defaults to `,`
+    pub delimiter: u8,
+    /// Quote character, defaults to `"`
+    pub quote: u8,
+    /// Optional escape character for quotes inside quoted fields
+    pub escape: Option<u8>,
+    /// Lines starting with this character are skipped
+    pub comment: Option<u8>,
+    /// Strings that should be read as null values
+    pub null_values: Vec<String>,
+    /// chrono format string used to parse Date32 and Date64 columns
+    pub date_format: Option<String>,
+    /// chrono format string used to parse Timestamp columns
+    pub timestamp_format: Option<String>,
+    /// Timezone that timestamps are read in, such as `+02:00`, which is UTC when not set.
+    /// Contexts set it from the session timezone when the plan is executed.
+    pub timezone: Option<String>,
+    /// Compression codec, detected from the file extension when not set
+    pub compression: Option<CsvCompression>,
+    /// Number of partitions to read the files as in parallel, splitting uncompressed files
+    /// into byte ranges when there are fewer files than partitions. Each file is read as a
+    /// partition when not set.
+    pub partitions: Option<usize>,
+    /// Whether the first line of each file is a header rather than a row, defaults to true
+    #[serde(default = "default_has_header")]
+    pub has_header: bool,
+}
+
+fn default_has_header() -> bool {
+    true
+}
+
+impl Default for CsvReadOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            comment: None,
+            null_values: vec![],
+            date_format: None,
+            timestamp_format: None,
+            timezone: None,
+            compression: None,
+            partitions: None,
+            has_header: true,
+        }
+    }
+}
+
+impl CsvReadOptions {
+    /// Create options for the default CSV dialect
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the field delimiter
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Set the quote character
+    pub fn with_quote(mut self, quote: u8) -> Self {
+        self.quote = quote;
+        self
+    }
+
+    /// Set the escape character
+    pub fn with_escape(mut self, escape: u8) -> Self {
+        self.escape = Some(escape);
+        self
+    }
+
+    /// Skip lines that start with the given character
+    pub fn with_comment(mut self, comment: u8) -> Self {
+        self.comment = Some(comment);
+        self
+    }
+
+    /// Add a string that should be read as null
+    pub fn with_null_value(mut self, null_value: &str) -> Self {
+        self.null_values.push(null_value.to_owned());
+        self
+    }
+
+    /// Set the format used to parse date columns
+    pub fn with_date_format(mut self, format: &str) -> Self {
+        self.date_format = Some(format.to_owned());
+        self
+    }
+
+    /// Set the format used to parse timestamp columns
+    pub fn with_timestamp_format(mut self, format: &str) -> Self {
+        self.timestamp_format = Some(format.to_owned());
+        self
+    }
+
+    /// Set the timezone that timestamps are read in, such as `UTC` or `+02:00`
+    pub fn with_timezone(mut self, timezone: &str) -> Self {
+        self.timezone = Some(timezone.to_owned());
+        self
+    }
+
+    /// Set the compression codec rather than detecting it from the file extension
+    pub fn with_compression(mut self, compression: CsvCompression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Set the number of partitions to read the files as. Uncompressed files can be split
+    /// into byte ranges, whose records are found by following quotes from the start of each
+    /// range, so quoted values may contain line breaks.
+    pub fn with_partitions(mut self, partitions: usize) -> Self {
+        self.partitions = Some(partitions.max(1));
+        self
+    }
+
+    /// Set whether the first line of each file is a header
+    pub fn with_has_header(mut self, has_header: bool) -> Self {
+        self.has_header = has_header;
+        self
+    }
+
+    /// Determine the compression codec to use for a file
+    pub fn compression_for(&self, path: &str) -> CsvCompression {
+        self.compression
+            .unwrap_or_else(|| CsvCompression::from_path(path))
+    }
+
+    /// Determine whether these options describe the default dialect that DataFusion
+    /// can read natively
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub(crate) fn is_null(&self, value: &str) -> bool {
+        self.null_values.iter().any(|n| n == value)
+    }
+}
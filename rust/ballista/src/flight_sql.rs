@@ -0,0 +1,346 @@
+//! The Arrow Flight SQL protocol, which executors serve alongside the Ballista actions.
+//!
+//! Generic Flight SQL drivers and BI tools describe a query or a metadata request with
+//! `GetFlightInfo`, passing a command wrapped in a `google.protobuf.Any` as the descriptor,
+//! and fetch the results with `DoGet` using the ticket of the returned endpoint. SQL queries
+//! are planned with DataFusion's SQL planner against the tables registered with the executor
+//! and then run like any other Ballista query, so they run across the cluster when they are
+//! sent to a scheduler.
+//!
+//! Statement handles, including the handles of prepared statements, are the text of the
+//! query, so they can be executed by any executor and closing them releases nothing.
+//! Prepared statements have no parameters. DataFusion has no catalogs or schemas, so the
+//! tables have neither and the lists of catalogs and schemas are empty.
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use crate::arrow::array::{ArrayRef, BinaryArray, StringArray};
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::datasource::table::TableRegistry;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::logicalplan::LogicalPlan;
+
+use flight::SchemaResult;
+use prost::Message;
+use prost_types::Any;
+
+// include the generated protobuf source as a submodule
+#[allow(clippy::all)]
+pub mod protobuf {
+    include!(concat!(env!("OUT_DIR"), "/arrow.flight.protocol.sql.rs"));
+}
+
+/// Type of the action that creates a prepared statement
+pub const CREATE_PREPARED_STATEMENT_ACTION_TYPE: &str = "CreatePreparedStatement";
+
+/// Type of the action that closes a prepared statement
+pub const CLOSE_PREPARED_STATEMENT_ACTION_TYPE: &str = "ClosePreparedStatement";
+
+/// The prefix of the type URLs of Flight SQL messages wrapped in an `Any`
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/arrow.flight.protocol.sql.";
+
+/// The type of every table, since executors only register tables
+const TABLE_TYPE: &str = "TABLE";
+
+/// A Flight SQL command, sent as a descriptor or as a ticket
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Execute a SQL query
+    Statement(String),
+    GetCatalogs,
+    GetDbSchemas(protobuf::CommandGetDbSchemas),
+    GetTables(protobuf::CommandGetTables),
+    GetTableTypes,
+}
+
+impl Command {
+    /// The ticket that the results of the command are fetched with
+    pub fn ticket(&self) -> Result<Vec<u8>> {
+        match self {
+            Command::Statement(query) => encode_any(
+                "TicketStatementQuery",
+                &protobuf::TicketStatementQuery {
+                    statement_handle: query.clone().into_bytes(),
+                },
+            ),
+            Command::GetCatalogs => {
+                encode_any("CommandGetCatalogs", &protobuf::CommandGetCatalogs {})
+            }
+            Command::GetDbSchemas(command) => encode_any("CommandGetDbSchemas", command),
+            Command::GetTables(command) => encode_any("CommandGetTables", command),
+            Command::GetTableTypes => {
+                encode_any("CommandGetTableTypes", &protobuf::CommandGetTableTypes {})
+            }
+        }
+    }
+
+    /// The plan that produces the results of the command. Metadata is read from the tables
+    /// when the command is planned and scanned from memory.
+    pub fn plan(&self, tables: &TableRegistry) -> Result<LogicalPlan> {
+        let batch = match self {
+            Command::Statement(query) => return sql_plan(query, tables),
+            Command::GetCatalogs => catalogs_batch()?,
+            Command::GetDbSchemas(_) => db_schemas_batch()?,
+            Command::GetTables(command) => tables_batch(command, tables)?,
+            Command::GetTableTypes => table_types_batch()?,
+        };
+        Ok(LogicalPlan::MemoryScan(vec![batch]))
+    }
+}
+
+/// Decode a Flight SQL command, returning `None` for bytes that are not a Flight SQL
+/// message, such as Ballista tickets
+pub fn decode_command(bytes: &[u8]) -> Option<Result<Command>> {
+    let any = Any::decode(&mut Cursor::new(bytes)).ok()?;
+    if !any.type_url.starts_with(TYPE_URL_PREFIX) {
+        return None;
+    }
+    let value = any.value.as_slice();
+    let command = match &any.type_url[TYPE_URL_PREFIX.len()..] {
+        "CommandStatementQuery" => decode_message::<protobuf::CommandStatementQuery>(value)
+            .map(|command| Command::Statement(command.query)),
+        "TicketStatementQuery" => decode_message::<protobuf::TicketStatementQuery>(value)
+            .and_then(|ticket| statement_query(ticket.statement_handle))
+            .map(Command::Statement),
+        "CommandPreparedStatementQuery" => {
+            decode_message::<protobuf::CommandPreparedStatementQuery>(value)
+                .and_then(|command| statement_query(command.prepared_statement_handle))
+                .map(Command::Statement)
+        }
+        "CommandGetCatalogs" => Ok(Command::GetCatalogs),
+        "CommandGetDbSchemas" => decode_message(value).map(Command::GetDbSchemas),
+        "CommandGetTables" => decode_message(value).map(Command::GetTables),
+        "CommandGetTableTypes" => Ok(Command::GetTableTypes),
+        other => Err(BallistaError::NotImplemented(format!(
+            "Unsupported Flight SQL command: {}",
+            other
+        ))),
+    };
+    Some(command)
+}
+
+/// Plan a SQL query against the registered tables and views
+pub fn sql_plan(sql: &str, tables: &TableRegistry) -> Result<LogicalPlan> {
+    tables.sql_plan(sql)
+}
+
+/// Perform a `CreatePreparedStatement` action, returning the body of its result
+pub fn create_prepared_statement(body: &[u8], tables: &TableRegistry) -> Result<Vec<u8>> {
+    let request: protobuf::ActionCreatePreparedStatementRequest =
+        decode_any(body, "ActionCreatePreparedStatementRequest")?;
+    let plan = sql_plan(&request.query, tables)?;
+    encode_any(
+        "ActionCreatePreparedStatementResult",
+        &protobuf::ActionCreatePreparedStatementResult {
+            prepared_statement_handle: request.query.into_bytes(),
+            dataset_schema: schema_bytes(plan.schema()),
+            parameter_schema: vec![],
+        },
+    )
+}
+
+/// Check the body of a `ClosePreparedStatement` action. Prepared statements hold no state,
+/// so there is nothing to release.
+pub fn close_prepared_statement(body: &[u8]) -> Result<()> {
+    let _: protobuf::ActionClosePreparedStatementRequest =
+        decode_any(body, "ActionClosePreparedStatementRequest")?;
+    Ok(())
+}
+
+/// A schema serialized as an IPC message, as schemas are sent in Flight SQL results
+pub fn schema_bytes(schema: &Schema) -> Vec<u8> {
+    SchemaResult::from(schema).schema
+}
+
+/// Whether a value matches a SQL `LIKE` pattern, in which `%` matches any sequence of
+/// characters and `_` matches any single character
+pub fn like(pattern: &str, value: &str) -> bool {
+    fn matches(pattern: &[char], value: &[char]) -> bool {
+        match pattern.split_first() {
+            None => value.is_empty(),
+            Some(('%', rest)) => (0..=value.len()).any(|i| matches(rest, &value[i..])),
+            Some(('_', rest)) => !value.is_empty() && matches(rest, &value[1..]),
+            Some((c, rest)) => value.first() == Some(c) && matches(rest, &value[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    matches(&pattern, &value)
+}
+
+/// The tables that match a `GetTables` command and the batch of their metadata. Tables
+/// have no catalog or schema, which only match missing names and patterns that match an
+/// empty name.
+fn tables_batch(
+    command: &protobuf::CommandGetTables,
+    tables: &TableRegistry,
+) -> Result<RecordBatch> {
+    let pattern_matches = |pattern: &str, name: &str| pattern.is_empty() || like(pattern, name);
+    let type_matches = command.table_types.is_empty()
+        || command
+            .table_types
+            .iter()
+            .any(|table_type| table_type.eq_ignore_ascii_case(TABLE_TYPE));
+    let names: Vec<String> = if command.catalog.is_empty()
+        && pattern_matches(&command.db_schema_filter_pattern, "")
+        && type_matches
+    {
+        tables
+            .names()
+            .into_iter()
+            .filter(|name| pattern_matches(&command.table_name_filter_pattern, name))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let mut fields = vec![
+        Field::new("catalog_name", DataType::Utf8, true),
+        Field::new("db_schema_name", DataType::Utf8, true),
+        Field::new("table_name", DataType::Utf8, false),
+        Field::new("table_type", DataType::Utf8, false),
+    ];
+    let nulls: Vec<Option<&str>> = names.iter().map(|_| None).collect();
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(nulls.clone())),
+        Arc::new(StringArray::from(nulls)),
+        Arc::new(StringArray::from(
+            names.iter().map(|name| name.as_str()).collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            names.iter().map(|_| TABLE_TYPE).collect::<Vec<_>>(),
+        )),
+    ];
+    if command.include_schema {
+        let schemas = names
+            .iter()
+            .map(|name| Ok(schema_bytes(tables.get(name)?.schema().as_ref())))
+            .collect::<Result<Vec<_>>>()?;
+        fields.push(Field::new("table_schema", DataType::Binary, false));
+        columns.push(Arc::new(BinaryArray::from(
+            schemas
+                .iter()
+                .map(|schema| schema.as_slice())
+                .collect::<Vec<_>>(),
+        )));
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+fn catalogs_batch() -> Result<RecordBatch> {
+    let schema = Schema::new(vec![Field::new("catalog_name", DataType::Utf8, false)]);
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![Arc::new(StringArray::from(Vec::<&str>::new()))],
+    )?)
+}
+
+fn db_schemas_batch() -> Result<RecordBatch> {
+    let schema = Schema::new(vec![
+        Field::new("catalog_name", DataType::Utf8, true),
+        Field::new("db_schema_name", DataType::Utf8, false),
+    ]);
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(StringArray::from(Vec::<Option<&str>>::new())),
+            Arc::new(StringArray::from(Vec::<&str>::new())),
+        ],
+    )?)
+}
+
+fn table_types_batch() -> Result<RecordBatch> {
+    let schema = Schema::new(vec![Field::new("table_type", DataType::Utf8, false)]);
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![Arc::new(StringArray::from(vec![TABLE_TYPE]))],
+    )?)
+}
+
+/// The query of a statement handle
+fn statement_query(handle: Vec<u8>) -> Result<String> {
+    String::from_utf8(handle).map_err(|_| ballista_error("Invalid Flight SQL statement handle"))
+}
+
+fn decode_message<M: Message + Default>(bytes: &[u8]) -> Result<M> {
+    M::decode(&mut Cursor::new(bytes)).map_err(BallistaError::from)
+}
+
+/// Decode a Flight SQL message of the given type that is wrapped in an `Any`
+fn decode_any<M: Message + Default>(bytes: &[u8], name: &str) -> Result<M> {
+    let any: Any = decode_message(bytes)?;
+    if any.type_url != format!("{}{}", TYPE_URL_PREFIX, name) {
+        return Err(ballista_error(&format!(
+            "Expected a Flight SQL {} but received {}",
+            name, any.type_url
+        )));
+    }
+    decode_message(&any.value)
+}
+
+/// Encode a Flight SQL message of the given type wrapped in an `Any`
+fn encode_any<M: Message>(name: &str, message: &M) -> Result<Vec<u8>> {
+    let mut value = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut value)?;
+    let any = Any {
+        type_url: format!("{}{}", TYPE_URL_PREFIX, name),
+        value,
+    };
+    let mut bytes = Vec::with_capacity(any.encoded_len());
+    any.encode(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafusion::datasource::MemTable;
+
+    #[test]
+    fn match_like_patterns() {
+        assert!(like("%", ""));
+        assert!(like("cust%", "customer"));
+        assert!(like("%_id", "order_id"));
+        assert!(like("t_", "t1"));
+        assert!(!like("t_", "t"));
+        assert!(!like("cust%", "orders"));
+    }
+
+    #[test]
+    fn get_tables_command() -> Result<()> {
+        let tables = TableRegistry::new();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        for name in &["customer", "orders"] {
+            tables.register(name, Arc::new(MemTable::new(schema.clone(), vec![])?));
+        }
+        let command = Command::GetTables(protobuf::CommandGetTables {
+            table_name_filter_pattern: "cust%".to_owned(),
+            include_schema: true,
+            ..Default::default()
+        });
+
+        let decoded = decode_command(&command.ticket()?).unwrap()?;
+        assert_eq!(command, decoded);
+        match decoded.plan(&tables)? {
+            LogicalPlan::MemoryScan(batches) => {
+                assert_eq!(5, batches[0].num_columns());
+                let names = batches[0]
+                    .column(2)
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .unwrap();
+                assert_eq!(1, names.len());
+                assert_eq!("customer", names.value(0));
+            }
+            other => panic!("unexpected plan: {:?}", other),
+        }
+
+        assert!(decode_command(b"result-0").is_none());
+        Ok(())
+    }
+}
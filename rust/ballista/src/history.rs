@@ -0,0 +1,289 @@
+//! History of the queries that have run on an executor or scheduler.
+//!
+//! Each query is recorded when it completes, fails or is cancelled, with its plan, how long
+//! it took and the number of rows that it produced. The most recent queries are kept in
+//! memory, and can also be appended to a file so that the history survives restarts. The
+//! history is returned by the `ballista.queryHistory` Flight action and served as JSON from
+//! `/queries` on the HTTP address of executors.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::{ballista_error, Result};
+use crate::plan::Action;
+
+use chrono::Utc;
+use log::warn;
+use serde_json::{json, Value};
+
+/// Number of queries kept in the history unless configured otherwise
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// How a query ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryState {
+    Completed,
+    Failed {
+        error: String,
+    },
+    /// The query was cancelled, or the client disconnected before receiving the results
+    Cancelled,
+}
+
+/// A query that has run
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryRecord {
+    /// The id that the client gave the query, if it sent one
+    pub query_id: Option<String>,
+    /// The kind of action, such as `collect` or `shuffle_write`
+    pub action: String,
+    pub plan: String,
+    /// When the query was received, in milliseconds since the Unix epoch
+    pub started: i64,
+    /// Wall time from receiving the query until it ended
+    pub duration: Duration,
+    /// Rows returned to the client, or written for queries that write their output
+    pub rows: u64,
+    pub state: QueryState,
+}
+
+impl QueryRecord {
+    /// Start recording an action that was received now
+    pub fn new(query_id: Option<&str>, action: &Action) -> Self {
+        let (name, plan) = match action {
+            Action::Collect { plan } => ("collect", format!("{:?}", plan)),
+            Action::ShuffleWrite { plan, .. } => ("shuffle_write", format!("{:?}", plan)),
+            Action::WriteParquet { plan, .. } => ("write_parquet", format!("{:?}", plan)),
+            Action::WriteCsv { plan, .. } => ("write_csv", format!("{:?}", plan)),
+            Action::FetchSchema { path, file_type } => {
+                ("fetch_schema", format!("{} {}", file_type, path))
+            }
+            Action::ListTables => ("list_tables", String::new()),
+        };
+        Self {
+            query_id: query_id.map(|id| id.to_owned()),
+            action: name.to_owned(),
+            plan,
+            started: Utc::now().timestamp_millis(),
+            duration: Duration::default(),
+            rows: 0,
+            state: QueryState::Completed,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        self.to_value().to_string()
+    }
+
+    /// Decode a record that was encoded with `to_json`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| ballista_error(&format!("Invalid query record: {:?}", e)))?;
+        let missing =
+            |name: &str| ballista_error(&format!("Query record has no {}: {}", name, json));
+        let number = |name: &str| value[name].as_i64().ok_or_else(|| missing(name));
+        let string = |name: &str| {
+            value[name]
+                .as_str()
+                .map(|s| s.to_owned())
+                .ok_or_else(|| missing(name))
+        };
+        let state = match string("state")?.as_str() {
+            "completed" => QueryState::Completed,
+            "failed" => QueryState::Failed {
+                error: string("error")?,
+            },
+            "cancelled" => QueryState::Cancelled,
+            other => return Err(ballista_error(&format!("Unknown query state: {}", other))),
+        };
+        Ok(Self {
+            query_id: value["query_id"].as_str().map(|s| s.to_owned()),
+            action: string("action")?,
+            plan: string("plan")?,
+            started: number("started")?,
+            duration: Duration::from_millis(number("duration_ms")? as u64),
+            rows: number("rows")? as u64,
+            state,
+        })
+    }
+
+    fn to_value(&self) -> Value {
+        let (state, error) = match &self.state {
+            QueryState::Completed => ("completed", None),
+            QueryState::Failed { error } => ("failed", Some(error)),
+            QueryState::Cancelled => ("cancelled", None),
+        };
+        json!({
+            "query_id": self.query_id,
+            "action": self.action,
+            "plan": self.plan,
+            "started": self.started,
+            "duration_ms": self.duration.as_millis() as u64,
+            "rows": self.rows,
+            "state": state,
+            "error": error,
+        })
+    }
+}
+
+/// Encode records as a JSON array
+pub fn records_to_json(records: &[QueryRecord]) -> String {
+    Value::Array(records.iter().map(|r| r.to_value()).collect()).to_string()
+}
+
+#[derive(Debug)]
+struct HistoryState {
+    records: VecDeque<QueryRecord>,
+    /// Number of records in the file, which is rewritten with only the records in memory
+    /// when it grows to twice the size of the history
+    persisted: usize,
+}
+
+/// The most recent queries, optionally persisted to a file
+#[derive(Debug)]
+pub struct QueryHistory {
+    capacity: usize,
+    file: Option<PathBuf>,
+    state: Mutex<HistoryState>,
+}
+
+impl QueryHistory {
+    /// Keep the given number of queries in memory
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            file: None,
+            state: Mutex::new(HistoryState {
+                records: VecDeque::new(),
+                persisted: 0,
+            }),
+        }
+    }
+
+    /// Append the queries to a file of JSON records, one per line, loading the most recent
+    /// queries from the file if it exists
+    pub fn with_file(mut self, path: &Path) -> Result<Self> {
+        let mut state = self.state.lock().unwrap();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match QueryRecord::from_json(&line) {
+                    Ok(record) => {
+                        state.records.push_back(record);
+                        if state.records.len() > self.capacity {
+                            state.records.pop_front();
+                        }
+                    }
+                    Err(e) => warn!("Skipping query record in {:?}: {:?}", path, e),
+                }
+                state.persisted += 1;
+            }
+        } else if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        drop(state);
+        self.file = Some(path.to_owned());
+        Ok(self)
+    }
+
+    /// Record a query, dropping the oldest query if the history is full. Failures to write
+    /// the file are logged rather than failing the query.
+    pub fn record(&self, record: QueryRecord) {
+        let mut state = self.state.lock().unwrap();
+        state.records.push_back(record);
+        if state.records.len() > self.capacity {
+            state.records.pop_front();
+        }
+        if let Some(path) = &self.file {
+            let written = if state.persisted + 1 >= 2 * self.capacity {
+                self.rewrite(path, &state.records)
+                    .map(|_| state.persisted = state.records.len())
+            } else {
+                self.append(path, state.records.back().unwrap())
+                    .map(|_| state.persisted += 1)
+            };
+            if let Err(e) = written {
+                warn!("Unable to write query history to {:?}: {:?}", path, e);
+            }
+        }
+    }
+
+    /// The queries in the history, oldest first
+    pub fn records(&self) -> Vec<QueryRecord> {
+        self.state.lock().unwrap().records.iter().cloned().collect()
+    }
+
+    fn append(&self, path: &Path, record: &QueryRecord) -> Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", record.to_json())?;
+        Ok(())
+    }
+
+    /// Replace the file with the records, writing a temporary file first so that the
+    /// history is not lost if the executor stops while the file is written
+    fn rewrite(&self, path: &Path, records: &VecDeque<QueryRecord>) -> Result<()> {
+        let temp = path.with_extension("tmp");
+        let mut file = File::create(&temp)?;
+        for record in records {
+            writeln!(file, "{}", record.to_json())?;
+        }
+        fs::rename(temp, path)?;
+        Ok(())
+    }
+}
+
+impl Default for QueryHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::Schema;
+    use crate::logicalplan::LogicalPlan;
+
+    #[test]
+    fn persist_query_history() -> Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("ballista-history-{}", std::process::id()))
+            .join("queries.json");
+        let _ = fs::remove_file(&path);
+        let history = QueryHistory::new(2).with_file(&path)?;
+        let action = Action::Collect {
+            plan: LogicalPlan::EmptyRelation {
+                schema: Schema::new(vec![]),
+            },
+        };
+        for i in 0..5 {
+            let mut record = QueryRecord::new(Some(&i.to_string()), &action);
+            record.rows = i;
+            if i == 4 {
+                record.state = QueryState::Failed {
+                    error: "Executor failed".to_owned(),
+                };
+            }
+            history.record(record);
+        }
+        let records = history.records();
+        assert_eq!(2, records.len());
+        assert_eq!(3, records[0].rows);
+        assert_eq!("collect", records[0].action);
+
+        // the history is loaded from the file, which was rewritten when it grew too large
+        let loaded = QueryHistory::new(2).with_file(&path)?;
+        assert_eq!(records, loaded.records());
+        let lines = fs::read_to_string(&path)?.lines().count();
+        assert!(lines < 4);
+        fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+}
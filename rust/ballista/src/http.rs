@@ -0,0 +1,106 @@
+//! Minimal HTTP server for the endpoints that executors serve for monitoring.
+//!
+//! The endpoints are only read by Prometheus, load balancers and dashboards, so each
+//! connection serves a single `GET` request and is then closed.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::error::Result;
+
+use log::warn;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The response to a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    /// Status line, such as `200 OK`
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    pub fn not_found(body: String) -> Self {
+        Self {
+            status: "404 Not Found",
+            content_type: "text/plain",
+            body,
+        }
+    }
+}
+
+/// Serve `GET` requests with the handler, which is given the path of each request. The
+/// handler runs on a blocking thread, so it can read files to build its response.
+pub async fn serve<F>(addr: SocketAddr, handler: F) -> Result<()>
+where
+    F: Fn(&str) -> HttpResponse + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let mut listener = TcpListener::bind(addr).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, handler).await {
+                warn!("Unable to serve HTTP request: {:?}", e);
+            }
+        });
+    }
+}
+
+/// Respond to a single HTTP request, closing the connection afterwards
+async fn respond<F>(stream: TcpStream, handler: Arc<F>) -> Result<()>
+where
+    F: Fn(&str) -> HttpResponse + Send + Sync + 'static,
+{
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await?;
+    // the headers are read so that the client does not see the connection reset
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let response = if request_line.starts_with("GET ") {
+        let path = request_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or("")
+            .to_owned();
+        tokio::task::spawn_blocking(move || handler(&path))
+            .await
+            .unwrap_or_else(|e| HttpResponse {
+                status: "500 Internal Server Error",
+                content_type: "text/plain",
+                body: format!("{:?}\n", e),
+            })
+    } else {
+        HttpResponse {
+            status: "405 Method Not Allowed",
+            content_type: "text/plain",
+            body: "Only GET requests are supported\n".to_owned(),
+        }
+    };
+    let text = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    );
+    stream.get_mut().write_all(text.as_bytes()).await?;
+    Ok(())
+}
@@ -0,0 +1,329 @@
+//! Hash joins of relations that have been read into memory.
+//!
+//! DataFusion does not support joins yet, so both inputs of a join are executed and the
+//! rows are joined here. The rows of the left input are indexed by the values of their
+//! join keys, and each batch of the right input is joined by looking up its keys. Rows
+//! with a null key value do not match any rows, as with `=` in SQL, unless the join matches
+//! nulls, in which case null key values match each other as with `<=>`.
+//!
+//! Streaming queries keep the index of a bounded input with `JoinIndex`, and join each batch
+//! of their unbounded input by looking up its keys.
+
+#[cfg(feature = "local-execution")]
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, Schema, SchemaRef};
+use crate::arrow::record_batch::RecordBatch;
+use crate::dictionary;
+use crate::error::{ballista_error, Result};
+
+/// The value of a join key in a row, which only needs to compare equal to values of the
+/// same type
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum KeyValue {
+    Boolean(bool),
+    Int(i64),
+    UInt(u64),
+    /// Floating point values are compared by their bits
+    Float(u64),
+    Utf8(String),
+    /// Null values, which are only keys of joins that match nulls
+    Null,
+}
+
+/// Join the batches of the left and right inputs on pairs of left and right key columns,
+/// producing the columns of the left input followed by the columns of the right input
+pub fn hash_join(
+    left: &[RecordBatch],
+    right: &[RecordBatch],
+    on: &[(usize, usize)],
+    null_equals_null: bool,
+    schema: &Schema,
+) -> Result<Vec<RecordBatch>> {
+    let left_columns: Vec<usize> = on.iter().map(|(l, _)| *l).collect();
+    let right_columns: Vec<usize> = on.iter().map(|(_, r)| *r).collect();
+    let index = JoinIndex::try_new(left.to_vec(), &left_columns, null_equals_null)?;
+    let schema = Arc::new(schema.clone());
+    let mut output = vec![];
+    for batch in right {
+        output.extend(index.join(batch, &right_columns, &schema, true)?);
+    }
+    Ok(output)
+}
+
+/// The rows of a set of batches indexed by the values of their join keys
+pub(crate) struct JoinIndex {
+    batches: Vec<RecordBatch>,
+    /// The rows for each key, as batch and row indices
+    rows: HashMap<Vec<KeyValue>, Vec<(usize, u32)>>,
+    null_equals_null: bool,
+}
+
+impl JoinIndex {
+    /// Index the rows of batches by the values of the given key columns, including the rows
+    /// with null values if nulls match each other
+    pub(crate) fn try_new(
+        batches: Vec<RecordBatch>,
+        columns: &[usize],
+        null_equals_null: bool,
+    ) -> Result<Self> {
+        let mut rows: HashMap<Vec<KeyValue>, Vec<(usize, u32)>> = HashMap::new();
+        for (i, batch) in batches.iter().enumerate() {
+            for (row, key) in row_keys(batch, columns, null_equals_null)?
+                .into_iter()
+                .enumerate()
+            {
+                if let Some(key) = key {
+                    rows.entry(key).or_default().push((i, row as u32));
+                }
+            }
+        }
+        Ok(Self {
+            batches,
+            rows,
+            null_equals_null,
+        })
+    }
+
+    /// Join a batch on the given key columns with the indexed rows, producing the columns
+    /// of the indexed rows first if `indexed_first` is true, or last otherwise
+    pub(crate) fn join(
+        &self,
+        batch: &RecordBatch,
+        columns: &[usize],
+        schema: &SchemaRef,
+        indexed_first: bool,
+    ) -> Result<Vec<RecordBatch>> {
+        // the matching rows are taken from each indexed batch separately
+        let mut indices: Vec<(Vec<u32>, Vec<u32>)> = vec![(vec![], vec![]); self.batches.len()];
+        let keys = row_keys(batch, columns, self.null_equals_null)?;
+        for (row, key) in keys.into_iter().enumerate() {
+            if let Some(rows) = key.and_then(|key| self.rows.get(&key)) {
+                for (i, indexed_row) in rows {
+                    indices[*i].0.push(*indexed_row);
+                    indices[*i].1.push(row as u32);
+                }
+            }
+        }
+
+        let mut output = vec![];
+        for (indexed_batch, (indexed_rows, rows)) in self.batches.iter().zip(indices) {
+            if indexed_rows.is_empty() {
+                continue;
+            }
+            let indexed_rows = UInt32Array::from(indexed_rows);
+            let rows = UInt32Array::from(rows);
+            let mut indexed = Vec::with_capacity(indexed_batch.num_columns());
+            for column in indexed_batch.columns() {
+                indexed.push(dictionary::take(column, &indexed_rows)?);
+            }
+            let mut probed = Vec::with_capacity(batch.num_columns());
+            for column in batch.columns() {
+                probed.push(dictionary::take(column, &rows)?);
+            }
+            let columns = if indexed_first {
+                indexed.into_iter().chain(probed).collect()
+            } else {
+                probed.into_iter().chain(indexed).collect()
+            };
+            output.push(RecordBatch::try_new(schema.clone(), columns)?);
+        }
+        Ok(output)
+    }
+}
+
+macro_rules! key_values {
+    ($column:expr, $array_type:ident, $value:expr) => {{
+        let array = $column.as_any().downcast_ref::<$array_type>().unwrap();
+        (0..array.len())
+            .map(|row| {
+                if array.is_null(row) {
+                    None
+                } else {
+                    Some($value(array.value(row)))
+                }
+            })
+            .collect()
+    }};
+}
+
+/// The value of each row of a column, with `None` for null values, or `None` if values of
+/// the type of the column cannot be used as keys
+pub(crate) fn column_values(column: &ArrayRef) -> Option<Vec<Option<KeyValue>>> {
+    let values = match column.data_type() {
+        DataType::Boolean => key_values!(column, BooleanArray, KeyValue::Boolean),
+        DataType::Int8 => key_values!(column, Int8Array, int),
+        DataType::Int16 => key_values!(column, Int16Array, int),
+        DataType::Int32 => key_values!(column, Int32Array, int),
+        DataType::Int64 => key_values!(column, Int64Array, KeyValue::Int),
+        DataType::UInt8 => key_values!(column, UInt8Array, uint),
+        DataType::UInt16 => key_values!(column, UInt16Array, uint),
+        DataType::UInt32 => key_values!(column, UInt32Array, uint),
+        DataType::UInt64 => key_values!(column, UInt64Array, KeyValue::UInt),
+        DataType::Float32 => key_values!(column, Float32Array, float),
+        DataType::Float64 => key_values!(column, Float64Array, float),
+        DataType::Utf8 => key_values!(column, StringArray, |v: &str| {
+            KeyValue::Utf8(v.to_owned())
+        }),
+        // the rows of dictionary columns have the values of their keys, so that they match
+        // the rows of columns that are not dictionary encoded
+        DataType::Dictionary(_, _) => {
+            let values = column_values(&dictionary::values(column))?;
+            dictionary::key_indices(column)
+                .ok()?
+                .into_iter()
+                .map(|key| key.and_then(|key| values[key].clone()))
+                .collect()
+        }
+        _ => return None,
+    };
+    Some(values)
+}
+
+/// The values of the key columns in each row of a batch. Null values are `KeyValue::Null`
+/// if nulls match each other, and rows where one of the values is null are `None`
+/// otherwise.
+fn row_keys(
+    batch: &RecordBatch,
+    columns: &[usize],
+    null_equals_null: bool,
+) -> Result<Vec<Option<Vec<KeyValue>>>> {
+    let mut keys = vec![Some(Vec::with_capacity(columns.len())); batch.num_rows()];
+    for column in columns {
+        let column = batch.column(*column);
+        let values = column_values(column).ok_or_else(|| {
+            ballista_error(&format!(
+                "Cannot join on a column of type {:?}",
+                column.data_type()
+            ))
+        })?;
+        for (key, value) in keys.iter_mut().zip(values) {
+            match value {
+                Some(value) => {
+                    if let Some(key) = key {
+                        key.push(value);
+                    }
+                }
+                None if null_equals_null => {
+                    if let Some(key) = key {
+                        key.push(KeyValue::Null);
+                    }
+                }
+                None => *key = None,
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Compare values of the same type, treating values of different types as equal
+#[cfg(feature = "local-execution")]
+pub(crate) fn compare_values(a: &KeyValue, b: &KeyValue) -> Ordering {
+    match (a, b) {
+        (KeyValue::Boolean(a), KeyValue::Boolean(b)) => a.cmp(b),
+        (KeyValue::Int(a), KeyValue::Int(b)) => a.cmp(b),
+        (KeyValue::UInt(a), KeyValue::UInt(b)) => a.cmp(b),
+        (KeyValue::Float(a), KeyValue::Float(b)) => f64::from_bits(*a)
+            .partial_cmp(&f64::from_bits(*b))
+            .unwrap_or(Ordering::Equal),
+        (KeyValue::Utf8(a), KeyValue::Utf8(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+fn int<T: Into<i64>>(value: T) -> KeyValue {
+    KeyValue::Int(value.into())
+}
+
+fn uint<T: Into<u64>>(value: T) -> KeyValue {
+    KeyValue::UInt(value.into())
+}
+
+fn float<T: Into<f64>>(value: T) -> KeyValue {
+    KeyValue::Float(value.into().to_bits())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::Field;
+
+    #[test]
+    fn join_on_key() -> Result<()> {
+        let left_schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, true),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let right_schema = Arc::new(Schema::new(vec![
+            Field::new("customer_id", DataType::Int32, false),
+            Field::new("amount", DataType::Float64, false),
+        ]));
+        let left = RecordBatch::try_new(
+            left_schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![Some(1), Some(2), None])),
+                Arc::new(StringArray::from(vec!["a", "b", "c"])),
+            ],
+        )?;
+        let right = RecordBatch::try_new(
+            right_schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![2, 3, 1, 2])),
+                Arc::new(Float64Array::from(vec![1.0, 2.0, 3.0, 4.0])),
+            ],
+        )?;
+        let mut fields = left_schema.fields().clone();
+        fields.extend(right_schema.fields().clone());
+        let schema = Schema::new(fields);
+
+        let output = hash_join(&[left], &[right], &[(0, 0)], false, &schema)?;
+        assert_eq!(1, output.len());
+        let batch = &output[0];
+        assert_eq!(3, batch.num_rows());
+        assert_eq!(4, batch.num_columns());
+        let names = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let amounts = batch
+            .column(3)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        let rows: Vec<(&str, f64)> = (0..batch.num_rows())
+            .map(|i| (names.value(i), amounts.value(i)))
+            .collect();
+        assert_eq!(vec![("b", 1.0), ("a", 3.0), ("b", 4.0)], rows);
+        Ok(())
+    }
+
+    #[test]
+    fn join_matching_nulls() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Utf8, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(StringArray::from(vec![Some("a"), None]))],
+        )?;
+        let joined = Schema::new(vec![
+            Field::new("id", DataType::Utf8, true),
+            Field::new("id", DataType::Utf8, true),
+        ]);
+        let rows = |null_equals_null| -> Result<usize> {
+            let output = hash_join(
+                &[batch.clone()],
+                &[batch.clone()],
+                &[(0, 0)],
+                null_equals_null,
+                &joined,
+            )?;
+            Ok(output.iter().map(|b| b.num_rows()).sum())
+        };
+        assert_eq!(rows(false)?, 1);
+        assert_eq!(rows(true)?, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Conversion of results to rows of JSON values.
+//!
+//! Services that return the results of queries as JSON convert each row of the result
+//! batches to a JSON object keyed by the names of the columns, either with `batches_to_json`
+//! or by collecting the results of a query as JSON with `DataFrame::collect_json`. Remote
+//! contexts ask the executor to convert the results, with the `COLLECT_JSON_ACTION_TYPE`
+//! Flight action, and receive a result message for each row.
+//!
+//! Numbers are JSON numbers, except that floating point values that are not finite are
+//! null. Arrow has no decimal type, so decimals are read as `Utf8` columns of their exact
+//! text (see `datasource::parquet`) and are strings, which keeps all of their digits. Dates
+//! are `YYYY-MM-DD` strings and timestamps are RFC 3339 strings in UTC, so that results are
+//! formatted in the same way whichever context ran the query. Binary values are base64
+//! strings, lists are arrays, structs are objects and dictionary columns have the values of
+//! their dictionaries.
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::dictionary;
+use crate::error::{BallistaError, Result};
+use crate::timezone::format_timestamp;
+
+use chrono::{Duration, FixedOffset, NaiveDate};
+use serde_json::{Map, Value};
+
+/// The JSON object of each row of the batches, in order
+pub fn batches_to_json(batches: &[RecordBatch]) -> Result<Vec<Value>> {
+    let mut rows = vec![];
+    for batch in batches {
+        rows.extend(batch_to_json(batch)?);
+    }
+    Ok(rows)
+}
+
+/// The JSON object of each row of a batch
+pub fn batch_to_json(batch: &RecordBatch) -> Result<Vec<Value>> {
+    let mut rows = vec![Map::new(); batch.num_rows()];
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        for (row, value) in rows.iter_mut().zip(json_values(column)?) {
+            row.insert(field.name().clone(), value);
+        }
+    }
+    Ok(rows.into_iter().map(Value::Object).collect())
+}
+
+/// The JSON value of each row of a column
+pub fn json_values(column: &ArrayRef) -> Result<Vec<Value>> {
+    macro_rules! values {
+        ($ARRAY:ty) => {
+            values!($ARRAY, Value::from)
+        };
+        ($ARRAY:ty, $TO_JSON:expr) => {{
+            let array = column.as_any().downcast_ref::<$ARRAY>().unwrap();
+            Ok((0..array.len())
+                .map(|i| {
+                    if array.is_null(i) {
+                        Value::Null
+                    } else {
+                        $TO_JSON(array.value(i))
+                    }
+                })
+                .collect())
+        }};
+    }
+    let epoch = NaiveDate::from_ymd(1970, 1, 1);
+    let date = |date: Option<NaiveDate>| match date {
+        Some(date) => Value::from(date.format("%Y-%m-%d").to_string()),
+        None => Value::Null,
+    };
+    let utc = FixedOffset::east(0);
+    let timestamp = |value, unit| match format_timestamp(value, unit, &utc) {
+        Some(timestamp) => Value::from(timestamp),
+        None => Value::Null,
+    };
+    match column.data_type() {
+        DataType::Boolean => values!(BooleanArray),
+        DataType::Int8 => values!(Int8Array),
+        DataType::Int16 => values!(Int16Array),
+        DataType::Int32 => values!(Int32Array),
+        DataType::Int64 => values!(Int64Array),
+        DataType::UInt8 => values!(UInt8Array),
+        DataType::UInt16 => values!(UInt16Array),
+        DataType::UInt32 => values!(UInt32Array),
+        DataType::UInt64 => values!(UInt64Array),
+        DataType::Float32 => values!(Float32Array),
+        DataType::Float64 => values!(Float64Array),
+        DataType::Utf8 => values!(StringArray),
+        DataType::Binary => values!(BinaryArray, |v| Value::from(base64::encode(v))),
+        DataType::Date32(_) => values!(Date32Array, |v| date(
+            epoch.checked_add_signed(Duration::days(v as i64))
+        )),
+        DataType::Date64(_) => values!(Date64Array, |v| date(
+            epoch.checked_add_signed(Duration::milliseconds(v))
+        )),
+        DataType::Timestamp(unit, _) => {
+            let to_json = |v| timestamp(v, unit);
+            match unit {
+                TimeUnit::Second => values!(TimestampSecondArray, to_json),
+                TimeUnit::Millisecond => values!(TimestampMillisecondArray, to_json),
+                TimeUnit::Microsecond => values!(TimestampMicrosecondArray, to_json),
+                TimeUnit::Nanosecond => values!(TimestampNanosecondArray, to_json),
+            }
+        }
+        DataType::List(_) => {
+            let lists = column.as_any().downcast_ref::<ListArray>().unwrap();
+            // the values of every list are converted at once
+            let values = json_values(&lists.values())?;
+            Ok((0..lists.len())
+                .map(|i| {
+                    if lists.is_null(i) {
+                        Value::Null
+                    } else {
+                        let start = lists.value_offset(i) as usize;
+                        let end = start + lists.value_length(i) as usize;
+                        Value::Array(values[start..end].to_vec())
+                    }
+                })
+                .collect())
+        }
+        DataType::Struct(fields) => {
+            let structs = column.as_any().downcast_ref::<StructArray>().unwrap();
+            let mut rows = vec![Map::new(); structs.len()];
+            for field in fields {
+                let values = match structs.column_by_name(field.name()) {
+                    Some(values) => json_values(values)?,
+                    None => vec![Value::Null; structs.len()],
+                };
+                for (row, value) in rows.iter_mut().zip(values) {
+                    row.insert(field.name().clone(), value);
+                }
+            }
+            Ok(rows
+                .into_iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    if structs.is_null(i) {
+                        Value::Null
+                    } else {
+                        Value::Object(row)
+                    }
+                })
+                .collect())
+        }
+        DataType::Dictionary(_, _) => {
+            let values = json_values(&dictionary::values(column))?;
+            Ok(dictionary::key_indices(column)?
+                .into_iter()
+                .map(|key| match key {
+                    Some(key) => values[key].clone(),
+                    None => Value::Null,
+                })
+                .collect())
+        }
+        other => Err(BallistaError::NotImplemented(format!(
+            "Converting columns of type {:?} to JSON",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{Field, Schema};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn convert_rows_to_json() -> Result<()> {
+        let structs = StructArray::from(vec![(
+            Field::new("score", DataType::Float64, true),
+            Arc::new(Float64Array::from(vec![Some(1.5), Some(std::f64::NAN)])) as ArrayRef,
+        )]);
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new(
+                "at",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("stats", structs.data_type().clone(), false),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("a"), None])),
+                Arc::new(TimestampMillisecondArray::from(vec![0, 1_500])),
+                Arc::new(structs),
+            ],
+        )?;
+
+        assert_eq!(
+            vec![
+                json!({
+                    "id": 1,
+                    "name": "a",
+                    "at": "1970-01-01T00:00:00+00:00",
+                    "stats": {"score": 1.5}
+                }),
+                json!({
+                    "id": 2,
+                    "name": null,
+                    "at": "1970-01-01T00:00:01.500+00:00",
+                    "stats": {"score": null}
+                }),
+            ],
+            batches_to_json(&[batch])?
+        );
+        Ok(())
+    }
+}
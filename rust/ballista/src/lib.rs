@@ -1,6 +1,7 @@
 //! Ballista is a proof-of-concept distributed compute platform based on Kubernetes and Apache Arrow.
 
 pub use arrow;
+#[cfg(feature = "local-execution")]
 pub use datafusion;
 
 // include the generated protobuf source as a submodule
@@ -11,11 +12,128 @@ pub mod protobuf {
 
 pub const BALLISTA_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+/// Whether the Arrow compute kernels were built with their SIMD paths
+pub const SIMD_ENABLED: bool = cfg!(feature = "simd");
+
+#[cfg(feature = "local-execution")]
+pub mod aggregate;
+#[cfg(feature = "client")]
+pub mod auth;
+#[cfg(feature = "local-execution")]
+pub mod c_data;
+#[cfg(feature = "client")]
+pub mod cancel;
+#[cfg(feature = "local-execution")]
+pub mod checkpoint;
+#[cfg(feature = "local-execution")]
+pub mod cli;
+#[cfg(feature = "client")]
 pub mod client;
+#[cfg(feature = "local-execution")]
 pub mod cluster;
+#[cfg(feature = "client")]
+pub mod compression;
+#[cfg(feature = "local-execution")]
 pub mod dataframe;
+#[cfg(feature = "local-execution")]
+pub mod datasource;
+#[cfg(feature = "plan")]
+pub mod decimal;
+#[cfg(feature = "plan")]
+pub mod dictionary;
+#[cfg(feature = "local-execution")]
+pub mod discovery;
 pub mod error;
+#[cfg(feature = "client")]
+pub mod exchange;
+#[cfg(feature = "client")]
+pub mod execution_metrics;
+#[cfg(feature = "executor-server")]
+pub mod executor;
+#[cfg(feature = "local-execution")]
+pub mod explain;
+#[cfg(feature = "plan")]
+pub mod extension;
+#[cfg(feature = "plan")]
+pub mod file_scan;
+#[cfg(feature = "executor-server")]
+pub mod flight_sql;
+#[cfg(feature = "client")]
+pub mod history;
+#[cfg(feature = "executor-server")]
+pub mod http;
+#[cfg(feature = "plan")]
+pub mod join;
+#[cfg(feature = "local-execution")]
+pub mod json;
+#[cfg(feature = "local-execution")]
+pub mod listener;
+#[cfg(feature = "local-execution")]
+pub mod logic;
+#[cfg(feature = "plan")]
 pub mod logicalplan;
+#[cfg(feature = "plan")]
+pub mod memory;
+#[cfg(feature = "local-execution")]
+pub mod metrics;
+#[cfg(feature = "local-execution")]
+pub mod nested;
+#[cfg(feature = "plan")]
+pub mod normalize;
+#[cfg(feature = "local-execution")]
+pub mod optimizer;
+#[cfg(feature = "local-execution")]
+pub mod physical_plan;
+#[cfg(feature = "plan")]
 pub mod plan;
+#[cfg(feature = "client")]
+pub mod pool;
+#[cfg(feature = "client")]
+pub mod profile;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "local-execution")]
+pub mod report;
+#[cfg(feature = "local-execution")]
+pub mod result_cache;
+#[cfg(feature = "client")]
+pub mod scheduler;
+#[cfg(feature = "plan")]
 pub mod serde;
+#[cfg(feature = "plan")]
+pub mod shuffle;
+#[cfg(feature = "local-execution")]
+pub mod sort;
+#[cfg(feature = "spark")]
+pub mod spark;
+#[cfg(feature = "executor-server")]
+pub mod standalone;
+#[cfg(feature = "local-execution")]
+pub mod statistics;
+#[cfg(feature = "executor-server")]
+pub mod status;
+#[cfg(feature = "client")]
+pub mod stream;
+#[cfg(feature = "local-execution")]
+pub mod streaming;
+#[cfg(feature = "local-execution")]
+pub mod substrait;
+#[cfg(feature = "local-execution")]
+pub mod timezone;
+#[cfg(feature = "client")]
+pub mod tls;
+#[cfg(feature = "local-execution")]
+pub mod tpch;
+#[cfg(feature = "client")]
+pub mod trace;
+#[cfg(feature = "local-execution")]
+pub mod translate;
+#[cfg(feature = "local-execution")]
+pub mod unparser;
 pub mod utils;
+#[cfg(feature = "plan")]
+pub mod validate;
+#[cfg(feature = "plan")]
+pub mod visitor;
+#[cfg(feature = "plan")]
+pub mod window;
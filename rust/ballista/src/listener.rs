@@ -0,0 +1,198 @@
+//! Callbacks for the queries executed by a context.
+//!
+//! Applications register a `QueryListener` with `Context::register_query_listener` to emit
+//! their own metrics or audit events, or to show the progress of queries, without changing
+//! the crate. Listeners are told when each query starts, when each of its stages completes,
+//! and when it ends. The stages of distributed queries are reported once the results have
+//! been received, since executors send the metrics of a stage with the results. Listeners
+//! are called while the query runs, so they should return quickly.
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::execution_metrics::{QueryMetrics, EXECUTE_OPERATOR};
+
+/// A query that is about to be executed
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryStart {
+    pub query_id: String,
+    /// The logical plan of the query
+    pub plan: String,
+}
+
+/// A stage of a query that has completed
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageCompletion {
+    /// The stage of a distributed query, or `None` for queries executed in a single process
+    pub stage: Option<usize>,
+    /// Number of partitions that the stage executed
+    pub partitions: usize,
+    /// Rows produced by the stage
+    pub rows: u64,
+    /// Time spent executing the stage, summed across its partitions
+    pub elapsed: Duration,
+    /// Time spent executing the slowest partition of the stage
+    pub slowest_partition: Duration,
+}
+
+impl StageCompletion {
+    /// The stages of a query described by the metrics of its operators, in order
+    pub fn from_metrics(metrics: &QueryMetrics) -> Vec<Self> {
+        metrics
+            .stages()
+            .into_iter()
+            .map(|stage| {
+                let stage_metrics = metrics.stage(stage);
+                let partitions: Vec<Duration> = stage_metrics
+                    .operators
+                    .iter()
+                    .filter(|m| m.operator == EXECUTE_OPERATOR)
+                    .map(|m| m.elapsed)
+                    .collect();
+                let execute = stage_metrics.total(EXECUTE_OPERATOR);
+                Self {
+                    stage,
+                    partitions: partitions.len(),
+                    rows: execute.rows,
+                    elapsed: execute.elapsed,
+                    slowest_partition: partitions.into_iter().max().unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A query that has completed, failed or been cancelled
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryEnd {
+    pub query_id: String,
+    /// Wall time from starting the query until it ended
+    pub elapsed: Duration,
+    /// Rows returned by the query, if it completed
+    pub rows: Option<usize>,
+    /// The error that the query failed with
+    pub error: Option<String>,
+}
+
+/// Receives the events of the queries executed by a context. All of the methods do nothing
+/// by default, so listeners only implement the events that they need.
+pub trait QueryListener: Send + Sync + fmt::Debug {
+    fn on_query_start(&self, _query: &QueryStart) {}
+
+    fn on_stage_complete(&self, _query_id: &str, _stage: &StageCompletion) {}
+
+    fn on_query_end(&self, _query: &QueryEnd) {}
+}
+
+/// The listeners registered with a context
+#[derive(Default)]
+pub struct QueryListeners {
+    listeners: RwLock<Vec<Arc<dyn QueryListener>>>,
+}
+
+impl QueryListeners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, listener: Arc<dyn QueryListener>) {
+        self.listeners
+            .write()
+            .expect("query listeners lock poisoned")
+            .push(listener);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners
+            .read()
+            .expect("query listeners lock poisoned")
+            .is_empty()
+    }
+
+    pub fn query_started(&self, query: &QueryStart) {
+        self.each(|listener| listener.on_query_start(query));
+    }
+
+    pub fn stage_completed(&self, query_id: &str, stage: &StageCompletion) {
+        self.each(|listener| listener.on_stage_complete(query_id, stage));
+    }
+
+    pub fn query_ended(&self, query: &QueryEnd) {
+        self.each(|listener| listener.on_query_end(query));
+    }
+
+    /// Call each listener, without holding the lock so that listeners can register other
+    /// listeners
+    fn each<F: Fn(&dyn QueryListener)>(&self, f: F) {
+        let listeners = self
+            .listeners
+            .read()
+            .expect("query listeners lock poisoned")
+            .clone();
+        for listener in listeners {
+            f(listener.as_ref());
+        }
+    }
+}
+
+impl fmt::Debug for QueryListeners {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let listeners = self
+            .listeners
+            .read()
+            .expect("query listeners lock poisoned");
+        f.debug_list().entries(listeners.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::arrow::record_batch::RecordBatch;
+    use crate::dataframe::Context;
+    use crate::error::Result;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct RecordingListener {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl QueryListener for RecordingListener {
+        fn on_query_start(&self, _query: &QueryStart) {
+            self.events.lock().unwrap().push("start".to_owned());
+        }
+
+        fn on_stage_complete(&self, _query_id: &str, stage: &StageCompletion) {
+            let event = format!("stage {:?}: {} rows", stage.stage, stage.rows);
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_query_end(&self, query: &QueryEnd) {
+            let event = format!("end: {:?} rows", query.rows);
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[tokio::test]
+    async fn listen_to_local_query() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![1, 2, 3]))])?;
+
+        let ctx = Context::local(HashMap::new());
+        let listener = Arc::new(RecordingListener::default());
+        ctx.register_query_listener(listener.clone());
+        ctx.create_dataframe(&[batch])?.collect().await?;
+
+        let events = listener.events.lock().unwrap().clone();
+        assert_eq!(
+            vec!["start", "stage None: 3 rows", "end: Some(3) rows"],
+            events
+        );
+        Ok(())
+    }
+}
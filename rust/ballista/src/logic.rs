@@ -0,0 +1,298 @@
+//! Three-valued logic of SQL.
+//!
+//! In SQL, `false AND NULL` is false and `true OR NULL` is true, because the result does not
+//! depend on the unknown value, but the boolean kernels of Arrow return null when either
+//! operand is null. `a <=> b`, or `a IS NOT DISTINCT FROM b`, is true when both values are
+//! null and false when only one of them is, where `a = b` is null.
+//!
+//! DataFusion has neither, so projections and selections with these expressions are executed
+//! in steps: a projection of the input computes the operands of the expressions, which are
+//! then evaluated here, and DataFusion evaluates the rest of the projection or selection with
+//! the expressions replaced by their results. `AND` and `OR` are only evaluated here when
+//! one of their operands may be null. Calls of `date_part`, which DataFusion does not have
+//! either, are evaluated in the same way, with the value that they extract a field from as
+//! their operand.
+
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::compute::kernels::cast::cast;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, Result};
+use crate::join::column_values;
+use crate::logicalplan::{get_supertype, Expr, Operator, ScalarValue};
+use crate::nested;
+use crate::timezone::{date_part_args, evaluate_date_part, is_date_part};
+
+/// Whether an expression is evaluated here rather than by DataFusion, on the columns of
+/// the schema
+pub fn is_three_valued(expr: &Expr, schema: &Schema) -> bool {
+    match expr {
+        Expr::BinaryExpr {
+            op: Operator::IsNotDistinctFrom,
+            ..
+        } => true,
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        }
+        | Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => may_be_null(left, schema) || may_be_null(right, schema),
+        _ => false,
+    }
+}
+
+/// Whether an expression on the columns of the schema may evaluate to null
+pub fn may_be_null(expr: &Expr, schema: &Schema) -> bool {
+    match expr {
+        Expr::Column(i) => schema.fields().get(*i).map_or(true, |f| f.is_nullable()),
+        Expr::UnresolvedColumn(name) => schema
+            .field_with_name(name)
+            .map_or(true, |f| f.is_nullable()),
+        Expr::Literal(value) => *value == ScalarValue::Null,
+        Expr::IsNull(_) | Expr::IsNotNull(_) => false,
+        Expr::BinaryExpr {
+            op: Operator::IsNotDistinctFrom,
+            ..
+        } => false,
+        Expr::BinaryExpr { left, right, .. } => {
+            may_be_null(left, schema) || may_be_null(right, schema)
+        }
+        Expr::Alias(e, _) | Expr::Not(e) | Expr::Cast { expr: e, .. } => may_be_null(e, schema),
+        _ => true,
+    }
+}
+
+/// Collect the operands of an expression that is evaluated here, which are the values
+/// that its logical operators combine or compare, without duplicates
+pub fn operands(expr: &Expr, operands: &mut Vec<Expr>) {
+    let push = |e: &Expr, operands: &mut Vec<Expr>| {
+        if !operands.contains(e) {
+            operands.push(e.clone());
+        }
+    };
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        }
+        | Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => {
+            self::operands(left, operands);
+            self::operands(right, operands);
+        }
+        Expr::Not(e) => self::operands(e, operands),
+        Expr::ScalarFunction { args, .. } if is_date_part(expr) && args.len() > 1 => {
+            push(&args[1], operands)
+        }
+        // boolean literals and nulls are evaluated here
+        Expr::Literal(ScalarValue::Boolean(_)) | Expr::Literal(ScalarValue::Null) => {}
+        Expr::BinaryExpr {
+            left,
+            op: Operator::IsNotDistinctFrom,
+            right,
+        } => {
+            for e in &[left, right] {
+                if **e != Expr::Literal(ScalarValue::Null) {
+                    push(e, operands);
+                }
+            }
+        }
+        other => push(other, operands),
+    }
+}
+
+/// Replace the operands of an expression that is evaluated here with the columns that hold
+/// their values, which follow the given number of columns
+pub fn replace_operands(expr: &Expr, operands: &[Expr], offset: usize) -> Expr {
+    let column = |e: &Expr| match operands.iter().position(|o| o == e) {
+        Some(i) => Expr::Column(offset + i),
+        None => e.clone(),
+    };
+    match expr {
+        Expr::BinaryExpr { left, op, right } if *op == Operator::And || *op == Operator::Or => {
+            Expr::BinaryExpr {
+                left: Box::new(replace_operands(left, operands, offset)),
+                op: op.clone(),
+                right: Box::new(replace_operands(right, operands, offset)),
+            }
+        }
+        Expr::Not(e) => Expr::Not(Box::new(replace_operands(e, operands, offset))),
+        Expr::BinaryExpr {
+            left,
+            op: Operator::IsNotDistinctFrom,
+            right,
+        } => Expr::BinaryExpr {
+            left: Box::new(column(left)),
+            op: Operator::IsNotDistinctFrom,
+            right: Box::new(column(right)),
+        },
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } if is_date_part(expr) && args.len() > 1 => {
+            let mut args = args.clone();
+            args[1] = column(&args[1]);
+            Expr::ScalarFunction {
+                name: name.clone(),
+                args,
+                return_type: return_type.clone(),
+            }
+        }
+        other => column(other),
+    }
+}
+
+/// Evaluate an expression whose operands have been replaced by the columns of a batch that
+/// hold their values
+pub fn evaluate(expr: &Expr, batch: &RecordBatch) -> Result<ArrayRef> {
+    let rows = batch.num_rows();
+    match expr {
+        Expr::Literal(ScalarValue::Boolean(b)) => {
+            Ok(Arc::new(BooleanArray::from(vec![Some(*b); rows])))
+        }
+        Expr::Literal(ScalarValue::Null) => Ok(Arc::new(BooleanArray::from(vec![None; rows]))),
+        Expr::Not(e) => {
+            let values = booleans(&evaluate(e, batch)?)?;
+            let values = values
+                .into_iter()
+                .map(|v| v.map(|v| !v))
+                .collect::<Vec<_>>();
+            Ok(Arc::new(BooleanArray::from(values)))
+        }
+        Expr::BinaryExpr { left, op, right } if *op == Operator::And || *op == Operator::Or => {
+            let left = booleans(&evaluate(left, batch)?)?;
+            let right = booleans(&evaluate(right, batch)?)?;
+            let values = left
+                .into_iter()
+                .zip(right)
+                .map(|(l, r)| match op {
+                    Operator::And => and(l, r),
+                    _ => or(l, r),
+                })
+                .collect::<Vec<_>>();
+            Ok(Arc::new(BooleanArray::from(values)))
+        }
+        Expr::BinaryExpr {
+            left,
+            op: Operator::IsNotDistinctFrom,
+            right,
+        } => not_distinct(&evaluate(left, batch)?, &evaluate(right, batch)?),
+        Expr::ScalarFunction { args, .. } if is_date_part(expr) && args.len() > 1 => {
+            let (part, timezone) = date_part_args(args)?;
+            evaluate_date_part(&part, &evaluate(&args[1], batch)?, &timezone)
+        }
+        other => nested::evaluate(other, batch),
+    }
+}
+
+fn and(left: Option<bool>, right: Option<bool>) -> Option<bool> {
+    match (left, right) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+fn or(left: Option<bool>, right: Option<bool>) -> Option<bool> {
+    match (left, right) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether the values of each row of two arrays are equal or both null
+fn not_distinct(left: &ArrayRef, right: &ArrayRef) -> Result<ArrayRef> {
+    // values of different types are compared as values of their common type
+    let (left, right) = match get_supertype(left.data_type(), right.data_type()) {
+        Ok(ref t) if left.data_type() != right.data_type() && *t != DataType::Null => {
+            (cast(left, t)?, cast(right, t)?)
+        }
+        _ => (left.clone(), right.clone()),
+    };
+    let values = |array: &ArrayRef| {
+        column_values(array).ok_or_else(|| {
+            ballista_error(&format!(
+                "Cannot compare values of type {:?}",
+                array.data_type()
+            ))
+        })
+    };
+    let left = values(&left)?;
+    let right = values(&right)?;
+    let result = left
+        .into_iter()
+        .zip(right)
+        .map(|(l, r)| Some(l == r))
+        .collect::<Vec<_>>();
+    Ok(Arc::new(BooleanArray::from(result)))
+}
+
+fn booleans(array: &ArrayRef) -> Result<Vec<Option<bool>>> {
+    let array = array
+        .as_any()
+        .downcast_ref::<BooleanArray>()
+        .ok_or_else(|| {
+            ballista_error(&format!(
+                "Expected a boolean, found {:?}",
+                array.data_type()
+            ))
+        })?;
+    Ok((0..array.len())
+        .map(|i| {
+            if array.is_valid(i) {
+                Some(array.value(i))
+            } else {
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::Field;
+    use crate::logicalplan::col;
+
+    #[test]
+    fn evaluate_three_valued_logic() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Boolean, true),
+            Field::new("b", DataType::Int32, true),
+            Field::new("c", DataType::Int64, true),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(BooleanArray::from(vec![None, Some(false), Some(true)])),
+                Arc::new(Int32Array::from(vec![None, Some(1), Some(2)])),
+                Arc::new(Int64Array::from(vec![None, None, Some(2)])),
+            ],
+        )?;
+        let results = |expr: &Expr| evaluate(expr, &batch).and_then(|a| booleans(&a));
+
+        let null = Expr::Literal(ScalarValue::Null);
+        assert_eq!(
+            results(&col("a").and(&Expr::Literal(ScalarValue::Boolean(false))))?,
+            vec![Some(false); 3]
+        );
+        assert_eq!(results(&col("a").or(&null))?, vec![None, None, Some(true)]);
+        assert_eq!(
+            results(&col("b").is_not_distinct_from(&col("c")))?,
+            vec![Some(true), Some(false), Some(true)]
+        );
+        Ok(())
+    }
+}
@@ -17,22 +17,48 @@
 
 ///! This file was forked from Apache Arrow.
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
-use crate::arrow::datatypes::{DataType, Field, Schema};
-
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
 use crate::arrow::record_batch::RecordBatch;
-use crate::datafusion::datasource::MemTable;
-use crate::datafusion::error::{ExecutionError, Result};
-use crate::datafusion::execution::context::ExecutionContext;
-use crate::datafusion::logicalplan::{
-    Expr as DFExpr, LogicalPlan as DFLogicalPlan, Operator as DFOperator,
-    ScalarValue as DFScalarValue,
-};
+use crate::decimal::{self, is_decimal_arithmetic};
+use crate::error::{BallistaError, Result};
+use crate::extension::UserDefinedLogicalNode;
+use crate::file_scan::{expand_path, CsvReadOptions};
+use crate::shuffle::ShuffleLocation;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    /// The schema of memory scans without any batches
+    static ref EMPTY_SCHEMA: Schema = Schema::empty();
+}
+
+/// The file types that file scans read
+pub const FILE_TYPES: &[&str] = &["csv", "parquet", "json", "avro", "ipc", "orc", "sql"];
+
+/// Deserialize the file type of a file scan, rejecting types that scans cannot read so that
+/// deserialized plans fail when they are decoded rather than when they are executed
+fn deserialize_file_type<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<String, D::Error> {
+    let file_type = String::deserialize(deserializer)?;
+    if FILE_TYPES.contains(&file_type.as_str()) {
+        Ok(file_type)
+    } else {
+        Err(serde::de::Error::unknown_variant(&file_type, FILE_TYPES))
+    }
+}
 
 /// The LogicalPlan represents different types of relations (such as Projection,
 /// Selection, etc) and can be created by the SQL query planner and the DataFrame API.
-#[derive(Clone)]
+///
+/// Plans implement serde, so that they can be persisted, logged and compared in formats
+/// such as JSON. The batches of memory scans are serialized as Arrow IPC streams.
+#[derive(Clone, Serialize, Deserialize)]
 pub enum LogicalPlan {
     /// A Projection (essentially a SELECT with an expression list)
     Projection {
@@ -72,9 +98,20 @@ pub enum LogicalPlan {
     },
     /// A table scan against a table that has been registered on a context
     FileScan {
-        /// The path to the files
+        /// The path to the files, which is the first path of a scan of several paths
         path: String,
-        /// File type (csv, parquet)
+        /// Every path of a scan of several paths, in order, or empty when the scan has a
+        /// single path. Paths are kept apart since they may contain any character.
+        #[serde(default)]
+        paths: Vec<String>,
+        /// The files that the path resolved to, with each file scanned as a partition
+        files: Vec<String>,
+        /// Hive-style partition columns that are derived from the file paths rather than
+        /// read from the files. These are the trailing fields of the schema.
+        partition_columns: Vec<String>,
+        /// File type (csv, parquet, etc). Database tables have the file type `sql`, with the
+        /// connection string as the path and the scan queries in place of the files.
+        #[serde(deserialize_with = "deserialize_file_type")]
         file_type: String,
         /// The underlying table schema
         schema: Schema,
@@ -82,6 +119,27 @@ pub enum LogicalPlan {
         projection: Option<Vec<usize>>,
         /// The projected schema
         projected_schema: Schema,
+        /// Options for parsing CSV files
+        csv_options: Option<CsvReadOptions>,
+        /// Filters on the columns of the table schema that the rows of the scan are
+        /// filtered by. Parquet scans skip the row groups whose statistics show that none
+        /// of their rows match, and the rows are still filtered by the selection above
+        /// the scan.
+        filters: Vec<Expr>,
+        /// The number of rows after which the scan may stop reading, which is pushed down
+        /// from a limit above the scan. The limit itself is still applied above the scan.
+        limit: Option<usize>,
+    },
+    /// A scan of a custom table that has been registered by name
+    TableScan {
+        /// The name that the table was registered as
+        table_name: String,
+        /// The underlying table schema
+        schema: Schema,
+        /// Optional column indices to use as a projection
+        projection: Option<Vec<usize>>,
+        /// The projected schema
+        projected_schema: Schema,
     },
     /// An empty relation with an empty schema
     EmptyRelation {
@@ -97,7 +155,61 @@ pub enum LogicalPlan {
         /// The schema description
         schema: Schema,
     },
-    MemoryScan(Vec<RecordBatch>),
+    MemoryScan(#[serde(with = "crate::serde::batches")] Vec<RecordBatch>),
+    /// An inner join of two relations on the equality of pairs of columns, producing the
+    /// columns of the left relation followed by the columns of the right relation
+    Join {
+        /// The left relation
+        left: Box<LogicalPlan>,
+        /// The right relation
+        right: Box<LogicalPlan>,
+        /// Pairs of column indices in the left and right relations that must be equal
+        on: Vec<(usize, usize)>,
+        /// Whether null values of the join columns are equal to each other, as with `<=>`,
+        /// rather than matching no rows as with `=`
+        #[serde(default)]
+        null_equals_null: bool,
+        /// The schema description
+        schema: Schema,
+    },
+    /// The rows of several relations, with the columns of each relation matched to the
+    /// columns of the schema by name. Columns that a relation does not have are null.
+    Union {
+        /// The relations
+        inputs: Vec<LogicalPlan>,
+        /// The schema description
+        schema: Schema,
+    },
+    /// A hint that a relation is small enough to be sent to every task of a distributed
+    /// join instead of being repartitioned by the join columns
+    Broadcast {
+        /// The relation to broadcast
+        input: Box<LogicalPlan>,
+    },
+    /// The output of a stage of a distributed query. The scheduler replaces this with a
+    /// read of the stage's shuffle partitions before the plan is executed.
+    StageOutput {
+        /// The stage that produces the output
+        stage_id: usize,
+        /// The number of partitions that the output is split into
+        partitions: usize,
+        /// The schema description
+        schema: Schema,
+    },
+    /// A partition of the shuffle output of an earlier stage, which the executor fetches
+    /// from the executors that wrote it
+    ShuffleRead {
+        /// The shuffle partitions written by each task of the earlier stage
+        locations: Vec<ShuffleLocation>,
+        /// The schema description
+        schema: Schema,
+    },
+    /// A custom operator that is implemented outside of Ballista. Extension nodes are
+    /// serialized by the codec that is registered for their name.
+    Extension {
+        #[serde(with = "crate::serde::extension")]
+        node: Arc<dyn UserDefinedLogicalNode>,
+    },
 }
 
 impl LogicalPlan {
@@ -108,17 +220,219 @@ impl LogicalPlan {
             LogicalPlan::FileScan {
                 projected_schema, ..
             } => &projected_schema,
+            LogicalPlan::TableScan {
+                projected_schema, ..
+            } => &projected_schema,
             LogicalPlan::Projection { schema, .. } => &schema,
             LogicalPlan::Selection { input, .. } => input.schema(),
             LogicalPlan::Aggregate { schema, .. } => &schema,
             LogicalPlan::Sort { schema, .. } => &schema,
             LogicalPlan::Limit { schema, .. } => &schema,
-            LogicalPlan::MemoryScan(batches) => (&batches[0]).schema(),
+            LogicalPlan::MemoryScan(batches) => match batches.first() {
+                Some(first) => first.schema().as_ref(),
+                None => &EMPTY_SCHEMA,
+            },
+            LogicalPlan::Join { schema, .. } => &schema,
+            LogicalPlan::Union { schema, .. } => &schema,
+            LogicalPlan::Broadcast { input } => input.schema(),
+            LogicalPlan::StageOutput { schema, .. } => &schema,
+            LogicalPlan::ShuffleRead { schema, .. } => &schema,
+            LogicalPlan::Extension { node } => node.schema(),
+        }
+    }
+
+    /// The inputs of the plan, which are empty for scans
+    pub fn inputs(&self) -> Vec<&LogicalPlan> {
+        match self {
+            LogicalPlan::Projection { input, .. }
+            | LogicalPlan::Selection { input, .. }
+            | LogicalPlan::Aggregate { input, .. }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. }
+            | LogicalPlan::Broadcast { input } => vec![input.as_ref()],
+            LogicalPlan::Join { left, right, .. } => vec![left.as_ref(), right.as_ref()],
+            LogicalPlan::Union { inputs, .. } => inputs.iter().collect(),
+            LogicalPlan::Extension { node } => node.inputs(),
+            _ => vec![],
+        }
+    }
+
+    /// The name of the operator, as used in the spans recorded when the plan is translated
+    pub fn operator_name(&self) -> &'static str {
+        match self {
+            LogicalPlan::Projection { .. } => "Projection",
+            LogicalPlan::Selection { .. } => "Selection",
+            LogicalPlan::Aggregate { .. } => "Aggregate",
+            LogicalPlan::Sort { .. } => "Sort",
+            LogicalPlan::FileScan { .. } => "FileScan",
+            LogicalPlan::TableScan { .. } => "TableScan",
+            LogicalPlan::EmptyRelation { .. } => "EmptyRelation",
+            LogicalPlan::Limit { .. } => "Limit",
+            LogicalPlan::MemoryScan(_) => "MemoryScan",
+            LogicalPlan::Join { .. } => "Join",
+            LogicalPlan::Union { .. } => "Union",
+            LogicalPlan::Broadcast { .. } => "Broadcast",
+            LogicalPlan::StageOutput { .. } => "StageOutput",
+            LogicalPlan::ShuffleRead { .. } => "ShuffleRead",
+            LogicalPlan::Extension { .. } => "Extension",
+        }
+    }
+
+    /// Copy the plan with different inputs, in the same order as returned by `inputs()`
+    pub fn with_new_inputs(&self, mut inputs: Vec<LogicalPlan>) -> LogicalPlan {
+        if let LogicalPlan::Extension { node } = self {
+            return LogicalPlan::Extension {
+                node: node.with_new_inputs(inputs),
+            };
+        }
+        if let LogicalPlan::Union { schema, .. } = self {
+            return LogicalPlan::Union {
+                inputs,
+                schema: schema.clone(),
+            };
+        }
+        if let LogicalPlan::Join {
+            on,
+            null_equals_null,
+            schema,
+            ..
+        } = self
+        {
+            if inputs.len() == 2 {
+                let right = Box::new(inputs.pop().unwrap());
+                let left = Box::new(inputs.pop().unwrap());
+                return LogicalPlan::Join {
+                    left,
+                    right,
+                    on: on.clone(),
+                    null_equals_null: *null_equals_null,
+                    schema: schema.clone(),
+                };
+            }
+            return self.clone();
+        }
+        let input = match inputs.pop() {
+            Some(input) => Box::new(input),
+            None => return self.clone(),
+        };
+        match self {
+            LogicalPlan::Projection { expr, schema, .. } => LogicalPlan::Projection {
+                expr: expr.clone(),
+                input,
+                schema: schema.clone(),
+            },
+            LogicalPlan::Selection { expr, .. } => LogicalPlan::Selection {
+                expr: expr.clone(),
+                input,
+            },
+            LogicalPlan::Aggregate {
+                group_expr,
+                aggr_expr,
+                schema,
+                ..
+            } => LogicalPlan::Aggregate {
+                input,
+                group_expr: group_expr.clone(),
+                aggr_expr: aggr_expr.clone(),
+                schema: schema.clone(),
+            },
+            LogicalPlan::Sort { expr, schema, .. } => LogicalPlan::Sort {
+                expr: expr.clone(),
+                input,
+                schema: schema.clone(),
+            },
+            LogicalPlan::Limit { expr, schema, .. } => LogicalPlan::Limit {
+                expr: expr.clone(),
+                input,
+                schema: schema.clone(),
+            },
+            LogicalPlan::Broadcast { .. } => LogicalPlan::Broadcast { input },
+            other => other.clone(),
         }
     }
 }
 
 impl LogicalPlan {
+    /// Render the plan as a Graphviz DOT graph, with a node for each operator showing its
+    /// key expressions and output schema, and an edge from each input to the operator
+    /// that reads it
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph plan {\n  node [shape=box];\n");
+        self.write_dot(&mut dot, "n", "  ");
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The operators of the plan with the names of their DOT nodes, where the inputs of a
+    /// node are named after the node and the position of the input
+    pub(crate) fn dot_nodes<'a>(&'a self, name: &str, nodes: &mut Vec<(String, &'a LogicalPlan)>) {
+        nodes.push((name.to_owned(), self));
+        for (i, input) in self.inputs().into_iter().enumerate() {
+            input.dot_nodes(&format!("{}_{}", name, i), nodes);
+        }
+    }
+
+    /// Write the nodes and edges of the plan, with the root node given the name
+    pub(crate) fn write_dot(&self, dot: &mut String, name: &str, indent: &str) {
+        let mut nodes = vec![];
+        self.dot_nodes(name, &mut nodes);
+        for (node, plan) in &nodes {
+            dot.push_str(&format!(
+                "{}{} [label=\"{}\"];\n",
+                indent,
+                node,
+                dot_escape(&plan.dot_label())
+            ));
+        }
+        for (node, plan) in &nodes {
+            for i in 0..plan.inputs().len() {
+                dot.push_str(&format!("{}{}_{} -> {};\n", indent, node, i, node));
+            }
+        }
+    }
+
+    /// The description of the operator and its schema in the DOT node of the operator
+    fn dot_label(&self) -> String {
+        let fields: Vec<String> = self
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| format!("{}: {:?}", field.name(), field.data_type()))
+            .collect();
+        format!("{}\nschema: [{}]", self.describe(), fields.join(", "))
+    }
+
+    /// A one line description of the operator, without its inputs
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            LogicalPlan::FileScan {
+                path,
+                paths,
+                files,
+                file_type,
+                ..
+            } => {
+                let paths = if paths.is_empty() {
+                    format!("'{}'", path)
+                } else {
+                    paths
+                        .iter()
+                        .map(|p| format!("'{}'", p))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                format!("FileScan: {} ({}, {} files)", paths, file_type, files.len())
+            }
+            LogicalPlan::TableScan { table_name, .. } => format!("TableScan: '{}'", table_name),
+            // the first line of the plan describes the operator without its inputs
+            other => format!("{:?}", other)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+        }
+    }
+
     fn fmt_with_indent(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
         if indent > 0 {
             writeln!(f)?;
@@ -129,6 +443,10 @@ impl LogicalPlan {
         match *self {
             LogicalPlan::EmptyRelation { .. } => write!(f, "EmptyRelation"),
             LogicalPlan::MemoryScan { .. } => write!(f, "MemoryScan"),
+            LogicalPlan::StageOutput { stage_id, .. } => write!(f, "StageOutput: {}", stage_id),
+            LogicalPlan::ShuffleRead { ref locations, .. } => {
+                write!(f, "ShuffleRead: {} partitions", locations.len())
+            }
             LogicalPlan::FileScan {
                 path: ref table_name,
                 ref projected_schema,
@@ -138,6 +456,15 @@ impl LogicalPlan {
                 "TableScan: '{}'; schema={:?}",
                 table_name, projected_schema
             ),
+            LogicalPlan::TableScan {
+                ref table_name,
+                ref projected_schema,
+                ..
+            } => write!(
+                f,
+                "TableScan: '{}'; schema={:?}",
+                table_name, projected_schema
+            ),
             LogicalPlan::Projection {
                 ref expr,
                 ref input,
@@ -195,10 +522,281 @@ impl LogicalPlan {
                 write!(f, "Limit: {:?}", expr)?;
                 input.fmt_with_indent(f, indent + 1)
             }
+            LogicalPlan::Join {
+                ref left,
+                ref right,
+                ref on,
+                null_equals_null,
+                ..
+            } => {
+                write!(f, "Join: on={:?}", on)?;
+                if null_equals_null {
+                    write!(f, " null_equals_null")?;
+                }
+                left.fmt_with_indent(f, indent + 1)?;
+                right.fmt_with_indent(f, indent + 1)
+            }
+            LogicalPlan::Union { ref inputs, .. } => {
+                write!(f, "Union")?;
+                for input in inputs {
+                    input.fmt_with_indent(f, indent + 1)?;
+                }
+                Ok(())
+            }
+            LogicalPlan::Broadcast { ref input } => {
+                write!(f, "Broadcast")?;
+                input.fmt_with_indent(f, indent + 1)
+            }
+            LogicalPlan::Extension { ref node } => {
+                write!(f, "Extension: {}", node.name())?;
+                for input in node.inputs() {
+                    input.fmt_with_indent(f, indent + 1)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Plans are equal when their operators have the same attributes and equal inputs. Memory
+/// scans are only equal when they scan the same arrays, rather than copies of them, and
+/// extension nodes are equal when they have the same name and encoded state.
+impl PartialEq for LogicalPlan {
+    fn eq(&self, other: &LogicalPlan) -> bool {
+        self.attributes() == other.attributes() && self.inputs() == other.inputs()
+    }
+}
+
+impl Eq for LogicalPlan {}
+
+impl Hash for LogicalPlan {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.attributes().hash(state);
+        for input in self.inputs() {
+            input.hash(state);
+        }
+    }
+}
+
+impl LogicalPlan {
+    /// The attributes of the operator, other than its inputs
+    fn attributes(&self) -> PlanAttributes {
+        match self {
+            LogicalPlan::Projection { expr, schema, .. } => PlanAttributes::Projection {
+                expr,
+                schema: SchemaKey(schema),
+            },
+            LogicalPlan::Selection { expr, .. } => PlanAttributes::Selection { expr },
+            LogicalPlan::Aggregate {
+                group_expr,
+                aggr_expr,
+                schema,
+                ..
+            } => PlanAttributes::Aggregate {
+                group_expr,
+                aggr_expr,
+                schema: SchemaKey(schema),
+            },
+            LogicalPlan::Sort { expr, schema, .. } => PlanAttributes::Sort {
+                expr,
+                schema: SchemaKey(schema),
+            },
+            LogicalPlan::FileScan {
+                path,
+                paths,
+                files,
+                partition_columns,
+                file_type,
+                schema,
+                projection,
+                csv_options,
+                filters,
+                limit,
+                ..
+            } => PlanAttributes::FileScan {
+                path,
+                paths,
+                files,
+                partition_columns,
+                file_type,
+                schema: SchemaKey(schema),
+                projection,
+                csv_options,
+                filters,
+                limit: *limit,
+            },
+            LogicalPlan::TableScan {
+                table_name,
+                schema,
+                projection,
+                ..
+            } => PlanAttributes::TableScan {
+                table_name,
+                schema: SchemaKey(schema),
+                projection,
+            },
+            LogicalPlan::EmptyRelation { schema } => PlanAttributes::EmptyRelation {
+                schema: SchemaKey(schema),
+            },
+            LogicalPlan::Limit { expr, schema, .. } => PlanAttributes::Limit {
+                expr,
+                schema: SchemaKey(schema),
+            },
+            LogicalPlan::MemoryScan(batches) => PlanAttributes::MemoryScan(BatchesKey(batches)),
+            LogicalPlan::Join {
+                on,
+                null_equals_null,
+                schema,
+                ..
+            } => PlanAttributes::Join {
+                on,
+                null_equals_null: *null_equals_null,
+                schema: SchemaKey(schema),
+            },
+            LogicalPlan::Union { schema, .. } => PlanAttributes::Union {
+                schema: SchemaKey(schema),
+            },
+            LogicalPlan::Broadcast { .. } => PlanAttributes::Broadcast,
+            LogicalPlan::StageOutput {
+                stage_id,
+                partitions,
+                schema,
+            } => PlanAttributes::StageOutput {
+                stage_id: *stage_id,
+                partitions: *partitions,
+                schema: SchemaKey(schema),
+            },
+            LogicalPlan::ShuffleRead { locations, schema } => PlanAttributes::ShuffleRead {
+                locations,
+                schema: SchemaKey(schema),
+            },
+            LogicalPlan::Extension { node } => PlanAttributes::Extension {
+                name: node.name(),
+                node: node.encode().ok(),
+            },
         }
     }
 }
 
+/// The attributes of an operator that plans are compared and hashed by. The projected
+/// schemas of scans follow from their schemas and projections.
+#[derive(PartialEq, Hash)]
+enum PlanAttributes<'a> {
+    Projection {
+        expr: &'a [Expr],
+        schema: SchemaKey<'a>,
+    },
+    Selection {
+        expr: &'a Expr,
+    },
+    Aggregate {
+        group_expr: &'a [Expr],
+        aggr_expr: &'a [Expr],
+        schema: SchemaKey<'a>,
+    },
+    Sort {
+        expr: &'a [Expr],
+        schema: SchemaKey<'a>,
+    },
+    FileScan {
+        path: &'a str,
+        paths: &'a [String],
+        files: &'a [String],
+        partition_columns: &'a [String],
+        file_type: &'a str,
+        schema: SchemaKey<'a>,
+        projection: &'a Option<Vec<usize>>,
+        csv_options: &'a Option<CsvReadOptions>,
+        filters: &'a [Expr],
+        limit: Option<usize>,
+    },
+    TableScan {
+        table_name: &'a str,
+        schema: SchemaKey<'a>,
+        projection: &'a Option<Vec<usize>>,
+    },
+    EmptyRelation {
+        schema: SchemaKey<'a>,
+    },
+    Limit {
+        expr: &'a Expr,
+        schema: SchemaKey<'a>,
+    },
+    MemoryScan(BatchesKey<'a>),
+    Join {
+        on: &'a [(usize, usize)],
+        null_equals_null: bool,
+        schema: SchemaKey<'a>,
+    },
+    Union {
+        schema: SchemaKey<'a>,
+    },
+    Broadcast,
+    StageOutput {
+        stage_id: usize,
+        partitions: usize,
+        schema: SchemaKey<'a>,
+    },
+    ShuffleRead {
+        locations: &'a [ShuffleLocation],
+        schema: SchemaKey<'a>,
+    },
+    Extension {
+        name: &'a str,
+        node: Option<Vec<u8>>,
+    },
+}
+
+/// Compares schemas, hashing them by their fields
+struct SchemaKey<'a>(&'a Schema);
+
+impl<'a> PartialEq for SchemaKey<'a> {
+    fn eq(&self, other: &SchemaKey) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<'a> Hash for SchemaKey<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for field in self.0.fields() {
+            field.name().hash(state);
+            field.data_type().hash(state);
+            field.is_nullable().hash(state);
+        }
+    }
+}
+
+/// Compares batches by the arrays that they hold
+struct BatchesKey<'a>(&'a [RecordBatch]);
+
+impl<'a> PartialEq for BatchesKey<'a> {
+    fn eq(&self, other: &BatchesKey) -> bool {
+        self.0.len() == other.0.len()
+            && self.0.iter().zip(other.0.iter()).all(|(a, b)| {
+                a.num_columns() == b.num_columns()
+                    && a.columns()
+                        .iter()
+                        .zip(b.columns().iter())
+                        .all(|(a, b)| Arc::ptr_eq(a, b))
+            })
+    }
+}
+
+impl<'a> Hash for BatchesKey<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for batch in self.0 {
+            batch.num_rows().hash(state);
+        }
+    }
+}
+
+/// Escape text for a quoted DOT string, keeping line breaks
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
 impl fmt::Debug for LogicalPlan {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.fmt_with_indent(f, 0)
@@ -223,17 +821,63 @@ impl LogicalPlanBuilder {
         })
     }
 
+    /// Scan a newline-delimited JSON data source
+    pub fn scan_json(path: &str, schema: &Schema, projection: Option<Vec<usize>>) -> Result<Self> {
+        let projected_schema = projection
+            .clone()
+            // the precision and scale of decimal columns are kept
+            .map(|p| {
+                let fields = p.iter().map(|i| schema.field(*i).clone()).collect();
+                Schema::new_with_metadata(fields, schema.metadata().clone())
+            });
+        Ok(Self::from(&LogicalPlan::FileScan {
+            path: path.to_owned(),
+            paths: vec![],
+            files: vec![path.to_owned()],
+            partition_columns: vec![],
+            file_type: "json".to_owned(),
+            schema: schema.clone(),
+            projected_schema: projected_schema.or(Some(schema.clone())).unwrap(),
+            projection,
+            csv_options: None,
+            filters: vec![],
+            limit: None,
+        }))
+    }
+
     /// Scan a data source
     pub fn scan_csv(path: &str, schema: &Schema, projection: Option<Vec<usize>>) -> Result<Self> {
+        Self::scan_csv_with_options(path, schema, projection, CsvReadOptions::default())
+    }
+
+    /// Scan a CSV data source using the given parsing options. The path may contain glob
+    /// patterns, which are expanded when the plan is built.
+    pub fn scan_csv_with_options(
+        path: &str,
+        schema: &Schema,
+        projection: Option<Vec<usize>>,
+        csv_options: CsvReadOptions,
+    ) -> Result<Self> {
+        let files = expand_path(path)?;
         let projected_schema = projection
             .clone()
-            .map(|p| Schema::new(p.iter().map(|i| schema.field(*i).clone()).collect()));
+            // the precision and scale of decimal columns are kept
+            .map(|p| {
+                let fields = p.iter().map(|i| schema.field(*i).clone()).collect();
+                Schema::new_with_metadata(fields, schema.metadata().clone())
+            });
         Ok(Self::from(&LogicalPlan::FileScan {
             path: path.to_owned(),
+            paths: vec![],
+            files,
+            partition_columns: vec![],
             file_type: "csv".to_owned(),
             schema: schema.clone(),
             projected_schema: projected_schema.or(Some(schema.clone())).unwrap(),
             projection,
+            csv_options: Some(csv_options),
+            filters: vec![],
+            limit: None,
         }))
     }
 
@@ -254,7 +898,8 @@ impl LogicalPlanBuilder {
             expr.clone()
         };
 
-        let schema = Schema::new(exprlist_to_fields(&projected_expr, input_schema)?);
+        let fields = exprlist_to_fields(&projected_expr, input_schema)?;
+        let schema = decimal::schema_with_decimals(fields, &projected_expr, input_schema);
 
         Ok(Self::from(&LogicalPlan::Projection {
             expr: projected_expr,
@@ -294,7 +939,8 @@ impl LogicalPlanBuilder {
         let mut all_fields: Vec<Expr> = group_expr.clone();
         aggr_expr.iter().for_each(|x| all_fields.push(x.clone()));
 
-        let aggr_schema = Schema::new(exprlist_to_fields(&all_fields, self.plan.schema())?);
+        let fields = exprlist_to_fields(&all_fields, self.plan.schema())?;
+        let aggr_schema = decimal::schema_with_decimals(fields, &all_fields, self.plan.schema());
 
         Ok(Self::from(&LogicalPlan::Aggregate {
             input: Box::new(self.plan.clone()),
@@ -304,14 +950,156 @@ impl LogicalPlanBuilder {
         }))
     }
 
+    /// Apply an inner join with another plan on pairs of column indices in this plan and
+    /// the other plan
+    pub fn join(&self, right: &LogicalPlan, on: Vec<(usize, usize)>) -> Result<Self> {
+        self.join_with_options(right, on, JoinOptions::default())
+    }
+
+    /// Apply an inner join with another plan on pairs of column indices in this plan and
+    /// the other plan, with options for how the join columns are compared
+    pub fn join_with_options(
+        &self,
+        right: &LogicalPlan,
+        on: Vec<(usize, usize)>,
+        options: JoinOptions,
+    ) -> Result<Self> {
+        let left_schema = self.plan.schema();
+        let right_schema = right.schema();
+        if on.is_empty() {
+            return Err(BallistaError::General(
+                "A join requires at least one pair of join columns".to_owned(),
+            ));
+        }
+        for (l, r) in &on {
+            if *l >= left_schema.fields().len() || *r >= right_schema.fields().len() {
+                return Err(BallistaError::General(format!(
+                    "Invalid join columns ({}, {})",
+                    l, r
+                )));
+            }
+            // keys are only equal to keys of the same type, including when they are hashed
+            // to shuffle partitions
+            let left_type = left_schema.field(*l).data_type();
+            let right_type = right_schema.field(*r).data_type();
+            if left_type != right_type {
+                return Err(BallistaError::General(format!(
+                    "Cannot join column {} of type {:?} with column {} of type {:?}",
+                    left_schema.field(*l).name(),
+                    left_type,
+                    right_schema.field(*r).name(),
+                    right_type
+                )));
+            }
+        }
+
+        let mut fields = left_schema.fields().clone();
+        fields.extend(right_schema.fields().clone());
+        let metadata = decimal::merged_metadata(&[left_schema, right_schema]);
+        Ok(Self::from(&LogicalPlan::Join {
+            left: Box::new(self.plan.clone()),
+            right: Box::new(right.clone()),
+            on,
+            null_equals_null: options.null_equals_null,
+            schema: Schema::new_with_metadata(fields, metadata),
+        }))
+    }
+
+    /// Mark the plan as small enough to broadcast to every task of a distributed join
+    pub fn broadcast(&self) -> Result<Self> {
+        Ok(Self::from(&LogicalPlan::Broadcast {
+            input: Box::new(self.plan.clone()),
+        }))
+    }
+
+    /// Append the rows of another relation, matching columns by name rather than by
+    /// position. The columns of the union are the columns of this plan followed by the
+    /// columns that only the other relation has. Columns that only one of the relations has
+    /// are an error unless missing columns are allowed, when they are null for the rows of
+    /// the other relation. Columns with different types are cast to their common type.
+    pub fn union_by_name(&self, other: &LogicalPlan, allow_missing_columns: bool) -> Result<Self> {
+        let left_schema = self.plan.schema();
+        let right_schema = other.schema();
+        let missing = |field: &Field, schema: &Schema| {
+            BallistaError::General(format!(
+                "Cannot union by name without allowing missing columns, column '{}' is \
+                 missing from the relation with columns {:?}",
+                field.name(),
+                schema
+                    .fields()
+                    .iter()
+                    .map(|f| f.name().as_str())
+                    .collect::<Vec<_>>()
+            ))
+        };
+        let mut fields = vec![];
+        for field in left_schema.fields() {
+            match right_schema.field_with_name(field.name()) {
+                Ok(other) if other.data_type() == field.data_type() => fields.push(Field::new(
+                    field.name(),
+                    field.data_type().clone(),
+                    field.is_nullable() || other.is_nullable(),
+                )),
+                Ok(other) => {
+                    let data_type =
+                        get_supertype(field.data_type(), other.data_type()).map_err(|_| {
+                            BallistaError::General(format!(
+                                "Cannot union column '{}' of type {:?} with type {:?}",
+                                field.name(),
+                                field.data_type(),
+                                other.data_type()
+                            ))
+                        })?;
+                    fields.push(Field::new(
+                        field.name(),
+                        data_type,
+                        field.is_nullable() || other.is_nullable(),
+                    ));
+                }
+                Err(_) if allow_missing_columns => {
+                    fields.push(Field::new(field.name(), field.data_type().clone(), true))
+                }
+                Err(_) => return Err(missing(field, right_schema)),
+            }
+        }
+        for field in right_schema.fields() {
+            if left_schema.field_with_name(field.name()).is_err() {
+                if !allow_missing_columns {
+                    return Err(missing(field, left_schema));
+                }
+                fields.push(Field::new(field.name(), field.data_type().clone(), true));
+            }
+        }
+
+        // unions of unions read all of their relations at once
+        let mut inputs = match &self.plan {
+            LogicalPlan::Union { inputs, .. } => inputs.clone(),
+            plan => vec![plan.clone()],
+        };
+        inputs.push(other.clone());
+        let metadata = decimal::merged_metadata(&[left_schema, right_schema]);
+        Ok(Self::from(&LogicalPlan::Union {
+            inputs,
+            schema: Schema::new_with_metadata(fields, metadata),
+        }))
+    }
+
     /// Build the plan
     pub fn build(&self) -> Result<LogicalPlan> {
         Ok(self.plan.clone())
     }
 }
 
+/// Options for how the join columns of a join are compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JoinOptions {
+    /// Whether null values of the join columns match each other, as with `<=>`. By default
+    /// rows with a null join value match no rows, as with `=`.
+    pub null_equals_null: bool,
+}
+
 /// Relation expression
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Expr {
     /// An aliased expression
     Alias(Box<Expr>, String),
@@ -370,6 +1158,20 @@ pub enum Expr {
     },
     /// Wildcard
     Wildcard,
+    /// field of a struct
+    GetField {
+        /// The struct expression
+        expr: Box<Expr>,
+        /// Name of the field
+        name: String,
+    },
+    /// element of a list at a zero-based index, which is null when the list is shorter
+    GetIndex {
+        /// The list expression
+        expr: Box<Expr>,
+        /// Index of the element
+        index: usize,
+    },
 }
 
 impl Expr {
@@ -382,7 +1184,12 @@ impl Expr {
             Expr::Literal(l) => Ok(l.get_datatype()),
             Expr::Cast { data_type, .. } => Ok(data_type.clone()),
             Expr::ScalarFunction { return_type, .. } => Ok(return_type.clone()),
+            // aggregates and arithmetic of decimals are decimal text
+            Expr::AggregateFunction { .. } if decimal::aggregate_type(self, schema).is_some() => {
+                Ok(DataType::Utf8)
+            }
             Expr::AggregateFunction { return_type, .. } => Ok(return_type.clone()),
+            Expr::BinaryExpr { .. } if is_decimal_arithmetic(self, schema) => Ok(DataType::Utf8),
             Expr::Not(_) => Ok(DataType::Boolean),
             Expr::IsNull(_) => Ok(DataType::Boolean),
             Expr::IsNotNull(_) => Ok(DataType::Boolean),
@@ -391,7 +1198,9 @@ impl Expr {
                 ref right,
                 ref op,
             } => match op {
-                Operator::Eq | Operator::NotEq => Ok(DataType::Boolean),
+                Operator::Eq | Operator::NotEq | Operator::IsNotDistinctFrom => {
+                    Ok(DataType::Boolean)
+                }
                 Operator::Lt | Operator::LtEq => Ok(DataType::Boolean),
                 Operator::Gt | Operator::GtEq => Ok(DataType::Boolean),
                 Operator::And | Operator::Or => Ok(DataType::Boolean),
@@ -402,9 +1211,29 @@ impl Expr {
                 }
             },
             Expr::Sort { ref expr, .. } => expr.get_type(schema),
-            Expr::Wildcard => Err(ExecutionError::General(
+            Expr::Wildcard => Err(BallistaError::General(
                 "Wildcard expressions are not valid in a logical query plan".to_owned(),
             )),
+            Expr::GetField { expr, name } => match expr.get_type(schema)? {
+                DataType::Struct(fields) => fields
+                    .iter()
+                    .find(|f| f.name() == name)
+                    .map(|f| f.data_type().clone())
+                    .ok_or_else(|| {
+                        BallistaError::General(format!("The struct has no field named {}", name))
+                    }),
+                other => Err(BallistaError::General(format!(
+                    "Cannot access field {} of a value of type {:?}",
+                    name, other
+                ))),
+            },
+            Expr::GetIndex { expr, .. } => match expr.get_type(schema)? {
+                DataType::List(item_type) => Ok(*item_type),
+                other => Err(BallistaError::General(format!(
+                    "Cannot index a value of type {:?}",
+                    other
+                ))),
+            },
         }
     }
 
@@ -421,7 +1250,7 @@ impl Expr {
                 data_type: cast_to_type.clone(),
             })
         } else {
-            Err(ExecutionError::General(format!(
+            Err(BallistaError::General(format!(
                 "Cannot automatically convert {:?} to {:?}",
                 this_type, cast_to_type
             )))
@@ -437,6 +1266,15 @@ impl Expr {
         }
     }
 
+    /// Equal or both null, which unlike `eq` is never null
+    pub fn is_not_distinct_from(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::IsNotDistinctFrom,
+            right: Box::new(other.clone()),
+        }
+    }
+
     /// Not equal
     pub fn not_eq(&self, other: &Expr) -> Expr {
         Expr::BinaryExpr {
@@ -482,6 +1320,60 @@ impl Expr {
         }
     }
 
+    /// Addition
+    pub fn plus(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Plus,
+            right: Box::new(other.clone()),
+        }
+    }
+
+    /// Subtraction
+    pub fn minus(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Minus,
+            right: Box::new(other.clone()),
+        }
+    }
+
+    /// Multiplication
+    pub fn multiply(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Multiply,
+            right: Box::new(other.clone()),
+        }
+    }
+
+    /// Division
+    pub fn divide(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Divide,
+            right: Box::new(other.clone()),
+        }
+    }
+
+    /// Logical AND
+    pub fn and(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::And,
+            right: Box::new(other.clone()),
+        }
+    }
+
+    /// Logical OR
+    pub fn or(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Or,
+            right: Box::new(other.clone()),
+        }
+    }
+
     /// Not
     pub fn not(&self) -> Expr {
         Expr::Not(Box::new(self.clone()))
@@ -491,6 +1383,42 @@ impl Expr {
     pub fn alias(&self, name: &str) -> Expr {
         Expr::Alias(Box::new(self.clone()), name.to_owned())
     }
+
+    /// Sort by the expression, in ascending or descending order
+    pub fn sort(&self, asc: bool) -> Expr {
+        Expr::Sort {
+            expr: Box::new(self.clone()),
+            asc,
+        }
+    }
+
+    /// Field of a struct
+    pub fn field(&self, name: &str) -> Expr {
+        Expr::GetField {
+            expr: Box::new(self.clone()),
+            name: name.to_owned(),
+        }
+    }
+
+    /// Element of a list at a zero-based index
+    pub fn index(&self, index: usize) -> Expr {
+        Expr::GetIndex {
+            expr: Box::new(self.clone()),
+            index,
+        }
+    }
+}
+
+/// Number of elements of a list
+pub fn array_length(e: Expr) -> Expr {
+    scalar_function("array_length", vec![e], DataType::UInt32)
+}
+
+/// A field of a timestamp or date, such as `year`, `month`, `day`, `hour` or `dow` for the
+/// day of the week from Sunday as 0. The fields of timestamps are those of the timestamp
+/// in the session timezone.
+pub fn date_part(part: &str, e: Expr) -> Expr {
+    scalar_function("date_part", vec![lit_str(part), e], DataType::Int32)
 }
 
 /// Create a column expression based on a column index
@@ -508,6 +1436,65 @@ pub fn lit_str(str: &str) -> Expr {
     Expr::Literal(ScalarValue::Utf8(str.to_owned()))
 }
 
+/// Create a literal date expression from a date such as `2020-05-14`
+pub fn lit_date(date: &str) -> Result<Expr> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| BallistaError::General(format!("Invalid date literal: {}", date)))?;
+    let days = date
+        .signed_duration_since(NaiveDate::from_ymd(1970, 1, 1))
+        .num_days();
+    Ok(Expr::Literal(ScalarValue::Date32(days as i32)))
+}
+
+/// Create a literal timestamp expression in milliseconds from a timestamp such as
+/// `2020-05-14 12:30:00` or `2020-05-14T12:30:00.250`
+pub fn lit_timestamp(timestamp: &str) -> Result<Expr> {
+    let parsed = NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.f"))
+        .map_err(|_| BallistaError::General(format!("Invalid timestamp literal: {}", timestamp)))?;
+    Ok(Expr::Literal(ScalarValue::Timestamp(
+        parsed.timestamp_millis(),
+        TimeUnit::Millisecond,
+    )))
+}
+
+/// Create a literal decimal expression from a number such as `123.45`, with the precision
+/// and scale of its digits
+pub fn lit_decimal(decimal: &str) -> Result<Expr> {
+    let invalid = || BallistaError::General(format!("Invalid decimal literal: {}", decimal));
+    let negative = decimal.starts_with('-');
+    let digits = decimal.trim_start_matches(|c| c == '-' || c == '+');
+    if decimal.len() - digits.len() > 1 {
+        return Err(invalid());
+    }
+    let (integer, fraction) = match digits.find('.') {
+        Some(i) => (&digits[..i], &digits[i + 1..]),
+        None => (digits, ""),
+    };
+    if integer.is_empty() && fraction.is_empty()
+        || !integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let unscaled: i128 = format!("{}{}", integer, fraction)
+        .parse()
+        .map_err(|_| invalid())?;
+    let precision = integer.trim_start_matches('0').len() + fraction.len();
+    Ok(Expr::Literal(ScalarValue::Decimal(
+        if negative { -unscaled } else { unscaled },
+        precision.max(1),
+        fraction.len(),
+    )))
+}
+
+/// Create a literal binary expression
+pub fn lit_binary(bytes: &[u8]) -> Expr {
+    Expr::Literal(ScalarValue::Binary(bytes.to_vec()))
+}
+
 /// Create an convenience function representing a unary scalar function
 macro_rules! unary_math_expr {
     ($NAME:expr, $FUNC:ident) => {
@@ -597,12 +1584,14 @@ impl fmt::Debug for Expr {
                 write!(f, ")")
             }
             Expr::Wildcard => write!(f, "*"),
+            Expr::GetField { expr, name } => write!(f, "{:?}.{}", expr, name),
+            Expr::GetIndex { expr, index } => write!(f, "{:?}[{}]", expr, index),
         }
     }
 }
 
 /// Operators applied to expressions
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Operator {
     /// Expressions are equal
     Eq,
@@ -636,10 +1625,13 @@ pub enum Operator {
     Like,
     /// Does not match a wildcard pattern
     NotLike,
+    /// Expressions are equal or both null, like `<=>` or `IS NOT DISTINCT FROM`
+    IsNotDistinctFrom,
 }
 
-/// ScalarValue enumeration
-#[derive(Debug, Clone, PartialEq)]
+/// ScalarValue enumeration. Floating point values are equal when they have the same bits,
+/// so that literals can be compared and hashed like other values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ScalarValue {
     /// null value
     Null,
@@ -667,10 +1659,88 @@ pub enum ScalarValue {
     UInt64(u64),
     /// utf-8 encoded string
     Utf8(String),
+    /// date as the number of days since the UNIX epoch
+    Date32(i32),
+    /// date as the number of milliseconds since the UNIX epoch
+    Date64(i64),
+    /// timestamp without a time zone, in the given unit since the UNIX epoch
+    Timestamp(i64, TimeUnit),
+    /// decimal number as its unscaled value, its precision and its scale, so that
+    /// `Decimal(12345, 5, 2)` is 123.45
+    Decimal(i128, usize, usize),
+    /// binary string
+    Binary(Vec<u8>),
     /// List of scalars packed as a struct
     Struct(Vec<ScalarValue>),
 }
 
+impl PartialEq for ScalarValue {
+    fn eq(&self, other: &ScalarValue) -> bool {
+        use self::ScalarValue::*;
+        match (self, other) {
+            (Null, Null) => true,
+            (Boolean(a), Boolean(b)) => a == b,
+            (Float32(a), Float32(b)) => a.to_bits() == b.to_bits(),
+            (Float64(a), Float64(b)) => a.to_bits() == b.to_bits(),
+            (Int8(a), Int8(b)) => a == b,
+            (Int16(a), Int16(b)) => a == b,
+            (Int32(a), Int32(b)) => a == b,
+            (Int64(a), Int64(b)) => a == b,
+            (UInt8(a), UInt8(b)) => a == b,
+            (UInt16(a), UInt16(b)) => a == b,
+            (UInt32(a), UInt32(b)) => a == b,
+            (UInt64(a), UInt64(b)) => a == b,
+            (Utf8(a), Utf8(b)) => a == b,
+            (Date32(a), Date32(b)) => a == b,
+            (Date64(a), Date64(b)) => a == b,
+            (Timestamp(a, a_unit), Timestamp(b, b_unit)) => a == b && a_unit == b_unit,
+            (Decimal(a, a_precision, a_scale), Decimal(b, b_precision, b_scale)) => {
+                a == b && a_precision == b_precision && a_scale == b_scale
+            }
+            (Binary(a), Binary(b)) => a == b,
+            (Struct(a), Struct(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ScalarValue {}
+
+impl Hash for ScalarValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use self::ScalarValue::*;
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Null => {}
+            Boolean(v) => v.hash(state),
+            Float32(v) => v.to_bits().hash(state),
+            Float64(v) => v.to_bits().hash(state),
+            Int8(v) => v.hash(state),
+            Int16(v) => v.hash(state),
+            Int32(v) => v.hash(state),
+            Int64(v) => v.hash(state),
+            UInt8(v) => v.hash(state),
+            UInt16(v) => v.hash(state),
+            UInt32(v) => v.hash(state),
+            UInt64(v) => v.hash(state),
+            Utf8(v) => v.hash(state),
+            Date32(v) => v.hash(state),
+            Date64(v) => v.hash(state),
+            Timestamp(v, unit) => {
+                v.hash(state);
+                unit.hash(state);
+            }
+            Decimal(v, precision, scale) => {
+                v.hash(state);
+                precision.hash(state);
+                scale.hash(state);
+            }
+            Binary(v) => v.hash(state),
+            Struct(v) => v.hash(state),
+        }
+    }
+}
+
 impl ScalarValue {
     /// Getter for the `DataType` of the value
     pub fn get_datatype(&self) -> DataType {
@@ -687,9 +1757,72 @@ impl ScalarValue {
             ScalarValue::Float32(_) => DataType::Float32,
             ScalarValue::Float64(_) => DataType::Float64,
             ScalarValue::Utf8(_) => DataType::Utf8,
+            ScalarValue::Date32(_) => DataType::Date32(DateUnit::Day),
+            ScalarValue::Date64(_) => DataType::Date64(DateUnit::Millisecond),
+            ScalarValue::Timestamp(_, unit) => DataType::Timestamp(unit, None),
+            // Arrow has no decimal type, so decimals are computed as doubles
+            ScalarValue::Decimal(..) => DataType::Float64,
+            ScalarValue::Binary(_) => DataType::Binary,
             _ => panic!("Cannot treat {:?} as scalar value", self),
         }
     }
+
+    /// Compare two values of the same type, or `None` if they cannot be compared. Decimals
+    /// are compared by value regardless of their scale, and timestamps are compared when
+    /// they have the same unit.
+    pub fn compare(&self, other: &ScalarValue) -> Option<std::cmp::Ordering> {
+        use self::ScalarValue::*;
+        match (self, other) {
+            (Boolean(a), Boolean(b)) => a.partial_cmp(b),
+            (Float32(a), Float32(b)) => a.partial_cmp(b),
+            (Float64(a), Float64(b)) => a.partial_cmp(b),
+            (Int8(a), Int8(b)) => a.partial_cmp(b),
+            (Int16(a), Int16(b)) => a.partial_cmp(b),
+            (Int32(a), Int32(b)) => a.partial_cmp(b),
+            (Int64(a), Int64(b)) => a.partial_cmp(b),
+            (UInt8(a), UInt8(b)) => a.partial_cmp(b),
+            (UInt16(a), UInt16(b)) => a.partial_cmp(b),
+            (UInt32(a), UInt32(b)) => a.partial_cmp(b),
+            (UInt64(a), UInt64(b)) => a.partial_cmp(b),
+            (Utf8(a), Utf8(b)) => a.partial_cmp(b),
+            (Date32(a), Date32(b)) => a.partial_cmp(b),
+            (Date64(a), Date64(b)) => a.partial_cmp(b),
+            (Timestamp(a, a_unit), Timestamp(b, b_unit)) if a_unit == b_unit => a.partial_cmp(b),
+            (Decimal(a, _, a_scale), Decimal(b, _, b_scale)) => {
+                let scale = *a_scale.max(b_scale);
+                let a = rescale_decimal(*a, *a_scale, scale)?;
+                let b = rescale_decimal(*b, *b_scale, scale)?;
+                a.partial_cmp(&b)
+            }
+            (Binary(a), Binary(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+/// Change the scale of an unscaled decimal value to a larger scale, or `None` on overflow
+pub(crate) fn rescale_decimal(value: i128, scale: usize, to_scale: usize) -> Option<i128> {
+    10i128
+        .checked_pow((to_scale - scale) as u32)
+        .and_then(|factor| value.checked_mul(factor))
+}
+
+/// The value of a decimal as a double
+#[cfg(feature = "local-execution")]
+pub(crate) fn decimal_to_f64(value: i128, scale: usize) -> f64 {
+    value as f64 / 10f64.powi(scale as i32)
+}
+
+/// The text of a decimal with the digits of its scale, such as `-0.50`
+pub(crate) fn format_decimal(value: i128, scale: usize) -> String {
+    let digits = format!("{:0>width$}", value.abs(), width = scale + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - scale);
+    let sign = if value < 0 { "-" } else { "" };
+    if scale == 0 {
+        format!("{}{}", sign, integer)
+    } else {
+        format!("{}{}.{}", sign, integer, fraction)
+    }
 }
 
 /// Verify a given type cast can be performed
@@ -755,7 +1888,7 @@ pub fn expr_to_field(e: &Expr, input_schema: &Schema) -> Result<Field> {
             if *i < input_schema_field_count {
                 Ok(input_schema.fields()[*i].clone())
             } else {
-                Err(ExecutionError::General(format!(
+                Err(BallistaError::General(format!(
                     "Column index {} out of bounds for input schema with {} field(s)",
                     *i, input_schema_field_count
                 )))
@@ -767,12 +1900,15 @@ pub fn expr_to_field(e: &Expr, input_schema: &Schema) -> Result<Field> {
             ref return_type,
             ..
         } => Ok(Field::new(&name, return_type.clone(), true)),
-        Expr::AggregateFunction {
-            ref name,
-            ref return_type,
-            ..
-        } => Ok(Field::new(&name, return_type.clone(), true)),
+        Expr::AggregateFunction { ref name, .. } => {
+            Ok(Field::new(&name, e.get_type(input_schema)?, true))
+        }
         Expr::Cast { ref data_type, .. } => Ok(Field::new("cast", data_type.clone(), true)),
+        Expr::GetField { name, .. } => Ok(Field::new(name, e.get_type(input_schema)?, true)),
+        Expr::GetIndex { .. } => Ok(Field::new("element", e.get_type(input_schema)?, true)),
+        Expr::BinaryExpr { .. } if is_decimal_arithmetic(e, input_schema) => {
+            Ok(Field::new("binary_expr", DataType::Utf8, true))
+        }
         Expr::BinaryExpr {
             ref left,
             ref right,
@@ -786,7 +1922,7 @@ pub fn expr_to_field(e: &Expr, input_schema: &Schema) -> Result<Field> {
                 true,
             ))
         }
-        _ => Err(ExecutionError::NotImplemented(format!(
+        _ => Err(BallistaError::NotImplemented(format!(
             "Cannot determine schema type for expression {:?}",
             e
         ))),
@@ -805,7 +1941,7 @@ pub fn get_supertype(l: &DataType, r: &DataType) -> Result<DataType> {
     match _get_supertype(l, r) {
         Some(dt) => Ok(dt),
         None => _get_supertype(r, l).ok_or_else(|| {
-            ExecutionError::InternalError(format!(
+            BallistaError::General(format!(
                 "Failed to determine supertype of {:?} and {:?}",
                 l, r
             ))
@@ -915,181 +2051,39 @@ fn _get_supertype(l: &DataType, r: &DataType) -> Option<DataType> {
     }
 }
 
-/// Translate Ballista plan to DataFusion plan
-pub fn translate_plan(ctx: &mut ExecutionContext, plan: &LogicalPlan) -> Result<DFLogicalPlan> {
-    match plan {
-        LogicalPlan::MemoryScan(batches) => {
-            let table_name = "df_t0"; //TODO generate unique table name
-            let schema = (&batches[0]).schema().as_ref();
-            let provider = MemTable::new(Arc::new(schema.clone()), batches.clone())?;
-            ctx.register_table(table_name, Box::new(provider));
-            Ok(DFLogicalPlan::TableScan {
-                schema_name: "default".to_owned(),
-                table_name: table_name.to_owned(),
-                table_schema: Box::new(schema.clone()),
-                projected_schema: Box::new(schema.clone()),
-                projection: None,
-            })
-        }
-        LogicalPlan::FileScan {
-            path,
-            file_type,
-            schema,
-            projection,
-            projected_schema,
-        } => {
-            //TODO generate unique table name
-            let table_name = "tbd".to_owned();
-
-            match file_type.as_str() {
-                "csv" => ctx.register_csv(&table_name, path.as_str(), schema, true),
-                "parquet" => ctx.register_parquet(&table_name, path.as_str())?,
-                _ => unimplemented!(),
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            Ok(DFLogicalPlan::TableScan {
-                schema_name: "default".to_owned(),
-                table_name: table_name.clone(),
-                table_schema: Box::new(schema.clone()),
-                projected_schema: Box::new(projected_schema.clone()),
-                projection: projection.clone(),
-            })
-        }
-        LogicalPlan::Projection {
-            expr,
-            input,
-            schema,
-        } => Ok(DFLogicalPlan::Projection {
-            expr: expr
-                .iter()
-                .map(|e| translate_expr(e))
-                .collect::<Result<Vec<_>>>()?,
-            input: Box::new(translate_plan(ctx, input)?),
-            schema: Box::new(schema.clone()),
-        }),
-        LogicalPlan::Selection { expr, input } => Ok(DFLogicalPlan::Selection {
-            expr: translate_expr(expr)?,
-            input: Box::new(translate_plan(ctx, input)?),
-        }),
-        LogicalPlan::Aggregate {
-            group_expr,
-            aggr_expr,
-            input,
-            schema,
-        } => Ok(DFLogicalPlan::Aggregate {
-            group_expr: group_expr
-                .iter()
-                .map(|e| translate_expr(e))
-                .collect::<Result<Vec<_>>>()?,
-            aggr_expr: aggr_expr
-                .iter()
-                .map(|e| translate_expr(e))
-                .collect::<Result<Vec<_>>>()?,
-            input: Box::new(translate_plan(ctx, input)?),
-            schema: Box::new(schema.clone()),
-        }),
-        LogicalPlan::Limit {
-            expr,
-            input,
-            schema,
-        } => Ok(DFLogicalPlan::Limit {
-            expr: translate_expr(expr)?,
-            input: Box::new(translate_plan(ctx, input)?),
-            schema: Box::new(schema.clone()),
-        }),
-        other => Err(ExecutionError::General(format!(
-            "Cannot translate operator to DataFusion: {:?}",
-            other
-        ))),
-    }
-}
-
-/// Translate Ballista expression to DataFusion expression
-fn translate_expr(expr: &Expr) -> Result<DFExpr> {
-    match expr {
-        Expr::Alias(expr, alias) => Ok(DFExpr::Alias(
-            Box::new(translate_expr(expr.as_ref())?),
-            alias.clone(),
-        )),
-        Expr::Column(index) => Ok(DFExpr::Column(*index)),
-        Expr::UnresolvedColumn(name) => Ok(DFExpr::UnresolvedColumn(name.clone())),
-        Expr::Literal(value) => {
-            let value = translate_scalar_value(value)?;
-            Ok(DFExpr::Literal(value.clone()))
-        }
-        Expr::BinaryExpr { left, op, right } => {
-            let left = translate_expr(left)?;
-            let right = translate_expr(right)?;
-            let op = translate_operator(op)?;
-            Ok(DFExpr::BinaryExpr {
-                left: Box::new(left),
-                op,
-                right: Box::new(right),
-            })
-        }
-        Expr::AggregateFunction {
-            name,
-            args,
-            return_type,
-        } => {
-            let args = args
-                .iter()
-                .map(|e| translate_expr(e))
-                .collect::<Result<Vec<_>>>()?;
-            Ok(DFExpr::AggregateFunction {
-                name: name.to_owned(),
-                args,
-                return_type: return_type.clone(),
-            })
-        }
-        other => Err(ExecutionError::General(format!(
-            "Cannot translate expression to DataFusion: {:?}",
-            other
-        ))),
-    }
-}
-
-fn translate_operator(op: &Operator) -> Result<DFOperator> {
-    match op {
-        Operator::Eq => Ok(DFOperator::Eq),
-        Operator::NotEq => Ok(DFOperator::NotEq),
-        Operator::Lt => Ok(DFOperator::Lt),
-        Operator::LtEq => Ok(DFOperator::LtEq),
-        Operator::Gt => Ok(DFOperator::Gt),
-        Operator::GtEq => Ok(DFOperator::GtEq),
-        Operator::And => Ok(DFOperator::And),
-        Operator::Or => Ok(DFOperator::Or),
-        Operator::Plus => Ok(DFOperator::Plus),
-        Operator::Minus => Ok(DFOperator::Minus),
-        Operator::Multiply => Ok(DFOperator::Multiply),
-        Operator::Divide => Ok(DFOperator::Divide),
-        Operator::Like => Ok(DFOperator::Like),
-        Operator::NotLike => Ok(DFOperator::NotLike),
-        Operator::Modulus => Ok(DFOperator::Modulus),
-        other => Err(ExecutionError::General(format!(
-            "Cannot translate binary operator to DataFusion: {:?}",
-            other
-        ))),
-    }
-}
-
-fn translate_scalar_value(value: &ScalarValue) -> Result<DFScalarValue> {
-    match value {
-        ScalarValue::Boolean(v) => Ok(DFScalarValue::Boolean(*v)),
-        ScalarValue::UInt8(v) => Ok(DFScalarValue::UInt8(*v)),
-        ScalarValue::UInt16(v) => Ok(DFScalarValue::UInt16(*v)),
-        ScalarValue::UInt32(v) => Ok(DFScalarValue::UInt32(*v)),
-        ScalarValue::UInt64(v) => Ok(DFScalarValue::UInt64(*v)),
-        ScalarValue::Int8(v) => Ok(DFScalarValue::Int8(*v)),
-        ScalarValue::Int16(v) => Ok(DFScalarValue::Int16(*v)),
-        ScalarValue::Int32(v) => Ok(DFScalarValue::Int32(*v)),
-        ScalarValue::Int64(v) => Ok(DFScalarValue::Int64(*v)),
-        ScalarValue::Float32(v) => Ok(DFScalarValue::Float32(*v)),
-        ScalarValue::Float64(v) => Ok(DFScalarValue::Float64(*v)),
-        ScalarValue::Utf8(v) => Ok(DFScalarValue::Utf8(v.clone())),
-        other => Err(ExecutionError::General(format!(
-            "Cannot translate scalar value to DataFusion: {:?}",
-            other
-        ))),
+    #[test]
+    fn compare_temporal_and_decimal_literals() -> Result<()> {
+        use std::cmp::Ordering;
+        let literal = |e: Expr| match e {
+            Expr::Literal(value) => value,
+            other => panic!("{:?} is not a literal", other),
+        };
+        assert_eq!(literal(lit_date("1970-01-02")?), ScalarValue::Date32(1));
+        assert_eq!(
+            literal(lit_timestamp("1970-01-01T00:00:01.5")?),
+            ScalarValue::Timestamp(1500, TimeUnit::Millisecond)
+        );
+        assert_eq!(
+            literal(lit_decimal("-0.050")?),
+            ScalarValue::Decimal(-50, 3, 3)
+        );
+        assert!(lit_decimal("1.2.3").is_err());
+        assert_eq!(format_decimal(-50, 3), "-0.050");
+
+        // decimals compare by value regardless of their scale
+        let a = literal(lit_decimal("1.5")?);
+        let b = literal(lit_decimal("1.50")?);
+        assert_ne!(a, b);
+        assert_eq!(a.compare(&b), Some(Ordering::Equal));
+        assert_eq!(
+            ScalarValue::Timestamp(1, TimeUnit::Second)
+                .compare(&ScalarValue::Timestamp(1, TimeUnit::Millisecond)),
+            None
+        );
+        Ok(())
     }
 }
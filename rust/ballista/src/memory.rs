@@ -0,0 +1,349 @@
+//! Memory budgets for executors.
+//!
+//! Queries reserve the memory that they need from the executor's `MemoryTracker` before
+//! they start, and the reservation is released when the query completes. When the executor
+//! has a memory limit, queries wait until the running queries release enough memory, and
+//! fail with a clear error if they need more memory than the limit or wait for too long,
+//! rather than running and exhausting the memory of the executor.
+//!
+//! Operators that Ballista executes while a query is planned, such as joins, read their
+//! inputs and results into memory. The query's `QueryMemory` reserves the memory of these
+//! batches from the tracker as they are read, failing the query if it is not available, and
+//! holds the reservations of the results until the query is dropped.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::arrow::array::ArrayData;
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, BallistaError, Result};
+
+use serde_json::{json, Value};
+
+/// How often queries that are waiting for memory check whether it has been released
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The memory in use on an executor
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryUsage {
+    /// The memory limit in bytes, or `None` if memory is not limited
+    pub limit: Option<usize>,
+    /// Bytes reserved by running queries
+    pub used: usize,
+    /// Number of reservations held by running queries
+    pub reservations: usize,
+    /// Number of queries waiting for memory to be released
+    pub queued: usize,
+}
+
+impl MemoryUsage {
+    /// Encode the usage as the JSON that is returned by the executor status action
+    pub fn to_json(&self) -> String {
+        self.to_value().to_string()
+    }
+
+    /// Decode usage that was encoded with `to_json`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| ballista_error(&format!("Invalid executor status: {:?}", e)))?;
+        Self::from_value(&value)
+    }
+
+    pub(crate) fn to_value(&self) -> Value {
+        json!({
+            "memory_limit": self.limit,
+            "memory_used": self.used,
+            "reservations": self.reservations,
+            "queued": self.queued,
+        })
+    }
+
+    pub(crate) fn from_value(value: &Value) -> Result<Self> {
+        let number = |name: &str| {
+            value[name].as_u64().map(|n| n as usize).ok_or_else(|| {
+                ballista_error(&format!("Executor status has no {}: {}", name, value))
+            })
+        };
+        Ok(Self {
+            limit: value["memory_limit"].as_u64().map(|n| n as usize),
+            used: number("memory_used")?,
+            reservations: number("reservations")?,
+            queued: number("queued")?,
+        })
+    }
+}
+
+/// Tracks the memory reserved by the queries running on an executor
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    limit: Option<usize>,
+    usage: Mutex<MemoryUsage>,
+}
+
+impl MemoryTracker {
+    /// Create a tracker that limits the memory that can be reserved, or only tracks the
+    /// memory that is reserved if there is no limit
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            usage: Mutex::new(MemoryUsage {
+                limit,
+                used: 0,
+                reservations: 0,
+                queued: 0,
+            }),
+        }
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        self.usage.lock().unwrap().clone()
+    }
+
+    /// Reserve memory if it is available now
+    pub fn try_reserve(self: &Arc<Self>, bytes: usize) -> Result<MemoryReservation> {
+        self.check_limit(bytes)?;
+        if self.reserve_now(bytes) {
+            Ok(self.reservation(bytes))
+        } else {
+            let usage = self.usage();
+            Err(ballista_error(&format!(
+                "Unable to reserve {} bytes of memory: {} of the {} bytes available to the \
+                 executor are in use",
+                bytes,
+                usage.used,
+                self.limit.unwrap_or_default()
+            )))
+        }
+    }
+
+    /// Reserve memory, waiting for up to `timeout` for running queries to release it
+    pub async fn reserve(
+        self: &Arc<Self>,
+        bytes: usize,
+        timeout: Duration,
+    ) -> Result<MemoryReservation> {
+        self.check_limit(bytes)?;
+        if self.reserve_now(bytes) {
+            return Ok(self.reservation(bytes));
+        }
+
+        self.usage.lock().unwrap().queued += 1;
+        let start = Instant::now();
+        let reserved = loop {
+            tokio::time::delay_for(POLL_INTERVAL).await;
+            if self.reserve_now(bytes) {
+                break true;
+            }
+            if start.elapsed() > timeout {
+                break false;
+            }
+        };
+        self.usage.lock().unwrap().queued -= 1;
+        if reserved {
+            Ok(self.reservation(bytes))
+        } else {
+            Err(ballista_error(&format!(
+                "Timed out after {:?} waiting for {} bytes of memory to be released",
+                timeout, bytes
+            )))
+        }
+    }
+
+    /// Queries that need more memory than the limit can never run
+    fn check_limit(&self, bytes: usize) -> Result<()> {
+        match self.limit {
+            Some(limit) if bytes > limit => Err(BallistaError::General(format!(
+                "Query needs {} bytes of memory, which exceeds the executor memory limit of {} \
+                 bytes",
+                bytes, limit
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn reserve_now(&self, bytes: usize) -> bool {
+        let mut usage = self.usage.lock().unwrap();
+        if self
+            .limit
+            .map(|limit| usage.used + bytes > limit)
+            .unwrap_or(false)
+        {
+            return false;
+        }
+        usage.used += bytes;
+        usage.reservations += 1;
+        true
+    }
+
+    fn reservation(self: &Arc<Self>, bytes: usize) -> MemoryReservation {
+        MemoryReservation {
+            tracker: self.clone(),
+            bytes,
+        }
+    }
+}
+
+/// Memory reserved from a `MemoryTracker`, which is released when the reservation is
+/// dropped
+#[derive(Debug)]
+pub struct MemoryReservation {
+    tracker: Arc<MemoryTracker>,
+    bytes: usize,
+}
+
+impl MemoryReservation {
+    /// Number of bytes reserved
+    pub fn size(&self) -> usize {
+        self.bytes
+    }
+
+    /// Reserve more memory, failing if it is not available now
+    pub fn try_grow(&mut self, bytes: usize) -> Result<()> {
+        let tracker = &self.tracker;
+        let mut usage = tracker.usage.lock().unwrap();
+        if let Some(limit) = tracker.limit {
+            if usage.used + bytes > limit {
+                return Err(ballista_error(&format!(
+                    "Unable to reserve {} more bytes of memory: {} of the {} bytes available \
+                     to the executor are in use",
+                    bytes, usage.used, limit
+                )));
+            }
+        }
+        usage.used += bytes;
+        self.bytes += bytes;
+        Ok(())
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        let mut usage = self.tracker.usage.lock().unwrap();
+        usage.used -= self.bytes;
+        usage.reservations -= 1;
+    }
+}
+
+/// The memory of the batches that a query reads into memory while it is planned
+#[derive(Debug, Clone)]
+pub struct QueryMemory {
+    tracker: Arc<MemoryTracker>,
+    /// The reservations of the batches that are held until the query is dropped
+    held: Arc<Mutex<Vec<MemoryReservation>>>,
+}
+
+impl QueryMemory {
+    /// Reserve the memory of a query from a tracker
+    pub fn new(tracker: Arc<MemoryTracker>) -> Self {
+        Self {
+            tracker,
+            held: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Reserve the memory of batches that are only used while the query is planned, which
+    /// is released when the reservation is dropped
+    pub fn reserve(&self, batches: &[RecordBatch]) -> Result<MemoryReservation> {
+        self.tracker
+            .try_reserve(batches.iter().map(batch_memory_size).sum())
+    }
+
+    /// Reserve the memory of batches that the query holds until it is dropped
+    pub fn hold(&self, batches: &[RecordBatch]) -> Result<()> {
+        let reservation = self.reserve(batches)?;
+        self.held.lock().unwrap().push(reservation);
+        Ok(())
+    }
+
+    /// Bytes held until the query is dropped
+    pub fn held(&self) -> usize {
+        self.held.lock().unwrap().iter().map(|r| r.size()).sum()
+    }
+}
+
+impl Default for QueryMemory {
+    /// Memory that is tracked without a limit
+    fn default() -> Self {
+        Self::new(Arc::new(MemoryTracker::default()))
+    }
+}
+
+/// The memory used by the buffers of a batch
+pub fn batch_memory_size(batch: &RecordBatch) -> usize {
+    batch
+        .columns()
+        .iter()
+        .map(|column| array_memory_size(&column.data()))
+        .sum()
+}
+
+fn array_memory_size(data: &ArrayData) -> usize {
+    let buffers: usize = data.buffers().iter().map(|buffer| buffer.len()).sum();
+    let nulls = data.null_buffer().map(|buffer| buffer.len()).unwrap_or(0);
+    let children: usize = data
+        .child_data()
+        .iter()
+        .map(|child| array_memory_size(child))
+        .sum();
+    buffers + nulls + children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reserve_within_limit() -> Result<()> {
+        let tracker = Arc::new(MemoryTracker::new(Some(100)));
+        assert!(tracker.try_reserve(101).is_err());
+
+        let mut first = tracker.try_reserve(60)?;
+        assert!(tracker.try_reserve(50).is_err());
+        assert!(first.try_grow(50).is_err());
+        first.try_grow(10)?;
+        assert_eq!(70, tracker.usage().used);
+
+        // queries wait for memory to be released
+        let waiting = {
+            let tracker = tracker.clone();
+            tokio::spawn(async move {
+                tracker
+                    .reserve(50, Duration::from_secs(5))
+                    .await
+                    .map(|reservation| reservation.size())
+            })
+        };
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+        assert_eq!(1, tracker.usage().queued);
+        drop(first);
+        assert_eq!(50, waiting.await.unwrap()?);
+
+        let usage = tracker.usage();
+        assert_eq!(0, usage.used);
+        assert_eq!(0, usage.reservations);
+        assert_eq!(usage, MemoryUsage::from_json(&usage.to_json())?);
+        Ok(())
+    }
+
+    #[test]
+    fn hold_query_memory() -> Result<()> {
+        use crate::arrow::array::Int64Array;
+        use crate::arrow::datatypes::{DataType, Field, Schema};
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from(vec![1; 64]))])?;
+        let size = batch_memory_size(&batch);
+        let tracker = Arc::new(MemoryTracker::new(Some(size * 2)));
+        let memory = QueryMemory::new(tracker.clone());
+        memory.hold(&[batch.clone()])?;
+        {
+            let _input = memory.reserve(&[batch.clone()])?;
+            assert!(memory.hold(&[batch.clone()]).is_err());
+        }
+        memory.hold(&[batch])?;
+        assert_eq!(size * 2, memory.held());
+        drop(memory);
+        assert_eq!(0, tracker.usage().used);
+        Ok(())
+    }
+}
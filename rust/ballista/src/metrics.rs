@@ -0,0 +1,381 @@
+//! Metrics for monitoring Ballista clusters with Prometheus.
+//!
+//! Executors count the queries that they run, the data that the queries scan and return,
+//! the time taken by each task, the shuffle data that is written and fetched, and the
+//! memory in use. The metrics are served in the Prometheus text format from `/metrics` on
+//! the address configured with `Server::with_http_addr`.
+//!
+//! Clients count the queries that they submit and the results that they receive. Clients
+//! are usually short-lived, so rather than being scraped their metrics are pushed to the
+//! Prometheus Pushgateway configured with `ballista.metrics.pushGateway` after each query.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::{ballista_error, Result};
+use crate::memory::MemoryTracker;
+
+/// URL of a Prometheus Pushgateway that clients push their metrics to after each query
+pub const METRICS_PUSH_GATEWAY: &str = "ballista.metrics.pushGateway";
+
+/// Job name that client metrics are grouped under in the Pushgateway
+pub const METRICS_JOB_NAME: &str = "ballista.metrics.jobName";
+
+/// Job name used when `ballista.metrics.jobName` is not set
+pub const DEFAULT_JOB_NAME: &str = "ballista_client";
+
+/// Content type of metrics in the Prometheus text format
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Upper bounds in seconds of the buckets of duration histograms
+const DURATION_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// A value that only increases, such as the number of queries executed
+#[derive(Debug)]
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "counter");
+        let _ = writeln!(out, "{} {}", self.name, self.get());
+    }
+}
+
+/// A value that can go up and down, such as the memory in use
+#[derive(Debug)]
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicI64,
+}
+
+impl Gauge {
+    pub fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicI64::new(0),
+        }
+    }
+
+    pub fn set(&self, value: i64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "gauge");
+        let _ = writeln!(out, "{} {}", self.name, self.get());
+    }
+}
+
+#[derive(Debug)]
+struct HistogramState {
+    /// Number of observations in each bucket, not including the smaller buckets
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// The distribution of observed values, such as task durations, counted in buckets
+#[derive(Debug)]
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    bounds: Vec<f64>,
+    state: Mutex<HistogramState>,
+}
+
+impl Histogram {
+    /// Create a histogram with buckets for the given upper bounds, in increasing order
+    pub fn new(name: &'static str, help: &'static str, bounds: &[f64]) -> Self {
+        Self {
+            name,
+            help,
+            bounds: bounds.to_vec(),
+            state: Mutex::new(HistogramState {
+                counts: vec![0; bounds.len()],
+                sum: 0.0,
+                count: 0,
+            }),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(i) = self.bounds.iter().position(|bound| value <= *bound) {
+            state.counts[i] += 1;
+        }
+        state.sum += value;
+        state.count += 1;
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    /// Number of values observed
+    pub fn count(&self) -> u64 {
+        self.state.lock().unwrap().count
+    }
+
+    fn render(&self, out: &mut String) {
+        let state = self.state.lock().unwrap();
+        header(out, self.name, self.help, "histogram");
+        // the buckets of Prometheus histograms are cumulative
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&state.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                self.name, bound, cumulative
+            );
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", self.name, state.count);
+        let _ = writeln!(out, "{}_sum {}", self.name, state.sum);
+        let _ = writeln!(out, "{}_count {}", self.name, state.count);
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Metrics of the queries run by an executor
+#[derive(Debug)]
+pub struct ExecutorMetrics {
+    pub queries: Counter,
+    pub failed_queries: Counter,
+    /// Bytes of the files and in-memory batches read by queries
+    pub bytes_scanned: Counter,
+    /// Rows and bytes returned to clients or written as the output of tasks
+    pub rows_output: Counter,
+    pub bytes_output: Counter,
+    pub task_duration: Histogram,
+    pub shuffle_bytes_written: Counter,
+    pub shuffle_bytes_fetched: Counter,
+    memory_used: Gauge,
+    memory_limit: Gauge,
+}
+
+impl ExecutorMetrics {
+    pub fn new() -> Self {
+        Self {
+            queries: Counter::new("ballista_queries_total", "Queries executed"),
+            failed_queries: Counter::new("ballista_queries_failed_total", "Queries that failed"),
+            bytes_scanned: Counter::new(
+                "ballista_scanned_bytes_total",
+                "Bytes of input data scanned by queries",
+            ),
+            rows_output: Counter::new("ballista_output_rows_total", "Rows output by queries"),
+            bytes_output: Counter::new("ballista_output_bytes_total", "Bytes output by queries"),
+            task_duration: Histogram::new(
+                "ballista_task_duration_seconds",
+                "Time taken to execute each query or task",
+                DURATION_BUCKETS,
+            ),
+            shuffle_bytes_written: Counter::new(
+                "ballista_shuffle_written_bytes_total",
+                "Bytes of shuffle partitions written",
+            ),
+            shuffle_bytes_fetched: Counter::new(
+                "ballista_shuffle_fetched_bytes_total",
+                "Bytes of shuffle partitions fetched from other executors",
+            ),
+            memory_used: Gauge::new(
+                "ballista_memory_used_bytes",
+                "Memory reserved by running queries",
+            ),
+            memory_limit: Gauge::new(
+                "ballista_memory_limit_bytes",
+                "Memory limit of the executor, or zero if memory is not limited",
+            ),
+        }
+    }
+
+    /// Render the metrics in the Prometheus text format, with the current memory usage of
+    /// the executor
+    pub fn render(&self, memory: &MemoryTracker) -> String {
+        let usage = memory.usage();
+        self.memory_used.set(usage.used as i64);
+        self.memory_limit.set(usage.limit.unwrap_or(0) as i64);
+
+        let mut out = String::new();
+        self.queries.render(&mut out);
+        self.failed_queries.render(&mut out);
+        self.bytes_scanned.render(&mut out);
+        self.rows_output.render(&mut out);
+        self.bytes_output.render(&mut out);
+        self.task_duration.render(&mut out);
+        self.shuffle_bytes_written.render(&mut out);
+        self.shuffle_bytes_fetched.render(&mut out);
+        self.memory_used.render(&mut out);
+        self.memory_limit.render(&mut out);
+        out
+    }
+}
+
+impl Default for ExecutorMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Metrics of the queries submitted by a client
+#[derive(Debug)]
+pub struct ClientMetrics {
+    pub queries: Counter,
+    pub failed_queries: Counter,
+    /// Queries that were answered from the result cache without being executed
+    pub cached_queries: Counter,
+    pub rows_received: Counter,
+    pub bytes_received: Counter,
+    pub query_duration: Histogram,
+}
+
+impl ClientMetrics {
+    pub fn new() -> Self {
+        Self {
+            queries: Counter::new("ballista_client_queries_total", "Queries submitted"),
+            failed_queries: Counter::new(
+                "ballista_client_queries_failed_total",
+                "Queries that failed",
+            ),
+            cached_queries: Counter::new(
+                "ballista_client_cached_queries_total",
+                "Queries answered from the result cache",
+            ),
+            rows_received: Counter::new(
+                "ballista_client_received_rows_total",
+                "Rows received in query results",
+            ),
+            bytes_received: Counter::new(
+                "ballista_client_received_bytes_total",
+                "Bytes received in query results",
+            ),
+            query_duration: Histogram::new(
+                "ballista_client_query_duration_seconds",
+                "Time taken for queries to return their results",
+                DURATION_BUCKETS,
+            ),
+        }
+    }
+
+    /// Render the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.queries.render(&mut out);
+        self.failed_queries.render(&mut out);
+        self.cached_queries.render(&mut out);
+        self.rows_received.render(&mut out);
+        self.bytes_received.render(&mut out);
+        self.query_duration.render(&mut out);
+        out
+    }
+
+    /// Push the metrics to the Pushgateway configured in the Context settings, if any. The
+    /// metrics replace those previously pushed for the same job.
+    pub fn push(&self, settings: &HashMap<String, String>) -> Result<()> {
+        let gateway = match settings.get(METRICS_PUSH_GATEWAY) {
+            Some(gateway) => gateway,
+            None => return Ok(()),
+        };
+        let job = settings
+            .get(METRICS_JOB_NAME)
+            .map(|job| job.as_str())
+            .unwrap_or(DEFAULT_JOB_NAME);
+        let url = format!("{}/metrics/job/{}", gateway.trim_end_matches('/'), job);
+        reqwest::Client::new()
+            .put(&url)
+            .body(self.render())
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ballista_error(&format!("Unable to push metrics to {}: {:?}", url, e)))?;
+        Ok(())
+    }
+}
+
+impl Default for ClientMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "executor-server"))]
+mod tests {
+    use super::*;
+    use crate::http::{self, HttpResponse};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn serve_executor_metrics() -> Result<()> {
+        let metrics = Arc::new(ExecutorMetrics::new());
+        metrics.queries.inc();
+        metrics.bytes_output.inc_by(100);
+        metrics.task_duration.observe(0.2);
+        metrics.task_duration.observe(20.0);
+        let memory = Arc::new(MemoryTracker::new(Some(1000)));
+        let _reservation = memory.try_reserve(300)?;
+
+        let text = metrics.render(&memory);
+        assert!(text.contains("# TYPE ballista_queries_total counter\nballista_queries_total 1\n"));
+        assert!(text.contains("ballista_output_bytes_total 100\n"));
+        assert!(text.contains("ballista_task_duration_seconds_bucket{le=\"0.1\"} 0\n"));
+        assert!(text.contains("ballista_task_duration_seconds_bucket{le=\"0.5\"} 1\n"));
+        assert!(text.contains("ballista_task_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("ballista_task_duration_seconds_count 2\n"));
+        assert!(text.contains("ballista_memory_used_bytes 300\n"));
+        assert!(text.contains("ballista_memory_limit_bytes 1000\n"));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        drop(listener);
+        tokio::spawn(http::serve(addr, move |_| {
+            HttpResponse::ok(PROMETHEUS_CONTENT_TYPE, metrics.render(&memory))
+        }));
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(&text));
+        Ok(())
+    }
+}
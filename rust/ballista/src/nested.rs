@@ -0,0 +1,154 @@
+//! Access to the fields of structs and the elements of lists.
+//!
+//! DataFusion cannot evaluate field access, list indexing or `array_length`, so projections
+//! and selections with these expressions are executed in two steps: the nested accesses are
+//! evaluated here as columns that follow the columns of the input, and DataFusion evaluates
+//! the rest of the expressions with the accesses replaced by references to those columns.
+
+use crate::arrow::array::*;
+use crate::arrow::compute::kernels::take::take;
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::Expr;
+
+/// Whether an expression is itself a nested access
+pub fn is_nested_access(expr: &Expr) -> bool {
+    match expr {
+        Expr::GetField { .. } | Expr::GetIndex { .. } => true,
+        Expr::ScalarFunction { name, .. } => name == "array_length",
+        _ => false,
+    }
+}
+
+/// Evaluate a nested access over a batch. The value that is accessed must be a column or
+/// another nested access.
+pub fn evaluate(expr: &Expr, batch: &RecordBatch) -> Result<ArrayRef> {
+    match expr {
+        Expr::Column(i) if *i < batch.num_columns() => Ok(batch.column(*i).clone()),
+        Expr::UnresolvedColumn(name) => Ok(batch.column(batch.schema().index_of(name)?).clone()),
+        Expr::Alias(expr, _) => evaluate(expr, batch),
+        Expr::GetField { expr, name } => get_field(&evaluate(expr, batch)?, name),
+        Expr::GetIndex { expr, index } => get_index(&evaluate(expr, batch)?, *index),
+        Expr::ScalarFunction { name, args, .. } if name == "array_length" && args.len() == 1 => {
+            array_length(&evaluate(&args[0], batch)?)
+        }
+        other => Err(ballista_error(&format!(
+            "Nested access to {:?} is not supported",
+            other
+        ))),
+    }
+}
+
+/// The values of a field of a struct array, which are null where the struct is null
+fn get_field(array: &ArrayRef, name: &str) -> Result<ArrayRef> {
+    let structs = array
+        .as_any()
+        .downcast_ref::<StructArray>()
+        .ok_or_else(|| {
+            ballista_error(&format!(
+                "Cannot access field {} of {:?}",
+                name,
+                array.data_type()
+            ))
+        })?;
+    let field = structs
+        .column_by_name(name)
+        .ok_or_else(|| ballista_error(&format!("Struct has no field {}", name)))?;
+    if structs.null_count() == 0 {
+        return Ok(field.clone());
+    }
+    let indices = (0..structs.len())
+        .map(|i| {
+            if structs.is_valid(i) {
+                Some(i as u32)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(take(field, &UInt32Array::from(indices), None)?)
+}
+
+/// The element at a zero-based index of each list, which is null where the list is null or
+/// shorter than the index
+fn get_index(array: &ArrayRef, index: usize) -> Result<ArrayRef> {
+    let lists = as_lists(array)?;
+    let indices = (0..lists.len())
+        .map(|i| {
+            if lists.is_valid(i) && index < lists.value_length(i) as usize {
+                Some((lists.value_offset(i) as usize + index) as u32)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(take(&lists.values(), &UInt32Array::from(indices), None)?)
+}
+
+/// The number of elements of each list
+fn array_length(array: &ArrayRef) -> Result<ArrayRef> {
+    let lists = as_lists(array)?;
+    let lengths = (0..lists.len())
+        .map(|i| {
+            if lists.is_valid(i) {
+                Some(lists.value_length(i) as u32)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(std::sync::Arc::new(UInt32Array::from(lengths)))
+}
+
+fn as_lists(array: &ArrayRef) -> Result<&ListArray> {
+    array
+        .as_any()
+        .downcast_ref::<ListArray>()
+        .ok_or_else(|| ballista_error(&format!("Expected a list, found {:?}", array.data_type())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::buffer::Buffer;
+    use crate::arrow::datatypes::{DataType, Field, Schema, ToByteSlice};
+    use crate::logicalplan::{array_length, col};
+    use std::sync::Arc;
+
+    #[test]
+    fn evaluate_field_access_and_list_indexing() -> Result<()> {
+        // [[1, 2], [], [3]]
+        let values = ArrayData::builder(DataType::Int32)
+            .len(3)
+            .add_buffer(Buffer::from(&[1, 2, 3].to_byte_slice()))
+            .build();
+        let list_type = DataType::List(Box::new(DataType::Int32));
+        let lists = ListArray::from(
+            ArrayData::builder(list_type.clone())
+                .len(3)
+                .add_buffer(Buffer::from(&[0, 2, 2, 3].to_byte_slice()))
+                .add_child_data(values)
+                .build(),
+        );
+        let structs = StructArray::from(vec![(
+            Field::new("b", list_type.clone(), true),
+            Arc::new(lists) as ArrayRef,
+        )]);
+        let schema = Schema::new(vec![Field::new("a", structs.data_type().clone(), true)]);
+        let batch = RecordBatch::try_new(Arc::new(schema), vec![Arc::new(structs)])?;
+
+        let first = evaluate(&col("a").field("b").index(0), &batch)?;
+        let first = first.as_any().downcast_ref::<Int32Array>().unwrap();
+        assert_eq!(first.value(0), 1);
+        assert!(first.is_null(1));
+        assert_eq!(first.value(2), 3);
+
+        let lengths = evaluate(&array_length(col("a").field("b")), &batch)?;
+        let lengths = lengths.as_any().downcast_ref::<UInt32Array>().unwrap();
+        assert_eq!(
+            (0..3).map(|i| lengths.value(i)).collect::<Vec<_>>(),
+            vec![2, 0, 1]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+//! Canonical forms of logical plans.
+//!
+//! Plans that are built in different ways can compute the same result, such as filters
+//! whose conditions are written in a different order or refer to columns by name rather
+//! than by index. Normalizing a plan rewrites its expressions into a canonical form so that
+//! such plans compare and hash as equal: columns are referenced by index, the terms of
+//! conjunctions and disjunctions are sorted and repeated terms are removed, the operands of
+//! commutative operators are sorted with literals on the right of comparisons, and floating
+//! point literals have a single zero and NaN. The filters of scans are sorted and
+//! deduplicated in the same way. Normalized plans compute the same result as the original
+//! plans and keep their schemas.
+
+use crate::arrow::datatypes::Schema;
+use crate::logicalplan::{Expr, LogicalPlan, Operator, ScalarValue};
+
+/// Rewrite the expressions of a plan into their canonical form
+pub fn normalize_plan(plan: &LogicalPlan) -> LogicalPlan {
+    let plan = plan.with_new_inputs(plan.inputs().into_iter().map(normalize_plan).collect());
+    match plan {
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => LogicalPlan::Projection {
+            expr: normalize_exprs(&expr, input.schema()),
+            input,
+            schema,
+        },
+        LogicalPlan::Selection { expr, input } => LogicalPlan::Selection {
+            expr: normalize_expr(&expr, input.schema()),
+            input,
+        },
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            schema,
+        } => LogicalPlan::Aggregate {
+            group_expr: normalize_exprs(&group_expr, input.schema()),
+            aggr_expr: normalize_exprs(&aggr_expr, input.schema()),
+            input,
+            schema,
+        },
+        LogicalPlan::Sort {
+            expr,
+            input,
+            schema,
+        } => LogicalPlan::Sort {
+            expr: normalize_exprs(&expr, input.schema()),
+            input,
+            schema,
+        },
+        LogicalPlan::FileScan {
+            path,
+            paths,
+            files,
+            partition_columns,
+            file_type,
+            schema,
+            projection,
+            projected_schema,
+            csv_options,
+            filters,
+            limit,
+        } => {
+            // scan filters refer to the columns of the table rather than the projection
+            let mut filters = normalize_exprs(&filters, &schema);
+            sort_and_dedup(&mut filters);
+            LogicalPlan::FileScan {
+                path,
+                paths,
+                files,
+                partition_columns,
+                file_type,
+                schema,
+                projection,
+                projected_schema,
+                csv_options,
+                filters,
+                limit,
+            }
+        }
+        other => other,
+    }
+}
+
+/// Rewrite an expression over a schema into its canonical form
+pub fn normalize_expr(expr: &Expr, schema: &Schema) -> Expr {
+    match expr {
+        Expr::UnresolvedColumn(name) => match schema.index_of(name) {
+            Ok(i) => Expr::Column(i),
+            Err(_) => expr.clone(),
+        },
+        Expr::Literal(value) => Expr::Literal(normalize_literal(value)),
+        Expr::BinaryExpr { op, .. } if *op == Operator::And || *op == Operator::Or => {
+            let mut terms = vec![];
+            flatten(expr, op, &mut terms);
+            let mut terms: Vec<Expr> = terms
+                .into_iter()
+                .map(|term| normalize_expr(term, schema))
+                .collect();
+            sort_and_dedup(&mut terms);
+            let mut terms = terms.into_iter();
+            let first = terms.next().unwrap();
+            terms.fold(first, |left, right| binary(left, op.clone(), right))
+        }
+        Expr::BinaryExpr { left, op, right } => {
+            let left = normalize_expr(left, schema);
+            let right = normalize_expr(right, schema);
+            let swap = match (is_literal(&left), is_literal(&right)) {
+                (true, false) => true,
+                (false, true) => false,
+                _ => sort_key(&left) > sort_key(&right),
+            };
+            match swapped(op) {
+                Some(swapped_op) if swap => binary(right, swapped_op, left),
+                _ => binary(left, op.clone(), right),
+            }
+        }
+        Expr::Alias(expr, alias) => {
+            Expr::Alias(Box::new(normalize_expr(expr, schema)), alias.clone())
+        }
+        Expr::Not(expr) => Expr::Not(Box::new(normalize_expr(expr, schema))),
+        Expr::IsNull(expr) => Expr::IsNull(Box::new(normalize_expr(expr, schema))),
+        Expr::IsNotNull(expr) => Expr::IsNotNull(Box::new(normalize_expr(expr, schema))),
+        Expr::Cast { expr, data_type } => Expr::Cast {
+            expr: Box::new(normalize_expr(expr, schema)),
+            data_type: data_type.clone(),
+        },
+        Expr::Sort { expr, asc } => Expr::Sort {
+            expr: Box::new(normalize_expr(expr, schema)),
+            asc: *asc,
+        },
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } => Expr::ScalarFunction {
+            name: name.clone(),
+            args: normalize_exprs(args, schema),
+            return_type: return_type.clone(),
+        },
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => Expr::AggregateFunction {
+            name: name.clone(),
+            args: normalize_exprs(args, schema),
+            return_type: return_type.clone(),
+        },
+        Expr::GetField { expr, name } => normalize_expr(expr, schema).field(name),
+        Expr::GetIndex { expr, index } => normalize_expr(expr, schema).index(*index),
+        Expr::Column(_) | Expr::Wildcard => expr.clone(),
+    }
+}
+
+fn normalize_exprs(exprs: &[Expr], schema: &Schema) -> Vec<Expr> {
+    exprs.iter().map(|e| normalize_expr(e, schema)).collect()
+}
+
+/// Give floating point literals a single representation of zero and of NaN
+fn normalize_literal(value: &ScalarValue) -> ScalarValue {
+    match value {
+        ScalarValue::Float32(v) if *v == 0.0 => ScalarValue::Float32(0.0),
+        ScalarValue::Float32(v) if v.is_nan() => ScalarValue::Float32(std::f32::NAN),
+        ScalarValue::Float64(v) if *v == 0.0 => ScalarValue::Float64(0.0),
+        ScalarValue::Float64(v) if v.is_nan() => ScalarValue::Float64(std::f64::NAN),
+        ScalarValue::Struct(values) => {
+            ScalarValue::Struct(values.iter().map(normalize_literal).collect())
+        }
+        other => other.clone(),
+    }
+}
+
+/// The terms of a chain of binary expressions with the same operator
+fn flatten<'a>(expr: &'a Expr, op: &Operator, terms: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: expr_op,
+            right,
+        } if expr_op == op => {
+            flatten(left, op, terms);
+            flatten(right, op, terms);
+        }
+        other => terms.push(other),
+    }
+}
+
+/// The operator that gives the same result when the operands are swapped, if there is one
+fn swapped(op: &Operator) -> Option<Operator> {
+    match op {
+        Operator::Eq
+        | Operator::NotEq
+        | Operator::IsNotDistinctFrom
+        | Operator::Plus
+        | Operator::Multiply => Some(op.clone()),
+        Operator::Lt => Some(Operator::Gt),
+        Operator::LtEq => Some(Operator::GtEq),
+        Operator::Gt => Some(Operator::Lt),
+        Operator::GtEq => Some(Operator::LtEq),
+        _ => None,
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) => true,
+        _ => false,
+    }
+}
+
+/// The key that the operands of commutative operators are sorted by
+fn sort_key(expr: &Expr) -> String {
+    format!("{:?}", expr)
+}
+
+fn sort_and_dedup(exprs: &mut Vec<Expr>) {
+    exprs.sort_by_key(sort_key);
+    exprs.dedup();
+}
+
+fn binary(left: Expr, op: Operator, right: Expr) -> Expr {
+    Expr::BinaryExpr {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field};
+    use crate::error::Result;
+    use crate::logicalplan::{col, lit_str, LogicalPlanBuilder};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash(plan: &LogicalPlan) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        plan.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn equivalent_plans_are_equal_once_normalized() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Utf8, false),
+            Field::new("b", DataType::Int64, false),
+        ]);
+        let scan = LogicalPlanBuilder::scan_csv("a.csv", &schema, None)?;
+        let b_is_one = Expr::BinaryExpr {
+            left: Box::new(Expr::Literal(ScalarValue::Int64(1))),
+            op: Operator::Lt,
+            right: Box::new(col("b")),
+        };
+        let a = scan
+            .filter(col("a").eq(&lit_str("x")).and(&b_is_one))?
+            .build()?;
+        let b = scan
+            .filter(
+                Expr::Column(1)
+                    .gt(&Expr::Literal(ScalarValue::Int64(1)))
+                    .and(&lit_str("x").eq(&col("a")))
+                    .and(&col("a").eq(&lit_str("x"))),
+            )?
+            .build()?;
+        assert_ne!(a, b);
+
+        let (a, b) = (normalize_plan(&a), normalize_plan(&b));
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_eq!(a, normalize_plan(&a));
+
+        let c = normalize_plan(&scan.filter(col("a").eq(&lit_str("y")))?.build()?);
+        assert_ne!(a, c);
+        Ok(())
+    }
+}
@@ -0,0 +1,2266 @@
+//! Optimizations of Ballista logical plans.
+//!
+//! Plans are optimized by the client before they are executed or sent to the scheduler or
+//! executors, so that the stages of distributed queries are planned from the optimized plan.
+//!
+//! Expression simplification evaluates the sub-expressions of filters, projections and
+//! sorts that only involve literals, removes the conditions that are always true or always
+//! false from `AND` and `OR` expressions, and removes casts to the type that an expression
+//! already has.
+//!
+//! Filter pushdown moves the conditions of filters below the joins and aggregates under
+//! them, to the side of a join whose columns they refer to and below aggregates when they
+//! only refer to grouping columns, so that fewer rows are joined and aggregated. Conditions
+//! that reach parquet scans are also used to skip row groups.
+//!
+//! Partition pruning evaluates the filters above partitioned file scans against the
+//! partition values of each file, and removes the files that cannot match from the scans,
+//! so that no tasks are scheduled for them.
+//!
+//! Join reordering joins the relations of multi-way joins in the order that is estimated to
+//! produce the fewest intermediate rows, from the statistics of the relations (see
+//! `statistics`).
+//!
+//! Common subexpression elimination computes the expensive expressions that are repeated in
+//! a projection or filter once for each row.
+//!
+//! Limit pushdown moves limits below the projections under them and into the file scans
+//! that they limit, so that scans stop reading once they have enough rows. Distributed
+//! queries apply a limit in each task before the combined output of the tasks is limited,
+//! so each task's scan also stops early.
+//!
+//! Projection pushdown computes the columns that each operator needs from its input and
+//! narrows the projections of the file and table scans to those columns, so that executors
+//! do not read or shuffle columns that the query does not use.
+//!
+//! Database query pushdown moves the limits and the projections of columns above database
+//! table scans into the queries of the scans (see `unparser`), so that the database only
+//! returns the rows and columns that the query uses. Filters are already added to the
+//! queries when they are applied to the DataFrame, and are still applied to the rows that
+//! the database returns, since databases may compare values differently.
+//!
+//! Each optimization is an `OptimizerRule`, and applications can add their own rules to the
+//! optimizer of a context with `Context::register_optimizer_rule`, before or after the
+//! built-in rules. Rules that rewrite particular operators can use
+//! `LogicalPlan::transform_up` or a `PlanRewriter` (see `visitor`) to rewrite every
+//! operator of the plan without handling the others.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::arrow::datatypes::{Field, Schema};
+use crate::datasource::is_remote_path;
+use crate::datasource::parquet::{parquet_statistics, FooterCache};
+use crate::datasource::partitioned::prune_files;
+use crate::datasource::sql::{conjuncts, SqlDialect};
+use crate::datasource::table::TableRegistry;
+use crate::decimal;
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::{expr_to_field, Expr, LogicalPlan, Operator, ScalarValue};
+use crate::statistics::{estimate_statistics, join_statistics, PlanStatistics};
+use crate::unparser::plan_to_sql;
+
+/// A rewrite of logical plans that does not change the results of the plans
+pub trait OptimizerRule: Send + Sync + fmt::Debug {
+    /// The name of the rule, which other rules can be positioned relative to
+    fn name(&self) -> &str;
+
+    /// Rewrite a plan, or return it unchanged if the rule does not apply to it
+    fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan>;
+}
+
+/// Simplifies the expressions of plans with `simplify_expressions`
+#[derive(Debug)]
+pub struct SimplifyExpressions;
+
+impl OptimizerRule for SimplifyExpressions {
+    fn name(&self) -> &str {
+        "simplify_expressions"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        simplify_expressions(plan)
+    }
+}
+
+/// Moves filters towards the scans with `push_down_filters`
+#[derive(Debug)]
+pub struct PushDownFilters;
+
+impl OptimizerRule for PushDownFilters {
+    fn name(&self) -> &str {
+        "push_down_filters"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        push_down_filters(plan)
+    }
+}
+
+/// Removes files from partitioned scans with `prune_partitions`
+#[derive(Debug)]
+pub struct PrunePartitions;
+
+impl OptimizerRule for PrunePartitions {
+    fn name(&self) -> &str {
+        "prune_partitions"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        prune_partitions(plan)
+    }
+}
+
+/// Reorders multi-way joins with `reorder_joins`, estimating the sizes of registered tables
+/// from the statistics in a table registry
+#[derive(Debug)]
+pub struct ReorderJoins {
+    tables: Arc<TableRegistry>,
+}
+
+impl ReorderJoins {
+    pub fn new(tables: Arc<TableRegistry>) -> Self {
+        Self { tables }
+    }
+}
+
+impl OptimizerRule for ReorderJoins {
+    fn name(&self) -> &str {
+        "reorder_joins"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        reorder_joins(plan, &self.tables)
+    }
+}
+
+/// Computes repeated expressions once with `eliminate_common_subexpressions`
+#[derive(Debug)]
+pub struct EliminateCommonSubexpressions;
+
+impl OptimizerRule for EliminateCommonSubexpressions {
+    fn name(&self) -> &str {
+        "eliminate_common_subexpressions"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        eliminate_common_subexpressions(plan)
+    }
+}
+
+/// Pushes limits into scans with `push_down_limits`
+#[derive(Debug)]
+pub struct PushDownLimits;
+
+impl OptimizerRule for PushDownLimits {
+    fn name(&self) -> &str {
+        "push_down_limits"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        push_down_limits(plan)
+    }
+}
+
+/// Narrows the projections of scans with `push_down_projections`
+#[derive(Debug)]
+pub struct PushDownProjections;
+
+impl OptimizerRule for PushDownProjections {
+    fn name(&self) -> &str {
+        "push_down_projections"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        push_down_projections(plan)
+    }
+}
+
+/// Moves operators into the queries of database scans with `push_down_sql_queries`
+#[derive(Debug)]
+pub struct PushDownSqlQueries;
+
+impl OptimizerRule for PushDownSqlQueries {
+    fn name(&self) -> &str {
+        "push_down_sql_queries"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        push_down_sql_queries(plan)
+    }
+}
+
+/// Where a rule is added to the rules of an optimizer
+#[derive(Debug, Clone, PartialEq)]
+pub enum RulePosition {
+    /// Before all of the other rules
+    First,
+    /// After all of the other rules
+    Last,
+    /// Immediately before the rule with the given name
+    Before(String),
+    /// Immediately after the rule with the given name
+    After(String),
+}
+
+/// The rules that the plans of a context are optimized with, which are applied in order.
+/// Optimizers start with the built-in rules, with expressions simplified and filters pushed
+/// down before partitions are pruned so that pruning sees the simplified filters below the
+/// joins, joins reordered once the sizes of the pruned scans are known, and projections
+/// pushed down before the operators above database scans are moved into their queries.
+pub struct Optimizer {
+    rules: RwLock<Vec<Arc<dyn OptimizerRule>>>,
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Self::with_tables(Arc::new(TableRegistry::new()))
+    }
+
+    /// An optimizer that estimates the sizes of the registered tables from the statistics
+    /// that have been collected for them in the registry
+    pub fn with_tables(tables: Arc<TableRegistry>) -> Self {
+        let rules: Vec<Arc<dyn OptimizerRule>> = vec![
+            Arc::new(SimplifyExpressions),
+            Arc::new(PushDownFilters),
+            Arc::new(PrunePartitions),
+            Arc::new(ReorderJoins::new(tables)),
+            Arc::new(EliminateCommonSubexpressions),
+            Arc::new(PushDownLimits),
+            Arc::new(PushDownProjections),
+            Arc::new(PushDownSqlQueries),
+        ];
+        Self {
+            rules: RwLock::new(rules),
+        }
+    }
+
+    /// Add a rule to the optimizer, failing if the rule that it is positioned relative to
+    /// has not been added
+    pub fn add_rule(&self, rule: Arc<dyn OptimizerRule>, position: RulePosition) -> Result<()> {
+        let mut rules = self.rules.write().expect("optimizer rules lock poisoned");
+        let find = |name: &str| {
+            rules.iter().position(|r| r.name() == name).ok_or_else(|| {
+                ballista_error(&format!("There is no optimizer rule named '{}'", name))
+            })
+        };
+        let index = match &position {
+            RulePosition::First => 0,
+            RulePosition::Last => rules.len(),
+            RulePosition::Before(name) => find(name)?,
+            RulePosition::After(name) => find(name)? + 1,
+        };
+        rules.insert(index, rule);
+        Ok(())
+    }
+
+    /// The names of the rules, in the order that they are applied
+    pub fn rule_names(&self) -> Vec<String> {
+        self.rules
+            .read()
+            .expect("optimizer rules lock poisoned")
+            .iter()
+            .map(|r| r.name().to_owned())
+            .collect()
+    }
+
+    /// Apply each of the rules to a plan in turn
+    pub fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        // the rules are applied without holding the lock, since custom rules may be slow
+        let rules = self
+            .rules
+            .read()
+            .expect("optimizer rules lock poisoned")
+            .clone();
+        let mut plan = plan.clone();
+        for rule in rules {
+            plan = rule.optimize(&plan).map_err(|e| {
+                ballista_error(&format!("Optimizer rule '{}' failed: {:?}", rule.name(), e))
+            })?;
+        }
+        Ok(plan)
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Optimizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.rule_names()).finish()
+    }
+}
+
+/// Simplify the expressions of the filters, projections and sorts of a plan
+pub fn simplify_expressions(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    plan.transform_up(simplify_operator)
+}
+
+fn simplify_operator(plan: LogicalPlan) -> Result<LogicalPlan> {
+    match plan {
+        LogicalPlan::Selection { expr, input } => match simplify(&expr, input.schema()) {
+            // filters that are always true are removed
+            Expr::Literal(ScalarValue::Boolean(true)) => Ok(*input),
+            expr => Ok(LogicalPlan::Selection { expr, input }),
+        },
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => {
+            // simplified expressions keep the names of the columns that they produce
+            let expr = expr
+                .iter()
+                .enumerate()
+                .map(|(i, e)| keep_name(e, simplify(e, input.schema()), schema.field(i).name()))
+                .collect();
+            Ok(LogicalPlan::Projection {
+                expr,
+                input,
+                schema,
+            })
+        }
+        LogicalPlan::Sort {
+            expr,
+            input,
+            schema,
+        } => Ok(LogicalPlan::Sort {
+            expr: expr.iter().map(|e| simplify(e, input.schema())).collect(),
+            input,
+            schema,
+        }),
+        other => Ok(other),
+    }
+}
+
+/// Alias a rewritten expression of a projection with the name of the column that the
+/// original expression produced, if rewriting it changed its name
+fn keep_name(original: &Expr, rewritten: Expr, name: &str) -> Expr {
+    match rewritten {
+        rewritten if rewritten == *original => rewritten,
+        Expr::Alias(rewritten, alias) => Expr::Alias(rewritten, alias),
+        rewritten => Expr::Alias(Box::new(rewritten), name.to_owned()),
+    }
+}
+
+/// Simplify an expression on the columns of the schema
+fn simplify(expr: &Expr, schema: &Schema) -> Expr {
+    let simplify = |e: &Expr| simplify(e, schema);
+    match expr {
+        Expr::BinaryExpr { left, op, right } => {
+            let left = simplify(left);
+            let right = simplify(right);
+            let boolean = |e: &Expr| match e {
+                Expr::Literal(ScalarValue::Boolean(b)) => Some(*b),
+                _ => None,
+            };
+            match (op, boolean(&left), boolean(&right)) {
+                // `false AND x` is false and `true OR x` is true even when x is null
+                (Operator::And, Some(true), _) => return right,
+                (Operator::And, _, Some(true)) => return left,
+                (Operator::And, Some(false), _) | (Operator::And, _, Some(false)) => {
+                    return Expr::Literal(ScalarValue::Boolean(false))
+                }
+                (Operator::Or, Some(false), _) => return right,
+                (Operator::Or, _, Some(false)) => return left,
+                (Operator::Or, Some(true), _) | (Operator::Or, _, Some(true)) => {
+                    return Expr::Literal(ScalarValue::Boolean(true))
+                }
+                _ => {}
+            }
+            if let (Expr::Literal(l), Expr::Literal(r)) = (&left, &right) {
+                if let Some(value) = fold(l, op, r) {
+                    return Expr::Literal(value);
+                }
+            }
+            // `x <=> NULL` is `x IS NULL`, which DataFusion can evaluate
+            if *op == Operator::IsNotDistinctFrom {
+                match (&left, &right) {
+                    (Expr::Literal(ScalarValue::Null), e)
+                    | (e, Expr::Literal(ScalarValue::Null)) => {
+                        return Expr::IsNull(Box::new(e.clone()))
+                    }
+                    _ => {}
+                }
+            }
+            Expr::BinaryExpr {
+                left: Box::new(left),
+                op: op.clone(),
+                right: Box::new(right),
+            }
+        }
+        Expr::Not(e) => match simplify(e) {
+            Expr::Literal(ScalarValue::Boolean(b)) => Expr::Literal(ScalarValue::Boolean(!b)),
+            Expr::Not(e) => *e,
+            e => Expr::Not(Box::new(e)),
+        },
+        Expr::IsNull(e) => match simplify(e) {
+            Expr::Literal(v) => Expr::Literal(ScalarValue::Boolean(v == ScalarValue::Null)),
+            e => Expr::IsNull(Box::new(e)),
+        },
+        Expr::IsNotNull(e) => match simplify(e) {
+            Expr::Literal(v) => Expr::Literal(ScalarValue::Boolean(v != ScalarValue::Null)),
+            e => Expr::IsNotNull(Box::new(e)),
+        },
+        Expr::Cast { expr, data_type } => {
+            let expr = simplify(expr);
+            match expr.get_type(schema) {
+                Ok(ref t) if t == data_type => expr,
+                _ => Expr::Cast {
+                    expr: Box::new(expr),
+                    data_type: data_type.clone(),
+                },
+            }
+        }
+        Expr::Alias(e, alias) => Expr::Alias(Box::new(simplify(e)), alias.clone()),
+        Expr::Sort { expr, asc } => Expr::Sort {
+            expr: Box::new(simplify(expr)),
+            asc: *asc,
+        },
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } => Expr::ScalarFunction {
+            name: name.clone(),
+            args: args.iter().map(simplify).collect(),
+            return_type: return_type.clone(),
+        },
+        Expr::GetField { expr, name } => simplify(expr).field(name),
+        Expr::GetIndex { expr, index } => simplify(expr).index(*index),
+        other => other.clone(),
+    }
+}
+
+/// Evaluate an operator on two literals of the same type, or return `None` if it cannot be
+/// evaluated at plan time, such as when integer arithmetic overflows
+pub(crate) fn fold(left: &ScalarValue, op: &Operator, right: &ScalarValue) -> Option<ScalarValue> {
+    if *op == Operator::IsNotDistinctFrom {
+        return match (left, right) {
+            (ScalarValue::Null, ScalarValue::Null) => Some(ScalarValue::Boolean(true)),
+            (ScalarValue::Null, _) | (_, ScalarValue::Null) => Some(ScalarValue::Boolean(false)),
+            _ => fold(left, &Operator::Eq, right),
+        };
+    }
+    macro_rules! fold_integers {
+        ($($variant:ident),*) => {
+            match (left, right) {
+                $((ScalarValue::$variant(a), ScalarValue::$variant(b)) => {
+                    return match op {
+                        Operator::Plus => a.checked_add(*b).map(ScalarValue::$variant),
+                        Operator::Minus => a.checked_sub(*b).map(ScalarValue::$variant),
+                        Operator::Multiply => a.checked_mul(*b).map(ScalarValue::$variant),
+                        Operator::Divide => a.checked_div(*b).map(ScalarValue::$variant),
+                        Operator::Modulus => a.checked_rem(*b).map(ScalarValue::$variant),
+                        op => compare(a.partial_cmp(b), op),
+                    }
+                })*
+                _ => {}
+            }
+        };
+    }
+    macro_rules! fold_floats {
+        ($($variant:ident),*) => {
+            match (left, right) {
+                $((ScalarValue::$variant(a), ScalarValue::$variant(b)) => {
+                    return match op {
+                        Operator::Plus => Some(ScalarValue::$variant(a + b)),
+                        Operator::Minus => Some(ScalarValue::$variant(a - b)),
+                        Operator::Multiply => Some(ScalarValue::$variant(a * b)),
+                        // division by zero is left to fail when the query is executed
+                        Operator::Divide if *b != 0.0 => Some(ScalarValue::$variant(a / b)),
+                        Operator::Divide | Operator::Modulus => None,
+                        op => compare(a.partial_cmp(b), op),
+                    }
+                })*
+                _ => {}
+            }
+        };
+    }
+    fold_integers!(Int8, Int16, Int32, Int64, UInt8, UInt16, UInt32, UInt64);
+    fold_floats!(Float32, Float64);
+    match (left, right) {
+        (ScalarValue::Utf8(a), ScalarValue::Utf8(b)) => compare(Some(a.cmp(b)), op),
+        (ScalarValue::Boolean(a), ScalarValue::Boolean(b)) => match op {
+            Operator::And => Some(ScalarValue::Boolean(*a && *b)),
+            Operator::Or => Some(ScalarValue::Boolean(*a || *b)),
+            op => compare(Some(a.cmp(b)), op),
+        },
+        (ScalarValue::Decimal(a, p1, s1), ScalarValue::Decimal(b, p2, s2)) => match op {
+            Operator::Plus | Operator::Minus | Operator::Multiply => {
+                fold_decimals(*a, (*p1, *s1), op, *b, (*p2, *s2))
+            }
+            op => compare(left.compare(right), op),
+        },
+        // dates, timestamps and binary strings are only compared
+        _ => compare(left.compare(right), op),
+    }
+}
+
+/// Evaluate arithmetic on two decimals exactly, with the precision and scale that SQL gives
+/// the result (see `decimal`). Results that would exceed the maximum precision are left to
+/// be evaluated when the query is executed.
+fn fold_decimals(
+    a: i128,
+    (p1, s1): (usize, usize),
+    op: &Operator,
+    b: i128,
+    (p2, s2): (usize, usize),
+) -> Option<ScalarValue> {
+    let (precision, scale) = decimal::result_type(op, (p1, s1), (p2, s2))?;
+    let value = decimal::apply(a, s1, op, b, s2)?;
+    Some(ScalarValue::Decimal(value, precision, scale))
+}
+
+fn compare(ordering: Option<Ordering>, op: &Operator) -> Option<ScalarValue> {
+    let ordering = ordering?;
+    let result = match op {
+        Operator::Eq => ordering == Ordering::Equal,
+        Operator::NotEq => ordering != Ordering::Equal,
+        Operator::Lt => ordering == Ordering::Less,
+        Operator::LtEq => ordering != Ordering::Greater,
+        Operator::Gt => ordering == Ordering::Greater,
+        Operator::GtEq => ordering != Ordering::Less,
+        _ => return None,
+    };
+    Some(ScalarValue::Boolean(result))
+}
+
+/// Compute the expensive expressions that occur more than once in a projection or filter,
+/// such as calls to the same function with the same arguments, once for each row. The
+/// repeated expressions are computed by a projection below the projection or filter, which
+/// then refers to their columns.
+pub fn eliminate_common_subexpressions(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    plan.transform_up(eliminate_common_in_operator)
+}
+
+fn eliminate_common_in_operator(plan: LogicalPlan) -> Result<LogicalPlan> {
+    match plan {
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => {
+            let (common, rewritten) = extract_common(&expr, input.schema().fields().len());
+            if common.is_empty() {
+                return Ok(LogicalPlan::Projection {
+                    expr,
+                    input,
+                    schema,
+                });
+            }
+            let expr = expr
+                .iter()
+                .zip(rewritten)
+                .enumerate()
+                .map(|(i, (e, rewritten))| keep_name(e, rewritten, schema.field(i).name()))
+                .collect();
+            Ok(LogicalPlan::Projection {
+                expr,
+                input: Box::new(compute_common(*input, &common)?),
+                schema,
+            })
+        }
+        LogicalPlan::Selection { expr, input } => {
+            let schema = input.schema().clone();
+            let width = schema.fields().len();
+            let (common, mut rewritten) = extract_common(&[expr.clone()], width);
+            if common.is_empty() {
+                return Ok(LogicalPlan::Selection { expr, input });
+            }
+            // the columns of the common expressions are removed after the filter
+            let selection = LogicalPlan::Selection {
+                expr: rewritten.remove(0),
+                input: Box::new(compute_common(*input, &common)?),
+            };
+            Ok(LogicalPlan::Projection {
+                expr: (0..width).map(Expr::Column).collect(),
+                input: Box::new(selection),
+                schema,
+            })
+        }
+        other => Ok(other),
+    }
+}
+
+/// Whether an expression is expensive enough to compute once when it is repeated
+fn is_expensive(expr: &Expr) -> bool {
+    match expr {
+        Expr::ScalarFunction { .. } => true,
+        Expr::BinaryExpr { op, .. } => *op == Operator::Like || *op == Operator::NotLike,
+        _ => false,
+    }
+}
+
+/// Replace the expensive expressions that occur more than once in the expressions with the
+/// columns that follow the columns of the input, returning the repeated expressions and
+/// the rewritten expressions. Repeated expressions that contain other repeated expressions
+/// are replaced first.
+fn extract_common(exprs: &[Expr], width: usize) -> (Vec<Expr>, Vec<Expr>) {
+    let mut exprs = exprs.to_vec();
+    let mut common = vec![];
+    loop {
+        let mut candidates = vec![];
+        for expr in &exprs {
+            expensive_exprs(expr, &mut candidates);
+        }
+        let repeated = candidates
+            .iter()
+            .find(|c| candidates.iter().filter(|other| other == c).count() > 1)
+            .cloned();
+        match repeated {
+            Some(repeated) => {
+                let column = Expr::Column(width + common.len());
+                exprs = exprs
+                    .iter()
+                    .map(|e| replace_expr(e, &repeated, &column))
+                    .collect();
+                common.push(repeated);
+            }
+            None => return (common, exprs),
+        }
+    }
+}
+
+/// Collect the expensive sub-expressions of an expression, outer expressions first
+fn expensive_exprs(expr: &Expr, exprs: &mut Vec<Expr>) {
+    if is_expensive(expr) {
+        exprs.push(expr.clone());
+    }
+    match expr {
+        Expr::Alias(e, _)
+        | Expr::Not(e)
+        | Expr::IsNull(e)
+        | Expr::IsNotNull(e)
+        | Expr::Cast { expr: e, .. }
+        | Expr::Sort { expr: e, .. } => expensive_exprs(e, exprs),
+        Expr::BinaryExpr { left, right, .. } => {
+            expensive_exprs(left, exprs);
+            expensive_exprs(right, exprs);
+        }
+        Expr::ScalarFunction { args, .. } | Expr::AggregateFunction { args, .. } => {
+            for arg in args {
+                expensive_exprs(arg, exprs);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Collect the outermost subexpressions of an expression that match a predicate, without
+/// duplicates
+pub(crate) fn collect_outermost(
+    expr: &Expr,
+    matches: &dyn Fn(&Expr) -> bool,
+    found: &mut Vec<Expr>,
+) {
+    if matches(expr) {
+        if !found.contains(expr) {
+            found.push(expr.clone());
+        }
+        return;
+    }
+    match expr {
+        Expr::Alias(e, _)
+        | Expr::Not(e)
+        | Expr::IsNull(e)
+        | Expr::IsNotNull(e)
+        | Expr::Cast { expr: e, .. }
+        | Expr::Sort { expr: e, .. }
+        | Expr::GetField { expr: e, .. }
+        | Expr::GetIndex { expr: e, .. } => collect_outermost(e, matches, found),
+        Expr::BinaryExpr { left, right, .. } => {
+            collect_outermost(left, matches, found);
+            collect_outermost(right, matches, found);
+        }
+        Expr::ScalarFunction { args, .. } | Expr::AggregateFunction { args, .. } => {
+            for arg in args {
+                collect_outermost(arg, matches, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace each occurrence of an expression within an expression
+pub(crate) fn replace_expr(expr: &Expr, from: &Expr, to: &Expr) -> Expr {
+    if expr == from {
+        return to.clone();
+    }
+    let replace = |e: &Expr| Box::new(replace_expr(e, from, to));
+    match expr {
+        Expr::Alias(e, alias) => Expr::Alias(replace(e), alias.clone()),
+        Expr::Not(e) => Expr::Not(replace(e)),
+        Expr::IsNull(e) => Expr::IsNull(replace(e)),
+        Expr::IsNotNull(e) => Expr::IsNotNull(replace(e)),
+        Expr::Cast { expr, data_type } => Expr::Cast {
+            expr: replace(expr),
+            data_type: data_type.clone(),
+        },
+        Expr::Sort { expr, asc } => Expr::Sort {
+            expr: replace(expr),
+            asc: *asc,
+        },
+        Expr::BinaryExpr { left, op, right } => Expr::BinaryExpr {
+            left: replace(left),
+            op: op.clone(),
+            right: replace(right),
+        },
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } => Expr::ScalarFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| replace_expr(a, from, to)).collect(),
+            return_type: return_type.clone(),
+        },
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => Expr::AggregateFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| replace_expr(a, from, to)).collect(),
+            return_type: return_type.clone(),
+        },
+        Expr::GetField { expr, name } => replace_expr(expr, from, to).field(name),
+        Expr::GetIndex { expr, index } => replace_expr(expr, from, to).index(*index),
+        other => other.clone(),
+    }
+}
+
+/// Project the columns of the input followed by the common expressions
+fn compute_common(input: LogicalPlan, common: &[Expr]) -> Result<LogicalPlan> {
+    let width = input.schema().fields().len();
+    let mut expr: Vec<Expr> = (0..width).map(Expr::Column).collect();
+    let mut fields = input.schema().fields().clone();
+    for (i, e) in common.iter().enumerate() {
+        // common expressions may contain the common expressions that were found before them
+        let mut e = e.clone();
+        for (j, earlier) in common[..i].iter().enumerate().rev() {
+            e = replace_expr(&e, &Expr::Column(width + j), earlier);
+        }
+        let field = expr_to_field(&e, input.schema())?;
+        let name = format!("__common_{}", i);
+        fields.push(Field::new(
+            &name,
+            field.data_type().clone(),
+            field.is_nullable(),
+        ));
+        expr.push(Expr::Alias(Box::new(e), name));
+    }
+    let schema = decimal::schema_with_decimals(fields, &expr, input.schema());
+    Ok(LogicalPlan::Projection {
+        expr,
+        input: Box::new(input),
+        schema,
+    })
+}
+
+/// Reorder the relations of each tree of inner joins of three or more relations into a
+/// left-deep tree, starting with the pair of relations whose join is estimated to produce
+/// the fewest rows and then adding the relation that keeps the estimated size of the join
+/// the smallest. Joins keep their order when the size of any of their relations cannot be
+/// estimated, and reordered joins are projected to produce their columns in the original
+/// order.
+pub fn reorder_joins(plan: &LogicalPlan, tables: &TableRegistry) -> Result<LogicalPlan> {
+    if let LogicalPlan::Join {
+        schema,
+        null_equals_null: false,
+        ..
+    } = plan
+    {
+        let mut relations = vec![];
+        let mut conditions = vec![];
+        flatten_joins(plan, 0, &mut relations, &mut conditions);
+        if relations.len() >= 3 {
+            let relations = relations
+                .into_iter()
+                .map(|(relation, offset)| Ok((reorder_joins(&relation, tables)?, offset)))
+                .collect::<Result<Vec<_>>>()?;
+            if let Some(order) = join_order(&relations, &conditions, tables) {
+                return Ok(build_joins(&relations, &conditions, &order, schema));
+            }
+        }
+    }
+    let inputs = plan
+        .inputs()
+        .into_iter()
+        .map(|input| reorder_joins(input, tables))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(plan.with_new_inputs(inputs))
+}
+
+/// Collect the relations of a tree of joins with the offsets of their columns in the output
+/// of the tree, and the join conditions as pairs of columns of the output. Joins that match
+/// nulls are relations of the tree, since the conditions of reordered joins do not.
+fn flatten_joins(
+    plan: &LogicalPlan,
+    offset: usize,
+    relations: &mut Vec<(LogicalPlan, usize)>,
+    conditions: &mut Vec<(usize, usize)>,
+) {
+    match plan {
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            null_equals_null: false,
+            ..
+        } => {
+            let left_width = left.schema().fields().len();
+            flatten_joins(left, offset, relations, conditions);
+            flatten_joins(right, offset + left_width, relations, conditions);
+            for (l, r) in on {
+                conditions.push((offset + l, offset + left_width + r));
+            }
+        }
+        other => relations.push((other.clone(), offset)),
+    }
+}
+
+/// The conditions that join a relation to the relations that have already been joined, as
+/// pairs of columns of the joined relations and the relation
+fn join_conditions(
+    relations: &[(LogicalPlan, usize)],
+    conditions: &[(usize, usize)],
+    positions: &HashMap<usize, usize>,
+    relation: usize,
+) -> Vec<(usize, usize)> {
+    let (plan, offset) = &relations[relation];
+    let columns = *offset..*offset + plan.schema().fields().len();
+    conditions
+        .iter()
+        .filter_map(|(a, b)| {
+            if columns.contains(b) && positions.contains_key(a) {
+                Some((positions[a], b - offset))
+            } else if columns.contains(a) && positions.contains_key(b) {
+                Some((positions[b], a - offset))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The positions of the columns of a relation in the output of the joins, after the columns
+/// of the relations that were joined before it
+fn add_positions(
+    relations: &[(LogicalPlan, usize)],
+    positions: &mut HashMap<usize, usize>,
+    relation: usize,
+) {
+    let (plan, offset) = &relations[relation];
+    let start = positions.len();
+    for i in 0..plan.schema().fields().len() {
+        positions.insert(offset + i, start + i);
+    }
+}
+
+/// The order to join the relations in, or `None` if they should keep their order
+fn join_order(
+    relations: &[(LogicalPlan, usize)],
+    conditions: &[(usize, usize)],
+    tables: &TableRegistry,
+) -> Option<Vec<usize>> {
+    let stats = relations
+        .iter()
+        .map(|(relation, _)| estimate_statistics(relation, tables))
+        .collect::<Option<Vec<_>>>()?;
+
+    // start with the smallest join of two relations, with the relation that is not a
+    // broadcast hint on the left so that the hint still applies
+    let mut best: Option<(Vec<usize>, PlanStatistics)> = None;
+    for i in 0..relations.len() {
+        for j in 0..relations.len() {
+            let broadcast = |r: usize| match relations[r].0 {
+                LogicalPlan::Broadcast { .. } => true,
+                _ => false,
+            };
+            if i == j || (broadcast(i) && !broadcast(j)) || (i > j && !broadcast(j)) {
+                continue;
+            }
+            let mut positions = HashMap::new();
+            add_positions(relations, &mut positions, i);
+            let on = join_conditions(relations, conditions, &positions, j);
+            if on.is_empty() {
+                continue;
+            }
+            let joined = join_statistics(&stats[i], &stats[j], &on);
+            if best
+                .as_ref()
+                .map(|(_, b)| joined.num_rows < b.num_rows)
+                .unwrap_or(true)
+            {
+                best = Some((vec![i, j], joined));
+            }
+        }
+    }
+
+    let (mut order, mut joined) = best?;
+    let mut positions = HashMap::new();
+    for r in &order {
+        add_positions(relations, &mut positions, *r);
+    }
+    while order.len() < relations.len() {
+        let mut next: Option<(usize, PlanStatistics)> = None;
+        for r in (0..relations.len()).filter(|r| !order.contains(r)) {
+            let on = join_conditions(relations, conditions, &positions, r);
+            if on.is_empty() {
+                continue;
+            }
+            let candidate = join_statistics(&joined, &stats[r], &on);
+            if next
+                .as_ref()
+                .map(|(_, n)| candidate.num_rows < n.num_rows)
+                .unwrap_or(true)
+            {
+                next = Some((r, candidate));
+            }
+        }
+        // relations that are not joined on any columns keep the original order
+        let (r, candidate) = next?;
+        add_positions(relations, &mut positions, r);
+        order.push(r);
+        joined = candidate;
+    }
+    if order.iter().enumerate().all(|(i, r)| i == *r) {
+        None
+    } else {
+        Some(order)
+    }
+}
+
+/// Join the relations in order, projecting the columns in the order of the original joins
+fn build_joins(
+    relations: &[(LogicalPlan, usize)],
+    conditions: &[(usize, usize)],
+    order: &[usize],
+    schema: &Schema,
+) -> LogicalPlan {
+    let mut positions = HashMap::new();
+    add_positions(relations, &mut positions, order[0]);
+    let mut plan = relations[order[0]].0.clone();
+    for r in &order[1..] {
+        let right = &relations[*r].0;
+        let on = join_conditions(relations, conditions, &positions, *r);
+        let fields: Vec<Field> = plan
+            .schema()
+            .fields()
+            .iter()
+            .chain(right.schema().fields())
+            .cloned()
+            .collect();
+        let metadata = decimal::merged_metadata(&[plan.schema(), right.schema()]);
+        plan = LogicalPlan::Join {
+            left: Box::new(plan),
+            right: Box::new(right.clone()),
+            on,
+            null_equals_null: false,
+            schema: Schema::new_with_metadata(fields, metadata),
+        };
+        add_positions(relations, &mut positions, *r);
+    }
+    LogicalPlan::Projection {
+        expr: (0..schema.fields().len())
+            .map(|i| Expr::Column(positions[&i]))
+            .collect(),
+        input: Box::new(plan),
+        schema: schema.clone(),
+    }
+}
+
+/// Push the conditions of the filters of a plan below joins, aggregates, projections and
+/// sorts, as far towards the scans as the columns that they refer to allow. Filters that
+/// are stacked on top of each other are combined.
+pub fn push_down_filters(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    match plan {
+        LogicalPlan::Selection { expr, input } => {
+            push_filters(input, conjuncts(expr).into_iter().cloned().collect())
+        }
+        other => Ok(other.with_new_inputs(
+            other
+                .inputs()
+                .into_iter()
+                .map(push_down_filters)
+                .collect::<Result<Vec<_>>>()?,
+        )),
+    }
+}
+
+/// Filter a plan by conditions on the columns of its schema, pushing the conditions into
+/// the plan where possible
+fn push_filters(plan: &LogicalPlan, filters: Vec<Expr>) -> Result<LogicalPlan> {
+    match plan {
+        LogicalPlan::Selection { expr, input } => {
+            let mut filters = filters;
+            filters.extend(conjuncts(expr).into_iter().cloned());
+            push_filters(input, filters)
+        }
+        // the joins are inner joins, so a condition on the columns of one side can filter
+        // that side before it is joined
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            null_equals_null,
+            schema,
+        } => {
+            let left_width = left.schema().fields().len();
+            let right_width = right.schema().fields().len();
+            let left_columns = (0..left_width).map(|i| (i, i)).collect();
+            let right_columns = (0..right_width).map(|i| (left_width + i, i)).collect();
+            let (left_filters, filters) = split_filters(filters, &left_columns);
+            let (right_filters, kept) = split_filters(filters, &right_columns);
+            let join = LogicalPlan::Join {
+                left: Box::new(push_filters(left, left_filters)?),
+                right: Box::new(push_filters(right, right_filters)?),
+                on: on.clone(),
+                null_equals_null: *null_equals_null,
+                schema: schema.clone(),
+            };
+            Ok(select(join, kept))
+        }
+        // conditions on the grouping columns remove whole groups, so they can filter the
+        // rows before they are grouped. Conditions without columns stay above aggregates
+        // without grouping columns, which produce a row even when their input is empty.
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            schema,
+        } => {
+            let mapping = group_expr
+                .iter()
+                .enumerate()
+                .filter_map(|(i, expr)| match expr {
+                    Expr::Column(c) => Some((i, *c)),
+                    Expr::Alias(expr, _) => match expr.as_ref() {
+                        Expr::Column(c) => Some((i, *c)),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect();
+            let (pushed, kept) = if group_expr.is_empty() {
+                (vec![], filters)
+            } else {
+                split_filters(filters, &mapping)
+            };
+            let aggregate = LogicalPlan::Aggregate {
+                input: Box::new(push_filters(input, pushed)?),
+                group_expr: group_expr.clone(),
+                aggr_expr: aggr_expr.clone(),
+                schema: schema.clone(),
+            };
+            Ok(select(aggregate, kept))
+        }
+        // conditions on the columns that a projection passes through can filter its input
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => {
+            let mapping = expr
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| match e {
+                    Expr::Column(c) => Some((i, *c)),
+                    Expr::Alias(e, _) => match e.as_ref() {
+                        Expr::Column(c) => Some((i, *c)),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect();
+            let (pushed, kept) = split_filters(filters, &mapping);
+            let projection = LogicalPlan::Projection {
+                expr: expr.clone(),
+                input: Box::new(push_filters(input, pushed)?),
+                schema: schema.clone(),
+            };
+            Ok(select(projection, kept))
+        }
+        LogicalPlan::Sort { input, .. } | LogicalPlan::Broadcast { input } => {
+            Ok(plan.with_new_inputs(vec![push_filters(input, filters)?]))
+        }
+        LogicalPlan::FileScan { .. } => {
+            let mut scan = plan.clone();
+            if let LogicalPlan::FileScan {
+                ref file_type,
+                ref projection,
+                filters: ref mut scan_filters,
+                ..
+            } = scan
+            {
+                if file_type == "parquet" {
+                    // scan filters refer to the columns of the table rather than the
+                    // projection
+                    let mapping = match projection {
+                        Some(projection) => projection.iter().cloned().enumerate().collect(),
+                        None => HashMap::new(),
+                    };
+                    for filter in &filters {
+                        let filter = rewrite_expr(filter, &mapping);
+                        if !scan_filters.contains(&filter) {
+                            scan_filters.push(filter);
+                        }
+                    }
+                }
+            }
+            Ok(select(scan, filters))
+        }
+        // filters cannot move below limits, and the other operators are the scans
+        other => Ok(select(push_down_filters(other)?, filters)),
+    }
+}
+
+/// Split filters into the filters that only refer to the columns in the mapping, which are
+/// rewritten with the mapping, and the other filters
+fn split_filters(filters: Vec<Expr>, mapping: &HashMap<usize, usize>) -> (Vec<Expr>, Vec<Expr>) {
+    let (pushed, kept): (Vec<Expr>, Vec<Expr>) = filters.into_iter().partition(|filter| {
+        let mut columns = vec![];
+        expr_columns(filter, &mut columns) && columns.iter().all(|c| mapping.contains_key(c))
+    });
+    let pushed = pushed.iter().map(|f| rewrite_expr(f, mapping)).collect();
+    (pushed, kept)
+}
+
+/// Filter a plan by all of the filters
+fn select(plan: LogicalPlan, filters: Vec<Expr>) -> LogicalPlan {
+    match filters
+        .into_iter()
+        .fold(None, |expr: Option<Expr>, filter| match expr {
+            Some(expr) => Some(expr.and(&filter)),
+            None => Some(filter),
+        }) {
+        Some(expr) => LogicalPlan::Selection {
+            expr,
+            input: Box::new(plan),
+        },
+        None => plan,
+    }
+}
+
+/// Remove the files of partitioned file scans whose partition values cannot match the
+/// filters above the scans
+pub fn prune_partitions(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    prune(plan, &[])
+}
+
+/// Prune the scans of a plan with the filters above it, whose columns refer to the plan's
+/// schema
+fn prune(plan: &LogicalPlan, filters: &[Expr]) -> Result<LogicalPlan> {
+    match plan {
+        LogicalPlan::Selection { expr, input } => {
+            let mut filters = filters.to_vec();
+            filters.push(expr.clone());
+            Ok(LogicalPlan::Selection {
+                expr: expr.clone(),
+                input: Box::new(prune(input, &filters)?),
+            })
+        }
+        // filters on the columns that a projection passes through also apply to its input
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => {
+            let mapping: HashMap<usize, usize> = expr
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| match e {
+                    Expr::Column(c) => Some((i, *c)),
+                    Expr::Alias(e, _) => match e.as_ref() {
+                        Expr::Column(c) => Some((i, *c)),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect();
+            let filters = rewrite_filters(filters, &mapping);
+            Ok(LogicalPlan::Projection {
+                expr: expr.clone(),
+                input: Box::new(prune(input, &filters)?),
+                schema: schema.clone(),
+            })
+        }
+        // filtering the rows before sorting them gives the same result
+        LogicalPlan::Sort {
+            expr,
+            input,
+            schema,
+        } => Ok(LogicalPlan::Sort {
+            expr: expr.clone(),
+            input: Box::new(prune(input, filters)?),
+            schema: schema.clone(),
+        }),
+        LogicalPlan::Broadcast { input } => Ok(LogicalPlan::Broadcast {
+            input: Box::new(prune(input, filters)?),
+        }),
+        LogicalPlan::FileScan {
+            files,
+            partition_columns,
+            schema,
+            projection,
+            ..
+        } if !partition_columns.is_empty() => {
+            // the partition values are matched against the columns of the table
+            let mapping: HashMap<usize, usize> = match projection {
+                Some(projection) => projection.iter().cloned().enumerate().collect(),
+                None => (0..schema.fields().len()).map(|i| (i, i)).collect(),
+            };
+            let mut pruned = files.clone();
+            for filter in rewrite_filters(filters, &mapping) {
+                pruned = prune_files(&pruned, partition_columns, schema, &filter);
+            }
+            let mut plan = plan.clone();
+            if let LogicalPlan::FileScan { ref mut files, .. } = plan {
+                *files = pruned;
+            }
+            Ok(plan)
+        }
+        // the filters above other operators, such as limits and aggregates, do not apply to
+        // their inputs
+        other => Ok(other.with_new_inputs(
+            other
+                .inputs()
+                .into_iter()
+                .map(|input| prune(input, &[]))
+                .collect::<Result<Vec<_>>>()?,
+        )),
+    }
+}
+
+/// Rewrite the filters that only refer to columns in the mapping, dropping the others
+fn rewrite_filters(filters: &[Expr], mapping: &HashMap<usize, usize>) -> Vec<Expr> {
+    filters
+        .iter()
+        .filter(|filter| {
+            let mut columns = vec![];
+            expr_columns(filter, &mut columns) && columns.iter().all(|c| mapping.contains_key(c))
+        })
+        .map(|filter| rewrite_expr(filter, mapping))
+        .collect()
+}
+
+/// Push the limits of a plan below the projections under them and into the file scans that
+/// they limit. The limits stay above the scans, since scans only stop reading between
+/// batches.
+pub fn push_down_limits(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    let (n, input) = match plan {
+        LogicalPlan::Limit {
+            expr: Expr::Literal(ScalarValue::UInt64(n)),
+            input,
+            ..
+        } => (*n, input.as_ref()),
+        other => {
+            return Ok(other.with_new_inputs(
+                other
+                    .inputs()
+                    .into_iter()
+                    .map(push_down_limits)
+                    .collect::<Result<Vec<_>>>()?,
+            ))
+        }
+    };
+    let limit = |input: &LogicalPlan, n: u64| LogicalPlan::Limit {
+        expr: Expr::Literal(ScalarValue::UInt64(n)),
+        input: Box::new(input.clone()),
+        schema: input.schema().clone(),
+    };
+    match input {
+        // projections produce a row for each row of their input
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => Ok(LogicalPlan::Projection {
+            expr: expr.clone(),
+            input: Box::new(push_down_limits(&limit(input, n))?),
+            schema: schema.clone(),
+        }),
+        LogicalPlan::Limit {
+            expr: Expr::Literal(ScalarValue::UInt64(m)),
+            input,
+            ..
+        } => push_down_limits(&limit(input, n.min(*m))),
+        LogicalPlan::FileScan { .. } => Ok(limit(&limit_scan(input, n as usize), n)),
+        input => Ok(limit(&push_down_limits(input)?, n)),
+    }
+}
+
+/// Limit a file scan to reading at least n rows. Parquet scans also drop the files that are
+/// not needed to read n rows, judging by the row counts in the metadata of the files.
+fn limit_scan(scan: &LogicalPlan, n: usize) -> LogicalPlan {
+    let mut scan = scan.clone();
+    if let LogicalPlan::FileScan {
+        ref mut files,
+        ref file_type,
+        ref schema,
+        ref filters,
+        ref mut limit,
+        ..
+    } = scan
+    {
+        *limit = Some(limit.map_or(n, |limit| limit.min(n)));
+        // the row groups that filtered scans skip do not count towards the limit
+        if file_type == "parquet" && filters.is_empty() && !files.iter().any(|f| is_remote_path(f))
+        {
+            if let Some(needed) = files_needed(files, schema, n) {
+                files.truncate(needed);
+            }
+        }
+    }
+    scan
+}
+
+/// The number of parquet files, in order, that contain at least n rows, or `None` if the
+/// metadata of a file cannot be read. The rules do not have the footers cached by the
+/// context, so the footers are read again.
+fn files_needed(files: &[String], schema: &Schema, n: usize) -> Option<usize> {
+    let footers = FooterCache::new(0);
+    let mut rows = 0.0;
+    for (i, file) in files.iter().enumerate() {
+        if i > 0 && rows >= n as f64 {
+            return Some(i);
+        }
+        rows += parquet_statistics(&[file.clone()], schema, &footers)
+            .ok()?
+            .num_rows;
+    }
+    Some(files.len())
+}
+
+/// Push the columns that a plan uses down to its scans. The optimized plan produces the
+/// same columns as the original plan.
+pub fn push_down_projections(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    let required: Vec<usize> = (0..plan.schema().fields().len()).collect();
+    let (optimized, _) = optimize(plan, &required)?;
+    Ok(optimized)
+}
+
+/// Optimize a plan that only needs to produce the required columns, which are indices into
+/// the plan's schema in ascending order. The optimized plan produces a subset of the
+/// original columns, in their original order, and the indices of those columns in the
+/// original schema are returned with it.
+fn optimize(plan: &LogicalPlan, required: &[usize]) -> Result<(LogicalPlan, Vec<usize>)> {
+    let width = plan.schema().fields().len();
+    if let Some(i) = required.iter().find(|i| **i >= width) {
+        return Err(ballista_error(&format!(
+            "Column {} is out of range for a plan with {} columns",
+            i, width
+        )));
+    }
+    match plan {
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => {
+            let kept = at_least_one(required);
+            let expr: Vec<Expr> = kept.iter().map(|i| expr[*i].clone()).collect();
+            let (input, mapping) = optimize_input(input, &expr, &[])?;
+            Ok((
+                LogicalPlan::Projection {
+                    expr: rewrite_exprs(&expr, &mapping),
+                    input: Box::new(input),
+                    schema: select_fields(schema, &kept),
+                },
+                kept,
+            ))
+        }
+        LogicalPlan::Selection { expr, input } => {
+            let (input, mapping) = optimize_input(input, &[expr.clone()], required)?;
+            let kept = kept_columns(&mapping);
+            Ok((
+                LogicalPlan::Selection {
+                    expr: rewrite_expr(expr, &mapping),
+                    input: Box::new(input),
+                },
+                kept,
+            ))
+        }
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            schema,
+        } => {
+            let exprs: Vec<Expr> = group_expr.iter().chain(aggr_expr).cloned().collect();
+            let (input, mapping) = optimize_input(input, &exprs, &[])?;
+            Ok((
+                LogicalPlan::Aggregate {
+                    input: Box::new(input),
+                    group_expr: rewrite_exprs(group_expr, &mapping),
+                    aggr_expr: rewrite_exprs(aggr_expr, &mapping),
+                    schema: schema.clone(),
+                },
+                (0..width).collect(),
+            ))
+        }
+        LogicalPlan::Sort { expr, input, .. } => {
+            let (input, mapping) = optimize_input(input, expr, required)?;
+            let kept = kept_columns(&mapping);
+            Ok((
+                LogicalPlan::Sort {
+                    expr: rewrite_exprs(expr, &mapping),
+                    schema: input.schema().clone(),
+                    input: Box::new(input),
+                },
+                kept,
+            ))
+        }
+        LogicalPlan::Limit { expr, input, .. } => {
+            let (input, kept) = optimize(input, required)?;
+            Ok((
+                LogicalPlan::Limit {
+                    expr: expr.clone(),
+                    schema: input.schema().clone(),
+                    input: Box::new(input),
+                },
+                kept,
+            ))
+        }
+        LogicalPlan::Broadcast { input } => {
+            let (input, kept) = optimize(input, required)?;
+            Ok((
+                LogicalPlan::Broadcast {
+                    input: Box::new(input),
+                },
+                kept,
+            ))
+        }
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            null_equals_null,
+            ..
+        } => {
+            let left_width = left.schema().fields().len();
+            let mut left_required: Vec<usize> = required
+                .iter()
+                .filter(|i| **i < left_width)
+                .cloned()
+                .collect();
+            let mut right_required: Vec<usize> = required
+                .iter()
+                .filter(|i| **i >= left_width)
+                .map(|i| i - left_width)
+                .collect();
+            for (l, r) in on {
+                left_required.push(*l);
+                right_required.push(*r);
+            }
+            left_required.sort_unstable();
+            left_required.dedup();
+            right_required.sort_unstable();
+            right_required.dedup();
+
+            let (left, left_kept) = optimize(left, &left_required)?;
+            let (right, right_kept) = optimize(right, &right_required)?;
+            let left_mapping = column_mapping(&left_kept);
+            let right_mapping = column_mapping(&right_kept);
+            let on = on
+                .iter()
+                .map(|(l, r)| (left_mapping[l], right_mapping[r]))
+                .collect();
+            let fields: Vec<Field> = left
+                .schema()
+                .fields()
+                .iter()
+                .chain(right.schema().fields())
+                .cloned()
+                .collect();
+            let kept = left_kept
+                .iter()
+                .cloned()
+                .chain(right_kept.iter().map(|i| i + left_width))
+                .collect();
+            let metadata = decimal::merged_metadata(&[left.schema(), right.schema()]);
+            Ok((
+                LogicalPlan::Join {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    on,
+                    null_equals_null: *null_equals_null,
+                    schema: Schema::new_with_metadata(fields, metadata),
+                },
+                kept,
+            ))
+        }
+        LogicalPlan::FileScan {
+            path,
+            paths,
+            files,
+            partition_columns,
+            file_type,
+            schema,
+            projection,
+            projected_schema,
+            csv_options,
+            filters,
+            limit,
+        } => {
+            let kept = at_least_one(required);
+            let (projection, projected_schema) =
+                narrow_projection(schema, projection, projected_schema, &kept);
+            Ok((
+                LogicalPlan::FileScan {
+                    path: path.clone(),
+                    paths: paths.clone(),
+                    files: files.clone(),
+                    partition_columns: partition_columns.clone(),
+                    file_type: file_type.clone(),
+                    schema: schema.clone(),
+                    projection,
+                    projected_schema,
+                    csv_options: csv_options.clone(),
+                    filters: filters.clone(),
+                    limit: *limit,
+                },
+                kept,
+            ))
+        }
+        LogicalPlan::TableScan {
+            table_name,
+            schema,
+            projection,
+            projected_schema,
+        } => {
+            let kept = at_least_one(required);
+            let (projection, projected_schema) =
+                narrow_projection(schema, projection, projected_schema, &kept);
+            Ok((
+                LogicalPlan::TableScan {
+                    table_name: table_name.clone(),
+                    schema: schema.clone(),
+                    projection,
+                    projected_schema,
+                },
+                kept,
+            ))
+        }
+        // the columns of the relations of a union are matched to its columns by name, so
+        // each relation only needs the required columns that it has
+        LogicalPlan::Union { inputs, schema } => {
+            let kept = at_least_one(required);
+            let names: Vec<&String> = kept.iter().map(|i| schema.field(*i).name()).collect();
+            let inputs = inputs
+                .iter()
+                .map(|input| {
+                    let input_schema = input.schema();
+                    let required: Vec<usize> = (0..input_schema.fields().len())
+                        .filter(|i| names.contains(&input_schema.field(*i).name()))
+                        .collect();
+                    optimize(input, &required).map(|(input, _)| input)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok((
+                LogicalPlan::Union {
+                    inputs,
+                    schema: select_fields(schema, &kept),
+                },
+                kept,
+            ))
+        }
+        // extension nodes need every column of their inputs
+        LogicalPlan::Extension { node } => {
+            let inputs = node
+                .inputs()
+                .into_iter()
+                .map(|input| {
+                    let required: Vec<usize> = (0..input.schema().fields().len()).collect();
+                    optimize(input, &required).map(|(input, _)| input)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok((plan.with_new_inputs(inputs), (0..width).collect()))
+        }
+        // the outputs of earlier stages and batches in memory are produced as they are
+        LogicalPlan::EmptyRelation { .. }
+        | LogicalPlan::MemoryScan(_)
+        | LogicalPlan::StageOutput { .. }
+        | LogicalPlan::ShuffleRead { .. } => Ok((plan.clone(), (0..width).collect())),
+    }
+}
+
+/// Optimize the input of an operator that evaluates the expressions and passes through the
+/// required columns of its input, returning the mapping from the columns of the original
+/// input to the columns of the optimized input
+fn optimize_input(
+    input: &LogicalPlan,
+    exprs: &[Expr],
+    required: &[usize],
+) -> Result<(LogicalPlan, HashMap<usize, usize>)> {
+    let mut columns = required.to_vec();
+    let resolved = exprs.iter().all(|expr| expr_columns(expr, &mut columns));
+    if !resolved {
+        // columns referenced by name need every column to keep their names
+        columns = (0..input.schema().fields().len()).collect();
+    }
+    columns.sort_unstable();
+    columns.dedup();
+    let (input, kept) = optimize(input, &columns)?;
+    Ok((input, column_mapping(&kept)))
+}
+
+/// Scans and projections produce at least one column, so that the number of rows is known
+/// even when no columns are used, such as for `COUNT(*)`
+fn at_least_one(required: &[usize]) -> Vec<usize> {
+    if required.is_empty() {
+        vec![0]
+    } else {
+        required.to_vec()
+    }
+}
+
+fn narrow_projection(
+    schema: &Schema,
+    projection: &Option<Vec<usize>>,
+    projected_schema: &Schema,
+    kept: &[usize],
+) -> (Option<Vec<usize>>, Schema) {
+    let columns = kept
+        .iter()
+        .map(|i| match projection {
+            Some(projection) => projection[*i],
+            None => *i,
+        })
+        .collect::<Vec<_>>();
+    if columns.len() == schema.fields().len() && projection.is_none() {
+        (None, schema.clone())
+    } else {
+        (Some(columns), select_fields(projected_schema, kept))
+    }
+}
+
+fn select_fields(schema: &Schema, columns: &[usize]) -> Schema {
+    // the precision and scale of decimal columns are kept
+    Schema::new_with_metadata(
+        columns.iter().map(|i| schema.field(*i).clone()).collect(),
+        schema.metadata().clone(),
+    )
+}
+
+fn column_mapping(kept: &[usize]) -> HashMap<usize, usize> {
+    kept.iter()
+        .enumerate()
+        .map(|(new, old)| (*old, new))
+        .collect()
+}
+
+/// The original columns that are kept in the optimized plan, in order
+fn kept_columns(mapping: &HashMap<usize, usize>) -> Vec<usize> {
+    let mut kept: Vec<usize> = mapping.keys().cloned().collect();
+    kept.sort_unstable();
+    kept
+}
+
+/// Add the columns that an expression references to the list, returning false if the
+/// expression references columns by name
+fn expr_columns(expr: &Expr, columns: &mut Vec<usize>) -> bool {
+    match expr {
+        Expr::Column(i) => {
+            columns.push(*i);
+            true
+        }
+        Expr::UnresolvedColumn(_) | Expr::Wildcard => false,
+        Expr::Literal(_) => true,
+        Expr::Alias(expr, _)
+        | Expr::Not(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::IsNull(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::Sort { expr, .. }
+        | Expr::GetField { expr, .. }
+        | Expr::GetIndex { expr, .. } => expr_columns(expr, columns),
+        Expr::BinaryExpr { left, right, .. } => {
+            let left = expr_columns(left, columns);
+            expr_columns(right, columns) && left
+        }
+        Expr::ScalarFunction { args, .. } | Expr::AggregateFunction { args, .. } => args
+            .iter()
+            .fold(true, |resolved, arg| expr_columns(arg, columns) && resolved),
+    }
+}
+
+fn rewrite_exprs(exprs: &[Expr], mapping: &HashMap<usize, usize>) -> Vec<Expr> {
+    exprs.iter().map(|e| rewrite_expr(e, mapping)).collect()
+}
+
+/// Rewrite the column indices of an expression to refer to the columns of the optimized
+/// input, keeping the columns that are not in the mapping
+pub(crate) fn rewrite_expr(expr: &Expr, mapping: &HashMap<usize, usize>) -> Expr {
+    let rewrite = |e: &Expr| Box::new(rewrite_expr(e, mapping));
+    match expr {
+        Expr::Column(i) => Expr::Column(mapping.get(i).cloned().unwrap_or(*i)),
+        Expr::Alias(expr, alias) => Expr::Alias(rewrite(expr), alias.clone()),
+        Expr::Not(expr) => Expr::Not(rewrite(expr)),
+        Expr::IsNotNull(expr) => Expr::IsNotNull(rewrite(expr)),
+        Expr::IsNull(expr) => Expr::IsNull(rewrite(expr)),
+        Expr::Cast { expr, data_type } => Expr::Cast {
+            expr: rewrite(expr),
+            data_type: data_type.clone(),
+        },
+        Expr::Sort { expr, asc } => Expr::Sort {
+            expr: rewrite(expr),
+            asc: *asc,
+        },
+        Expr::BinaryExpr { left, op, right } => Expr::BinaryExpr {
+            left: rewrite(left),
+            op: op.clone(),
+            right: rewrite(right),
+        },
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } => Expr::ScalarFunction {
+            name: name.clone(),
+            args: rewrite_exprs(args, mapping),
+            return_type: return_type.clone(),
+        },
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => Expr::AggregateFunction {
+            name: name.clone(),
+            args: rewrite_exprs(args, mapping),
+            return_type: return_type.clone(),
+        },
+        Expr::GetField { expr, name } => rewrite_expr(expr, mapping).field(name),
+        Expr::GetIndex { expr, index } => rewrite_expr(expr, mapping).index(*index),
+        Expr::UnresolvedColumn(_) | Expr::Literal(_) | Expr::Wildcard => expr.clone(),
+    }
+}
+
+/// Move the limits and the projections of columns above database table scans into the
+/// queries of the scans, along with the projections of the scans. Operators whose
+/// expressions cannot be rendered as SQL stay in the plan.
+pub fn push_down_sql_queries(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    if let Some(conn_str) = sql_subplan(plan, true) {
+        let dialect = SqlDialect::from_connection_string(conn_str)?;
+        if let Ok(query) = plan_to_sql(plan, dialect) {
+            let schema = plan.schema().clone();
+            return Ok(LogicalPlan::FileScan {
+                path: conn_str.to_owned(),
+                paths: vec![],
+                files: vec![query],
+                partition_columns: vec![],
+                file_type: "sql".to_owned(),
+                projected_schema: schema.clone(),
+                schema,
+                projection: None,
+                csv_options: None,
+                filters: vec![],
+                limit: None,
+            });
+        }
+    }
+    Ok(plan.with_new_inputs(
+        plan.inputs()
+            .into_iter()
+            .map(push_down_sql_queries)
+            .collect::<Result<Vec<_>>>()?,
+    ))
+}
+
+/// The connection string of the database table that a plan scans, if the plan only limits
+/// and selects columns of a single database scan. Scans that read every column are only
+/// included below other operators, so that the rewritten plans are not rewritten again.
+fn sql_subplan(plan: &LogicalPlan, root: bool) -> Option<&str> {
+    match plan {
+        LogicalPlan::Limit { input, .. } => sql_subplan(input, false),
+        LogicalPlan::Projection { expr, input, .. } if expr.iter().all(is_column) => {
+            sql_subplan(input, false)
+        }
+        LogicalPlan::FileScan {
+            path,
+            files,
+            file_type,
+            projection,
+            ..
+        } if file_type == "sql" && files.len() == 1 && (!root || projection.is_some()) => {
+            Some(path)
+        }
+        _ => None,
+    }
+}
+
+fn is_column(expr: &Expr) -> bool {
+    match expr {
+        Expr::Column(_) | Expr::UnresolvedColumn(_) => true,
+        Expr::Alias(expr, _) => is_column(expr),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{ArrayRef, Int32Array};
+    use crate::arrow::datatypes::DataType;
+    use crate::arrow::record_batch::RecordBatch;
+    use crate::logicalplan::{col_index, scalar_function};
+
+    fn scan(name: &str, columns: &[&str]) -> LogicalPlan {
+        let schema = Schema::new(
+            columns
+                .iter()
+                .map(|c| Field::new(c, DataType::Int32, false))
+                .collect(),
+        );
+        LogicalPlan::FileScan {
+            path: name.to_owned(),
+            paths: vec![],
+            files: vec![name.to_owned()],
+            partition_columns: vec![],
+            file_type: "csv".to_owned(),
+            schema: schema.clone(),
+            projection: None,
+            projected_schema: schema,
+            csv_options: None,
+            filters: vec![],
+            limit: None,
+        }
+    }
+
+    fn projection(scan: &LogicalPlan) -> Option<Vec<usize>> {
+        match scan {
+            LogicalPlan::FileScan { projection, .. } => projection.clone(),
+            other => panic!("expected a scan but found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn push_projections_through_joins() -> Result<()> {
+        let join = LogicalPlan::Join {
+            left: Box::new(scan("orders", &["id", "customer", "total", "notes"])),
+            right: Box::new(scan("customers", &["id", "name", "address"])),
+            on: vec![(1, 0)],
+            null_equals_null: false,
+            schema: Schema::new(
+                [
+                    "id", "customer", "total", "notes", "c_id", "name", "address",
+                ]
+                .iter()
+                .map(|c| Field::new(c, DataType::Int32, false))
+                .collect(),
+            ),
+        };
+        let plan = LogicalPlan::Projection {
+            expr: vec![Expr::Column(5), Expr::Column(2)],
+            input: Box::new(LogicalPlan::Selection {
+                expr: col_index(2).gt(&Expr::Literal(ScalarValue::Int32(100))),
+                input: Box::new(join),
+            }),
+            schema: Schema::new(vec![
+                Field::new("name", DataType::Int32, false),
+                Field::new("total", DataType::Int32, false),
+            ]),
+        };
+
+        let optimized = push_down_projections(&plan)?;
+        assert_eq!(plan.schema(), optimized.schema());
+        let (expr, selection) = match &optimized {
+            LogicalPlan::Projection { expr, input, .. } => (expr, input.as_ref()),
+            other => panic!("expected a projection but found {:?}", other),
+        };
+        // the join produces orders.customer, orders.total, customers.id and customers.name
+        assert_eq!(&vec![Expr::Column(3), Expr::Column(1)], expr);
+        match selection {
+            LogicalPlan::Selection { expr, input } => {
+                assert_eq!(
+                    &col_index(1).gt(&Expr::Literal(ScalarValue::Int32(100))),
+                    expr
+                );
+                match input.as_ref() {
+                    LogicalPlan::Join {
+                        left, right, on, ..
+                    } => {
+                        assert_eq!(Some(vec![1, 2]), projection(left));
+                        assert_eq!(Some(vec![0, 1]), projection(right));
+                        assert_eq!(&vec![(0, 0)], on);
+                    }
+                    other => panic!("expected a join but found {:?}", other),
+                }
+            }
+            other => panic!("expected a selection but found {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn simplify_filters() -> Result<()> {
+        let scan = scan("orders", &["id", "total"]);
+        let literal = |v: i32| Expr::Literal(ScalarValue::Int32(v));
+        let sum = Expr::BinaryExpr {
+            left: Box::new(literal(1)),
+            op: Operator::Plus,
+            right: Box::new(literal(2)),
+        };
+        let always = sum.eq(&literal(3));
+        let total = Expr::Cast {
+            expr: Box::new(col_index(1)),
+            data_type: DataType::Int32,
+        };
+        let plan = LogicalPlan::Selection {
+            expr: total.gt(&literal(100)).and(&always),
+            input: Box::new(scan.clone()),
+        };
+        match simplify_expressions(&plan)? {
+            LogicalPlan::Selection { expr, .. } => assert_eq!(col_index(1).gt(&literal(100)), expr),
+            other => panic!("expected a selection but found {:?}", other),
+        }
+
+        let plan = LogicalPlan::Selection {
+            expr: Expr::Not(Box::new(always.not_eq(&always))),
+            input: Box::new(scan.clone()),
+        };
+        match simplify_expressions(&plan)? {
+            LogicalPlan::FileScan { .. } => {}
+            other => panic!("expected the filter to be removed but found {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fold_decimals_with_their_precision_and_scale() {
+        let a = ScalarValue::Decimal(1050, 4, 2);
+        let b = ScalarValue::Decimal(25, 2, 1);
+        let eval = |op| fold(&a, &op, &b);
+        assert_eq!(eval(Operator::Plus), Some(ScalarValue::Decimal(1300, 5, 2)));
+        assert_eq!(eval(Operator::Minus), Some(ScalarValue::Decimal(800, 5, 2)));
+        assert_eq!(
+            eval(Operator::Multiply),
+            Some(ScalarValue::Decimal(26250, 7, 3))
+        );
+        assert_eq!(eval(Operator::Divide), None);
+        assert_eq!(eval(Operator::Gt), Some(ScalarValue::Boolean(true)));
+        let large = ScalarValue::Decimal(1, 38, 0);
+        assert_eq!(fold(&large, &Operator::Multiply, &large), None);
+    }
+
+    #[derive(Debug)]
+    struct RenameScans;
+
+    impl OptimizerRule for RenameScans {
+        fn name(&self) -> &str {
+            "rename_scans"
+        }
+
+        fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+            let mut plan = plan.clone();
+            if let LogicalPlan::FileScan { ref mut path, .. } = plan {
+                *path = format!("{}_v2", path);
+            }
+            Ok(plan)
+        }
+    }
+
+    #[test]
+    fn register_rules() -> Result<()> {
+        let optimizer = Optimizer::new();
+        let position = RulePosition::After("simplify_expressions".to_owned());
+        optimizer.add_rule(Arc::new(RenameScans), position)?;
+        assert_eq!(
+            vec![
+                "simplify_expressions",
+                "rename_scans",
+                "push_down_filters",
+                "prune_partitions",
+                "reorder_joins",
+                "eliminate_common_subexpressions",
+                "push_down_limits",
+                "push_down_projections",
+                "push_down_sql_queries"
+            ],
+            optimizer.rule_names()
+        );
+        let position = RulePosition::Before("missing".to_owned());
+        assert!(optimizer.add_rule(Arc::new(RenameScans), position).is_err());
+
+        match optimizer.optimize(&scan("orders", &["id"]))? {
+            LogicalPlan::FileScan { path, .. } => assert_eq!("orders_v2", path),
+            other => panic!("expected a scan but found {:?}", other),
+        }
+        Ok(())
+    }
+
+    fn memory_scan(columns: &[&str], rows: usize) -> LogicalPlan {
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|c| Field::new(c, DataType::Int32, false))
+                .collect(),
+        ));
+        let values: Vec<i32> = (0..rows as i32).collect();
+        let arrays = columns
+            .iter()
+            .map(|_| Arc::new(Int32Array::from(values.clone())) as ArrayRef)
+            .collect();
+        LogicalPlan::MemoryScan(vec![RecordBatch::try_new(schema, arrays).unwrap()])
+    }
+
+    fn join(left: LogicalPlan, right: LogicalPlan, on: Vec<(usize, usize)>) -> LogicalPlan {
+        let fields = left
+            .schema()
+            .fields()
+            .iter()
+            .chain(right.schema().fields())
+            .cloned()
+            .collect();
+        LogicalPlan::Join {
+            left: Box::new(left),
+            right: Box::new(right),
+            on,
+            null_equals_null: false,
+            schema: Schema::new(fields),
+        }
+    }
+
+    #[test]
+    fn reorder_multi_way_joins() -> Result<()> {
+        let orders = memory_scan(&["id", "customer", "product"], 1000);
+        let products = memory_scan(&["id"], 500);
+        let customers = memory_scan(&["id"], 5);
+        // joining the orders with the few customers first keeps the intermediate result small
+        let plan = join(
+            join(orders, products, vec![(2, 0)]),
+            customers,
+            vec![(1, 0)],
+        );
+
+        let tables = TableRegistry::new();
+        let reordered = reorder_joins(&plan, &tables)?;
+        assert_eq!(plan.schema(), reordered.schema());
+        match reordered {
+            LogicalPlan::Projection { expr, input, .. } => {
+                let columns = vec![0, 1, 2, 4, 3];
+                assert_eq!(
+                    columns.into_iter().map(Expr::Column).collect::<Vec<_>>(),
+                    expr
+                );
+                match *input {
+                    LogicalPlan::Join { left, on, .. } => {
+                        assert_eq!(vec![(2, 0)], on);
+                        match *left {
+                            LogicalPlan::Join { right, on, .. } => {
+                                assert_eq!(vec![(1, 0)], on);
+                                assert_eq!(
+                                    Some(5.0),
+                                    estimate_statistics(&right, &tables).map(|s| s.num_rows)
+                                );
+                            }
+                            other => panic!("expected a join but found {:?}", other),
+                        }
+                    }
+                    other => panic!("expected a join but found {:?}", other),
+                }
+            }
+            other => panic!("expected a projection but found {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compute_repeated_functions_once() -> Result<()> {
+        let upper = scalar_function("upper", vec![col_index(0)], DataType::Utf8);
+        let plan = LogicalPlan::Projection {
+            expr: vec![
+                upper.clone(),
+                upper.eq(&Expr::Literal(ScalarValue::Utf8("A".into()))),
+            ],
+            input: Box::new(scan("names", &["name"])),
+            schema: Schema::new(vec![
+                Field::new("upper", DataType::Utf8, false),
+                Field::new("is_a", DataType::Boolean, false),
+            ]),
+        };
+
+        match eliminate_common_subexpressions(&plan)? {
+            LogicalPlan::Projection { expr, input, .. } => {
+                let common = Expr::Column(1);
+                assert_eq!(
+                    Expr::Alias(Box::new(common.clone()), "upper".to_owned()),
+                    expr[0]
+                );
+                match &*input {
+                    LogicalPlan::Projection { expr, schema, .. } => {
+                        assert_eq!(
+                            Expr::Alias(Box::new(upper), "__common_0".to_owned()),
+                            expr[1]
+                        );
+                        assert_eq!(&DataType::Utf8, schema.field(1).data_type());
+                    }
+                    other => panic!("expected a projection but found {:?}", other),
+                }
+            }
+            other => panic!("expected a projection but found {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn prune_partitions_through_projections() -> Result<()> {
+        let mut scan = scan("/events", &["id", "year"]);
+        if let LogicalPlan::FileScan {
+            ref mut files,
+            ref mut partition_columns,
+            ..
+        } = scan
+        {
+            *files = vec![
+                "/events/year=2019/part-0.parquet".to_owned(),
+                "/events/year=2020/part-0.parquet".to_owned(),
+            ];
+            *partition_columns = vec!["year".to_owned()];
+        }
+        let plan = LogicalPlan::Selection {
+            expr: col_index(0).eq(&Expr::Literal(ScalarValue::Int32(2020))),
+            input: Box::new(LogicalPlan::Projection {
+                expr: vec![
+                    Expr::Alias(Box::new(col_index(1)), "y".to_owned()),
+                    col_index(0),
+                ],
+                input: Box::new(scan),
+                schema: Schema::new(vec![
+                    Field::new("y", DataType::Int32, false),
+                    Field::new("id", DataType::Int32, false),
+                ]),
+            }),
+        };
+
+        let optimized = Optimizer::new().optimize(&plan)?;
+        let mut scan = &optimized;
+        while let Some(input) = scan.inputs().into_iter().next() {
+            scan = input;
+        }
+        match scan {
+            LogicalPlan::FileScan { files, .. } => {
+                assert_eq!(&vec!["/events/year=2020/part-0.parquet".to_owned()], files)
+            }
+            other => panic!("expected a scan but found {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn push_limits_into_scans() -> Result<()> {
+        let limit = |input: LogicalPlan, n: u64| LogicalPlan::Limit {
+            expr: Expr::Literal(ScalarValue::UInt64(n)),
+            schema: input.schema().clone(),
+            input: Box::new(input),
+        };
+        let scan = scan("orders", &["id", "amount"]);
+        let projection = LogicalPlan::Projection {
+            expr: vec![col_index(1)],
+            schema: select_fields(scan.schema(), &[1]),
+            input: Box::new(scan),
+        };
+        let plan = limit(limit(projection, 20), 10);
+
+        match push_down_limits(&plan)? {
+            LogicalPlan::Projection { input, .. } => match *input {
+                LogicalPlan::Limit { expr, input, .. } => {
+                    assert_eq!(Expr::Literal(ScalarValue::UInt64(10)), expr);
+                    match *input {
+                        LogicalPlan::FileScan { limit, .. } => assert_eq!(Some(10), limit),
+                        other => panic!("expected a scan but found {:?}", other),
+                    }
+                }
+                other => panic!("expected a limit but found {:?}", other),
+            },
+            other => panic!("expected a projection but found {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn push_limits_and_projections_into_database_queries() -> Result<()> {
+        let mut scan = scan("orders", &["id", "amount"]);
+        if let LogicalPlan::FileScan {
+            ref mut path,
+            ref mut files,
+            ref mut file_type,
+            ..
+        } = scan
+        {
+            *path = "postgres://localhost/shop".to_owned();
+            *files = vec!["SELECT \"id\", \"amount\" FROM \"orders\"".to_owned()];
+            *file_type = "sql".to_owned();
+        }
+        let projection = LogicalPlan::Projection {
+            expr: vec![col_index(1)],
+            schema: select_fields(scan.schema(), &[1]),
+            input: Box::new(scan),
+        };
+        let plan = LogicalPlan::Limit {
+            expr: Expr::Literal(ScalarValue::UInt64(5)),
+            schema: projection.schema().clone(),
+            input: Box::new(projection),
+        };
+
+        let optimized = push_down_sql_queries(&plan)?;
+        match &optimized {
+            LogicalPlan::FileScan { files, schema, .. } => {
+                assert_eq!(
+                    vec![
+                        "SELECT \"amount\" FROM (SELECT \"id\", \"amount\" FROM \"orders\") \
+                          AS \"t0\" LIMIT 5"
+                    ],
+                    *files
+                );
+                assert_eq!(plan.schema(), schema);
+            }
+            other => panic!("expected a scan but found {:?}", other),
+        }
+        assert_eq!(optimized, push_down_sql_queries(&optimized)?);
+        Ok(())
+    }
+
+    #[test]
+    fn push_filters_below_joins() -> Result<()> {
+        let orders = scan("orders", &["id", "customer"]);
+        let customers = scan("customers", &["id", "region"]);
+        let lit = |v| Expr::Literal(ScalarValue::Int32(v));
+        let both_sides = col_index(0).gt(&col_index(2));
+        let plan = LogicalPlan::Selection {
+            expr: col_index(1)
+                .eq(&lit(5))
+                .and(&col_index(3).eq(&lit(1)))
+                .and(&both_sides),
+            input: Box::new(join(orders, customers, vec![(1, 0)])),
+        };
+
+        match push_down_filters(&plan)? {
+            LogicalPlan::Selection { expr, input } => {
+                assert_eq!(both_sides, expr);
+                match *input {
+                    LogicalPlan::Join { left, right, .. } => {
+                        for (side, filter) in vec![
+                            (left, col_index(1).eq(&lit(5))),
+                            (right, col_index(1).eq(&lit(1))),
+                        ] {
+                            match *side {
+                                LogicalPlan::Selection { expr, .. } => assert_eq!(filter, expr),
+                                other => panic!("expected a filter but found {:?}", other),
+                            }
+                        }
+                    }
+                    other => panic!("expected a join but found {:?}", other),
+                }
+            }
+            other => panic!("expected a filter but found {:?}", other),
+        }
+        Ok(())
+    }
+}
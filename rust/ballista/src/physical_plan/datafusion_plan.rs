@@ -0,0 +1,55 @@
+//! Execution of the operators that DataFusion plans.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::arrow::datatypes::SchemaRef;
+use crate::datafusion::execution::physical_plan::{ExecutionPlan as DFExecutionPlan, Partition};
+use crate::error::{ballista_error, Result};
+use crate::physical_plan::{ExecutionPlan, OutputPartitioning, PartitionReader};
+
+/// A physical plan that DataFusion created, which executes the partitions of the plan
+pub struct DataFusionExec {
+    schema: SchemaRef,
+    partitions: Vec<Arc<dyn Partition>>,
+}
+
+impl DataFusionExec {
+    /// Wrap a DataFusion plan, creating its partitions
+    pub fn try_new(plan: &dyn DFExecutionPlan) -> Result<Self> {
+        Ok(Self {
+            schema: plan.schema(),
+            partitions: plan.partitions()?,
+        })
+    }
+}
+
+impl fmt::Debug for DataFusionExec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DataFusionExec")
+            .field("partitions", &self.partitions.len())
+            .finish()
+    }
+}
+
+impl ExecutionPlan for DataFusionExec {
+    fn name(&self) -> &'static str {
+        "DataFusionExec"
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> OutputPartitioning {
+        OutputPartitioning::Unknown(self.partitions.len())
+    }
+
+    fn execute(&self, partition: usize) -> Result<PartitionReader> {
+        let partition = self
+            .partitions
+            .get(partition)
+            .ok_or_else(|| ballista_error(&format!("Invalid partition {}", partition)))?;
+        Ok(partition.execute()?)
+    }
+}
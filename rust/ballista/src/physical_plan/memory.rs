@@ -0,0 +1,81 @@
+//! Execution of batches that are held in memory.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::datatypes::SchemaRef;
+use crate::arrow::error::Result as ArrowResult;
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::error::{ballista_error, Result};
+use crate::physical_plan::{ExecutionPlan, OutputPartitioning, PartitionReader};
+
+/// Produces batches that are held in memory, with a partition for each list of batches
+pub struct MemoryExec {
+    schema: SchemaRef,
+    partitions: Vec<Vec<RecordBatch>>,
+}
+
+impl MemoryExec {
+    pub fn new(schema: SchemaRef, partitions: Vec<Vec<RecordBatch>>) -> Self {
+        Self { schema, partitions }
+    }
+}
+
+impl fmt::Debug for MemoryExec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryExec")
+            .field("partitions", &self.partitions.len())
+            .finish()
+    }
+}
+
+impl ExecutionPlan for MemoryExec {
+    fn name(&self) -> &'static str {
+        "MemoryExec"
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> OutputPartitioning {
+        OutputPartitioning::Unknown(self.partitions.len())
+    }
+
+    fn execute(&self, partition: usize) -> Result<PartitionReader> {
+        let batches = self
+            .partitions
+            .get(partition)
+            .ok_or_else(|| ballista_error(&format!("Invalid partition {}", partition)))?;
+        Ok(Arc::new(Mutex::new(MemoryReader::new(
+            self.schema.clone(),
+            batches.clone(),
+        ))))
+    }
+}
+
+/// Reads batches that are held in memory
+pub struct MemoryReader {
+    schema: SchemaRef,
+    batches: VecDeque<RecordBatch>,
+}
+
+impl MemoryReader {
+    pub fn new(schema: SchemaRef, batches: Vec<RecordBatch>) -> Self {
+        Self {
+            schema,
+            batches: batches.into_iter().collect(),
+        }
+    }
+}
+
+impl RecordBatchReader for MemoryReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        Ok(self.batches.pop_front())
+    }
+}
@@ -0,0 +1,218 @@
+//! Physical plans that Ballista executes.
+//!
+//! A physical plan is a tree of operators that each produce their output as a number of
+//! partitions, which are executed independently as readers of batches. The operators that
+//! DataFusion implements are planned by DataFusion and wrapped in a `DataFusionExec`, while
+//! the operators of distributed queries, such as the readers and writers of shuffle
+//! partitions, are implemented here, so that every plan is executed in the same way
+//! whichever engine implements its operators. Ballista operators below DataFusion operators
+//! are registered with the DataFusion context as tables (see `PlanTable`).
+//!
+//! The partitions of a plan execute on the threads that they are read from, and
+//! `execute_stream` runs them on an execution pool as an asynchronous stream.
+
+pub mod datafusion_plan;
+pub mod memory;
+pub mod planner;
+pub mod shuffle;
+pub mod table;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::datatypes::SchemaRef;
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::cancel::CancellationToken;
+use crate::datafusion::error::{ExecutionError, Result as DataFusionResult};
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::error::Result;
+use crate::pool::ExecutionPool;
+use crate::stream::PartitionStream;
+
+pub use datafusion_plan::DataFusionExec;
+pub use memory::MemoryExec;
+pub use planner::PhysicalPlanner;
+pub use shuffle::{ShuffleReaderExec, ShuffleWriterExec};
+pub use table::PlanTable;
+
+/// Reader of the batches of a partition, in the form that DataFusion partitions produce
+pub type PartitionReader = Arc<Mutex<dyn RecordBatchReader + Send + Sync>>;
+
+/// How the output of an operator is split into partitions
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputPartitioning {
+    /// The given number of partitions, whose rows are not assigned by any key
+    Unknown(usize),
+    /// Rows are assigned to partitions by hashing the values of the given columns
+    Hash {
+        columns: Vec<usize>,
+        partitions: usize,
+    },
+}
+
+impl OutputPartitioning {
+    /// Number of partitions that the output is split into
+    pub fn partition_count(&self) -> usize {
+        match self {
+            OutputPartitioning::Unknown(partitions) => *partitions,
+            OutputPartitioning::Hash { partitions, .. } => *partitions,
+        }
+    }
+}
+
+/// An operator of a physical plan
+pub trait ExecutionPlan: fmt::Debug + Send + Sync {
+    /// Name of the operator, as shown in formatted plans and metrics
+    fn name(&self) -> &'static str;
+
+    /// The schema of the output
+    fn schema(&self) -> SchemaRef;
+
+    /// How the output is split into partitions
+    fn output_partitioning(&self) -> OutputPartitioning;
+
+    /// The operators whose output this operator reads
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    /// Execute a partition of the output, returning a reader of its batches
+    fn execute(&self, partition: usize) -> Result<PartitionReader>;
+}
+
+/// The partitions of a plan as DataFusion partitions, so that they can be executed where
+/// the partitions of DataFusion plans are
+pub fn partitions(plan: &Arc<dyn ExecutionPlan>) -> Vec<Arc<dyn Partition>> {
+    (0..plan.output_partitioning().partition_count())
+        .map(|partition| {
+            Arc::new(PlanPartition {
+                plan: plan.clone(),
+                partition,
+            }) as Arc<dyn Partition>
+        })
+        .collect()
+}
+
+/// Execute every partition of a plan in turn, collecting the batches
+pub fn collect(plan: &Arc<dyn ExecutionPlan>) -> Result<Vec<RecordBatch>> {
+    let mut batches = vec![];
+    for partition in 0..plan.output_partitioning().partition_count() {
+        let reader = execute(plan.as_ref(), partition)?;
+        let mut reader = reader.lock().unwrap();
+        while let Some(batch) = reader.next_batch()? {
+            batches.push(batch);
+        }
+    }
+    Ok(batches)
+}
+
+/// Execute the partitions of a plan on the pool as a stream of their batches, with at most
+/// `max_parallelism` partitions executing at the same time
+pub fn execute_stream(
+    plan: &Arc<dyn ExecutionPlan>,
+    token: &CancellationToken,
+    pool: Arc<ExecutionPool>,
+    max_parallelism: usize,
+) -> PartitionStream {
+    PartitionStream::execute(
+        plan.schema(),
+        partitions(plan),
+        token,
+        pool,
+        max_parallelism,
+    )
+}
+
+/// Format a plan with an operator on each line, indented below the operator that reads it
+pub fn format_plan(plan: &dyn ExecutionPlan) -> String {
+    fn format(plan: &dyn ExecutionPlan, indent: usize, text: &mut String) {
+        text.push_str(&format!(
+            "{}{}: partitions={}\n",
+            "  ".repeat(indent),
+            plan.name(),
+            plan.output_partitioning().partition_count()
+        ));
+        for child in plan.children() {
+            format(child.as_ref(), indent + 1, text);
+        }
+    }
+    let mut text = String::new();
+    format(plan, 0, &mut text);
+    text
+}
+
+/// Execute a partition of a plan, giving errors the context of the operator and the plan
+/// fragment rooted at it
+fn execute(plan: &dyn ExecutionPlan, partition: usize) -> Result<PartitionReader> {
+    plan.execute(partition)
+        .map_err(|e| e.in_operator(plan.name(), Some(format_plan(plan))))
+}
+
+/// A partition of a plan
+struct PlanPartition {
+    plan: Arc<dyn ExecutionPlan>,
+    partition: usize,
+}
+
+impl fmt::Debug for PlanPartition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PlanPartition")
+            .field("plan", &self.plan.name())
+            .field("partition", &self.partition)
+            .finish()
+    }
+}
+
+impl Partition for PlanPartition {
+    fn execute(&self) -> DataFusionResult<PartitionReader> {
+        execute(self.plan.as_ref(), self.partition)
+            .map_err(|e| ExecutionError::General(format!("{:?}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::datafusion::execution::context::ExecutionContext;
+    use crate::datasource::object_store::ObjectStoreRegistry;
+    use crate::execution_metrics::MetricsCollector;
+    use crate::logicalplan::{col, LogicalPlan, LogicalPlanBuilder};
+    use std::collections::HashMap;
+
+    #[test]
+    fn execute_native_and_datafusion_operators() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = |values: Vec<i32>| {
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(values))])
+        };
+        let memory: Arc<dyn ExecutionPlan> = Arc::new(MemoryExec::new(
+            schema.clone(),
+            vec![vec![batch(vec![1, 2])?], vec![batch(vec![3])?]],
+        ));
+        assert_eq!(2, partitions(&memory).len());
+        assert_eq!(
+            3,
+            collect(&memory)?
+                .iter()
+                .map(|b| b.num_rows())
+                .sum::<usize>()
+        );
+
+        // other operators are planned by DataFusion
+        let plan = LogicalPlanBuilder::from(&LogicalPlan::MemoryScan(vec![batch(vec![1, 2])?]))
+            .project(vec![col("a")])?
+            .build()?;
+        let object_stores = ObjectStoreRegistry::new(&HashMap::new());
+        let metrics = MetricsCollector::new();
+        let planner = PhysicalPlanner::new(&object_stores, &metrics);
+        let plan = planner.create_physical_plan(&mut ExecutionContext::new(), &plan)?;
+        assert!(format_plan(plan.as_ref()).starts_with("DataFusionExec"));
+        assert_eq!(
+            2,
+            collect(&plan)?.iter().map(|b| b.num_rows()).sum::<usize>()
+        );
+        Ok(())
+    }
+}
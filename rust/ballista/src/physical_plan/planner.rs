@@ -0,0 +1,133 @@
+//! Planning of the physical plans that executors run for the logical plans of tasks.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::arrow::datatypes::Schema;
+use crate::datafusion::execution::context::ExecutionContext;
+use crate::datasource::object_store::ObjectStoreRegistry;
+use crate::datasource::DEFAULT_BATCH_SIZE;
+use crate::error::{ballista_error, Result};
+use crate::execution_metrics::MetricsCollector;
+use crate::logicalplan::LogicalPlan;
+use crate::memory::QueryMemory;
+use crate::physical_plan::{DataFusionExec, ExecutionPlan, PlanTable, ShuffleReaderExec};
+use crate::shuffle::{ShuffleLocation, ShufflePartitionId};
+use crate::translate::translate_plan_with_metrics;
+
+use log::debug;
+use tracing::info_span;
+
+/// Creates physical plans, planning shuffle reads as Ballista operators and the other
+/// operators with DataFusion
+pub struct PhysicalPlanner<'a> {
+    object_stores: &'a ObjectStoreRegistry,
+    metrics: &'a MetricsCollector,
+    memory: Option<&'a QueryMemory>,
+    fetched: Option<&'a HashMap<ShufflePartitionId, PathBuf>>,
+    batch_size: usize,
+}
+
+impl<'a> PhysicalPlanner<'a> {
+    /// Create a planner that reads remote files through the object stores and records the
+    /// metrics of the operators that execute while the plan is created
+    pub fn new(object_stores: &'a ObjectStoreRegistry, metrics: &'a MetricsCollector) -> Self {
+        Self {
+            object_stores,
+            metrics,
+            memory: None,
+            fetched: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Reserve the memory of the batches that are read into memory while the plan is
+    /// created for a query, rather than only tracking it while the plan is created
+    pub fn with_memory(mut self, memory: &'a QueryMemory) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    /// Read shuffle partitions from the local files that they were fetched into
+    pub fn with_fetched_shuffle(
+        mut self,
+        fetched: &'a HashMap<ShufflePartitionId, PathBuf>,
+    ) -> Self {
+        self.fetched = Some(fetched);
+        self
+    }
+
+    /// The number of rows in the batches of the operators that DataFusion plans
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Create the physical plan for a logical plan. Shuffle reads below other operators are
+    /// registered with the context as tables.
+    pub fn create_physical_plan(
+        &self,
+        ctx: &mut ExecutionContext,
+        plan: &LogicalPlan,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if let LogicalPlan::ShuffleRead { locations, schema } = plan {
+            if !locations.is_empty() {
+                return Ok(Arc::new(self.shuffle_reader(locations, schema)?));
+            }
+        }
+
+        let mut tables = 0;
+        let plan = plan.transform_up(|plan| match plan {
+            LogicalPlan::ShuffleRead { locations, schema } if locations.is_empty() => {
+                Ok(LogicalPlan::EmptyRelation { schema })
+            }
+            LogicalPlan::ShuffleRead { locations, schema } => {
+                let reader = self.shuffle_reader(&locations, &schema)?;
+                let table_name = format!("shuffle_read_{}", tables);
+                tables += 1;
+                ctx.register_table(&table_name, Box::new(PlanTable::new(Arc::new(reader))));
+                Ok(LogicalPlan::TableScan {
+                    table_name,
+                    projected_schema: schema.clone(),
+                    schema,
+                    projection: None,
+                })
+            }
+            other => Ok(other),
+        })?;
+
+        let untracked = QueryMemory::default();
+        let memory = self.memory.unwrap_or(&untracked);
+        let datafusion_plan =
+            translate_plan_with_metrics(ctx, &plan, self.object_stores, self.metrics, memory)?;
+        let optimized_plan = info_span!("optimize").in_scope(|| ctx.optimize(&datafusion_plan))?;
+        debug!("Optimized Plan: {:?}", optimized_plan);
+        let physical_plan = info_span!("create_physical_plan")
+            .in_scope(|| ctx.create_physical_plan(&optimized_plan, self.batch_size))?;
+        Ok(Arc::new(DataFusionExec::try_new(physical_plan.as_ref())?))
+    }
+
+    /// A reader of the local files that shuffle partitions were fetched into
+    fn shuffle_reader(
+        &self,
+        locations: &[ShuffleLocation],
+        schema: &Schema,
+    ) -> Result<ShuffleReaderExec> {
+        let files = locations
+            .iter()
+            .map(|location| {
+                self.fetched
+                    .and_then(|fetched| fetched.get(&location.partition_id))
+                    .cloned()
+                    .ok_or_else(|| {
+                        ballista_error(&format!(
+                            "Shuffle partition {:?} was not fetched",
+                            location.partition_id
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ShuffleReaderExec::new(files, Arc::new(schema.clone())))
+    }
+}
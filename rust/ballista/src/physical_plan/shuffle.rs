@@ -0,0 +1,168 @@
+//! Operators that read and write the shuffle partitions of distributed queries.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::datatypes::SchemaRef;
+use crate::arrow::record_batch::RecordBatch;
+use crate::cancel::CancellationToken;
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::ipc;
+use crate::error::{ballista_error, Result};
+use crate::physical_plan::memory::MemoryReader;
+use crate::physical_plan::{partitions, ExecutionPlan, OutputPartitioning, PartitionReader};
+use crate::shuffle::{self, Partitioning, ShuffleDirs, ShuffleWriter};
+
+/// Reads shuffle partitions that were fetched into local files, with a partition for
+/// each file
+pub struct ShuffleReaderExec {
+    files: Vec<PathBuf>,
+    schema: SchemaRef,
+}
+
+impl ShuffleReaderExec {
+    pub fn new(files: Vec<PathBuf>, schema: SchemaRef) -> Self {
+        Self { files, schema }
+    }
+}
+
+impl fmt::Debug for ShuffleReaderExec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShuffleReaderExec")
+            .field("files", &self.files)
+            .finish()
+    }
+}
+
+impl ExecutionPlan for ShuffleReaderExec {
+    fn name(&self) -> &'static str {
+        "ShuffleReaderExec"
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> OutputPartitioning {
+        OutputPartitioning::Unknown(self.files.len())
+    }
+
+    fn execute(&self, partition: usize) -> Result<PartitionReader> {
+        let file = self
+            .files
+            .get(partition)
+            .ok_or_else(|| ballista_error(&format!("Invalid partition {}", partition)))?;
+        let reader = ipc::open(&file.to_string_lossy())?;
+        Ok(Arc::new(Mutex::new(ipc::ProjectedReader::new(
+            reader,
+            (0..self.schema.fields().len()).collect(),
+            self.schema.clone(),
+        ))))
+    }
+}
+
+/// Writes every partition of its input as the shuffle partitions of a task, producing a
+/// single partition with the summary of the shuffle partitions that were written
+pub struct ShuffleWriterExec {
+    input: Arc<dyn ExecutionPlan>,
+    shuffle_dirs: ShuffleDirs,
+    job_id: String,
+    stage_id: usize,
+    map_partition: usize,
+    partitioning: Partitioning,
+    token: CancellationToken,
+}
+
+impl ShuffleWriterExec {
+    pub fn new(
+        input: Arc<dyn ExecutionPlan>,
+        shuffle_dirs: ShuffleDirs,
+        job_id: &str,
+        stage_id: usize,
+        map_partition: usize,
+        partitioning: Partitioning,
+    ) -> Self {
+        Self {
+            input,
+            shuffle_dirs,
+            job_id: job_id.to_owned(),
+            stage_id,
+            map_partition,
+            partitioning,
+            token: CancellationToken::new(),
+        }
+    }
+
+    /// Stop writing when the token is cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.token = token;
+        self
+    }
+}
+
+impl fmt::Debug for ShuffleWriterExec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShuffleWriterExec")
+            .field("job_id", &self.job_id)
+            .field("stage_id", &self.stage_id)
+            .field("map_partition", &self.map_partition)
+            .field("partitioning", &self.partitioning)
+            .finish()
+    }
+}
+
+impl ExecutionPlan for ShuffleWriterExec {
+    fn name(&self) -> &'static str {
+        "ShuffleWriterExec"
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::new(shuffle::summary_schema())
+    }
+
+    fn output_partitioning(&self) -> OutputPartitioning {
+        OutputPartitioning::Unknown(1)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn execute(&self, partition: usize) -> Result<PartitionReader> {
+        if partition != 0 {
+            return Err(ballista_error(&format!("Invalid partition {}", partition)));
+        }
+        let writer = ShuffleWriter::try_new(
+            &self.shuffle_dirs,
+            &self.job_id,
+            self.stage_id,
+            self.map_partition,
+            &self.partitioning,
+            &self.input.schema(),
+        )?;
+        let summary = write_partitions(&partitions(&self.input), writer, &self.token)?;
+        Ok(Arc::new(Mutex::new(MemoryReader::new(
+            self.schema(),
+            vec![summary],
+        ))))
+    }
+}
+
+/// Execute partitions in turn, writing their batches as shuffle partitions and returning
+/// the summary of the shuffle partitions
+pub(crate) fn write_partitions(
+    partitions: &[Arc<dyn Partition>],
+    mut writer: ShuffleWriter,
+    token: &CancellationToken,
+) -> Result<RecordBatch> {
+    for partition in partitions {
+        let reader = partition.execute()?;
+        let mut reader = reader.lock().unwrap();
+        while let Some(batch) = reader.next_batch()? {
+            token.check()?;
+            writer.write(&batch)?;
+        }
+    }
+    writer.finish()
+}
@@ -0,0 +1,197 @@
+//! Tables that read the output of Ballista operators, so that DataFusion operators can be
+//! planned above them, and tables that read the inputs of unions as they are scanned.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::datatypes::{Schema, SchemaRef};
+use crate::arrow::error::{ArrowError, Result as ArrowResult};
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datafusion::datasource::TableProvider;
+use crate::datafusion::error::{ExecutionError, Result as DataFusionResult};
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::adapt_batch;
+use crate::datasource::ipc::ProjectedReader;
+use crate::physical_plan::{ExecutionPlan, PartitionReader};
+
+/// Table whose partitions are the partitions of a plan
+pub struct PlanTable {
+    plan: Arc<dyn ExecutionPlan>,
+}
+
+impl PlanTable {
+    pub fn new(plan: Arc<dyn ExecutionPlan>) -> Self {
+        Self { plan }
+    }
+}
+
+impl TableProvider for PlanTable {
+    fn schema(&self) -> SchemaRef {
+        self.plan.schema()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let table_schema = self.plan.schema();
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..table_schema.fields().len()).collect());
+        let schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| table_schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok((0..self.plan.output_partitioning().partition_count())
+            .map(|partition| {
+                Arc::new(PlanTablePartition {
+                    plan: self.plan.clone(),
+                    partition,
+                    projection: projection.clone(),
+                    schema: schema.clone(),
+                }) as Arc<dyn Partition>
+            })
+            .collect())
+    }
+}
+
+/// A partition of a `PlanTable`
+struct PlanTablePartition {
+    plan: Arc<dyn ExecutionPlan>,
+    partition: usize,
+    projection: Vec<usize>,
+    /// The schema of the projected columns
+    schema: SchemaRef,
+}
+
+impl fmt::Debug for PlanTablePartition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PlanTablePartition")
+            .field("plan", &self.plan.name())
+            .field("partition", &self.partition)
+            .field("projection", &self.projection)
+            .finish()
+    }
+}
+
+impl Partition for PlanTablePartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let reader = self
+            .plan
+            .execute(self.partition)
+            .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            Box::new(SharedReader(reader)),
+            self.projection.clone(),
+            self.schema.clone(),
+        ))))
+    }
+}
+
+/// Reads the batches of a reader that is shared behind a lock
+struct SharedReader(PartitionReader);
+
+impl RecordBatchReader for SharedReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.0.lock().unwrap().schema()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        self.0.lock().unwrap().next_batch()
+    }
+}
+
+/// Table whose partitions are the partitions of the plans of the inputs of a union. The
+/// batches of each input are adapted to the schema of the union as they are read, matching
+/// columns by name.
+pub struct UnionTable {
+    schema: SchemaRef,
+    partitions: Vec<Arc<dyn Partition>>,
+}
+
+impl UnionTable {
+    pub fn new(schema: SchemaRef, partitions: Vec<Arc<dyn Partition>>) -> Self {
+        Self { schema, partitions }
+    }
+}
+
+impl TableProvider for UnionTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let projected_schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok(self
+            .partitions
+            .iter()
+            .map(|partition| {
+                Arc::new(UnionPartition {
+                    input: partition.clone(),
+                    schema: self.schema.clone(),
+                    projection: projection.clone(),
+                    projected_schema: projected_schema.clone(),
+                }) as Arc<dyn Partition>
+            })
+            .collect())
+    }
+}
+
+/// A partition of a `UnionTable`, which is a partition of the plan of one of its inputs
+struct UnionPartition {
+    input: Arc<dyn Partition>,
+    /// The schema of the union
+    schema: SchemaRef,
+    projection: Vec<usize>,
+    projected_schema: SchemaRef,
+}
+
+impl Partition for UnionPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let reader = AdaptedReader {
+            input: SharedReader(self.input.execute()?),
+            schema: self.schema.clone(),
+        };
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            Box::new(reader),
+            self.projection.clone(),
+            self.projected_schema.clone(),
+        ))))
+    }
+}
+
+/// Adapts the batches of a reader to a wider schema
+struct AdaptedReader {
+    input: SharedReader,
+    schema: SchemaRef,
+}
+
+impl RecordBatchReader for AdaptedReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        match self.input.next_batch()? {
+            Some(batch) => adapt_batch(&batch, &self.schema)
+                .map(Some)
+                .map_err(|e| ArrowError::ComputeError(format!("{:?}", e))),
+            None => Ok(None),
+        }
+    }
+}
@@ -1,8 +1,81 @@
 use crate::logicalplan::LogicalPlan;
+use crate::shuffle::Partitioning;
 
 #[derive(Debug, Clone)]
 pub enum Action {
-    Collect { plan: LogicalPlan },
-    WriteCsv { plan: LogicalPlan, path: String },
-    WriteParquet { plan: LogicalPlan, path: String },
+    Collect {
+        plan: LogicalPlan,
+    },
+    /// Execute a plan and write the output as CSV files with a header row in the `path`
+    /// directory. The result is the manifest of the files. Only the Spark executor executes
+    /// these actions.
+    WriteCsv {
+        plan: LogicalPlan,
+        path: String,
+    },
+    /// Execute a plan and write the output to the Parquet file for the given partition in
+    /// the `path` directory. The result is the manifest of the file. Schedulers write a file
+    /// for each task of the last stage of the plan instead.
+    WriteParquet {
+        plan: LogicalPlan,
+        path: String,
+        partition: usize,
+    },
+    /// Execute a task of a distributed query, writing the output as shuffle partitions.
+    /// The result is the number of rows written to each partition.
+    ShuffleWrite {
+        job_id: String,
+        stage_id: usize,
+        partition: usize,
+        plan: LogicalPlan,
+        partitioning: Partitioning,
+    },
+    /// Fetch the schema of data that the executor can access, so that clients can plan
+    /// queries against data that they cannot read themselves. `file_type` is the format of
+    /// the files at `path`, or "table" for a table registered on the executor, whose name is
+    /// the path. The result is an empty stream with the schema.
+    FetchSchema {
+        path: String,
+        file_type: String,
+    },
+    /// List the tables registered on the executor. The result has a `name` column with the
+    /// name of each table, in order.
+    ListTables,
 }
+
+/// Flight action type for submitting a serialized `Action`. The result of the action is a
+/// ticket that is used to fetch the results with `DoGet`.
+pub const SUBMIT_ACTION_TYPE: &str = "ballista.submit";
+
+/// Flight action type for cancelling a submitted action. The body is the ticket returned
+/// when the action was submitted.
+pub const CANCEL_ACTION_TYPE: &str = "ballista.cancel";
+
+/// Flight action type for removing the shuffle partitions of a job once they are no longer
+/// needed. The body is the job id.
+pub const REMOVE_SHUFFLE_ACTION_TYPE: &str = "ballista.removeShuffle";
+
+/// Flight action type for fetching the memory usage of an executor. The result is the usage
+/// encoded as JSON.
+pub const EXECUTOR_STATUS_ACTION_TYPE: &str = "ballista.executorStatus";
+
+/// Flight action type for listing the jobs of a scheduler. There is a result for each job,
+/// encoded as JSON.
+pub const LIST_JOBS_ACTION_TYPE: &str = "ballista.listJobs";
+
+/// Flight action type for fetching the progress of a job from a scheduler. The body is the
+/// job id, and the result is the job encoded as JSON.
+pub const JOB_STATUS_ACTION_TYPE: &str = "ballista.jobStatus";
+
+/// Flight action type for cancelling a job that is running on a scheduler. The body is the
+/// job id.
+pub const CANCEL_JOB_ACTION_TYPE: &str = "ballista.cancelJob";
+
+/// Flight action type for fetching the queries that have run on an executor or scheduler,
+/// oldest first. There is a result for each query, encoded as JSON.
+pub const QUERY_HISTORY_ACTION_TYPE: &str = "ballista.queryHistory";
+
+/// Flight action type for executing a serialized Ballista action and returning its results
+/// as rows of JSON (see `json`). The body is the action, and there is a result for each row,
+/// encoded as a JSON object.
+pub const COLLECT_JSON_ACTION_TYPE: &str = "ballista.collectJson";
@@ -0,0 +1,212 @@
+//! Bounded thread pools for executing partitions.
+//!
+//! DataFusion operators block while they produce their batches, so partitions are executed
+//! on threads rather than as async tasks. Each local context and each executor owns an
+//! `ExecutionPool` with a fixed number of threads, which are shared by all of its queries,
+//! instead of spawning a thread for every partition. Jobs run in the order that they are
+//! submitted, so the first partitions of a query, which are consumed first, always start
+//! before the partitions that are submitted after them.
+
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::error::{BallistaError, Result};
+
+use log::warn;
+use tokio::sync::oneshot;
+
+/// Number of threads that execute the partitions of queries, which defaults to the number
+/// of CPUs
+pub const EXECUTION_THREADS: &str = "ballista.execution.threads";
+
+/// Maximum number of partitions of a query that execute at the same time, which defaults
+/// to the number of execution threads. Lower values leave threads for other queries.
+pub const MAX_PARALLELISM: &str = "ballista.execution.maxParallelism";
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed-size pool of threads that execute blocking jobs in submission order
+#[derive(Debug)]
+pub struct ExecutionPool {
+    sender: Mutex<mpsc::Sender<Job>>,
+    threads: usize,
+}
+
+impl ExecutionPool {
+    /// Create a pool with the given number of threads, which is at least one
+    pub fn new(threads: usize) -> Self {
+        let threads = threads.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("ballista-exec-{}", i))
+                .spawn(move || loop {
+                    // the lock is released before the job runs. The threads exit once the
+                    // pool has been dropped.
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => return,
+                    }
+                })
+                .expect("Failed to spawn execution thread");
+        }
+        Self {
+            sender: Mutex::new(sender),
+            threads,
+        }
+    }
+
+    /// Create a pool with the number of threads in the `ballista.execution.threads`
+    /// setting, or one thread for each CPU if it is not set
+    pub fn from_settings(settings: &HashMap<String, String>) -> Self {
+        Self::new(setting(settings, EXECUTION_THREADS).unwrap_or_else(num_cpus))
+    }
+
+    /// The number of threads in the pool
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// The number of partitions of each query that may execute at the same time, from the
+    /// `ballista.execution.maxParallelism` setting, which is at most the size of the pool
+    pub fn max_parallelism(&self, settings: &HashMap<String, String>) -> usize {
+        setting(settings, MAX_PARALLELISM)
+            .unwrap_or(self.threads)
+            .max(1)
+            .min(self.threads)
+    }
+
+    /// Run a job on the pool once a thread is free. Panics in the job are reported by the
+    /// handle rather than stopping the thread.
+    pub fn spawn<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            let _ = tx.send(result.ok());
+        });
+        // the threads only stop receiving when the pool is dropped, so sending cannot fail
+        let _ = self.sender.lock().unwrap().send(job);
+        TaskHandle { rx }
+    }
+}
+
+/// Handle to the result of a job running on an `ExecutionPool`. Dropping the handle does
+/// not stop the job.
+pub struct TaskHandle<T> {
+    rx: oneshot::Receiver<Option<T>>,
+}
+
+impl<T> TaskHandle<T> {
+    /// Wait for the job to complete, failing if it panicked
+    pub async fn join(self) -> Result<T> {
+        match self.rx.await {
+            Ok(Some(result)) => Ok(result),
+            _ => Err(BallistaError::General(
+                "Execution thread panicked".to_owned(),
+            )),
+        }
+    }
+
+    /// Block the current thread until the job completes, failing if it panicked. This must
+    /// not be called from a job on the same pool, which could wait for itself.
+    pub fn wait(self) -> Result<T> {
+        futures::executor::block_on(self.join())
+    }
+}
+
+/// The number of partitions of each query that may execute at the same time on a pool that
+/// is created from the settings, which is also the size of the pools for the blocking reads
+/// of a query's data sources
+pub fn parallelism(settings: &HashMap<String, String>) -> usize {
+    let threads = setting(settings, EXECUTION_THREADS).unwrap_or_else(num_cpus);
+    setting(settings, MAX_PARALLELISM)
+        .unwrap_or(threads)
+        .min(threads)
+}
+
+/// Parse a setting that is a number of threads, ignoring it with a warning if it is invalid
+fn setting(settings: &HashMap<String, String>, name: &str) -> Option<usize> {
+    let value = settings.get(name)?;
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Some(n),
+        _ => {
+            warn!("Invalid value for {}: {}", name, value);
+            None
+        }
+    }
+}
+
+/// The number of CPUs that are online, or one if it cannot be determined
+fn num_cpus() -> usize {
+    let n = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if n > 0 {
+        n as usize
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn bound_running_jobs() -> Result<()> {
+        let pool = ExecutionPool::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(Mutex::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let running = running.clone();
+                let max_running = max_running.clone();
+                pool.spawn(move || {
+                    let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    {
+                        let mut max = max_running.lock().unwrap();
+                        *max = (*max).max(n);
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                })
+            })
+            .collect();
+        let mut results = vec![];
+        for handle in handles {
+            results.push(handle.join().await?);
+        }
+        assert_eq!((0..8).collect::<Vec<_>>(), results);
+        assert_eq!(2, *max_running.lock().unwrap());
+
+        // a panicking job is reported without losing the thread
+        assert!(pool.spawn(|| panic!("job failed")).join().await.is_err());
+        assert_eq!(1, pool.spawn(|| 1).join().await?);
+
+        let settings: HashMap<String, String> = vec![(MAX_PARALLELISM.to_owned(), "4".to_owned())]
+            .into_iter()
+            .collect();
+        assert_eq!(2, pool.max_parallelism(&settings));
+        assert_eq!(2, pool.max_parallelism(&HashMap::new()));
+        assert_eq!(3, pool.spawn(|| 3).wait()?);
+
+        let settings: HashMap<String, String> = vec![
+            (EXECUTION_THREADS.to_owned(), "8".to_owned()),
+            (MAX_PARALLELISM.to_owned(), "4".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(4, parallelism(&settings));
+        Ok(())
+    }
+}
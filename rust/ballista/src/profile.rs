@@ -0,0 +1,212 @@
+//! Profiles of the operators of a query.
+//!
+//! Profiling is opt-in, with `DataFrame::collect_with_profile`, because it samples the CPU
+//! time of the executing thread around every batch. Profiled queries collect the metrics of
+//! their operators as usual (see `execution_metrics`), with the CPU time of each partition,
+//! and the report summarizes each operator by the share of the recorded time that it
+//! accounts for and the rate at which it produced rows and batches, so that the operator
+//! that dominates the runtime of a complex plan stands out. Clients ask executors to profile
+//! a query with request metadata, and the scheduler profiles the tasks of profiled jobs.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::execution_metrics::QueryMetrics;
+
+use tonic::metadata::MetadataMap;
+use tonic::Request;
+
+const PROFILE_METADATA: &str = "x-ballista-profile";
+
+/// The CPU time used by the current thread, or `None` on platforms where it is not
+/// available
+#[cfg(unix)]
+pub fn thread_cpu_time() -> Option<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // safe because the timespec is valid for the duration of the call
+    let result = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    if result == 0 {
+        Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    } else {
+        None
+    }
+}
+
+/// The CPU time used by the current thread, or `None` on platforms where it is not
+/// available
+#[cfg(not(unix))]
+pub fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+/// The profile of an operator, combined across its partitions
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperatorProfile {
+    pub operator: String,
+    /// The stage of a distributed query that the operator ran in
+    pub stage: Option<usize>,
+    /// Number of times the operator ran, such as once for each partition
+    pub instances: usize,
+    pub rows: u64,
+    pub batches: u64,
+    /// Time spent in the operator, summed across its instances
+    pub elapsed: Duration,
+    pub cpu_time: Duration,
+    pub slowest_batch: Duration,
+    /// Fraction of the time recorded for all of the operators that was spent in this
+    /// operator
+    pub share: f64,
+}
+
+impl OperatorProfile {
+    pub fn rows_per_second(&self) -> f64 {
+        per_second(self.rows, self.elapsed)
+    }
+
+    pub fn batches_per_second(&self) -> f64 {
+        per_second(self.batches, self.elapsed)
+    }
+}
+
+fn per_second(count: u64, elapsed: Duration) -> f64 {
+    if elapsed.as_secs_f64() > 0.0 {
+        count as f64 / elapsed.as_secs_f64()
+    } else {
+        0.0
+    }
+}
+
+/// The profile of a query, with its operators ordered by the time spent in them
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileReport {
+    /// Wall time of the query
+    pub elapsed: Duration,
+    pub operators: Vec<OperatorProfile>,
+}
+
+impl ProfileReport {
+    /// Build the profile of a query from the metrics of its operators
+    pub fn from_metrics(metrics: &QueryMetrics, elapsed: Duration) -> Self {
+        let mut operators: Vec<OperatorProfile> = vec![];
+        for m in &metrics.operators {
+            let index = operators
+                .iter()
+                .position(|p| p.operator == m.operator && p.stage == m.stage);
+            let profile = match index {
+                Some(i) => &mut operators[i],
+                None => {
+                    operators.push(OperatorProfile {
+                        operator: m.operator.clone(),
+                        stage: m.stage,
+                        instances: 0,
+                        rows: 0,
+                        batches: 0,
+                        elapsed: Duration::default(),
+                        cpu_time: Duration::default(),
+                        slowest_batch: Duration::default(),
+                        share: 0.0,
+                    });
+                    operators.last_mut().unwrap()
+                }
+            };
+            profile.instances += 1;
+            profile.rows += m.rows;
+            profile.batches += m.batches;
+            profile.elapsed += m.elapsed;
+            profile.cpu_time += m.cpu_time;
+            profile.slowest_batch = profile.slowest_batch.max(m.slowest_batch);
+        }
+
+        let total: f64 = operators.iter().map(|p| p.elapsed.as_secs_f64()).sum();
+        for profile in &mut operators {
+            if total > 0.0 {
+                profile.share = profile.elapsed.as_secs_f64() / total;
+            }
+        }
+        operators.sort_by(|a, b| b.elapsed.cmp(&a.elapsed));
+        Self { elapsed, operators }
+    }
+
+    /// The operator that the most time was spent in
+    pub fn dominant(&self) -> Option<&OperatorProfile> {
+        self.operators.first()
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Query took {:?}", self.elapsed)?;
+        for p in &self.operators {
+            write!(f, "{:5.1}% {}", p.share * 100.0, p.operator)?;
+            if let Some(stage) = p.stage {
+                write!(f, " stage={}", stage)?;
+            }
+            writeln!(
+                f,
+                ": instances={} rows={} batches={} elapsed={:?} cpu={:?} slowest_batch={:?} \
+                 rows/s={:.0} batches/s={:.1}",
+                p.instances,
+                p.rows,
+                p.batches,
+                p.elapsed,
+                p.cpu_time,
+                p.slowest_batch,
+                p.rows_per_second(),
+                p.batches_per_second()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Ask an executor to profile a query, which also needs the metrics to be requested so
+/// that the profile is sent after the results
+pub fn request_profile<T>(request: &mut Request<T>) {
+    request
+        .metadata_mut()
+        .insert(PROFILE_METADATA, "true".parse().unwrap());
+}
+
+/// Whether a client asked for a query to be profiled
+pub fn profile_requested(metadata: &MetadataMap) -> bool {
+    metadata
+        .get(PROFILE_METADATA)
+        .map(|v| v.to_str() == Ok("true"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution_metrics::{OperatorMetrics, EXECUTE_OPERATOR};
+
+    #[test]
+    fn profile_operators() {
+        let mut metrics = QueryMetrics::default();
+        for (operator, ms, batches) in &[(EXECUTE_OPERATOR, 300, 3), (EXECUTE_OPERATOR, 500, 5)] {
+            let mut m = OperatorMetrics::new(operator);
+            m.rows = 1000;
+            m.batches = *batches;
+            m.elapsed = Duration::from_millis(*ms);
+            m.slowest_batch = Duration::from_millis(*ms / 2);
+            metrics.operators.push(m);
+        }
+        let mut scan = OperatorMetrics::new("FileScan");
+        scan.elapsed = Duration::from_millis(200);
+        metrics.operators.push(scan);
+
+        let report = ProfileReport::from_metrics(&metrics, Duration::from_millis(600));
+        let dominant = report.dominant().unwrap();
+        assert_eq!(EXECUTE_OPERATOR, dominant.operator);
+        assert_eq!(2, dominant.instances);
+        assert_eq!(8, dominant.batches);
+        assert_eq!(Duration::from_millis(250), dominant.slowest_batch);
+        assert!((dominant.share - 0.8).abs() < 1e-9);
+        assert!((dominant.rows_per_second() - 2500.0).abs() < 1e-6);
+        assert_eq!("FileScan", report.operators[1].operator);
+        assert!(thread_cpu_time().is_some() || cfg!(not(unix)));
+    }
+}
@@ -0,0 +1,319 @@
+//! Python bindings of contexts and DataFrames, built with the `python` feature.
+//!
+//! The `ballista` Python module exposes `Context`, `DataFrame` and `Expr`, along with
+//! functions for building expressions, so that clusters can be queried from notebooks.
+//! Collected results are returned as a list of `pyarrow.RecordBatch`, which are imported
+//! through the Arrow C Data Interface without copying them, so pyarrow 0.17 or later must be
+//! installed. The GIL is released while queries run.
+//!
+//! ```python
+//! import ballista
+//! from ballista import col, lit, max
+//!
+//! ctx = ballista.Context.remote("localhost", 50051)
+//! batches = (
+//!     ctx.read_parquet("/data/tripdata")
+//!     .filter(col("passenger_count") > lit(1))
+//!     .aggregate([col("passenger_count")], [max(col("fare_amount"))])
+//!     .collect()
+//! )
+//! ```
+
+use std::collections::HashMap;
+
+use crate::arrow::record_batch::RecordBatch;
+use crate::c_data::{export_batch, export_schema, ArrowArray, ArrowSchema};
+use crate::dataframe::{self, Context, DataFrame};
+use crate::error::BallistaError;
+use crate::logicalplan::{self, Expr, JoinOptions, ScalarValue};
+
+use pyo3::basic::CompareOp;
+use pyo3::exceptions::{RuntimeError, TypeError};
+use pyo3::prelude::*;
+use pyo3::types::PyBool;
+use pyo3::wrap_pyfunction;
+use pyo3::{PyNumberProtocol, PyObjectProtocol};
+
+/// A context for running queries
+#[pyclass(name = Context)]
+pub struct PyContext {
+    ctx: Context,
+}
+
+#[pymethods]
+impl PyContext {
+    /// Create a context that executes queries in this process
+    #[staticmethod]
+    #[args(settings = "None")]
+    fn local(settings: Option<HashMap<String, String>>) -> Self {
+        let settings = settings.unwrap_or_default();
+        Self {
+            ctx: Context::local(borrow_settings(&settings)),
+        }
+    }
+
+    /// Create a context that executes queries on a remote executor or scheduler
+    #[staticmethod]
+    #[args(settings = "None")]
+    fn remote(host: &str, port: usize, settings: Option<HashMap<String, String>>) -> Self {
+        let settings = settings.unwrap_or_default();
+        Self {
+            ctx: Context::remote(host, port, borrow_settings(&settings)),
+        }
+    }
+
+    /// Create a context that executes queries on a Spark executor
+    #[staticmethod]
+    #[args(settings = "None")]
+    fn spark(master: &str, settings: Option<HashMap<String, String>>) -> PyResult<Self> {
+        let settings = settings.unwrap_or_default();
+        let ctx = Context::spark(master, borrow_settings(&settings)).map_err(py_err)?;
+        Ok(Self { ctx })
+    }
+
+    /// Read a Parquet file or a directory of Parquet files
+    fn read_parquet(&self, path: &str) -> PyResult<PyDataFrame> {
+        py_dataframe(self.ctx.read_parquet(path, None))
+    }
+
+    /// Read an Avro file or a directory of Avro files
+    fn read_avro(&self, path: &str) -> PyResult<PyDataFrame> {
+        py_dataframe(self.ctx.read_avro(path))
+    }
+
+    /// Read an Arrow IPC file or a directory of IPC files
+    fn read_ipc(&self, path: &str) -> PyResult<PyDataFrame> {
+        py_dataframe(self.ctx.read_ipc(path))
+    }
+
+    /// Scan a table registered with the context
+    fn table(&self, name: &str) -> PyResult<PyDataFrame> {
+        py_dataframe(self.ctx.table(name))
+    }
+}
+
+/// A query, which is run when its results are collected
+#[pyclass(name = DataFrame)]
+pub struct PyDataFrame {
+    df: DataFrame,
+}
+
+#[pymethods]
+impl PyDataFrame {
+    fn select(&self, exprs: Vec<PyExpr>) -> PyResult<Self> {
+        py_dataframe(self.df.project(exprs_of(exprs)))
+    }
+
+    fn filter(&self, predicate: PyExpr) -> PyResult<Self> {
+        py_dataframe(self.df.filter(predicate.expr))
+    }
+
+    fn aggregate(&self, group_by: Vec<PyExpr>, aggregates: Vec<PyExpr>) -> PyResult<Self> {
+        py_dataframe(self.df.aggregate(exprs_of(group_by), exprs_of(aggregates)))
+    }
+
+    fn sort(&self, exprs: Vec<PyExpr>) -> PyResult<Self> {
+        py_dataframe(self.df.sort(exprs_of(exprs)))
+    }
+
+    fn limit(&self, n: usize) -> PyResult<Self> {
+        py_dataframe(self.df.limit(n))
+    }
+
+    /// Join with another DataFrame on pairs of left and right column names, matching null
+    /// values of the join columns with each other if `null_equals_null` is true
+    #[args(null_equals_null = "false")]
+    fn join(
+        &self,
+        right: PyRef<PyDataFrame>,
+        on: Vec<(String, String)>,
+        null_equals_null: bool,
+    ) -> PyResult<Self> {
+        let on: Vec<(&str, &str)> = on
+            .iter()
+            .map(|(left, right)| (left.as_str(), right.as_str()))
+            .collect();
+        let options = JoinOptions { null_equals_null };
+        py_dataframe(self.df.join_with_options(&right.df, &on, options))
+    }
+
+    /// The logical plan of the query
+    fn explain(&self) -> String {
+        format!("{:?}", self.df.logical_plan())
+    }
+
+    /// Run the query, returning its results as a list of `pyarrow.RecordBatch`
+    fn collect(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        let df = &self.df;
+        let batches = py
+            .allow_threads(|| -> crate::error::Result<Vec<RecordBatch>> {
+                let mut runtime = tokio::runtime::Runtime::new()?;
+                runtime.block_on(df.collect())
+            })
+            .map_err(py_err)?;
+        batches.iter().map(|batch| to_pyarrow(py, batch)).collect()
+    }
+}
+
+/// An expression of a DataFrame. The comparison operators and `&`, `|` and `~` build
+/// expressions of the same operators.
+#[pyclass(name = Expr)]
+#[derive(Clone)]
+pub struct PyExpr {
+    expr: Expr,
+}
+
+#[pymethods]
+impl PyExpr {
+    fn alias(&self, name: &str) -> Self {
+        expr(self.expr.alias(name))
+    }
+
+    /// Whether the values are equal or both null, like `<=>` in SQL
+    fn is_not_distinct_from(&self, other: PyExpr) -> Self {
+        expr(self.expr.is_not_distinct_from(&other.expr))
+    }
+
+    /// Sort by the expression, in ascending order unless `ascending` is false
+    #[args(ascending = "true")]
+    fn sort(&self, ascending: bool) -> Self {
+        expr(self.expr.sort(ascending))
+    }
+}
+
+#[pyproto]
+impl PyObjectProtocol for PyExpr {
+    fn __richcmp__(&self, other: PyExpr, op: CompareOp) -> PyExpr {
+        expr(match op {
+            CompareOp::Lt => self.expr.lt(&other.expr),
+            CompareOp::Le => self.expr.lt_eq(&other.expr),
+            CompareOp::Eq => self.expr.eq(&other.expr),
+            CompareOp::Ne => self.expr.not_eq(&other.expr),
+            CompareOp::Gt => self.expr.gt(&other.expr),
+            CompareOp::Ge => self.expr.gt_eq(&other.expr),
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.expr)
+    }
+}
+
+#[pyproto]
+impl PyNumberProtocol for PyExpr {
+    fn __and__(lhs: PyExpr, rhs: PyExpr) -> PyExpr {
+        expr(lhs.expr.and(&rhs.expr))
+    }
+
+    fn __or__(lhs: PyExpr, rhs: PyExpr) -> PyExpr {
+        expr(lhs.expr.or(&rhs.expr))
+    }
+
+    fn __invert__(&self) -> PyExpr {
+        expr(self.expr.not())
+    }
+}
+
+/// A column of the input
+#[pyfunction]
+fn col(name: &str) -> PyExpr {
+    expr(logicalplan::col(name))
+}
+
+/// A literal boolean, integer, float or string
+#[pyfunction]
+fn lit(value: &PyAny) -> PyResult<PyExpr> {
+    let value = if let Ok(value) = value.downcast::<PyBool>() {
+        ScalarValue::Boolean(value.is_true())
+    } else if let Ok(value) = value.extract::<i64>() {
+        ScalarValue::Int64(value)
+    } else if let Ok(value) = value.extract::<f64>() {
+        ScalarValue::Float64(value)
+    } else if let Ok(value) = value.extract::<String>() {
+        ScalarValue::Utf8(value)
+    } else {
+        return Err(TypeError::py_err(
+            "Literals must be booleans, integers, floats or strings",
+        ));
+    };
+    Ok(expr(Expr::Literal(value)))
+}
+
+#[pyfunction]
+fn min(value: PyExpr) -> PyExpr {
+    expr(dataframe::min(value.expr))
+}
+
+#[pyfunction]
+fn max(value: PyExpr) -> PyExpr {
+    expr(dataframe::max(value.expr))
+}
+
+#[pyfunction]
+fn sum(value: PyExpr) -> PyExpr {
+    expr(dataframe::sum(value.expr))
+}
+
+#[pyfunction]
+fn avg(value: PyExpr) -> PyExpr {
+    expr(dataframe::avg(value.expr))
+}
+
+#[pyfunction]
+fn count(value: PyExpr) -> PyExpr {
+    expr(dataframe::count(value.expr))
+}
+
+/// The `ballista` Python module
+#[pymodule]
+fn ballista(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyContext>()?;
+    m.add_class::<PyDataFrame>()?;
+    m.add_class::<PyExpr>()?;
+    m.add_wrapped(wrap_pyfunction!(col))?;
+    m.add_wrapped(wrap_pyfunction!(lit))?;
+    m.add_wrapped(wrap_pyfunction!(min))?;
+    m.add_wrapped(wrap_pyfunction!(max))?;
+    m.add_wrapped(wrap_pyfunction!(sum))?;
+    m.add_wrapped(wrap_pyfunction!(avg))?;
+    m.add_wrapped(wrap_pyfunction!(count))?;
+    Ok(())
+}
+
+/// Import a batch into pyarrow. pyarrow moves the exported structs out when it imports
+/// them, so dropping them afterwards releases nothing, and they are released if the import
+/// fails.
+fn to_pyarrow(py: Python, batch: &RecordBatch) -> PyResult<PyObject> {
+    let mut array = Box::new(export_batch(batch).map_err(py_err)?);
+    let mut schema = Box::new(export_schema(&batch.schema()).map_err(py_err)?);
+    let array_ptr = array.as_mut() as *mut ArrowArray as usize;
+    let schema_ptr = schema.as_mut() as *mut ArrowSchema as usize;
+    let batch = py
+        .import("pyarrow")?
+        .get("RecordBatch")?
+        .call_method1("_import_from_c", (array_ptr, schema_ptr))?;
+    Ok(batch.to_object(py))
+}
+
+fn py_dataframe(df: crate::error::Result<DataFrame>) -> PyResult<PyDataFrame> {
+    df.map(|df| PyDataFrame { df }).map_err(py_err)
+}
+
+fn expr(expr: Expr) -> PyExpr {
+    PyExpr { expr }
+}
+
+fn exprs_of(exprs: Vec<PyExpr>) -> Vec<Expr> {
+    exprs.into_iter().map(|expr| expr.expr).collect()
+}
+
+fn borrow_settings(settings: &HashMap<String, String>) -> HashMap<&str, &str> {
+    settings
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
+
+fn py_err(e: BallistaError) -> PyErr {
+    RuntimeError::py_err(e.to_string())
+}
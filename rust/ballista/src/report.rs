@@ -0,0 +1,38 @@
+//! Reports of how queries were executed.
+//!
+//! `DataFrame::collect_with_report` returns a report with the results of a query, with the
+//! plan that was executed, how long the query took and the settings that it ran with, so
+//! that applications can log or display them deliberately rather than relying on the debug
+//! log of the context.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// How a query was executed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecutionReport {
+    /// The logical plan after the optimizer, as it was executed or sent to the cluster
+    pub optimized_plan: String,
+    /// For local contexts, the plan that DataFusion optimized and the number of partitions
+    /// of its physical plan. For remote and Spark contexts, the stages that the plan is
+    /// split into.
+    pub physical_plan: String,
+    /// How long the query took, from submitting it to receiving the last batch
+    pub elapsed: Duration,
+    /// The settings of the context, including the defaults of the settings that are not set
+    pub settings: BTreeMap<String, String>,
+}
+
+impl fmt::Display for ExecutionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Optimized plan:\n{}", self.optimized_plan.trim_end())?;
+        writeln!(f, "Physical plan:\n{}", self.physical_plan.trim_end())?;
+        writeln!(f, "Elapsed: {:?}", self.elapsed)?;
+        write!(f, "Settings:")?;
+        for (key, value) in &self.settings {
+            write!(f, "\n  {} = {}", key, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+//! Caching of query results keyed by their plans.
+//!
+//! Dashboards often issue the same queries over and over. When a result cache is enabled,
+//! the results of queries are kept in memory, keyed by the normalized optimized plan and the
+//! size and modification time of each file that it scans, so that a query is executed again
+//! only once its plan or its files change, and equivalent plans share their results. Plans
+//! that scan in-memory batches, custom tables, database tables or shuffle partitions are not
+//! cached, since there is no way to tell whether their inputs have changed. The least
+//! recently used results are evicted once the cache is full, and results expire once they
+//! are older than the time to live, if one is set.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::arrow::record_batch::RecordBatch;
+use crate::datasource::object_store::{self, LocalFileSystem, ObjectStoreRegistry};
+use crate::error::Result;
+use crate::logicalplan::LogicalPlan;
+use crate::memory::batch_memory_size;
+use crate::normalize::normalize_plan;
+use crate::visitor::{PlanVisitor, Recursion};
+
+use log::warn;
+
+/// Bytes of results that the cache holds, which is zero by default, disabling the cache
+pub const RESULT_CACHE_MAX_BYTES: &str = "ballista.resultCache.maxBytes";
+
+/// Milliseconds after which cached results expire. Results are kept until they are
+/// evicted when this is not set.
+pub const RESULT_CACHE_TTL_MS: &str = "ballista.resultCache.ttlMs";
+
+/// Identifies the results of a plan over particular versions of the files that it scans
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PlanKey {
+    plan: LogicalPlan,
+    /// The path, size and modification time of each file. The modification time of files
+    /// in remote object stores is not known.
+    files: Vec<(String, u64, Option<SystemTime>)>,
+}
+
+impl PlanKey {
+    /// The key of an optimized plan, or `None` if its results cannot be cached
+    pub fn new(plan: &LogicalPlan, object_stores: &ObjectStoreRegistry) -> Option<Self> {
+        let mut scanned = ScannedFiles::default();
+        if !plan.accept(&mut scanned).ok()? {
+            return None;
+        }
+        let files = scanned
+            .paths
+            .iter()
+            .map(|path| {
+                if object_store::scheme(path) == "file" {
+                    let metadata = fs::metadata(LocalFileSystem::local_path(path)).ok()?;
+                    Some((path.clone(), metadata.len(), metadata.modified().ok()))
+                } else {
+                    let size = object_stores.get(path).ok()?.size(path).ok()?;
+                    Some((path.clone(), size, None))
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            plan: normalize_plan(plan),
+            files,
+        })
+    }
+}
+
+/// Collects the files that a plan scans, stopping at inputs other than files
+#[derive(Default)]
+struct ScannedFiles {
+    paths: Vec<String>,
+}
+
+impl PlanVisitor for ScannedFiles {
+    fn pre_visit(&mut self, plan: &LogicalPlan) -> Result<Recursion> {
+        match plan {
+            LogicalPlan::FileScan {
+                files, file_type, ..
+            } if file_type != "sql" => {
+                self.paths.extend(files.iter().cloned());
+                Ok(Recursion::Continue)
+            }
+            LogicalPlan::FileScan { .. }
+            | LogicalPlan::TableScan { .. }
+            | LogicalPlan::MemoryScan(_)
+            | LogicalPlan::StageOutput { .. }
+            | LogicalPlan::ShuffleRead { .. } => Ok(Recursion::Stop),
+            _ => Ok(Recursion::Continue),
+        }
+    }
+}
+
+/// In-memory cache of the results of queries
+pub struct ResultCache {
+    max_bytes: usize,
+    ttl: Option<Duration>,
+    results: Mutex<CachedResults>,
+}
+
+#[derive(Default)]
+struct CachedResults {
+    results: HashMap<PlanKey, CachedResult>,
+    /// Keys in the order that their results were last used, least recent first
+    order: VecDeque<PlanKey>,
+    bytes: usize,
+}
+
+struct CachedResult {
+    batches: Vec<RecordBatch>,
+    bytes: usize,
+    cached: Instant,
+}
+
+impl ResultCache {
+    /// Create a cache that holds up to `max_bytes` of results, which expire after the time
+    /// to live if one is given. A cache without any capacity is disabled.
+    pub fn new(max_bytes: usize, ttl: Option<Duration>) -> Self {
+        Self {
+            max_bytes,
+            ttl,
+            results: Mutex::new(CachedResults::default()),
+        }
+    }
+
+    /// Create a cache that is configured by the `ballista.resultCache.maxBytes` and
+    /// `ballista.resultCache.ttlMs` settings, ignoring invalid values with a warning
+    pub fn from_settings(settings: &HashMap<String, String>) -> Self {
+        let setting = |name: &str| {
+            let value = settings.get(name)?;
+            match value.parse::<u64>() {
+                Ok(n) => Some(n),
+                Err(_) => {
+                    warn!("Invalid value for {}: {}", name, value);
+                    None
+                }
+            }
+        };
+        let max_bytes = setting(RESULT_CACHE_MAX_BYTES).unwrap_or(0) as usize;
+        let ttl = setting(RESULT_CACHE_TTL_MS).map(Duration::from_millis);
+        Self::new(max_bytes, ttl)
+    }
+
+    /// Whether results are cached
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// The key that the results of an optimized plan are cached by, or `None` if the cache
+    /// is disabled or the results of the plan cannot be cached
+    pub fn key(&self, plan: &LogicalPlan, object_stores: &ObjectStoreRegistry) -> Option<PlanKey> {
+        if self.is_enabled() {
+            PlanKey::new(plan, object_stores)
+        } else {
+            None
+        }
+    }
+
+    /// The cached results for a key, unless they have expired
+    pub fn get(&self, key: &PlanKey) -> Option<Vec<RecordBatch>> {
+        let mut cached = self.results.lock().unwrap();
+        let result = cached.remove(key)?;
+        if self.ttl.map_or(false, |ttl| result.cached.elapsed() > ttl) {
+            return None;
+        }
+        // the result is inserted again as the most recently used
+        let batches = result.batches.clone();
+        cached.insert(key.clone(), result);
+        Some(batches)
+    }
+
+    /// Cache the results of a plan, evicting the least recently used results to make room.
+    /// Results that are larger than the cache are not cached.
+    pub fn insert(&self, key: PlanKey, batches: &[RecordBatch]) {
+        let bytes = batches.iter().map(batch_memory_size).sum::<usize>() + key.plan.len();
+        if !self.is_enabled() || bytes > self.max_bytes {
+            return;
+        }
+        let mut cached = self.results.lock().unwrap();
+        cached.remove(&key);
+        while cached.bytes + bytes > self.max_bytes {
+            match cached.order.front().cloned() {
+                Some(oldest) => drop(cached.remove(&oldest)),
+                None => break,
+            }
+        }
+        let result = CachedResult {
+            batches: batches.to_vec(),
+            bytes,
+            cached: Instant::now(),
+        };
+        cached.insert(key, result);
+    }
+
+    /// Collect the batches of a result as they are produced, so that they can be cached
+    /// once the query completes
+    pub fn collector(&self) -> ResultCollector {
+        ResultCollector {
+            max_bytes: self.max_bytes,
+            bytes: 0,
+            batches: Some(vec![]),
+        }
+    }
+
+    /// The number of cached results
+    pub fn len(&self) -> usize {
+        self.results.lock().unwrap().results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all of the cached results
+    pub fn clear(&self) {
+        *self.results.lock().unwrap() = CachedResults::default();
+    }
+}
+
+/// Collects the batches of a result that is streamed, giving up once they are too large to
+/// be cached so that large results are not held in memory
+pub struct ResultCollector {
+    max_bytes: usize,
+    bytes: usize,
+    batches: Option<Vec<RecordBatch>>,
+}
+
+impl ResultCollector {
+    pub fn push(&mut self, batch: &RecordBatch) {
+        self.bytes += batch_memory_size(batch);
+        if self.bytes > self.max_bytes {
+            self.batches = None;
+        } else if let Some(batches) = &mut self.batches {
+            batches.push(batch.clone());
+        }
+    }
+
+    /// The batches of the result, or `None` if they were too large to be cached
+    pub fn finish(self) -> Option<Vec<RecordBatch>> {
+        self.batches
+    }
+}
+
+impl CachedResults {
+    fn insert(&mut self, key: PlanKey, result: CachedResult) {
+        self.bytes += result.bytes;
+        self.order.push_back(key.clone());
+        self.results.insert(key, result);
+    }
+
+    fn remove(&mut self, key: &PlanKey) -> Option<CachedResult> {
+        let result = self.results.remove(key)?;
+        if let Some(i) = self.order.iter().position(|k| k == key) {
+            self.order.remove(i);
+        }
+        self.bytes -= result.bytes;
+        Some(result)
+    }
+}
+
+impl Default for ResultCache {
+    fn default() -> Self {
+        Self::new(0, None)
+    }
+}
+
+impl fmt::Debug for ResultCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResultCache")
+            .field("max_bytes", &self.max_bytes)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::error::Result;
+    use crate::logicalplan::LogicalPlanBuilder;
+    use std::sync::Arc;
+
+    #[test]
+    fn cache_results_by_plan_and_file_version() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("ballista-result-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("a.csv");
+        fs::write(&path, "a\n1\n")?;
+        let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+        let plan = LogicalPlanBuilder::scan_csv(path.to_str().unwrap(), &schema, None)?.build()?;
+        let batch = RecordBatch::try_new(
+            Arc::new(schema.clone()),
+            vec![Arc::new(Int32Array::from(vec![1]))],
+        )?;
+
+        let object_stores = ObjectStoreRegistry::new(&HashMap::new());
+        assert!(ResultCache::default().key(&plan, &object_stores).is_none());
+        let cache = ResultCache::new(1024 * 1024, None);
+        let key = cache.key(&plan, &object_stores).unwrap();
+        assert!(cache.get(&key).is_none());
+        cache.insert(key.clone(), &[batch.clone()]);
+        assert_eq!(1, cache.get(&key).unwrap()[0].num_rows());
+        assert_eq!(Some(key.clone()), cache.key(&plan, &object_stores));
+
+        // changing the file changes the key of the plan
+        fs::write(&path, "a\n1\n2\n")?;
+        assert_ne!(Some(key.clone()), cache.key(&plan, &object_stores));
+
+        // results larger than the cache are not cached, and expired results are removed
+        let small = ResultCache::new(1, None);
+        small.insert(key.clone(), &[batch.clone()]);
+        assert!(small.is_empty());
+        let expiring = ResultCache::new(1024 * 1024, Some(Duration::from_millis(0)));
+        expiring.insert(key.clone(), &[batch]);
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(expiring.get(&key).is_none());
+        assert!(expiring.is_empty());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Rewriting of aggregates into partial and final aggregates.
+//!
+//! The partial aggregate runs in each task of the stage that reads the input, producing one
+//! row per group with the state of each aggregate. The state is shuffled by the grouping
+//! columns and merged by the final aggregate, so that only the partial results are sent
+//! between executors. `AVG` is computed from a partial `SUM` and `COUNT`.
+
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::error::Result;
+use crate::logicalplan::{Expr, LogicalPlan, LogicalPlanBuilder, Operator};
+
+/// How the partial results of an aggregate expression are merged
+enum Merge {
+    /// The partial results are aggregated with the given function
+    Aggregate(&'static str),
+    /// The partial sums are divided by the partial counts
+    Average,
+}
+
+/// Create the partial aggregate for an aggregate over the given input, or `None` if some of
+/// the aggregate expressions cannot be merged
+pub fn partial_aggregate(
+    input: &LogicalPlan,
+    group_expr: &[Expr],
+    aggr_expr: &[Expr],
+) -> Result<Option<LogicalPlan>> {
+    let mut partial_expr = vec![];
+    for expr in aggr_expr {
+        let (arg, return_type) = match aggregate_arg(expr) {
+            Some(arg) => arg,
+            None => return Ok(None),
+        };
+        match merge(expr) {
+            Some(Merge::Aggregate(_)) => partial_expr.push(expr.clone()),
+            Some(Merge::Average) => {
+                partial_expr.push(aggregate("SUM", arg.clone(), return_type));
+                partial_expr.push(aggregate("COUNT", arg, DataType::UInt64));
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(
+        LogicalPlanBuilder::from(input)
+            .aggregate(group_expr.to_vec(), partial_expr)?
+            .build()?,
+    ))
+}
+
+/// Create the final aggregate that merges the output of the partial aggregate, producing
+/// the same schema as the original aggregate
+pub fn final_aggregate(
+    partial: LogicalPlan,
+    group_count: usize,
+    aggr_expr: &[Expr],
+    schema: &Schema,
+) -> Result<LogicalPlan> {
+    let group_expr: Vec<Expr> = (0..group_count).map(Expr::Column).collect();
+    let mut final_expr = vec![];
+    let mut projection: Vec<Expr> = (0..group_count).map(Expr::Column).collect();
+    let mut column = group_count;
+    for (i, expr) in aggr_expr.iter().enumerate() {
+        let name = schema.field(group_count + i).name();
+        let (_, return_type) =
+            aggregate_arg(expr).ok_or_else(|| format!("Aggregate {:?} cannot be merged", expr))?;
+        match merge(expr) {
+            Some(Merge::Aggregate(function)) => {
+                final_expr.push(aggregate(function, Expr::Column(column), return_type));
+                projection.push(Expr::Column(group_count + final_expr.len() - 1).alias(name));
+                column += 1;
+            }
+            Some(Merge::Average) => {
+                final_expr.push(aggregate("SUM", Expr::Column(column), return_type));
+                final_expr.push(aggregate("SUM", Expr::Column(column + 1), DataType::UInt64));
+                let sum = group_count + final_expr.len() - 2;
+                let average = Expr::BinaryExpr {
+                    left: Box::new(float64(Expr::Column(sum))),
+                    op: Operator::Divide,
+                    right: Box::new(float64(Expr::Column(sum + 1))),
+                };
+                projection.push(average.alias(name));
+                column += 2;
+            }
+            None => return Err(format!("Aggregate {:?} cannot be merged", expr).into()),
+        }
+    }
+
+    let aggregate = LogicalPlanBuilder::from(&partial)
+        .aggregate(group_expr, final_expr)?
+        .build()?;
+    Ok(LogicalPlan::Projection {
+        expr: projection,
+        input: Box::new(aggregate),
+        schema: schema.clone(),
+    })
+}
+
+/// The argument and return type of an aggregate function with a single argument
+fn aggregate_arg(expr: &Expr) -> Option<(Expr, DataType)> {
+    match expr {
+        Expr::AggregateFunction {
+            args, return_type, ..
+        } if args.len() == 1 => Some((args[0].clone(), return_type.clone())),
+        _ => None,
+    }
+}
+
+fn merge(expr: &Expr) -> Option<Merge> {
+    match expr {
+        Expr::AggregateFunction { name, .. } => match name.to_uppercase().as_str() {
+            "SUM" => Some(Merge::Aggregate("SUM")),
+            "MIN" => Some(Merge::Aggregate("MIN")),
+            "MAX" => Some(Merge::Aggregate("MAX")),
+            "COUNT" => Some(Merge::Aggregate("SUM")),
+            "AVG" => Some(Merge::Average),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn aggregate(name: &str, arg: Expr, return_type: DataType) -> Expr {
+    Expr::AggregateFunction {
+        name: name.to_owned(),
+        args: vec![arg],
+        return_type,
+    }
+}
+
+fn float64(expr: Expr) -> Expr {
+    Expr::Cast {
+        expr: Box::new(expr),
+        data_type: DataType::Float64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::Field;
+    use crate::dataframe::{avg, count, max};
+    use crate::logicalplan::col_index;
+
+    #[test]
+    fn merge_average() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Utf8, false),
+            Field::new("b", DataType::Int64, false),
+        ]);
+        let input = LogicalPlan::EmptyRelation { schema };
+        let aggr_expr = vec![max(col_index(1)), avg(col_index(1)), count(col_index(1))];
+        let aggregate = LogicalPlanBuilder::from(&input)
+            .aggregate(vec![col_index(0)], aggr_expr.clone())?
+            .build()?;
+
+        let partial = partial_aggregate(&input, &[col_index(0)], &aggr_expr)?.unwrap();
+        // max, sum and count for the average, and count
+        assert_eq!(5, partial.schema().fields().len());
+
+        let plan = final_aggregate(partial, 1, &aggr_expr, aggregate.schema())?;
+        assert_eq!(aggregate.schema(), plan.schema());
+        match plan {
+            LogicalPlan::Projection { expr, input, .. } => {
+                assert_eq!(4, expr.len());
+                assert_eq!(5, input.schema().fields().len());
+            }
+            other => panic!("unexpected plan {:?}", other),
+        }
+
+        let unknown = vec![Expr::AggregateFunction {
+            name: "MEDIAN".to_owned(),
+            args: vec![col_index(1)],
+            return_type: DataType::Float64,
+        }];
+        assert!(partial_aggregate(&input, &[col_index(0)], &unknown)?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! The jobs run by a scheduler.
+//!
+//! Every query executed by a scheduler is a job with an id that is unique to the scheduler.
+//! Jobs can be listed and cancelled while they run, and the progress of each stage is
+//! reported from the status of its tasks. Jobs are encoded as JSON when they are returned
+//! to clients with Flight actions.
+
+#[cfg(feature = "local-execution")]
+use std::collections::HashMap;
+
+#[cfg(feature = "local-execution")]
+use crate::cancel::CancellationToken;
+use crate::error::{ballista_error, Result};
+#[cfg(feature = "local-execution")]
+use crate::execution_metrics::MetricsCollector;
+use crate::scheduler::queues::DEFAULT_QUEUE;
+#[cfg(feature = "local-execution")]
+use crate::scheduler::{TaskId, TaskStatus};
+
+#[cfg(feature = "local-execution")]
+use chrono::Utc;
+use serde_json::{json, Value};
+
+/// The state of a job
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobState {
+    Running,
+    Completed,
+    Failed { error: String },
+    Cancelled,
+}
+
+/// The progress of a stage of a job
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageProgress {
+    pub stage_id: usize,
+    /// Number of tasks in the stage
+    pub tasks: usize,
+    /// Number of tasks that have not started, including tasks that are waiting to be retried
+    pub pending: usize,
+    pub running: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+/// A job and the progress of its stages
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobInfo {
+    pub job_id: usize,
+    pub state: JobState,
+    /// When the job was submitted, in milliseconds since the Unix epoch
+    pub submitted: i64,
+    /// The plan of the query
+    pub plan: String,
+    /// The queue that the job runs in
+    pub queue: String,
+    pub stages: Vec<StageProgress>,
+}
+
+impl JobInfo {
+    /// Encode the job as the JSON that is returned to clients
+    pub fn to_json(&self) -> String {
+        let (state, error) = match &self.state {
+            JobState::Running => ("running", None),
+            JobState::Completed => ("completed", None),
+            JobState::Failed { error } => ("failed", Some(error)),
+            JobState::Cancelled => ("cancelled", None),
+        };
+        let stages: Vec<Value> = self
+            .stages
+            .iter()
+            .map(|stage| {
+                json!({
+                    "stage_id": stage.stage_id,
+                    "tasks": stage.tasks,
+                    "pending": stage.pending,
+                    "running": stage.running,
+                    "completed": stage.completed,
+                    "failed": stage.failed,
+                })
+            })
+            .collect();
+        json!({
+            "job_id": self.job_id,
+            "state": state,
+            "error": error,
+            "submitted": self.submitted,
+            "plan": self.plan,
+            "queue": self.queue,
+            "stages": stages,
+        })
+        .to_string()
+    }
+
+    /// Decode a job that was encoded with `to_json`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| ballista_error(&format!("Invalid job: {:?}", e)))?;
+        let missing = |name: &str| ballista_error(&format!("Job has no {}: {}", name, json));
+        let number = |value: &Value, name: &str| value[name].as_i64().ok_or_else(|| missing(name));
+        let string = |name: &str| {
+            value[name]
+                .as_str()
+                .map(|s| s.to_owned())
+                .ok_or_else(|| missing(name))
+        };
+
+        let state = match string("state")?.as_str() {
+            "running" => JobState::Running,
+            "completed" => JobState::Completed,
+            "failed" => JobState::Failed {
+                error: string("error")?,
+            },
+            "cancelled" => JobState::Cancelled,
+            other => return Err(ballista_error(&format!("Unknown job state: {}", other))),
+        };
+        let stages = value["stages"]
+            .as_array()
+            .ok_or_else(|| missing("stages"))?
+            .iter()
+            .map(|stage| {
+                Ok(StageProgress {
+                    stage_id: number(stage, "stage_id")? as usize,
+                    tasks: number(stage, "tasks")? as usize,
+                    pending: number(stage, "pending")? as usize,
+                    running: number(stage, "running")? as usize,
+                    completed: number(stage, "completed")? as usize,
+                    failed: number(stage, "failed")? as usize,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            job_id: number(&value, "job_id")? as usize,
+            state,
+            submitted: number(&value, "submitted")?,
+            plan: string("plan")?,
+            // jobs from schedulers without queues run in the default queue
+            queue: string("queue").unwrap_or_else(|_| DEFAULT_QUEUE.to_owned()),
+            stages,
+        })
+    }
+}
+
+/// A job tracked by the scheduler
+#[cfg(feature = "local-execution")]
+pub(crate) struct Job {
+    pub state: JobState,
+    pub submitted: i64,
+    pub plan: String,
+    pub queue: String,
+    /// Number of tasks in each stage
+    pub stage_tasks: Vec<usize>,
+    /// The status of the tasks that have been started
+    pub tasks: HashMap<TaskId, TaskStatus>,
+    pub token: CancellationToken,
+    /// The metrics of the operators of the tasks that have completed
+    pub metrics: MetricsCollector,
+}
+
+#[cfg(feature = "local-execution")]
+impl Job {
+    pub fn new(plan: String, queue: &str, stage_tasks: Vec<usize>) -> Self {
+        Self {
+            state: JobState::Running,
+            submitted: Utc::now().timestamp_millis(),
+            plan,
+            queue: queue.to_owned(),
+            stage_tasks,
+            tasks: HashMap::new(),
+            token: CancellationToken::new(),
+            metrics: MetricsCollector::new(),
+        }
+    }
+
+    pub fn info(&self, job_id: usize) -> JobInfo {
+        let stages = self
+            .stage_tasks
+            .iter()
+            .enumerate()
+            .map(|(stage_id, tasks)| {
+                let mut progress = StageProgress {
+                    stage_id,
+                    tasks: *tasks,
+                    pending: 0,
+                    running: 0,
+                    completed: 0,
+                    failed: 0,
+                };
+                for (_, status) in self.tasks.iter().filter(|(id, _)| id.stage_id == stage_id) {
+                    match status {
+                        TaskStatus::Pending => {}
+                        TaskStatus::Running { .. } => progress.running += 1,
+                        TaskStatus::Completed { .. } => progress.completed += 1,
+                        TaskStatus::Failed { .. } => progress.failed += 1,
+                    }
+                }
+                progress.pending =
+                    tasks.saturating_sub(progress.running + progress.completed + progress.failed);
+                progress
+            })
+            .collect();
+        JobInfo {
+            job_id,
+            state: self.state.clone(),
+            submitted: self.submitted,
+            plan: self.plan.clone(),
+            queue: self.queue.clone(),
+            stages,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "local-execution"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_progress() -> Result<()> {
+        let mut job = Job::new("TableScan: t".to_owned(), "batch", vec![3, 1]);
+        for (partition, status) in vec![
+            TaskStatus::Completed {
+                executor_id: "e1".to_owned(),
+            },
+            TaskStatus::Running {
+                executor_id: "e2".to_owned(),
+            },
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let task_id = TaskId {
+                job_id: 7,
+                stage_id: 0,
+                partition,
+            };
+            job.tasks.insert(task_id, status);
+        }
+        job.state = JobState::Failed {
+            error: "executor lost".to_owned(),
+        };
+
+        let info = job.info(7);
+        assert_eq!(1, info.stages[0].pending);
+        assert_eq!(1, info.stages[0].running);
+        assert_eq!(1, info.stages[0].completed);
+        assert_eq!(1, info.stages[1].pending);
+        assert_eq!(info, JobInfo::from_json(&info.to_json())?);
+        Ok(())
+    }
+}
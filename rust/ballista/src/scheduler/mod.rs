@@ -0,0 +1,1533 @@
+//! Distributed execution of queries across executors.
+//!
+//! The scheduler splits a plan into stages, creates a task for each partition of a stage,
+//! and assigns the tasks to the registered executors. A stage runs once all of the stages
+//! that it reads from have completed. Tasks write their output as shuffle partitions on the
+//! executor that ran them, except for the tasks of the last stage, whose results are the
+//! results of the query.
+//!
+//! Executors send heartbeats to the scheduler, either directly or through a discovery
+//! service, and executors that have not sent a heartbeat within the configured timeout are
+//! considered lost and are not assigned tasks until they send a heartbeat again.
+//!
+//! Tasks that fail because of an executor or connection failure are retried on another
+//! executor, while tasks that fail because of an error in the query fail the query. When a
+//! task cannot fetch the shuffle partitions that it reads, the tasks that wrote partitions
+//! on executors that have failed are run again before the task is retried.
+//!
+//! Tasks that scan files are assigned to executors on the hosts that store the files when
+//! the object store knows where they are stored. If none of those executors are available,
+//! the scheduler waits for the configured locality wait before assigning the tasks to any
+//! executor.
+//!
+//! With speculative execution enabled, a task that runs for much longer than the median of
+//! the completed tasks of its stage is started again on another executor, and the results
+//! of whichever copy finishes first are used.
+//!
+//! Queries that write Parquet files write a file for each task of the last stage, from the
+//! executor that ran the task, and the scheduler returns the manifest of the files.
+//!
+//! Queries run in named queues, and when the number of tasks that run on each executor is
+//! limited, the queues share the task slots of the cluster according to their weights.
+//!
+//! With adaptive execution enabled, the stages that have not run yet are adjusted after
+//! each stage completes, using the size of the shuffle partitions that it wrote.
+//!
+//! Each job is recorded in a `tracing` span, with a span for each stage and task.
+
+#[cfg(feature = "local-execution")]
+pub mod aggregate;
+pub mod jobs;
+#[cfg(feature = "local-execution")]
+pub mod planner;
+pub mod queues;
+#[cfg(feature = "local-execution")]
+pub mod scaling;
+
+#[cfg(feature = "local-execution")]
+use std::collections::{BTreeMap, HashMap, HashSet};
+#[cfg(feature = "local-execution")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "local-execution")]
+use std::sync::{Arc, Mutex, RwLock};
+#[cfg(feature = "local-execution")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "local-execution")]
+use crate::arrow::record_batch::RecordBatch;
+#[cfg(feature = "local-execution")]
+use crate::cancel::CancellationToken;
+#[cfg(feature = "local-execution")]
+use crate::client::{self, ClientConfig, ConnectionPool};
+#[cfg(feature = "local-execution")]
+use crate::datasource::object_store::{self, ObjectStoreRegistry};
+#[cfg(feature = "local-execution")]
+use crate::datasource::write::WriteManifest;
+#[cfg(feature = "local-execution")]
+use crate::discovery::Discovery;
+#[cfg(feature = "local-execution")]
+use crate::error::{ballista_error, BallistaError, Result};
+#[cfg(feature = "local-execution")]
+use crate::execution_metrics::{MetricsCollector, QueryMetrics};
+#[cfg(feature = "local-execution")]
+use crate::logicalplan::LogicalPlan;
+#[cfg(feature = "local-execution")]
+use crate::plan::Action;
+#[cfg(feature = "local-execution")]
+use crate::scheduler::jobs::{Job, JobInfo, JobState};
+#[cfg(feature = "local-execution")]
+use crate::scheduler::planner::{adapt_stages, plan_stages, PlannerConfig, Stage};
+#[cfg(feature = "local-execution")]
+use crate::scheduler::queues::{QueueConfig, TaskQueues};
+#[cfg(feature = "local-execution")]
+use crate::scheduler::scaling::{ScalingHook, SchedulerLoad};
+#[cfg(feature = "local-execution")]
+use crate::serde::{check_plan_version, PLAN_VERSION};
+#[cfg(feature = "local-execution")]
+use crate::shuffle::ShuffleSummary;
+
+#[cfg(feature = "local-execution")]
+use chrono::Utc;
+#[cfg(feature = "local-execution")]
+use futures::future::{self, Either};
+#[cfg(feature = "local-execution")]
+use log::{info, warn};
+#[cfg(feature = "local-execution")]
+use tonic::Code;
+#[cfg(feature = "local-execution")]
+use tracing::info_span;
+#[cfg(feature = "local-execution")]
+use tracing_futures::Instrument;
+
+#[cfg(feature = "local-execution")]
+pub const EXECUTOR_TIMEOUT_SECONDS: &str = "ballista.scheduler.executorTimeoutSeconds";
+#[cfg(feature = "local-execution")]
+pub const MAX_TASK_ATTEMPTS: &str = "ballista.scheduler.maxTaskAttempts";
+#[cfg(feature = "local-execution")]
+pub const LOCALITY_WAIT_MS: &str = "ballista.scheduler.localityWaitMs";
+#[cfg(feature = "local-execution")]
+pub const SPECULATION: &str = "ballista.scheduler.speculation";
+#[cfg(feature = "local-execution")]
+pub const SPECULATION_MULTIPLIER: &str = "ballista.scheduler.speculationMultiplier";
+#[cfg(feature = "local-execution")]
+pub const SPECULATION_QUANTILE: &str = "ballista.scheduler.speculationQuantile";
+#[cfg(feature = "local-execution")]
+pub const MAX_TASKS_PER_EXECUTOR: &str = "ballista.scheduler.maxTasksPerExecutor";
+
+/// Number of completed jobs whose task statuses are kept
+#[cfg(feature = "local-execution")]
+const MAX_RETAINED_JOBS: usize = 100;
+
+/// How often the registered executors are checked while waiting for an executor on the
+/// hosts that store the files scanned by a task
+#[cfg(feature = "local-execution")]
+const LOCALITY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often running tasks are checked for stragglers when speculative execution is enabled
+#[cfg(feature = "local-execution")]
+const SPECULATION_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often executors that are being drained are checked for running tasks
+#[cfg(feature = "local-execution")]
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options for assigning tasks to executors
+#[cfg(feature = "local-execution")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchedulerConfig {
+    /// Executors that have not sent a heartbeat for this long are considered lost
+    pub executor_timeout: Duration,
+    /// Number of times a task is attempted before the query fails. Tasks are only retried
+    /// after failures of executors or connections, and not after errors in the query.
+    pub max_task_attempts: usize,
+    /// How long tasks wait for an executor on a host that stores the files that they scan
+    /// before they are assigned to any executor
+    pub locality_wait: Duration,
+    /// Whether copies of slow tasks are started on other executors
+    pub speculation: bool,
+    /// Tasks are speculated once they have run for this many times the median duration of
+    /// the completed tasks of their stage
+    pub speculation_multiplier: f64,
+    /// Fraction of the tasks of a stage that must complete before tasks are speculated
+    pub speculation_quantile: f64,
+    /// The number of tasks that run at the same time is limited to this many tasks for
+    /// each live executor, with the slots shared between queues. There is no limit if not
+    /// set.
+    pub max_tasks_per_executor: Option<usize>,
+}
+
+#[cfg(feature = "local-execution")]
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            executor_timeout: Duration::from_secs(30),
+            max_task_attempts: 4,
+            locality_wait: Duration::from_secs(3),
+            speculation: false,
+            speculation_multiplier: 1.5,
+            speculation_quantile: 0.75,
+            max_tasks_per_executor: None,
+        }
+    }
+}
+
+#[cfg(feature = "local-execution")]
+impl SchedulerConfig {
+    /// Read the scheduler configuration from the Context settings
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            executor_timeout: match settings.get(EXECUTOR_TIMEOUT_SECONDS) {
+                Some(n) => n.parse::<u64>().map(Duration::from_secs).map_err(|_| {
+                    ballista_error(&format!(
+                        "Invalid value for {}: {}",
+                        EXECUTOR_TIMEOUT_SECONDS, n
+                    ))
+                })?,
+                None => default.executor_timeout,
+            },
+            max_task_attempts: match settings.get(MAX_TASK_ATTEMPTS) {
+                Some(n) => n.parse::<usize>().map(|n| n.max(1)).map_err(|_| {
+                    ballista_error(&format!("Invalid value for {}: {}", MAX_TASK_ATTEMPTS, n))
+                })?,
+                None => default.max_task_attempts,
+            },
+            locality_wait: match settings.get(LOCALITY_WAIT_MS) {
+                Some(n) => n.parse::<u64>().map(Duration::from_millis).map_err(|_| {
+                    ballista_error(&format!("Invalid value for {}: {}", LOCALITY_WAIT_MS, n))
+                })?,
+                None => default.locality_wait,
+            },
+            speculation: match settings.get(SPECULATION).map(|s| s.as_str()) {
+                Some("true") => true,
+                Some("false") => false,
+                Some(other) => {
+                    return Err(ballista_error(&format!(
+                        "Invalid value for {}: {}",
+                        SPECULATION, other
+                    )))
+                }
+                None => default.speculation,
+            },
+            speculation_multiplier: parse_f64(settings, SPECULATION_MULTIPLIER)?
+                .unwrap_or(default.speculation_multiplier),
+            speculation_quantile: parse_f64(settings, SPECULATION_QUANTILE)?
+                .unwrap_or(default.speculation_quantile),
+            max_tasks_per_executor: match settings.get(MAX_TASKS_PER_EXECUTOR) {
+                Some(n) => Some(n.parse::<usize>().map(|n| n.max(1)).map_err(|_| {
+                    ballista_error(&format!(
+                        "Invalid value for {}: {}",
+                        MAX_TASKS_PER_EXECUTOR, n
+                    ))
+                })?),
+                None => default.max_tasks_per_executor,
+            },
+        })
+    }
+
+    /// How long a task may run before it is speculated, given the durations of the tasks
+    /// of its stage that have completed, or `None` if too few tasks have completed
+    fn speculation_threshold(&self, completed: &[Duration], tasks: usize) -> Option<Duration> {
+        if completed.is_empty()
+            || (completed.len() as f64) < self.speculation_quantile * tasks as f64
+        {
+            return None;
+        }
+        let mut completed = completed.to_vec();
+        completed.sort();
+        let median = completed[completed.len() / 2];
+        Some(median.mul_f64(self.speculation_multiplier))
+    }
+}
+
+#[cfg(feature = "local-execution")]
+fn parse_f64(settings: &HashMap<String, String>, name: &str) -> Result<Option<f64>> {
+    match settings.get(name) {
+        Some(n) => n
+            .parse::<f64>()
+            .map(Some)
+            .map_err(|_| ballista_error(&format!("Invalid value for {}: {}", name, n))),
+        None => Ok(None),
+    }
+}
+
+/// An executor that tasks can be assigned to
+#[cfg(feature = "local-execution")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutorMeta {
+    pub id: String,
+    pub host: String,
+    pub port: usize,
+}
+
+/// Whether an executor is assigned tasks
+#[cfg(feature = "local-execution")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutorState {
+    /// The executor has sent a heartbeat within the timeout and is assigned tasks
+    Alive,
+    /// The executor has not sent a heartbeat within the timeout
+    Lost,
+    /// The executor is not assigned new tasks because it is shutting down
+    Draining,
+}
+
+/// The liveness of a registered executor
+#[cfg(feature = "local-execution")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutorStatus {
+    pub executor: ExecutorMeta,
+    pub state: ExecutorState,
+    /// When the executor last sent a heartbeat, in milliseconds since the Unix epoch
+    pub last_heartbeat: i64,
+}
+
+/// An executor that has registered with the scheduler
+#[cfg(feature = "local-execution")]
+#[derive(Debug, Clone)]
+struct RegisteredExecutor {
+    meta: ExecutorMeta,
+    last_heartbeat: i64,
+    draining: bool,
+    /// Newest version of the plan format that the executor decodes
+    plan_version: u32,
+}
+
+/// Identifies a task within a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId {
+    pub job_id: usize,
+    pub stage_id: usize,
+    pub partition: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskStatus {
+    Pending,
+    Running { executor_id: String },
+    Completed { executor_id: String },
+    Failed { executor_id: String, error: String },
+}
+
+/// Scheduler for distributed queries
+#[cfg(feature = "local-execution")]
+pub struct Scheduler {
+    /// Prefix of the job ids used for shuffle partitions, which is unique to this scheduler
+    /// so that jobs from different schedulers do not conflict on executors
+    id: String,
+    executors: RwLock<Vec<RegisteredExecutor>>,
+    jobs: Mutex<BTreeMap<usize, Job>>,
+    next_job_id: AtomicUsize,
+    connections: ConnectionPool,
+    config: ClientConfig,
+    planner_config: PlannerConfig,
+    scheduler_config: SchedulerConfig,
+    /// Object stores that are asked where the files scanned by tasks are stored
+    object_stores: Arc<ObjectStoreRegistry>,
+    scaling_hooks: Vec<Arc<dyn ScalingHook>>,
+    queues: Arc<TaskQueues>,
+}
+
+#[cfg(feature = "local-execution")]
+impl Scheduler {
+    /// Create a scheduler that connects to executors with the given client configuration.
+    /// Executors are always asked for the metrics of tasks, which are collected for jobs.
+    pub fn new(config: ClientConfig) -> Self {
+        Self {
+            id: format!("{:x}", Utc::now().timestamp_nanos()),
+            executors: RwLock::new(vec![]),
+            jobs: Mutex::new(BTreeMap::new()),
+            next_job_id: AtomicUsize::new(0),
+            connections: ConnectionPool::new(),
+            config: ClientConfig {
+                collect_metrics: true,
+                ..config
+            },
+            planner_config: PlannerConfig::default(),
+            scheduler_config: SchedulerConfig::default(),
+            object_stores: Arc::new(ObjectStoreRegistry::new(&HashMap::new())),
+            scaling_hooks: vec![],
+            queues: Arc::new(TaskQueues::default()),
+        }
+    }
+
+    /// Split queries into stages with the given planner configuration
+    pub fn with_planner_config(mut self, planner_config: PlannerConfig) -> Self {
+        self.planner_config = planner_config;
+        self
+    }
+
+    /// Assign tasks with the given scheduler configuration
+    pub fn with_scheduler_config(mut self, scheduler_config: SchedulerConfig) -> Self {
+        self.scheduler_config = scheduler_config;
+        self
+    }
+
+    /// Look up where scanned files are stored with the given object stores
+    pub fn with_object_stores(mut self, object_stores: Arc<ObjectStoreRegistry>) -> Self {
+        self.object_stores = object_stores;
+        self
+    }
+
+    /// Run queries in the given queues as well as the default queue
+    pub fn with_queues(mut self, queues: Vec<QueueConfig>) -> Self {
+        self.queues = Arc::new(TaskQueues::new(queues));
+        self
+    }
+
+    /// Report the load on the scheduler to a scaling hook
+    pub fn with_scaling_hook(mut self, hook: Arc<dyn ScalingHook>) -> Self {
+        self.scaling_hooks.push(hook);
+        self
+    }
+
+    /// Register an executor, replacing any executor with the same id. Registering an
+    /// executor counts as a heartbeat.
+    pub fn register_executor(&self, executor: ExecutorMeta) {
+        self.register_executor_with_plan_version(executor, PLAN_VERSION)
+    }
+
+    /// Register an executor that decodes the versions of the plan format up to the given
+    /// version. Tasks are encoded with the oldest version that the registered executors
+    /// decode, so that executors can be upgraded one at a time.
+    pub fn register_executor_with_plan_version(&self, executor: ExecutorMeta, plan_version: u32) {
+        if check_plan_version(plan_version).is_err() {
+            warn!(
+                "Ignoring executor {} that decodes plan version {}",
+                executor.id, plan_version
+            );
+            return;
+        }
+        {
+            let mut executors = self.executors.write().unwrap();
+            // executors that register again keep draining
+            let existing = executors
+                .iter()
+                .find(|e| e.meta == executor)
+                .map(|e| e.draining);
+            executors.retain(|e| e.meta.id != executor.id);
+            executors.push(RegisteredExecutor {
+                meta: executor,
+                last_heartbeat: Utc::now().timestamp_millis(),
+                draining: existing.unwrap_or(false),
+                plan_version,
+            });
+            if existing.is_some() {
+                return;
+            }
+        }
+        self.load_changed();
+    }
+
+    /// Record a heartbeat from an executor, returning false if the executor is not
+    /// registered
+    pub fn heartbeat(&self, executor_id: &str) -> bool {
+        let mut executors = self.executors.write().unwrap();
+        match executors.iter_mut().find(|e| e.meta.id == executor_id) {
+            Some(executor) => {
+                executor.last_heartbeat = Utc::now().timestamp_millis();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop assigning tasks to an executor
+    pub fn remove_executor(&self, executor_id: &str) {
+        self.executors
+            .write()
+            .unwrap()
+            .retain(|e| e.meta.id != executor_id);
+        self.load_changed();
+    }
+
+    /// Stop assigning new tasks to an executor and wait for the tasks that are running on it
+    /// to complete, after which the executor is removed and can be stopped. Shuffle
+    /// partitions that the executor wrote for jobs that are still running are written again
+    /// by other executors when they are read.
+    pub async fn drain_executor(&self, executor_id: &str) -> Result<()> {
+        {
+            let mut executors = self.executors.write().unwrap();
+            match executors.iter_mut().find(|e| e.meta.id == executor_id) {
+                Some(executor) => executor.draining = true,
+                None => {
+                    return Err(ballista_error(&format!(
+                        "Executor {} is not registered",
+                        executor_id
+                    )))
+                }
+            }
+        }
+        self.load_changed();
+
+        while self.running_tasks(executor_id) > 0 {
+            tokio::time::delay_for(DRAIN_POLL_INTERVAL).await;
+        }
+        self.remove_executor(executor_id);
+        Ok(())
+    }
+
+    /// Number of tasks of running jobs that are running on an executor
+    fn running_tasks(&self, executor_id: &str) -> usize {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values()
+            .filter(|job| job.state == JobState::Running)
+            .flat_map(|job| job.tasks.values())
+            .filter(|status| match status {
+                TaskStatus::Running { executor_id: id } => id == executor_id,
+                _ => false,
+            })
+            .count()
+    }
+
+    /// The current load on the scheduler
+    pub fn load(&self) -> SchedulerLoad {
+        let mut pending_tasks = 0;
+        let mut running_tasks = 0;
+        let mut busy = HashSet::new();
+        let mut active_jobs = 0;
+        {
+            let jobs = self.jobs.lock().unwrap();
+            for job in jobs.values().filter(|job| job.state == JobState::Running) {
+                active_jobs += 1;
+                for status in job.tasks.values() {
+                    match status {
+                        TaskStatus::Pending => pending_tasks += 1,
+                        TaskStatus::Running { executor_id } => {
+                            running_tasks += 1;
+                            busy.insert(executor_id.clone());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        let executors = self.executors();
+        SchedulerLoad {
+            active_jobs,
+            pending_tasks,
+            running_tasks,
+            executors: executors.len(),
+            idle_executors: executors
+                .into_iter()
+                .map(|e| e.id)
+                .filter(|id| !busy.contains(id))
+                .collect(),
+        }
+    }
+
+    /// Report the load to the scaling hooks
+    fn load_changed(&self) {
+        if self.scaling_hooks.is_empty() {
+            return;
+        }
+        let load = self.load();
+        for hook in &self.scaling_hooks {
+            hook.load_changed(&load);
+        }
+    }
+
+    /// Replace the registered executors with the executors listed by a discovery service,
+    /// so that executors that have stopped are no longer assigned tasks. The last heartbeat
+    /// of each executor is the time that it last renewed its registration.
+    pub fn sync_executors(&self, discovery: &dyn Discovery) -> Result<()> {
+        let executors = discovery.executors()?;
+        {
+            let mut registered = self.executors.write().unwrap();
+            let draining: HashSet<String> = registered
+                .iter()
+                .filter(|e| e.draining)
+                .map(|e| e.meta.id.clone())
+                .collect();
+            *registered = executors
+                .iter()
+                .filter_map(|e| match e.negotiate_plan_version(PLAN_VERSION) {
+                    Some(plan_version) => Some(RegisteredExecutor {
+                        meta: e.executor_meta(),
+                        last_heartbeat: e.last_heartbeat,
+                        draining: draining.contains(&e.id),
+                        plan_version,
+                    }),
+                    None => {
+                        warn!(
+                            "Ignoring executor {} that decodes plan version {}",
+                            e.id, e.plan_version
+                        );
+                        None
+                    }
+                })
+                .collect();
+        }
+        self.load_changed();
+        Ok(())
+    }
+
+    /// The liveness of the registered executors
+    pub fn executor_status(&self) -> Vec<ExecutorStatus> {
+        let lost_before = Utc::now().timestamp_millis()
+            - self.scheduler_config.executor_timeout.as_millis() as i64;
+        self.executors
+            .read()
+            .unwrap()
+            .iter()
+            .map(|e| ExecutorStatus {
+                executor: e.meta.clone(),
+                state: if e.last_heartbeat < lost_before {
+                    ExecutorState::Lost
+                } else if e.draining {
+                    ExecutorState::Draining
+                } else {
+                    ExecutorState::Alive
+                },
+                last_heartbeat: e.last_heartbeat,
+            })
+            .collect()
+    }
+
+    /// The executors that tasks can be assigned to, which are the registered executors
+    /// that have not been lost and are not being drained
+    pub fn executors(&self) -> Vec<ExecutorMeta> {
+        self.executor_status()
+            .into_iter()
+            .filter(|status| status.state == ExecutorState::Alive)
+            .map(|status| status.executor)
+            .collect()
+    }
+
+    /// The status of the tasks in a job, ordered by stage and partition
+    pub fn job_status(&self, job_id: usize) -> Vec<(TaskId, TaskStatus)> {
+        let jobs = self.jobs.lock().unwrap();
+        let mut tasks: Vec<(TaskId, TaskStatus)> = jobs
+            .get(&job_id)
+            .map(|job| job.tasks.iter().map(|(id, s)| (*id, s.clone())).collect())
+            .unwrap_or_default();
+        tasks.sort_by_key(|(id, _)| *id);
+        tasks
+    }
+
+    /// The running jobs and the most recently completed jobs, ordered by job id
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter().map(|(job_id, job)| job.info(*job_id)).collect()
+    }
+
+    /// A job and the progress of its stages, or `None` if there is no such job or it
+    /// completed too long ago
+    pub fn job(&self, job_id: usize) -> Option<JobInfo> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(&job_id).map(|job| job.info(job_id))
+    }
+
+    /// Cancel a running job, stopping its running tasks. Returns false if the job is not
+    /// running.
+    pub fn cancel_job(&self, job_id: usize) -> bool {
+        let jobs = self.jobs.lock().unwrap();
+        match jobs.get(&job_id) {
+            Some(job) if job.state == JobState::Running => {
+                job.token.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Execute a query across the registered executors in the given queue
+    pub async fn execute(&self, plan: &LogicalPlan, queue: &str) -> Result<Vec<RecordBatch>> {
+        self.execute_with_metrics(plan, queue, &MetricsCollector::new())
+            .await
+    }
+
+    /// Execute a query, recording the metrics of the operators of its tasks labelled with
+    /// the stages that ran them
+    pub async fn execute_with_metrics(
+        &self,
+        plan: &LogicalPlan,
+        queue: &str,
+        metrics: &MetricsCollector,
+    ) -> Result<Vec<RecordBatch>> {
+        let partitions = self.execute_job(plan, None, queue, metrics).await?;
+        Ok(partitions.into_iter().flatten().collect())
+    }
+
+    /// Execute a query, returning the results of each task of the last stage separately, in
+    /// partition order
+    pub async fn execute_partitioned(
+        &self,
+        plan: &LogicalPlan,
+        queue: &str,
+    ) -> Result<Vec<Vec<RecordBatch>>> {
+        self.execute_partitioned_with_metrics(plan, queue, &MetricsCollector::new())
+            .await
+    }
+
+    /// Execute a query, returning the results of each task of the last stage separately and
+    /// recording the metrics of the operators of its tasks
+    pub async fn execute_partitioned_with_metrics(
+        &self,
+        plan: &LogicalPlan,
+        queue: &str,
+        metrics: &MetricsCollector,
+    ) -> Result<Vec<Vec<RecordBatch>>> {
+        self.execute_job(plan, None, queue, metrics).await
+    }
+
+    /// Execute a query and write the results as Parquet files in a directory, with a file
+    /// for each task of the last stage, returning the manifest of the files. Tasks that are
+    /// retried or speculated replace the file written by the earlier attempt.
+    pub async fn write_parquet(
+        &self,
+        plan: &LogicalPlan,
+        path: &str,
+        queue: &str,
+    ) -> Result<WriteManifest> {
+        self.write_parquet_with_metrics(plan, path, queue, &MetricsCollector::new())
+            .await
+    }
+
+    /// Execute a query and write the results as Parquet files, recording the metrics of the
+    /// operators of its tasks
+    pub async fn write_parquet_with_metrics(
+        &self,
+        plan: &LogicalPlan,
+        path: &str,
+        queue: &str,
+        metrics: &MetricsCollector,
+    ) -> Result<WriteManifest> {
+        let partitions = self.execute_job(plan, Some(path), queue, metrics).await?;
+        WriteManifest::from_batches(&partitions.concat())
+    }
+
+    /// Execute a query, writing the results of the last stage to Parquet files in the
+    /// `output` directory when it is set. The results of each task of the last stage are
+    /// returned separately.
+    async fn execute_job(
+        &self,
+        plan: &LogicalPlan,
+        output: Option<&str>,
+        queue: &str,
+        metrics: &MetricsCollector,
+    ) -> Result<Vec<Vec<RecordBatch>>> {
+        if !self.queues.contains(queue) {
+            return Err(ballista_error(&format!(
+                "Unknown scheduler queue '{}'",
+                queue
+            )));
+        }
+        let job_id = self.next_job_id.fetch_add(1, Ordering::SeqCst);
+        let span = info_span!("job", job_id, queue);
+        let stages = span.in_scope(|| {
+            info_span!("plan_stages").in_scope(|| plan_stages(plan, &self.planner_config))
+        })?;
+        let stage_tasks = stages.iter().map(|stage| stage.partition_count()).collect();
+        let token = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let mut job = Job::new(format!("{:?}", plan), queue, stage_tasks);
+            job.metrics = metrics.clone();
+            let token = job.token.clone();
+            jobs.insert(job_id, job);
+            // running jobs are kept until they complete
+            while jobs.len() > MAX_RETAINED_JOBS {
+                let oldest = jobs
+                    .iter()
+                    .find(|(_, job)| job.state != JobState::Running)
+                    .map(|(job_id, _)| *job_id);
+                match oldest {
+                    Some(oldest) => jobs.remove(&oldest),
+                    None => break,
+                };
+            }
+            token
+        };
+        self.load_changed();
+
+        // the running tasks are stopped when the job is cancelled, since the streams of
+        // their results are dropped
+        let result = match future::select(
+            Box::pin(
+                self.execute_stages(job_id, &stages, output)
+                    .instrument(span),
+            ),
+            Box::pin(token.cancelled()),
+        )
+        .await
+        {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(BallistaError::Cancelled),
+        };
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            job.state = match &result {
+                Ok(_) => JobState::Completed,
+                Err(e) if e.is_cancelled() => JobState::Cancelled,
+                Err(e) => JobState::Failed {
+                    error: e.to_string(),
+                },
+            };
+        }
+        self.load_changed();
+        if stages.len() > 1 {
+            self.remove_shuffle(job_id).await;
+        }
+        result
+    }
+
+    async fn execute_stages(
+        &self,
+        job_id: usize,
+        stages: &[Stage],
+        output: Option<&str>,
+    ) -> Result<Vec<Vec<RecordBatch>>> {
+        let shuffle_job_id = self.shuffle_job_id(job_id);
+
+        // stages only read from earlier stages, so they can run in order
+        let mut stages = stages.to_vec();
+        let mut completed: HashMap<usize, Vec<ExecutorMeta>> = HashMap::new();
+        // the size of the shuffle partitions written by each completed stage
+        let mut statistics: HashMap<usize, ShuffleSummary> = HashMap::new();
+        // executors that failed during the job, whose shuffle partitions may have been lost
+        let mut failed_executors: HashSet<String> = HashSet::new();
+        let mut output = vec![];
+        for stage_id in 0..stages.len() {
+            // the stages that have not run yet are adapted as stages complete
+            let stage = &stages[stage_id].clone();
+            if stage.skipped {
+                continue;
+            }
+            let is_last = stage.id + 1 == stages.len();
+            let partition_count = stage.partition_count();
+            let mut attempts = vec![0; partition_count];
+            let mut results: Vec<Option<(ExecutorMeta, Vec<RecordBatch>)>> =
+                vec![None; partition_count];
+            let mut pending: Vec<usize> = (0..partition_count).collect();
+            let locations = self.task_locations(stage).await;
+            // the durations of the tasks of the stage that have completed
+            let durations = Mutex::new(vec![]);
+            while !pending.is_empty() {
+                let waiting: Vec<&[String]> = pending
+                    .iter()
+                    .filter(|&&partition| attempts[partition] == 0)
+                    .map(|&partition| locations[partition].as_slice())
+                    .collect();
+                let executors = self
+                    .wait_for_local_executors(&waiting, &failed_executors)
+                    .await;
+                let tasks: Vec<(TaskId, ExecutorMeta, Action)> = pending
+                    .iter()
+                    .map(|&partition| {
+                        let task_id = TaskId {
+                            job_id,
+                            stage_id: stage.id,
+                            partition,
+                        };
+                        // tasks are assigned to executors in turn, starting from a different
+                        // executor for each job and for each attempt
+                        let executor = choose_executor(
+                            &executors,
+                            &failed_executors,
+                            &locations[partition],
+                            job_id + partition + attempts[partition],
+                        )?;
+                        let action = task_action(
+                            stage,
+                            partition,
+                            is_last,
+                            output,
+                            &shuffle_job_id,
+                            &completed,
+                        )?;
+                        self.set_status(task_id, TaskStatus::Pending);
+                        Ok((task_id, executor, action))
+                    })
+                    .collect::<Result<_>>()?;
+
+                let span = info_span!("stage", stage_id = stage.id, tasks = tasks.len());
+                let task_results =
+                    future::join_all(tasks.iter().map(|(task_id, executor, action)| {
+                        self.run_task_with_speculation(
+                            *task_id,
+                            executor,
+                            action,
+                            &executors,
+                            &failed_executors,
+                            &durations,
+                            partition_count,
+                        )
+                    }))
+                    .instrument(span)
+                    .await;
+
+                pending = vec![];
+                let mut lost_inputs = false;
+                for ((task_id, _, _), (executor, result)) in tasks.into_iter().zip(task_results) {
+                    let partition = task_id.partition;
+                    attempts[partition] += 1;
+                    let error = match result {
+                        Ok(batches) => {
+                            results[partition] = Some((executor, batches));
+                            continue;
+                        }
+                        Err(e) => e,
+                    };
+                    let retry = attempts[partition] < self.scheduler_config.max_task_attempts;
+                    if retry && is_lost_input(&error) {
+                        lost_inputs = true;
+                    } else if retry && client::is_transient(&error) {
+                        failed_executors.insert(executor.id.clone());
+                    } else {
+                        return Err(error);
+                    }
+                    warn!(
+                        "Retrying task {:?} after attempt {} failed on executor {}: {:?}",
+                        task_id, attempts[partition], executor.id, error
+                    );
+                    pending.push(partition);
+                }
+
+                if lost_inputs {
+                    self.recover_shuffle_outputs(
+                        job_id,
+                        &stages[..stage.id],
+                        &mut completed,
+                        &mut failed_executors,
+                    )
+                    .await?;
+                }
+            }
+
+            let (executors, batches): (Vec<ExecutorMeta>, Vec<Vec<RecordBatch>>) =
+                results.into_iter().map(|result| result.unwrap()).unzip();
+            if is_last {
+                output = batches;
+            } else {
+                // the shuffle partitions are read from the executors that wrote them
+                completed.insert(stage.id, executors);
+                let mut summary = ShuffleSummary::default();
+                for batches in &batches {
+                    summary.merge(&ShuffleSummary::from_batches(batches)?);
+                }
+                statistics.insert(stage.id, summary);
+                for change in adapt_stages(&mut stages, &statistics, &self.planner_config) {
+                    info!("Adapting job {}: {}", job_id, change);
+                }
+                self.set_stage_tasks(job_id, &stages);
+            }
+        }
+        Ok(output)
+    }
+
+    /// The hosts that store the files scanned by each task of a stage. Files whose locations
+    /// cannot be determined are read from any executor.
+    async fn task_locations(&self, stage: &Stage) -> Vec<Vec<String>> {
+        let files: Vec<Vec<String>> = (0..stage.partition_count())
+            .map(|partition| stage.task_files(partition))
+            .collect();
+        if files
+            .iter()
+            .flatten()
+            .all(|f| object_store::scheme(f) == "file")
+        {
+            return vec![vec![]; files.len()];
+        }
+
+        // object stores make blocking requests
+        let object_stores = self.object_stores.clone();
+        let locations = files.clone();
+        tokio::task::spawn_blocking(move || {
+            locations
+                .iter()
+                .map(|files| {
+                    let mut hosts = vec![];
+                    for file in files {
+                        match object_stores
+                            .get(file)
+                            .and_then(|store| store.locations(file))
+                        {
+                            Ok(locations) => hosts.extend(locations),
+                            // database tables are not read from object stores
+                            Err(BallistaError::NotImplemented(_)) => {}
+                            Err(e) => warn!("Unable to locate {}: {:?}", file, e),
+                        }
+                    }
+                    hosts
+                })
+                .collect()
+        })
+        .await
+        .unwrap_or_else(|_| vec![vec![]; files.len()])
+    }
+
+    /// The live executors, after waiting up to the locality wait for an executor that has
+    /// not failed to be available on one of the hosts of each task
+    async fn wait_for_local_executors(
+        &self,
+        hosts: &[&[String]],
+        failed_executors: &HashSet<String>,
+    ) -> Vec<ExecutorMeta> {
+        let deadline = Instant::now() + self.scheduler_config.locality_wait;
+        loop {
+            let executors = self.executors();
+            let waiting = hosts.iter().any(|hosts| {
+                !hosts.is_empty()
+                    && !executors
+                        .iter()
+                        .any(|e| hosts.contains(&e.host) && !failed_executors.contains(&e.id))
+            });
+            if !waiting || Instant::now() >= deadline {
+                return executors;
+            }
+            tokio::time::delay_for(LOCALITY_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Re-run the tasks of completed stages whose shuffle partitions were written by
+    /// executors that have failed or can no longer be reached, so that the tasks that read
+    /// them can be retried
+    async fn recover_shuffle_outputs(
+        &self,
+        job_id: usize,
+        stages: &[Stage],
+        completed: &mut HashMap<usize, Vec<ExecutorMeta>>,
+        failed_executors: &mut HashSet<String>,
+    ) -> Result<()> {
+        let alive: HashSet<String> = self.executors().into_iter().map(|e| e.id).collect();
+        let mut checked = HashSet::new();
+        for executors in completed.values() {
+            for executor in executors {
+                if failed_executors.contains(&executor.id) || !checked.insert(executor.id.clone()) {
+                    continue;
+                }
+                if !alive.contains(&executor.id)
+                    || !client::is_reachable(
+                        &self.connections,
+                        &executor.host,
+                        executor.port,
+                        &self.config,
+                    )
+                    .await
+                {
+                    failed_executors.insert(executor.id.clone());
+                }
+            }
+        }
+
+        // stages are re-run in order, since the tasks that are re-run may read partitions
+        // that were lost from earlier stages
+        let shuffle_job_id = self.shuffle_job_id(job_id);
+        for stage in stages.iter().filter(|stage| !stage.skipped) {
+            let lost: Vec<usize> = completed[&stage.id]
+                .iter()
+                .enumerate()
+                .filter(|(_, executor)| failed_executors.contains(&executor.id))
+                .map(|(partition, _)| partition)
+                .collect();
+            if lost.is_empty() {
+                continue;
+            }
+            warn!(
+                "Re-running {} tasks of stage {} of job {} whose output was lost",
+                lost.len(),
+                stage.id,
+                job_id
+            );
+
+            let executors = self.executors();
+            let locations = self.task_locations(stage).await;
+            let tasks: Vec<(TaskId, ExecutorMeta, Action)> = lost
+                .iter()
+                .map(|&partition| {
+                    let task_id = TaskId {
+                        job_id,
+                        stage_id: stage.id,
+                        partition,
+                    };
+                    let executor = choose_executor(
+                        &executors,
+                        failed_executors,
+                        &locations[partition],
+                        job_id + partition,
+                    )?;
+                    let action =
+                        task_action(stage, partition, false, None, &shuffle_job_id, completed)?;
+                    Ok((task_id, executor, action))
+                })
+                .collect::<Result<_>>()?;
+            let task_results = future::join_all(tasks.iter().map(|(task_id, executor, action)| {
+                self.run_task(*task_id, executor, action.clone())
+            }))
+            .await;
+            for ((task_id, executor, _), result) in tasks.into_iter().zip(task_results) {
+                result?;
+                completed.get_mut(&stage.id).unwrap()[task_id.partition] = executor;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a task, starting a copy on another executor if speculative execution is enabled
+    /// and the task becomes a straggler. Returns the executor that the results are from.
+    #[allow(clippy::too_many_arguments)]
+    async fn run_task_with_speculation(
+        &self,
+        task_id: TaskId,
+        executor: &ExecutorMeta,
+        action: &Action,
+        executors: &[ExecutorMeta],
+        failed_executors: &HashSet<String>,
+        durations: &Mutex<Vec<Duration>>,
+        tasks: usize,
+    ) -> (ExecutorMeta, Result<Vec<RecordBatch>>) {
+        let start = Instant::now();
+        let task = Box::pin(self.run_task(task_id, executor, action.clone()));
+        if !self.scheduler_config.speculation {
+            return (executor.clone(), task.await);
+        }
+
+        let straggling = Box::pin(async {
+            loop {
+                tokio::time::delay_for(SPECULATION_INTERVAL).await;
+                let threshold = self
+                    .scheduler_config
+                    .speculation_threshold(&durations.lock().unwrap(), tasks);
+                if threshold.map(|t| start.elapsed() > t).unwrap_or(false) {
+                    return;
+                }
+            }
+        });
+        let task = match future::select(task, straggling).await {
+            Either::Left((result, _)) => {
+                if result.is_ok() {
+                    durations.lock().unwrap().push(start.elapsed());
+                }
+                return (executor.clone(), result);
+            }
+            Either::Right((_, task)) => task,
+        };
+
+        // the copy runs on another executor that has not failed
+        let others: Vec<ExecutorMeta> = executors
+            .iter()
+            .filter(|e| e.id != executor.id && !failed_executors.contains(&e.id))
+            .cloned()
+            .collect();
+        if others.is_empty() {
+            return (executor.clone(), task.await);
+        }
+        let other = &others[task_id.partition % others.len()];
+        info!(
+            "Speculating task {:?} on executor {} after {:?} on executor {}",
+            task_id,
+            other.id,
+            start.elapsed(),
+            executor.id
+        );
+        let copy = Box::pin(self.run_task(task_id, other, action.clone()));
+
+        // the first copy to succeed is used, and the other copy is cancelled when it is
+        // dropped
+        let (first, second) = match future::select(task, copy).await {
+            Either::Left((result, copy)) => ((executor, result), Either::Left(copy)),
+            Either::Right((result, task)) => ((other, result), Either::Right(task)),
+        };
+        let (first_executor, first_result) = first;
+        let (executor, result) = match first_result {
+            Ok(batches) => (first_executor.clone(), Ok(batches)),
+            Err(_) => match second {
+                Either::Left(copy) => (other.clone(), copy.await),
+                Either::Right(task) => (executor.clone(), task.await),
+            },
+        };
+        if result.is_ok() {
+            durations.lock().unwrap().push(start.elapsed());
+        }
+        (executor, result)
+    }
+
+    async fn run_task(
+        &self,
+        task_id: TaskId,
+        executor: &ExecutorMeta,
+        action: Action,
+    ) -> Result<Vec<RecordBatch>> {
+        // tasks wait for a slot in the queue of their job, and release it when they complete
+        let queue = self.job_queue(task_id.job_id);
+        let config = self.task_config(task_id.job_id);
+        let _slot = self.queues.acquire(&queue, || self.task_slots()).await?;
+        let executor_id = executor.id.clone();
+        self.set_status(
+            task_id,
+            TaskStatus::Running {
+                executor_id: executor_id.clone(),
+            },
+        );
+        let span = info_span!(
+            "task",
+            job_id = task_id.job_id,
+            stage_id = task_id.stage_id,
+            partition = task_id.partition,
+            executor_id = executor_id.as_str()
+        );
+        let result = async {
+            match client::execute_action_stream(
+                &self.connections,
+                &executor.host,
+                executor.port,
+                action,
+                &config,
+            )
+            .await
+            {
+                Ok(stream) => stream.collect_with_metrics(&CancellationToken::new()).await,
+                Err(e) => Err(e),
+            }
+        }
+        .instrument(span)
+        .await;
+        // only the metrics of tasks that succeed are recorded, so that the operators of
+        // tasks that are retried or speculated are counted once
+        let result = result.map(|(batches, metrics)| {
+            if let Some(metrics) = metrics {
+                self.record_task_metrics(task_id, metrics);
+            }
+            batches
+        });
+        match &result {
+            Ok(_) => self.set_status(task_id, TaskStatus::Completed { executor_id }),
+            Err(e) => self.set_status(
+                task_id,
+                TaskStatus::Failed {
+                    executor_id,
+                    error: format!("{:?}", e),
+                },
+            ),
+        }
+        result
+    }
+
+    /// Remove the shuffle partitions of a job from all executors. Failures are ignored,
+    /// since the partitions are no longer read.
+    async fn remove_shuffle(&self, job_id: usize) {
+        let shuffle_job_id = self.shuffle_job_id(job_id);
+        let executors = self.executors();
+        let results = future::join_all(executors.iter().map(|executor| {
+            client::remove_shuffle(
+                &self.connections,
+                &executor.host,
+                executor.port,
+                &shuffle_job_id,
+                &self.config,
+            )
+        }))
+        .await;
+        for (executor, result) in executors.iter().zip(results) {
+            if let Err(e) = result {
+                warn!(
+                    "Unable to remove shuffle partitions for job {} from executor {}: {:?}",
+                    shuffle_job_id, executor.id, e
+                );
+            }
+        }
+    }
+
+    /// The job id that identifies the shuffle partitions of a job on executors
+    fn shuffle_job_id(&self, job_id: usize) -> String {
+        format!("{}-{}", self.id, job_id)
+    }
+
+    /// The queue that a job runs in
+    fn job_queue(&self, job_id: usize) -> String {
+        match self.jobs.lock().unwrap().get(&job_id) {
+            Some(job) => job.queue.clone(),
+            None => queues::DEFAULT_QUEUE.to_owned(),
+        }
+    }
+
+    /// The client configuration for the tasks of a job, which are profiled when the job is
+    fn task_config(&self, job_id: usize) -> ClientConfig {
+        let profile = match self.jobs.lock().unwrap().get(&job_id) {
+            Some(job) => job.metrics.is_profiling(),
+            None => false,
+        };
+        ClientConfig {
+            profile,
+            plan_version: self.plan_version(),
+            ..self.config.clone()
+        }
+    }
+
+    /// The version of the plan format that tasks are encoded with, which is the oldest
+    /// version that the registered executors decode
+    fn plan_version(&self) -> u32 {
+        self.executors
+            .read()
+            .unwrap()
+            .iter()
+            .map(|e| e.plan_version)
+            .fold(self.config.plan_version, u32::min)
+    }
+
+    /// Number of tasks that may run at the same time across all queues, or `None` if there
+    /// is no limit
+    fn task_slots(&self) -> Option<usize> {
+        self.scheduler_config
+            .max_tasks_per_executor
+            .map(|n| n * self.executors().len().max(1))
+    }
+
+    /// Update the number of tasks in each stage of a job after the stages were adapted
+    fn set_stage_tasks(&self, job_id: usize, stages: &[Stage]) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            job.stage_tasks = stages
+                .iter()
+                .map(|stage| {
+                    if stage.skipped {
+                        0
+                    } else {
+                        stage.partition_count()
+                    }
+                })
+                .collect();
+        }
+    }
+
+    fn record_task_metrics(&self, task_id: TaskId, metrics: QueryMetrics) {
+        if let Some(job) = self.jobs.lock().unwrap().get(&task_id.job_id) {
+            job.metrics.extend(metrics.with_stage(task_id.stage_id));
+        }
+    }
+
+    fn set_status(&self, task_id: TaskId, status: TaskStatus) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&task_id.job_id) {
+            job.tasks.insert(task_id, status);
+        }
+        self.load_changed();
+    }
+}
+
+/// Choose the executor for a task, preferring executors that have not failed during the job
+/// and then executors on the hosts that store the files that the task scans
+#[cfg(feature = "local-execution")]
+fn choose_executor(
+    executors: &[ExecutorMeta],
+    failed_executors: &HashSet<String>,
+    hosts: &[String],
+    n: usize,
+) -> Result<ExecutorMeta> {
+    let candidates: Vec<&ExecutorMeta> = executors
+        .iter()
+        .filter(|e| !failed_executors.contains(&e.id))
+        .collect();
+    let local: Vec<&ExecutorMeta> = candidates
+        .iter()
+        .copied()
+        .filter(|e| hosts.contains(&e.host))
+        .collect();
+    if !local.is_empty() {
+        Ok(local[n % local.len()].clone())
+    } else if !candidates.is_empty() {
+        Ok(candidates[n % candidates.len()].clone())
+    } else if !executors.is_empty() {
+        Ok(executors[n % executors.len()].clone())
+    } else {
+        Err(ballista_error("No live executors are registered"))
+    }
+}
+
+/// The action that executes a partition of a stage, reading the shuffle partitions of the
+/// completed stages that it depends on. Tasks of the last stage write their results to the
+/// output directory when it is set.
+#[cfg(feature = "local-execution")]
+fn task_action(
+    stage: &Stage,
+    partition: usize,
+    is_last: bool,
+    output: Option<&str>,
+    shuffle_job_id: &str,
+    completed: &HashMap<usize, Vec<ExecutorMeta>>,
+) -> Result<Action> {
+    let plan = stage.resolve_task_plan(shuffle_job_id, partition, completed)?;
+    Ok(match (is_last, output) {
+        (true, Some(path)) => Action::WriteParquet {
+            plan,
+            path: path.to_owned(),
+            partition,
+        },
+        (true, None) => Action::Collect { plan },
+        (false, _) => Action::ShuffleWrite {
+            job_id: shuffle_job_id.to_owned(),
+            stage_id: stage.id,
+            partition,
+            plan,
+            partitioning: stage.partitioning.clone(),
+        },
+    })
+}
+
+/// Whether a task failed because the shuffle partitions that it reads could not be fetched
+#[cfg(feature = "local-execution")]
+fn is_lost_input(error: &BallistaError) -> bool {
+    match error.root_cause() {
+        BallistaError::TonicError(status) => status.code() == Code::FailedPrecondition,
+        _ => false,
+    }
+}
+
+#[cfg(all(test, feature = "local-execution"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lost_executors() -> Result<()> {
+        let mut settings = HashMap::new();
+        settings.insert(EXECUTOR_TIMEOUT_SECONDS.to_owned(), "10".to_owned());
+        let scheduler = Scheduler::new(ClientConfig::default())
+            .with_scheduler_config(SchedulerConfig::from_settings(&settings)?);
+        for id in &["e1", "e2"] {
+            scheduler.register_executor(ExecutorMeta {
+                id: id.to_string(),
+                host: "localhost".to_owned(),
+                port: 50051,
+            });
+        }
+        scheduler.executors.write().unwrap()[0].last_heartbeat -= 11_000;
+
+        let states: Vec<ExecutorState> = scheduler
+            .executor_status()
+            .iter()
+            .map(|status| status.state)
+            .collect();
+        assert_eq!(vec![ExecutorState::Lost, ExecutorState::Alive], states);
+        assert_eq!(vec!["e2"], ids(&scheduler));
+
+        assert!(scheduler.heartbeat("e1"));
+        assert!(!scheduler.heartbeat("e3"));
+        assert_eq!(vec!["e1", "e2"], ids(&scheduler));
+        Ok(())
+    }
+
+    #[test]
+    fn retry_on_other_executors() -> Result<()> {
+        let executors: Vec<ExecutorMeta> = (0..3)
+            .map(|i| ExecutorMeta {
+                id: format!("e{}", i),
+                host: "localhost".to_owned(),
+                port: 50051 + i,
+            })
+            .collect();
+        let mut failed = HashSet::new();
+        failed.insert("e1".to_owned());
+        assert_eq!("e2", choose_executor(&executors, &failed, &[], 1)?.id);
+        assert_eq!("e0", choose_executor(&executors, &failed, &[], 2)?.id);
+        assert_eq!("e1", choose_executor(&executors[1..2], &failed, &[], 0)?.id);
+        assert!(choose_executor(&[], &failed, &[], 0).is_err());
+
+        let lost = BallistaError::TonicError(tonic::Status::failed_precondition("lost"));
+        let unavailable = BallistaError::TonicError(tonic::Status::unavailable("stopping"));
+        let invalid = BallistaError::TonicError(tonic::Status::internal("no such column"));
+        assert!(is_lost_input(&lost));
+        assert!(!client::is_transient(&lost));
+        assert!(client::is_transient(&unavailable));
+        assert!(!client::is_transient(&invalid));
+
+        let mut settings = HashMap::new();
+        settings.insert(MAX_TASK_ATTEMPTS.to_owned(), "0".to_owned());
+        assert_eq!(
+            1,
+            SchedulerConfig::from_settings(&settings)?.max_task_attempts
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn prefer_local_executors() -> Result<()> {
+        let executors: Vec<ExecutorMeta> = ["dn1", "dn2", "dn3"]
+            .iter()
+            .map(|host| ExecutorMeta {
+                id: host.to_string(),
+                host: host.to_string(),
+                port: 50051,
+            })
+            .collect();
+        let mut failed = HashSet::new();
+        let hosts = vec!["dn3".to_owned(), "dn2".to_owned()];
+        assert_eq!("dn2", choose_executor(&executors, &failed, &hosts, 0)?.id);
+        assert_eq!("dn3", choose_executor(&executors, &failed, &hosts, 1)?.id);
+        failed.insert("dn2".to_owned());
+        failed.insert("dn3".to_owned());
+        assert_eq!("dn1", choose_executor(&executors, &failed, &hosts, 0)?.id);
+        Ok(())
+    }
+
+    #[test]
+    fn speculation_threshold() -> Result<()> {
+        let mut settings = HashMap::new();
+        settings.insert(SPECULATION.to_owned(), "true".to_owned());
+        settings.insert(SPECULATION_MULTIPLIER.to_owned(), "2".to_owned());
+        let config = SchedulerConfig::from_settings(&settings)?;
+        assert!(config.speculation);
+
+        let durations: Vec<Duration> = [3, 1, 2].iter().map(|s| Duration::from_secs(*s)).collect();
+        assert_eq!(None, config.speculation_threshold(&durations, 5));
+        assert_eq!(
+            Some(Duration::from_secs(4)),
+            config.speculation_threshold(&durations, 4)
+        );
+
+        settings.insert(SPECULATION.to_owned(), "yes".to_owned());
+        assert!(SchedulerConfig::from_settings(&settings).is_err());
+        Ok(())
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingHook {
+        loads: Mutex<Vec<SchedulerLoad>>,
+    }
+
+    impl ScalingHook for RecordingHook {
+        fn load_changed(&self, load: &SchedulerLoad) {
+            self.loads.lock().unwrap().push(load.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn scaling_and_draining() -> Result<()> {
+        let hook = Arc::new(RecordingHook::default());
+        let scheduler = Scheduler::new(ClientConfig::default()).with_scaling_hook(hook.clone());
+        for id in &["e1", "e2"] {
+            scheduler.register_executor(ExecutorMeta {
+                id: id.to_string(),
+                host: "localhost".to_owned(),
+                port: 50051,
+            });
+        }
+        scheduler
+            .jobs
+            .lock()
+            .unwrap()
+            .insert(0, Job::new("TableScan: t".to_owned(), vec![2]));
+        let task = |partition| TaskId {
+            job_id: 0,
+            stage_id: 0,
+            partition,
+        };
+        scheduler.set_status(task(0), TaskStatus::Pending);
+        scheduler.set_status(
+            task(1),
+            TaskStatus::Running {
+                executor_id: "e1".to_owned(),
+            },
+        );
+        let load = hook.loads.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(1, load.pending_tasks);
+        assert_eq!(1, load.running_tasks);
+        assert_eq!(vec!["e2"], load.idle_executors);
+        assert_eq!(2, load.required_executors());
+
+        scheduler.drain_executor("e2").await?;
+        assert_eq!(vec!["e1"], ids(&scheduler));
+        assert!(scheduler.drain_executor("e3").await.is_err());
+        Ok(())
+    }
+
+    fn ids(scheduler: &Scheduler) -> Vec<String> {
+        scheduler.executors().into_iter().map(|e| e.id).collect()
+    }
+}
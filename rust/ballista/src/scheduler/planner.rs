@@ -0,0 +1,1127 @@
+//! Splitting of logical plans into stages for distributed execution.
+//!
+//! Scans of files are split into one task per file, and projections and selections are
+//! applied within each task. Operators that need all of their input, such as aggregates and
+//! sorts, start a new stage that reads the shuffled output of the stage below it. Aggregates
+//! are split into a partial aggregate in the stage below and a final aggregate that merges
+//! the partial results, hash partitioned on the grouping columns so that the final
+//! aggregate of each partition runs in a separate task. Both inputs of a join are hash
+//! partitioned on their join columns into the same number of partitions, so that the rows
+//! with the same keys are joined by the same task. When one input of a join has a
+//! `broadcast` hint or is estimated to be smaller than the broadcast threshold, it is
+//! instead written once as a single partition that every task of the join reads, and the
+//! other input is not repartitioned.
+//!
+//! With adaptive execution enabled, the stages that have not run yet are adjusted using the
+//! size of the shuffle partitions written by the stages that have completed. Adjacent
+//! partitions that are smaller than the target size are coalesced so that each task of the
+//! stage that reads them reads several, which reduces the number of tasks. When one input
+//! of a shuffled join turns out to be smaller than the broadcast threshold and the other
+//! input has not run yet, the small input is broadcast to every task of the join and the
+//! other input is scanned by the join's stage instead of being repartitioned.
+
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+
+use crate::arrow::array::Array;
+use crate::datasource::is_remote_path;
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::{Expr, LogicalPlan};
+use crate::memory::batch_memory_size;
+use crate::normalize::normalize_plan;
+use crate::scheduler::aggregate::{final_aggregate, partial_aggregate};
+use crate::scheduler::ExecutorMeta;
+use crate::shuffle::{Partitioning, ShuffleLocation, ShufflePartitionId, ShuffleSummary};
+
+pub const JOIN_BROADCAST_THRESHOLD: &str = "ballista.join.broadcastThreshold";
+pub const ADAPTIVE_EXECUTION: &str = "ballista.adaptive.enabled";
+pub const ADAPTIVE_PARTITION_BYTES: &str = "ballista.adaptive.targetPartitionBytes";
+
+/// Options for splitting plans into stages
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannerConfig {
+    /// Inputs of joins that are estimated to be smaller than this many bytes are broadcast
+    /// to every task of the join. Zero only broadcasts inputs with a `broadcast` hint.
+    pub broadcast_threshold: u64,
+    /// Whether stages are adjusted using the size of the output of the stages that they
+    /// read once those stages have completed
+    pub adaptive: bool,
+    /// Adjacent shuffle partitions are coalesced until they reach this many bytes when
+    /// adaptive execution is enabled
+    pub target_partition_bytes: u64,
+}
+
+impl Default for PlannerConfig {
+    fn default() -> Self {
+        Self {
+            broadcast_threshold: 10 * 1024 * 1024,
+            adaptive: false,
+            target_partition_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl PlannerConfig {
+    /// Read the planner configuration from the Context settings
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            broadcast_threshold: match settings.get(JOIN_BROADCAST_THRESHOLD) {
+                Some(n) => n.parse::<u64>().map_err(|_| {
+                    ballista_error(&format!(
+                        "Invalid value for {}: {}",
+                        JOIN_BROADCAST_THRESHOLD, n
+                    ))
+                })?,
+                None => default.broadcast_threshold,
+            },
+            adaptive: match settings.get(ADAPTIVE_EXECUTION).map(|s| s.as_str()) {
+                Some("true") => true,
+                Some("false") => false,
+                Some(other) => {
+                    return Err(ballista_error(&format!(
+                        "Invalid value for {}: {}",
+                        ADAPTIVE_EXECUTION, other
+                    )))
+                }
+                None => default.adaptive,
+            },
+            target_partition_bytes: match settings.get(ADAPTIVE_PARTITION_BYTES) {
+                Some(n) => n.parse::<u64>().map_err(|_| {
+                    ballista_error(&format!(
+                        "Invalid value for {}: {}",
+                        ADAPTIVE_PARTITION_BYTES, n
+                    ))
+                })?,
+                None => default.target_partition_bytes,
+            },
+        })
+    }
+}
+
+/// The input of a join that is broadcast
+enum JoinSide {
+    Left,
+    Right,
+}
+
+/// A stage of a distributed query
+#[derive(Debug, Clone)]
+pub struct Stage {
+    pub id: usize,
+    /// The plan for the stage, which reads the output of earlier stages through
+    /// `StageOutput` nodes
+    pub plan: LogicalPlan,
+    /// The stages that must complete before this stage can run
+    pub inputs: Vec<usize>,
+    /// How the output of each task is partitioned for the stage that reads it
+    pub partitioning: Partitioning,
+    /// The partitions of the stage outputs that each task reads, when adjacent partitions
+    /// were coalesced by adaptive execution. Otherwise each task reads the partition with
+    /// the same number.
+    pub coalesced: Option<Vec<Range<usize>>>,
+    /// Stages whose partitions are all read by every task, with their number of
+    /// partitions, when adaptive execution changed a shuffled join to a broadcast join
+    pub broadcast_inputs: Vec<(usize, usize)>,
+    /// Whether the plan of the stage was moved into the stage that reads it by adaptive
+    /// execution, in which case the stage is not run
+    pub skipped: bool,
+}
+
+impl Stage {
+    fn new(id: usize, plan: LogicalPlan, partitioning: Partitioning) -> Self {
+        Self {
+            id,
+            inputs: stage_inputs(&plan),
+            plan,
+            partitioning,
+            coalesced: None,
+            broadcast_inputs: vec![],
+            skipped: false,
+        }
+    }
+
+    /// Number of tasks, which is the number of files scanned by the stage or the number of
+    /// partitions of the stage output that it reads
+    pub fn partition_count(&self) -> usize {
+        match &self.coalesced {
+            Some(ranges) => ranges.len(),
+            None => partition_count(&self.plan),
+        }
+    }
+
+    /// The files scanned by the task that executes a partition of the stage
+    pub fn task_files(&self, partition: usize) -> Vec<String> {
+        scan_files(&self.task_plan(partition))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The plan for the task that executes a partition of the stage
+    pub fn task_plan(&self, partition: usize) -> LogicalPlan {
+        if scan_files(&self.plan).map(|f| f.len() > 1).unwrap_or(false) {
+            with_scan_files(&self.plan, partition)
+        } else {
+            self.plan.clone()
+        }
+    }
+
+    /// The plan for the task that executes a partition of the stage, reading the shuffle
+    /// partitions of the completed stages that it depends on
+    pub fn resolve_task_plan(
+        &self,
+        job_id: &str,
+        partition: usize,
+        completed: &HashMap<usize, Vec<ExecutorMeta>>,
+    ) -> Result<LogicalPlan> {
+        resolve_reads(
+            &self.task_plan(partition),
+            job_id,
+            completed,
+            &|stage_id, partitions| self.task_reads(partition, stage_id, partitions),
+        )
+    }
+
+    /// The partitions of the output of a stage with the given number of partitions that a
+    /// task reads
+    fn task_reads(&self, partition: usize, stage_id: usize, partitions: usize) -> Vec<usize> {
+        if let Some((_, n)) = self.broadcast_inputs.iter().find(|(id, _)| *id == stage_id) {
+            (0..*n).collect()
+        } else if partitions == 1 {
+            vec![0]
+        } else if let Some(ranges) = &self.coalesced {
+            ranges[partition].clone().collect()
+        } else {
+            vec![partition]
+        }
+    }
+}
+
+/// Describe how a query is split into stages, with the number of tasks in each stage, the
+/// stages that it reads, how its output is exchanged with the stages that read it, and the
+/// plan that each of its tasks runs on an executor
+pub fn explain_stages(stages: &[Stage]) -> String {
+    let stage_list = |ids: &[usize]| {
+        ids.iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut explain = String::new();
+    for stage in stages {
+        explain.push_str(&format!(
+            "Stage {}: {} tasks\n",
+            stage.id,
+            stage.partition_count()
+        ));
+        if !stage.inputs.is_empty() {
+            explain.push_str(&format!("  Reads: stage {}\n", stage_list(&stage.inputs)));
+        }
+
+        let readers: Vec<usize> = stages
+            .iter()
+            .filter(|s| s.inputs.contains(&stage.id))
+            .map(|s| s.id)
+            .collect();
+        let output = if readers.is_empty() {
+            "returned to the client".to_owned()
+        } else {
+            match &stage.partitioning {
+                Partitioning::Single => format!(
+                    "a single partition read by every task of stage {}",
+                    stage_list(&readers)
+                ),
+                Partitioning::Hash {
+                    columns,
+                    partitions,
+                } => format!(
+                    "{} partitions hashed on columns {:?}, read by stage {}",
+                    partitions,
+                    columns,
+                    stage_list(&readers)
+                ),
+            }
+        };
+        explain.push_str(&format!("  Output: {}\n", output));
+        if stage.skipped {
+            explain.push_str("  Skipped: runs as part of the stage that reads it\n");
+        }
+        for line in format!("{:?}", stage.plan).lines() {
+            explain.push_str(&format!("    {}\n", line));
+        }
+    }
+    explain
+}
+
+/// Render the stages of a query as a Graphviz DOT graph, with a cluster for each stage
+/// containing the plan that its tasks run, and an edge from the last operator of each
+/// stage to the `StageOutput` nodes that read its output
+pub fn stages_to_dot(stages: &[Stage]) -> String {
+    let mut dot = String::from("digraph stages {\n  node [shape=box];\n");
+    let mut shuffles = vec![];
+    for stage in stages {
+        let name = format!("s{}", stage.id);
+        let skipped = if stage.skipped { " (skipped)" } else { "" };
+        dot.push_str(&format!(
+            "  subgraph cluster_{} {{\n    label=\"Stage {}: {} tasks{}\";\n",
+            stage.id,
+            stage.id,
+            stage.partition_count(),
+            skipped
+        ));
+        stage.plan.write_dot(&mut dot, &name, "    ");
+        dot.push_str("  }\n");
+
+        let mut nodes = vec![];
+        stage.plan.dot_nodes(&name, &mut nodes);
+        for (node, plan) in nodes {
+            if let LogicalPlan::StageOutput { stage_id, .. } = plan {
+                shuffles.push(format!("  s{} -> {} [style=dashed];\n", stage_id, node));
+            }
+        }
+    }
+    for shuffle in shuffles {
+        dot.push_str(&shuffle);
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Split a plan into stages. Each stage only depends on stages that come before it, and
+/// the last stage produces the results of the query.
+pub fn plan_stages(plan: &LogicalPlan, config: &PlannerConfig) -> Result<Vec<Stage>> {
+    let mut stages = vec![];
+    let plan = split(plan, config, &mut stages)?;
+    stages.push(Stage::new(stages.len(), plan, Partitioning::Single));
+    Ok(stages)
+}
+
+/// Replace the `StageOutput` nodes in the plan for a task with reads of the shuffle
+/// partitions for the task. The executors that ran each completed stage are listed in
+/// the order of the stage's partitions. Every task reads the output of stages that have a
+/// single partition.
+pub fn resolve_stage_outputs(
+    plan: &LogicalPlan,
+    job_id: &str,
+    partition: usize,
+    completed: &HashMap<usize, Vec<ExecutorMeta>>,
+) -> Result<LogicalPlan> {
+    resolve_reads(plan, job_id, completed, &|_, partitions| {
+        vec![if partitions == 1 { 0 } else { partition }]
+    })
+}
+
+/// Replace the `StageOutput` nodes in a plan with reads of the given partitions of each
+/// stage, from every executor that ran a task of the stage
+fn resolve_reads(
+    plan: &LogicalPlan,
+    job_id: &str,
+    completed: &HashMap<usize, Vec<ExecutorMeta>>,
+    reads: &dyn Fn(usize, usize) -> Vec<usize>,
+) -> Result<LogicalPlan> {
+    match plan {
+        LogicalPlan::StageOutput {
+            stage_id,
+            partitions,
+            schema,
+        } => match completed.get(stage_id) {
+            Some(executors) => Ok(LogicalPlan::ShuffleRead {
+                locations: reads(*stage_id, *partitions)
+                    .into_iter()
+                    .flat_map(move |output_partition| {
+                        executors
+                            .iter()
+                            .enumerate()
+                            .map(move |(map_partition, executor)| ShuffleLocation {
+                                partition_id: ShufflePartitionId {
+                                    job_id: job_id.to_owned(),
+                                    stage_id: *stage_id,
+                                    map_partition,
+                                    output_partition,
+                                },
+                                host: executor.host.clone(),
+                                port: executor.port,
+                            })
+                    })
+                    .collect(),
+                schema: schema.clone(),
+            }),
+            None => Err(format!("Stage {} has not completed", stage_id).into()),
+        },
+        other => Ok(other.with_new_inputs(
+            other
+                .inputs()
+                .into_iter()
+                .map(|input| resolve_reads(input, job_id, completed, reads))
+                .collect::<Result<Vec<_>>>()?,
+        )),
+    }
+}
+
+/// Adjust the stages that have not run yet using the size of the output of the stages that
+/// have completed, returning a description of each change. Does nothing unless adaptive
+/// execution is enabled.
+pub fn adapt_stages(
+    stages: &mut [Stage],
+    statistics: &HashMap<usize, ShuffleSummary>,
+    config: &PlannerConfig,
+) -> Vec<String> {
+    let mut changes = vec![];
+    if !config.adaptive {
+        return changes;
+    }
+    for id in 0..stages.len() {
+        if stages[id].skipped || statistics.contains_key(&id) {
+            continue;
+        }
+        if let Some((small, large)) = broadcast_candidate(&stages[id], stages, statistics, config) {
+            let plan = broadcast_join(&stages[id].plan, small, &stages[large]);
+            let partitions = statistics[&small].num_rows.len();
+            let stage = &mut stages[id];
+            stage.inputs = stage_inputs(&plan);
+            stage.plan = plan;
+            stage.broadcast_inputs.push((small, partitions));
+            stages[large].skipped = true;
+            changes.push(format!(
+                "Stage {} broadcasts the output of stage {} ({} bytes) and runs stage {} as \
+                 part of the join",
+                id,
+                small,
+                statistics[&small].total_bytes(),
+                large
+            ));
+        }
+    }
+    for stage in stages.iter_mut() {
+        if stage.skipped || stage.coalesced.is_some() || statistics.contains_key(&stage.id) {
+            continue;
+        }
+        if let Some(ranges) = coalesce_partitions(stage, statistics, config) {
+            changes.push(format!(
+                "Stage {} reads {} shuffle partitions in {} tasks",
+                stage.id,
+                stage.partition_count(),
+                ranges.len()
+            ));
+            stage.coalesced = Some(ranges);
+        }
+    }
+    changes
+}
+
+/// The completed input and the input that has not run yet of a shuffled join in a stage,
+/// when the completed input is below the broadcast threshold and the other input is only
+/// read by the join
+fn broadcast_candidate(
+    stage: &Stage,
+    stages: &[Stage],
+    statistics: &HashMap<usize, ShuffleSummary>,
+    config: &PlannerConfig,
+) -> Option<(usize, usize)> {
+    let (left, right) = shuffled_join(&stage.plan)?;
+    let small = |id: usize| {
+        statistics
+            .get(&id)
+            .map(|summary| summary.total_bytes() < config.broadcast_threshold)
+            .unwrap_or(false)
+    };
+    let pending = |id: usize| {
+        !statistics.contains_key(&id)
+            && !stages[id].skipped
+            && stages
+                .iter()
+                .all(|s| s.id == stage.id || !s.inputs.contains(&id))
+    };
+    if small(left) && pending(right) {
+        Some((left, right))
+    } else if small(right) && pending(left) {
+        Some((right, left))
+    } else {
+        None
+    }
+}
+
+/// The stages read by a join whose inputs are both hash partitioned stage outputs
+fn shuffled_join(plan: &LogicalPlan) -> Option<(usize, usize)> {
+    match plan {
+        LogicalPlan::Join { left, right, .. } => match (left.as_ref(), right.as_ref()) {
+            (
+                LogicalPlan::StageOutput {
+                    stage_id: left,
+                    partitions: l,
+                    ..
+                },
+                LogicalPlan::StageOutput {
+                    stage_id: right,
+                    partitions: r,
+                    ..
+                },
+            ) if *l > 1 && *r > 1 => Some((*left, *right)),
+            _ => None,
+        },
+        other => other.inputs().into_iter().find_map(shuffled_join),
+    }
+}
+
+/// Replace the output of the large input of a join with the plan that produces it, and read
+/// the small input as a single partition
+fn broadcast_join(plan: &LogicalPlan, small: usize, large: &Stage) -> LogicalPlan {
+    match plan {
+        LogicalPlan::StageOutput {
+            stage_id, schema, ..
+        } if *stage_id == small => LogicalPlan::StageOutput {
+            stage_id: *stage_id,
+            partitions: 1,
+            schema: schema.clone(),
+        },
+        LogicalPlan::StageOutput { stage_id, .. } if *stage_id == large.id => large.plan.clone(),
+        other => other.with_new_inputs(
+            other
+                .inputs()
+                .into_iter()
+                .map(|input| broadcast_join(input, small, large))
+                .collect(),
+        ),
+    }
+}
+
+/// Group the shuffle partitions read by a stage into ranges of adjacent partitions that
+/// are together no larger than the target size, when that reduces the number of tasks.
+/// Every input of the stage must have completed, and stages that scan files are not
+/// coalesced.
+fn coalesce_partitions(
+    stage: &Stage,
+    statistics: &HashMap<usize, ShuffleSummary>,
+    config: &PlannerConfig,
+) -> Option<Vec<Range<usize>>> {
+    if stage.inputs.is_empty() || scan_files(&stage.plan).is_some() {
+        return None;
+    }
+    let partitions = partition_count(&stage.plan);
+    let mut sizes = vec![0; partitions];
+    for input in &stage.inputs {
+        let summary = statistics.get(input)?;
+        if stage.broadcast_inputs.iter().any(|(id, _)| id == input) || summary.num_bytes.len() == 1
+        {
+            continue;
+        }
+        if summary.num_bytes.len() != partitions {
+            return None;
+        }
+        for (size, bytes) in sizes.iter_mut().zip(&summary.num_bytes) {
+            *size += bytes;
+        }
+    }
+
+    let mut ranges: Vec<Range<usize>> = vec![];
+    let mut range_bytes = 0;
+    for (partition, size) in sizes.into_iter().enumerate() {
+        match ranges.last_mut() {
+            Some(range) if range_bytes + size <= config.target_partition_bytes => {
+                range.end = partition + 1;
+                range_bytes += size;
+            }
+            _ => {
+                ranges.push(partition..partition + 1);
+                range_bytes = size;
+            }
+        }
+    }
+    if ranges.len() < partitions {
+        Some(ranges)
+    } else {
+        None
+    }
+}
+
+/// Move the inputs of operators that need all of their input into separate stages
+fn split(
+    plan: &LogicalPlan,
+    config: &PlannerConfig,
+    stages: &mut Vec<Stage>,
+) -> Result<LogicalPlan> {
+    match plan {
+        LogicalPlan::Projection { input, .. }
+        | LogicalPlan::Selection { input, .. }
+        | LogicalPlan::Broadcast { input } => {
+            Ok(plan.with_new_inputs(vec![split(input, config, stages)?]))
+        }
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            schema,
+        } => {
+            let input = split(input, config, stages)?;
+            let partitions = partition_count(&input);
+            if partitions > 1 {
+                if let Some(partial) = partial_aggregate(&input, group_expr, aggr_expr)? {
+                    // the grouping columns are the leading columns of the partial aggregate
+                    let partitioning = if group_expr.is_empty() {
+                        Partitioning::Single
+                    } else {
+                        Partitioning::Hash {
+                            columns: (0..group_expr.len()).collect(),
+                            partitions,
+                        }
+                    };
+                    let partial = new_stage(partial, partitioning, stages);
+                    return final_aggregate(partial, group_expr.len(), aggr_expr, schema);
+                }
+            }
+
+            // aggregates that cannot be merged are computed from the shuffled input
+            let partitioning = match group_columns(group_expr, &input) {
+                Some(columns) => Partitioning::Hash {
+                    columns,
+                    partitions,
+                },
+                None => Partitioning::Single,
+            };
+            Ok(plan.with_new_inputs(vec![new_stage(input, partitioning, stages)]))
+        }
+        LogicalPlan::Sort { input, .. } => {
+            let input = split(input, config, stages)?;
+            Ok(plan.with_new_inputs(vec![new_stage(input, Partitioning::Single, stages)]))
+        }
+        LogicalPlan::Limit { input, .. } => {
+            // each task applies the limit, and the limit is applied again to the combined
+            // output of the tasks
+            let input = plan.with_new_inputs(vec![split(input, config, stages)?]);
+            Ok(plan.with_new_inputs(vec![new_stage(input, Partitioning::Single, stages)]))
+        }
+        LogicalPlan::Join {
+            left: left_input,
+            right: right_input,
+            on,
+            ..
+        } => {
+            let left = split(left_input, config, stages)?;
+            let right = split(right_input, config, stages)?;
+            let partitions = partition_count(&left).max(partition_count(&right));
+            if partitions <= 1 {
+                return Ok(plan.with_new_inputs(vec![left, right]));
+            }
+            match broadcast_side(left_input, right_input, config) {
+                Some(JoinSide::Left) => {
+                    let left = shuffle_stage(left, Partitioning::Single, stages);
+                    return Ok(plan.with_new_inputs(vec![left, right]));
+                }
+                Some(JoinSide::Right) => {
+                    let right = shuffle_stage(right, Partitioning::Single, stages);
+                    return Ok(plan.with_new_inputs(vec![left, right]));
+                }
+                None => {}
+            }
+            // an input with a single partition is also repartitioned, since each task must
+            // only read the rows for its keys
+            let left = shuffle_stage(
+                left,
+                Partitioning::Hash {
+                    columns: on.iter().map(|(l, _)| *l).collect(),
+                    partitions,
+                },
+                stages,
+            );
+            let right = shuffle_stage(
+                right,
+                Partitioning::Hash {
+                    columns: on.iter().map(|(_, r)| *r).collect(),
+                    partitions,
+                },
+                stages,
+            );
+            Ok(plan.with_new_inputs(vec![left, right]))
+        }
+        // the relations of a union may have different numbers of partitions, so each
+        // relation is combined into a single partition
+        LogicalPlan::Union { inputs, .. } => {
+            let inputs = inputs
+                .iter()
+                .map(|input| {
+                    let input = split(input, config, stages)?;
+                    Ok(new_stage(input, Partitioning::Single, stages))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(plan.with_new_inputs(inputs))
+        }
+        // extension nodes execute on all of the rows of each input
+        LogicalPlan::Extension { node } => {
+            let inputs = node
+                .inputs()
+                .into_iter()
+                .map(|input| {
+                    let input = split(input, config, stages)?;
+                    Ok(new_stage(input, Partitioning::Single, stages))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(plan.with_new_inputs(inputs))
+        }
+        other => Ok(other.clone()),
+    }
+}
+
+/// Create a stage for a plan, returning the node that reads its output. Plans with a single
+/// partition are executed as part of the stage that reads them.
+fn new_stage(
+    plan: LogicalPlan,
+    partitioning: Partitioning,
+    stages: &mut Vec<Stage>,
+) -> LogicalPlan {
+    if partition_count(&plan) <= 1 {
+        return plan;
+    }
+    shuffle_stage(plan, partitioning, stages)
+}
+
+/// Create a stage for a plan, returning the node that reads its output. Plans that are
+/// equivalent to the plan of an existing stage with the same partitioning, such as both
+/// sides of a self join, read the output of that stage instead.
+fn shuffle_stage(
+    plan: LogicalPlan,
+    partitioning: Partitioning,
+    stages: &mut Vec<Stage>,
+) -> LogicalPlan {
+    let normalized = normalize_plan(&plan);
+    let existing = stages
+        .iter()
+        .position(|s| s.partitioning == partitioning && normalize_plan(&s.plan) == normalized);
+    let stage = match existing {
+        Some(id) => &stages[id],
+        None => {
+            stages.push(Stage::new(stages.len(), plan, partitioning));
+            &stages[stages.len() - 1]
+        }
+    };
+    LogicalPlan::StageOutput {
+        stage_id: stage.id,
+        partitions: stage.partitioning.partition_count(),
+        schema: stage.plan.schema().clone(),
+    }
+}
+
+/// The input of a join to broadcast, preferring inputs with a `broadcast` hint and then the
+/// smaller input if it is below the broadcast threshold
+fn broadcast_side(
+    left: &LogicalPlan,
+    right: &LogicalPlan,
+    config: &PlannerConfig,
+) -> Option<JoinSide> {
+    if let LogicalPlan::Broadcast { .. } = right {
+        return Some(JoinSide::Right);
+    }
+    if let LogicalPlan::Broadcast { .. } = left {
+        return Some(JoinSide::Left);
+    }
+    let small = |size: Option<u64>| size.filter(|size| *size < config.broadcast_threshold);
+    match (small(estimated_size(left)), small(estimated_size(right))) {
+        (Some(l), Some(r)) if l < r => Some(JoinSide::Left),
+        (_, Some(_)) => Some(JoinSide::Right),
+        (Some(_), None) => Some(JoinSide::Left),
+        (None, None) => None,
+    }
+}
+
+/// Estimated size in bytes of the output of a plan, or `None` if it is not known. Operators
+/// are assumed to produce no more than their input.
+fn estimated_size(plan: &LogicalPlan) -> Option<u64> {
+    match plan {
+        LogicalPlan::FileScan {
+            files, file_type, ..
+        } => {
+            if file_type == "sql" {
+                return None;
+            }
+            files
+                .iter()
+                .map(|file| {
+                    if is_remote_path(file) {
+                        None
+                    } else {
+                        fs::metadata(file).ok().map(|m| m.len())
+                    }
+                })
+                .sum()
+        }
+        LogicalPlan::MemoryScan(batches) => Some(
+            batches
+                .iter()
+                .map(|batch| batch_memory_size(batch) as u64)
+                .sum(),
+        ),
+        LogicalPlan::EmptyRelation { .. } => Some(0),
+        LogicalPlan::Projection { input, .. }
+        | LogicalPlan::Selection { input, .. }
+        | LogicalPlan::Aggregate { input, .. }
+        | LogicalPlan::Sort { input, .. }
+        | LogicalPlan::Limit { input, .. }
+        | LogicalPlan::Broadcast { input } => estimated_size(input),
+        LogicalPlan::Union { inputs, .. } => inputs.iter().map(estimated_size).sum(),
+        _ => None,
+    }
+}
+
+/// The indices of the grouping columns in the input, or `None` if there are no grouping
+/// expressions or some of them are not columns
+fn group_columns(group_expr: &[Expr], input: &LogicalPlan) -> Option<Vec<usize>> {
+    if group_expr.is_empty() {
+        return None;
+    }
+    group_expr
+        .iter()
+        .map(|expr| match expr {
+            Expr::Column(i) => Some(*i),
+            Expr::UnresolvedColumn(name) => input.schema().index_of(name).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Number of partitions of a plan, which is the number of files that it scans or the number
+/// of partitions of the stage output that it reads
+fn partition_count(plan: &LogicalPlan) -> usize {
+    match plan {
+        LogicalPlan::FileScan { files, .. } => files.len().max(1),
+        LogicalPlan::StageOutput { partitions, .. } => *partitions,
+        other => other
+            .inputs()
+            .into_iter()
+            .map(partition_count)
+            .max()
+            .unwrap_or(1),
+    }
+}
+
+/// The stages whose output a plan reads, each listed once
+fn stage_inputs(plan: &LogicalPlan) -> Vec<usize> {
+    let mut ids: Vec<usize> = vec![];
+    for id in stage_output_ids(plan) {
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+fn stage_output_ids(plan: &LogicalPlan) -> Vec<usize> {
+    match plan {
+        LogicalPlan::StageOutput { stage_id, .. } => vec![*stage_id],
+        other => other
+            .inputs()
+            .into_iter()
+            .flat_map(stage_output_ids)
+            .collect(),
+    }
+}
+
+/// The files scanned by a plan
+fn scan_files(plan: &LogicalPlan) -> Option<&Vec<String>> {
+    match plan {
+        LogicalPlan::FileScan { files, .. } => Some(files),
+        other => other.inputs().into_iter().find_map(scan_files),
+    }
+}
+
+/// Restrict the scan in a plan to a single file
+fn with_scan_files(plan: &LogicalPlan, partition: usize) -> LogicalPlan {
+    match plan {
+        LogicalPlan::FileScan {
+            path,
+            paths,
+            files,
+            partition_columns,
+            file_type,
+            schema,
+            projection,
+            projected_schema,
+            csv_options,
+            filters,
+            limit,
+        } => LogicalPlan::FileScan {
+            path: path.clone(),
+            paths: paths.clone(),
+            files: vec![files[partition].clone()],
+            partition_columns: partition_columns.clone(),
+            file_type: file_type.clone(),
+            schema: schema.clone(),
+            projection: projection.clone(),
+            projected_schema: projected_schema.clone(),
+            csv_options: csv_options.clone(),
+            filters: filters.clone(),
+            limit: *limit,
+        },
+        other => other.with_new_inputs(
+            other
+                .inputs()
+                .into_iter()
+                .map(|input| with_scan_files(input, partition))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::dataframe::max;
+    use crate::logicalplan::{col_index, LogicalPlanBuilder};
+
+    #[test]
+    fn split_aggregate() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Utf8, false),
+            Field::new("b", DataType::Int64, false),
+        ]);
+        let scan = LogicalPlan::FileScan {
+            path: "/data".to_owned(),
+            paths: vec![],
+            files: vec!["/data/1.csv".to_owned(), "/data/2.csv".to_owned()],
+            partition_columns: vec![],
+            file_type: "csv".to_owned(),
+            schema: schema.clone(),
+            projection: None,
+            projected_schema: schema,
+            csv_options: None,
+            filters: vec![],
+            limit: None,
+        };
+        let plan = LogicalPlanBuilder::from(&scan)
+            .aggregate(vec![col_index(0)], vec![max(col_index(1))])?
+            .build()?;
+
+        let stages = plan_stages(&plan, &PlannerConfig::default())?;
+        assert_eq!(2, stages.len());
+        assert_eq!(2, stages[0].partition_count());
+        assert_eq!(
+            Partitioning::Hash {
+                columns: vec![0],
+                partitions: 2
+            },
+            stages[0].partitioning
+        );
+        assert_eq!(
+            Some(&vec!["/data/2.csv".to_owned()]),
+            scan_files(&stages[0].task_plan(1))
+        );
+        assert_eq!(vec![0], stages[1].inputs);
+        assert_eq!(2, stages[1].partition_count());
+        let explain = explain_stages(&stages);
+        assert!(explain.contains("Stage 0: 2 tasks\n"));
+        assert!(explain.contains("Output: 2 partitions hashed on columns [0], read by stage 1"));
+        assert!(explain.contains("Stage 1: 2 tasks\n  Reads: stage 0\n"));
+        assert!(explain.contains("Output: returned to the client"));
+        let dot = stages_to_dot(&stages);
+        assert!(dot.contains("subgraph cluster_1 {\n    label=\"Stage 1: 2 tasks\";\n"));
+        assert!(dot.contains("s0 -> s1_0_0 [style=dashed];\n"));
+        assert!(plan.to_dot().contains(
+            "n_0 [label=\"FileScan: '/data' (csv, 2 files)\\nschema: [a: Utf8, b: Int64]\"];\n"
+        ));
+        assert!(resolve_stage_outputs(&stages[1].plan, "job-1", 0, &HashMap::new()).is_err());
+
+        let executor = ExecutorMeta {
+            id: "e1".to_owned(),
+            host: "localhost".to_owned(),
+            port: 50051,
+        };
+        let mut completed = HashMap::new();
+        completed.insert(0, vec![executor.clone(), executor]);
+        let task_plan = resolve_stage_outputs(&stages[1].task_plan(1), "job-1", 1, &completed)?;
+        let read = task_plan.inputs()[0].inputs()[0];
+        match read {
+            LogicalPlan::ShuffleRead { locations, .. } => {
+                assert_eq!(2, locations.len());
+                assert_eq!(1, locations[0].partition_id.output_partition);
+                assert_eq!(1, locations[1].partition_id.map_partition);
+            }
+            other => panic!("unexpected input {:?}", other),
+        }
+        assert_eq!(plan.schema(), task_plan.schema());
+
+        // a single file is aggregated in the same stage
+        let plan = LogicalPlanBuilder::from(&stages[0].task_plan(0))
+            .aggregate(vec![col_index(0)], vec![max(col_index(1))])?
+            .build()?;
+        assert_eq!(1, plan_stages(&plan, &PlannerConfig::default())?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn split_join() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, false),
+        ]);
+        let left = LogicalPlan::FileScan {
+            path: "/data".to_owned(),
+            paths: vec![],
+            files: vec![
+                "/data/1.csv".to_owned(),
+                "/data/2.csv".to_owned(),
+                "/data/3.csv".to_owned(),
+            ],
+            partition_columns: vec![],
+            file_type: "csv".to_owned(),
+            schema: schema.clone(),
+            projection: None,
+            projected_schema: schema.clone(),
+            csv_options: None,
+            filters: vec![],
+            limit: None,
+        };
+        let right = LogicalPlan::EmptyRelation {
+            schema: Schema::new(vec![
+                Field::new("value", DataType::Utf8, false),
+                Field::new("id", DataType::Int64, false),
+            ]),
+        };
+        let plan = LogicalPlanBuilder::from(&left)
+            .join(&right, vec![(0, 1)])?
+            .build()?;
+
+        // the empty relation is below the broadcast threshold
+        let stages = plan_stages(&plan, &PlannerConfig::default())?;
+        assert_eq!(2, stages.len());
+        assert_eq!(Partitioning::Single, stages[0].partitioning);
+        assert_eq!(vec![0], stages[1].inputs);
+        assert_eq!(3, stages[1].partition_count());
+        let executor = ExecutorMeta {
+            id: "e1".to_owned(),
+            host: "localhost".to_owned(),
+            port: 50051,
+        };
+        let mut completed = HashMap::new();
+        completed.insert(0, vec![executor]);
+        let task_plan = resolve_stage_outputs(&stages[1].task_plan(2), "job-1", 2, &completed)?;
+        match task_plan.inputs()[1] {
+            LogicalPlan::ShuffleRead { locations, .. } => {
+                assert_eq!(0, locations[0].partition_id.output_partition)
+            }
+            other => panic!("unexpected input {:?}", other),
+        }
+
+        let config = PlannerConfig {
+            broadcast_threshold: 0,
+            ..PlannerConfig::default()
+        };
+        let stages = plan_stages(&plan, &config)?;
+        assert_eq!(3, stages.len());
+        assert_eq!(
+            Partitioning::Hash {
+                columns: vec![0],
+                partitions: 3
+            },
+            stages[0].partitioning
+        );
+        assert_eq!(
+            Partitioning::Hash {
+                columns: vec![1],
+                partitions: 3
+            },
+            stages[1].partitioning
+        );
+        assert_eq!(1, stages[1].partition_count());
+        assert_eq!(vec![0, 1], stages[2].inputs);
+        assert_eq!(3, stages[2].partition_count());
+        assert_eq!(plan.schema(), stages[2].plan.schema());
+
+        // a hint broadcasts the input regardless of the threshold
+        let plan = LogicalPlanBuilder::from(&left)
+            .join(
+                &LogicalPlanBuilder::from(&right).broadcast()?.build()?,
+                vec![(0, 1)],
+            )?
+            .build()?;
+        assert_eq!(2, plan_stages(&plan, &config)?.len());
+
+        // joins of single partitions run in a single stage
+        let plan = LogicalPlanBuilder::from(&right)
+            .join(&right, vec![(0, 0)])?
+            .build()?;
+        assert_eq!(1, plan_stages(&plan, &PlannerConfig::default())?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn adapt_to_partition_sizes() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int64, false)]);
+        let scan = LogicalPlan::FileScan {
+            path: "/data".to_owned(),
+            paths: vec![],
+            files: vec![
+                "/data/1.csv".to_owned(),
+                "/data/2.csv".to_owned(),
+                "/data/3.csv".to_owned(),
+            ],
+            partition_columns: vec![],
+            file_type: "csv".to_owned(),
+            schema: schema.clone(),
+            projection: None,
+            projected_schema: schema,
+            csv_options: None,
+            filters: vec![],
+            limit: None,
+        };
+        let plan = LogicalPlanBuilder::from(&scan)
+            .join(&scan, vec![(0, 0)])?
+            .build()?;
+        let mut config = PlannerConfig {
+            broadcast_threshold: 0,
+            adaptive: true,
+            target_partition_bytes: 100,
+        };
+        let stages = plan_stages(&plan, &config)?;
+        assert_eq!(3, stages.len());
+        let executor = ExecutorMeta {
+            id: "e1".to_owned(),
+            host: "localhost".to_owned(),
+            port: 50051,
+        };
+        let mut completed = HashMap::new();
+        completed.insert(0, vec![executor.clone(); 3]);
+        let summary = |num_bytes: Vec<u64>| ShuffleSummary {
+            num_rows: vec![1; num_bytes.len()],
+            num_bytes,
+        };
+
+        // small partitions are coalesced once both inputs of the join have completed
+        let mut coalesced = stages.clone();
+        let mut statistics = HashMap::new();
+        statistics.insert(0, summary(vec![10, 10, 200]));
+        assert!(adapt_stages(&mut coalesced, &statistics, &config).is_empty());
+        statistics.insert(1, summary(vec![0, 50, 0]));
+        assert_eq!(1, adapt_stages(&mut coalesced, &statistics, &config).len());
+        assert_eq!(Some(vec![0..2, 2..3]), coalesced[2].coalesced);
+        assert_eq!(2, coalesced[2].partition_count());
+        completed.insert(1, vec![executor.clone(); 3]);
+        let task_plan = coalesced[2].resolve_task_plan("job-1", 0, &completed)?;
+        match task_plan.inputs()[0] {
+            LogicalPlan::ShuffleRead { locations, .. } => {
+                assert_eq!(6, locations.len());
+                assert_eq!(1, locations[5].partition_id.output_partition);
+            }
+            other => panic!("unexpected input {:?}", other),
+        }
+
+        // an input below the broadcast threshold is broadcast, and the other input is
+        // scanned by the join
+        config.broadcast_threshold = 1000;
+        let mut broadcast = stages.clone();
+        statistics.remove(&1);
+        assert_eq!(1, adapt_stages(&mut broadcast, &statistics, &config).len());
+        assert!(broadcast[1].skipped);
+        assert_eq!(vec![0], broadcast[2].inputs);
+        assert_eq!(3, broadcast[2].partition_count());
+        let task_plan = broadcast[2].resolve_task_plan("job-1", 2, &completed)?;
+        match task_plan.inputs()[0] {
+            LogicalPlan::ShuffleRead { locations, .. } => assert_eq!(9, locations.len()),
+            other => panic!("unexpected input {:?}", other),
+        }
+        assert_eq!(
+            Some(&vec!["/data/3.csv".to_owned()]),
+            scan_files(&task_plan)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! Named queues that share the executors of a cluster between the users of a scheduler.
+//!
+//! Each query runs in the queue named by the `ballista.scheduler.queue` setting of the
+//! Context that submitted it, or in the default queue. When the scheduler limits the number
+//! of tasks that run on each executor, tasks wait for a free slot, and each slot that
+//! becomes free goes to the queue that is using the smallest share of the cluster relative
+//! to its weight, so that a queue with a few interactive queries is not starved by a queue
+//! with large batch jobs. Queues can also limit the number of their tasks that run at the
+//! same time. Tasks within a queue start in the order that they started waiting.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::{ballista_error, Result};
+
+use tonic::metadata::MetadataMap;
+use tonic::Request;
+
+/// Name of the queue that the queries of a Context run in
+pub const SCHEDULER_QUEUE: &str = "ballista.scheduler.queue";
+
+/// Queue that queries run in when no queue is requested, which always exists
+pub const DEFAULT_QUEUE: &str = "default";
+
+const QUEUE_METADATA: &str = "x-ballista-queue";
+
+/// How often tasks that are waiting for a slot check whether they can start
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Options for a queue
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueConfig {
+    pub name: String,
+    /// Share of the task slots that the queue receives relative to the other queues that
+    /// have tasks waiting
+    pub weight: usize,
+    /// Maximum number of tasks of the queue that run at the same time, or no limit if not
+    /// set
+    pub max_running_tasks: Option<usize>,
+}
+
+impl QueueConfig {
+    pub fn new(name: &str, weight: usize) -> Self {
+        Self {
+            name: name.to_owned(),
+            weight: weight.max(1),
+            max_running_tasks: None,
+        }
+    }
+
+    pub fn with_max_running_tasks(mut self, max_running_tasks: usize) -> Self {
+        self.max_running_tasks = Some(max_running_tasks);
+        self
+    }
+
+    /// Parse a queue in the form `NAME:WEIGHT[:MAX_RUNNING_TASKS]`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = || ballista_error(&format!("Invalid queue '{}'", spec));
+        let parts: Vec<&str> = spec.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 || parts[0].is_empty() {
+            return Err(invalid());
+        }
+        let weight = parts[1].parse::<usize>().map_err(|_| invalid())?;
+        let queue = Self::new(parts[0], weight);
+        match parts.get(2) {
+            Some(max) => Ok(queue.with_max_running_tasks(max.parse().map_err(|_| invalid())?)),
+            None => Ok(queue),
+        }
+    }
+}
+
+/// Ask the scheduler to run the query of a request in a queue
+pub fn request_queue<T>(queue: &str, request: &mut Request<T>) -> Result<()> {
+    let value = queue
+        .parse()
+        .map_err(|_| ballista_error(&format!("Invalid queue name '{}'", queue)))?;
+    request.metadata_mut().insert(QUEUE_METADATA, value);
+    Ok(())
+}
+
+/// The queue requested by a client, or the default queue
+pub fn requested_queue(metadata: &MetadataMap) -> String {
+    metadata
+        .get(QUEUE_METADATA)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or(DEFAULT_QUEUE)
+        .to_owned()
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    /// Number of running tasks in each queue
+    running: HashMap<String, usize>,
+    /// Tasks waiting for a slot, in the order that they started waiting
+    waiting: Vec<(usize, String)>,
+    next_waiter: usize,
+}
+
+/// Shares the task slots of a cluster between queues
+#[derive(Debug)]
+pub struct TaskQueues {
+    queues: HashMap<String, QueueConfig>,
+    state: Mutex<QueueState>,
+}
+
+impl TaskQueues {
+    /// Create the queues, adding the default queue with a weight of one if it is not
+    /// configured
+    pub fn new(queues: Vec<QueueConfig>) -> Self {
+        let mut queues: HashMap<String, QueueConfig> = queues
+            .into_iter()
+            .map(|queue| (queue.name.clone(), queue))
+            .collect();
+        queues
+            .entry(DEFAULT_QUEUE.to_owned())
+            .or_insert_with(|| QueueConfig::new(DEFAULT_QUEUE, 1));
+        Self {
+            queues,
+            state: Mutex::new(QueueState::default()),
+        }
+    }
+
+    pub fn contains(&self, queue: &str) -> bool {
+        self.queues.contains_key(queue)
+    }
+
+    /// Number of tasks of a queue that are running
+    pub fn running_tasks(&self, queue: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.running.get(queue).copied().unwrap_or(0)
+    }
+
+    /// Wait for a slot to run a task of a queue, when no more than the number of slots
+    /// returned by `slots` may run across all queues, or any number if it returns `None`
+    pub async fn acquire<F: Fn() -> Option<usize>>(
+        self: &Arc<Self>,
+        queue: &str,
+        slots: F,
+    ) -> Result<TaskSlot> {
+        if !self.contains(queue) {
+            return Err(ballista_error(&format!(
+                "Unknown scheduler queue '{}'",
+                queue
+            )));
+        }
+        let waiter = {
+            let mut state = self.state.lock().unwrap();
+            let id = state.next_waiter;
+            state.next_waiter += 1;
+            state.waiting.push((id, queue.to_owned()));
+            Waiter {
+                queues: self.clone(),
+                id,
+            }
+        };
+        loop {
+            if self.try_start(waiter.id, slots()) {
+                return Ok(TaskSlot {
+                    queues: self.clone(),
+                    queue: queue.to_owned(),
+                });
+            }
+            tokio::time::delay_for(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Start a waiting task if it is one of the tasks that the free slots are given to
+    fn try_start(&self, waiter: usize, slots: Option<usize>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let mut running = state.running.clone();
+        let mut total: usize = running.values().sum();
+        let mut waiting = state.waiting.clone();
+        // the free slots are given out one at a time to the first waiting task of the queue
+        // with the smallest share until this task is reached
+        loop {
+            if slots.map(|slots| total >= slots).unwrap_or(false) {
+                return false;
+            }
+            let share = |queue: &str| running.get(queue).copied().unwrap_or(0);
+            let next = waiting
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, queue))| {
+                    self.queues[queue]
+                        .max_running_tasks
+                        .map(|max| share(queue) < max)
+                        .unwrap_or(true)
+                })
+                .min_by(|(_, (a, queue_a)), (_, (b, queue_b))| {
+                    // compares the shares of the queues divided by their weights
+                    let weight = |queue: &str| self.queues[queue].weight;
+                    let share_a = share(queue_a) * weight(queue_b);
+                    let share_b = share(queue_b) * weight(queue_a);
+                    share_a.cmp(&share_b).then(a.cmp(b))
+                })
+                .map(|(i, _)| i);
+            let (id, queue) = match next {
+                Some(i) => waiting.remove(i),
+                None => return false,
+            };
+            if id == waiter {
+                state.waiting.retain(|(id, _)| *id != waiter);
+                *state.running.entry(queue).or_insert(0) += 1;
+                return true;
+            }
+            *running.entry(queue).or_insert(0) += 1;
+            total += 1;
+        }
+    }
+}
+
+impl Default for TaskQueues {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+/// A task that is waiting for a slot, which stops waiting when it is dropped
+struct Waiter {
+    queues: Arc<TaskQueues>,
+    id: usize,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        let mut state = self.queues.state.lock().unwrap();
+        state.waiting.retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// A slot held by a running task, which is released when it is dropped
+#[derive(Debug)]
+pub struct TaskSlot {
+    queues: Arc<TaskQueues>,
+    queue: String,
+}
+
+impl Drop for TaskSlot {
+    fn drop(&mut self) {
+        let mut state = self.queues.state.lock().unwrap();
+        if let Some(running) = state.running.get_mut(&self.queue) {
+            *running -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn share_slots_between_queues() -> Result<()> {
+        let queues = Arc::new(TaskQueues::new(vec![
+            QueueConfig::parse("batch:1")?,
+            QueueConfig::parse("interactive:3:1")?,
+        ]));
+        assert!(QueueConfig::parse("batch").is_err());
+        assert!(queues.acquire("unknown", || None).await.is_err());
+
+        // the batch queue fills the cluster, and the free slot goes to the interactive
+        // queue even though batch tasks started waiting first
+        let slots = || Some(2);
+        let first = queues.acquire("batch", slots).await?;
+        let _second = queues.acquire("batch", slots).await?;
+        let waiting_batch = {
+            let queues = queues.clone();
+            tokio::spawn(async move { queues.acquire("batch", || Some(2)).await.map(|_| ()) })
+        };
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        let interactive = {
+            let queues = queues.clone();
+            tokio::spawn(async move { queues.acquire("interactive", || Some(2)).await })
+        };
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        drop(first);
+        let interactive = interactive.await.unwrap()?;
+        assert_eq!(1, queues.running_tasks("interactive"));
+        assert_eq!(1, queues.running_tasks("batch"));
+
+        // the interactive queue is limited to one running task
+        drop(interactive);
+        waiting_batch.await.unwrap()?;
+        let _interactive = queues.acquire("interactive", || None).await?;
+        let limited = queues.acquire("interactive", || None);
+        assert!(tokio::time::timeout(Duration::from_millis(50), limited)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
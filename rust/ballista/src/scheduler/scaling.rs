@@ -0,0 +1,40 @@
+//! Hooks for scaling the executors of a cluster with the load on the scheduler.
+//!
+//! The scheduler reports its load to the registered hooks whenever tasks start or complete
+//! and executors join or leave, so that an autoscaler can start executors while tasks are
+//! waiting to run and stop executors that are idle. Executors should be drained with
+//! `Scheduler::drain_executor` before they are stopped, so that the tasks running on them
+//! complete.
+
+use std::fmt;
+
+/// The load on a scheduler
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchedulerLoad {
+    /// Number of jobs that have been submitted and have not completed
+    pub active_jobs: usize,
+    /// Number of tasks of the running stages that have not been started
+    pub pending_tasks: usize,
+    /// Number of tasks that are running on executors
+    pub running_tasks: usize,
+    /// Number of live executors that tasks can be assigned to
+    pub executors: usize,
+    /// The ids of the live executors that are not running any tasks
+    pub idle_executors: Vec<String>,
+}
+
+impl SchedulerLoad {
+    /// The number of executors that would run all of the pending and running tasks at the
+    /// same time, with each executor running one task at a time
+    pub fn required_executors(&self) -> usize {
+        self.pending_tasks + self.running_tasks
+    }
+}
+
+/// Receives the load on the scheduler, such as to request more or fewer executors from a
+/// cluster manager
+pub trait ScalingHook: Send + Sync + fmt::Debug {
+    /// Called whenever the load changes. This is called while tasks are being scheduled, so
+    /// implementations should not block.
+    fn load_changed(&self, load: &SchedulerLoad);
+}
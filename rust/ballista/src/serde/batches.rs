@@ -0,0 +1,102 @@
+//! Serde representation of the record batches of memory scans.
+//!
+//! Record batches do not implement serde, so the batches of a `LogicalPlan::MemoryScan`
+//! are serialized as an Arrow IPC stream, in the same way as in the protobuf encoding of
+//! plans. Human-readable formats such as JSON hold the stream as a base64 string, and
+//! binary formats hold the bytes.
+//!
+//! The stream always starts with a schema, so that a table without rows round-trips as a
+//! batch without rows, and a scan without any batches is written with an empty schema.
+
+use std::fmt;
+use std::io::Cursor;
+
+use crate::arrow::datatypes::Schema;
+use crate::arrow::ipc::reader::StreamReader;
+use crate::arrow::ipc::writer::StreamWriter;
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::error;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{self, Serializer};
+
+/// Serialize batches, which must all have the schema of the first batch, if any
+pub fn serialize<S: Serializer>(batches: &[RecordBatch], serializer: S) -> Result<S::Ok, S::Error> {
+    let bytes = write_stream(batches).map_err(|e| ser::Error::custom(format!("{:?}", e)))?;
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&base64::encode(&bytes))
+    } else {
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+/// Deserialize batches that were serialized by `serialize`
+pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<RecordBatch>, D::Error> {
+    let bytes = if deserializer.is_human_readable() {
+        let encoded = String::deserialize(deserializer)?;
+        base64::decode(&encoded).map_err(|e| de::Error::custom(format!("{:?}", e)))?
+    } else {
+        deserialize_bytes(deserializer)?
+    };
+    read_stream(bytes).map_err(|e| de::Error::custom(format!("{:?}", e)))
+}
+
+fn write_stream(batches: &[RecordBatch]) -> error::Result<Vec<u8>> {
+    let mut buf = vec![];
+    let schema = match batches.first() {
+        Some(first) => first.schema().as_ref().clone(),
+        None => Schema::empty(),
+    };
+    let mut writer = StreamWriter::try_new(&mut buf, &schema)?;
+    for batch in batches {
+        writer.write(batch)?;
+    }
+    writer.finish()?;
+    Ok(buf)
+}
+
+fn read_stream(bytes: Vec<u8>) -> error::Result<Vec<RecordBatch>> {
+    // plans serialized before the schema was always written have no stream at all
+    if bytes.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut reader = StreamReader::try_new(Cursor::new(bytes))?;
+    let mut batches = vec![];
+    while let Some(batch) = reader.next_batch()? {
+        batches.push(batch);
+    }
+    Ok(batches)
+}
+
+/// Deserialize bytes, which binary formats may hold either as bytes or as a sequence
+fn deserialize_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> de::Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("the bytes of an Arrow IPC stream")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                bytes.push(b);
+            }
+            Ok(bytes)
+        }
+    }
+
+    deserializer.deserialize_byte_buf(BytesVisitor)
+}
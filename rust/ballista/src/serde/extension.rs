@@ -0,0 +1,45 @@
+//! Serde representation of the nodes of `LogicalPlan::Extension`.
+//!
+//! A node is serialized as its name, the state returned by its `encode` hook and its
+//! inputs, and is deserialized by the codec that is registered for its name, in the same
+//! way as in the protobuf encoding of plans.
+
+use std::sync::Arc;
+
+use crate::extension::{decode_extension, UserDefinedLogicalNode};
+use crate::logicalplan::LogicalPlan;
+
+use serde::de::{self, Deserializer};
+use serde::ser::{self, Serializer};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct ExtensionRepr {
+    name: String,
+    node: Vec<u8>,
+    inputs: Vec<LogicalPlan>,
+}
+
+/// Serialize an extension node with its inputs
+pub fn serialize<S: Serializer>(
+    node: &Arc<dyn UserDefinedLogicalNode>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    ExtensionRepr {
+        name: node.name().to_owned(),
+        node: node
+            .encode()
+            .map_err(|e| ser::Error::custom(format!("{:?}", e)))?,
+        inputs: node.inputs().into_iter().cloned().collect(),
+    }
+    .serialize(serializer)
+}
+
+/// Deserialize an extension node that was serialized by `serialize`
+pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Arc<dyn UserDefinedLogicalNode>, D::Error> {
+    let repr = ExtensionRepr::deserialize(deserializer)?;
+    decode_extension(&repr.name, &repr.node, repr.inputs)
+        .map_err(|e| de::Error::custom(format!("{:?}", e)))
+}
@@ -1,12 +1,23 @@
 use crate::error::{ballista_error, BallistaError};
+use crate::extension::decode_extension;
+use crate::file_scan::{CsvCompression, CsvReadOptions};
 use crate::plan::Action;
 use crate::protobuf;
+use crate::serde::check_plan_version;
+use crate::shuffle::{Partitioning, ShuffleLocation, ShufflePartitionId};
 
-use crate::logicalplan::{Expr, LogicalPlan, LogicalPlanBuilder, Operator, ScalarValue};
+use crate::logicalplan::{
+    Expr, JoinOptions, LogicalPlan, LogicalPlanBuilder, Operator, ScalarValue,
+};
 
-use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
+use crate::arrow::ipc::reader::StreamReader;
+use crate::arrow::record_batch::RecordBatchReader;
 
 use std::convert::TryInto;
+use std::io::Cursor;
+
+use log::debug;
 
 impl TryInto<LogicalPlan> for protobuf::LogicalPlanNode {
     type Error = BallistaError;
@@ -47,9 +58,105 @@ impl TryInto<LogicalPlan> for protobuf::LogicalPlanNode {
                 .aggregate(group_expr, aggr_expr)?
                 .build()
                 .map_err(|e| e.into())
+        } else if let Some(limit) = self.limit {
+            let input: LogicalPlan = self.input.unwrap().as_ref().to_owned().try_into()?;
+            LogicalPlanBuilder::from(&input)
+                .limit(Expr::Literal(ScalarValue::UInt64(limit.limit as u64)))?
+                .build()
+                .map_err(|e| e.into())
+        } else if let Some(sort) = self.sort {
+            let input: LogicalPlan = self.input.unwrap().as_ref().to_owned().try_into()?;
+            LogicalPlanBuilder::from(&input)
+                .sort(
+                    sort.expr
+                        .iter()
+                        .map(|expr| expr.to_owned().try_into())
+                        .collect::<Result<Vec<_>, _>>()?,
+                )?
+                .build()
+                .map_err(|e| e.into())
+        } else if let Some(join) = self.join {
+            let left: LogicalPlan = self.input.unwrap().as_ref().to_owned().try_into()?;
+            let right: LogicalPlan = match self.right {
+                Some(right) => right.as_ref().to_owned().try_into()?,
+                None => return Err(ballista_error("Join has no right input")),
+            };
+            let on = join
+                .left_columns
+                .iter()
+                .zip(join.right_columns.iter())
+                .map(|(l, r)| (*l as usize, *r as usize))
+                .collect();
+            let options = JoinOptions {
+                null_equals_null: join.null_equals_null,
+            };
+            LogicalPlanBuilder::from(&left)
+                .join_with_options(&right, on, options)?
+                .build()
+                .map_err(|e| e.into())
+        } else if self.broadcast.is_some() {
+            let input: LogicalPlan = self.input.unwrap().as_ref().to_owned().try_into()?;
+            LogicalPlanBuilder::from(&input)
+                .broadcast()?
+                .build()
+                .map_err(|e| e.into())
+        } else if let Some(empty_relation) = self.empty_relation {
+            let schema = match empty_relation.schema {
+                Some(schema) => schema.try_into()?,
+                None => Schema::empty(),
+            };
+            Ok(LogicalPlan::EmptyRelation { schema })
+        } else if let Some(memory_scan) = self.memory_scan {
+            let mut batches = vec![];
+            if !memory_scan.batches.is_empty() {
+                let mut reader = StreamReader::try_new(Cursor::new(memory_scan.batches))?;
+                while let Some(batch) = reader.next_batch()? {
+                    batches.push(batch);
+                }
+            }
+            Ok(LogicalPlan::MemoryScan(batches))
+        } else if let Some(extension) = self.extension {
+            let inputs = extension
+                .inputs
+                .into_iter()
+                .map(|input| input.try_into())
+                .collect::<Result<Vec<LogicalPlan>, BallistaError>>()?;
+            let node = decode_extension(&extension.name, &extension.node, inputs)?;
+            Ok(LogicalPlan::Extension { node })
+        } else if let Some(union) = self.union {
+            let inputs = union
+                .inputs
+                .into_iter()
+                .map(|input| input.try_into())
+                .collect::<Result<Vec<LogicalPlan>, BallistaError>>()?;
+            let schema = match union.schema {
+                Some(schema) => schema.try_into()?,
+                None => return Err(ballista_error("Union has no schema")),
+            };
+            Ok(LogicalPlan::Union { inputs, schema })
+        } else if let Some(shuffle_read) = self.shuffle_read {
+            let schema = match shuffle_read.schema {
+                Some(schema) => schema.try_into()?,
+                None => Schema::empty(),
+            };
+            let locations = shuffle_read
+                .locations
+                .into_iter()
+                .map(|l| ShuffleLocation {
+                    partition_id: ShufflePartitionId {
+                        job_id: l.job_id,
+                        stage_id: l.stage_id as usize,
+                        map_partition: l.map_partition as usize,
+                        output_partition: l.output_partition as usize,
+                    },
+                    host: l.host,
+                    port: l.port as usize,
+                })
+                .collect();
+            Ok(LogicalPlan::ShuffleRead { locations, schema })
         } else if let Some(scan) = self.scan {
             let schema: Schema = scan.schema.unwrap().try_into()?;
-            println!("schema: {:?}", schema);
+            debug!("schema: {:?}", schema);
 
             let projection: Vec<usize> = scan
                 .projection
@@ -57,11 +164,87 @@ impl TryInto<LogicalPlan> for protobuf::LogicalPlanNode {
                 .map(|name| schema.index_of(name))
                 .collect::<Result<Vec<_>, _>>()?;
 
-            println!("projection: {:?}", projection);
+            debug!("projection: {:?}", projection);
 
-            LogicalPlanBuilder::scan_csv(&scan.path, &schema, None)? //TODO
-                .build()
-                .map_err(|e| e.into())
+            let projected_schema = if projection.is_empty() {
+                schema.clone()
+            } else {
+                Schema::new(
+                    projection
+                        .iter()
+                        .map(|i| schema.field(*i).clone())
+                        .collect(),
+                )
+            };
+            let projection = if projection.is_empty() {
+                None
+            } else {
+                Some(projection)
+            };
+
+            let filters = scan
+                .filters
+                .iter()
+                .map(|expr| expr.to_owned().try_into())
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let limit = if scan.has_limit {
+                Some(scan.limit as usize)
+            } else {
+                None
+            };
+
+            // the file list was resolved when the plan was built so it is used as-is
+            let files = if scan.files.is_empty() {
+                vec![scan.path.clone()]
+            } else {
+                scan.files.clone()
+            };
+
+            match scan.file_type.as_str() {
+                "csv" | "" => {
+                    let csv_options = match scan.csv_options {
+                        Some(options) => options.try_into()?,
+                        None => CsvReadOptions::default(),
+                    };
+                    Ok(LogicalPlan::FileScan {
+                        path: scan.path.clone(),
+                        paths: scan.paths.clone(),
+                        files,
+                        partition_columns: scan.partition_columns.clone(),
+                        file_type: "csv".to_owned(),
+                        schema,
+                        projection,
+                        projected_schema,
+                        csv_options: Some(csv_options),
+                        filters,
+                        limit,
+                    })
+                }
+                "parquet" | "json" | "avro" | "ipc" | "orc" | "sql" => Ok(LogicalPlan::FileScan {
+                    path: scan.path.clone(),
+                    paths: scan.paths.clone(),
+                    files,
+                    partition_columns: scan.partition_columns.clone(),
+                    file_type: scan.file_type.clone(),
+                    schema,
+                    projection,
+                    projected_schema,
+                    csv_options: None,
+                    filters,
+                    limit,
+                }),
+                "table" => Ok(LogicalPlan::TableScan {
+                    table_name: scan.path.clone(),
+                    schema,
+                    projection,
+                    projected_schema,
+                }),
+                other => Err(ballista_error(&format!(
+                    "Unsupported file type '{}'",
+                    other
+                ))),
+            }
         } else {
             Err(ballista_error(&format!(
                 "Unsupported logical plan '{:?}'",
@@ -78,28 +261,75 @@ impl TryInto<Expr> for protobuf::LogicalExprNode {
         if let Some(binary_expr) = self.binary_expr {
             Ok(Expr::BinaryExpr {
                 left: Box::new(parse_required_expr(binary_expr.l)?),
-                op: Operator::Eq, //TODO parse binary_expr.op.clone(),
+                op: parse_operator(&binary_expr.op)?,
                 right: Box::new(parse_required_expr(binary_expr.r)?),
             })
         } else if self.has_column_index {
             Ok(Expr::Column(self.column_index as usize))
         } else if self.has_column_name {
             Ok(Expr::UnresolvedColumn(self.column_name))
+        } else if self.has_literal_string
+            && self.literal_type == /*protobuf::ArrowType::Decimal*/ 22
+        {
+            let v = self.literal_string.parse::<i128>().map_err(|_| {
+                ballista_error(&format!("Invalid decimal literal {}", self.literal_string))
+            })?;
+            Ok(Expr::Literal(ScalarValue::Decimal(
+                v,
+                self.literal_precision as usize,
+                self.literal_scale as usize,
+            )))
         } else if self.has_literal_string {
             Ok(Expr::Literal(ScalarValue::Utf8(
                 self.literal_string.clone(),
             )))
+        } else if self.has_literal_binary {
+            Ok(Expr::Literal(ScalarValue::Binary(self.literal_binary)))
         } else if self.has_literal_double {
-            Ok(Expr::Literal(ScalarValue::Float64(
-                self.literal_double.clone(),
-            )))
+            let v = self.literal_double;
+            match self.literal_type {
+                /*protobuf::ArrowType::Float*/
+                11 => Ok(Expr::Literal(ScalarValue::Float32(v as f32))),
+                _ => Ok(Expr::Literal(ScalarValue::Float64(v))),
+            }
         } else if self.has_literal_long {
-            Ok(Expr::Literal(ScalarValue::Int64(self.literal_long.clone())))
+            let v = self.literal_long;
+            let value = match self.literal_type {
+                0 => ScalarValue::Int64(v),
+                /*protobuf::ArrowType::Timestamp*/
+                18 => ScalarValue::Timestamp(v, from_proto_time_unit(self.literal_time_unit)?),
+                other => match from_proto_arrow_type(other)? {
+                    DataType::Int8 => ScalarValue::Int8(v as i8),
+                    DataType::Int16 => ScalarValue::Int16(v as i16),
+                    DataType::Int32 => ScalarValue::Int32(v as i32),
+                    DataType::Int64 => ScalarValue::Int64(v),
+                    DataType::UInt8 => ScalarValue::UInt8(v as u8),
+                    DataType::UInt16 => ScalarValue::UInt16(v as u16),
+                    DataType::UInt32 => ScalarValue::UInt32(v as u32),
+                    DataType::UInt64 => ScalarValue::UInt64(v as u64),
+                    DataType::Date32(_) => ScalarValue::Date32(v as i32),
+                    DataType::Date64(_) => ScalarValue::Date64(v),
+                    other => {
+                        return Err(ballista_error(&format!(
+                            "Invalid type {:?} for integer literal",
+                            other
+                        )))
+                    }
+                },
+            };
+            Ok(Expr::Literal(value))
+        } else if self.has_literal_bool {
+            Ok(Expr::Literal(ScalarValue::Boolean(self.literal_bool)))
+        } else if self.has_literal_null {
+            Ok(Expr::Literal(ScalarValue::Null))
         } else if let Some(aggregate_expr) = self.aggregate_expr {
             let name = match aggregate_expr.aggr_function {
                 0 => Ok("MIN"),
                 1 => Ok("MAX"),
                 2 => Ok("SUM"),
+                3 => Ok("AVG"),
+                4 => Ok("COUNT"),
+                5 => Ok("COUNT_DISTINCT"),
                 other => Err(ballista_error(&format!(
                     "Unsupported aggregate function '{:?}'",
                     other
@@ -109,7 +339,53 @@ impl TryInto<Expr> for protobuf::LogicalExprNode {
             Ok(Expr::AggregateFunction {
                 name: name.to_owned(),
                 args: vec![parse_required_expr(aggregate_expr.expr)?],
-                return_type: DataType::Boolean, //TODO
+                return_type: match aggregate_expr.return_type {
+                    0 => DataType::Float64,
+                    other => from_proto_arrow_type(other)?,
+                },
+            })
+        } else if let Some(expr) = self.not_expr {
+            Ok(Expr::Not(Box::new(parse_required_expr(Some(expr))?)))
+        } else if let Some(expr) = self.is_null_expr {
+            Ok(Expr::IsNull(Box::new(parse_required_expr(Some(expr))?)))
+        } else if let Some(expr) = self.is_not_null_expr {
+            Ok(Expr::IsNotNull(Box::new(parse_required_expr(Some(expr))?)))
+        } else if let Some(alias) = self.alias {
+            Ok(Expr::Alias(
+                Box::new(parse_required_expr(alias.expr)?),
+                alias.alias,
+            ))
+        } else if let Some(cast) = self.cast {
+            Ok(Expr::Cast {
+                expr: Box::new(parse_required_expr(cast.expr)?),
+                data_type: from_proto_arrow_type(cast.arrow_type)?,
+            })
+        } else if let Some(sort) = self.sort {
+            Ok(Expr::Sort {
+                expr: Box::new(parse_required_expr(sort.expr)?),
+                asc: sort.asc,
+            })
+        } else if let Some(function) = self.scalar_function {
+            Ok(Expr::ScalarFunction {
+                name: function.name,
+                args: function
+                    .args
+                    .iter()
+                    .map(|expr| expr.to_owned().try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                return_type: from_proto_arrow_type(function.return_type)?,
+            })
+        } else if self.wildcard {
+            Ok(Expr::Wildcard)
+        } else if let Some(get_field) = self.get_field {
+            Ok(Expr::GetField {
+                expr: Box::new(parse_required_expr(get_field.expr)?),
+                name: get_field.name,
+            })
+        } else if let Some(get_index) = self.get_index {
+            Ok(Expr::GetIndex {
+                expr: Box::new(parse_required_expr(get_index.expr)?),
+                index: get_index.index as usize,
             })
         } else {
             Err(ballista_error(&format!(
@@ -120,10 +396,92 @@ impl TryInto<Expr> for protobuf::LogicalExprNode {
     }
 }
 
+pub(crate) fn parse_operator(op: &str) -> Result<Operator, BallistaError> {
+    match op {
+        "Eq" => Ok(Operator::Eq),
+        "NotEq" => Ok(Operator::NotEq),
+        "Lt" => Ok(Operator::Lt),
+        "LtEq" => Ok(Operator::LtEq),
+        "Gt" => Ok(Operator::Gt),
+        "GtEq" => Ok(Operator::GtEq),
+        "Plus" => Ok(Operator::Plus),
+        "Minus" => Ok(Operator::Minus),
+        "Multiply" => Ok(Operator::Multiply),
+        "Divide" => Ok(Operator::Divide),
+        "Modulus" => Ok(Operator::Modulus),
+        "And" => Ok(Operator::And),
+        "Or" => Ok(Operator::Or),
+        "Not" => Ok(Operator::Not),
+        "Like" => Ok(Operator::Like),
+        "NotLike" => Ok(Operator::NotLike),
+        "IsNotDistinctFrom" => Ok(Operator::IsNotDistinctFrom),
+        other => Err(ballista_error(&format!("Unsupported operator '{}'", other))),
+    }
+}
+
+impl TryInto<CsvReadOptions> for protobuf::CsvOptions {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<CsvReadOptions, Self::Error> {
+        let defaults = CsvReadOptions::default();
+        Ok(CsvReadOptions {
+            delimiter: parse_char(self.delimiter_byte, &self.delimiter)?
+                .unwrap_or(defaults.delimiter),
+            quote: parse_char(self.quote_byte, &self.quote)?.unwrap_or(defaults.quote),
+            escape: parse_char(self.escape_byte, &self.escape)?,
+            comment: parse_char(self.comment_byte, &self.comment)?,
+            null_values: self.null_values.clone(),
+            date_format: parse_optional_string(&self.date_format),
+            timestamp_format: parse_optional_string(&self.timestamp_format),
+            timezone: parse_optional_string(&self.timezone),
+            compression: match parse_optional_string(&self.compression) {
+                Some(name) => Some(CsvCompression::from_name(&name)?),
+                None => None,
+            },
+            partitions: match self.partitions {
+                0 => None,
+                n => Some(n as usize),
+            },
+            has_header: !self.no_header,
+        })
+    }
+}
+
+/// A character that is encoded as its byte plus one, or as a string when the byte is 0
+fn parse_char(byte: u32, s: &str) -> Result<Option<u8>, BallistaError> {
+    match byte {
+        0 => {}
+        1..=256 => return Ok(Some((byte - 1) as u8)),
+        _ => {
+            return Err(ballista_error(&format!(
+                "Expected a single byte but found {}",
+                byte - 1
+            )))
+        }
+    }
+    match s.as_bytes() {
+        [] => Ok(None),
+        [c] => Ok(Some(*c)),
+        _ => Err(ballista_error(&format!(
+            "Expected a single character but found '{}'",
+            s
+        ))),
+    }
+}
+
+fn parse_optional_string(s: &str) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_owned())
+    }
+}
+
 impl TryInto<Action> for protobuf::Action {
     type Error = BallistaError;
 
     fn try_into(self) -> Result<Action, Self::Error> {
+        check_plan_version(self.version)?;
         if self.query.is_some() {
             let plan: LogicalPlan = self.query.unwrap().try_into()?;
             // let tables = self
@@ -150,6 +508,54 @@ impl TryInto<Action> for protobuf::Action {
             //     .collect::<Result<Vec<_>, _>>()?;
 
             Ok(Action::Collect { plan })
+        } else if let Some(shuffle_write) = self.shuffle_write {
+            let plan: LogicalPlan = shuffle_write
+                .plan
+                .ok_or_else(|| ballista_error("Shuffle write has no plan"))?
+                .try_into()?;
+            let partitioning = if shuffle_write.hash_columns.is_empty() {
+                Partitioning::Single
+            } else {
+                Partitioning::Hash {
+                    columns: shuffle_write
+                        .hash_columns
+                        .iter()
+                        .map(|c| *c as usize)
+                        .collect(),
+                    partitions: shuffle_write.partitions as usize,
+                }
+            };
+            Ok(Action::ShuffleWrite {
+                job_id: shuffle_write.job_id,
+                stage_id: shuffle_write.stage_id as usize,
+                partition: shuffle_write.partition as usize,
+                plan,
+                partitioning,
+            })
+        } else if let Some(write_parquet) = self.write_parquet {
+            Ok(Action::WriteParquet {
+                plan: write_parquet
+                    .plan
+                    .ok_or_else(|| ballista_error("Parquet write has no plan"))?
+                    .try_into()?,
+                path: write_parquet.path,
+                partition: write_parquet.partition as usize,
+            })
+        } else if let Some(write_csv) = self.write_csv {
+            Ok(Action::WriteCsv {
+                plan: write_csv
+                    .plan
+                    .ok_or_else(|| ballista_error("CSV write has no plan"))?
+                    .try_into()?,
+                path: write_csv.path,
+            })
+        } else if let Some(fetch_schema) = self.fetch_schema {
+            Ok(Action::FetchSchema {
+                path: fetch_schema.path,
+                file_type: fetch_schema.file_type,
+            })
+        } else if self.list_tables.is_some() {
+            Ok(Action::ListTables)
         } else {
             Err(BallistaError::NotImplemented(format!("{:?}", self)))
         }
@@ -170,6 +576,10 @@ fn from_proto_arrow_type(dt: i32 /*protobuf::ArrowType*/) -> Result<DataType, Ba
         /*protobuf::ArrowType::Float*/ 11 => Ok(DataType::Float32),
         /*protobuf::ArrowType::Double*/ 12 => Ok(DataType::Float64),
         /*protobuf::ArrowType::Utf8*/ 13 => Ok(DataType::Utf8),
+        /*protobuf::ArrowType::Bool*/ 1 => Ok(DataType::Boolean),
+        /*protobuf::ArrowType::Binary*/ 14 => Ok(DataType::Binary),
+        /*protobuf::ArrowType::Date32*/ 16 => Ok(DataType::Date32(DateUnit::Day)),
+        /*protobuf::ArrowType::Date64*/ 17 => Ok(DataType::Date64(DateUnit::Millisecond)),
         other => Err(BallistaError::General(format!(
             "Unsupported data type {:?}",
             other
@@ -177,6 +587,19 @@ fn from_proto_arrow_type(dt: i32 /*protobuf::ArrowType*/) -> Result<DataType, Ba
     }
 }
 
+fn from_proto_time_unit(unit: i32 /*protobuf::TimeUnit*/) -> Result<TimeUnit, BallistaError> {
+    match unit {
+        /*protobuf::TimeUnit::Second*/ 0 => Ok(TimeUnit::Second),
+        /*protobuf::TimeUnit::Millisecond*/ 1 => Ok(TimeUnit::Millisecond),
+        /*protobuf::TimeUnit::Microsecond*/ 2 => Ok(TimeUnit::Microsecond),
+        /*protobuf::TimeUnit::Nanosecond*/ 3 => Ok(TimeUnit::Nanosecond),
+        other => Err(BallistaError::General(format!(
+            "Unsupported time unit {:?}",
+            other
+        ))),
+    }
+}
+
 impl TryInto<Schema> for protobuf::Schema {
     type Error = BallistaError;
 
@@ -184,15 +607,34 @@ impl TryInto<Schema> for protobuf::Schema {
         let fields = self
             .columns
             .iter()
-            .map(|c| {
-                let dt: Result<DataType, _> = from_proto_arrow_type(c.arrow_type);
-                dt.and_then(|dt| Ok(Field::new(&c.name, dt, c.nullable)))
-            })
+            .map(from_proto_field)
             .collect::<Result<Vec<_>, _>>()?;
-        Ok(Schema::new(fields))
+        Ok(Schema::new_with_metadata(fields, self.metadata))
     }
 }
 
+/// Dictionary fields have a child field for the keys and one for the values, struct fields
+/// have a child for each of their fields and list fields have a child for their elements
+fn from_proto_field(field: &protobuf::Field) -> Result<Field, BallistaError> {
+    let data_type = match (field.arrow_type, field.children.as_slice()) {
+        (/*protobuf::ArrowType::Dictionary*/ 26, [keys, values]) => DataType::Dictionary(
+            Box::new(from_proto_field(keys)?.data_type().clone()),
+            Box::new(from_proto_field(values)?.data_type().clone()),
+        ),
+        (/*protobuf::ArrowType::Struct*/ 24, fields) => DataType::Struct(
+            fields
+                .iter()
+                .map(from_proto_field)
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        (/*protobuf::ArrowType::List*/ 23, [item]) => {
+            DataType::List(Box::new(from_proto_field(item)?.data_type().clone()))
+        }
+        (arrow_type, _) => from_proto_arrow_type(arrow_type)?,
+    };
+    Ok(Field::new(&field.name, data_type, field.nullable))
+}
+
 fn parse_required_expr(p: Option<Box<protobuf::LogicalExprNode>>) -> Result<Expr, BallistaError> {
     match p {
         Some(expr) => expr.as_ref().to_owned().try_into(),
@@ -1,4 +1,13 @@
+//! Serialization of actions and their plans in the protobuf format.
+//!
+//! Encoded actions carry the version of the plan format, so that executors reject actions
+//! that they cannot decode with a clear error instead of misinterpreting them. Executors
+//! decode the current version and the versions before it down to `MIN_PLAN_VERSION`, and
+//! advertise the newest version that they decode when they register, so that clients and
+//! schedulers can keep encoding the older version while a cluster is upgraded node by node.
+
 use crate::error::BallistaError;
+use crate::logicalplan::LogicalPlan;
 use crate::plan::Action;
 use crate::protobuf;
 
@@ -7,24 +16,102 @@ use prost::Message;
 use std::convert::TryInto;
 use std::io::Cursor;
 
+pub mod batches;
+pub mod extension;
 pub mod from_proto;
+pub mod roundtrip;
 pub mod to_proto;
 
+/// Version of the plan format that actions are encoded with. Version 2 added extension
+/// nodes and version 3 added unions.
+pub const PLAN_VERSION: u32 = 3;
+
+/// Oldest version of the plan format that can encode extension nodes
+const EXTENSION_PLAN_VERSION: u32 = 2;
+
+/// Oldest version of the plan format that can encode unions
+const UNION_PLAN_VERSION: u32 = 3;
+
+/// Oldest version of the plan format that can be decoded. Version 0 is the format of
+/// actions that were encoded before plans were versioned.
+pub const MIN_PLAN_VERSION: u32 = 0;
+
+/// Check that a plan was encoded with a version of the plan format that can be decoded
+pub fn check_plan_version(version: u32) -> Result<(), BallistaError> {
+    if (MIN_PLAN_VERSION..=PLAN_VERSION).contains(&version) {
+        Ok(())
+    } else {
+        Err(BallistaError::UnsupportedPlanVersion(version))
+    }
+}
+
+/// Encode an action with the given version of the plan format, which older executors that
+/// do not decode the current version are sent
+pub fn encode_protobuf(action: Action, version: u32) -> Result<Vec<u8>, BallistaError> {
+    check_plan_version(version)?;
+    let plan = match &action {
+        Action::Collect { plan }
+        | Action::WriteCsv { plan, .. }
+        | Action::WriteParquet { plan, .. }
+        | Action::ShuffleWrite { plan, .. } => Some(plan),
+        Action::FetchSchema { .. } | Action::ListTables => None,
+    };
+    if version < EXTENSION_PLAN_VERSION && plan.map(has_extensions).unwrap_or(false) {
+        return Err(BallistaError::General(format!(
+            "Extension nodes cannot be encoded with plan version {}",
+            version
+        )));
+    }
+    if version < UNION_PLAN_VERSION && plan.map(has_unions).unwrap_or(false) {
+        return Err(BallistaError::General(format!(
+            "Unions cannot be encoded with plan version {}",
+            version
+        )));
+    }
+    let mut proto: protobuf::Action = action.try_into()?;
+    proto.version = version;
+    let mut buf: Vec<u8> = Vec::with_capacity(proto.encoded_len());
+    proto.encode(&mut buf)?;
+    Ok(buf)
+}
+
+fn has_extensions(plan: &LogicalPlan) -> bool {
+    match plan {
+        LogicalPlan::Extension { .. } => true,
+        other => other.inputs().into_iter().any(has_extensions),
+    }
+}
+
+fn has_unions(plan: &LogicalPlan) -> bool {
+    match plan {
+        LogicalPlan::Union { .. } => true,
+        other => other.inputs().into_iter().any(has_unions),
+    }
+}
+
 pub fn decode_protobuf(bytes: &[u8]) -> Result<Action, BallistaError> {
     let mut buf = Cursor::new(bytes);
     protobuf::Action::decode(&mut buf)
-        .map_err(|e| BallistaError::General(format!("{:?}", e)))
+        .map_err(BallistaError::from)
         .and_then(|node| node.try_into())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::arrow::datatypes::{DataType, Field, Schema};
-    use crate::error::Result;
-    use crate::logicalplan::{col, lit_str, Expr, LogicalPlanBuilder};
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use crate::arrow::record_batch::RecordBatch;
+    use crate::error::{BallistaError, Result};
+    use crate::file_scan::CsvReadOptions;
+    use crate::logicalplan::{
+        col, lit_binary, lit_date, lit_decimal, lit_str, Expr, LogicalPlan, LogicalPlanBuilder,
+        ScalarValue,
+    };
     use crate::plan::*;
     use crate::protobuf;
+    use crate::shuffle::{Partitioning, ShuffleLocation, ShufflePartitionId};
     use std::convert::TryInto;
+    use std::sync::Arc;
 
     #[test]
     fn roundtrip() -> Result<()> {
@@ -97,6 +184,267 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn roundtrip_expressions() -> Result<()> {
+        let exprs = vec![
+            Expr::Literal(ScalarValue::Int8(-1)),
+            Expr::Literal(ScalarValue::UInt64(u64::max_value())),
+            Expr::Literal(ScalarValue::Float32(1.5)),
+            Expr::Literal(ScalarValue::Boolean(true)),
+            Expr::Literal(ScalarValue::Null),
+            lit_date("2020-05-14")?,
+            Expr::Literal(ScalarValue::Timestamp(-1, TimeUnit::Nanosecond)),
+            lit_decimal("-123.450")?,
+            lit_binary(&[0, 255]),
+            Expr::Alias(Box::new(col("a").gt(&lit_str("b"))), "c".to_owned()),
+            Expr::Not(Box::new(Expr::IsNull(Box::new(col("a"))))),
+            Expr::Cast {
+                expr: Box::new(Expr::Column(0)),
+                data_type: DataType::Int64,
+            },
+            Expr::Sort {
+                expr: Box::new(col("a")),
+                asc: false,
+            },
+            Expr::ScalarFunction {
+                name: "sqrt".to_owned(),
+                args: vec![col("a")],
+                return_type: DataType::Float64,
+            },
+            col("a").field("b").index(2),
+            col("a").is_not_distinct_from(&Expr::Column(1)),
+            Expr::Wildcard,
+        ];
+        for expr in exprs {
+            let proto: protobuf::LogicalExprNode = expr.clone().try_into()?;
+            let expr2: Expr = proto.try_into()?;
+            assert_eq!(expr, expr2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_generated_plans() {
+        for seed in 0..500 {
+            let mut generator = super::roundtrip::PlanGenerator::new(seed);
+            super::roundtrip::assert_roundtrip(&generator.plan());
+            super::roundtrip::assert_expr_roundtrip(&generator.expr());
+        }
+    }
+
+    #[test]
+    fn roundtrip_sort_limit() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let plan = LogicalPlanBuilder::scan_csv("employee.csv", &schema, Some(vec![0]))
+            .and_then(|plan| {
+                plan.sort(vec![Expr::Sort {
+                    expr: Box::new(col("id")),
+                    asc: true,
+                }])
+            })
+            .and_then(|plan| plan.limit(Expr::Literal(ScalarValue::UInt64(10))))
+            .and_then(|plan| plan.build())
+            .unwrap();
+
+        let action = Action::Collect { plan };
+        let proto: protobuf::Action = action.clone().try_into()?;
+        let action2: Action = proto.try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_write_parquet() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let plan = LogicalPlanBuilder::scan_csv("employee.csv", &schema, None)?.build()?;
+        let action = Action::WriteParquet {
+            plan,
+            path: "s3://bucket/output".to_owned(),
+            partition: 2,
+        };
+        let proto: protobuf::Action = action.clone().try_into()?;
+        let action2: Action = proto.try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_write_csv() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let plan = LogicalPlanBuilder::scan_csv("employee.csv", &schema, None)?.build()?;
+        let action = Action::WriteCsv {
+            plan,
+            path: "hdfs:///output".to_owned(),
+        };
+        let proto: protobuf::Action = action.clone().try_into()?;
+        let action2: Action = proto.try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_csv_without_header() -> Result<()> {
+        let options = CsvReadOptions::new().with_has_header(false);
+        let proto: protobuf::CsvOptions = options.clone().into();
+        let decoded: CsvReadOptions = proto.try_into()?;
+        assert_eq!(options, decoded);
+        // options encoded before the flag existed read a header
+        let decoded: CsvReadOptions = protobuf::CsvOptions::default().try_into()?;
+        assert!(decoded.has_header);
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_csv_non_ascii_characters() -> Result<()> {
+        let options = CsvReadOptions::new().with_delimiter(0xA7).with_escape(0xFF);
+        let proto: protobuf::CsvOptions = options.clone().into();
+        assert_eq!("", proto.delimiter);
+        let decoded: CsvReadOptions = proto.try_into()?;
+        assert_eq!(options, decoded);
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_shuffle_write() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let location = ShuffleLocation {
+            partition_id: ShufflePartitionId {
+                job_id: "job-1".to_owned(),
+                stage_id: 0,
+                map_partition: 2,
+                output_partition: 1,
+            },
+            host: "executor-1".to_owned(),
+            port: 50051,
+        };
+        let plan = LogicalPlan::ShuffleRead {
+            locations: vec![location.clone()],
+            schema,
+        };
+        let action = Action::ShuffleWrite {
+            job_id: "job-1".to_owned(),
+            stage_id: 1,
+            partition: 1,
+            plan,
+            partitioning: Partitioning::Hash {
+                columns: vec![0],
+                partitions: 4,
+            },
+        };
+        let proto: protobuf::Action = action.try_into()?;
+        let action: Action = proto.try_into()?;
+        match action {
+            Action::ShuffleWrite {
+                job_id,
+                plan: LogicalPlan::ShuffleRead { locations, .. },
+                partitioning,
+                ..
+            } => {
+                assert_eq!("job-1", job_id);
+                assert_eq!(vec![location], locations);
+                assert_eq!(
+                    Partitioning::Hash {
+                        columns: vec![0],
+                        partitions: 4
+                    },
+                    partitioning
+                );
+            }
+            other => panic!("unexpected action {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_json() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![1, 2]))])?;
+        let plan = LogicalPlan::Selection {
+            expr: col("id").gt(&Expr::Literal(ScalarValue::Int32(1))),
+            input: Box::new(LogicalPlan::MemoryScan(vec![batch])),
+        };
+        let json = serde_json::to_string(&plan).map_err(|e| format!("{:?}", e))?;
+        let plan2: LogicalPlan = serde_json::from_str(&json).map_err(|e| format!("{:?}", e))?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+        match plan2 {
+            LogicalPlan::Selection { input, .. } => match *input {
+                LogicalPlan::MemoryScan(batches) => {
+                    let ids = batches[0].column(0).as_any().downcast_ref::<Int32Array>();
+                    assert_eq!(2, ids.unwrap().value(1));
+                }
+                other => panic!("unexpected plan {:?}", other),
+            },
+            other => panic!("unexpected plan {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_empty_memory_scan() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let empty = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(Vec::<i32>::new()))],
+        )?;
+        for batches in vec![vec![empty], vec![]] {
+            let plan = LogicalPlan::MemoryScan(batches);
+            let json = serde_json::to_string(&plan).map_err(|e| format!("{:?}", e))?;
+            let from_json: LogicalPlan =
+                serde_json::from_str(&json).map_err(|e| format!("{:?}", e))?;
+            let action = Action::Collect { plan: plan.clone() };
+            let bytes = super::encode_protobuf(action, super::PLAN_VERSION)?;
+            let from_proto = match super::decode_protobuf(&bytes)? {
+                Action::Collect { plan } => plan,
+                other => panic!("unexpected action {:?}", other),
+            };
+            for plan2 in vec![from_json, from_proto] {
+                assert_eq!(plan.schema(), plan2.schema());
+                assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_file_types_in_json() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let plan = LogicalPlanBuilder::scan_csv("employee.csv", &schema, None)?.build()?;
+        let json = serde_json::to_string(&plan).map_err(|e| format!("{:?}", e))?;
+        let json = json.replace("\"file_type\":\"csv\"", "\"file_type\":\"foo\"");
+        let err = serde_json::from_str::<LogicalPlan>(&json).unwrap_err();
+        assert!(err.to_string().contains("unknown variant `foo`"));
+        Ok(())
+    }
+
+    #[test]
+    fn plan_versions() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let action = Action::Collect {
+            plan: LogicalPlanBuilder::scan_csv("employee.csv", &schema, None)?.build()?,
+        };
+
+        // actions encoded before plans were versioned are still decoded
+        let legacy = super::decode_protobuf(&super::encode_protobuf(action.clone(), 0)?)?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", legacy));
+
+        // actions from newer clients are rejected with the supported versions
+        let mut proto: protobuf::Action = action.try_into()?;
+        assert_eq!(super::PLAN_VERSION, proto.version);
+        proto.version = super::PLAN_VERSION + 1;
+        match TryInto::<Action>::try_into(proto) {
+            Err(e @ BallistaError::UnsupportedPlanVersion(_)) => assert_eq!(
+                format!(
+                    "Plan version {} is not supported, versions 0 to {} are supported",
+                    super::PLAN_VERSION + 1,
+                    super::PLAN_VERSION
+                ),
+                e.to_string()
+            ),
+            other => panic!("unexpected result {:?}", other),
+        }
+        Ok(())
+    }
+
     fn max(expr: Expr) -> Expr {
         Expr::AggregateFunction {
             name: "MAX".to_owned(),
@@ -0,0 +1,561 @@
+//! Round-trip testing of the protobuf serialization of plans.
+//!
+//! `PlanGenerator` generates random plans and expressions from a seed, covering each plan
+//! node and expression that the protobuf format encodes, and `assert_roundtrip` checks that
+//! a plan is decoded as the plan that was encoded. Plans are built with the same builder
+//! that decodes them, so that their schemas are those that decoding computes, and every
+//! generated plan is expected to round trip. New plan nodes and expressions should be added
+//! to the generator when they are added to the format.
+//!
+//! Memory scans are only equal to themselves and extension nodes need a registered codec,
+//! so neither is generated.
+
+use std::convert::TryInto;
+
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
+use crate::file_scan::{CsvCompression, CsvReadOptions};
+use crate::logicalplan::{
+    Expr, JoinOptions, LogicalPlan, LogicalPlanBuilder, Operator, ScalarValue, FILE_TYPES,
+};
+use crate::plan::Action;
+use crate::protobuf;
+use crate::serde::{decode_protobuf, encode_protobuf, PLAN_VERSION};
+use crate::shuffle::{ShuffleLocation, ShufflePartitionId};
+
+/// Operators that binary expressions are generated with
+const OPERATORS: &[Operator] = &[
+    Operator::Eq,
+    Operator::NotEq,
+    Operator::Lt,
+    Operator::LtEq,
+    Operator::Gt,
+    Operator::GtEq,
+    Operator::Plus,
+    Operator::Minus,
+    Operator::Multiply,
+    Operator::Divide,
+    Operator::Modulus,
+    Operator::And,
+    Operator::Or,
+    Operator::Not,
+    Operator::Like,
+    Operator::NotLike,
+    Operator::IsNotDistinctFrom,
+];
+
+/// Types that columns, casts and function results are generated with
+const DATA_TYPES: &[DataType] = &[
+    DataType::Boolean,
+    DataType::Int8,
+    DataType::Int16,
+    DataType::Int32,
+    DataType::Int64,
+    DataType::UInt8,
+    DataType::UInt16,
+    DataType::UInt32,
+    DataType::UInt64,
+    DataType::Float32,
+    DataType::Float64,
+    DataType::Utf8,
+    DataType::Binary,
+    DataType::Date32(DateUnit::Day),
+    DataType::Date64(DateUnit::Millisecond),
+];
+
+const AGGREGATES: &[&str] = &["MIN", "MAX", "SUM", "AVG", "COUNT", "COUNT_DISTINCT"];
+
+const FUNCTIONS: &[&str] = &["sqrt", "abs", "concat", "date_part", "array_length"];
+
+const TIME_UNITS: &[TimeUnit] = &[
+    TimeUnit::Second,
+    TimeUnit::Millisecond,
+    TimeUnit::Microsecond,
+    TimeUnit::Nanosecond,
+];
+
+/// Generator of random plans and expressions, which generates the same plans for the same
+/// seed
+pub struct PlanGenerator {
+    state: u64,
+    max_depth: usize,
+    names: usize,
+}
+
+impl PlanGenerator {
+    /// Create a generator of plans with up to four operators above their scans
+    pub fn new(seed: u64) -> Self {
+        Self {
+            // xorshift needs a state that is not zero
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+            max_depth: 4,
+            names: 0,
+        }
+    }
+
+    /// Set the number of operators that plans have above their scans at most
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Generate a plan
+    pub fn plan(&mut self) -> LogicalPlan {
+        let depth = self.max_depth;
+        self.plan_with_depth(depth)
+    }
+
+    /// Generate an expression, which need not be valid for any schema
+    pub fn expr(&mut self) -> Expr {
+        let schema = self.schema();
+        self.any_expr(&schema, 3)
+    }
+
+    fn plan_with_depth(&mut self, depth: usize) -> LogicalPlan {
+        if depth == 0 || self.one_in(4) {
+            return self.leaf();
+        }
+        let input = self.plan_with_depth(depth - 1);
+        let schema = input.schema().clone();
+        let builder = LogicalPlanBuilder::from(&input);
+        let plan = match self.below(8) {
+            0 => {
+                let mut expr: Vec<Expr> = (0..1 + self.below(3))
+                    .map(|_| self.field_expr(&schema, 2))
+                    .collect();
+                if self.one_in(4) {
+                    expr.push(Expr::Wildcard);
+                }
+                builder.project(expr)
+            }
+            1 => builder.filter(self.any_expr(&schema, 3)),
+            2 => {
+                let group_expr = (0..self.below(3))
+                    .map(|_| self.field_expr(&schema, 1))
+                    .collect();
+                let aggr_expr = (0..1 + self.below(2))
+                    .map(|_| self.aggregate_expr(&schema))
+                    .collect();
+                builder.aggregate(group_expr, aggr_expr)
+            }
+            3 => {
+                let expr = (0..1 + self.below(2))
+                    .map(|_| Expr::Sort {
+                        expr: Box::new(self.any_expr(&schema, 2)),
+                        asc: self.one_in(2),
+                    })
+                    .collect();
+                builder.sort(expr)
+            }
+            4 => builder.limit(Expr::Literal(ScalarValue::UInt64(
+                self.below(100_000) as u64
+            ))),
+            5 => builder.broadcast(),
+            6 => {
+                let other = self.plan_with_depth(depth - 1);
+                // columns with the same name may not have a common type
+                match builder.union_by_name(&other, true) {
+                    Ok(builder) => Ok(builder),
+                    Err(_) => return input,
+                }
+            }
+            _ => {
+                let right = self.plan_with_depth(depth - 1);
+                let right_schema = right.schema().clone();
+                // join columns are pairs of columns of the same type
+                let mut on = vec![];
+                for (l, left_field) in schema.fields().iter().enumerate() {
+                    for (r, right_field) in right_schema.fields().iter().enumerate() {
+                        if left_field.data_type() == right_field.data_type() && on.len() < 2 {
+                            on.push((l, r));
+                        }
+                    }
+                }
+                if on.is_empty() {
+                    return input;
+                }
+                let options = JoinOptions {
+                    null_equals_null: self.one_in(2),
+                };
+                builder.join_with_options(&right, on, options)
+            }
+        };
+        plan.and_then(|plan| plan.build())
+            .expect("generated plans are valid")
+    }
+
+    /// A scan, empty relation or shuffle read
+    fn leaf(&mut self) -> LogicalPlan {
+        let schema = self.schema();
+        let projection = if self.one_in(2) {
+            let mut indices: Vec<usize> = (0..schema.fields().len())
+                .filter(|_| self.one_in(2))
+                .collect();
+            if indices.is_empty() {
+                indices.push(0);
+            }
+            if self.one_in(2) {
+                indices.reverse();
+            }
+            Some(indices)
+        } else {
+            None
+        };
+        let projected_schema = match &projection {
+            Some(indices) => {
+                Schema::new(indices.iter().map(|i| schema.field(*i).clone()).collect())
+            }
+            None => schema.clone(),
+        };
+        match self.below(4) {
+            0 | 1 => {
+                let file_type = *self.pick(FILE_TYPES);
+                let path = format!("/data/{}", self.name());
+                let files = (0..1 + self.below(3))
+                    .map(|i| format!("{}/part-{}.{}", path, i, file_type))
+                    .collect();
+                let paths = if self.one_in(3) {
+                    vec![
+                        path.clone(),
+                        format!("/data/{},{}", self.name(), self.name()),
+                    ]
+                } else {
+                    vec![]
+                };
+                let partition_columns = (0..self.below(2)).map(|_| self.name()).collect();
+                let filters = (0..self.below(2))
+                    .map(|_| self.any_expr(&schema, 2))
+                    .collect();
+                LogicalPlan::FileScan {
+                    path,
+                    paths,
+                    files,
+                    partition_columns,
+                    file_type: file_type.to_owned(),
+                    schema,
+                    projection,
+                    projected_schema,
+                    csv_options: if file_type == "csv" {
+                        Some(self.csv_options())
+                    } else {
+                        None
+                    },
+                    filters,
+                    limit: if self.one_in(3) {
+                        Some(self.below(1_000))
+                    } else {
+                        None
+                    },
+                }
+            }
+            2 => {
+                if self.one_in(2) {
+                    LogicalPlan::TableScan {
+                        table_name: self.name(),
+                        schema,
+                        projection,
+                        projected_schema,
+                    }
+                } else {
+                    LogicalPlan::EmptyRelation { schema }
+                }
+            }
+            _ => LogicalPlan::ShuffleRead {
+                locations: (0..1 + self.below(3))
+                    .map(|i| ShuffleLocation {
+                        partition_id: ShufflePartitionId {
+                            job_id: self.name(),
+                            stage_id: self.below(10),
+                            map_partition: i,
+                            output_partition: self.below(10),
+                        },
+                        host: self.name(),
+                        port: 50051 + self.below(100),
+                    })
+                    .collect(),
+                schema,
+            },
+        }
+    }
+
+    fn csv_options(&mut self) -> CsvReadOptions {
+        let mut options = CsvReadOptions::new()
+            .with_delimiter(*self.pick(&b",|;\t"[..]))
+            .with_quote(*self.pick(&b"\"'"[..]))
+            .with_has_header(self.one_in(2));
+        if self.one_in(3) {
+            options = options.with_escape(b'\\');
+        }
+        if self.one_in(3) {
+            options = options.with_comment(b'#');
+        }
+        for _ in 0..self.below(3) {
+            options = options.with_null_value(self.pick(&["", "NULL", "\\N"][..]));
+        }
+        if self.one_in(3) {
+            options = options.with_date_format("%d/%m/%Y");
+        }
+        if self.one_in(3) {
+            options = options.with_timestamp_format("%Y-%m-%dT%H:%M:%S");
+        }
+        if self.one_in(3) {
+            options = options.with_timezone("+02:00");
+        }
+        if self.one_in(3) {
+            options = options.with_compression(*self.pick(
+                &[
+                    CsvCompression::Uncompressed,
+                    CsvCompression::Gzip,
+                    CsvCompression::Bzip2,
+                    CsvCompression::Zstd,
+                ][..],
+            ));
+        }
+        if self.one_in(3) {
+            options = options.with_partitions(1 + self.below(8));
+        }
+        options
+    }
+
+    /// A schema with between one and four columns with distinct names
+    fn schema(&mut self) -> Schema {
+        Schema::new(
+            (0..1 + self.below(4))
+                .map(|_| {
+                    let data_type = self.column_type(1);
+                    Field::new(&self.name(), data_type, self.one_in(2))
+                })
+                .collect(),
+        )
+    }
+
+    fn column_type(&mut self, depth: usize) -> DataType {
+        match self.below(8) {
+            0 if depth > 0 => DataType::Struct(
+                (0..1 + self.below(2))
+                    .map(|_| {
+                        let data_type = self.column_type(depth - 1);
+                        Field::new(&self.name(), data_type, self.one_in(2))
+                    })
+                    .collect(),
+            ),
+            1 if depth > 0 => DataType::List(Box::new(self.column_type(depth - 1))),
+            _ => self.pick(DATA_TYPES).clone(),
+        }
+    }
+
+    /// An expression for which a field of the schema of a projection or aggregate can be
+    /// determined
+    fn field_expr(&mut self, schema: &Schema, depth: usize) -> Expr {
+        if depth == 0 {
+            return self.column_or_literal(schema);
+        }
+        match self.below(7) {
+            0 => Expr::Cast {
+                expr: Box::new(self.any_expr(schema, depth - 1)),
+                data_type: self.pick(DATA_TYPES).clone(),
+            },
+            1 => Expr::ScalarFunction {
+                name: self.pick(FUNCTIONS).to_string(),
+                args: (0..self.below(3))
+                    .map(|_| self.any_expr(schema, depth - 1))
+                    .collect(),
+                return_type: self.pick(DATA_TYPES).clone(),
+            },
+            2 => Expr::Alias(Box::new(self.typed_expr(schema, depth - 1)), self.name()),
+            // the supertype of any type and Utf8 is Utf8
+            3 => Expr::BinaryExpr {
+                left: Box::new(self.typed_expr(schema, depth - 1)),
+                op: self.pick(OPERATORS).clone(),
+                right: Box::new(Expr::Literal(ScalarValue::Utf8(self.name()))),
+            },
+            4 => self.nested_access(schema),
+            _ => self.column_or_literal(schema),
+        }
+    }
+
+    /// An expression whose type can be determined
+    fn typed_expr(&mut self, schema: &Schema, depth: usize) -> Expr {
+        if depth == 0 {
+            return self.column_or_literal(schema);
+        }
+        match self.below(5) {
+            0 => Expr::Not(Box::new(self.any_expr(schema, depth - 1))),
+            1 => Expr::IsNull(Box::new(self.any_expr(schema, depth - 1))),
+            2 => Expr::IsNotNull(Box::new(self.any_expr(schema, depth - 1))),
+            3 => Expr::BinaryExpr {
+                left: Box::new(self.any_expr(schema, depth - 1)),
+                op: self
+                    .pick(&[Operator::Eq, Operator::Lt, Operator::And, Operator::Or][..])
+                    .clone(),
+                right: Box::new(self.any_expr(schema, depth - 1)),
+            },
+            _ => self.field_expr(schema, depth),
+        }
+    }
+
+    /// An expression that is only valid for a schema by chance, as the expressions of
+    /// selections, sorts and scans need not be until the plan is executed
+    fn any_expr(&mut self, schema: &Schema, depth: usize) -> Expr {
+        if depth == 0 {
+            // null literals have no type, so they are only generated here
+            return match self.below(20) {
+                0 => Expr::Wildcard,
+                1 => Expr::Literal(ScalarValue::Null),
+                _ => self.column_or_literal(schema),
+            };
+        }
+        match self.below(5) {
+            0 => Expr::BinaryExpr {
+                left: Box::new(self.any_expr(schema, depth - 1)),
+                op: self.pick(OPERATORS).clone(),
+                right: Box::new(self.any_expr(schema, depth - 1)),
+            },
+            1 => Expr::Sort {
+                expr: Box::new(self.any_expr(schema, depth - 1)),
+                asc: self.one_in(2),
+            },
+            2 => Expr::GetField {
+                expr: Box::new(self.any_expr(schema, depth - 1)),
+                name: self.name(),
+            },
+            3 => Expr::GetIndex {
+                expr: Box::new(self.any_expr(schema, depth - 1)),
+                index: self.below(10),
+            },
+            _ => self.typed_expr(schema, depth),
+        }
+    }
+
+    fn aggregate_expr(&mut self, schema: &Schema) -> Expr {
+        Expr::AggregateFunction {
+            name: self.pick(AGGREGATES).to_string(),
+            args: vec![self.any_expr(schema, 1)],
+            return_type: self.pick(DATA_TYPES).clone(),
+        }
+    }
+
+    /// A field of a struct column or an element of a list column, if the schema has one
+    fn nested_access(&mut self, schema: &Schema) -> Expr {
+        let nested: Vec<&Field> = schema
+            .fields()
+            .iter()
+            .filter(|f| match f.data_type() {
+                DataType::Struct(_) | DataType::List(_) => true,
+                _ => false,
+            })
+            .collect();
+        if nested.is_empty() {
+            return self.column_or_literal(schema);
+        }
+        let field = *self.pick(&nested);
+        let column = Expr::UnresolvedColumn(field.name().clone());
+        match field.data_type() {
+            DataType::Struct(fields) => Expr::GetField {
+                expr: Box::new(column),
+                name: self.pick(fields).name().clone(),
+            },
+            _ => Expr::GetIndex {
+                expr: Box::new(column),
+                index: self.below(10),
+            },
+        }
+    }
+
+    fn column_or_literal(&mut self, schema: &Schema) -> Expr {
+        match self.below(3) {
+            0 => Expr::Column(self.below(schema.fields().len())),
+            1 => Expr::UnresolvedColumn(self.pick(schema.fields()).name().clone()),
+            _ => Expr::Literal(self.literal()),
+        }
+    }
+
+    fn literal(&mut self) -> ScalarValue {
+        let v = self.next();
+        match self.below(17) {
+            0 | 1 => ScalarValue::Boolean(v % 2 == 0),
+            2 => ScalarValue::Int8(v as i8),
+            3 => ScalarValue::Int16(v as i16),
+            4 => ScalarValue::Int32(v as i32),
+            5 => ScalarValue::Int64(v as i64),
+            6 => ScalarValue::UInt8(v as u8),
+            7 => ScalarValue::UInt16(v as u16),
+            8 => ScalarValue::UInt32(v as u32),
+            9 => ScalarValue::UInt64(v),
+            // floats are never NaN, whose bits need not survive a conversion
+            10 => ScalarValue::Float32((v as i32) as f32 / 64.0),
+            11 => ScalarValue::Float64((v as i64) as f64 / 1024.0),
+            12 => ScalarValue::Date32(v as i32),
+            13 => ScalarValue::Date64(v as i64),
+            14 => ScalarValue::Timestamp(v as i64, self.pick(TIME_UNITS).clone()),
+            15 => {
+                let precision = 1 + self.below(38);
+                let scale = self.below(precision + 1);
+                ScalarValue::Decimal(v as i64 as i128, precision, scale)
+            }
+            _ => {
+                if self.one_in(2) {
+                    ScalarValue::Utf8(self.name())
+                } else {
+                    ScalarValue::Binary(v.to_le_bytes()[..self.below(9)].to_vec())
+                }
+            }
+        }
+    }
+
+    /// A name that the generator has not generated before
+    fn name(&mut self) -> String {
+        self.names += 1;
+        format!("n{}", self.names)
+    }
+
+    fn next(&mut self) -> u64 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// Assert that a plan is encoded and decoded with the current version of the plan format
+/// as the same plan, with the same schema
+pub fn assert_roundtrip(plan: &LogicalPlan) {
+    let encoded = encode_protobuf(Action::Collect { plan: plan.clone() }, PLAN_VERSION)
+        .unwrap_or_else(|e| panic!("Failed to encode {:?}: {:?}", plan, e));
+    let decoded = match decode_protobuf(&encoded) {
+        Ok(Action::Collect { plan }) => plan,
+        other => panic!("Failed to decode {:?}: {:?}", plan, other),
+    };
+    assert!(
+        *plan == decoded && plan.schema() == decoded.schema(),
+        "Plan changed when it was serialized\nencoded:\n{:?}\n{:?}\ndecoded:\n{:?}\n{:?}",
+        plan,
+        plan.schema(),
+        decoded,
+        decoded.schema()
+    );
+}
+
+/// Assert that an expression is decoded as the expression that was encoded
+pub fn assert_expr_roundtrip(expr: &Expr) {
+    let proto: protobuf::LogicalExprNode = expr
+        .clone()
+        .try_into()
+        .unwrap_or_else(|e| panic!("Failed to encode {:?}: {:?}", expr, e));
+    let decoded: Expr = proto
+        .try_into()
+        .unwrap_or_else(|e| panic!("Failed to decode {:?}: {:?}", expr, e));
+    assert_eq!(*expr, decoded, "Expression changed when it was serialized");
+}
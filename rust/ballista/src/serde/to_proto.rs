@@ -1,10 +1,14 @@
 use crate::error::BallistaError;
+use crate::file_scan::CsvReadOptions;
 use crate::plan::Action;
 use crate::protobuf;
+use crate::serde::PLAN_VERSION;
+use crate::shuffle::Partitioning;
 
 use crate::logicalplan::{Expr, LogicalPlan, ScalarValue};
 
-use crate::arrow::datatypes::{DataType, Schema};
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
+use crate::arrow::ipc::writer::StreamWriter;
 
 use std::convert::TryInto;
 
@@ -12,15 +16,69 @@ impl TryInto<protobuf::Action> for Action {
     type Error = BallistaError;
 
     fn try_into(self) -> Result<protobuf::Action, Self::Error> {
-        match self {
-            Action::Collect { plan } => {
-                let plan_proto: protobuf::LogicalPlanNode = plan.try_into()?;
-                Ok(protobuf::Action {
-                    query: Some(plan_proto),
-                })
+        let action = match self {
+            Action::Collect { plan } => protobuf::Action {
+                query: Some(plan.try_into()?),
+                ..Default::default()
+            },
+            Action::ShuffleWrite {
+                job_id,
+                stage_id,
+                partition,
+                plan,
+                partitioning,
+            } => {
+                let (hash_columns, partitions) = match partitioning {
+                    Partitioning::Single => (vec![], 1),
+                    Partitioning::Hash {
+                        columns,
+                        partitions,
+                    } => (columns.iter().map(|c| *c as u32).collect(), partitions),
+                };
+                protobuf::Action {
+                    shuffle_write: Some(protobuf::ShuffleWriteNode {
+                        job_id,
+                        stage_id: stage_id as u32,
+                        partition: partition as u32,
+                        plan: Some(plan.try_into()?),
+                        hash_columns,
+                        partitions: partitions as u32,
+                    }),
+                    ..Default::default()
+                }
             }
-            _ => unimplemented!(),
-        }
+            Action::WriteParquet {
+                plan,
+                path,
+                partition,
+            } => protobuf::Action {
+                write_parquet: Some(protobuf::WriteParquetNode {
+                    path,
+                    partition: partition as u32,
+                    plan: Some(plan.try_into()?),
+                }),
+                ..Default::default()
+            },
+            Action::WriteCsv { plan, path } => protobuf::Action {
+                write_csv: Some(protobuf::WriteCsvNode {
+                    path,
+                    plan: Some(plan.try_into()?),
+                }),
+                ..Default::default()
+            },
+            Action::FetchSchema { path, file_type } => protobuf::Action {
+                fetch_schema: Some(protobuf::FetchSchemaNode { path, file_type }),
+                ..Default::default()
+            },
+            Action::ListTables => protobuf::Action {
+                list_tables: Some(protobuf::ListTablesNode {}),
+                ..Default::default()
+            },
+        };
+        Ok(protobuf::Action {
+            version: PLAN_VERSION,
+            ..action
+        })
     }
 }
 
@@ -32,22 +90,49 @@ impl TryInto<protobuf::Schema> for Schema {
             columns: self
                 .fields()
                 .iter()
-                .map(|field| {
-                    let proto = to_proto_arrow_type(&field.data_type());
-                    proto.and_then(|arrow_type| {
-                        Ok(protobuf::Field {
-                            name: field.name().to_owned(),
-                            arrow_type: arrow_type.into(),
-                            nullable: field.is_nullable(),
-                            children: vec![],
-                        })
-                    })
-                })
+                .map(to_proto_field)
                 .collect::<Result<Vec<_>, _>>()?,
+            metadata: self.metadata().clone(),
         })
     }
 }
 
+/// Dictionary fields have a child field for the keys and one for the values, struct fields
+/// have a child for each of their fields and list fields have a child for their elements
+fn to_proto_field(field: &Field) -> Result<protobuf::Field, BallistaError> {
+    let (arrow_type, children) = match field.data_type() {
+        DataType::Dictionary(key_type, value_type) => (
+            protobuf::ArrowType::Dictionary,
+            vec![
+                to_proto_field(&Field::new("keys", key_type.as_ref().clone(), false))?,
+                to_proto_field(&Field::new("values", value_type.as_ref().clone(), true))?,
+            ],
+        ),
+        DataType::Struct(fields) => (
+            protobuf::ArrowType::Struct,
+            fields
+                .iter()
+                .map(to_proto_field)
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        DataType::List(item_type) => (
+            protobuf::ArrowType::List,
+            vec![to_proto_field(&Field::new(
+                "item",
+                item_type.as_ref().clone(),
+                true,
+            ))?],
+        ),
+        other => (to_proto_arrow_type(other)?, vec![]),
+    };
+    Ok(protobuf::Field {
+        name: field.name().to_owned(),
+        arrow_type: arrow_type.into(),
+        nullable: field.is_nullable(),
+        children,
+    })
+}
+
 fn to_proto_arrow_type(dt: &DataType) -> Result<protobuf::ArrowType, BallistaError> {
     match dt {
         DataType::Int8 => Ok(protobuf::ArrowType::Int8),
@@ -61,6 +146,10 @@ fn to_proto_arrow_type(dt: &DataType) -> Result<protobuf::ArrowType, BallistaErr
         DataType::Float32 => Ok(protobuf::ArrowType::Float),
         DataType::Float64 => Ok(protobuf::ArrowType::Double),
         DataType::Utf8 => Ok(protobuf::ArrowType::Utf8),
+        DataType::Boolean => Ok(protobuf::ArrowType::Bool),
+        DataType::Binary => Ok(protobuf::ArrowType::Binary),
+        DataType::Date32(DateUnit::Day) => Ok(protobuf::ArrowType::Date32),
+        DataType::Date64(DateUnit::Millisecond) => Ok(protobuf::ArrowType::Date64),
         other => Err(BallistaError::General(format!(
             "Unsupported data type {:?}",
             other
@@ -75,8 +164,15 @@ impl TryInto<protobuf::LogicalPlanNode> for LogicalPlan {
         match self {
             LogicalPlan::FileScan {
                 path,
+                paths,
+                files,
+                partition_columns,
+                file_type,
                 schema,
                 projection,
+                csv_options,
+                filters,
+                limit,
                 ..
             } => {
                 let mut node = empty_plan_node();
@@ -92,6 +188,49 @@ impl TryInto<protobuf::LogicalPlanNode> for LogicalPlan {
                     path: path.clone(),
                     projection: projected_field_names,
                     schema: Some(schema),
+                    file_type,
+                    csv_options: csv_options.map(|o| o.into()),
+                    files,
+                    partition_columns,
+                    filters: filters
+                        .into_iter()
+                        .map(|expr| expr.try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                    has_limit: limit.is_some(),
+                    limit: limit.unwrap_or_default() as u64,
+                    paths,
+                });
+                Ok(node)
+            }
+            // registered tables are referenced by name and must also be registered on the
+            // executor
+            LogicalPlan::TableScan {
+                table_name,
+                schema,
+                projection,
+                ..
+            } => {
+                let mut node = empty_plan_node();
+
+                let projected_field_names = match projection {
+                    Some(p) => p.iter().map(|i| schema.field(*i).name().clone()).collect(),
+                    _ => vec![],
+                };
+
+                let schema: protobuf::Schema = schema.to_owned().try_into()?;
+
+                node.scan = Some(protobuf::ScanNode {
+                    path: table_name,
+                    projection: projected_field_names,
+                    schema: Some(schema),
+                    file_type: "table".to_owned(),
+                    csv_options: None,
+                    files: vec![],
+                    partition_columns: vec![],
+                    filters: vec![],
+                    has_limit: false,
+                    limit: 0,
+                    paths: vec![],
                 });
                 Ok(node)
             }
@@ -137,7 +276,133 @@ impl TryInto<protobuf::LogicalPlanNode> for LogicalPlan {
                 });
                 Ok(node)
             }
-            _ => Err(BallistaError::NotImplemented(format!("{:?}", self))),
+            LogicalPlan::Limit { expr, input, .. } => {
+                let limit = match expr {
+                    Expr::Literal(ScalarValue::UInt64(n)) => n as u32,
+                    other => {
+                        return Err(BallistaError::NotImplemented(format!(
+                            "Limit must be a UInt64 literal but was {:?}",
+                            other
+                        )))
+                    }
+                };
+                let input: protobuf::LogicalPlanNode = input.as_ref().to_owned().try_into()?;
+                let mut node = empty_plan_node();
+                node.input = Some(Box::new(input));
+                node.limit = Some(protobuf::LimitNode { limit });
+                Ok(node)
+            }
+            LogicalPlan::Sort { expr, input, .. } => {
+                let input: protobuf::LogicalPlanNode = input.as_ref().to_owned().try_into()?;
+                let mut node = empty_plan_node();
+                node.input = Some(Box::new(input));
+                node.sort = Some(protobuf::SortNode {
+                    expr: expr
+                        .iter()
+                        .map(|expr| expr.to_owned().try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                });
+                Ok(node)
+            }
+            LogicalPlan::EmptyRelation { schema } => {
+                let mut node = empty_plan_node();
+                node.empty_relation = Some(protobuf::EmptyRelationNode {
+                    schema: Some(schema.try_into()?),
+                });
+                Ok(node)
+            }
+            LogicalPlan::MemoryScan(batches) => {
+                let mut buf = vec![];
+                {
+                    // the schema is written even when there are no batches
+                    let schema = match batches.first() {
+                        Some(first) => first.schema().as_ref().clone(),
+                        None => Schema::empty(),
+                    };
+                    let mut writer = StreamWriter::try_new(&mut buf, &schema)?;
+                    for batch in &batches {
+                        writer.write(batch)?;
+                    }
+                    writer.finish()?;
+                }
+                let mut node = empty_plan_node();
+                node.memory_scan = Some(protobuf::MemoryScanNode { batches: buf });
+                Ok(node)
+            }
+            LogicalPlan::ShuffleRead { locations, schema } => {
+                let mut node = empty_plan_node();
+                node.shuffle_read = Some(protobuf::ShuffleReadNode {
+                    locations: locations
+                        .iter()
+                        .map(|l| protobuf::ShuffleLocation {
+                            job_id: l.partition_id.job_id.clone(),
+                            stage_id: l.partition_id.stage_id as u32,
+                            map_partition: l.partition_id.map_partition as u32,
+                            output_partition: l.partition_id.output_partition as u32,
+                            host: l.host.clone(),
+                            port: l.port as u32,
+                        })
+                        .collect(),
+                    schema: Some(schema.try_into()?),
+                });
+                Ok(node)
+            }
+            LogicalPlan::Join {
+                left,
+                right,
+                on,
+                null_equals_null,
+                ..
+            } => {
+                let left: protobuf::LogicalPlanNode = left.as_ref().to_owned().try_into()?;
+                let right: protobuf::LogicalPlanNode = right.as_ref().to_owned().try_into()?;
+                let mut node = empty_plan_node();
+                node.input = Some(Box::new(left));
+                node.right = Some(Box::new(right));
+                node.join = Some(protobuf::JoinNode {
+                    left_columns: on.iter().map(|(l, _)| *l as u32).collect(),
+                    right_columns: on.iter().map(|(_, r)| *r as u32).collect(),
+                    null_equals_null,
+                });
+                Ok(node)
+            }
+            LogicalPlan::Union { inputs, schema } => {
+                let inputs = inputs
+                    .into_iter()
+                    .map(|input| input.try_into())
+                    .collect::<Result<Vec<protobuf::LogicalPlanNode>, BallistaError>>()?;
+                let mut node = empty_plan_node();
+                node.union = Some(protobuf::UnionNode {
+                    inputs,
+                    schema: Some(schema.try_into()?),
+                });
+                Ok(node)
+            }
+            LogicalPlan::Broadcast { input } => {
+                let input: protobuf::LogicalPlanNode = input.as_ref().to_owned().try_into()?;
+                let mut node = empty_plan_node();
+                node.input = Some(Box::new(input));
+                node.broadcast = Some(protobuf::BroadcastNode {});
+                Ok(node)
+            }
+            LogicalPlan::Extension { node: extension } => {
+                let inputs = extension
+                    .inputs()
+                    .into_iter()
+                    .map(|input| input.to_owned().try_into())
+                    .collect::<Result<Vec<protobuf::LogicalPlanNode>, BallistaError>>()?;
+                let mut node = empty_plan_node();
+                node.extension = Some(protobuf::ExtensionNode {
+                    name: extension.name().to_owned(),
+                    node: extension.encode()?,
+                    inputs,
+                });
+                Ok(node)
+            }
+            LogicalPlan::StageOutput { stage_id, .. } => Err(BallistaError::General(format!(
+                "The output of stage {} must be resolved before the plan is serialized",
+                stage_id
+            ))),
         }
     }
 }
@@ -159,10 +424,74 @@ impl TryInto<protobuf::LogicalExprNode> for Expr {
                 expr.column_name = name.clone();
                 Ok(expr)
             }
-            Expr::Literal(ScalarValue::Utf8(str)) => {
+            Expr::Literal(value) => {
                 let mut expr = empty_expr_node();
-                expr.has_literal_string = true;
-                expr.literal_string = str.clone();
+                match value {
+                    ScalarValue::Null => expr.has_literal_null = true,
+                    ScalarValue::Boolean(v) => {
+                        expr.has_literal_bool = true;
+                        expr.literal_bool = v;
+                    }
+                    ScalarValue::Utf8(v) => {
+                        expr.has_literal_string = true;
+                        expr.literal_string = v;
+                    }
+                    ScalarValue::Float32(v) => {
+                        set_literal_double(&mut expr, v as f64, &DataType::Float32)?
+                    }
+                    ScalarValue::Float64(v) => {
+                        set_literal_double(&mut expr, v, &DataType::Float64)?
+                    }
+                    ScalarValue::Int8(v) => set_literal_long(&mut expr, v as i64, &DataType::Int8)?,
+                    ScalarValue::Int16(v) => {
+                        set_literal_long(&mut expr, v as i64, &DataType::Int16)?
+                    }
+                    ScalarValue::Int32(v) => {
+                        set_literal_long(&mut expr, v as i64, &DataType::Int32)?
+                    }
+                    ScalarValue::Int64(v) => set_literal_long(&mut expr, v, &DataType::Int64)?,
+                    ScalarValue::UInt8(v) => {
+                        set_literal_long(&mut expr, v as i64, &DataType::UInt8)?
+                    }
+                    ScalarValue::UInt16(v) => {
+                        set_literal_long(&mut expr, v as i64, &DataType::UInt16)?
+                    }
+                    ScalarValue::UInt32(v) => {
+                        set_literal_long(&mut expr, v as i64, &DataType::UInt32)?
+                    }
+                    ScalarValue::UInt64(v) => {
+                        set_literal_long(&mut expr, v as i64, &DataType::UInt64)?
+                    }
+                    ScalarValue::Date32(v) => {
+                        set_literal_long(&mut expr, v as i64, &DataType::Date32(DateUnit::Day))?
+                    }
+                    ScalarValue::Date64(v) => {
+                        set_literal_long(&mut expr, v, &DataType::Date64(DateUnit::Millisecond))?
+                    }
+                    ScalarValue::Timestamp(v, unit) => {
+                        expr.has_literal_long = true;
+                        expr.literal_long = v;
+                        expr.literal_type = protobuf::ArrowType::Timestamp.into();
+                        expr.literal_time_unit = to_proto_time_unit(&unit).into();
+                    }
+                    ScalarValue::Decimal(v, precision, scale) => {
+                        expr.has_literal_string = true;
+                        expr.literal_string = v.to_string();
+                        expr.literal_type = protobuf::ArrowType::Decimal.into();
+                        expr.literal_precision = precision as u32;
+                        expr.literal_scale = scale as u32;
+                    }
+                    ScalarValue::Binary(v) => {
+                        expr.has_literal_binary = true;
+                        expr.literal_binary = v;
+                    }
+                    other => {
+                        return Err(BallistaError::NotImplemented(format!(
+                            "Literal {:?}",
+                            other
+                        )))
+                    }
+                }
                 Ok(expr)
             }
             Expr::BinaryExpr { left, op, right } => {
@@ -174,15 +503,75 @@ impl TryInto<protobuf::LogicalExprNode> for Expr {
                 }));
                 Ok(expr)
             }
-            Expr::AggregateFunction { name, args, .. } => {
+            Expr::Not(e) => {
+                let mut expr = empty_expr_node();
+                expr.not_expr = Some(Box::new(e.as_ref().to_owned().try_into()?));
+                Ok(expr)
+            }
+            Expr::IsNull(e) => {
+                let mut expr = empty_expr_node();
+                expr.is_null_expr = Some(Box::new(e.as_ref().to_owned().try_into()?));
+                Ok(expr)
+            }
+            Expr::IsNotNull(e) => {
+                let mut expr = empty_expr_node();
+                expr.is_not_null_expr = Some(Box::new(e.as_ref().to_owned().try_into()?));
+                Ok(expr)
+            }
+            Expr::Alias(e, alias) => {
+                let mut expr = empty_expr_node();
+                expr.alias = Some(Box::new(protobuf::AliasNode {
+                    expr: Some(Box::new(e.as_ref().to_owned().try_into()?)),
+                    alias,
+                }));
+                Ok(expr)
+            }
+            Expr::Cast { expr: e, data_type } => {
+                let mut expr = empty_expr_node();
+                expr.cast = Some(Box::new(protobuf::CastNode {
+                    expr: Some(Box::new(e.as_ref().to_owned().try_into()?)),
+                    arrow_type: to_proto_arrow_type(&data_type)?.into(),
+                }));
+                Ok(expr)
+            }
+            Expr::Sort { expr: e, asc } => {
+                let mut expr = empty_expr_node();
+                expr.sort = Some(Box::new(protobuf::SortExprNode {
+                    expr: Some(Box::new(e.as_ref().to_owned().try_into()?)),
+                    asc,
+                }));
+                Ok(expr)
+            }
+            Expr::ScalarFunction {
+                name,
+                args,
+                return_type,
+            } => {
+                let mut expr = empty_expr_node();
+                expr.scalar_function = Some(protobuf::ScalarFunctionNode {
+                    name,
+                    args: args
+                        .iter()
+                        .map(|e| e.to_owned().try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                    return_type: to_proto_arrow_type(&return_type)?.into(),
+                });
+                Ok(expr)
+            }
+            Expr::AggregateFunction {
+                name,
+                args,
+                return_type,
+            } => {
                 let mut expr = empty_expr_node();
 
                 let aggr_function = match name.as_str() {
-                    "MIN" => Ok(0),   // TODO use protobuf enum
-                    "MAX" => Ok(1),   // TODO use protobuf enum
-                    "SUM" => Ok(2),   // TODO use protobuf enum
-                    "AVG" => Ok(3),   // TODO use protobuf enum
-                    "COUNT" => Ok(4), // TODO use protobuf enum
+                    "MIN" => Ok(protobuf::AggregateFunction::Min),
+                    "MAX" => Ok(protobuf::AggregateFunction::Max),
+                    "SUM" => Ok(protobuf::AggregateFunction::Sum),
+                    "AVG" => Ok(protobuf::AggregateFunction::Avg),
+                    "COUNT" => Ok(protobuf::AggregateFunction::Count),
+                    "COUNT_DISTINCT" => Ok(protobuf::AggregateFunction::CountDistinct),
                     other => Err(BallistaError::NotImplemented(format!(
                         "Aggregate function {:?}",
                         other
@@ -190,16 +579,103 @@ impl TryInto<protobuf::LogicalExprNode> for Expr {
                 }?;
 
                 expr.aggregate_expr = Some(Box::new(protobuf::AggregateExprNode {
-                    aggr_function,
+                    aggr_function: aggr_function.into(),
                     expr: Some(Box::new(args[0].clone().try_into()?)),
+                    return_type: to_proto_arrow_type(&return_type)?.into(),
                 }));
                 Ok(expr)
             }
-            _ => Err(BallistaError::NotImplemented(format!("{:?}", self))),
+            Expr::Wildcard => {
+                let mut expr = empty_expr_node();
+                expr.wildcard = true;
+                Ok(expr)
+            }
+            Expr::GetField { expr: e, name } => {
+                let mut expr = empty_expr_node();
+                expr.get_field = Some(Box::new(protobuf::GetFieldNode {
+                    expr: Some(Box::new(e.as_ref().to_owned().try_into()?)),
+                    name,
+                }));
+                Ok(expr)
+            }
+            Expr::GetIndex { expr: e, index } => {
+                let mut expr = empty_expr_node();
+                expr.get_index = Some(Box::new(protobuf::GetIndexNode {
+                    expr: Some(Box::new(e.as_ref().to_owned().try_into()?)),
+                    index: index as u64,
+                }));
+                Ok(expr)
+            }
+        }
+    }
+}
+
+fn to_proto_time_unit(unit: &TimeUnit) -> protobuf::TimeUnit {
+    match unit {
+        TimeUnit::Second => protobuf::TimeUnit::Second,
+        TimeUnit::Millisecond => protobuf::TimeUnit::Millisecond,
+        TimeUnit::Microsecond => protobuf::TimeUnit::Microsecond,
+        TimeUnit::Nanosecond => protobuf::TimeUnit::Nanosecond,
+    }
+}
+
+fn set_literal_long(
+    expr: &mut protobuf::LogicalExprNode,
+    value: i64,
+    data_type: &DataType,
+) -> Result<(), BallistaError> {
+    expr.has_literal_long = true;
+    expr.literal_long = value;
+    expr.literal_type = to_proto_arrow_type(data_type)?.into();
+    Ok(())
+}
+
+fn set_literal_double(
+    expr: &mut protobuf::LogicalExprNode,
+    value: f64,
+    data_type: &DataType,
+) -> Result<(), BallistaError> {
+    expr.has_literal_double = true;
+    expr.literal_double = value;
+    expr.literal_type = to_proto_arrow_type(data_type)?.into();
+    Ok(())
+}
+
+impl Into<protobuf::CsvOptions> for CsvReadOptions {
+    fn into(self) -> protobuf::CsvOptions {
+        protobuf::CsvOptions {
+            delimiter: char_to_string(Some(self.delimiter)),
+            quote: char_to_string(Some(self.quote)),
+            escape: char_to_string(self.escape),
+            comment: char_to_string(self.comment),
+            delimiter_byte: char_to_byte(Some(self.delimiter)),
+            quote_byte: char_to_byte(Some(self.quote)),
+            escape_byte: char_to_byte(self.escape),
+            comment_byte: char_to_byte(self.comment),
+            null_values: self.null_values,
+            date_format: self.date_format.unwrap_or_default(),
+            timestamp_format: self.timestamp_format.unwrap_or_default(),
+            timezone: self.timezone.unwrap_or_default(),
+            compression: self
+                .compression
+                .map(|c| c.name().to_owned())
+                .unwrap_or_default(),
+            partitions: self.partitions.unwrap_or_default() as u32,
+            no_header: !self.has_header,
         }
     }
 }
 
+fn char_to_string(c: Option<u8>) -> String {
+    c.filter(|c| c.is_ascii())
+        .map(|c| (c as char).to_string())
+        .unwrap_or_default()
+}
+
+fn char_to_byte(c: Option<u8>) -> u32 {
+    c.map(|c| c as u32 + 1).unwrap_or_default()
+}
+
 /// Create an empty ExprNode
 fn empty_expr_node() -> protobuf::LogicalExprNode {
     protobuf::LogicalExprNode {
@@ -211,10 +687,29 @@ fn empty_expr_node() -> protobuf::LogicalExprNode {
         has_literal_double: false,
         literal_long: 0,
         has_literal_long: false,
+        literal_bool: false,
+        has_literal_bool: false,
+        has_literal_null: false,
+        literal_binary: vec![],
+        has_literal_binary: false,
+        literal_type: 0,
+        literal_time_unit: 0,
+        literal_precision: 0,
+        literal_scale: 0,
         column_index: 0,
         has_column_index: false,
         binary_expr: None,
         aggregate_expr: None,
+        not_expr: None,
+        is_null_expr: None,
+        is_not_null_expr: None,
+        alias: None,
+        cast: None,
+        sort: None,
+        scalar_function: None,
+        wildcard: false,
+        get_field: None,
+        get_index: None,
     }
 }
 
@@ -223,9 +718,18 @@ fn empty_plan_node() -> protobuf::LogicalPlanNode {
     protobuf::LogicalPlanNode {
         scan: None,
         input: None,
+        right: None,
         projection: None,
         selection: None,
         limit: None,
         aggregate: None,
+        sort: None,
+        empty_relation: None,
+        memory_scan: None,
+        shuffle_read: None,
+        join: None,
+        broadcast: None,
+        extension: None,
+        union: None,
     }
 }
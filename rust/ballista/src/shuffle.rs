@@ -0,0 +1,557 @@
+//! Shuffles between the stages of a distributed query.
+//!
+//! A task whose output is read by a later stage writes its output to the executor's
+//! shuffle directory as a set of partitions, assigning rows to partitions by hashing the
+//! values of key columns. Each task of the next stage reads one partition, fetching it from
+//! every executor that ran a task of the earlier stage with a `DoGet` ticket that identifies
+//! the partition. The shuffle files of a job are removed when the job completes or is
+//! cancelled.
+//!
+//! Shuffle partitions are written to local disk as they are produced, and can be spread
+//! across several directories, such as one on each disk of the executor. Partitions that a
+//! task fetches from other executors are also written to local disk, and read from there as
+//! the task executes, so that shuffles do not need to fit in the memory of the executors.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::dictionary::{self, DictionaryFileReader, DictionaryFileWriter};
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::LogicalPlan;
+use crate::memory::batch_memory_size;
+
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the `DoGet` tickets used to fetch shuffle partitions
+pub const SHUFFLE_TICKET_PREFIX: &str = "ballista.shuffle/";
+
+/// How the output of a task is split into shuffle partitions
+#[derive(Debug, Clone, PartialEq)]
+pub enum Partitioning {
+    /// All rows are written to a single partition
+    Single,
+    /// Rows are assigned to partitions by hashing the values of the given columns, so that
+    /// rows with the same values are in the same partition
+    Hash {
+        columns: Vec<usize>,
+        partitions: usize,
+    },
+}
+
+impl Partitioning {
+    /// Number of partitions that the output is split into
+    pub fn partition_count(&self) -> usize {
+        match self {
+            Partitioning::Single => 1,
+            Partitioning::Hash { partitions, .. } => *partitions,
+        }
+    }
+}
+
+/// Identifies a shuffle partition written by a task
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ShufflePartitionId {
+    pub job_id: String,
+    pub stage_id: usize,
+    /// The partition of the stage that the task executed
+    pub map_partition: usize,
+    /// The partition of the task's output
+    pub output_partition: usize,
+}
+
+impl ShufflePartitionId {
+    /// The `DoGet` ticket for fetching the partition
+    pub fn ticket(&self) -> Vec<u8> {
+        format!(
+            "{}{}/{}/{}/{}",
+            SHUFFLE_TICKET_PREFIX,
+            self.job_id,
+            self.stage_id,
+            self.map_partition,
+            self.output_partition
+        )
+        .into_bytes()
+    }
+
+    /// Parse a ticket created by `ticket()`, returning `None` for other tickets
+    pub fn from_ticket(ticket: &[u8]) -> Option<Self> {
+        let ticket = std::str::from_utf8(ticket).ok()?;
+        if !ticket.starts_with(SHUFFLE_TICKET_PREFIX) {
+            return None;
+        }
+        let parts: Vec<&str> = ticket[SHUFFLE_TICKET_PREFIX.len()..].split('/').collect();
+        match parts.as_slice() {
+            [job_id, stage_id, map_partition, output_partition] if valid_job_id(job_id) => {
+                Some(Self {
+                    job_id: (*job_id).to_owned(),
+                    stage_id: stage_id.parse().ok()?,
+                    map_partition: map_partition.parse().ok()?,
+                    output_partition: output_partition.parse().ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The file that the partition is written to
+    pub fn path(&self, shuffle_dir: &Path) -> PathBuf {
+        shuffle_dir
+            .join(&self.job_id)
+            .join(self.stage_id.to_string())
+            .join(self.map_partition.to_string())
+            .join(format!("{}.arrow", self.output_partition))
+    }
+}
+
+/// An executor that a shuffle partition can be fetched from
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ShuffleLocation {
+    pub partition_id: ShufflePartitionId,
+    pub host: String,
+    pub port: usize,
+}
+
+/// The local directories that an executor writes shuffle partitions to. The partitions
+/// written by a task, and the partitions that a task fetches, are written to one of the
+/// directories, so that the shuffles of a job are spread across all of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShuffleDirs {
+    dirs: Vec<PathBuf>,
+}
+
+impl ShuffleDirs {
+    /// Use the given directories, or the default directory if there are none
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        if dirs.is_empty() {
+            Self::default()
+        } else {
+            Self { dirs }
+        }
+    }
+
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// The directory for the partitions written by a task, chosen by hashing the task
+    fn dir(&self, job_id: &str, stage_id: usize, partition: usize) -> &Path {
+        let hash = hash_value((job_id, stage_id, partition));
+        &self.dirs[(hash % self.dirs.len() as u64) as usize]
+    }
+
+    /// The file that a shuffle partition written by this executor is stored in
+    pub fn path(&self, partition_id: &ShufflePartitionId) -> PathBuf {
+        let dir = self.dir(
+            &partition_id.job_id,
+            partition_id.stage_id,
+            partition_id.map_partition,
+        );
+        partition_id.path(dir)
+    }
+
+    /// The file that a shuffle partition fetched from another executor is stored in while a
+    /// task reads it. Each fetch uses a different directory, so that concurrent attempts of
+    /// a task do not share files, and fetched partitions are removed with the job.
+    pub fn fetch_path(&self, partition_id: &ShufflePartitionId, fetch: usize) -> PathBuf {
+        self.dirs[fetch % self.dirs.len()]
+            .join(&partition_id.job_id)
+            .join("fetched")
+            .join(fetch.to_string())
+            .join(format!(
+                "{}-{}-{}.arrow",
+                partition_id.stage_id, partition_id.map_partition, partition_id.output_partition
+            ))
+    }
+
+    /// Remove the shuffle partitions written and fetched for a job from all of the
+    /// directories
+    pub fn remove_job(&self, job_id: &str) -> Result<()> {
+        if !valid_job_id(job_id) {
+            return Err(ballista_error(&format!("Invalid job id '{}'", job_id)));
+        }
+        for dir in &self.dirs {
+            let dir = dir.join(job_id);
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for ShuffleDirs {
+    fn default() -> Self {
+        Self {
+            dirs: vec![default_shuffle_dir()],
+        }
+    }
+}
+
+/// Job ids are used as directory names, so they cannot contain path separators
+pub fn valid_job_id(job_id: &str) -> bool {
+    !job_id.is_empty()
+        && job_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Directory that executors write shuffle partitions to unless another is configured
+pub fn default_shuffle_dir() -> PathBuf {
+    std::env::temp_dir().join("ballista-shuffle")
+}
+
+/// Writes the output of a task as shuffle partitions
+pub struct ShuffleWriter {
+    partitioning: Partitioning,
+    writers: Vec<DictionaryFileWriter>,
+    num_rows: Vec<usize>,
+    num_bytes: Vec<usize>,
+}
+
+impl ShuffleWriter {
+    /// Create the files for the partitions of a task's output
+    pub fn try_new(
+        shuffle_dirs: &ShuffleDirs,
+        job_id: &str,
+        stage_id: usize,
+        map_partition: usize,
+        partitioning: &Partitioning,
+        schema: &Schema,
+    ) -> Result<Self> {
+        if !valid_job_id(job_id) {
+            return Err(ballista_error(&format!("Invalid job id '{}'", job_id)));
+        }
+        let writers = (0..partitioning.partition_count())
+            .map(|output_partition| {
+                let path = shuffle_dirs.path(&ShufflePartitionId {
+                    job_id: job_id.to_owned(),
+                    stage_id,
+                    map_partition,
+                    output_partition,
+                });
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                DictionaryFileWriter::try_new(&path, Arc::new(schema.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            partitioning: partitioning.clone(),
+            num_rows: vec![0; writers.len()],
+            num_bytes: vec![0; writers.len()],
+            writers,
+        })
+    }
+
+    /// Split a batch into partitions and append them to the partition files
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let partitions = match &self.partitioning {
+            Partitioning::Single => vec![Some(batch.clone())],
+            Partitioning::Hash {
+                columns,
+                partitions,
+            } => hash_partition(batch, columns, *partitions)?,
+        };
+        for (i, partition) in partitions.into_iter().enumerate() {
+            if let Some(partition) = partition {
+                self.num_rows[i] += partition.num_rows();
+                self.num_bytes[i] += batch_memory_size(&partition);
+                self.writers[i].write(&partition)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish writing the partitions, returning a batch with the number of rows in each and
+    /// the size of their batches in memory
+    pub fn finish(mut self) -> Result<RecordBatch> {
+        for writer in &mut self.writers {
+            writer.finish()?;
+        }
+        let partitions: Vec<u32> = (0..self.num_rows.len() as u32).collect();
+        let num_rows: Vec<u64> = self.num_rows.iter().map(|n| *n as u64).collect();
+        let num_bytes: Vec<u64> = self.num_bytes.iter().map(|n| *n as u64).collect();
+        Ok(RecordBatch::try_new(
+            Arc::new(summary_schema()),
+            vec![
+                Arc::new(UInt32Array::from(partitions)),
+                Arc::new(UInt64Array::from(num_rows)),
+                Arc::new(UInt64Array::from(num_bytes)),
+            ],
+        )?)
+    }
+}
+
+/// Schema of the batch returned by `ShuffleWriter::finish`
+pub fn summary_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("partition", DataType::UInt32, false),
+        Field::new("num_rows", DataType::UInt64, false),
+        Field::new("num_bytes", DataType::UInt64, false),
+    ])
+}
+
+/// The size of each shuffle partition of a stage, added up across the tasks of the stage
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ShuffleSummary {
+    pub num_rows: Vec<u64>,
+    pub num_bytes: Vec<u64>,
+}
+
+impl ShuffleSummary {
+    /// Read the summary returned by a task whose output was shuffled
+    pub fn from_batches(batches: &[RecordBatch]) -> Result<Self> {
+        let mut summary = Self::default();
+        for batch in batches {
+            if batch.schema().as_ref() != &summary_schema() {
+                return Err(ballista_error(&format!(
+                    "Unexpected schema for shuffle summary: {:?}",
+                    batch.schema()
+                )));
+            }
+            let column = |i: usize| batch.column(i).as_any();
+            let partitions = column(0).downcast_ref::<UInt32Array>().unwrap();
+            let num_rows = column(1).downcast_ref::<UInt64Array>().unwrap();
+            let num_bytes = column(2).downcast_ref::<UInt64Array>().unwrap();
+            for row in 0..batch.num_rows() {
+                let partition = partitions.value(row) as usize;
+                if summary.num_rows.len() <= partition {
+                    summary.num_rows.resize(partition + 1, 0);
+                    summary.num_bytes.resize(partition + 1, 0);
+                }
+                summary.num_rows[partition] += num_rows.value(row);
+                summary.num_bytes[partition] += num_bytes.value(row);
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Add the partitions written by another task of the stage
+    pub fn merge(&mut self, other: &ShuffleSummary) {
+        let len = self.num_rows.len().max(other.num_rows.len());
+        self.num_rows.resize(len, 0);
+        self.num_bytes.resize(len, 0);
+        for (i, (rows, bytes)) in other.num_rows.iter().zip(&other.num_bytes).enumerate() {
+            self.num_rows[i] += rows;
+            self.num_bytes[i] += bytes;
+        }
+    }
+
+    /// Total size of the partitions in bytes
+    pub fn total_bytes(&self) -> u64 {
+        self.num_bytes.iter().sum()
+    }
+}
+
+/// Open a shuffle partition that was written by this executor
+pub fn read_shuffle_partition(
+    shuffle_dirs: &ShuffleDirs,
+    partition_id: &ShufflePartitionId,
+) -> Result<DictionaryFileReader> {
+    let path = shuffle_dirs.path(partition_id);
+    DictionaryFileReader::try_new(&path).map_err(|e| {
+        ballista_error(&format!(
+            "Shuffle partition {:?} is not available: {:?}",
+            partition_id, e
+        ))
+    })
+}
+
+/// Split a batch into partitions by hashing the values of the given columns. Partitions
+/// that have no rows are `None`.
+pub fn hash_partition(
+    batch: &RecordBatch,
+    columns: &[usize],
+    partitions: usize,
+) -> Result<Vec<Option<RecordBatch>>> {
+    if partitions == 0 {
+        return Err(ballista_error("Cannot hash partition into zero partitions"));
+    }
+    let mut hashes = vec![0_u64; batch.num_rows()];
+    for column in columns {
+        hash_column(batch.column(*column), &mut hashes)?;
+    }
+
+    let mut indices: Vec<Vec<u32>> = vec![vec![]; partitions];
+    for (row, hash) in hashes.iter().enumerate() {
+        indices[(*hash % partitions as u64) as usize].push(row as u32);
+    }
+
+    let mut output = Vec::with_capacity(partitions);
+    for indices in indices {
+        if indices.is_empty() {
+            output.push(None);
+            continue;
+        }
+        let indices = UInt32Array::from(indices);
+        let mut columns = Vec::with_capacity(batch.num_columns());
+        for column in batch.columns() {
+            columns.push(dictionary::take(column, &indices)?);
+        }
+        output.push(Some(RecordBatch::try_new(batch.schema(), columns)?));
+    }
+    Ok(output)
+}
+
+macro_rules! hash_values {
+    ($column:expr, $array_type:ident, $hashes:expr, $value:expr) => {{
+        let array = $column.as_any().downcast_ref::<$array_type>().unwrap();
+        for (row, hash) in $hashes.iter_mut().enumerate() {
+            // nulls are all assigned to the same partition
+            let value = if array.is_null(row) {
+                0
+            } else {
+                hash_value($value(array.value(row)))
+            };
+            *hash = hash.wrapping_mul(31).wrapping_add(value);
+        }
+    }};
+}
+
+/// Combine the hashes of the values in a column with the hashes of earlier columns
+fn hash_column(column: &ArrayRef, hashes: &mut [u64]) -> Result<()> {
+    match column.data_type() {
+        DataType::Boolean => hash_values!(column, BooleanArray, hashes, |v| v),
+        DataType::Int8 => hash_values!(column, Int8Array, hashes, |v| v),
+        DataType::Int16 => hash_values!(column, Int16Array, hashes, |v| v),
+        DataType::Int32 => hash_values!(column, Int32Array, hashes, |v| v),
+        DataType::Int64 => hash_values!(column, Int64Array, hashes, |v| v),
+        DataType::UInt8 => hash_values!(column, UInt8Array, hashes, |v| v),
+        DataType::UInt16 => hash_values!(column, UInt16Array, hashes, |v| v),
+        DataType::UInt32 => hash_values!(column, UInt32Array, hashes, |v| v),
+        DataType::UInt64 => hash_values!(column, UInt64Array, hashes, |v| v),
+        DataType::Float32 => hash_values!(column, Float32Array, hashes, |v: f32| v.to_bits()),
+        DataType::Float64 => hash_values!(column, Float64Array, hashes, |v: f64| v.to_bits()),
+        DataType::Utf8 => hash_values!(column, StringArray, hashes, |v| v),
+        DataType::Dictionary(_, _) => {
+            // each value of the dictionary is hashed once, and rows hash the same as the
+            // rows of columns that are not dictionary encoded
+            let values = dictionary::values(column);
+            let mut value_hashes = vec![0_u64; values.len()];
+            hash_column(&values, &mut value_hashes)?;
+            let keys = dictionary::key_indices(column)?;
+            for (hash, key) in hashes.iter_mut().zip(keys) {
+                let value = key.map(|key| value_hashes[key]).unwrap_or(0);
+                *hash = hash.wrapping_mul(31).wrapping_add(value);
+            }
+        }
+        other => {
+            return Err(ballista_error(&format!(
+                "Cannot hash partition on a column of type {:?}",
+                other
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Hash a value the same way in every executor, which uses the same keys for each hasher
+fn hash_value<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The shuffle partitions that a plan reads
+pub fn shuffle_locations(plan: &LogicalPlan) -> Vec<ShuffleLocation> {
+    match plan {
+        LogicalPlan::ShuffleRead { locations, .. } => locations.clone(),
+        other => other
+            .inputs()
+            .into_iter()
+            .flat_map(shuffle_locations)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::record_batch::RecordBatchReader;
+
+    #[test]
+    fn write_and_read_partitions() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Int64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b", "a", "c", "b", "a"])),
+                Arc::new(Int64Array::from(vec![1, 2, 3, 4, 5, 6])),
+            ],
+        )?;
+
+        let dir = std::env::temp_dir().join("ballista-shuffle-test");
+        let other_dir = std::env::temp_dir().join("ballista-shuffle-test-2");
+        let dirs = ShuffleDirs::new(vec![dir.clone(), other_dir.clone()]);
+        let partitioning = Partitioning::Hash {
+            columns: vec![0],
+            partitions: 2,
+        };
+        let mut writer = ShuffleWriter::try_new(&dirs, "job-1", 0, 3, &partitioning, &schema)?;
+        writer.write(&batch)?;
+        let summary = writer.finish()?;
+        assert_eq!(2, summary.num_rows());
+        let mut sizes = ShuffleSummary::from_batches(&[summary.clone()])?;
+        sizes.merge(&ShuffleSummary::from_batches(&[summary])?);
+        assert_eq!(2, sizes.num_rows.len());
+        assert!(sizes.total_bytes() > 0);
+
+        // every key is in exactly one partition
+        let mut keys: Vec<Vec<String>> = vec![];
+        for output_partition in 0..2 {
+            let id = ShufflePartitionId {
+                job_id: "job-1".to_owned(),
+                stage_id: 0,
+                map_partition: 3,
+                output_partition,
+            };
+            assert_eq!(
+                Some(id.clone()),
+                ShufflePartitionId::from_ticket(&id.ticket())
+            );
+            assert!(dirs.path(&id).starts_with(&dir) || dirs.path(&id).starts_with(&other_dir));
+            let mut reader = read_shuffle_partition(&dirs, &id)?;
+            let mut partition_keys = vec![];
+            while let Some(batch) = reader.next_batch()? {
+                let array = batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .unwrap();
+                partition_keys.extend((0..array.len()).map(|i| array.value(i).to_owned()));
+            }
+            keys.push(partition_keys);
+        }
+        assert_eq!(6, keys.iter().map(|k| k.len()).sum::<usize>());
+        for key in &["a", "b", "c"] {
+            let key = key.to_string();
+            assert_eq!(1, keys.iter().filter(|k| k.contains(&key)).count());
+        }
+
+        // fetched partitions are removed with the partitions written for the job
+        let id = ShufflePartitionId {
+            job_id: "job-1".to_owned(),
+            stage_id: 0,
+            map_partition: 3,
+            output_partition: 0,
+        };
+        let fetched = dirs.fetch_path(&id, 1);
+        fs::create_dir_all(fetched.parent().unwrap())?;
+        fs::write(&fetched, b"")?;
+        dirs.remove_job("job-1")?;
+        assert!(!fetched.exists());
+        assert!(!dir.join("job-1").exists());
+        assert!(!other_dir.join("job-1").exists());
+        assert!(dirs.remove_job("../job-1").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,537 @@
+//! External merge sort.
+//!
+//! DataFusion cannot sort yet, so sorts are executed here. The batches of the input are
+//! inserted into the sorter as the plan of the input is executed, and are buffered until
+//! their memory exceeds the budget, which is set with `ballista.sort.memoryLimit`, and
+//! the buffered rows are then sorted into a run that is spilled to an Arrow IPC file.
+//! Once the input has been read, the runs are merged as the output is scanned, holding one
+//! batch of each run in memory at a time, so sorts work on inputs that are larger than
+//! memory. Sorts whose input fits in the budget are sorted in memory. Nulls sort after
+//! other values in ascending order and before them in descending order.
+
+use std::cmp::Ordering;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::array::{ArrayRef, UInt32Array};
+use crate::arrow::datatypes::{Schema, SchemaRef};
+use crate::arrow::error::{ArrowError, Result as ArrowResult};
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datafusion::datasource::{MemTable, TableProvider};
+use crate::datafusion::error::{ExecutionError, Result as DataFusionResult};
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::ipc::ProjectedReader;
+use crate::dictionary::{self, DictionaryFileReader, DictionaryFileWriter};
+use crate::error::{ballista_error, Result};
+use crate::join::{column_values, compare_values, KeyValue};
+use crate::logicalplan::Expr;
+use crate::memory::batch_memory_size;
+
+/// Bytes of memory that a sort may buffer its input in before spilling a sorted run
+pub const SORT_MEMORY_LIMIT: &str = "ballista.sort.memoryLimit";
+
+/// The memory budget of sorts when it is not configured
+pub const DEFAULT_SORT_MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
+/// Used to give spilled runs unique names
+static NEXT_RUN_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A column that the rows are sorted by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub column: usize,
+    pub asc: bool,
+}
+
+/// The keys of sort expressions over an input with the given schema
+pub fn sort_keys(expr: &[Expr], schema: &Schema) -> Result<Vec<SortKey>> {
+    fn column(expr: &Expr, schema: &Schema) -> Result<usize> {
+        match expr {
+            Expr::Column(i) => Ok(*i),
+            Expr::UnresolvedColumn(name) => Ok(schema.index_of(name)?),
+            Expr::Alias(expr, _) => column(expr, schema),
+            other => Err(ballista_error(&format!("Cannot sort by {:?}", other))),
+        }
+    }
+    expr.iter()
+        .map(|expr| match expr {
+            Expr::Sort { expr, asc } => Ok(SortKey {
+                column: column(expr, schema)?,
+                asc: *asc,
+            }),
+            expr => Ok(SortKey {
+                column: column(expr, schema)?,
+                asc: true,
+            }),
+        })
+        .collect()
+}
+
+/// Sorts batches in runs that fit in the memory budget, spilling the runs to disk
+pub struct ExternalSorter {
+    schema: SchemaRef,
+    keys: Vec<SortKey>,
+    memory_limit: usize,
+    batch_size: usize,
+    buffered: Vec<RecordBatch>,
+    /// Bytes of memory used by the buffered batches
+    memory: usize,
+    runs: Vec<PathBuf>,
+}
+
+impl ExternalSorter {
+    pub fn new(
+        schema: &Schema,
+        keys: Vec<SortKey>,
+        memory_limit: usize,
+        batch_size: usize,
+    ) -> Self {
+        Self {
+            schema: Arc::new(schema.clone()),
+            keys,
+            memory_limit,
+            batch_size,
+            buffered: vec![],
+            memory: 0,
+            runs: vec![],
+        }
+    }
+
+    /// Number of sorted runs that were spilled to disk
+    pub fn spills(&self) -> u64 {
+        self.runs.len() as u64
+    }
+
+    /// Add a batch of the input, spilling the buffered rows as a sorted run if they no
+    /// longer fit in memory
+    pub fn insert(&mut self, batch: RecordBatch) -> Result<()> {
+        self.memory += batch_memory_size(&batch);
+        self.buffered.push(batch);
+        if self.memory > self.memory_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// The sorted rows, as a table that merges the spilled runs as it is scanned
+    pub fn finish(mut self) -> Result<Box<dyn TableProvider + Send + Sync>> {
+        if self.runs.is_empty() {
+            let run = sort_run(&self.buffered, &self.keys, &self.schema, self.batch_size)?;
+            return Ok(Box::new(MemTable::new(self.schema.clone(), vec![run])?));
+        }
+        if !self.buffered.is_empty() {
+            self.spill()?;
+        }
+        Ok(Box::new(SortedTable {
+            schema: self.schema.clone(),
+            keys: self.keys.clone(),
+            runs: Arc::new(SpilledRuns(mem::take(&mut self.runs))),
+            batch_size: self.batch_size,
+        }))
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        let batches = mem::take(&mut self.buffered);
+        self.memory = 0;
+        let run = sort_run(&batches, &self.keys, &self.schema, self.batch_size)?;
+        let id = NEXT_RUN_ID.fetch_add(1, atomic::Ordering::SeqCst);
+        let path =
+            std::env::temp_dir().join(format!("ballista-sort-{}-{}.arrow", std::process::id(), id));
+        // the run is recorded first so that it is removed even if writing it fails
+        self.runs.push(path.clone());
+        let mut writer = DictionaryFileWriter::try_new(&path, self.schema.clone())?;
+        for batch in &run {
+            writer.write(batch)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        // runs that were handed to a table are removed by the table
+        drop(SpilledRuns(mem::take(&mut self.runs)));
+    }
+}
+
+/// Sort rows into batches of at most `batch_size` rows
+fn sort_run(
+    batches: &[RecordBatch],
+    keys: &[SortKey],
+    schema: &SchemaRef,
+    batch_size: usize,
+) -> Result<Vec<RecordBatch>> {
+    let batches: Vec<&RecordBatch> = batches.iter().filter(|b| b.num_rows() > 0).collect();
+    if batches.is_empty() {
+        return Ok(vec![]);
+    }
+    let columns = (0..schema.fields().len())
+        .map(|i| {
+            let arrays: Vec<ArrayRef> = batches.iter().map(|b| b.column(i).clone()).collect();
+            dictionary::concat(&arrays)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    let values = key_values(&batch, keys)?;
+
+    let mut indices: Vec<usize> = (0..batch.num_rows()).collect();
+    indices.sort_by(|a, b| compare_rows(keys, &values, *a, &values, *b));
+    indices
+        .chunks(batch_size)
+        .map(|chunk| {
+            let chunk = UInt32Array::from(chunk.iter().map(|i| *i as u32).collect::<Vec<_>>());
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|column| dictionary::take(column, &chunk))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(RecordBatch::try_new(schema.clone(), columns)?)
+        })
+        .collect()
+}
+
+/// The values of the key columns of a batch
+fn key_values(batch: &RecordBatch, keys: &[SortKey]) -> Result<Vec<Vec<Option<KeyValue>>>> {
+    keys.iter()
+        .map(|key| {
+            let column = batch.column(key.column);
+            column_values(column).ok_or_else(|| {
+                ballista_error(&format!(
+                    "Cannot sort by a column of type {:?}",
+                    column.data_type()
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Compare a row of a batch with a row of another batch, given the values of their keys
+fn compare_rows(
+    keys: &[SortKey],
+    a: &[Vec<Option<KeyValue>>],
+    a_row: usize,
+    b: &[Vec<Option<KeyValue>>],
+    b_row: usize,
+) -> Ordering {
+    for (i, key) in keys.iter().enumerate() {
+        let ordering = match (&a[i][a_row], &b[i][b_row]) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(a), Some(b)) => compare_values(a, b),
+        };
+        let ordering = if key.asc {
+            ordering
+        } else {
+            ordering.reverse()
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Sorted runs that were spilled to disk, which are removed when they are dropped
+struct SpilledRuns(Vec<PathBuf>);
+
+impl Drop for SpilledRuns {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = dictionary::remove_file(path);
+        }
+    }
+}
+
+/// The output of a sort that spilled runs to disk, with a single partition that merges
+/// the runs
+struct SortedTable {
+    schema: SchemaRef,
+    keys: Vec<SortKey>,
+    runs: Arc<SpilledRuns>,
+    batch_size: usize,
+}
+
+impl TableProvider for SortedTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let projected_schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok(vec![Arc::new(SortedPartition {
+            schema: self.schema.clone(),
+            keys: self.keys.clone(),
+            runs: self.runs.clone(),
+            batch_size: self.batch_size,
+            projection,
+            projected_schema,
+        })])
+    }
+}
+
+struct SortedPartition {
+    schema: SchemaRef,
+    keys: Vec<SortKey>,
+    runs: Arc<SpilledRuns>,
+    batch_size: usize,
+    projection: Vec<usize>,
+    projected_schema: SchemaRef,
+}
+
+impl Partition for SortedPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let mut cursors = vec![];
+        for path in &self.runs.0 {
+            let cursor = DictionaryFileReader::try_new(path)
+                .and_then(|reader| RunCursor::try_new(reader, &self.keys))
+                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+            cursors.push(cursor);
+        }
+        let merge = MergeReader {
+            schema: self.schema.clone(),
+            keys: self.keys.clone(),
+            cursors,
+            batch_size: self.batch_size,
+            _runs: self.runs.clone(),
+        };
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            Box::new(merge),
+            self.projection.clone(),
+            self.projected_schema.clone(),
+        ))))
+    }
+}
+
+/// The position of a merge in a sorted run
+struct RunCursor {
+    reader: DictionaryFileReader,
+    /// The current batch, or `None` once the run has been read
+    batch: Option<RecordBatch>,
+    /// Counts the batches of the run, to tell them apart
+    batch_id: usize,
+    values: Vec<Vec<Option<KeyValue>>>,
+    row: usize,
+}
+
+impl RunCursor {
+    fn try_new(reader: DictionaryFileReader, keys: &[SortKey]) -> Result<Self> {
+        let mut cursor = Self {
+            reader,
+            batch: None,
+            batch_id: 0,
+            values: vec![],
+            row: 0,
+        };
+        cursor.read_batch(keys)?;
+        Ok(cursor)
+    }
+
+    /// Move to the next row, reading the next batch of the run once the current batch is
+    /// exhausted
+    fn advance(&mut self, keys: &[SortKey]) -> Result<()> {
+        self.row += 1;
+        match &self.batch {
+            Some(batch) if self.row >= batch.num_rows() => self.read_batch(keys),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the next batch of the run that has rows
+    fn read_batch(&mut self, keys: &[SortKey]) -> Result<()> {
+        self.batch = None;
+        while let Some(batch) = self.reader.next_batch()? {
+            if batch.num_rows() > 0 {
+                self.values = key_values(&batch, keys)?;
+                self.batch = Some(batch);
+                self.batch_id += 1;
+                self.row = 0;
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merges sorted runs into sorted batches
+struct MergeReader {
+    schema: SchemaRef,
+    keys: Vec<SortKey>,
+    cursors: Vec<RunCursor>,
+    batch_size: usize,
+    /// Keeps the runs from being removed until the merge is dropped
+    _runs: Arc<SpilledRuns>,
+}
+
+impl MergeReader {
+    fn merge_batch(&mut self) -> Result<Option<RecordBatch>> {
+        // the rows of the output, as runs of rows taken from the same batch
+        let mut segments: Vec<(usize, usize, RecordBatch, Vec<u32>)> = vec![];
+        let mut rows = 0;
+        while rows < self.batch_size {
+            // the cursor at the smallest row, preferring earlier runs so the sort is stable
+            let mut next: Option<usize> = None;
+            for (i, cursor) in self.cursors.iter().enumerate() {
+                if cursor.batch.is_none() {
+                    continue;
+                }
+                let smaller = match next {
+                    Some(j) => {
+                        let other = &self.cursors[j];
+                        compare_rows(
+                            &self.keys,
+                            &cursor.values,
+                            cursor.row,
+                            &other.values,
+                            other.row,
+                        ) == Ordering::Less
+                    }
+                    None => true,
+                };
+                if smaller {
+                    next = Some(i);
+                }
+            }
+            let i = match next {
+                Some(i) => i,
+                None => break,
+            };
+
+            let cursor = &mut self.cursors[i];
+            match segments.last_mut() {
+                Some((run, batch_id, _, rows)) if *run == i && *batch_id == cursor.batch_id => {
+                    rows.push(cursor.row as u32)
+                }
+                _ => segments.push((
+                    i,
+                    cursor.batch_id,
+                    cursor.batch.clone().unwrap(),
+                    vec![cursor.row as u32],
+                )),
+            }
+            cursor.advance(&self.keys)?;
+            rows += 1;
+        }
+
+        if segments.is_empty() {
+            return Ok(None);
+        }
+        let columns = (0..self.schema.fields().len())
+            .map(|column| {
+                let arrays = segments
+                    .iter()
+                    .map(|(_, _, batch, rows)| {
+                        dictionary::take(batch.column(column), &UInt32Array::from(rows.clone()))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                dictionary::concat(&arrays)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), columns)?))
+    }
+}
+
+impl RecordBatchReader for MergeReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        self.merge_batch()
+            .map_err(|e| ArrowError::ComputeError(format!("{:?}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{Array, Int32Array};
+    use crate::arrow::datatypes::{DataType, Field};
+
+    #[test]
+    fn merge_spilled_runs() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Int32, true),
+            Field::new("b", DataType::Int32, false),
+        ]);
+        let keys = sort_keys(
+            &[
+                Expr::Sort {
+                    expr: Box::new(Expr::Column(0)),
+                    asc: true,
+                },
+                Expr::Sort {
+                    expr: Box::new(Expr::Column(1)),
+                    asc: false,
+                },
+            ],
+            &schema,
+        )?;
+        // every batch exceeds the budget, so each batch is spilled as a run
+        let mut sorter = ExternalSorter::new(&schema, keys, 1, 2);
+        let inputs = vec![
+            (vec![Some(3), None, Some(1)], vec![1, 2, 3]),
+            (vec![Some(2), Some(1), Some(3)], vec![4, 5, 6]),
+        ];
+        for (a, b) in inputs {
+            let batch = RecordBatch::try_new(
+                Arc::new(schema.clone()),
+                vec![Arc::new(Int32Array::from(a)), Arc::new(Int32Array::from(b))],
+            )?;
+            sorter.insert(batch)?;
+        }
+        assert_eq!(2, sorter.spills());
+
+        let table = sorter.finish()?;
+        let partitions = table.scan(&None, 1024)?;
+        let reader = partitions[0].execute()?;
+        let mut reader = reader.lock().unwrap();
+        let mut rows = vec![];
+        while let Some(batch) = reader.next_batch()? {
+            assert!(batch.num_rows() <= 2);
+            let a = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .unwrap();
+            let b = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .unwrap();
+            for i in 0..batch.num_rows() {
+                rows.push((
+                    if a.is_null(i) { None } else { Some(a.value(i)) },
+                    b.value(i),
+                ));
+            }
+        }
+        assert_eq!(
+            vec![
+                (Some(1), 5),
+                (Some(1), 3),
+                (Some(2), 4),
+                (Some(3), 6),
+                (Some(3), 1),
+                (None, 2)
+            ],
+            rows
+        );
+        Ok(())
+    }
+}
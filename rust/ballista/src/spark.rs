@@ -0,0 +1,565 @@
+//! Actions for the Spark executor.
+//!
+//! The Spark executor decodes the plans of `Collect`, `WriteParquet` and `WriteCsv` actions
+//! into the logical plans of the JVM implementation and runs them as Spark DataFrames. Write
+//! actions are executed by Spark as a whole, writing a file for each Spark partition to the
+//! output directory and returning the manifest of the files, so the partition of a
+//! `WriteParquet` action is ignored. The executor runs a subset of the plans that Rust
+//! executors do: scans of CSV files, projections, selections, aggregates, sorts, limits and
+//! joins. Plans are checked before they are sent, so that the operators and expressions that
+//! it does not support fail with an error that names them instead of failing on the
+//! executor.
+//!
+//! Plans are encoded in the form that the JVM implementation decodes. Columns are
+//! referenced by name, since Spark resolves columns by name, binary operators are encoded
+//! with the names that the JVM implementation uses, and broadcast hints are removed, since
+//! Spark chooses how to execute each join itself.
+//!
+//! Spark contexts are configured with `spark.*` settings. The `spark.ballista.host` and
+//! `spark.ballista.port` settings give the address of the Spark executor and are required,
+//! and the other `spark.*` settings are sent with each action and set on the Spark session
+//! that the executor runs the action in. The session timezone of Spark, which it reads and
+//! writes timestamps in, is set from `ballista.session.timezone` unless it is set itself.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::logicalplan::{Expr, LogicalPlan, Operator, ScalarValue};
+use crate::plan::Action;
+use crate::protobuf;
+use crate::serde::from_proto::parse_operator;
+use crate::timezone::{parse_timezone, SESSION_TIMEZONE};
+
+use prost::Message;
+
+pub const SPARK_HOST: &str = "spark.ballista.host";
+pub const SPARK_PORT: &str = "spark.ballista.port";
+
+/// Setting of the Spark session with its timezone
+const SPARK_SESSION_TIMEZONE: &str = "spark.sql.session.timeZone";
+
+/// The aggregate functions that the Spark executor computes
+const AGGREGATE_FUNCTIONS: &[&str] = &["MIN", "MAX", "SUM", "AVG", "COUNT"];
+
+/// Configuration of a Spark context
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparkConfig {
+    /// Host of the Spark executor
+    pub host: String,
+    /// Port of the Spark executor
+    pub port: usize,
+    /// The `spark.*` settings other than those of Ballista, which are set on the Spark
+    /// session that each action runs in
+    pub session_settings: HashMap<String, String>,
+}
+
+impl SparkConfig {
+    /// Read the Spark configuration from the Context settings
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self> {
+        let required = |name: &str| match settings.get(name) {
+            Some(value) if !value.is_empty() => Ok(value),
+            _ => Err(ballista_error(&format!(
+                "Spark contexts require the {} setting",
+                name
+            ))),
+        };
+        let host = required(SPARK_HOST)?.clone();
+        let port = required(SPARK_PORT)?;
+        let port = match port.parse::<u16>() {
+            Ok(port) if port > 0 => port as usize,
+            _ => {
+                return Err(ballista_error(&format!(
+                    "Invalid value for {}: {}",
+                    SPARK_PORT, port
+                )))
+            }
+        };
+        let mut session_settings: HashMap<String, String> = settings
+            .iter()
+            .filter(|(name, _)| name.starts_with("spark.") && !name.starts_with("spark.ballista."))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        if let Some(timezone) = settings.get(SESSION_TIMEZONE) {
+            let timezone = parse_timezone(timezone)?;
+            session_settings
+                .entry(SPARK_SESSION_TIMEZONE.to_owned())
+                .or_insert_with(|| timezone.to_string());
+        }
+        Ok(Self {
+            host,
+            port,
+            session_settings,
+        })
+    }
+}
+
+/// Encode an action for the Spark executor, with the settings of the Spark session that it
+/// runs in, failing if the executor cannot run it
+pub fn encode_spark_action(
+    action: Action,
+    session_settings: &HashMap<String, String>,
+) -> Result<Vec<u8>> {
+    let action = match action {
+        Action::Collect { plan } => Action::Collect {
+            plan: spark_plan(plan)?,
+        },
+        Action::WriteParquet {
+            plan,
+            path,
+            partition,
+        } => Action::WriteParquet {
+            plan: spark_plan(plan)?,
+            path,
+            partition,
+        },
+        Action::WriteCsv { plan, path } => Action::WriteCsv {
+            plan: spark_plan(plan)?,
+            path,
+        },
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "The Spark executor does not execute {} actions",
+                action_name(&other)
+            )))
+        }
+    };
+    let mut proto: protobuf::Action = action.try_into()?;
+    proto.settings = session_settings.clone();
+    let plans = vec![
+        proto.query.as_mut(),
+        proto.write_parquet.as_mut().and_then(|w| w.plan.as_mut()),
+        proto.write_csv.as_mut().and_then(|w| w.plan.as_mut()),
+    ];
+    plans.into_iter().flatten().for_each(rename_plan_operators);
+    let mut buf = Vec::with_capacity(proto.encoded_len());
+    proto.encode(&mut buf)?;
+    Ok(buf)
+}
+
+/// Check a plan and rewrite it into the form that the Spark executor decodes
+fn spark_plan(plan: LogicalPlan) -> Result<LogicalPlan> {
+    check_spark_plan(&plan)?;
+    plan.transform_up(|plan| Ok(with_named_columns(plan)))
+}
+
+/// Check that the Spark executor supports each of the operators and expressions of a plan
+pub fn check_spark_plan(plan: &LogicalPlan) -> Result<()> {
+    match plan {
+        LogicalPlan::FileScan {
+            file_type,
+            csv_options,
+            ..
+        } => {
+            if file_type != "csv" {
+                return Err(unsupported(&format!("{} scans", file_type), plan));
+            }
+            if !csv_options.clone().unwrap_or_default().is_default() {
+                return Err(unsupported("CSV scans with custom options", plan));
+            }
+        }
+        LogicalPlan::Projection { expr, input, .. } => check_exprs(expr, input, plan)?,
+        LogicalPlan::Selection { expr, input } => check_expr(expr, input, plan)?,
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            ..
+        } => {
+            check_exprs(group_expr, input, plan)?;
+            for expr in aggr_expr {
+                // aggregates are computed over columns of the input
+                match expr {
+                    Expr::AggregateFunction { name, args, .. }
+                        if AGGREGATE_FUNCTIONS.contains(&name.as_str()) =>
+                    {
+                        match args.as_slice() {
+                            [Expr::Column(_)] | [Expr::UnresolvedColumn(_)] => {}
+                            _ => return Err(unsupported_expr(expr, plan)),
+                        }
+                    }
+                    other => return Err(unsupported_expr(other, plan)),
+                }
+            }
+        }
+        LogicalPlan::Sort { expr, input, .. } => {
+            for e in expr {
+                match e {
+                    Expr::Sort { expr, .. } => check_expr(expr, input, plan)?,
+                    other => return Err(unsupported_expr(other, plan)),
+                }
+            }
+        }
+        LogicalPlan::Limit { expr, .. } => match expr {
+            Expr::Literal(ScalarValue::UInt64(n)) if *n <= std::i32::MAX as u64 => {}
+            other => return Err(unsupported_expr(other, plan)),
+        },
+        LogicalPlan::Join {
+            null_equals_null: true,
+            ..
+        } => return Err(unsupported("joins that match nulls", plan)),
+        LogicalPlan::Join { schema, .. } => {
+            // Spark references the columns of the output by name
+            let fields = schema.fields();
+            if fields
+                .iter()
+                .enumerate()
+                .any(|(i, f)| fields[..i].iter().any(|g| g.name() == f.name()))
+            {
+                return Err(unsupported(
+                    "joins of inputs with columns of the same name",
+                    plan,
+                ));
+            }
+        }
+        LogicalPlan::Broadcast { .. } => {}
+        other => {
+            return Err(unsupported(
+                &format!("{} operators", other.operator_name()),
+                other,
+            ))
+        }
+    }
+    plan.inputs().into_iter().try_for_each(check_spark_plan)
+}
+
+fn check_exprs(exprs: &[Expr], input: &LogicalPlan, plan: &LogicalPlan) -> Result<()> {
+    exprs.iter().try_for_each(|e| check_expr(e, input, plan))
+}
+
+/// Check that the Spark executor evaluates an expression over the columns of an input
+fn check_expr(expr: &Expr, input: &LogicalPlan, plan: &LogicalPlan) -> Result<()> {
+    match expr {
+        Expr::Column(i) if *i < input.schema().fields().len() => Ok(()),
+        Expr::UnresolvedColumn(_) => Ok(()),
+        Expr::Literal(value) => match value {
+            ScalarValue::Null | ScalarValue::Struct(_) => Err(unsupported_expr(expr, plan)),
+            _ => Ok(()),
+        },
+        Expr::BinaryExpr { left, op, right } if jvm_operator(op).is_some() => {
+            check_expr(left, input, plan)?;
+            check_expr(right, input, plan)
+        }
+        Expr::Not(e) | Expr::IsNull(e) | Expr::IsNotNull(e) | Expr::Alias(e, _) => {
+            check_expr(e, input, plan)
+        }
+        Expr::Cast { expr: e, data_type } if is_jvm_type(data_type) => check_expr(e, input, plan),
+        _ => Err(unsupported_expr(expr, plan)),
+    }
+}
+
+/// The name of a binary operator in the plans of the JVM implementation
+fn jvm_operator(op: &Operator) -> Option<&'static str> {
+    match op {
+        Operator::Eq => Some("eq"),
+        Operator::NotEq => Some("neq"),
+        Operator::Lt => Some("lt"),
+        Operator::LtEq => Some("lteq"),
+        Operator::Gt => Some("gt"),
+        Operator::GtEq => Some("gteq"),
+        Operator::And => Some("and"),
+        Operator::Or => Some("or"),
+        Operator::Plus => Some("add"),
+        Operator::Minus => Some("subtract"),
+        Operator::Multiply => Some("multiply"),
+        Operator::Divide => Some("divide"),
+        Operator::Modulus => Some("modulus"),
+        _ => None,
+    }
+}
+
+/// Whether the JVM implementation decodes a type
+fn is_jvm_type(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::Utf8 => true,
+        _ => false,
+    }
+}
+
+fn unsupported(what: &str, plan: &LogicalPlan) -> BallistaError {
+    BallistaError::NotImplemented(format!(
+        "The Spark executor does not support {}: {}",
+        what,
+        describe(plan)
+    ))
+}
+
+fn unsupported_expr(expr: &Expr, plan: &LogicalPlan) -> BallistaError {
+    BallistaError::NotImplemented(format!(
+        "The Spark executor does not support the expression {:?} in {}",
+        expr,
+        describe(plan)
+    ))
+}
+
+/// The first line of the description of a plan, which describes the operator without its
+/// inputs
+fn describe(plan: &LogicalPlan) -> String {
+    format!("{:?}", plan)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+fn action_name(action: &Action) -> &'static str {
+    match action {
+        Action::Collect { .. } => "Collect",
+        Action::WriteCsv { .. } => "WriteCsv",
+        Action::WriteParquet { .. } => "WriteParquet",
+        Action::ShuffleWrite { .. } => "ShuffleWrite",
+        Action::FetchSchema { .. } => "FetchSchema",
+        Action::ListTables => "ListTables",
+    }
+}
+
+/// Reference the columns of the inputs of an operator by name, and remove broadcast hints
+fn with_named_columns(plan: LogicalPlan) -> LogicalPlan {
+    match plan {
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => LogicalPlan::Projection {
+            expr: named_exprs(&expr, input.schema()),
+            input,
+            schema,
+        },
+        LogicalPlan::Selection { expr, input } => LogicalPlan::Selection {
+            expr: named(&expr, input.schema()),
+            input,
+        },
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            schema,
+        } => LogicalPlan::Aggregate {
+            group_expr: named_exprs(&group_expr, input.schema()),
+            aggr_expr: named_exprs(&aggr_expr, input.schema()),
+            input,
+            schema,
+        },
+        LogicalPlan::Sort {
+            expr,
+            input,
+            schema,
+        } => LogicalPlan::Sort {
+            expr: named_exprs(&expr, input.schema()),
+            input,
+            schema,
+        },
+        LogicalPlan::Broadcast { input } => *input,
+        other => other,
+    }
+}
+
+fn named_exprs(exprs: &[Expr], schema: &Schema) -> Vec<Expr> {
+    exprs.iter().map(|e| named(e, schema)).collect()
+}
+
+fn named(expr: &Expr, schema: &Schema) -> Expr {
+    let boxed = |e: &Expr| Box::new(named(e, schema));
+    match expr {
+        Expr::Column(i) => Expr::UnresolvedColumn(schema.field(*i).name().clone()),
+        Expr::BinaryExpr { left, op, right } => Expr::BinaryExpr {
+            left: boxed(left),
+            op: op.clone(),
+            right: boxed(right),
+        },
+        Expr::Not(e) => Expr::Not(boxed(e)),
+        Expr::IsNull(e) => Expr::IsNull(boxed(e)),
+        Expr::IsNotNull(e) => Expr::IsNotNull(boxed(e)),
+        Expr::Alias(e, alias) => Expr::Alias(boxed(e), alias.clone()),
+        Expr::Cast { expr, data_type } => Expr::Cast {
+            expr: boxed(expr),
+            data_type: data_type.clone(),
+        },
+        Expr::Sort { expr, asc } => Expr::Sort {
+            expr: boxed(expr),
+            asc: *asc,
+        },
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => Expr::AggregateFunction {
+            name: name.clone(),
+            args: named_exprs(args, schema),
+            return_type: return_type.clone(),
+        },
+        other => other.clone(),
+    }
+}
+
+/// Encode the binary operators of a plan with the names of the JVM implementation
+fn rename_plan_operators(node: &mut protobuf::LogicalPlanNode) {
+    if let Some(input) = node.input.as_deref_mut() {
+        rename_plan_operators(input);
+    }
+    if let Some(right) = node.right.as_deref_mut() {
+        rename_plan_operators(right);
+    }
+    let mut exprs: Vec<&mut protobuf::LogicalExprNode> = vec![];
+    if let Some(projection) = node.projection.as_mut() {
+        exprs.extend(projection.expr.iter_mut());
+    }
+    if let Some(expr) = node.selection.as_mut().and_then(|s| s.expr.as_mut()) {
+        exprs.push(expr);
+    }
+    if let Some(aggregate) = node.aggregate.as_mut() {
+        exprs.extend(aggregate.group_expr.iter_mut());
+        exprs.extend(aggregate.aggr_expr.iter_mut());
+    }
+    if let Some(sort) = node.sort.as_mut() {
+        exprs.extend(sort.expr.iter_mut());
+    }
+    exprs.into_iter().for_each(rename_expr_operators);
+}
+
+fn rename_expr_operators(expr: &mut protobuf::LogicalExprNode) {
+    if let Some(binary) = expr.binary_expr.as_deref_mut() {
+        if let Ok(op) = parse_operator(&binary.op) {
+            if let Some(name) = jvm_operator(&op) {
+                binary.op = name.to_owned();
+            }
+        }
+        binary
+            .l
+            .as_deref_mut()
+            .into_iter()
+            .for_each(rename_expr_operators);
+        binary
+            .r
+            .as_deref_mut()
+            .into_iter()
+            .for_each(rename_expr_operators);
+    }
+    let children = vec![
+        expr.not_expr.as_deref_mut(),
+        expr.is_null_expr.as_deref_mut(),
+        expr.is_not_null_expr.as_deref_mut(),
+        expr.alias
+            .as_deref_mut()
+            .and_then(|a| a.expr.as_deref_mut()),
+        expr.cast.as_deref_mut().and_then(|c| c.expr.as_deref_mut()),
+        expr.sort.as_deref_mut().and_then(|s| s.expr.as_deref_mut()),
+    ];
+    children
+        .into_iter()
+        .flatten()
+        .for_each(rename_expr_operators);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::Field;
+    use crate::logicalplan::{col, lit_str, LogicalPlanBuilder};
+
+    #[test]
+    fn encode_plans_for_spark() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("state", DataType::Utf8, false),
+        ]);
+        let orders = Schema::new(vec![
+            Field::new("customer_id", DataType::Int64, false),
+            Field::new("total", DataType::Float64, false),
+        ]);
+        let scan = LogicalPlanBuilder::scan_csv("customers.csv", &schema, None)?;
+        let plan = scan
+            .filter(Expr::Column(1).eq(&lit_str("CA")))?
+            .join(
+                &LogicalPlanBuilder::scan_csv("orders.csv", &orders, None)?.build()?,
+                vec![(0, 0)],
+            )?
+            .sort(vec![col("id").sort(false)])?
+            .limit(Expr::Literal(ScalarValue::UInt64(10)))?
+            .build()?;
+        let bytes = encode_spark_action(Action::Collect { plan }, &HashMap::new())?;
+        let proto = protobuf::Action::decode(bytes.as_slice())?;
+        let join = proto.query.unwrap().input.unwrap().input.unwrap();
+        assert!(join.join.is_some());
+        let selection = join.input.unwrap().selection.unwrap().expr.unwrap();
+        let binary = selection.binary_expr.unwrap();
+        assert_eq!("eq", binary.op);
+        assert_eq!("state", binary.l.unwrap().column_name);
+
+        let action = Action::WriteCsv {
+            plan: scan.filter(Expr::Column(1).eq(&lit_str("CA")))?.build()?,
+            path: "hdfs:///customers".to_owned(),
+        };
+        let config = SparkConfig::from_settings(&settings(&[
+            (SPARK_HOST, "localhost"),
+            (SPARK_PORT, "50051"),
+            ("spark.sql.shuffle.partitions", "8"),
+        ]))?;
+        let bytes = encode_spark_action(action, &config.session_settings)?;
+        let proto = protobuf::Action::decode(bytes.as_slice())?;
+        assert_eq!(config.session_settings, proto.settings);
+        assert_eq!(1, proto.settings.len());
+        let write_csv = proto.write_csv.unwrap();
+        assert_eq!("hdfs:///customers", write_csv.path);
+        let selection = write_csv.plan.unwrap().selection.unwrap().expr.unwrap();
+        assert_eq!("eq", selection.binary_expr.unwrap().op);
+
+        let plan = scan
+            .project(vec![Expr::ScalarFunction {
+                name: "sqrt".to_owned(),
+                args: vec![col("id")],
+                return_type: DataType::Float64,
+            }])?
+            .build()?;
+        match encode_spark_action(Action::Collect { plan }, &HashMap::new()) {
+            Err(BallistaError::NotImplemented(message)) => assert!(message.contains("sqrt")),
+            other => panic!("expected an error but found {:?}", other),
+        }
+        Ok(())
+    }
+
+    fn settings(settings: &[(&str, &str)]) -> HashMap<String, String> {
+        settings
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn validate_spark_settings() {
+        let config = SparkConfig::from_settings(&settings(&[
+            (SPARK_HOST, "spark-1"),
+            (SPARK_PORT, "50051"),
+            ("spark.app.name", "demo"),
+            ("ballista.client.maxAttempts", "5"),
+        ]))
+        .unwrap();
+        assert_eq!("spark-1", config.host);
+        assert_eq!(50051, config.port);
+        assert_eq!(
+            settings(&[("spark.app.name", "demo")]),
+            config.session_settings
+        );
+
+        let err = SparkConfig::from_settings(&settings(&[(SPARK_PORT, "50051")])).unwrap_err();
+        assert!(err.to_string().contains(SPARK_HOST));
+        let err =
+            SparkConfig::from_settings(&settings(&[(SPARK_HOST, "spark-1"), (SPARK_PORT, "x")]))
+                .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Invalid value for spark.ballista.port: x"));
+    }
+}
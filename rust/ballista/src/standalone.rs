@@ -0,0 +1,202 @@
+//! Standalone clusters that do not need an external coordination service.
+//!
+//! One process runs the scheduler, which accepts queries from clients in the same way as an
+//! executor and runs them across the executors that have joined the cluster. Executors join
+//! by connecting to the membership port of the scheduler and sending their registration as
+//! a line of JSON, which they send again at every heartbeat. The scheduler stops assigning
+//! tasks to an executor when its connection closes or its heartbeats stop.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::discovery::ExecutorRegistration;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::executor::{BallistaFlightService, Server};
+use crate::history::QueryHistory;
+use crate::scheduler::Scheduler;
+
+use chrono::Utc;
+use log::{info, warn};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Default port that the scheduler accepts executor connections on
+pub const DEFAULT_MEMBERSHIP_PORT: u16 = 50050;
+
+/// How often executors send their registration to the scheduler
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Executors that do not send a heartbeat for this long are removed from the cluster
+const MEMBER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Delay before an executor reconnects to the scheduler after losing its connection
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Run a scheduler as the scheduler of a standalone cluster, accepting queries on the
+/// Flight address and executor connections on the membership address
+pub async fn run_scheduler(
+    addr: SocketAddr,
+    membership_addr: SocketAddr,
+    scheduler: Scheduler,
+    history: QueryHistory,
+) -> Result<()> {
+    let scheduler = Arc::new(scheduler);
+    let membership = Membership::new(scheduler.clone());
+    let mut listener = TcpListener::bind(membership_addr).await?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(membership.clone().serve(stream, peer));
+                }
+                Err(e) => warn!("Unable to accept executor connection: {:?}", e),
+            }
+        }
+    });
+
+    let service = BallistaFlightService::default()
+        .with_scheduler(scheduler)
+        .with_query_history(history);
+    Server::with_service(addr, service).serve().await
+}
+
+/// Run an executor that joins the cluster of the scheduler with the given membership
+/// address. Executors registered without a host are reached at the address that they
+/// connect to the scheduler from.
+pub async fn run_executor(
+    server: Server,
+    scheduler_addr: &str,
+    registration: ExecutorRegistration,
+) -> Result<()> {
+    tokio::spawn(join_cluster(scheduler_addr.to_owned(), registration));
+    server.serve().await
+}
+
+/// Send heartbeats to the scheduler for as long as the executor runs, reconnecting when the
+/// connection is lost
+async fn join_cluster(scheduler_addr: String, mut registration: ExecutorRegistration) {
+    loop {
+        match TcpStream::connect(scheduler_addr.as_str()).await {
+            Ok(mut stream) => loop {
+                registration.last_heartbeat = Utc::now().timestamp_millis();
+                let line = format!("{}\n", registration.to_json());
+                if let Err(e) = stream.write_all(line.as_bytes()).await {
+                    warn!("Lost connection to scheduler {}: {:?}", scheduler_addr, e);
+                    break;
+                }
+                tokio::time::delay_for(HEARTBEAT_INTERVAL).await;
+            },
+            Err(e) => warn!("Unable to connect to scheduler {}: {:?}", scheduler_addr, e),
+        }
+        tokio::time::delay_for(RECONNECT_DELAY).await;
+    }
+}
+
+/// The executors that are connected to the scheduler
+#[derive(Clone)]
+struct Membership {
+    scheduler: Arc<Scheduler>,
+    /// The connection that each executor last registered on, so that a connection that is
+    /// replaced by a new one does not remove the executor when it closes
+    connections: Arc<Mutex<HashMap<String, usize>>>,
+    next_connection: Arc<AtomicUsize>,
+}
+
+impl Membership {
+    fn new(scheduler: Arc<Scheduler>) -> Self {
+        Self {
+            scheduler,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            next_connection: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Register the executor on a connection until the connection closes or its
+    /// heartbeats stop
+    async fn serve(self, stream: TcpStream, peer: SocketAddr) {
+        let connection = self.next_connection.fetch_add(1, Ordering::SeqCst);
+        let mut reader = BufReader::new(stream);
+        let mut executor_id = None;
+        loop {
+            let mut line = String::new();
+            let registration =
+                match tokio::time::timeout(MEMBER_TIMEOUT, reader.read_line(&mut line)).await {
+                    Ok(Ok(0)) => break,
+                    Ok(Ok(_)) => ExecutorRegistration::from_json(line.trim()),
+                    Ok(Err(e)) => Err(BallistaError::IoError(e)),
+                    Err(_) => Err(ballista_error("No heartbeat received")),
+                };
+            match registration {
+                Ok(mut registration) => {
+                    if registration.host.is_empty() {
+                        registration.host = peer.ip().to_string();
+                    }
+                    if executor_id.as_ref() != Some(&registration.id) {
+                        info!("Executor {} joined from {}", registration.id, peer);
+                        self.connections
+                            .lock()
+                            .unwrap()
+                            .insert(registration.id.clone(), connection);
+                        executor_id = Some(registration.id.clone());
+                    }
+                    self.scheduler.register_executor_with_plan_version(
+                        registration.executor_meta(),
+                        registration.plan_version,
+                    );
+                }
+                Err(e) => {
+                    warn!("Closing executor connection from {}: {:?}", peer, e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(executor_id) = executor_id {
+            let mut connections = self.connections.lock().unwrap();
+            if connections.get(&executor_id) == Some(&connection) {
+                info!("Executor {} left", executor_id);
+                connections.remove(&executor_id);
+                self.scheduler.remove_executor(&executor_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientConfig;
+
+    #[tokio::test]
+    async fn executors_join_and_leave() -> Result<()> {
+        let scheduler = Arc::new(Scheduler::new(ClientConfig::default()));
+        let membership = Membership::new(scheduler.clone());
+        let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((stream, peer)) = listener.accept().await {
+                tokio::spawn(membership.clone().serve(stream, peer));
+            }
+        });
+
+        let mut registration = ExecutorRegistration::new("", 50061);
+        registration.id = "e1".to_owned();
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(format!("{}\n", registration.to_json()).as_bytes())
+            .await?;
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+        let executors = scheduler.executors();
+        assert_eq!(1, executors.len());
+        assert_eq!("127.0.0.1", executors[0].host);
+        assert_eq!(50061, executors[0].port);
+
+        drop(stream);
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+        assert!(scheduler.executors().is_empty());
+        Ok(())
+    }
+}
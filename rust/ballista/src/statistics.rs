@@ -0,0 +1,462 @@
+//! Statistics of tables, and estimates of the number of rows that plans produce.
+//!
+//! The estimates are used by the optimizer to order joins. Parquet scans are estimated from
+//! the row counts and distinct value counts in the metadata of the files, other local files
+//! from their size and the width of their rows, registered tables from the statistics that
+//! `Context::analyze_table` collected for them, and batches in memory exactly. Filters are
+//! assumed to select a fixed fraction of their input, unless they compare a column with a
+//! known number of distinct values to a literal. Plans that read remote files, databases,
+//! registered tables that have not been analyzed or the output of other stages are not
+//! estimated.
+
+use std::fs;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, Schema, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::datasource::is_remote_path;
+use crate::datasource::parquet::{parquet_statistics, FooterCache};
+use crate::datasource::table::TableRegistry;
+use crate::logicalplan::{Expr, LogicalPlan, Operator, ScalarValue};
+use crate::optimizer::fold;
+
+/// Fraction of the rows of its input that a filter is assumed to select when it cannot be
+/// estimated from the statistics of the input
+pub const FILTER_SELECTIVITY: f64 = 0.25;
+
+/// Estimated statistics of the output of a plan
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanStatistics {
+    pub num_rows: f64,
+    /// Number of distinct values of each column of the output, if known
+    pub distinct_counts: Vec<Option<f64>>,
+}
+
+impl PlanStatistics {
+    /// Statistics of a plan whose columns have unknown numbers of distinct values
+    pub fn new(num_rows: f64, columns: usize) -> Self {
+        Self {
+            num_rows,
+            distinct_counts: vec![None; columns],
+        }
+    }
+
+    /// The number of distinct values of a column, assuming that every value is distinct
+    /// when it is not known
+    pub fn distinct_count(&self, column: usize) -> f64 {
+        self.distinct_counts
+            .get(column)
+            .cloned()
+            .flatten()
+            .unwrap_or(self.num_rows)
+            .min(self.num_rows)
+            .max(1.0)
+    }
+
+    /// Scale the number of rows, with no column having more distinct values than rows
+    fn with_rows(&self, num_rows: f64) -> Self {
+        Self {
+            num_rows,
+            distinct_counts: self
+                .distinct_counts
+                .iter()
+                .map(|d| d.map(|d| d.min(num_rows)))
+                .collect(),
+        }
+    }
+}
+
+/// Statistics of a column of a table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStatistics {
+    /// Number of null values, if known
+    pub null_count: Option<u64>,
+    /// The smallest non-null value, which is only known for numeric and string columns
+    pub min: Option<ScalarValue>,
+    /// The largest non-null value, which is only known for numeric and string columns
+    pub max: Option<ScalarValue>,
+}
+
+/// Statistics of a table, which are collected by `Context::analyze_table` or read from the
+/// metadata of parquet files
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStatistics {
+    pub num_rows: u64,
+    pub columns: Vec<ColumnStatistics>,
+}
+
+impl TableStatistics {
+    /// The statistics of a table with no rows
+    pub fn empty(columns: usize) -> Self {
+        let column = ColumnStatistics {
+            null_count: Some(0),
+            min: None,
+            max: None,
+        };
+        Self {
+            num_rows: 0,
+            columns: vec![column; columns],
+        }
+    }
+
+    /// Add the rows of a batch with the schema of the table to the statistics
+    pub fn add_batch(&mut self, batch: &RecordBatch) {
+        self.num_rows += batch.num_rows() as u64;
+        for (array, column) in batch.columns().iter().zip(&mut self.columns) {
+            column.null_count = column.null_count.map(|n| n + array.null_count() as u64);
+            if let Some((min, max)) = array_range(array.as_ref()) {
+                column.min = Some(extreme(column.min.take(), min, &Operator::Lt));
+                column.max = Some(extreme(column.max.take(), max, &Operator::Gt));
+            }
+        }
+    }
+
+    /// The statistics of the columns of the table that a scan projects
+    pub fn project(&self, projection: &Option<Vec<usize>>) -> Self {
+        match projection {
+            Some(projection) => Self {
+                num_rows: self.num_rows,
+                columns: projection
+                    .iter()
+                    .map(|i| self.columns[*i].clone())
+                    .collect(),
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// The estimated statistics of a scan of the table
+    pub fn plan_statistics(&self) -> PlanStatistics {
+        PlanStatistics::new(self.num_rows as f64, self.columns.len())
+    }
+}
+
+/// The value, unless the current value is already smaller or larger than it by the operator
+fn extreme(current: Option<ScalarValue>, value: ScalarValue, op: &Operator) -> ScalarValue {
+    match current {
+        Some(current) if fold(&value, op, &current) != Some(ScalarValue::Boolean(true)) => current,
+        _ => value,
+    }
+}
+
+macro_rules! array_range {
+    ($array:expr, $array_type:ident, $value:expr) => {{
+        let array = $array.as_any().downcast_ref::<$array_type>()?;
+        let mut range = None;
+        for row in (0..array.len()).filter(|row| !array.is_null(*row)) {
+            let v = array.value(row);
+            range = match range {
+                Some((min, max)) => {
+                    Some((if v < min { v } else { min }, if v > max { v } else { max }))
+                }
+                None => Some((v, v)),
+            };
+        }
+        range.map(|(min, max)| ($value(min), $value(max)))
+    }};
+}
+
+/// The smallest and largest non-null values of an array of numbers, strings, dates or
+/// timestamps
+fn array_range(array: &dyn Array) -> Option<(ScalarValue, ScalarValue)> {
+    match array.data_type() {
+        DataType::Int8 => array_range!(array, Int8Array, ScalarValue::Int8),
+        DataType::Int16 => array_range!(array, Int16Array, ScalarValue::Int16),
+        DataType::Int32 => array_range!(array, Int32Array, ScalarValue::Int32),
+        DataType::Int64 => array_range!(array, Int64Array, ScalarValue::Int64),
+        DataType::UInt8 => array_range!(array, UInt8Array, ScalarValue::UInt8),
+        DataType::UInt16 => array_range!(array, UInt16Array, ScalarValue::UInt16),
+        DataType::UInt32 => array_range!(array, UInt32Array, ScalarValue::UInt32),
+        DataType::UInt64 => array_range!(array, UInt64Array, ScalarValue::UInt64),
+        DataType::Float32 => array_range!(array, Float32Array, ScalarValue::Float32),
+        DataType::Float64 => array_range!(array, Float64Array, ScalarValue::Float64),
+        DataType::Utf8 => array_range!(array, StringArray, |v: &str| {
+            ScalarValue::Utf8(v.to_owned())
+        }),
+        DataType::Date32(_) => array_range!(array, Date32Array, ScalarValue::Date32),
+        DataType::Date64(_) => array_range!(array, Date64Array, ScalarValue::Date64),
+        DataType::Timestamp(TimeUnit::Second, _) => {
+            array_range!(array, TimestampSecondArray, |v| {
+                ScalarValue::Timestamp(v, TimeUnit::Second)
+            })
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            array_range!(array, TimestampMillisecondArray, |v| {
+                ScalarValue::Timestamp(v, TimeUnit::Millisecond)
+            })
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            array_range!(array, TimestampMicrosecondArray, |v| {
+                ScalarValue::Timestamp(v, TimeUnit::Microsecond)
+            })
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            array_range!(array, TimestampNanosecondArray, |v| {
+                ScalarValue::Timestamp(v, TimeUnit::Nanosecond)
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Estimate the statistics of the output of a plan, or `None` if they cannot be estimated.
+/// Registered tables are estimated from the statistics stored with them in the registry.
+pub fn estimate_statistics(plan: &LogicalPlan, tables: &TableRegistry) -> Option<PlanStatistics> {
+    match plan {
+        LogicalPlan::FileScan {
+            files,
+            file_type,
+            schema,
+            projection,
+            ..
+        } => {
+            if file_type == "sql" || files.iter().any(|f| is_remote_path(f)) {
+                return None;
+            }
+            let table = if file_type == "parquet" {
+                // plans are estimated without the footers cached by the context
+                parquet_statistics(files, schema, &FooterCache::new(0)).ok()?
+            } else {
+                let bytes: u64 = files
+                    .iter()
+                    .map(|f| fs::metadata(f).ok().map(|m| m.len()))
+                    .sum::<Option<u64>>()?;
+                PlanStatistics::new(bytes as f64 / row_width(schema), schema.fields().len())
+            };
+            Some(project(table, projection))
+        }
+        LogicalPlan::TableScan {
+            table_name,
+            projection,
+            ..
+        } => {
+            let table = tables.statistics(table_name)?.plan_statistics();
+            Some(project(table, projection))
+        }
+        LogicalPlan::MemoryScan(batches) => Some(PlanStatistics::new(
+            batches.iter().map(|b| b.num_rows()).sum::<usize>() as f64,
+            plan.schema().fields().len(),
+        )),
+        LogicalPlan::EmptyRelation { schema } => {
+            Some(PlanStatistics::new(0.0, schema.fields().len()))
+        }
+        LogicalPlan::Projection { expr, input, .. } => {
+            let input = estimate_statistics(input, tables)?;
+            let distinct_counts = expr
+                .iter()
+                .map(|e| match column(e) {
+                    Some(i) => input.distinct_counts.get(i).cloned().flatten(),
+                    None => None,
+                })
+                .collect();
+            Some(PlanStatistics {
+                num_rows: input.num_rows,
+                distinct_counts,
+            })
+        }
+        LogicalPlan::Selection { expr, input } => {
+            let input = estimate_statistics(input, tables)?;
+            Some(input.with_rows(input.num_rows * selectivity(expr, &input)))
+        }
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            schema,
+            ..
+        } => {
+            let input = estimate_statistics(input, tables)?;
+            // each group is assumed to be a distinct combination of the grouping columns
+            let groups = group_expr
+                .iter()
+                .map(|e| match column(e) {
+                    Some(i) => input.distinct_count(i),
+                    None => input.num_rows,
+                })
+                .product::<f64>()
+                .min(input.num_rows)
+                .max(1.0);
+            Some(PlanStatistics::new(groups, schema.fields().len()))
+        }
+        LogicalPlan::Limit { expr, input, .. } => {
+            let input = estimate_statistics(input, tables)?;
+            match expr {
+                Expr::Literal(ScalarValue::UInt64(n)) => {
+                    Some(input.with_rows(input.num_rows.min(*n as f64)))
+                }
+                _ => Some(input),
+            }
+        }
+        LogicalPlan::Sort { input, .. } | LogicalPlan::Broadcast { input } => {
+            estimate_statistics(input, tables)
+        }
+        LogicalPlan::Join {
+            left, right, on, ..
+        } => Some(join_statistics(
+            &estimate_statistics(left, tables)?,
+            &estimate_statistics(right, tables)?,
+            on,
+        )),
+        // the distinct values of the relations of a union may overlap
+        LogicalPlan::Union { inputs, schema } => {
+            let rows = inputs
+                .iter()
+                .map(|input| estimate_statistics(input, tables).map(|s| s.num_rows))
+                .sum::<Option<f64>>()?;
+            Some(PlanStatistics::new(rows, schema.fields().len()))
+        }
+        LogicalPlan::StageOutput { .. }
+        | LogicalPlan::ShuffleRead { .. }
+        | LogicalPlan::Extension { .. } => None,
+    }
+}
+
+/// The statistics of the columns of a table that a scan projects
+fn project(table: PlanStatistics, projection: &Option<Vec<usize>>) -> PlanStatistics {
+    match projection {
+        Some(projection) => PlanStatistics {
+            num_rows: table.num_rows,
+            distinct_counts: projection
+                .iter()
+                .map(|i| table.distinct_counts[*i])
+                .collect(),
+        },
+        None => table,
+    }
+}
+
+/// Estimate the statistics of an inner join on pairs of left and right columns. Each pair
+/// is assumed to match the rows of the side with fewer distinct values to rows of the other
+/// side, and the most selective pair determines the size of the join.
+pub fn join_statistics(
+    left: &PlanStatistics,
+    right: &PlanStatistics,
+    on: &[(usize, usize)],
+) -> PlanStatistics {
+    let divisor = on
+        .iter()
+        .map(|(l, r)| left.distinct_count(*l).max(right.distinct_count(*r)))
+        .fold(1.0, f64::max);
+    let stats = PlanStatistics {
+        num_rows: left.num_rows * right.num_rows / divisor,
+        distinct_counts: left
+            .distinct_counts
+            .iter()
+            .chain(&right.distinct_counts)
+            .cloned()
+            .collect(),
+    };
+    stats.with_rows(stats.num_rows)
+}
+
+/// Estimated fraction of the rows of the input that a filter selects
+fn selectivity(expr: &Expr, input: &PlanStatistics) -> f64 {
+    match expr {
+        Expr::BinaryExpr { left, op, right } => match (op, left.as_ref(), right.as_ref()) {
+            (Operator::And, _, _) => selectivity(left, input) * selectivity(right, input),
+            (Operator::Or, _, _) => {
+                let l = selectivity(left, input);
+                let r = selectivity(right, input);
+                l + r - l * r
+            }
+            (Operator::Eq, Expr::Column(i), Expr::Literal(_))
+            | (Operator::Eq, Expr::Literal(_), Expr::Column(i)) => {
+                match input.distinct_counts.get(*i).cloned().flatten() {
+                    Some(distinct) => 1.0 / distinct.max(1.0),
+                    None => FILTER_SELECTIVITY,
+                }
+            }
+            _ => FILTER_SELECTIVITY,
+        },
+        Expr::Literal(ScalarValue::Boolean(true)) => 1.0,
+        Expr::Literal(ScalarValue::Boolean(false)) => 0.0,
+        _ => FILTER_SELECTIVITY,
+    }
+}
+
+/// The column of the input that an expression passes through unchanged
+fn column(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Column(i) => Some(*i),
+        Expr::Alias(expr, _) => column(expr),
+        _ => None,
+    }
+}
+
+/// Estimated number of bytes that a row of a file with the schema takes up
+fn row_width(schema: &Schema) -> f64 {
+    let width: usize = schema
+        .fields()
+        .iter()
+        .map(|f| match f.data_type() {
+            DataType::Boolean | DataType::Int8 | DataType::UInt8 => 1,
+            DataType::Int16 | DataType::UInt16 => 2,
+            DataType::Int32 | DataType::UInt32 | DataType::Float32 => 4,
+            DataType::Utf8 => 16,
+            _ => 8,
+        })
+        .sum();
+    width.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::Field;
+    use std::sync::Arc;
+
+    #[test]
+    fn estimate_joins() {
+        let orders = PlanStatistics {
+            num_rows: 10000.0,
+            distinct_counts: vec![None, Some(100.0)],
+        };
+        let customers = PlanStatistics::new(100.0, 2);
+        let joined = join_statistics(&orders, &customers, &[(1, 0)]);
+        assert_eq!(10000.0, joined.num_rows);
+        assert_eq!(4, joined.distinct_counts.len());
+
+        let filtered = customers.with_rows(customers.num_rows * FILTER_SELECTIVITY);
+        let joined = join_statistics(&orders, &filtered, &[(1, 0)]);
+        assert_eq!(2500.0, joined.num_rows);
+        assert_eq!(100.0, joined.distinct_count(1));
+    }
+
+    #[test]
+    fn collect_table_statistics() -> crate::error::Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, true),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let mut statistics = TableStatistics::empty(2);
+        for (ids, names) in &[
+            (vec![Some(3), None], vec![Some("b"), Some("c")]),
+            (vec![Some(1), Some(2)], vec![None, Some("a")]),
+        ] {
+            statistics.add_batch(&RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int32Array::from(ids.clone())),
+                    Arc::new(StringArray::from(names.clone())),
+                ],
+            )?);
+        }
+        assert_eq!(4, statistics.num_rows);
+        assert_eq!(
+            ColumnStatistics {
+                null_count: Some(1),
+                min: Some(ScalarValue::Int32(1)),
+                max: Some(ScalarValue::Int32(3)),
+            },
+            statistics.columns[0]
+        );
+        assert_eq!(
+            Some(ScalarValue::Utf8("a".to_owned())),
+            statistics.columns[1].min
+        );
+        assert_eq!(
+            Some(ScalarValue::Utf8("c".to_owned())),
+            statistics.columns[1].max
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+//! Status of an executor for dashboards and load balancers.
+//!
+//! Executors serve their status as JSON from `/status` on the address configured with
+//! `Server::with_http_addr`, reporting the version, the queries that are running or waiting
+//! to be fetched, the memory in use, and the shuffle partitions and remote objects that are
+//! stored on the executor. `/health` responds with `200 OK` while the executor is serving,
+//! for load balancer health checks.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::{ballista_error, Result};
+use crate::memory::MemoryUsage;
+use crate::shuffle::ShuffleDirs;
+
+use serde_json::{json, Value};
+
+/// Files stored in a local directory
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DiskUsage {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl DiskUsage {
+    /// The files in a directory and its subdirectories, which are none if the directory
+    /// does not exist
+    pub fn of_dir(dir: &Path) -> Self {
+        let mut usage = Self::default();
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                match entry.metadata() {
+                    Ok(metadata) if metadata.is_dir() => usage.add(Self::of_dir(&entry.path())),
+                    Ok(metadata) => {
+                        usage.files += 1;
+                        usage.bytes += metadata.len();
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+        usage
+    }
+
+    fn add(&mut self, other: Self) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+    }
+
+    fn to_value(&self) -> Value {
+        json!({ "files": self.files, "bytes": self.bytes })
+    }
+
+    fn from_value(value: &Value) -> Result<Self> {
+        let number = |name: &str| {
+            value[name]
+                .as_u64()
+                .ok_or_else(|| ballista_error(&format!("Disk usage has no {}: {}", name, value)))
+        };
+        Ok(Self {
+            files: number("files")? as usize,
+            bytes: number("bytes")?,
+        })
+    }
+}
+
+/// The status of an executor
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutorStatusReport {
+    pub version: String,
+    /// When the executor started, in milliseconds since the Unix epoch
+    pub started: i64,
+    /// Number of queries and tasks that are executing
+    pub running_tasks: usize,
+    /// Number of queries that have been submitted and whose results have not been fetched
+    pub submitted_queries: usize,
+    pub memory: MemoryUsage,
+    /// Number of jobs whose shuffle partitions are stored on the executor
+    pub shuffle_jobs: usize,
+    pub shuffle_data: DiskUsage,
+    /// Remote objects that have been downloaded into the local cache
+    pub object_cache: DiskUsage,
+    /// Newest version of the plan format that the executor decodes
+    pub plan_version: u32,
+}
+
+impl ExecutorStatusReport {
+    pub fn to_json(&self) -> String {
+        json!({
+            "status": "ok",
+            "version": self.version,
+            "started": self.started,
+            "running_tasks": self.running_tasks,
+            "submitted_queries": self.submitted_queries,
+            "memory": self.memory.to_value(),
+            "shuffle": {
+                "jobs": self.shuffle_jobs,
+                "files": self.shuffle_data.files,
+                "bytes": self.shuffle_data.bytes,
+            },
+            "object_cache": self.object_cache.to_value(),
+            "plan_version": self.plan_version,
+        })
+        .to_string()
+    }
+
+    /// Decode a status that was encoded with `to_json`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| ballista_error(&format!("Invalid executor status: {:?}", e)))?;
+        let number = |name: &str| {
+            value[name].as_u64().ok_or_else(|| {
+                ballista_error(&format!("Executor status has no {}: {}", name, json))
+            })
+        };
+        Ok(Self {
+            version: value["version"].as_str().unwrap_or_default().to_owned(),
+            started: number("started")? as i64,
+            running_tasks: number("running_tasks")? as usize,
+            submitted_queries: number("submitted_queries")? as usize,
+            memory: MemoryUsage::from_value(&value["memory"])?,
+            shuffle_jobs: value["shuffle"]["jobs"].as_u64().unwrap_or(0) as usize,
+            shuffle_data: DiskUsage::from_value(&value["shuffle"])?,
+            object_cache: DiskUsage::from_value(&value["object_cache"])?,
+            plan_version: value["plan_version"].as_u64().unwrap_or(0) as u32,
+        })
+    }
+}
+
+/// The jobs whose shuffle partitions are stored in the shuffle directories, and the size
+/// of the partitions written and fetched for them
+pub fn shuffle_usage(shuffle_dirs: &ShuffleDirs) -> (usize, DiskUsage) {
+    let mut jobs = vec![];
+    let mut usage = DiskUsage::default();
+    for dir in shuffle_dirs.dirs() {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                if entry.path().is_dir() {
+                    jobs.push(entry.file_name());
+                    usage.add(DiskUsage::of_dir(&entry.path()));
+                }
+            }
+        }
+    }
+    // the partitions of a job are spread across the directories
+    jobs.sort();
+    jobs.dedup();
+    (jobs.len(), usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn report_executor_status() -> Result<()> {
+        let dirs: Vec<PathBuf> = (0..2)
+            .map(|i| {
+                std::env::temp_dir().join(format!("ballista-status-{}-{}", std::process::id(), i))
+            })
+            .collect();
+        for (i, dir) in dirs.iter().enumerate() {
+            fs::create_dir_all(dir.join("job-1").join("0"))?;
+            fs::write(
+                dir.join("job-1").join("0").join("0.arrow"),
+                vec![0; 10 * i + 10],
+            )?;
+        }
+        fs::create_dir_all(dirs[1].join("job-2"))?;
+        let (jobs, usage) = shuffle_usage(&ShuffleDirs::new(dirs.clone()));
+        for dir in &dirs {
+            fs::remove_dir_all(dir)?;
+        }
+        assert_eq!(2, jobs);
+        assert_eq!(
+            DiskUsage {
+                files: 2,
+                bytes: 30
+            },
+            usage
+        );
+
+        let status = ExecutorStatusReport {
+            version: "0.3.0".to_owned(),
+            started: 1_600_000_000_000,
+            running_tasks: 1,
+            submitted_queries: 2,
+            memory: MemoryUsage {
+                limit: Some(1000),
+                used: 300,
+                reservations: 1,
+                queued: 0,
+            },
+            shuffle_jobs: jobs,
+            shuffle_data: usage,
+            object_cache: DiskUsage::default(),
+            plan_version: 1,
+        };
+        assert_eq!(status, ExecutorStatusReport::from_json(&status.to_json())?);
+        Ok(())
+    }
+}
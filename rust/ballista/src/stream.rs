@@ -0,0 +1,292 @@
+//! Asynchronous execution of the partitions of local queries.
+//!
+//! DataFusion operators produce their batches by blocking, so each partition of a physical
+//! plan runs on the execution pool of the context and sends its batches into a bounded
+//! channel as they are produced. The batches can be consumed as a stream while the
+//! partitions are still executing, which overlaps the I/O of the scans with the work of
+//! the consumer without holding every result in memory. At most the maximum parallelism
+//! of the query is executing or buffered at a time, and the next partition starts when
+//! the consumer finishes one. Partitions stop at their next batch when the token of the
+//! query is cancelled or the stream is dropped.
+//!
+//! The partitions of a query end in the order that they started, so the stream records where
+//! each partition ended. Executors send an empty message after each partition of the
+//! results when the client asks them to, so that the results of distributed queries can be
+//! split into their partitions too.
+
+#[cfg(feature = "local-execution")]
+use std::collections::VecDeque;
+#[cfg(feature = "local-execution")]
+use std::sync::Arc;
+
+#[cfg(feature = "local-execution")]
+use crate::arrow::datatypes::SchemaRef;
+use crate::arrow::record_batch::RecordBatch;
+#[cfg(feature = "local-execution")]
+use crate::cancel::CancellationToken;
+#[cfg(feature = "local-execution")]
+use crate::datafusion::execution::physical_plan::Partition;
+#[cfg(feature = "local-execution")]
+use crate::error::{BallistaError, Result};
+#[cfg(feature = "local-execution")]
+use crate::pool::{ExecutionPool, TaskHandle};
+
+use flight::FlightData;
+#[cfg(feature = "local-execution")]
+use futures::executor::block_on;
+#[cfg(feature = "local-execution")]
+use futures::future::{self, Either};
+#[cfg(feature = "local-execution")]
+use tokio::sync::mpsc;
+use tonic::metadata::MetadataMap;
+use tonic::Request;
+
+/// Number of batches that each partition produces ahead of the consumer
+#[cfg(feature = "local-execution")]
+const PARTITION_BUFFER_BATCHES: usize = 2;
+
+const PARTITIONS_METADATA: &str = "x-ballista-partitions";
+
+/// Stream of the batches of the partitions of a local query, in partition order. The
+/// partitions execute concurrently up to the maximum parallelism, each buffering a few
+/// batches until the consumer reaches it.
+#[cfg(feature = "local-execution")]
+pub struct PartitionStream {
+    schema: SchemaRef,
+    pool: Arc<ExecutionPool>,
+    token: CancellationToken,
+    pending: VecDeque<Arc<dyn Partition>>,
+    partitions: VecDeque<(mpsc::Receiver<Result<RecordBatch>>, TaskHandle<()>)>,
+    /// The number of batches received so far
+    received: usize,
+    /// The number of batches that had been received when each exhausted partition ended
+    partition_ends: Vec<usize>,
+}
+
+#[cfg(feature = "local-execution")]
+impl PartitionStream {
+    /// Start executing the partitions on the pool, with at most `max_parallelism` of them
+    /// executing at the same time
+    pub fn execute(
+        schema: SchemaRef,
+        partitions: Vec<Arc<dyn Partition>>,
+        token: &CancellationToken,
+        pool: Arc<ExecutionPool>,
+        max_parallelism: usize,
+    ) -> Self {
+        let mut stream = Self {
+            schema,
+            pool,
+            token: token.clone(),
+            pending: partitions.into_iter().collect(),
+            partitions: VecDeque::new(),
+            received: 0,
+            partition_ends: vec![],
+        };
+        for _ in 0..max_parallelism.max(1) {
+            stream.start_next();
+        }
+        stream
+    }
+
+    /// The schema of the batches
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Receive the next batch, returning `None` once every partition has been exhausted
+    pub async fn next(&mut self) -> Result<Option<RecordBatch>> {
+        while let Some((rx, _)) = self.partitions.front_mut() {
+            match rx.recv().await {
+                Some(batch) => {
+                    self.received += 1;
+                    return batch.map(Some);
+                }
+                None => {
+                    // the partition either finished or panicked before sending an error
+                    let (_, handle) = self.partitions.pop_front().unwrap();
+                    handle.join().await.map_err(|_| {
+                        BallistaError::General("Partition thread panicked".to_owned())
+                    })?;
+                    self.partition_ends.push(self.received);
+                    self.start_next();
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The number of batches that had been received when each of the partitions that have
+    /// been exhausted so far ended
+    pub fn partition_ends(&self) -> &[usize] {
+        &self.partition_ends
+    }
+
+    /// Receive all of the remaining batches, stopping with `BallistaError::Cancelled` if
+    /// the token is cancelled first, in which case the partitions stop once they find that
+    /// the stream has been dropped
+    pub async fn collect(mut self, token: &CancellationToken) -> Result<Vec<RecordBatch>> {
+        let mut batches = vec![];
+        loop {
+            let next = Box::pin(self.next());
+            let cancelled = Box::pin(token.cancelled());
+            match future::select(next, cancelled).await {
+                Either::Left((batch, _)) => match batch? {
+                    Some(batch) => batches.push(batch),
+                    None => return Ok(batches),
+                },
+                Either::Right(_) => return Err(BallistaError::Cancelled),
+            }
+        }
+    }
+
+    /// Start executing the next pending partition, if there is one
+    fn start_next(&mut self) {
+        if let Some(partition) = self.pending.pop_front() {
+            let (mut tx, rx) = mpsc::channel(PARTITION_BUFFER_BATCHES);
+            let token = self.token.clone();
+            let handle = self.pool.spawn(move || {
+                let result = execute_partition(partition.as_ref(), &token, &mut tx);
+                if let Err(e) = result {
+                    let _ = block_on(tx.send(Err(e)));
+                }
+            });
+            self.partitions.push_back((rx, handle));
+        }
+    }
+}
+
+/// Split batches into the partitions that produced them, given the number of batches that
+/// had been received when each partition ended. Batches without the ends of their partitions
+/// are a single partition.
+pub fn split_partitions(
+    batches: Vec<RecordBatch>,
+    partition_ends: &[usize],
+) -> Vec<Vec<RecordBatch>> {
+    if partition_ends.is_empty() {
+        return vec![batches];
+    }
+    let mut batches = batches.into_iter();
+    let mut start = 0;
+    partition_ends
+        .iter()
+        .map(|&end| {
+            let partition = batches.by_ref().take(end - start).collect();
+            start = end;
+            partition
+        })
+        .collect()
+}
+
+/// Ask an executor to send the message that marks the end of each partition of the results
+/// of a query
+pub fn request_partitions<T>(request: &mut Request<T>) {
+    request
+        .metadata_mut()
+        .insert(PARTITIONS_METADATA, "true".parse().unwrap());
+}
+
+/// Whether a client asked for the ends of the partitions of the results of a query
+pub fn partitions_requested(metadata: &MetadataMap) -> bool {
+    metadata
+        .get(PARTITIONS_METADATA)
+        .map(|v| v.to_str() == Ok("true"))
+        .unwrap_or(false)
+}
+
+/// The message that executors send after the last batch of each partition. Batch messages
+/// always have a header and the metrics of a query have metadata, while this has neither.
+pub fn partition_end_flight_data() -> FlightData {
+    FlightData::default()
+}
+
+/// Whether a message marks the end of a partition of the results
+pub fn is_partition_end(data: &FlightData) -> bool {
+    data.data_header.is_empty() && data.app_metadata.is_empty() && data.data_body.is_empty()
+}
+
+/// Execute a partition, sending its batches until it is exhausted, the query is cancelled
+/// or the stream is dropped
+#[cfg(feature = "local-execution")]
+fn execute_partition(
+    partition: &dyn Partition,
+    token: &CancellationToken,
+    tx: &mut mpsc::Sender<Result<RecordBatch>>,
+) -> Result<()> {
+    let reader = partition.execute()?;
+    let mut reader = reader.lock().unwrap();
+    loop {
+        token.check()?;
+        match reader.next_batch()? {
+            Some(batch) => {
+                if block_on(tx.send(Ok(batch))).is_err() {
+                    return Ok(());
+                }
+            }
+            None => return Ok(()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "local-execution"))]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::datafusion::datasource::{MemTable, TableProvider};
+    use crate::datafusion::execution::physical_plan::ExecutionPlan;
+
+    #[tokio::test]
+    async fn stream_partitions_in_order() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let partitions: Vec<Vec<RecordBatch>> = (0..4)
+            .map(|p| {
+                let values = Int32Array::from(vec![p * 10, p * 10 + 1]);
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(values)]).map(|b| vec![b])
+            })
+            .collect::<std::result::Result<_, _>>()?;
+        let table = MemTable::new(schema.clone(), partitions)?;
+        let plan = table.scan(&None, 1024)?;
+
+        let token = CancellationToken::new();
+        let pool = Arc::new(ExecutionPool::new(2));
+        let stream = PartitionStream::execute(schema, plan.partitions()?, &token, pool, 1);
+        let values: Vec<i32> = stream
+            .collect(&token)
+            .await?
+            .iter()
+            .flat_map(|b| {
+                let a = b.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+                (0..a.len()).map(|i| a.value(i)).collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(vec![0, 1, 10, 11, 20, 21, 30, 31], values);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn split_batches_into_partitions() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![1, 2]))])?;
+        // the second partition is empty
+        let partitions = vec![vec![batch.clone(), batch.clone()], vec![], vec![batch]];
+        let table = MemTable::new(schema.clone(), partitions)?;
+        let plan = table.scan(&None, 1024)?;
+
+        let token = CancellationToken::new();
+        let pool = Arc::new(ExecutionPool::new(2));
+        let mut stream = PartitionStream::execute(schema, plan.partitions()?, &token, pool, 2);
+        let mut batches = vec![];
+        while let Some(batch) = stream.next().await? {
+            batches.push(batch);
+        }
+        assert_eq!(&[2, 2, 3], stream.partition_ends());
+        let sizes: Vec<usize> = split_partitions(batches, stream.partition_ends())
+            .iter()
+            .map(|p| p.len())
+            .collect();
+        assert_eq!(vec![2, 0, 1], sizes);
+        Ok(())
+    }
+}
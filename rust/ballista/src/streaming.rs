@@ -0,0 +1,1392 @@
+//! Streaming execution of queries over unbounded sources.
+//!
+//! Contexts created with `Context::streaming`, or with `ballista.execution.streaming` set
+//! to true, run queries continuously instead of to completion. Their sources are
+//! unbounded tables such as a `StreamTable`, whose batches are pushed by the application as
+//! they arrive, so `DataFrame::collect()` is replaced by `DataFrame::start()`, which returns
+//! a `StreamingQuery` that produces the results as the sources produce their input.
+//!
+//! Only operators that handle each batch on its own, such as projections and selections,
+//! can run over unbounded input. Aggregates and sorts wait for the whole of their input, so
+//! they can only run above a window, from `DataFrame::window()`, where they are executed on
+//! the rows of each set of windows as they close and should group by the window columns.
+//! An unbounded input can be joined with a bounded one, such as a dimension table in Parquet
+//! files, which is read once and indexed by its join keys, so that each batch of the
+//! unbounded input is joined as it arrives. The bounded input is read again at the interval
+//! set with `ballista.execution.streaming.tableRefreshInterval`, if it is set. Joins of two
+//! unbounded inputs and limits are rejected. Streaming queries only run in local contexts.
+//!
+//! Each partition of a streaming query runs on a thread of its own rather than on the
+//! execution pool of the context, because an unbounded partition would never return its
+//! thread to the pool. The batches of the partitions are interleaved in the order that they
+//! are produced.
+//!
+//! The trigger of a query, given to `DataFrame::start()` or set with
+//! `ballista.execution.streaming.trigger`, decides when the input that has arrived is
+//! processed as a micro-batch: as soon as each batch arrives, at a fixed interval, or once a
+//! number of rows has arrived. Windows are closed and the operators above them executed once
+//! for each micro-batch, and the results of other queries are released a micro-batch at a
+//! time.
+//!
+//! The results of a query are published continuously with `StreamingQuery::write_to()`,
+//! which passes them to a `StreamSink`, such as a Kafka topic or a directory of Parquet
+//! files, and flushes the sink at an interval. Delivery is at least once: the batches that a
+//! sink buffers are kept until a flush succeeds, and a failed flush is retried with all of
+//! them, so a retry may publish results that a failed attempt had already published.
+//!
+//! Windowed queries save the rows of their open windows, along with the offsets that their
+//! sources had reached, to the checkpoint directory set with
+//! `ballista.execution.streaming.checkpointDir`, and restore them when they are started
+//! again, so that they resume where they left off. Sources record the offset of each batch on
+//! the thread that reads it, which is how the offsets of the batches that have reached the
+//! windows are known.
+//!
+//! Each query has an id and a status, and counts the rows that its sources have read and
+//! the time that its micro-batches take to process. A `StreamingQueryHandle` follows them
+//! and stops the query, or waits for it to terminate, while the results are received
+//! elsewhere, and `Context::streaming_queries()` lists the handles of the active queries of
+//! a context.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::arrow::datatypes::{Schema, SchemaRef};
+use crate::arrow::error::Result as ArrowResult;
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::cancel::CancellationToken;
+use crate::checkpoint::Checkpointer;
+use crate::datafusion::datasource::TableProvider;
+use crate::datafusion::error::Result as DataFusionResult;
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::datasource::ipc::ProjectedReader;
+use crate::datasource::table::TableRegistry;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::join::JoinIndex;
+use crate::logicalplan::LogicalPlan;
+use crate::trace;
+use crate::visitor::{PlanVisitor, Recursion};
+use crate::window::{find_window, watermark_of, window_of, WindowAssigner};
+
+use futures::executor::block_on;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+
+/// Setting that switches a context into streaming mode
+pub const STREAMING_MODE: &str = "ballista.execution.streaming";
+
+/// Setting with the default trigger of the streaming queries of a context, in the form
+/// parsed by `Trigger::parse()`
+pub const STREAMING_TRIGGER: &str = "ballista.execution.streaming.trigger";
+
+/// Setting with the interval at which the bounded inputs of joins with unbounded inputs are
+/// read again, such as `10m`. They are only read once if it is not set.
+pub const STREAMING_TABLE_REFRESH: &str = "ballista.execution.streaming.tableRefreshInterval";
+
+/// Number of batches that a `StreamTable` holds before `StreamSender::send` blocks
+const SOURCE_BUFFER_BATCHES: usize = 16;
+
+/// Number of results that a streaming query produces ahead of its consumer
+const RESULT_BUFFER_BATCHES: usize = 16;
+
+/// How long to wait before the first retry of a failed flush, doubling for each retry
+const FLUSH_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// A batch of results, with the offsets that the sources reached by the time it was produced
+type Results = Result<(RecordBatch, Vec<SourceOffset>)>;
+
+/// The position that a partition of a source has reached, such as the offset of the next
+/// message of a Kafka partition
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceOffset {
+    pub source: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+thread_local! {
+    /// The offsets that the sources read on this thread have reached since they were taken
+    static REACHED_OFFSETS: RefCell<Vec<SourceOffset>> = RefCell::new(vec![]);
+    /// The offsets that the sources read on this thread resume from
+    static RESUME_OFFSETS: RefCell<Vec<SourceOffset>> = RefCell::new(vec![]);
+}
+
+/// Record the offset that a partition of a source has reached, once the batches before it
+/// have been read. Sources record their offsets on the thread that reads them, so that the
+/// offsets are saved in the checkpoints of windowed queries.
+pub fn record_source_offset(source: &str, partition: i32, offset: i64) {
+    REACHED_OFFSETS.with(|offsets| {
+        offsets.borrow_mut().push(SourceOffset {
+            source: source.to_owned(),
+            partition,
+            offset,
+        })
+    });
+}
+
+/// The offset that a partition of a source resumes from, if it is read on the thread of a
+/// query that was restored from a checkpoint
+pub fn resume_offset(source: &str, partition: i32) -> Option<i64> {
+    RESUME_OFFSETS.with(|offsets| {
+        offsets
+            .borrow()
+            .iter()
+            .find(|o| o.source == source && o.partition == partition)
+            .map(|o| o.offset)
+    })
+}
+
+/// When a streaming query processes the input that has arrived
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Process each batch as soon as it arrives
+    AsFastAsPossible,
+    /// Process the input that has arrived at a fixed interval
+    Interval(Duration),
+    /// Process the input once at least the given number of rows has arrived, or the input
+    /// has ended
+    Records(usize),
+}
+
+impl Trigger {
+    /// Parse a trigger from a setting: `fast`, an interval such as `500ms`, `10s` or `5m`,
+    /// or a number of rows such as `1000 records`
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let trigger = if value == "fast" {
+            Some(Trigger::AsFastAsPossible)
+        } else if value.ends_with("records") {
+            positive_number(value, "records").map(|n| Trigger::Records(n as usize))
+        } else {
+            duration(value).map(Trigger::Interval)
+        };
+        trigger.ok_or_else(|| BallistaError::General(format!("Invalid trigger: {}", value)))
+    }
+}
+
+/// Parse a positive duration such as `500ms`, `10s` or `5m`
+pub(crate) fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    duration(value).ok_or_else(|| BallistaError::General(format!("Invalid duration: {}", value)))
+}
+
+fn duration(value: &str) -> Option<Duration> {
+    if value.ends_with("ms") {
+        positive_number(value, "ms").map(Duration::from_millis)
+    } else if value.ends_with('s') {
+        positive_number(value, "s").map(Duration::from_secs)
+    } else if value.ends_with('m') {
+        positive_number(value, "m").map(|n| Duration::from_secs(n * 60))
+    } else {
+        None
+    }
+}
+
+fn positive_number(value: &str, suffix: &str) -> Option<u64> {
+    value[..value.len() - suffix.len()]
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|n| *n > 0)
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Trigger::AsFastAsPossible
+    }
+}
+
+/// A destination that the results of a streaming query are published to
+pub trait StreamSink: Send {
+    /// Buffer a batch of results
+    fn write(&mut self, batch: &RecordBatch) -> Result<()>;
+
+    /// Publish the buffered batches, returning once they are durable. The batches remain
+    /// buffered if the flush fails, so that the next flush publishes them again.
+    fn flush(&mut self) -> Result<()>;
+}
+
+/// How the results of a streaming query are flushed to a sink
+#[derive(Debug, Clone)]
+pub struct SinkOptions {
+    /// How often the buffered results are flushed
+    pub flush_interval: Duration,
+    /// How many times a flush is attempted before the query fails
+    pub max_flush_attempts: usize,
+}
+
+impl SinkOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    pub fn with_max_flush_attempts(mut self, max_flush_attempts: usize) -> Self {
+        self.max_flush_attempts = max_flush_attempts.max(1);
+        self
+    }
+}
+
+impl Default for SinkOptions {
+    fn default() -> Self {
+        Self {
+            flush_interval: Duration::from_secs(10),
+            max_flush_attempts: 3,
+        }
+    }
+}
+
+/// An unbounded table whose batches are pushed with its `StreamSender`. The table is
+/// exhausted once the sender is dropped. Each batch is scanned by one query, so a table
+/// should only be read by one running query at a time.
+pub struct StreamTable {
+    schema: SchemaRef,
+    receiver: Arc<Mutex<Receiver<RecordBatch>>>,
+}
+
+impl StreamTable {
+    /// Create a table with the given schema, along with the sender of its batches
+    pub fn new(schema: SchemaRef) -> (Self, StreamSender) {
+        let (tx, rx) = sync_channel(SOURCE_BUFFER_BATCHES);
+        let table = Self {
+            schema: schema.clone(),
+            receiver: Arc::new(Mutex::new(rx)),
+        };
+        (table, StreamSender { schema, tx })
+    }
+}
+
+impl TableProvider for StreamTable {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+    ) -> DataFusionResult<Vec<Arc<dyn Partition>>> {
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        ));
+        Ok(vec![Arc::new(StreamPartition {
+            table_schema: self.schema.clone(),
+            receiver: self.receiver.clone(),
+            projection,
+            schema,
+        }) as Arc<dyn Partition>])
+    }
+}
+
+/// Pushes batches into a `StreamTable`
+#[derive(Clone)]
+pub struct StreamSender {
+    schema: SchemaRef,
+    tx: SyncSender<RecordBatch>,
+}
+
+impl StreamSender {
+    /// Push a batch into the table, waiting while the queries that read it are behind.
+    /// Fails if the batch has a different schema or the table has been dropped.
+    pub fn send(&self, batch: RecordBatch) -> Result<()> {
+        if batch.schema() != self.schema {
+            return Err(ballista_error(
+                "Batches must have the schema of their stream",
+            ));
+        }
+        self.tx
+            .send(batch)
+            .map_err(|_| ballista_error("The stream table has been dropped"))
+    }
+}
+
+/// The only partition of a `StreamTable`
+struct StreamPartition {
+    table_schema: SchemaRef,
+    receiver: Arc<Mutex<Receiver<RecordBatch>>>,
+    projection: Vec<usize>,
+    /// The schema of the projected columns
+    schema: SchemaRef,
+}
+
+impl Partition for StreamPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let reader = StreamReader {
+            schema: self.table_schema.clone(),
+            receiver: self.receiver.clone(),
+        };
+        Ok(Arc::new(Mutex::new(ProjectedReader::new(
+            Box::new(reader),
+            self.projection.clone(),
+            self.schema.clone(),
+        ))))
+    }
+}
+
+/// Reads the batches of a `StreamTable`, blocking until the next one is pushed
+struct StreamReader {
+    schema: SchemaRef,
+    receiver: Arc<Mutex<Receiver<RecordBatch>>>,
+}
+
+impl RecordBatchReader for StreamReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        Ok(self.receiver.lock().unwrap().recv().ok())
+    }
+}
+
+/// A running streaming query. The query stops when it is stopped or dropped, once each of
+/// its partitions produces its next batch, and ends once all of its sources are exhausted.
+pub struct StreamingQuery {
+    schema: SchemaRef,
+    rx: mpsc::Receiver<Results>,
+    token: CancellationToken,
+    state: Arc<QueryState>,
+    /// The rows that arrive after their windows have closed, if they are routed
+    late: Option<Box<StreamingQuery>>,
+    /// Whether all of the results have been received
+    ended: bool,
+}
+
+impl StreamingQuery {
+    /// Start executing the partitions of a query, each on a thread of its own
+    pub fn start(schema: SchemaRef, partitions: Vec<Arc<dyn Partition>>) -> Result<Self> {
+        Self::start_from(schema, partitions, &[])
+    }
+
+    /// Start executing the partitions of a query, with their sources resuming from the given
+    /// offsets
+    pub fn start_from(
+        schema: SchemaRef,
+        partitions: Vec<Arc<dyn Partition>>,
+        resume: &[SourceOffset],
+    ) -> Result<Self> {
+        let (tx, rx) = mpsc::channel(RESULT_BUFFER_BATCHES);
+        let token = CancellationToken::new();
+        let state = Arc::new(QueryState::new(trace::new_query_id()));
+        // the query is held open until all of its partitions have started
+        state.thread_started();
+        for (i, partition) in partitions.into_iter().enumerate() {
+            let mut tx = tx.clone();
+            let token = token.clone();
+            let resume = resume.to_vec();
+            let state = state.clone();
+            state.thread_started();
+            thread::Builder::new()
+                .name(format!("ballista-stream-{}", i))
+                .spawn(move || {
+                    RESUME_OFFSETS.with(|offsets| *offsets.borrow_mut() = resume);
+                    let result = stream_partition(partition.as_ref(), &token, &state, &mut tx);
+                    end_thread(&mut tx, &state, result);
+                })?;
+        }
+        state.thread_ended();
+        Ok(Self {
+            schema,
+            rx,
+            token,
+            state,
+            late: None,
+            ended: false,
+        })
+    }
+
+    /// Start assigning the results of a query to windows on a thread of its own, executing
+    /// the rest of the query on the rows of the windows that close after each micro-batch,
+    /// and saving the open windows to the checkpoints of the checkpointer if there is one
+    pub fn start_windowed<F>(
+        input: StreamingQuery,
+        trigger: Trigger,
+        mut assigner: WindowAssigner,
+        mut checkpointer: Option<Checkpointer>,
+        schema: SchemaRef,
+        execute: F,
+    ) -> Result<Self>
+    where
+        F: Fn(Vec<RecordBatch>) -> Result<Vec<RecordBatch>> + Send + 'static,
+    {
+        let (mut tx, rx) = mpsc::channel(RESULT_BUFFER_BATCHES);
+        let token = input.token.clone();
+        let window_token = token.clone();
+        let state = Arc::new(QueryState::wrap(&input.state));
+        let window_state = state.clone();
+        let (mut late_tx, late) = if assigner.routes_late_records() {
+            let (late_tx, late_rx) = mpsc::channel(RESULT_BUFFER_BATCHES);
+            let late = StreamingQuery {
+                schema: input.schema(),
+                rx: late_rx,
+                token: CancellationToken::new(),
+                state: Arc::new(QueryState::new(trace::new_query_id())),
+                late: None,
+                ended: false,
+            };
+            (Some(late_tx), Some(Box::new(late)))
+        } else {
+            (None, None)
+        };
+        let mut micro_batches = MicroBatches::new(input, trigger);
+        state.thread_started();
+        thread::Builder::new()
+            .name("ballista-stream-window".to_owned())
+            .spawn(move || {
+                let mut run = || -> Result<()> {
+                    let mut runtime = micro_batch_runtime()?;
+                    while let Some((batches, offsets)) = runtime.block_on(micro_batches.next())? {
+                        let started = Instant::now();
+                        for batch in &batches {
+                            assigner.push(batch)?;
+                        }
+                        if let Some(late_tx) = &mut late_tx {
+                            // the late rows are discarded once their query is dropped
+                            send_all(late_tx, assigner.take_late(), vec![]);
+                        }
+                        let closed = assigner.close_ready()?;
+                        if !closed.is_empty() && !send_all(&mut tx, execute(closed)?, vec![]) {
+                            return Ok(());
+                        }
+                        if let Some(checkpointer) = &mut checkpointer {
+                            checkpointer.advance(offsets);
+                            checkpointer.save_if_due(&assigner)?;
+                        }
+                        window_state.record_micro_batch(started.elapsed());
+                        window_token.check()?;
+                    }
+                    // the input also ends when the query is stopped
+                    window_token.check()?;
+                    let closed = assigner.finish()?;
+                    if !closed.is_empty() {
+                        send_all(&mut tx, execute(closed)?, vec![]);
+                    }
+                    // the windows have all been produced, so none are restored
+                    if let Some(checkpointer) = &mut checkpointer {
+                        checkpointer.save(&assigner)?;
+                    }
+                    Ok(())
+                };
+                let result = run();
+                end_thread(&mut tx, &window_state, result);
+            })?;
+        Ok(Self {
+            schema,
+            rx,
+            token,
+            state,
+            late,
+            ended: false,
+        })
+    }
+
+    /// Start joining the results of a query with the rows of a bounded table on a thread of
+    /// its own, executing the rest of the query on the joined rows of each batch. The table
+    /// is loaded when the thread starts and loaded again once the refresh interval of the
+    /// join has passed, keeping the rows that were loaded last if loading fails.
+    pub fn start_joined<L, F>(
+        mut input: StreamingQuery,
+        join: TableJoin,
+        load: L,
+        schema: SchemaRef,
+        execute: F,
+    ) -> Result<Self>
+    where
+        L: Fn() -> Result<Vec<RecordBatch>> + Send + 'static,
+        F: Fn(Vec<RecordBatch>) -> Result<Vec<RecordBatch>> + Send + 'static,
+    {
+        let (mut tx, rx) = mpsc::channel(RESULT_BUFFER_BATCHES);
+        let token = input.token.clone();
+        let late = input.late.take();
+        let state = Arc::new(QueryState::wrap(&input.state));
+        let join_state = state.clone();
+        let (input_columns, table_columns): (Vec<usize>, Vec<usize>) =
+            join.on.iter().cloned().unzip();
+        state.thread_started();
+        thread::Builder::new()
+            .name("ballista-stream-join".to_owned())
+            .spawn(move || {
+                let mut run = || -> Result<()> {
+                    let nulls = join.null_equals_null;
+                    let mut index = JoinIndex::try_new(load()?, &table_columns, nulls)?;
+                    let mut loaded = Instant::now();
+                    // the offsets of batches without matching rows are sent with the next
+                    // results
+                    let mut offsets = vec![];
+                    while let Some((batch, reached)) = block_on(input.next_with_offsets())? {
+                        let started = Instant::now();
+                        if join.refresh.map_or(false, |r| loaded.elapsed() >= r) {
+                            match load().and_then(|b| JoinIndex::try_new(b, &table_columns, nulls))
+                            {
+                                Ok(reloaded) => index = reloaded,
+                                Err(e) => warn!("Failed to reload a streaming join table: {:?}", e),
+                            }
+                            loaded = Instant::now();
+                        }
+                        offsets.extend(reached);
+                        let joined =
+                            index.join(&batch, &input_columns, &join.schema, join.table_first)?;
+                        if joined.is_empty() {
+                            continue;
+                        }
+                        if !send_all(&mut tx, execute(joined)?, std::mem::take(&mut offsets)) {
+                            return Ok(());
+                        }
+                        join_state.record_micro_batch(started.elapsed());
+                    }
+                    Ok(())
+                };
+                let result = run();
+                end_thread(&mut tx, &join_state, result);
+            })?;
+        Ok(Self {
+            schema,
+            rx,
+            token,
+            state,
+            late,
+            ended: false,
+        })
+    }
+
+    /// Release the results of the query a micro-batch at a time, as the trigger fires
+    pub fn with_trigger(mut self, trigger: Trigger) -> Result<Self> {
+        if trigger == Trigger::AsFastAsPossible {
+            return Ok(self);
+        }
+        let (mut tx, rx) = mpsc::channel(RESULT_BUFFER_BATCHES);
+        let schema = self.schema();
+        let token = self.token.clone();
+        let late = self.late.take();
+        let state = Arc::new(QueryState::wrap(&self.state));
+        let trigger_state = state.clone();
+        let mut micro_batches = MicroBatches::new(self, trigger);
+        state.thread_started();
+        thread::Builder::new()
+            .name("ballista-stream-trigger".to_owned())
+            .spawn(move || {
+                let mut run = || -> Result<()> {
+                    let mut runtime = micro_batch_runtime()?;
+                    while let Some((batches, offsets)) = runtime.block_on(micro_batches.next())? {
+                        let started = Instant::now();
+                        if !send_all(&mut tx, batches, offsets) {
+                            return Ok(());
+                        }
+                        trigger_state.record_micro_batch(started.elapsed());
+                    }
+                    Ok(())
+                };
+                let result = run();
+                end_thread(&mut tx, &trigger_state, result);
+            })?;
+        Ok(Self {
+            schema,
+            rx,
+            token,
+            state,
+            late,
+            ended: false,
+        })
+    }
+
+    /// The schema of the results
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// The id of the query
+    pub fn id(&self) -> &str {
+        &self.state.id
+    }
+
+    /// A handle that follows the status and progress of the query and can stop it, which
+    /// can be shared while the results are received
+    pub fn handle(&self) -> StreamingQueryHandle {
+        StreamingQueryHandle {
+            state: self.state.clone(),
+            token: self.token.clone(),
+        }
+    }
+
+    pub fn status(&self) -> StreamingQueryStatus {
+        self.state.status()
+    }
+
+    pub fn progress(&self) -> StreamingQueryProgress {
+        self.state.progress()
+    }
+
+    /// Receive the next batch of results, returning `None` once the query has ended
+    pub async fn next(&mut self) -> Result<Option<RecordBatch>> {
+        Ok(self.next_with_offsets().await?.map(|(batch, _)| batch))
+    }
+
+    /// Receive the next batch of results, with the offsets that the sources had reached by
+    /// the time it was produced
+    async fn next_with_offsets(&mut self) -> Result<Option<(RecordBatch, Vec<SourceOffset>)>> {
+        if self.token.is_cancelled() {
+            return Ok(None);
+        }
+        let next = self.rx.recv().await.transpose()?;
+        match &next {
+            Some((batch, _)) => self.state.record_output(batch.num_rows()),
+            None => self.ended = true,
+        }
+        Ok(next)
+    }
+
+    /// Take the query that receives the rows that arrive after their windows have closed,
+    /// if the window of the query routes them. The query waits for each batch of late rows
+    /// to be received, so the returned query must be read or dropped.
+    pub fn late_records(&mut self) -> Option<StreamingQuery> {
+        self.late.take().map(|late| *late)
+    }
+
+    /// Stop the query
+    pub fn stop(&self) {
+        self.handle().stop();
+    }
+
+    /// Publish the results of the query to a sink until the query ends, flushing the sink at
+    /// the interval of the options and once the query ends, returning the number of rows
+    /// that were published. The results that have not been flushed are discarded if the
+    /// query fails or the returned future is dropped, which stops the query.
+    pub async fn write_to<S: StreamSink>(
+        mut self,
+        mut sink: S,
+        options: SinkOptions,
+    ) -> Result<usize> {
+        let mut published = 0;
+        let mut buffered = 0;
+        let mut last_flush = Instant::now();
+        loop {
+            let wait = options
+                .flush_interval
+                .checked_sub(last_flush.elapsed())
+                .unwrap_or_default();
+            let ended = match tokio::time::timeout(wait, self.next()).await {
+                Ok(next) => match next? {
+                    Some(batch) => {
+                        sink.write(&batch)?;
+                        buffered += batch.num_rows();
+                        false
+                    }
+                    None => true,
+                },
+                // the flush interval has passed
+                Err(_) => false,
+            };
+            if ended || last_flush.elapsed() >= options.flush_interval {
+                flush_sink(&mut sink, &options).await?;
+                published += buffered;
+                buffered = 0;
+                last_flush = Instant::now();
+            }
+            if ended {
+                return Ok(published);
+            }
+        }
+    }
+}
+
+/// Flush a sink, retrying failed flushes up to the maximum attempts of the options
+async fn flush_sink<S: StreamSink>(sink: &mut S, options: &SinkOptions) -> Result<()> {
+    let mut delay = FLUSH_RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match sink.flush() {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= options.max_flush_attempts => return Err(e),
+            Err(e) => {
+                warn!(
+                    "Flush {} of a streaming sink failed, retrying: {:?}",
+                    attempt, e
+                );
+                tokio::time::delay_for(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+}
+
+impl Drop for StreamingQuery {
+    fn drop(&mut self) {
+        // the queries that read the results of a query that has ended do not stop it
+        if !self.ended {
+            self.stop();
+        }
+    }
+}
+
+/// The status of a streaming query
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamingQueryStatus {
+    Active,
+    /// The query was stopped, or the handle that received its results was dropped
+    Stopped,
+    /// The sources of the query were exhausted and all of its results were produced
+    Finished,
+    /// The query failed with the given error
+    Failed(String),
+}
+
+impl StreamingQueryStatus {
+    pub fn is_active(&self) -> bool {
+        *self == StreamingQueryStatus::Active
+    }
+}
+
+/// The progress of a streaming query since it started
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamingQueryProgress {
+    /// Rows read from the sources
+    pub input_rows: u64,
+    /// Rows read from the sources per second since the query started
+    pub input_rows_per_second: f64,
+    /// Rows of results that have been received
+    pub output_rows: u64,
+    /// Micro-batches that have been processed. Queries without a trigger, window or join
+    /// produce each batch of results as it is read, without micro-batches.
+    pub micro_batches: u64,
+    /// How long the last micro-batch took to process, from receiving its input to
+    /// producing its results
+    pub last_batch_duration: Option<Duration>,
+    /// How long micro-batches took to process on average
+    pub average_batch_duration: Option<Duration>,
+    /// How long the query has been running
+    pub elapsed: Duration,
+}
+
+/// Follows the status and progress of a streaming query, and stops it
+#[derive(Clone)]
+pub struct StreamingQueryHandle {
+    state: Arc<QueryState>,
+    token: CancellationToken,
+}
+
+impl StreamingQueryHandle {
+    pub fn id(&self) -> &str {
+        &self.state.id
+    }
+
+    pub fn status(&self) -> StreamingQueryStatus {
+        self.state.status()
+    }
+
+    pub fn progress(&self) -> StreamingQueryProgress {
+        self.state.progress()
+    }
+
+    /// Stop the query, once each of its partitions produces its next batch
+    pub fn stop(&self) {
+        self.token.cancel();
+        self.state.set_status(StreamingQueryStatus::Stopped);
+    }
+
+    /// Wait for the query to stop or end, returning the error of a query that failed. The
+    /// results of the query must be received for it to make progress.
+    pub async fn await_termination(&self) -> Result<()> {
+        let mut status = self.state.status_rx.clone();
+        loop {
+            let current = status.borrow().clone();
+            match current {
+                StreamingQueryStatus::Active => {
+                    if status.recv().await.is_none() {
+                        return Ok(());
+                    }
+                }
+                StreamingQueryStatus::Failed(e) => return Err(BallistaError::General(e)),
+                StreamingQueryStatus::Stopped | StreamingQueryStatus::Finished => return Ok(()),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for StreamingQueryHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StreamingQueryHandle")
+            .field("id", &self.state.id)
+            .field("status", &self.status())
+            .finish()
+    }
+}
+
+/// The streaming queries that have been started by a context
+#[derive(Debug, Default)]
+pub struct StreamingQueries {
+    queries: Mutex<Vec<StreamingQueryHandle>>,
+}
+
+impl StreamingQueries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, query: StreamingQueryHandle) {
+        let mut queries = self.queries.lock().unwrap();
+        queries.retain(|q| q.status().is_active());
+        queries.push(query);
+    }
+
+    /// The queries that are still active, in the order that they were started
+    pub fn active(&self) -> Vec<StreamingQueryHandle> {
+        let mut queries = self.queries.lock().unwrap();
+        queries.retain(|q| q.status().is_active());
+        queries.clone()
+    }
+}
+
+/// The status and progress of a streaming query, which is updated by the threads of the
+/// query
+struct QueryState {
+    id: String,
+    started: Instant,
+    /// The rows read from the sources, which is shared with the queries that read the
+    /// results of this one
+    input_rows: Arc<AtomicU64>,
+    output_rows: AtomicU64,
+    micro_batches: Mutex<(u64, Duration, Option<Duration>)>,
+    status_tx: Mutex<watch::Sender<StreamingQueryStatus>>,
+    status_rx: watch::Receiver<StreamingQueryStatus>,
+    /// The threads that produce the results of the query
+    running: AtomicUsize,
+}
+
+impl QueryState {
+    fn new(id: String) -> Self {
+        Self::with_input_rows(id, Instant::now(), Arc::new(AtomicU64::new(0)))
+    }
+
+    /// The state of a query that reads the results of another query, with its id and
+    /// input rows
+    fn wrap(input: &QueryState) -> Self {
+        Self::with_input_rows(input.id.clone(), input.started, input.input_rows.clone())
+    }
+
+    fn with_input_rows(id: String, started: Instant, input_rows: Arc<AtomicU64>) -> Self {
+        let (status_tx, status_rx) = watch::channel(StreamingQueryStatus::Active);
+        Self {
+            id,
+            started,
+            input_rows,
+            output_rows: AtomicU64::new(0),
+            micro_batches: Mutex::new((0, Duration::default(), None)),
+            status_tx: Mutex::new(status_tx),
+            status_rx,
+            running: AtomicUsize::new(0),
+        }
+    }
+
+    fn status(&self) -> StreamingQueryStatus {
+        self.status_rx.borrow().clone()
+    }
+
+    /// Move an active query to another status
+    fn set_status(&self, status: StreamingQueryStatus) {
+        let status_tx = self.status_tx.lock().unwrap();
+        if self.status().is_active() {
+            // the receiver of the state is never dropped, so the broadcast succeeds
+            let _ = status_tx.broadcast(status);
+        }
+    }
+
+    fn thread_started(&self) {
+        self.running.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn thread_ended(&self) {
+        if self.running.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.set_status(StreamingQueryStatus::Finished);
+        }
+    }
+
+    fn record_input(&self, rows: usize) {
+        self.input_rows.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    fn record_output(&self, rows: usize) {
+        self.output_rows.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    fn record_micro_batch(&self, duration: Duration) {
+        let mut micro_batches = self.micro_batches.lock().unwrap();
+        micro_batches.0 += 1;
+        micro_batches.1 += duration;
+        micro_batches.2 = Some(duration);
+    }
+
+    fn progress(&self) -> StreamingQueryProgress {
+        let (count, total, last) = *self.micro_batches.lock().unwrap();
+        let elapsed = self.started.elapsed();
+        let input_rows = self.input_rows.load(Ordering::Relaxed);
+        StreamingQueryProgress {
+            input_rows,
+            input_rows_per_second: input_rows as f64 / elapsed.as_secs_f64().max(1e-3),
+            output_rows: self.output_rows.load(Ordering::Relaxed),
+            micro_batches: count,
+            last_batch_duration: last,
+            average_batch_duration: if count > 0 {
+                Some(total / count as u32)
+            } else {
+                None
+            },
+            elapsed,
+        }
+    }
+}
+
+/// Execute a partition, sending its batches until it is exhausted or the query stops
+fn stream_partition(
+    partition: &dyn Partition,
+    token: &CancellationToken,
+    state: &QueryState,
+    tx: &mut mpsc::Sender<Results>,
+) -> Result<()> {
+    let reader = partition.execute()?;
+    let mut reader = reader.lock().unwrap();
+    loop {
+        token.check()?;
+        match reader.next_batch()? {
+            Some(batch) => {
+                state.record_input(batch.num_rows());
+                let offsets = REACHED_OFFSETS.with(|offsets| offsets.replace(vec![]));
+                if block_on(tx.send(Ok((batch, offsets)))).is_err() {
+                    return Ok(());
+                }
+            }
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Receives the results of a query a micro-batch at a time
+struct MicroBatches {
+    input: StreamingQuery,
+    trigger: Trigger,
+    /// When an interval trigger fires next
+    next_interval: Instant,
+}
+
+impl MicroBatches {
+    fn new(input: StreamingQuery, trigger: Trigger) -> Self {
+        let next_interval = match trigger {
+            Trigger::Interval(interval) => Instant::now() + interval,
+            _ => Instant::now(),
+        };
+        Self {
+            input,
+            trigger,
+            next_interval,
+        }
+    }
+
+    /// Receive the batches of the next micro-batch, with the offsets that the sources had
+    /// reached by the time they were produced, returning `None` once the input has ended.
+    /// The micro-batches of an interval trigger may be empty.
+    async fn next(&mut self) -> Result<Option<(Vec<RecordBatch>, Vec<SourceOffset>)>> {
+        let mut batches = vec![];
+        let mut offsets = vec![];
+        match self.trigger {
+            Trigger::AsFastAsPossible => Ok(self
+                .input
+                .next_with_offsets()
+                .await?
+                .map(|(batch, offsets)| (vec![batch], offsets))),
+            Trigger::Records(min_rows) => {
+                let mut rows = 0;
+                while rows < min_rows {
+                    match self.input.next_with_offsets().await? {
+                        Some((batch, reached)) => {
+                            rows += batch.num_rows();
+                            batches.push(batch);
+                            offsets.extend(reached);
+                        }
+                        None => break,
+                    }
+                }
+                Ok(Some((batches, offsets)).filter(|(batches, _)| !batches.is_empty()))
+            }
+            Trigger::Interval(interval) => loop {
+                let wait = self.next_interval.saturating_duration_since(Instant::now());
+                match tokio::time::timeout(wait, self.input.next_with_offsets()).await {
+                    Ok(next) => match next? {
+                        Some((batch, reached)) => {
+                            batches.push(batch);
+                            offsets.extend(reached);
+                        }
+                        None => {
+                            let micro_batch = (batches, offsets);
+                            return Ok(Some(micro_batch).filter(|(batches, _)| !batches.is_empty()));
+                        }
+                    },
+                    Err(_) => {
+                        // skip the intervals that were missed while processing was behind
+                        self.next_interval =
+                            (self.next_interval + interval).max(Instant::now() + interval);
+                        return Ok(Some((batches, offsets)));
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// A runtime for the timers of the micro-batches that a thread receives
+fn micro_batch_runtime() -> Result<tokio::runtime::Runtime> {
+    Ok(tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_time()
+        .build()?)
+}
+
+/// End a thread of a query, sending its error unless the query was stopped
+fn end_thread(tx: &mut mpsc::Sender<Results>, state: &QueryState, result: Result<()>) {
+    match result {
+        Ok(()) => {}
+        Err(e) if e.is_cancelled() => {}
+        Err(e) => {
+            state.set_status(StreamingQueryStatus::Failed(format!("{:?}", e)));
+            let _ = block_on(tx.send(Err(e)));
+        }
+    }
+    state.thread_ended();
+}
+
+/// Send batches of results, with the offsets that the sources had reached by the time they
+/// were produced, returning false if the query has been dropped
+fn send_all(
+    tx: &mut mpsc::Sender<Results>,
+    batches: Vec<RecordBatch>,
+    offsets: Vec<SourceOffset>,
+) -> bool {
+    let mut offsets = Some(offsets);
+    let last = batches.len().saturating_sub(1);
+    batches.into_iter().enumerate().all(|(i, batch)| {
+        let offsets = if i == last { offsets.take() } else { None };
+        block_on(tx.send(Ok((batch, offsets.unwrap_or_default())))).is_ok()
+    })
+}
+
+/// How the results of a query are joined with the rows of a bounded table
+#[derive(Debug, Clone)]
+pub struct TableJoin {
+    /// Pairs of column indices in the results and the table that must be equal
+    pub on: Vec<(usize, usize)>,
+    /// Whether null values of the join columns match each other
+    pub null_equals_null: bool,
+    /// Whether the columns of the table come before the columns of the results
+    pub table_first: bool,
+    /// The schema of the joined rows
+    pub schema: SchemaRef,
+    /// How often the table is loaded again, if it is
+    pub refresh: Option<Duration>,
+}
+
+/// Whether a plan scans an unbounded table
+pub fn is_unbounded(plan: &LogicalPlan, tables: &TableRegistry) -> bool {
+    match plan {
+        LogicalPlan::TableScan { table_name, .. } => tables.is_unbounded(table_name),
+        other => other
+            .inputs()
+            .iter()
+            .any(|input| is_unbounded(input, tables)),
+    }
+}
+
+/// The first join of a plan that has an unbounded input, with its unbounded input, its
+/// bounded input and how the two are joined
+pub(crate) fn find_stream_join<'a>(
+    plan: &'a LogicalPlan,
+    tables: &TableRegistry,
+    refresh: Option<Duration>,
+) -> Option<(&'a LogicalPlan, &'a LogicalPlan, TableJoin)> {
+    if let LogicalPlan::Join {
+        left,
+        right,
+        on,
+        null_equals_null,
+        schema,
+    } = plan
+    {
+        let table_first = !is_unbounded(left, tables);
+        if !table_first || is_unbounded(right, tables) {
+            let (input, table, on) = if table_first {
+                (right, left, on.iter().map(|(l, r)| (*r, *l)).collect())
+            } else {
+                (left, right, on.clone())
+            };
+            let join = TableJoin {
+                on,
+                null_equals_null: *null_equals_null,
+                table_first,
+                schema: Arc::new(schema.clone()),
+                refresh,
+            };
+            return Some((input.as_ref(), table.as_ref(), join));
+        }
+    }
+    plan.inputs()
+        .into_iter()
+        .find_map(|input| find_stream_join(input, tables, refresh))
+}
+
+/// Replace the first join of a plan that has an unbounded input with a scan of the rows
+/// that it joined
+pub(crate) fn scan_joined(
+    plan: &LogicalPlan,
+    tables: &TableRegistry,
+    batches: Vec<RecordBatch>,
+) -> LogicalPlan {
+    match plan {
+        LogicalPlan::Join { .. } if is_unbounded(plan, tables) => LogicalPlan::MemoryScan(batches),
+        other => {
+            let mut batches = Some(batches);
+            let inputs = other
+                .inputs()
+                .into_iter()
+                .map(|input| match batches.take() {
+                    Some(b) if is_unbounded(input, tables) => scan_joined(input, tables, b),
+                    taken => {
+                        batches = taken;
+                        input.clone()
+                    }
+                })
+                .collect();
+            other.with_new_inputs(inputs)
+        }
+    }
+}
+
+/// Check that every operator of a plan can run over unbounded input. A plan may have one
+/// window, above which aggregates and sorts are allowed, and operators that only read
+/// bounded tables are not restricted.
+pub fn validate_streaming_plan(plan: &LogicalPlan, tables: &TableRegistry) -> Result<()> {
+    struct Validator<'a> {
+        tables: &'a TableRegistry,
+        windowed: bool,
+        /// Whether the operators being visited are below the window
+        below_window: bool,
+        unsupported: Option<&'static str>,
+    }
+
+    impl PlanVisitor for Validator<'_> {
+        fn pre_visit(&mut self, plan: &LogicalPlan) -> Result<Recursion> {
+            if watermark_of(plan)?.is_some() {
+                return Ok(Recursion::Continue);
+            }
+            if window_of(plan)?.is_some() {
+                if self.below_window {
+                    self.unsupported = Some("More than one window");
+                    return Ok(Recursion::Stop);
+                }
+                self.below_window = true;
+                return Ok(Recursion::Continue);
+            }
+            // bounded inputs are executed to completion
+            if !is_unbounded(plan, self.tables) {
+                return Ok(Recursion::Skip);
+            }
+            match plan {
+                LogicalPlan::Join { left, right, .. } => {
+                    let above_window = self.windowed && !self.below_window;
+                    // above a window, an input with the window is bounded
+                    let unbounded = [left, right]
+                        .iter()
+                        .filter(|input| {
+                            is_unbounded(input, self.tables)
+                                && !(above_window && find_window(input).ok().flatten().is_some())
+                        })
+                        .count();
+                    if unbounded == 2 || (above_window && unbounded == 1) {
+                        self.unsupported = Some("A join of two unbounded inputs");
+                        return Ok(Recursion::Stop);
+                    }
+                    Ok(Recursion::Continue)
+                }
+                LogicalPlan::Projection { .. }
+                | LogicalPlan::Selection { .. }
+                | LogicalPlan::TableScan { .. }
+                | LogicalPlan::FileScan { .. }
+                | LogicalPlan::MemoryScan(_)
+                | LogicalPlan::EmptyRelation { .. } => Ok(Recursion::Continue),
+                LogicalPlan::Aggregate { .. } | LogicalPlan::Sort { .. }
+                    if self.windowed && !self.below_window =>
+                {
+                    Ok(Recursion::Continue)
+                }
+                other => {
+                    self.unsupported = Some(other.operator_name());
+                    Ok(Recursion::Stop)
+                }
+            }
+        }
+    }
+
+    let mut validator = Validator {
+        tables,
+        windowed: find_window(plan)?.is_some(),
+        below_window: false,
+        unsupported: None,
+    };
+    plan.accept(&mut validator)?;
+    match validator.unsupported {
+        Some(operator @ "Aggregate") | Some(operator @ "Sort") if !validator.windowed => {
+            Err(BallistaError::NotImplemented(format!(
+                "{} is only supported by streaming queries above a window",
+                operator
+            )))
+        }
+        Some(operator) => Err(BallistaError::NotImplemented(format!(
+            "{} is not supported by streaming queries yet",
+            operator
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{Int32Array, StringArray};
+    use crate::arrow::datatypes::{DataType, Field};
+
+    #[tokio::test]
+    async fn stream_batches_as_they_are_pushed() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let (table, sender) = StreamTable::new(schema.clone());
+        let partitions = table.scan(&None, 1024)?;
+        let mut query = StreamingQuery::start(schema.clone(), partitions)?;
+
+        for i in 0..3 {
+            let values = Int32Array::from(vec![i]);
+            sender.send(RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(values)],
+            )?)?;
+            let batch = query.next().await?.unwrap();
+            let a = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .unwrap();
+            assert_eq!(i, a.value(0));
+        }
+        // the query ends once its only source is exhausted
+        drop(sender);
+        assert!(query.next().await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_each_batch_with_a_table() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let (table, sender) = StreamTable::new(schema.clone());
+        let input = StreamingQuery::start(schema.clone(), table.scan(&None, 1024)?)?;
+        let names = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let dimension = RecordBatch::try_new(
+            names.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec!["one", "two"])),
+            ],
+        )?;
+        let joined = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, false),
+            Field::new("a", DataType::Int32, false),
+        ]));
+        let join = TableJoin {
+            on: vec![(0, 0)],
+            null_equals_null: false,
+            table_first: true,
+            schema: joined.clone(),
+            refresh: None,
+        };
+        let mut query = StreamingQuery::start_joined(
+            input,
+            join,
+            move || Ok(vec![dimension.clone()]),
+            joined,
+            Ok,
+        )?;
+
+        sender.send(RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![2, 3, 1]))],
+        )?)?;
+        drop(sender);
+        let batch = query.next().await?.unwrap();
+        let name = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let a = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        let mut rows: Vec<(String, i32)> = (0..batch.num_rows())
+            .map(|i| (name.value(i).to_owned(), a.value(i)))
+            .collect();
+        rows.sort();
+        assert_eq!(rows, vec![("one".to_owned(), 1), ("two".to_owned(), 2)]);
+        assert!(query.next().await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follow_the_status_of_a_query() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let (table, sender) = StreamTable::new(schema.clone());
+        let mut query = StreamingQuery::start(schema.clone(), table.scan(&None, 1024)?)?;
+        let handle = query.handle();
+        assert_eq!(handle.status(), StreamingQueryStatus::Active);
+
+        let values = Int32Array::from(vec![1, 2, 3]);
+        sender.send(RecordBatch::try_new(schema, vec![Arc::new(values)])?)?;
+        drop(sender);
+        while query.next().await?.is_some() {}
+        handle.await_termination().await?;
+        assert_eq!(handle.status(), StreamingQueryStatus::Finished);
+        let progress = query.progress();
+        assert_eq!((progress.input_rows, progress.output_rows), (3, 3));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_triggers() -> Result<()> {
+        assert_eq!(Trigger::parse("fast")?, Trigger::AsFastAsPossible);
+        assert_eq!(
+            Trigger::parse("500ms")?,
+            Trigger::Interval(Duration::from_millis(500))
+        );
+        assert_eq!(
+            Trigger::parse("2m")?,
+            Trigger::Interval(Duration::from_secs(120))
+        );
+        assert_eq!(Trigger::parse("1000 records")?, Trigger::Records(1000));
+        assert!(Trigger::parse("0s").is_err());
+        assert!(Trigger::parse("often").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,916 @@
+//! Import of Substrait plans as logical plans.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::datasource::csv::CsvReadOptions;
+use crate::datasource::expand_path;
+use crate::datasource::object_store::LocalFileSystem;
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::{
+    expr_to_field, Expr, JoinOptions, LogicalPlan, LogicalPlanBuilder, Operator, ScalarValue,
+};
+
+use super::protobuf::{self, expression, read_rel};
+use super::{OPERATOR_FUNCTIONS, RENAMED_FUNCTIONS};
+
+use expression::literal::LiteralType;
+use expression::RexType;
+use protobuf::data_type::Kind;
+use read_rel::local_files::file_or_files::{FileFormat, PathType};
+
+/// The names of the functions that a plan declares, by their anchors
+type Functions = HashMap<u32, String>;
+
+/// Convert a Substrait plan with a single relation to a logical plan. The columns of a
+/// root relation are named by the names of the root.
+pub fn from_substrait(plan: &protobuf::Plan) -> Result<LogicalPlan> {
+    use protobuf::simple_extension_declaration::MappingType;
+    // the names of functions may be followed by their signatures, as in `equal:any_any`
+    let functions = plan
+        .extensions
+        .iter()
+        .filter_map(|extension| match &extension.mapping_type {
+            Some(MappingType::ExtensionFunction(function)) => Some((
+                function.function_anchor,
+                function
+                    .name
+                    .split(':')
+                    .next()
+                    .unwrap_or_default()
+                    .to_owned(),
+            )),
+            None => None,
+        })
+        .collect::<Functions>();
+    let relation = match plan.relations.as_slice() {
+        [relation] => relation,
+        _ => {
+            return Err(ballista_error(&format!(
+                "Substrait plans must have a single relation, not {}",
+                plan.relations.len()
+            )))
+        }
+    };
+    match &relation.rel_type {
+        Some(protobuf::plan_rel::RelType::Rel(rel)) => from_rel(rel, &functions),
+        Some(protobuf::plan_rel::RelType::Root(root)) => {
+            let input = root
+                .input
+                .as_ref()
+                .ok_or_else(|| ballista_error("Root relation has no input"))?;
+            rename(from_rel(input, &functions)?, &root.names)
+        }
+        None => Err(ballista_error("Plan relation has no relation")),
+    }
+}
+
+/// Name the columns of a plan by the depth-first names of a root relation
+fn rename(plan: LogicalPlan, names: &[String]) -> Result<LogicalPlan> {
+    let schema = plan.schema().clone();
+    let mut top_level = vec![];
+    let mut i = 0;
+    for field in schema.fields() {
+        top_level.push(
+            names
+                .get(i)
+                .ok_or_else(|| ballista_error("Root relation has too few names"))?,
+        );
+        i += 1 + nested_fields(field.data_type());
+    }
+    if i != names.len() {
+        return Err(ballista_error("Root relation has too many names"));
+    }
+    if schema
+        .fields()
+        .iter()
+        .zip(&top_level)
+        .all(|(field, name)| field.name() == *name)
+    {
+        return Ok(plan);
+    }
+    let fields = schema
+        .fields()
+        .iter()
+        .zip(&top_level)
+        .map(|(field, name)| Field::new(name, field.data_type().clone(), field.is_nullable()))
+        .collect();
+    let alias = |expr: Expr, field: &Field, name: &str| match expr {
+        expr if field.name() == name => expr,
+        Expr::Alias(expr, _) => expr.alias(name),
+        expr => expr.alias(name),
+    };
+    // projections are renamed in place rather than projected again
+    let expr = match plan {
+        LogicalPlan::Projection { expr, input, .. } => {
+            let expr = expr
+                .into_iter()
+                .zip(schema.fields().iter().zip(&top_level))
+                .map(|(expr, (field, name))| alias(expr, field, name))
+                .collect();
+            return Ok(LogicalPlan::Projection {
+                expr,
+                input,
+                schema: Schema::new(fields),
+            });
+        }
+        _ => schema
+            .fields()
+            .iter()
+            .zip(&top_level)
+            .enumerate()
+            .map(|(i, (field, name))| alias(Expr::Column(i), field, name))
+            .collect(),
+    };
+    Ok(LogicalPlan::Projection {
+        expr,
+        input: Box::new(plan),
+        schema: Schema::new(fields),
+    })
+}
+
+/// The number of fields that are nested in a type
+fn nested_fields(data_type: &DataType) -> usize {
+    match data_type {
+        DataType::Struct(fields) => fields
+            .iter()
+            .map(|field| 1 + nested_fields(field.data_type()))
+            .sum(),
+        _ => 0,
+    }
+}
+
+fn from_rel(rel: &protobuf::Rel, functions: &Functions) -> Result<LogicalPlan> {
+    use protobuf::rel::RelType;
+    let (plan, common) = match &rel.rel_type {
+        Some(RelType::Project(project)) => {
+            // the output mapping selects from the columns of the input and the expressions
+            let input = from_input(&project.input, functions)?;
+            let input_schema = input.schema().clone();
+            let mut exprs = (0..input_schema.fields().len())
+                .map(Expr::Column)
+                .collect::<Vec<_>>();
+            for expr in &project.expressions {
+                exprs.push(from_expression(expr, functions)?);
+            }
+            let expr = match output_mapping(&project.common) {
+                Some(columns) => columns
+                    .iter()
+                    .map(|i| {
+                        exprs
+                            .get(*i)
+                            .cloned()
+                            .ok_or_else(|| ballista_error(&format!("Invalid output mapping {}", i)))
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                None => exprs,
+            };
+            let fields = expr
+                .iter()
+                .map(|expr| expr_field(expr, &input_schema))
+                .collect::<Result<_>>()?;
+            return Ok(LogicalPlan::Projection {
+                expr,
+                input: Box::new(input),
+                schema: Schema::new(fields),
+            });
+        }
+        Some(RelType::Filter(filter)) => {
+            let condition = filter
+                .condition
+                .as_ref()
+                .ok_or_else(|| ballista_error("Filter relation has no condition"))?;
+            let plan = LogicalPlan::Selection {
+                expr: from_expression(condition, functions)?,
+                input: Box::new(from_input(&filter.input, functions)?),
+            };
+            (plan, &filter.common)
+        }
+        Some(RelType::Fetch(fetch)) => {
+            if fetch.offset != 0 {
+                return Err(ballista_error(
+                    "Fetch relations with an offset are not supported",
+                ));
+            }
+            let input = from_input(&fetch.input, functions)?;
+            // a negative count fetches all of the rows
+            let plan = if fetch.count < 0 {
+                input
+            } else {
+                LogicalPlanBuilder::from(&input)
+                    .limit(Expr::Literal(ScalarValue::UInt64(fetch.count as u64)))?
+                    .build()?
+            };
+            (plan, &fetch.common)
+        }
+        Some(RelType::Aggregate(aggregate)) => {
+            (from_aggregate_rel(aggregate, functions)?, &aggregate.common)
+        }
+        Some(RelType::Sort(sort)) => {
+            let expr = sort
+                .sorts
+                .iter()
+                .map(|sort| from_sort_field(sort, functions))
+                .collect::<Result<_>>()?;
+            let plan = LogicalPlanBuilder::from(&from_input(&sort.input, functions)?)
+                .sort(expr)?
+                .build()?;
+            (plan, &sort.common)
+        }
+        Some(RelType::Join(join)) => (from_join_rel(join, functions)?, &join.common),
+        Some(RelType::Read(read)) => (from_read_rel(read, functions)?, &read.common),
+        None => return Err(ballista_error("Relation has no relation type")),
+    };
+    match output_mapping(common) {
+        Some(columns) => select_columns(plan, &columns),
+        None => Ok(plan),
+    }
+}
+
+fn from_input(input: &Option<Box<protobuf::Rel>>, functions: &Functions) -> Result<LogicalPlan> {
+    match input {
+        Some(input) => from_rel(input, functions),
+        None => Err(ballista_error("Relation has no input")),
+    }
+}
+
+/// The columns that a relation emits, or `None` if it emits all of its columns
+fn output_mapping(common: &Option<protobuf::RelCommon>) -> Option<Vec<usize>> {
+    match common.as_ref()?.emit_kind.as_ref()? {
+        protobuf::rel_common::EmitKind::Emit(emit) => {
+            Some(emit.output_mapping.iter().map(|i| *i as usize).collect())
+        }
+        protobuf::rel_common::EmitKind::Direct(_) => None,
+    }
+}
+
+/// Project columns of a plan
+fn select_columns(plan: LogicalPlan, columns: &[usize]) -> Result<LogicalPlan> {
+    let fields = columns
+        .iter()
+        .map(|i| field_at(plan.schema(), *i).map(|field| field.clone()))
+        .collect::<Result<_>>()?;
+    Ok(LogicalPlan::Projection {
+        expr: columns.iter().map(|i| Expr::Column(*i)).collect(),
+        input: Box::new(plan),
+        schema: Schema::new(fields),
+    })
+}
+
+fn field_at(schema: &Schema, i: usize) -> Result<&Field> {
+    schema.fields().get(i).ok_or_else(|| {
+        ballista_error(&format!(
+            "Column index {} out of bounds for schema with {} field(s)",
+            i,
+            schema.fields().len()
+        ))
+    })
+}
+
+/// The field of an expression, which is named by its debug string when it has no name
+fn expr_field(expr: &Expr, schema: &Schema) -> Result<Field> {
+    match expr {
+        Expr::Column(i) => field_at(schema, *i).map(|field| field.clone()),
+        _ => match expr_to_field(expr, schema) {
+            Ok(field) => Ok(field),
+            Err(_) => Ok(Field::new(
+                &format!("{:?}", expr),
+                expr.get_type(schema)?,
+                true,
+            )),
+        },
+    }
+}
+
+fn from_aggregate_rel(
+    aggregate: &protobuf::AggregateRel,
+    functions: &Functions,
+) -> Result<LogicalPlan> {
+    let input = from_input(&aggregate.input, functions)?;
+    let group_expr = match aggregate.groupings.as_slice() {
+        [] => vec![],
+        [grouping] => grouping
+            .grouping_expressions
+            .iter()
+            .map(|expr| from_expression(expr, functions))
+            .collect::<Result<_>>()?,
+        _ => return Err(ballista_error("Grouping sets are not supported")),
+    };
+    let aggr_expr = aggregate
+        .measures
+        .iter()
+        .map(|measure| {
+            if measure.filter.is_some() {
+                return Err(ballista_error("Filtered aggregates are not supported"));
+            }
+            let function = measure
+                .measure
+                .as_ref()
+                .ok_or_else(|| ballista_error("Measure has no aggregate function"))?;
+            from_aggregate_function(function, functions)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let fields = group_expr
+        .iter()
+        .chain(&aggr_expr)
+        .map(|expr| expr_field(expr, input.schema()))
+        .collect::<Result<_>>()?;
+    Ok(LogicalPlan::Aggregate {
+        input: Box::new(input),
+        group_expr,
+        aggr_expr,
+        schema: Schema::new(fields),
+    })
+}
+
+fn from_aggregate_function(
+    function: &protobuf::AggregateFunction,
+    functions: &Functions,
+) -> Result<Expr> {
+    let name = function_name(function.function_reference, functions)?.to_uppercase();
+    let distinct = function.invocation == protobuf::AggregationInvocation::Distinct as i32;
+    let name = if !distinct {
+        name
+    } else if name == "COUNT" {
+        "COUNT_DISTINCT".to_owned()
+    } else {
+        return Err(ballista_error(&format!(
+            "Distinct invocations of {} are not supported",
+            name
+        )));
+    };
+    // counts are unsigned in Ballista
+    let return_type = match (name.as_str(), &function.output_type) {
+        ("COUNT", _) | ("COUNT_DISTINCT", _) => DataType::UInt64,
+        (_, Some(output_type)) => from_type(output_type)?,
+        (_, None) => DataType::Float64,
+    };
+    Ok(Expr::AggregateFunction {
+        name,
+        args: from_arguments(&function.arguments, functions)?,
+        return_type,
+    })
+}
+
+fn from_sort_field(sort: &protobuf::SortField, functions: &Functions) -> Result<Expr> {
+    use protobuf::sort_field::{SortDirection, SortKind};
+    let expr = sort
+        .expr
+        .as_ref()
+        .ok_or_else(|| ballista_error("Sort field has no expression"))?;
+    let asc = match &sort.sort_kind {
+        Some(SortKind::Direction(direction)) => match SortDirection::from_i32(*direction) {
+            Some(SortDirection::DescNullsFirst) | Some(SortDirection::DescNullsLast) => false,
+            Some(SortDirection::Clustered) => {
+                return Err(ballista_error("Clustered sorts are not supported"))
+            }
+            _ => true,
+        },
+        None => true,
+    };
+    Ok(from_expression(expr, functions)?.sort(asc))
+}
+
+/// Convert an inner join whose expression is a conjunction of equalities between the
+/// columns of its inputs, which match nulls if they are all `is_not_distinct_from`
+fn from_join_rel(join: &protobuf::JoinRel, functions: &Functions) -> Result<LogicalPlan> {
+    if join.join_type != protobuf::join_rel::JoinType::Inner as i32 {
+        return Err(ballista_error("Only inner joins are supported"));
+    }
+    let left = from_input(&join.left, functions)?;
+    let right = from_input(&join.right, functions)?;
+    let left_columns = left.schema().fields().len();
+    let expression = join
+        .expression
+        .as_ref()
+        .ok_or_else(|| ballista_error("Join relation has no expression"))?;
+    let mut on = vec![];
+    let mut null_equals_null = None;
+    for conjunct in conjuncts(from_expression(expression, functions)?) {
+        match conjunct {
+            Expr::BinaryExpr {
+                left: l,
+                op,
+                right: r,
+            } if op == Operator::Eq || op == Operator::IsNotDistinctFrom => {
+                let nulls = op == Operator::IsNotDistinctFrom;
+                if *null_equals_null.get_or_insert(nulls) != nulls {
+                    return Err(ballista_error(
+                        "Join conditions that compare nulls differently are not supported",
+                    ));
+                }
+                match (*l, *r) {
+                    (Expr::Column(l), Expr::Column(r)) if l < left_columns && r >= left_columns => {
+                        on.push((l, r - left_columns))
+                    }
+                    (Expr::Column(r), Expr::Column(l)) if l < left_columns && r >= left_columns => {
+                        on.push((l, r - left_columns))
+                    }
+                    (l, r) => {
+                        return Err(ballista_error(&format!(
+                            "Unsupported join condition {:?} {:?} {:?}",
+                            l, op, r
+                        )))
+                    }
+                }
+            }
+            other => {
+                return Err(ballista_error(&format!(
+                    "Unsupported join condition {:?}",
+                    other
+                )))
+            }
+        }
+    }
+    let options = JoinOptions {
+        null_equals_null: null_equals_null.unwrap_or(false),
+    };
+    let mut builder = LogicalPlanBuilder::from(&left).join_with_options(&right, on, options)?;
+    if let Some(filter) = &join.post_join_filter {
+        builder = builder.filter(from_expression(filter, functions)?)?;
+    }
+    Ok(builder.build()?)
+}
+
+/// Split a conjunction into the expressions that it combines
+fn conjuncts(expr: Expr) -> Vec<Expr> {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => {
+            let mut exprs = conjuncts(*left);
+            exprs.extend(conjuncts(*right));
+            exprs
+        }
+        expr => vec![expr],
+    }
+}
+
+fn from_read_rel(read: &protobuf::ReadRel, functions: &Functions) -> Result<LogicalPlan> {
+    let schema = match &read.base_schema {
+        Some(base_schema) => from_named_struct(base_schema)?,
+        None => return Err(ballista_error("Read relation has no base schema")),
+    };
+    let projection = read.projection.as_ref().map(|mask| {
+        mask.select
+            .iter()
+            .flat_map(|select| &select.struct_items)
+            .map(|item| item.field as usize)
+            .collect::<Vec<_>>()
+    });
+    // columns are projected after the rows are filtered, since the filter refers to the
+    // columns of the base schema
+    let (scan_projection, projection) = match &read.filter {
+        Some(_) => (None, projection),
+        None => (projection, None),
+    };
+    let projected_schema = match &scan_projection {
+        Some(columns) => Schema::new(
+            columns
+                .iter()
+                .map(|i| field_at(&schema, *i).map(|field| field.clone()))
+                .collect::<Result<_>>()?,
+        ),
+        None => schema.clone(),
+    };
+    let plan = match &read.read_type {
+        Some(read_rel::ReadType::NamedTable(table)) => LogicalPlan::TableScan {
+            table_name: table.names.join("."),
+            schema,
+            projection: scan_projection,
+            projected_schema,
+        },
+        Some(read_rel::ReadType::LocalFiles(files)) => {
+            let filters = match &read.best_effort_filter {
+                Some(filter) => conjuncts(from_expression(filter, functions)?),
+                None => vec![],
+            };
+            from_local_files(files, schema, scan_projection, projected_schema, filters)?
+        }
+        Some(read_rel::ReadType::VirtualTable(table)) => {
+            let plan = from_virtual_table(table, &schema)?;
+            match scan_projection {
+                Some(columns) => select_columns(plan, &columns)?,
+                None => plan,
+            }
+        }
+        None => return Err(ballista_error("Read relation has no read type")),
+    };
+    match &read.filter {
+        Some(filter) => {
+            let plan = LogicalPlan::Selection {
+                expr: from_expression(filter, functions)?,
+                input: Box::new(plan),
+            };
+            match projection {
+                Some(columns) => select_columns(plan, &columns),
+                None => Ok(plan),
+            }
+        }
+        None => Ok(plan),
+    }
+}
+
+/// Convert a read of files, which must all have the same format. Globs and folders are
+/// expanded to the files that they contain.
+fn from_local_files(
+    local_files: &read_rel::LocalFiles,
+    schema: Schema,
+    projection: Option<Vec<usize>>,
+    projected_schema: Schema,
+    filters: Vec<Expr>,
+) -> Result<LogicalPlan> {
+    let mut files = vec![];
+    let mut path = None;
+    let mut format = None;
+    for item in &local_files.items {
+        if item.start != 0 || item.length != 0 {
+            return Err(ballista_error(
+                "Reads of byte ranges of files are not supported",
+            ));
+        }
+        match &item.path_type {
+            Some(PathType::UriFile(uri)) | Some(PathType::UriPath(uri)) => {
+                files.push(uri.clone());
+                path.get_or_insert_with(|| uri.clone());
+            }
+            Some(PathType::UriPathGlob(glob)) => {
+                files.extend(expand_path(&LocalFileSystem::local_path(glob))?);
+                path.get_or_insert_with(|| glob.clone());
+            }
+            Some(PathType::UriFolder(folder)) => {
+                let glob = format!("{}/*", LocalFileSystem::local_path(folder));
+                files.extend(expand_path(&glob)?);
+                path.get_or_insert_with(|| folder.clone());
+            }
+            None => return Err(ballista_error("File has no path")),
+        }
+        let file_format = item
+            .file_format
+            .as_ref()
+            .ok_or_else(|| ballista_error("File has no format"))?;
+        if format
+            .as_ref()
+            .map_or(false, |format| format != file_format)
+        {
+            return Err(ballista_error("Files of a read must have the same format"));
+        }
+        format.get_or_insert_with(|| file_format.clone());
+    }
+    let (file_type, csv_options) = match format {
+        Some(FileFormat::Parquet(_)) => ("parquet", None),
+        Some(FileFormat::Arrow(_)) => ("ipc", None),
+        Some(FileFormat::Orc(_)) => ("orc", None),
+        Some(FileFormat::Text(options)) => ("csv", Some(from_text_options(&options)?)),
+        None => return Err(ballista_error("Read relation has no files")),
+    };
+    Ok(LogicalPlan::FileScan {
+        path: path.unwrap_or_default(),
+        paths: vec![],
+        files,
+        partition_columns: vec![],
+        file_type: file_type.to_owned(),
+        schema,
+        projection,
+        projected_schema,
+        csv_options,
+        filters,
+        limit: None,
+    })
+}
+
+/// The options of a CSV scan. Text files must have a single header line or none.
+fn from_text_options(
+    options: &read_rel::local_files::file_or_files::DelimiterSeparatedTextReadOptions,
+) -> Result<CsvReadOptions> {
+    if options.header_lines_to_skip > 1 {
+        return Err(ballista_error(
+            "Text files must have at most one header line to be read as CSV files",
+        ));
+    }
+    let byte = |name: &str, value: &str| match value.as_bytes() {
+        [b] => Ok(Some(*b)),
+        [] => Ok(None),
+        _ => Err(ballista_error(&format!(
+            "The {} of CSV files must be a single byte, not '{}'",
+            name, value
+        ))),
+    };
+    let defaults = CsvReadOptions::default();
+    Ok(CsvReadOptions {
+        delimiter: byte("delimiter", &options.field_delimiter)?.unwrap_or(defaults.delimiter),
+        quote: byte("quote", &options.quote)?.unwrap_or(defaults.quote),
+        escape: byte("escape", &options.escape)?,
+        has_header: options.header_lines_to_skip == 1,
+        ..defaults
+    })
+}
+
+/// Convert the rows of a virtual table to an in-memory scan
+fn from_virtual_table(table: &read_rel::VirtualTable, schema: &Schema) -> Result<LogicalPlan> {
+    if table.values.is_empty() {
+        return Ok(LogicalPlan::EmptyRelation {
+            schema: schema.clone(),
+        });
+    }
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let values = table
+                .values
+                .iter()
+                .map(|row| {
+                    row.fields
+                        .get(i)
+                        .ok_or_else(|| ballista_error("Row of virtual table has too few values"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            literal_array(field.data_type(), &values)
+        })
+        .collect::<Result<_>>()?;
+    let batch = RecordBatch::try_new(Arc::new(schema.clone()), columns)?;
+    Ok(LogicalPlan::MemoryScan(vec![batch]))
+}
+
+macro_rules! build_array {
+    ($builder:ident, $values:expr, $variant:ident, $v:ident => $value:expr) => {{
+        let mut builder = $builder::new($values.len());
+        for literal in $values {
+            match &literal.literal_type {
+                Some(LiteralType::$variant($v)) => builder.append_value($value)?,
+                Some(LiteralType::Null(_)) => builder.append_null()?,
+                other => {
+                    return Err(ballista_error(&format!(
+                        "Invalid value {:?} for column of type {}",
+                        other,
+                        stringify!($variant)
+                    )))
+                }
+            }
+        }
+        Arc::new(builder.finish()) as ArrayRef
+    }};
+}
+
+/// Build an array of the values of a column of a virtual table
+fn literal_array(data_type: &DataType, values: &[&expression::Literal]) -> Result<ArrayRef> {
+    Ok(match data_type {
+        DataType::Boolean => build_array!(BooleanBuilder, values, Boolean, v => *v),
+        DataType::Int8 => build_array!(Int8Builder, values, I8, v => *v as i8),
+        DataType::Int16 => build_array!(Int16Builder, values, I16, v => *v as i16),
+        DataType::Int32 => build_array!(Int32Builder, values, I32, v => *v),
+        DataType::Int64 => build_array!(Int64Builder, values, I64, v => *v),
+        DataType::Float32 => build_array!(Float32Builder, values, Fp32, v => *v),
+        DataType::Float64 => build_array!(Float64Builder, values, Fp64, v => *v),
+        DataType::Utf8 => build_array!(StringBuilder, values, String, v => v),
+        DataType::Binary => build_array!(BinaryBuilder, values, Binary, v => v),
+        DataType::Date32(DateUnit::Day) => build_array!(Date32Builder, values, Date, v => *v),
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            build_array!(TimestampMicrosecondBuilder, values, Timestamp, v => *v)
+        }
+        other => {
+            return Err(ballista_error(&format!(
+                "Virtual tables with columns of type {:?} are not supported",
+                other
+            )))
+        }
+    })
+}
+
+/// Convert a named struct to a schema, where the names of nested fields follow the name of
+/// the struct that they are nested in
+fn from_named_struct(named_struct: &protobuf::NamedStruct) -> Result<Schema> {
+    let struct_type = named_struct
+        .struct_type
+        .as_ref()
+        .ok_or_else(|| ballista_error("Named struct has no type"))?;
+    let mut names = named_struct.names.iter();
+    let fields = struct_type
+        .types
+        .iter()
+        .map(|data_type| named_field(data_type, &mut names))
+        .collect::<Result<_>>()?;
+    if names.next().is_some() {
+        return Err(ballista_error("Named struct has too many names"));
+    }
+    Ok(Schema::new(fields))
+}
+
+fn named_field<'a>(
+    data_type: &protobuf::DataType,
+    names: &mut impl Iterator<Item = &'a String>,
+) -> Result<Field> {
+    let name = names
+        .next()
+        .ok_or_else(|| ballista_error("Named struct has too few names"))?;
+    let nullable = nullability(data_type) != protobuf::Nullability::Required as i32;
+    let data_type = match &data_type.kind {
+        Some(Kind::Struct(struct_type)) => DataType::Struct(
+            struct_type
+                .types
+                .iter()
+                .map(|data_type| named_field(data_type, names))
+                .collect::<Result<_>>()?,
+        ),
+        _ => from_type(data_type)?,
+    };
+    Ok(Field::new(name, data_type, nullable))
+}
+
+fn nullability(data_type: &protobuf::DataType) -> i32 {
+    match &data_type.kind {
+        Some(Kind::Struct(struct_type)) => struct_type.nullability,
+        Some(Kind::Bool(t))
+        | Some(Kind::I8(t))
+        | Some(Kind::I16(t))
+        | Some(Kind::I32(t))
+        | Some(Kind::I64(t))
+        | Some(Kind::Fp32(t))
+        | Some(Kind::Fp64(t))
+        | Some(Kind::String(t))
+        | Some(Kind::Binary(t))
+        | Some(Kind::Timestamp(t))
+        | Some(Kind::Date(t)) => t.nullability,
+        None => protobuf::Nullability::Unspecified as i32,
+    }
+}
+
+/// Convert a type, where struct types are only supported in named structs since Substrait
+/// does not name their fields
+fn from_type(data_type: &protobuf::DataType) -> Result<DataType> {
+    match &data_type.kind {
+        Some(Kind::Bool(_)) => Ok(DataType::Boolean),
+        Some(Kind::I8(_)) => Ok(DataType::Int8),
+        Some(Kind::I16(_)) => Ok(DataType::Int16),
+        Some(Kind::I32(_)) => Ok(DataType::Int32),
+        Some(Kind::I64(_)) => Ok(DataType::Int64),
+        Some(Kind::Fp32(_)) => Ok(DataType::Float32),
+        Some(Kind::Fp64(_)) => Ok(DataType::Float64),
+        Some(Kind::String(_)) => Ok(DataType::Utf8),
+        Some(Kind::Binary(_)) => Ok(DataType::Binary),
+        Some(Kind::Timestamp(_)) => Ok(DataType::Timestamp(TimeUnit::Microsecond, None)),
+        Some(Kind::Date(_)) => Ok(DataType::Date32(DateUnit::Day)),
+        Some(Kind::Struct(_)) => Err(ballista_error(
+            "Struct types are only supported in the schemas of read relations",
+        )),
+        None => Err(ballista_error("Type has no kind")),
+    }
+}
+
+fn function_name(anchor: u32, functions: &Functions) -> Result<&str> {
+    functions
+        .get(&anchor)
+        .map(|name| name.as_str())
+        .ok_or_else(|| ballista_error(&format!("Undeclared function {}", anchor)))
+}
+
+fn from_arguments(
+    arguments: &[protobuf::FunctionArgument],
+    functions: &Functions,
+) -> Result<Vec<Expr>> {
+    arguments
+        .iter()
+        .map(|argument| match &argument.arg_type {
+            Some(protobuf::function_argument::ArgType::Value(expr)) => {
+                from_expression(expr, functions)
+            }
+            None => Err(ballista_error("Function argument has no value")),
+        })
+        .collect()
+}
+
+fn from_expression(expr: &protobuf::Expression, functions: &Functions) -> Result<Expr> {
+    match &expr.rex_type {
+        Some(RexType::Literal(literal)) => Ok(Expr::Literal(from_literal(literal)?)),
+        Some(RexType::Selection(reference)) => from_field_reference(reference),
+        Some(RexType::ScalarFunction(function)) => from_scalar_function(function, functions),
+        Some(RexType::Cast(cast)) => {
+            let input = cast
+                .input
+                .as_ref()
+                .ok_or_else(|| ballista_error("Cast has no input"))?;
+            let data_type = cast
+                .target_type
+                .as_ref()
+                .ok_or_else(|| ballista_error("Cast has no type"))?;
+            Ok(Expr::Cast {
+                expr: Box::new(from_expression(input, functions)?),
+                data_type: from_type(data_type)?,
+            })
+        }
+        None => Err(ballista_error("Expression has no type")),
+    }
+}
+
+/// Convert a reference to a column of the input of a relation
+fn from_field_reference(reference: &expression::FieldReference) -> Result<Expr> {
+    use expression::field_reference::ReferenceType;
+    use expression::reference_segment;
+    let segment = match &reference.reference_type {
+        Some(ReferenceType::DirectReference(segment)) => segment,
+        None => return Err(ballista_error("Field reference has no reference")),
+    };
+    match &segment.reference_type {
+        Some(reference_segment::ReferenceType::StructField(field)) if field.field >= 0 => {
+            Ok(Expr::Column(field.field as usize))
+        }
+        _ => Err(ballista_error(&format!(
+            "Unsupported field reference {:?}",
+            segment
+        ))),
+    }
+}
+
+/// Convert a function call, where the functions of binary operators are converted to binary
+/// expressions, combining more than two arguments from the left
+fn from_scalar_function(
+    function: &expression::ScalarFunction,
+    functions: &Functions,
+) -> Result<Expr> {
+    let name = function_name(function.function_reference, functions)?;
+    let mut args = from_arguments(&function.arguments, functions)?;
+    if let Some((op, _)) = OPERATOR_FUNCTIONS.iter().find(|(_, f)| *f == name) {
+        if args.len() < 2 {
+            return Err(ballista_error(&format!(
+                "Function {} requires at least two arguments",
+                name
+            )));
+        }
+        let first = args.remove(0);
+        return Ok(args
+            .into_iter()
+            .fold(first, |left, right| Expr::BinaryExpr {
+                left: Box::new(left),
+                op: op.clone(),
+                right: Box::new(right),
+            }));
+    }
+    if ["not", "is_null", "is_not_null"].contains(&name) {
+        if args.len() != 1 {
+            return Err(ballista_error(&format!(
+                "Function {} requires a single argument",
+                name
+            )));
+        }
+        return Ok(match (name, args.remove(0)) {
+            (
+                "not",
+                Expr::BinaryExpr {
+                    left,
+                    op: Operator::Like,
+                    right,
+                },
+            ) => Expr::BinaryExpr {
+                left,
+                op: Operator::NotLike,
+                right,
+            },
+            ("not", arg) => Expr::Not(Box::new(arg)),
+            ("is_null", arg) => Expr::IsNull(Box::new(arg)),
+            (_, arg) => Expr::IsNotNull(Box::new(arg)),
+        });
+    }
+    let name = RENAMED_FUNCTIONS
+        .iter()
+        .find(|(_, substrait)| *substrait == name)
+        .map(|(ballista, _)| *ballista)
+        .unwrap_or(name);
+    let return_type = match &function.output_type {
+        Some(output_type) => from_type(output_type)?,
+        None => DataType::Float64,
+    };
+    Ok(Expr::ScalarFunction {
+        name: name.to_owned(),
+        args,
+        return_type,
+    })
+}
+
+/// Convert a literal, which fails for the types that Ballista has no literals of
+fn from_literal(literal: &expression::Literal) -> Result<ScalarValue> {
+    match &literal.literal_type {
+        Some(LiteralType::Boolean(v)) => Ok(ScalarValue::Boolean(*v)),
+        Some(LiteralType::I8(v)) => Ok(ScalarValue::Int8(*v as i8)),
+        Some(LiteralType::I16(v)) => Ok(ScalarValue::Int16(*v as i16)),
+        Some(LiteralType::I32(v)) => Ok(ScalarValue::Int32(*v)),
+        Some(LiteralType::I64(v)) => Ok(ScalarValue::Int64(*v)),
+        Some(LiteralType::Fp32(v)) => Ok(ScalarValue::Float32(*v)),
+        Some(LiteralType::Fp64(v)) => Ok(ScalarValue::Float64(*v)),
+        Some(LiteralType::String(v)) => Ok(ScalarValue::Utf8(v.clone())),
+        Some(LiteralType::Binary(v)) => Ok(ScalarValue::Binary(v.clone())),
+        Some(LiteralType::Date(v)) => Ok(ScalarValue::Date32(*v)),
+        Some(LiteralType::Timestamp(v)) => Ok(ScalarValue::Timestamp(*v, TimeUnit::Microsecond)),
+        Some(LiteralType::Struct(v)) => Ok(ScalarValue::Struct(
+            v.fields.iter().map(from_literal).collect::<Result<_>>()?,
+        )),
+        Some(LiteralType::Null(_)) => Ok(ScalarValue::Null),
+        other => Err(ballista_error(&format!("Unsupported literal {:?}", other))),
+    }
+}
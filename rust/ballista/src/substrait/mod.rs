@@ -0,0 +1,170 @@
+//! Conversion of logical plans to and from Substrait.
+//!
+//! [Substrait](https://substrait.io) is a format for query plans that is shared between
+//! engines. Plans are exported as a Substrait plan with a single root relation, named by the
+//! columns of the plan, so that they can be consumed by other engines, and Substrait plans
+//! that other engines produced are imported as logical plans that execute like any other
+//! plan. Functions are declared with the names of the standard Substrait extensions.
+//!
+//! Only the relations, expressions and types that Ballista has an equivalent of can be
+//! converted, and conversion fails otherwise. Plans of distributed queries, which read the
+//! output of other stages, cannot be exported.
+
+use std::io::Cursor;
+
+use crate::error::Result;
+use crate::logicalplan::{LogicalPlan, Operator};
+
+use prost::Message;
+
+// include the generated protobuf source as a submodule
+#[allow(clippy::all)]
+pub mod protobuf {
+    include!(concat!(env!("OUT_DIR"), "/substrait.rs"));
+}
+
+pub mod from_substrait;
+pub mod to_substrait;
+
+pub use from_substrait::from_substrait;
+pub use to_substrait::to_substrait;
+
+/// Location of the standard extensions that declare the functions of exported plans
+const EXTENSIONS_URI: &str = "https://github.com/substrait-io/substrait/blob/main/extensions/";
+
+/// The Substrait functions of binary operators. `NotLike` is exported as the negation of
+/// `like`.
+const OPERATOR_FUNCTIONS: [(Operator, &str); 15] = [
+    (Operator::Eq, "equal"),
+    (Operator::IsNotDistinctFrom, "is_not_distinct_from"),
+    (Operator::NotEq, "not_equal"),
+    (Operator::Lt, "lt"),
+    (Operator::LtEq, "lte"),
+    (Operator::Gt, "gt"),
+    (Operator::GtEq, "gte"),
+    (Operator::Plus, "add"),
+    (Operator::Minus, "subtract"),
+    (Operator::Multiply, "multiply"),
+    (Operator::Divide, "divide"),
+    (Operator::Modulus, "modulus"),
+    (Operator::And, "and"),
+    (Operator::Or, "or"),
+    (Operator::Like, "like"),
+];
+
+/// Scalar functions whose Substrait names differ from their Ballista names
+const RENAMED_FUNCTIONS: [(&str, &str); 2] = [("log", "ln"), ("signum", "sign")];
+
+/// Encode a plan as a Substrait plan in the protobuf format
+pub fn encode_substrait(plan: &LogicalPlan) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    to_substrait(plan)?.encode(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Decode a Substrait plan in the protobuf format
+pub fn decode_substrait(bytes: &[u8]) -> Result<LogicalPlan> {
+    let plan = protobuf::Plan::decode(&mut Cursor::new(bytes))?;
+    from_substrait(&plan)
+}
+
+/// The extension file that declares a Substrait function
+fn extension_file(function: &str) -> &'static str {
+    match function {
+        "equal"
+        | "not_equal"
+        | "is_not_distinct_from"
+        | "lt"
+        | "lte"
+        | "gt"
+        | "gte"
+        | "is_null"
+        | "is_not_null" => "functions_comparison.yaml",
+        "and" | "or" | "not" => "functions_boolean.yaml",
+        "like" => "functions_string.yaml",
+        "count" => "functions_aggregate_generic.yaml",
+        _ => "functions_arithmetic.yaml",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{Int32Array, StringArray};
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::arrow::record_batch::RecordBatch;
+    use crate::dataframe::{count, max};
+    use crate::logicalplan::{col, lit_str, Expr, LogicalPlanBuilder, ScalarValue};
+    use std::sync::Arc;
+
+    #[test]
+    fn roundtrip_substrait() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("state", DataType::Utf8, true),
+            Field::new("salary", DataType::Float64, true),
+        ]);
+        let plan = LogicalPlanBuilder::scan_csv("/data/employee.csv", &schema, None)?
+            .filter(col("state").eq(&lit_str("CO")))?
+            .aggregate(
+                vec![col("state")],
+                vec![max(col("salary")), count(col("id"))],
+            )?
+            .sort(vec![col("state").sort(false)])?
+            .limit(Expr::Literal(ScalarValue::UInt64(10)))?
+            .build()?;
+        // columns are referred to by their index, and files by their URI
+        let expected = LogicalPlanBuilder::scan_csv("file:///data/employee.csv", &schema, None)?
+            .filter(Expr::Column(1).eq(&lit_str("CO")))?
+            .aggregate(
+                vec![Expr::Column(1)],
+                vec![max(Expr::Column(2)), count(Expr::Column(0))],
+            )?
+            .sort(vec![Expr::Column(0).sort(false)])?
+            .limit(Expr::Literal(ScalarValue::UInt64(10)))?
+            .build()?;
+        let plan2 = decode_substrait(&encode_substrait(&plan)?)?;
+        assert_eq!(format!("{:?}", expected), format!("{:?}", plan2));
+
+        // in-memory batches are exported as virtual tables, and joins as inner joins whose
+        // expression compares the join columns
+        let left = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let right = Arc::new(Schema::new(vec![
+            Field::new("b", DataType::Int32, false),
+            Field::new("c", DataType::Utf8, true),
+        ]));
+        let left = RecordBatch::try_new(left, vec![Arc::new(Int32Array::from(vec![1, 2]))])?;
+        let right = RecordBatch::try_new(
+            right,
+            vec![
+                Arc::new(Int32Array::from(vec![2])),
+                Arc::new(StringArray::from(vec![Some("x")])),
+            ],
+        )?;
+        let plan = LogicalPlanBuilder::from(&LogicalPlan::MemoryScan(vec![left]))
+            .join(&LogicalPlan::MemoryScan(vec![right]), vec![(0, 0)])?
+            .project(vec![Expr::Column(2).alias("name")])?
+            .build()?;
+        let plan2 = decode_substrait(&encode_substrait(&plan)?)?;
+        match &plan2 {
+            LogicalPlan::Projection { input, schema, .. } => {
+                assert_eq!("name", schema.field(0).name());
+                match input.as_ref() {
+                    LogicalPlan::Join { on, right, .. } => {
+                        assert_eq!(&vec![(0, 0)], on);
+                        match right.as_ref() {
+                            LogicalPlan::MemoryScan(batches) => {
+                                assert_eq!(1, batches[0].num_rows());
+                                assert_eq!(2, batches[0].num_columns());
+                            }
+                            other => panic!("unexpected plan {:?}", other),
+                        }
+                    }
+                    other => panic!("unexpected plan {:?}", other),
+                }
+            }
+            other => panic!("unexpected plan {:?}", other),
+        }
+        Ok(())
+    }
+}
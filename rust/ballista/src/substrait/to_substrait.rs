@@ -0,0 +1,784 @@
+//! Export of logical plans as Substrait plans.
+
+use std::convert::TryFrom;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
+use crate::datasource::csv::{CsvCompression, CsvReadOptions};
+use crate::datasource::object_store;
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::{Expr, LogicalPlan, Operator, ScalarValue};
+
+use super::protobuf::{self, expression, read_rel};
+use super::{extension_file, EXTENSIONS_URI, OPERATOR_FUNCTIONS, RENAMED_FUNCTIONS};
+
+use expression::literal::LiteralType;
+use expression::RexType;
+use protobuf::data_type::Kind;
+use read_rel::local_files::file_or_files::{self, FileFormat, PathType};
+
+/// Convert a plan to a Substrait plan with a single root relation, whose names are the names
+/// of the columns of the plan
+pub fn to_substrait(plan: &LogicalPlan) -> Result<protobuf::Plan> {
+    let mut extensions = Extensions::default();
+    let root = protobuf::RelRoot {
+        input: Some(to_rel(plan, &mut extensions)?),
+        names: names(plan.schema()),
+    };
+    Ok(protobuf::Plan {
+        extension_uris: extensions
+            .uris
+            .iter()
+            .enumerate()
+            .map(|(i, uri)| protobuf::SimpleExtensionUri {
+                extension_uri_anchor: i as u32 + 1,
+                uri: uri.clone(),
+            })
+            .collect(),
+        extensions: extensions
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(i, (uri, name))| protobuf::SimpleExtensionDeclaration {
+                mapping_type: Some(
+                    protobuf::simple_extension_declaration::MappingType::ExtensionFunction(
+                        protobuf::simple_extension_declaration::ExtensionFunction {
+                            extension_uri_reference: *uri,
+                            function_anchor: i as u32 + 1,
+                            name: name.clone(),
+                        },
+                    ),
+                ),
+            })
+            .collect(),
+        relations: vec![protobuf::PlanRel {
+            rel_type: Some(protobuf::plan_rel::RelType::Root(root)),
+        }],
+        version: Some(protobuf::Version {
+            producer: "ballista".to_owned(),
+            ..Default::default()
+        }),
+    })
+}
+
+/// The functions that a plan calls, which are declared by the plan and referred to by
+/// their anchors, starting from one
+#[derive(Default)]
+struct Extensions {
+    uris: Vec<String>,
+    /// The anchor of the URI of each function, and its name
+    functions: Vec<(u32, String)>,
+}
+
+impl Extensions {
+    /// The anchor of a function, declaring it the first time that it is called
+    fn function(&mut self, name: &str) -> u32 {
+        if let Some(i) = self.functions.iter().position(|(_, n)| n == name) {
+            return i as u32 + 1;
+        }
+        let uri = format!("{}{}", EXTENSIONS_URI, extension_file(name));
+        let uri = match self.uris.iter().position(|u| *u == uri) {
+            Some(i) => i + 1,
+            None => {
+                self.uris.push(uri);
+                self.uris.len()
+            }
+        };
+        self.functions.push((uri as u32, name.to_owned()));
+        self.functions.len() as u32
+    }
+}
+
+fn to_rel(plan: &LogicalPlan, extensions: &mut Extensions) -> Result<protobuf::Rel> {
+    use protobuf::rel::RelType;
+    let rel_type = match plan {
+        LogicalPlan::Projection { expr, input, .. } => {
+            // projections emit the columns of the input followed by the expressions, so only
+            // the expressions are selected
+            let columns = input.schema().fields().len();
+            RelType::Project(Box::new(protobuf::ProjectRel {
+                common: Some(emit((columns..columns + expr.len()).collect())),
+                expressions: expressions(expr, input.schema(), extensions)?,
+                input: Some(Box::new(to_rel(input, extensions)?)),
+            }))
+        }
+        LogicalPlan::Selection { expr, input } => RelType::Filter(Box::new(protobuf::FilterRel {
+            common: None,
+            condition: Some(to_expression(expr, input.schema(), extensions)?),
+            input: Some(Box::new(to_rel(input, extensions)?)),
+        })),
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            ..
+        } => {
+            // global aggregates have no groupings
+            let groupings = if group_expr.is_empty() {
+                vec![]
+            } else {
+                vec![protobuf::aggregate_rel::Grouping {
+                    grouping_expressions: expressions(group_expr, input.schema(), extensions)?,
+                }]
+            };
+            let measures = aggr_expr
+                .iter()
+                .map(|expr| to_measure(expr, input.schema(), extensions))
+                .collect::<Result<_>>()?;
+            RelType::Aggregate(Box::new(protobuf::AggregateRel {
+                common: None,
+                input: Some(Box::new(to_rel(input, extensions)?)),
+                groupings,
+                measures,
+            }))
+        }
+        LogicalPlan::Sort { expr, input, .. } => {
+            let sorts = expr
+                .iter()
+                .map(|expr| to_sort_field(expr, input.schema(), extensions))
+                .collect::<Result<_>>()?;
+            RelType::Sort(Box::new(protobuf::SortRel {
+                common: None,
+                input: Some(Box::new(to_rel(input, extensions)?)),
+                sorts,
+            }))
+        }
+        LogicalPlan::Limit { expr, input, .. } => RelType::Fetch(Box::new(protobuf::FetchRel {
+            common: None,
+            input: Some(Box::new(to_rel(input, extensions)?)),
+            offset: 0,
+            count: limit_count(expr)?,
+        })),
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            null_equals_null,
+            schema,
+        } => {
+            // the expression refers to the columns of both inputs
+            let left_columns = left.schema().fields().len();
+            let condition = on
+                .iter()
+                .map(|(l, r)| {
+                    let (l, r) = (Expr::Column(*l), Expr::Column(left_columns + r));
+                    if *null_equals_null {
+                        l.is_not_distinct_from(&r)
+                    } else {
+                        l.eq(&r)
+                    }
+                })
+                .fold(None, |condition: Option<Expr>, eq| match condition {
+                    Some(condition) => Some(condition.and(&eq)),
+                    None => Some(eq),
+                });
+            let expression = match condition {
+                Some(condition) => Some(to_expression(&condition, schema, extensions)?),
+                None => None,
+            };
+            RelType::Join(Box::new(protobuf::JoinRel {
+                common: None,
+                left: Some(Box::new(to_rel(left, extensions)?)),
+                right: Some(Box::new(to_rel(right, extensions)?)),
+                expression,
+                post_join_filter: None,
+                join_type: protobuf::join_rel::JoinType::Inner as i32,
+            }))
+        }
+        // the hint has no equivalent in Substrait
+        LogicalPlan::Broadcast { input } => return to_rel(input, extensions),
+        LogicalPlan::FileScan { .. }
+        | LogicalPlan::TableScan { .. }
+        | LogicalPlan::EmptyRelation { .. }
+        | LogicalPlan::MemoryScan(_) => RelType::Read(Box::new(to_read_rel(plan, extensions)?)),
+        LogicalPlan::Union { .. }
+        | LogicalPlan::StageOutput { .. }
+        | LogicalPlan::ShuffleRead { .. }
+        | LogicalPlan::Extension { .. } => {
+            return Err(ballista_error(&format!(
+                "{} plans cannot be exported to Substrait",
+                plan.operator_name()
+            )))
+        }
+    };
+    Ok(protobuf::Rel {
+        rel_type: Some(rel_type),
+    })
+}
+
+/// Convert a scan to a read of the files, the table or the rows that it scans
+fn to_read_rel(plan: &LogicalPlan, extensions: &mut Extensions) -> Result<protobuf::ReadRel> {
+    let mut read = protobuf::ReadRel::default();
+    match plan {
+        LogicalPlan::FileScan {
+            files,
+            partition_columns,
+            file_type,
+            schema,
+            projection,
+            csv_options,
+            filters,
+            ..
+        } => {
+            if !partition_columns.is_empty() {
+                return Err(ballista_error(
+                    "Scans of partitioned files cannot be exported to Substrait",
+                ));
+            }
+            let file_format = match file_type.as_str() {
+                "parquet" => FileFormat::Parquet(file_or_files::ParquetReadOptions {}),
+                "ipc" => FileFormat::Arrow(file_or_files::ArrowReadOptions {}),
+                "orc" => FileFormat::Orc(file_or_files::OrcReadOptions {}),
+                "csv" => FileFormat::Text(text_options(files, csv_options)?),
+                other => {
+                    return Err(ballista_error(&format!(
+                        "Scans of {} files cannot be exported to Substrait",
+                        other
+                    )))
+                }
+            };
+            read.base_schema = Some(named_struct(schema)?);
+            read.projection = projection.as_deref().map(mask);
+            // the rows of scans are also filtered above the scan
+            read.best_effort_filter = match conjunction(filters) {
+                Some(filter) => Some(to_expression(&filter, schema, extensions)?),
+                None => None,
+            };
+            let items = files
+                .iter()
+                .enumerate()
+                .map(|(i, file)| read_rel::local_files::FileOrFiles {
+                    path_type: Some(PathType::UriFile(file_uri(file))),
+                    partition_index: i as u64,
+                    start: 0,
+                    length: 0,
+                    file_format: Some(file_format.clone()),
+                })
+                .collect();
+            read.read_type = Some(read_rel::ReadType::LocalFiles(read_rel::LocalFiles {
+                items,
+            }));
+        }
+        LogicalPlan::TableScan {
+            table_name,
+            schema,
+            projection,
+            ..
+        } => {
+            read.base_schema = Some(named_struct(schema)?);
+            read.projection = projection.as_deref().map(mask);
+            read.read_type = Some(read_rel::ReadType::NamedTable(read_rel::NamedTable {
+                names: vec![table_name.clone()],
+            }));
+        }
+        LogicalPlan::EmptyRelation { schema } => {
+            read.base_schema = Some(named_struct(schema)?);
+            read.read_type = Some(read_rel::ReadType::VirtualTable(
+                read_rel::VirtualTable::default(),
+            ));
+        }
+        LogicalPlan::MemoryScan(batches) => {
+            read.base_schema = Some(named_struct(plan.schema())?);
+            let mut values = vec![];
+            for batch in batches {
+                for row in 0..batch.num_rows() {
+                    let fields = batch
+                        .columns()
+                        .iter()
+                        .map(|column| array_literal(column, row))
+                        .collect::<Result<_>>()?;
+                    values.push(expression::literal::Struct { fields });
+                }
+            }
+            read.read_type = Some(read_rel::ReadType::VirtualTable(read_rel::VirtualTable {
+                values,
+            }));
+        }
+        other => {
+            return Err(ballista_error(&format!(
+                "{} is not a scan",
+                other.operator_name()
+            )))
+        }
+    }
+    Ok(read)
+}
+
+/// The options of a CSV scan, which fails for the options that Substrait has no equivalent
+/// of, including compression. Files with a header skip their first line.
+fn text_options(
+    files: &[String],
+    options: &Option<CsvReadOptions>,
+) -> Result<file_or_files::DelimiterSeparatedTextReadOptions> {
+    let options = options.clone().unwrap_or_default();
+    let compressed = match options.compression {
+        Some(compression) => compression != CsvCompression::Uncompressed,
+        None => files
+            .iter()
+            .any(|file| CsvCompression::from_path(file) != CsvCompression::Uncompressed),
+    };
+    if compressed
+        || options.comment.is_some()
+        || !options.null_values.is_empty()
+        || options.date_format.is_some()
+        || options.timestamp_format.is_some()
+        || options.timezone.is_some()
+    {
+        return Err(ballista_error(&format!(
+            "CSV scans with the options {:?} cannot be exported to Substrait",
+            options
+        )));
+    }
+    Ok(file_or_files::DelimiterSeparatedTextReadOptions {
+        field_delimiter: (options.delimiter as char).to_string(),
+        max_line_size: 0,
+        quote: (options.quote as char).to_string(),
+        header_lines_to_skip: if options.has_header { 1 } else { 0 },
+        escape: options
+            .escape
+            .map(|c| (c as char).to_string())
+            .unwrap_or_default(),
+    })
+}
+
+/// The URI of a file, where local paths are given the `file` scheme
+fn file_uri(path: &str) -> String {
+    if object_store::scheme(path) == "file" && path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        path.to_owned()
+    }
+}
+
+fn mask(projection: &[usize]) -> expression::MaskExpression {
+    expression::MaskExpression {
+        select: Some(expression::mask_expression::StructSelect {
+            struct_items: projection
+                .iter()
+                .map(|i| expression::mask_expression::StructItem { field: *i as i32 })
+                .collect(),
+        }),
+        maintain_singular_struct: false,
+    }
+}
+
+fn emit(columns: Vec<usize>) -> protobuf::RelCommon {
+    protobuf::RelCommon {
+        emit_kind: Some(protobuf::rel_common::EmitKind::Emit(
+            protobuf::rel_common::Emit {
+                output_mapping: columns.into_iter().map(|i| i as i32).collect(),
+            },
+        )),
+    }
+}
+
+/// The number of rows of a limit
+fn limit_count(expr: &Expr) -> Result<i64> {
+    let count = match expr {
+        Expr::Literal(ScalarValue::UInt64(n)) => i64::try_from(*n).ok(),
+        Expr::Literal(ScalarValue::Int64(n)) => Some(*n),
+        Expr::Literal(ScalarValue::UInt32(n)) => Some(*n as i64),
+        Expr::Literal(ScalarValue::Int32(n)) => Some(*n as i64),
+        _ => None,
+    };
+    count
+        .filter(|n| *n >= 0)
+        .ok_or_else(|| ballista_error(&format!("Invalid limit: {:?}", expr)))
+}
+
+/// Combine filters into a single filter that all of them must match
+fn conjunction(filters: &[Expr]) -> Option<Expr> {
+    filters
+        .iter()
+        .fold(None, |conjunction, filter| match conjunction {
+            Some(conjunction) => Some(conjunction.and(filter)),
+            None => Some(filter.clone()),
+        })
+}
+
+/// The names of the columns of a schema, in depth-first order for nested structs
+fn names(schema: &Schema) -> Vec<String> {
+    fn add_names(field: &Field, names: &mut Vec<String>) {
+        names.push(field.name().clone());
+        if let DataType::Struct(children) = field.data_type() {
+            for child in children {
+                add_names(child, names);
+            }
+        }
+    }
+    let mut names = vec![];
+    for field in schema.fields() {
+        add_names(field, &mut names);
+    }
+    names
+}
+
+fn named_struct(schema: &Schema) -> Result<protobuf::NamedStruct> {
+    Ok(protobuf::NamedStruct {
+        names: names(schema),
+        struct_type: Some(protobuf::StructType {
+            types: schema
+                .fields()
+                .iter()
+                .map(|field| to_type(field.data_type(), field.is_nullable()))
+                .collect::<Result<_>>()?,
+            type_variation_reference: 0,
+            nullability: protobuf::Nullability::Required as i32,
+        }),
+    })
+}
+
+fn to_type(data_type: &DataType, nullable: bool) -> Result<protobuf::DataType> {
+    let nullability = if nullable {
+        protobuf::Nullability::Nullable
+    } else {
+        protobuf::Nullability::Required
+    } as i32;
+    let primitive = protobuf::PrimitiveType {
+        type_variation_reference: 0,
+        nullability,
+    };
+    let kind = match data_type {
+        DataType::Boolean => Kind::Bool(primitive),
+        DataType::Int8 => Kind::I8(primitive),
+        DataType::Int16 => Kind::I16(primitive),
+        DataType::Int32 => Kind::I32(primitive),
+        DataType::Int64 => Kind::I64(primitive),
+        DataType::Float32 => Kind::Fp32(primitive),
+        DataType::Float64 => Kind::Fp64(primitive),
+        DataType::Utf8 => Kind::String(primitive),
+        DataType::Binary => Kind::Binary(primitive),
+        DataType::Date32(DateUnit::Day) => Kind::Date(primitive),
+        DataType::Timestamp(TimeUnit::Microsecond, None) => Kind::Timestamp(primitive),
+        DataType::Struct(fields) => Kind::Struct(protobuf::StructType {
+            types: fields
+                .iter()
+                .map(|field| to_type(field.data_type(), field.is_nullable()))
+                .collect::<Result<_>>()?,
+            type_variation_reference: 0,
+            nullability,
+        }),
+        other => {
+            return Err(ballista_error(&format!(
+                "The type {:?} has no equivalent in Substrait",
+                other
+            )))
+        }
+    };
+    Ok(protobuf::DataType { kind: Some(kind) })
+}
+
+fn expressions(
+    exprs: &[Expr],
+    schema: &Schema,
+    extensions: &mut Extensions,
+) -> Result<Vec<protobuf::Expression>> {
+    exprs
+        .iter()
+        .map(|expr| to_expression(expr, schema, extensions))
+        .collect()
+}
+
+/// Convert an expression on the columns of the schema. The names of aliases are not kept,
+/// since Substrait only names the columns of the root relation.
+fn to_expression(
+    expr: &Expr,
+    schema: &Schema,
+    extensions: &mut Extensions,
+) -> Result<protobuf::Expression> {
+    let rex_type = match expr {
+        Expr::Alias(expr, _) => return to_expression(expr, schema, extensions),
+        Expr::Column(i) => RexType::Selection(field_reference(*i)),
+        Expr::UnresolvedColumn(name) => RexType::Selection(field_reference(schema.index_of(name)?)),
+        Expr::Literal(value) => RexType::Literal(literal(value)?),
+        Expr::BinaryExpr {
+            left,
+            op: Operator::NotLike,
+            right,
+        } => {
+            let like = Expr::BinaryExpr {
+                left: left.clone(),
+                op: Operator::Like,
+                right: right.clone(),
+            };
+            return to_expression(&like.not(), schema, extensions);
+        }
+        Expr::BinaryExpr { left, op, right } => {
+            let name = OPERATOR_FUNCTIONS
+                .iter()
+                .find(|(o, _)| o == op)
+                .map(|(_, name)| *name)
+                .ok_or_else(|| {
+                    ballista_error(&format!(
+                        "The operator {:?} has no equivalent in Substrait",
+                        op
+                    ))
+                })?;
+            let output_type = expr.get_type(schema)?;
+            let args = [left.as_ref().clone(), right.as_ref().clone()];
+            function(name, &args, &output_type, schema, extensions)?
+        }
+        Expr::Not(arg) => function(
+            "not",
+            &[*arg.clone()],
+            &DataType::Boolean,
+            schema,
+            extensions,
+        )?,
+        Expr::IsNull(arg) => function(
+            "is_null",
+            &[*arg.clone()],
+            &DataType::Boolean,
+            schema,
+            extensions,
+        )?,
+        Expr::IsNotNull(arg) => function(
+            "is_not_null",
+            &[*arg.clone()],
+            &DataType::Boolean,
+            schema,
+            extensions,
+        )?,
+        Expr::Cast { expr, data_type } => RexType::Cast(Box::new(expression::Cast {
+            target_type: Some(to_type(data_type, true)?),
+            input: Some(Box::new(to_expression(expr, schema, extensions)?)),
+        })),
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } => {
+            let name = RENAMED_FUNCTIONS
+                .iter()
+                .find(|(ballista, _)| *ballista == name.as_str())
+                .map(|(_, substrait)| substrait.to_string())
+                .unwrap_or_else(|| name.to_lowercase());
+            function(&name, args, return_type, schema, extensions)?
+        }
+        Expr::Sort { .. }
+        | Expr::AggregateFunction { .. }
+        | Expr::Wildcard
+        | Expr::GetField { .. }
+        | Expr::GetIndex { .. } => {
+            return Err(ballista_error(&format!(
+                "The expression {:?} cannot be exported to Substrait",
+                expr
+            )))
+        }
+    };
+    Ok(protobuf::Expression {
+        rex_type: Some(rex_type),
+    })
+}
+
+fn function(
+    name: &str,
+    args: &[Expr],
+    output_type: &DataType,
+    schema: &Schema,
+    extensions: &mut Extensions,
+) -> Result<RexType> {
+    Ok(RexType::ScalarFunction(expression::ScalarFunction {
+        function_reference: extensions.function(name),
+        output_type: Some(to_type(output_type, true)?),
+        arguments: arguments(args, schema, extensions)?,
+    }))
+}
+
+fn arguments(
+    args: &[Expr],
+    schema: &Schema,
+    extensions: &mut Extensions,
+) -> Result<Vec<protobuf::FunctionArgument>> {
+    args.iter()
+        .map(|arg| {
+            Ok(protobuf::FunctionArgument {
+                arg_type: Some(protobuf::function_argument::ArgType::Value(to_expression(
+                    arg, schema, extensions,
+                )?)),
+            })
+        })
+        .collect()
+}
+
+fn field_reference(index: usize) -> expression::FieldReference {
+    use expression::field_reference::{ReferenceType, RootReference, RootType};
+    use expression::reference_segment::{self, StructField};
+    expression::FieldReference {
+        reference_type: Some(ReferenceType::DirectReference(
+            expression::ReferenceSegment {
+                reference_type: Some(reference_segment::ReferenceType::StructField(StructField {
+                    field: index as i32,
+                })),
+            },
+        )),
+        root_type: Some(RootType::RootReference(RootReference {})),
+    }
+}
+
+/// Convert a literal. Unsigned integers are converted to the smallest signed integers that
+/// hold all of their values, since Substrait has no unsigned types, and timestamps are
+/// converted to microseconds.
+fn literal(value: &ScalarValue) -> Result<expression::Literal> {
+    let literal_type = match value {
+        // null literals are untyped in Ballista
+        ScalarValue::Null => LiteralType::Null(to_type(&DataType::Boolean, true)?),
+        ScalarValue::Boolean(v) => LiteralType::Boolean(*v),
+        ScalarValue::Int8(v) => LiteralType::I8(*v as i32),
+        ScalarValue::Int16(v) => LiteralType::I16(*v as i32),
+        ScalarValue::Int32(v) => LiteralType::I32(*v),
+        ScalarValue::Int64(v) => LiteralType::I64(*v),
+        ScalarValue::UInt8(v) => LiteralType::I16(*v as i32),
+        ScalarValue::UInt16(v) => LiteralType::I32(*v as i32),
+        ScalarValue::UInt32(v) => LiteralType::I64(*v as i64),
+        ScalarValue::UInt64(v) => LiteralType::I64(i64::try_from(*v).map_err(|_| {
+            ballista_error(&format!("The literal {} is too large for Substrait", v))
+        })?),
+        ScalarValue::Float32(v) => LiteralType::Fp32(*v),
+        ScalarValue::Float64(v) => LiteralType::Fp64(*v),
+        ScalarValue::Utf8(v) => LiteralType::String(v.clone()),
+        ScalarValue::Binary(v) => LiteralType::Binary(v.clone()),
+        ScalarValue::Date32(v) => LiteralType::Date(*v),
+        ScalarValue::Date64(v) => LiteralType::Date(v.div_euclid(86_400_000) as i32),
+        ScalarValue::Timestamp(v, unit) => {
+            let micros = match unit {
+                TimeUnit::Second => v.checked_mul(1_000_000),
+                TimeUnit::Millisecond => v.checked_mul(1_000),
+                TimeUnit::Microsecond => Some(*v),
+                TimeUnit::Nanosecond => Some(v.div_euclid(1_000)),
+            };
+            LiteralType::Timestamp(micros.ok_or_else(|| {
+                ballista_error(&format!(
+                    "The timestamp {} is out of range for Substrait",
+                    v
+                ))
+            })?)
+        }
+        ScalarValue::Decimal(..) => {
+            return Err(ballista_error(&format!(
+                "The literal {:?} has no Substrait type",
+                value
+            )))
+        }
+        ScalarValue::Struct(values) => LiteralType::Struct(expression::literal::Struct {
+            fields: values.iter().map(literal).collect::<Result<_>>()?,
+        }),
+    };
+    Ok(expression::Literal {
+        nullable: false,
+        literal_type: Some(literal_type),
+    })
+}
+
+macro_rules! array_value {
+    ($array:expr, $array_type:ident, $row:expr) => {
+        $array
+            .as_any()
+            .downcast_ref::<$array_type>()
+            .unwrap()
+            .value($row)
+    };
+}
+
+/// Convert a value of an array to a literal, for the rows of virtual tables
+fn array_literal(array: &ArrayRef, row: usize) -> Result<expression::Literal> {
+    if array.is_null(row) {
+        return Ok(expression::Literal {
+            nullable: true,
+            literal_type: Some(LiteralType::Null(to_type(array.data_type(), true)?)),
+        });
+    }
+    let literal_type = match array.data_type() {
+        DataType::Boolean => LiteralType::Boolean(array_value!(array, BooleanArray, row)),
+        DataType::Int8 => LiteralType::I8(array_value!(array, Int8Array, row) as i32),
+        DataType::Int16 => LiteralType::I16(array_value!(array, Int16Array, row) as i32),
+        DataType::Int32 => LiteralType::I32(array_value!(array, Int32Array, row)),
+        DataType::Int64 => LiteralType::I64(array_value!(array, Int64Array, row)),
+        DataType::Float32 => LiteralType::Fp32(array_value!(array, Float32Array, row)),
+        DataType::Float64 => LiteralType::Fp64(array_value!(array, Float64Array, row)),
+        DataType::Utf8 => LiteralType::String(array_value!(array, StringArray, row).to_owned()),
+        DataType::Binary => LiteralType::Binary(array_value!(array, BinaryArray, row).to_vec()),
+        DataType::Date32(DateUnit::Day) => LiteralType::Date(array_value!(array, Date32Array, row)),
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            LiteralType::Timestamp(array_value!(array, TimestampMicrosecondArray, row))
+        }
+        other => {
+            return Err(ballista_error(&format!(
+                "Values of type {:?} cannot be exported to Substrait",
+                other
+            )))
+        }
+    };
+    Ok(expression::Literal {
+        nullable: false,
+        literal_type: Some(literal_type),
+    })
+}
+
+/// Convert a sort expression, where nulls sort first as in Ballista's sorts
+fn to_sort_field(
+    expr: &Expr,
+    schema: &Schema,
+    extensions: &mut Extensions,
+) -> Result<protobuf::SortField> {
+    use protobuf::sort_field::{SortDirection, SortKind};
+    let (expr, asc) = match expr {
+        Expr::Sort { expr, asc } => (expr.as_ref(), *asc),
+        expr => (expr, true),
+    };
+    let direction = if asc {
+        SortDirection::AscNullsFirst
+    } else {
+        SortDirection::DescNullsFirst
+    };
+    Ok(protobuf::SortField {
+        expr: Some(to_expression(expr, schema, extensions)?),
+        sort_kind: Some(SortKind::Direction(direction as i32)),
+    })
+}
+
+/// Convert an aggregate expression, where `COUNT_DISTINCT` is a distinct invocation of
+/// `count`
+fn to_measure(
+    expr: &Expr,
+    schema: &Schema,
+    extensions: &mut Extensions,
+) -> Result<protobuf::aggregate_rel::Measure> {
+    let (name, args, return_type) = match expr {
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => (name.to_lowercase(), args, return_type),
+        Expr::Alias(expr, _) => return to_measure(expr, schema, extensions),
+        other => {
+            return Err(ballista_error(&format!(
+                "The aggregate expression {:?} cannot be exported to Substrait",
+                other
+            )))
+        }
+    };
+    let (name, invocation) = match name.as_str() {
+        "count_distinct" => (
+            "count".to_owned(),
+            protobuf::AggregationInvocation::Distinct,
+        ),
+        _ => (name, protobuf::AggregationInvocation::All),
+    };
+    // counts are unsigned in Ballista
+    let output_type = if name == "count" {
+        DataType::Int64
+    } else {
+        return_type.clone()
+    };
+    Ok(protobuf::aggregate_rel::Measure {
+        measure: Some(protobuf::AggregateFunction {
+            function_reference: extensions.function(&name),
+            phase: protobuf::AggregationPhase::InitialToResult as i32,
+            output_type: Some(to_type(&output_type, true)?),
+            invocation: invocation as i32,
+            arguments: arguments(args, schema, extensions)?,
+        }),
+        filter: None,
+    })
+}
@@ -0,0 +1,319 @@
+//! The timezone of a session.
+//!
+//! Arrow timestamps are instants in UTC, but text such as the timestamps of CSV files has no
+//! offset, and the fields of a timestamp such as its hour depend on where it is read. The
+//! `ballista.session.timezone` setting, which is `UTC` or a fixed offset such as `+02:00`,
+//! sets the timezone that timestamps without an offset are read in, that `date_part`
+//! extracts the fields of timestamps in, and that timestamps are rendered in when results
+//! are published as JSON.
+//!
+//! The timezone is applied to the plan of a query before it is executed, as the timezone of
+//! its CSV scans and an argument of its `date_part` calls, so that executors interpret the
+//! plan in the timezone of the client rather than their own. Spark contexts set the
+//! timezone of the Spark session instead, which Spark reads and writes timestamps in.
+
+use std::sync::Arc;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, TimeUnit};
+use crate::datasource::csv::CsvReadOptions;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::logicalplan::{lit_str, Expr, LogicalPlan, ScalarValue};
+use crate::optimizer::{collect_outermost, replace_expr};
+
+use chrono::{Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, Timelike};
+
+/// Setting with the timezone of the session, which is `UTC` or an offset such as `+02:00`
+pub const SESSION_TIMEZONE: &str = "ballista.session.timezone";
+
+/// The fields that `date_part` extracts
+const DATE_PARTS: &[&str] = &[
+    "year", "quarter", "month", "week", "day", "dow", "doy", "hour", "minute", "second",
+];
+
+/// Parse a timezone, which is `UTC` or an offset from UTC such as `+02:00`, `-0530` or
+/// `UTC+01`
+pub fn parse_timezone(timezone: &str) -> Result<FixedOffset> {
+    let invalid = || {
+        BallistaError::General(format!(
+            "Invalid timezone '{}', expected UTC or an offset such as +02:00",
+            timezone
+        ))
+    };
+    let trimmed = timezone.trim();
+    if !trimmed.is_ascii() {
+        return Err(invalid());
+    }
+    if trimmed.eq_ignore_ascii_case("utc") || trimmed == "Z" {
+        return Ok(FixedOffset::east(0));
+    }
+    let offset = if trimmed.len() > 3 && trimmed[..3].eq_ignore_ascii_case("utc") {
+        &trimmed[3..]
+    } else {
+        trimmed
+    };
+    let sign = match offset.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+    let rest = &offset[1..];
+    let (hours, minutes) = match rest.len() {
+        2 => (rest, "00"),
+        4 => (&rest[..2], &rest[2..]),
+        5 if &rest[2..3] == ":" => (&rest[..2], &rest[3..]),
+        _ => return Err(invalid()),
+    };
+    if !hours
+        .chars()
+        .chain(minutes.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if minutes >= 60 {
+        return Err(invalid());
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+/// The instant in UTC of a time without an offset in a timezone
+pub fn to_utc(local: NaiveDateTime, timezone: &FixedOffset) -> NaiveDateTime {
+    local - Duration::seconds(timezone.local_minus_utc() as i64)
+}
+
+/// The time in a timezone of a timestamp since the epoch, if it is in the range of dates
+fn to_local(value: i64, unit: &TimeUnit, timezone: &FixedOffset) -> Option<NaiveDateTime> {
+    let per_second = match unit {
+        TimeUnit::Second => 1,
+        TimeUnit::Millisecond => 1_000,
+        TimeUnit::Microsecond => 1_000_000,
+        TimeUnit::Nanosecond => 1_000_000_000,
+    };
+    let nanos = value.rem_euclid(per_second) * (1_000_000_000 / per_second);
+    let utc = NaiveDateTime::from_timestamp_opt(value.div_euclid(per_second), nanos as u32)?;
+    utc.checked_add_signed(Duration::seconds(timezone.local_minus_utc() as i64))
+}
+
+/// Render a timestamp in a timezone with its offset, such as `2020-05-14T14:30:00+02:00`
+pub fn format_timestamp(value: i64, unit: &TimeUnit, timezone: &FixedOffset) -> Option<String> {
+    to_local(value, unit, timezone)
+        .map(|local| format!("{}{}", local.format("%Y-%m-%dT%H:%M:%S%.f"), timezone))
+}
+
+/// Whether an expression is a call of `date_part`
+pub fn is_date_part(expr: &Expr) -> bool {
+    match expr {
+        Expr::ScalarFunction { name, .. } => name == "date_part",
+        _ => false,
+    }
+}
+
+/// The field that a call of `date_part` extracts and the timezone that it extracts it in,
+/// which is UTC unless the session timezone has been applied to the call
+pub fn date_part_args(args: &[Expr]) -> Result<(String, FixedOffset)> {
+    let part = match args.first() {
+        Some(Expr::Literal(ScalarValue::Utf8(part))) => part.to_lowercase(),
+        _ => {
+            return Err(ballista_error(
+                "The first argument of date_part must be a string",
+            ))
+        }
+    };
+    if !DATE_PARTS.contains(&part.as_str()) {
+        return Err(BallistaError::General(format!(
+            "Unsupported date_part field '{}', expected one of {}",
+            part,
+            DATE_PARTS.join(", ")
+        )));
+    }
+    let timezone = match args.get(2) {
+        Some(Expr::Literal(ScalarValue::Utf8(timezone))) => parse_timezone(timezone)?,
+        Some(other) => {
+            return Err(BallistaError::General(format!(
+                "The timezone of date_part must be a string, not {:?}",
+                other
+            )))
+        }
+        None => FixedOffset::east(0),
+    };
+    Ok((part, timezone))
+}
+
+/// Extract a field from each value of an array of timestamps or dates, in the timezone for
+/// timestamps
+pub fn evaluate_date_part(
+    part: &str,
+    array: &ArrayRef,
+    timezone: &FixedOffset,
+) -> Result<ArrayRef> {
+    let values = local_times(array, timezone)?
+        .into_iter()
+        .map(|time| {
+            time.map(|t| match part {
+                "year" => t.year(),
+                "quarter" => t.month0() as i32 / 3 + 1,
+                "month" => t.month() as i32,
+                "week" => t.iso_week().week() as i32,
+                "day" => t.day() as i32,
+                "dow" => t.weekday().num_days_from_sunday() as i32,
+                "doy" => t.ordinal() as i32,
+                "hour" => t.hour() as i32,
+                "minute" => t.minute() as i32,
+                _ => t.second() as i32,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(Arc::new(Int32Array::from(values)))
+}
+
+/// The times of the values of an array, with timestamps in the timezone and dates at midnight
+fn local_times(array: &ArrayRef, timezone: &FixedOffset) -> Result<Vec<Option<NaiveDateTime>>> {
+    macro_rules! times {
+        ($ARRAY:ty, $TO_TIME:expr) => {{
+            let array = array.as_any().downcast_ref::<$ARRAY>().unwrap();
+            Ok((0..array.len())
+                .map(|i| {
+                    if array.is_null(i) {
+                        None
+                    } else {
+                        $TO_TIME(array.value(i))
+                    }
+                })
+                .collect())
+        }};
+    }
+    let epoch = NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0);
+    match array.data_type() {
+        DataType::Timestamp(unit, _) => {
+            let to_time = |v| to_local(v, unit, timezone);
+            match unit {
+                TimeUnit::Second => times!(TimestampSecondArray, to_time),
+                TimeUnit::Millisecond => times!(TimestampMillisecondArray, to_time),
+                TimeUnit::Microsecond => times!(TimestampMicrosecondArray, to_time),
+                TimeUnit::Nanosecond => times!(TimestampNanosecondArray, to_time),
+            }
+        }
+        DataType::Date32(_) => times!(Date32Array, |v| epoch
+            .checked_add_signed(Duration::days(v as i64))),
+        DataType::Date64(_) => times!(Date64Array, |v| epoch
+            .checked_add_signed(Duration::milliseconds(v))),
+        other => Err(BallistaError::General(format!(
+            "date_part expects timestamps or dates, found {:?}",
+            other
+        ))),
+    }
+}
+
+/// Apply the timezone of the session to a plan, as the timezone of its CSV scans and of the
+/// calls of `date_part` in its projections and selections that do not have one. Plans are
+/// left as they are in UTC, which is how timestamps are interpreted without a timezone.
+pub fn apply_session_timezone(plan: &LogicalPlan, timezone: &FixedOffset) -> Result<LogicalPlan> {
+    if timezone.local_minus_utc() == 0 {
+        return Ok(plan.clone());
+    }
+    let name = timezone.to_string();
+    plan.transform_up(|plan| {
+        Ok(match plan {
+            LogicalPlan::Projection {
+                expr,
+                input,
+                schema,
+            } => LogicalPlan::Projection {
+                expr: expr.iter().map(|e| with_timezone(e, &name)).collect(),
+                input,
+                schema,
+            },
+            LogicalPlan::Selection { expr, input } => LogicalPlan::Selection {
+                expr: with_timezone(&expr, &name),
+                input,
+            },
+            mut other => {
+                if let LogicalPlan::FileScan {
+                    file_type,
+                    csv_options,
+                    ..
+                } = &mut other
+                {
+                    if file_type == "csv" {
+                        let options = csv_options.get_or_insert_with(CsvReadOptions::default);
+                        if options.timezone.is_none() {
+                            options.timezone = Some(name.clone());
+                        }
+                    }
+                }
+                other
+            }
+        })
+    })
+}
+
+/// Add a timezone to the calls of `date_part` in an expression that do not have one
+fn with_timezone(expr: &Expr, timezone: &str) -> Expr {
+    let mut calls = vec![];
+    collect_outermost(
+        expr,
+        &|e| match e {
+            Expr::ScalarFunction { args, .. } => is_date_part(e) && args.len() == 2,
+            _ => false,
+        },
+        &mut calls,
+    );
+    calls.iter().fold(expr.clone(), |expr, call| match call {
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } => {
+            let mut args = args.clone();
+            args.push(lit_str(timezone));
+            let with_timezone = Expr::ScalarFunction {
+                name: name.clone(),
+                args,
+                return_type: return_type.clone(),
+            };
+            replace_expr(&expr, call, &with_timezone)
+        }
+        _ => expr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logicalplan::{col, date_part};
+
+    #[test]
+    fn extract_and_render_in_timezone() -> Result<()> {
+        let timezone = parse_timezone("+02:00")?;
+        assert_eq!(
+            parse_timezone("UTC-0530")?.local_minus_utc(),
+            -(5 * 3600 + 1800)
+        );
+        assert!(parse_timezone("Europe/Paris").is_err());
+
+        // 2020-05-14 23:30:00 UTC is the next day two hours east
+        let local = NaiveDate::from_ymd(2020, 5, 14).and_hms(23, 30, 0);
+        let utc = local.timestamp_millis();
+        assert_eq!(to_utc(local, &timezone).timestamp_millis(), utc - 7_200_000);
+        assert_eq!(
+            format_timestamp(utc, &TimeUnit::Millisecond, &timezone),
+            Some("2020-05-15T01:30:00+02:00".to_owned())
+        );
+
+        let expr = with_timezone(&date_part("day", col("t")), &timezone.to_string());
+        let args = match &expr {
+            Expr::ScalarFunction { args, .. } => args.clone(),
+            other => panic!("unexpected expression {:?}", other),
+        };
+        let (part, timezone) = date_part_args(&args)?;
+        let array: ArrayRef = Arc::new(TimestampMillisecondArray::from(vec![Some(utc), None]));
+        let days = evaluate_date_part(&part, &array, &timezone)?;
+        let days = days.as_any().downcast_ref::<Int32Array>().unwrap();
+        assert_eq!(days.value(0), 15);
+        assert!(days.is_null(1));
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+//! TLS for connections between clients and executors (requires the `tls` feature).
+//!
+//! The same settings are used on both sides of a connection. A client verifies the
+//! executor's certificate against the CA bundle and presents its own certificate if one is
+//! configured. An executor presents its certificate and, when a CA bundle is configured,
+//! only accepts clients with a certificate signed by that CA.
+
+use std::collections::HashMap;
+#[cfg(feature = "tls")]
+use std::fs;
+
+use crate::error::{ballista_error, Result};
+
+#[cfg(feature = "tls")]
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+use tonic::transport::{Endpoint, Server};
+
+/// Path to a PEM file with the CA certificates used to verify the other side
+pub const TLS_CA_CERT: &str = "ballista.tls.caCert";
+/// Path to a PEM file with this side's certificate
+pub const TLS_CERT: &str = "ballista.tls.cert";
+/// Path to a PEM file with the private key for the certificate
+pub const TLS_KEY: &str = "ballista.tls.key";
+/// Name that the executor's certificate is verified against, defaulting to the host name
+/// that the client connects to
+pub const TLS_DOMAIN_NAME: &str = "ballista.tls.domainName";
+
+/// TLS settings
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsConfig {
+    pub ca_cert: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub domain_name: Option<String>,
+}
+
+impl TlsConfig {
+    /// Read the TLS configuration from the Context settings, returning `None` if TLS is
+    /// not configured
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Option<Self>> {
+        let config = Self {
+            ca_cert: settings.get(TLS_CA_CERT).cloned(),
+            cert: settings.get(TLS_CERT).cloned(),
+            key: settings.get(TLS_KEY).cloned(),
+            domain_name: settings.get(TLS_DOMAIN_NAME).cloned(),
+        };
+        if config.cert.is_some() != config.key.is_some() {
+            return Err(ballista_error(&format!(
+                "{} and {} must be set together",
+                TLS_CERT, TLS_KEY
+            )));
+        }
+        if config == Self::default() {
+            Ok(None)
+        } else {
+            Ok(Some(config))
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn identity(&self) -> Result<Option<Identity>> {
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                Ok(Some(Identity::from_pem(read_pem(cert)?, read_pem(key)?)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn client_config(&self, host: &str) -> Result<ClientTlsConfig> {
+        let mut config = ClientTlsConfig::with_rustls()
+            .domain_name(self.domain_name.clone().unwrap_or_else(|| host.to_owned()));
+        if let Some(ca_cert) = &self.ca_cert {
+            config = config.ca_certificate(Certificate::from_pem(read_pem(ca_cert)?));
+        }
+        if let Some(identity) = self.identity()? {
+            config = config.identity(identity);
+        }
+        Ok(config)
+    }
+
+    #[cfg(feature = "tls")]
+    fn server_config(&self) -> Result<ServerTlsConfig> {
+        let identity = self.identity()?.ok_or_else(|| {
+            ballista_error(&format!(
+                "{} and {} must be set for an executor to use TLS",
+                TLS_CERT, TLS_KEY
+            ))
+        })?;
+        let mut config = ServerTlsConfig::with_rustls().identity(identity);
+        if let Some(ca_cert) = &self.ca_cert {
+            config = config.client_ca_root(Certificate::from_pem(read_pem(ca_cert)?));
+        }
+        Ok(config)
+    }
+}
+
+/// Create the endpoint for connecting to an executor
+pub fn endpoint(host: &str, port: usize, tls: Option<&TlsConfig>) -> Result<Endpoint> {
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    let endpoint = Endpoint::from_shared(format!("{}://{}:{}", scheme, host, port))
+        .map_err(|e| ballista_error(&format!("Invalid executor address: {:?}", e)))?;
+    match tls {
+        #[cfg(feature = "tls")]
+        Some(tls) => Ok(endpoint.tls_config(tls.client_config(host)?)),
+        #[cfg(not(feature = "tls"))]
+        Some(_) => Err(tls_not_enabled()),
+        None => Ok(endpoint),
+    }
+}
+
+/// Create the server builder for an executor
+pub fn server(tls: Option<&TlsConfig>) -> Result<Server> {
+    match tls {
+        #[cfg(feature = "tls")]
+        Some(tls) => Ok(Server::builder().tls_config(tls.server_config()?)),
+        #[cfg(not(feature = "tls"))]
+        Some(_) => Err(tls_not_enabled()),
+        None => Ok(Server::builder()),
+    }
+}
+
+#[cfg(feature = "tls")]
+fn read_pem(path: &str) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| ballista_error(&format!("Unable to read {}: {:?}", path, e)))
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_not_enabled() -> crate::error::BallistaError {
+    crate::error::BallistaError::NotImplemented("TLS requires the tls feature".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings() -> Result<()> {
+        let mut settings = HashMap::new();
+        assert_eq!(None, TlsConfig::from_settings(&settings)?);
+
+        settings.insert(TLS_CA_CERT.to_owned(), "/etc/ballista/ca.pem".to_owned());
+        settings.insert(TLS_CERT.to_owned(), "/etc/ballista/client.pem".to_owned());
+        assert!(TlsConfig::from_settings(&settings).is_err());
+
+        settings.insert(TLS_KEY.to_owned(), "/etc/ballista/client.key".to_owned());
+        let config = TlsConfig::from_settings(&settings)?.unwrap();
+        assert_eq!(Some("/etc/ballista/ca.pem".to_owned()), config.ca_cert);
+        assert_eq!(None, config.domain_name);
+        Ok(())
+    }
+}
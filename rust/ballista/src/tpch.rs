@@ -0,0 +1,473 @@
+//! TPC-H tables and queries for measuring the performance of the engine.
+//!
+//! The tables are read from a directory with the `.tbl` files that `dbgen` generates, which
+//! are `|` delimited without a header, or with a directory of Parquet files for each table
+//! such as those written by `convert_to_parquet`. Dates are read as `YYYY-MM-DD` strings and
+//! decimals as doubles, so that the same queries run on both formats.
+//!
+//! Queries 1, 3, 5, 6 and 12 are expressed with the DataFrame API, with the substitution
+//! parameters of the validation queries of the specification.
+
+use std::time::{Duration, Instant};
+
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::dataframe::{avg, count, sum, Context, DataFrame};
+use crate::datasource::csv::CsvReadOptions;
+use crate::error::{BallistaError, Result};
+use crate::logicalplan::{col, col_index, lit_str, Expr, ScalarValue};
+
+/// The tables of the TPC-H schema
+pub const TPCH_TABLES: &[&str] = &[
+    "part", "supplier", "partsupp", "customer", "orders", "lineitem", "nation", "region",
+];
+
+/// The TPC-H queries that are expressed with the DataFrame API
+pub const TPCH_QUERIES: &[usize] = &[1, 3, 5, 6, 12];
+
+/// The format of the files of the TPC-H tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TpchFormat {
+    /// `|` delimited `.tbl` files without a header, as generated by `dbgen`
+    Tbl,
+    /// A directory of Parquet files for each table
+    Parquet,
+}
+
+impl TpchFormat {
+    /// Parse a format name, which is `tbl`, `csv` or `parquet`
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "tbl" | "csv" => Ok(TpchFormat::Tbl),
+            "parquet" => Ok(TpchFormat::Parquet),
+            other => Err(BallistaError::General(format!(
+                "Unsupported TPC-H format '{}', expected tbl or parquet",
+                other
+            ))),
+        }
+    }
+}
+
+/// The schema of a TPC-H table
+pub fn tpch_schema(table: &str) -> Result<Schema> {
+    let fields: Vec<(&str, DataType)> = match table {
+        "part" => vec![
+            ("p_partkey", DataType::Int32),
+            ("p_name", DataType::Utf8),
+            ("p_mfgr", DataType::Utf8),
+            ("p_brand", DataType::Utf8),
+            ("p_type", DataType::Utf8),
+            ("p_size", DataType::Int32),
+            ("p_container", DataType::Utf8),
+            ("p_retailprice", DataType::Float64),
+            ("p_comment", DataType::Utf8),
+        ],
+        "supplier" => vec![
+            ("s_suppkey", DataType::Int32),
+            ("s_name", DataType::Utf8),
+            ("s_address", DataType::Utf8),
+            ("s_nationkey", DataType::Int32),
+            ("s_phone", DataType::Utf8),
+            ("s_acctbal", DataType::Float64),
+            ("s_comment", DataType::Utf8),
+        ],
+        "partsupp" => vec![
+            ("ps_partkey", DataType::Int32),
+            ("ps_suppkey", DataType::Int32),
+            ("ps_availqty", DataType::Int32),
+            ("ps_supplycost", DataType::Float64),
+            ("ps_comment", DataType::Utf8),
+        ],
+        "customer" => vec![
+            ("c_custkey", DataType::Int32),
+            ("c_name", DataType::Utf8),
+            ("c_address", DataType::Utf8),
+            ("c_nationkey", DataType::Int32),
+            ("c_phone", DataType::Utf8),
+            ("c_acctbal", DataType::Float64),
+            ("c_mktsegment", DataType::Utf8),
+            ("c_comment", DataType::Utf8),
+        ],
+        "orders" => vec![
+            ("o_orderkey", DataType::Int32),
+            ("o_custkey", DataType::Int32),
+            ("o_orderstatus", DataType::Utf8),
+            ("o_totalprice", DataType::Float64),
+            ("o_orderdate", DataType::Utf8),
+            ("o_orderpriority", DataType::Utf8),
+            ("o_clerk", DataType::Utf8),
+            ("o_shippriority", DataType::Int32),
+            ("o_comment", DataType::Utf8),
+        ],
+        "lineitem" => vec![
+            ("l_orderkey", DataType::Int32),
+            ("l_partkey", DataType::Int32),
+            ("l_suppkey", DataType::Int32),
+            ("l_linenumber", DataType::Int32),
+            ("l_quantity", DataType::Float64),
+            ("l_extendedprice", DataType::Float64),
+            ("l_discount", DataType::Float64),
+            ("l_tax", DataType::Float64),
+            ("l_returnflag", DataType::Utf8),
+            ("l_linestatus", DataType::Utf8),
+            ("l_shipdate", DataType::Utf8),
+            ("l_commitdate", DataType::Utf8),
+            ("l_receiptdate", DataType::Utf8),
+            ("l_shipinstruct", DataType::Utf8),
+            ("l_shipmode", DataType::Utf8),
+            ("l_comment", DataType::Utf8),
+        ],
+        "nation" => vec![
+            ("n_nationkey", DataType::Int32),
+            ("n_name", DataType::Utf8),
+            ("n_regionkey", DataType::Int32),
+            ("n_comment", DataType::Utf8),
+        ],
+        "region" => vec![
+            ("r_regionkey", DataType::Int32),
+            ("r_name", DataType::Utf8),
+            ("r_comment", DataType::Utf8),
+        ],
+        other => {
+            return Err(BallistaError::General(format!(
+                "Unknown TPC-H table '{}'",
+                other
+            )))
+        }
+    };
+    // the columns of TPC-H tables are never null
+    Ok(Schema::new(
+        fields
+            .into_iter()
+            .map(|(name, data_type)| Field::new(name, data_type, false))
+            .collect(),
+    ))
+}
+
+/// The time that an iteration of a query took to run, and the number of rows it returned
+#[derive(Debug, Clone)]
+pub struct QueryTiming {
+    pub query: usize,
+    pub iteration: usize,
+    pub elapsed: Duration,
+    pub rows: usize,
+}
+
+/// The TPC-H tables of a directory, read by a context
+pub struct TpchTables {
+    ctx: Context,
+    path: String,
+    format: TpchFormat,
+    partitions: Option<usize>,
+}
+
+impl TpchTables {
+    /// Read the tables of a directory, with a `.tbl` file or a directory of Parquet files
+    /// named after each table
+    pub fn new(ctx: Context, path: &str, format: TpchFormat) -> Self {
+        Self {
+            ctx,
+            path: path.trim_end_matches('/').to_owned(),
+            format,
+            partitions: None,
+        }
+    }
+
+    /// Set the number of partitions to read each `.tbl` file as
+    pub fn with_partitions(mut self, partitions: usize) -> Self {
+        self.partitions = Some(partitions);
+        self
+    }
+
+    /// Scan a table
+    pub fn table(&self, name: &str) -> Result<DataFrame> {
+        let schema = tpch_schema(name)?;
+        match self.format {
+            TpchFormat::Tbl => {
+                let mut options = CsvReadOptions::new().with_delimiter(b'|');
+                if let Some(partitions) = self.partitions {
+                    options = options.with_partitions(partitions);
+                }
+                // the trailing delimiter of each line is an extra empty field that is not
+                // in the schema, so it is not read
+                self.ctx.read_csv_with_options(
+                    &format!("{}/{}.tbl", self.path, name),
+                    Some(schema),
+                    None,
+                    false,
+                    options,
+                )
+            }
+            TpchFormat::Parquet => {
+                let df = self
+                    .ctx
+                    .read_parquet(&format!("{}/{}", self.path, name), None)?;
+                for field in schema.fields() {
+                    let found = df.schema().field_with_name(field.name())?;
+                    if found.data_type() != field.data_type() {
+                        return Err(BallistaError::General(format!(
+                            "Column {} of TPC-H table {} is {:?}, expected {:?}",
+                            field.name(),
+                            name,
+                            found.data_type(),
+                            field.data_type()
+                        )));
+                    }
+                }
+                Ok(df)
+            }
+        }
+    }
+
+    /// Build one of the queries in `TPCH_QUERIES`
+    pub fn query(&self, query: usize) -> Result<DataFrame> {
+        match query {
+            1 => self.q1(),
+            3 => self.q3(),
+            5 => self.q5(),
+            6 => self.q6(),
+            12 => self.q12(),
+            other => Err(BallistaError::General(format!(
+                "TPC-H query {} is not supported, expected one of {:?}",
+                other, TPCH_QUERIES
+            ))),
+        }
+    }
+
+    /// Run a query a number of times, timing each iteration
+    pub async fn run(&self, query: usize, iterations: usize) -> Result<Vec<QueryTiming>> {
+        let mut timings = vec![];
+        for iteration in 0..iterations {
+            let start = Instant::now();
+            let batches = self.query(query)?.collect().await?;
+            timings.push(QueryTiming {
+                query,
+                iteration,
+                elapsed: start.elapsed(),
+                rows: batches.iter().map(|b| b.num_rows()).sum(),
+            });
+        }
+        Ok(timings)
+    }
+
+    /// Pricing summary report
+    fn q1(&self) -> Result<DataFrame> {
+        self.table("lineitem")?
+            .filter(col("l_shipdate").lt_eq(&lit_str("1998-09-02")))?
+            .project(vec![
+                col("l_returnflag"),
+                col("l_linestatus"),
+                col("l_quantity"),
+                col("l_extendedprice"),
+                col("l_discount"),
+                revenue().alias("disc_price"),
+                revenue()
+                    .multiply(&lit_f64(1.0).plus(&col("l_tax")))
+                    .alias("charge"),
+            ])?
+            .aggregate(
+                vec![col("l_returnflag"), col("l_linestatus")],
+                vec![
+                    sum(col("l_quantity")),
+                    sum(col("l_extendedprice")),
+                    sum(col("disc_price")),
+                    sum(col("charge")),
+                    avg(col("l_quantity")),
+                    avg(col("l_extendedprice")),
+                    avg(col("l_discount")),
+                    count(col("l_quantity")),
+                ],
+            )?
+            .project(aliases(&[
+                "l_returnflag",
+                "l_linestatus",
+                "sum_qty",
+                "sum_base_price",
+                "sum_disc_price",
+                "sum_charge",
+                "avg_qty",
+                "avg_price",
+                "avg_disc",
+                "count_order",
+            ]))?
+            .sort(vec![
+                col("l_returnflag").sort(true),
+                col("l_linestatus").sort(true),
+            ])
+    }
+
+    /// Shipping priority
+    fn q3(&self) -> Result<DataFrame> {
+        let customer = self
+            .table("customer")?
+            .filter(col("c_mktsegment").eq(&lit_str("BUILDING")))?;
+        let orders = self
+            .table("orders")?
+            .filter(col("o_orderdate").lt(&lit_str("1995-03-15")))?;
+        let lineitem = self
+            .table("lineitem")?
+            .filter(col("l_shipdate").gt(&lit_str("1995-03-15")))?;
+        customer
+            .join(&orders, &[("c_custkey", "o_custkey")])?
+            .join(&lineitem, &[("o_orderkey", "l_orderkey")])?
+            .project(vec![
+                col("l_orderkey"),
+                col("o_orderdate"),
+                col("o_shippriority"),
+                revenue().alias("volume"),
+            ])?
+            .aggregate(
+                vec![col("l_orderkey"), col("o_orderdate"), col("o_shippriority")],
+                vec![sum(col("volume"))],
+            )?
+            .project(aliases(&[
+                "l_orderkey",
+                "o_orderdate",
+                "o_shippriority",
+                "revenue",
+            ]))?
+            .sort(vec![
+                col("revenue").sort(false),
+                col("o_orderdate").sort(true),
+            ])?
+            .limit(10)
+    }
+
+    /// Local supplier volume
+    fn q5(&self) -> Result<DataFrame> {
+        let region = self
+            .table("region")?
+            .filter(col("r_name").eq(&lit_str("ASIA")))?;
+        let orders = self.table("orders")?.filter(
+            col("o_orderdate")
+                .gt_eq(&lit_str("1994-01-01"))
+                .and(&col("o_orderdate").lt(&lit_str("1995-01-01"))),
+        )?;
+        self.table("nation")?
+            .join(&region, &[("n_regionkey", "r_regionkey")])?
+            .join(&self.table("customer")?, &[("n_nationkey", "c_nationkey")])?
+            .join(&orders, &[("c_custkey", "o_custkey")])?
+            .join(&self.table("lineitem")?, &[("o_orderkey", "l_orderkey")])?
+            .join(
+                &self.table("supplier")?,
+                &[("l_suppkey", "s_suppkey"), ("n_nationkey", "s_nationkey")],
+            )?
+            .project(vec![col("n_name"), revenue().alias("volume")])?
+            .aggregate(vec![col("n_name")], vec![sum(col("volume"))])?
+            .project(aliases(&["n_name", "revenue"]))?
+            .sort(vec![col("revenue").sort(false)])
+    }
+
+    /// Forecasting revenue change
+    fn q6(&self) -> Result<DataFrame> {
+        self.table("lineitem")?
+            .filter(
+                col("l_shipdate")
+                    .gt_eq(&lit_str("1994-01-01"))
+                    .and(&col("l_shipdate").lt(&lit_str("1995-01-01")))
+                    .and(&col("l_discount").gt_eq(&lit_f64(0.05)))
+                    .and(&col("l_discount").lt_eq(&lit_f64(0.07)))
+                    .and(&col("l_quantity").lt(&lit_f64(24.0))),
+            )?
+            .project(vec![col("l_extendedprice")
+                .multiply(&col("l_discount"))
+                .alias("volume")])?
+            .aggregate(vec![], vec![sum(col("volume"))])?
+            .project(aliases(&["revenue"]))
+    }
+
+    /// Shipping modes and order priority
+    fn q12(&self) -> Result<DataFrame> {
+        let lineitem = self.table("lineitem")?.filter(
+            col("l_shipmode")
+                .eq(&lit_str("MAIL"))
+                .or(&col("l_shipmode").eq(&lit_str("SHIP")))
+                .and(&col("l_commitdate").lt(&col("l_receiptdate")))
+                .and(&col("l_shipdate").lt(&col("l_commitdate")))
+                .and(&col("l_receiptdate").gt_eq(&lit_str("1994-01-01")))
+                .and(&col("l_receiptdate").lt(&lit_str("1995-01-01"))),
+        )?;
+        let high = col("o_orderpriority")
+            .eq(&lit_str("1-URGENT"))
+            .or(&col("o_orderpriority").eq(&lit_str("2-HIGH")));
+        self.table("orders")?
+            .join(&lineitem, &[("o_orderkey", "l_orderkey")])?
+            .project(vec![
+                col("l_shipmode"),
+                cast_f64(high.clone()).alias("high"),
+                cast_f64(high.not()).alias("low"),
+            ])?
+            .aggregate(
+                vec![col("l_shipmode")],
+                vec![sum(col("high")), sum(col("low"))],
+            )?
+            .project(aliases(&[
+                "l_shipmode",
+                "high_line_count",
+                "low_line_count",
+            ]))?
+            .sort(vec![col("l_shipmode").sort(true)])
+    }
+}
+
+/// Convert the `.tbl` files of the TPC-H tables in a directory to a directory of Parquet
+/// files for each table in another directory
+pub async fn convert_to_parquet(ctx: Context, input: &str, output: &str) -> Result<()> {
+    let tables = TpchTables::new(ctx, input, TpchFormat::Tbl);
+    for table in TPCH_TABLES {
+        let path = format!("{}/{}", output.trim_end_matches('/'), table);
+        tables.table(table)?.write_parquet(&path).await?;
+    }
+    Ok(())
+}
+
+/// The price of line items after their discount
+fn revenue() -> Expr {
+    col("l_extendedprice").multiply(&lit_f64(1.0).minus(&col("l_discount")))
+}
+
+fn lit_f64(value: f64) -> Expr {
+    Expr::Literal(ScalarValue::Float64(value))
+}
+
+/// Booleans as 1.0 and 0.0, so that they can be summed
+fn cast_f64(expr: Expr) -> Expr {
+    Expr::Cast {
+        expr: Box::new(expr),
+        data_type: DataType::Float64,
+    }
+}
+
+/// Name the columns of an aggregate, whose aggregates are named after their functions
+fn aliases(names: &[&str]) -> Vec<Expr> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| col_index(i).alias(name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn queries_resolve_against_schemas() -> Result<()> {
+        let tables = TpchTables::new(
+            Context::local(HashMap::new()),
+            "/data/tpch",
+            TpchFormat::Tbl,
+        );
+        for query in TPCH_QUERIES {
+            tables.query(*query)?;
+        }
+        let q1 = tables.query(1)?;
+        assert_eq!(q1.schema().field(2).name(), "sum_qty");
+        assert_eq!(q1.schema().fields().len(), 10);
+        assert!(tables.query(2).is_err());
+        assert!(tpch_schema("lineitem")?
+            .field_with_name("l_shipdate")
+            .is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! Tracing of the planning and execution of queries.
+//!
+//! Ballista records spans with the `tracing` crate for the translation and optimization of
+//! plans, the execution of each partition, the stages and tasks of distributed jobs, and
+//! the requests sent to executors and schedulers. Applications collect the spans by
+//! installing a subscriber, such as a `tracing-opentelemetry` layer that exports them to an
+//! OpenTelemetry collector.
+//!
+//! Clients give each query an id, which is sent with its requests so that the spans
+//! recorded by executors and schedulers for the query carry the same `query_id`. The spans
+//! of the tasks of distributed jobs carry the `job_id` of the job.
+
+#[cfg(feature = "local-execution")]
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "local-execution")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "local-execution")]
+use crate::arrow::datatypes::SchemaRef;
+#[cfg(feature = "local-execution")]
+use crate::arrow::error::Result as ArrowResult;
+#[cfg(feature = "local-execution")]
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+#[cfg(feature = "local-execution")]
+use crate::datafusion::error::Result as DataFusionResult;
+#[cfg(feature = "local-execution")]
+use crate::datafusion::execution::physical_plan::Partition;
+use crate::error::{ballista_error, Result};
+use crate::plan::Action;
+
+use chrono::Utc;
+use tonic::metadata::MetadataMap;
+use tonic::Request;
+#[cfg(feature = "local-execution")]
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+
+const QUERY_ID_METADATA: &str = "x-ballista-query-id";
+
+static NEXT_QUERY_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Create an id for a query that is unique across the clients of a cluster
+pub fn new_query_id() -> String {
+    format!(
+        "{:x}-{}-{}",
+        Utc::now().timestamp_millis(),
+        std::process::id(),
+        NEXT_QUERY_ID.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Send the id of the query that a request belongs to
+pub fn request_query_id<T>(query_id: &str, request: &mut Request<T>) -> Result<()> {
+    let value = query_id
+        .parse()
+        .map_err(|_| ballista_error(&format!("Invalid query id '{}'", query_id)))?;
+    request.metadata_mut().insert(QUERY_ID_METADATA, value);
+    Ok(())
+}
+
+/// The id of the query that a request belongs to, if the client sent one
+pub fn requested_query_id(metadata: &MetadataMap) -> Option<String> {
+    metadata
+        .get(QUERY_ID_METADATA)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned())
+}
+
+/// Create the span for executing an action, with the ids of the query and the job that the
+/// action belongs to
+pub fn action_span(action: &Action, query_id: Option<&str>) -> Span {
+    let query_id = query_id.unwrap_or("");
+    match action {
+        Action::ShuffleWrite {
+            job_id,
+            stage_id,
+            partition,
+            ..
+        } => info_span!(
+            "shuffle_write",
+            query_id,
+            job_id = job_id.as_str(),
+            stage_id,
+            partition
+        ),
+        Action::WriteParquet {
+            path, partition, ..
+        } => info_span!("write_parquet", query_id, path = path.as_str(), partition),
+        Action::WriteCsv { path, .. } => info_span!("write_csv", query_id, path = path.as_str()),
+        Action::Collect { .. } => info_span!("collect", query_id),
+        Action::FetchSchema { path, file_type } => info_span!(
+            "fetch_schema",
+            query_id,
+            path = path.as_str(),
+            file_type = file_type.as_str()
+        ),
+        Action::ListTables => info_span!("list_tables", query_id),
+    }
+}
+
+/// Record the execution of each partition in a span that is a child of the current span,
+/// with the number of batches and rows that the partition produced
+#[cfg(feature = "local-execution")]
+pub fn trace_partitions(partitions: Vec<Arc<dyn Partition>>) -> Vec<Arc<dyn Partition>> {
+    partitions
+        .into_iter()
+        .enumerate()
+        .map(|(i, partition)| {
+            Arc::new(TracedPartition {
+                partition,
+                span: info_span!(
+                    "execute_partition",
+                    partition = i,
+                    batches = Empty,
+                    rows = Empty
+                ),
+            }) as Arc<dyn Partition>
+        })
+        .collect()
+}
+
+/// A partition whose execution is recorded in a span
+#[cfg(feature = "local-execution")]
+struct TracedPartition {
+    partition: Arc<dyn Partition>,
+    span: Span,
+}
+
+#[cfg(feature = "local-execution")]
+impl fmt::Debug for TracedPartition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TracedPartition")
+            .field("span", &self.span)
+            .finish()
+    }
+}
+
+#[cfg(feature = "local-execution")]
+impl Partition for TracedPartition {
+    fn execute(&self) -> DataFusionResult<Arc<Mutex<dyn RecordBatchReader + Send + Sync>>> {
+        let reader = self.span.in_scope(|| self.partition.execute())?;
+        Ok(Arc::new(Mutex::new(TracedReader {
+            reader,
+            span: self.span.clone(),
+            batches: 0,
+            rows: 0,
+        })))
+    }
+}
+
+/// Enters the span of a partition while the batches of the partition are produced
+#[cfg(feature = "local-execution")]
+struct TracedReader {
+    reader: Arc<Mutex<dyn RecordBatchReader + Send + Sync>>,
+    span: Span,
+    batches: usize,
+    rows: usize,
+}
+
+#[cfg(feature = "local-execution")]
+impl RecordBatchReader for TracedReader {
+    fn schema(&mut self) -> SchemaRef {
+        self.reader.lock().unwrap().schema()
+    }
+
+    fn next_batch(&mut self) -> ArrowResult<Option<RecordBatch>> {
+        let _enter = self.span.enter();
+        let batch = self.reader.lock().unwrap().next_batch()?;
+        match &batch {
+            Some(batch) => {
+                self.batches += 1;
+                self.rows += batch.num_rows();
+            }
+            None => {
+                self.span.record("batches", &self.batches);
+                self.span.record("rows", &self.rows);
+            }
+        }
+        Ok(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn propagate_query_id() -> Result<()> {
+        let query_id = new_query_id();
+        assert_ne!(query_id, new_query_id());
+
+        let mut request = Request::new(());
+        assert_eq!(None, requested_query_id(request.metadata()));
+        request_query_id(&query_id, &mut request)?;
+        assert_eq!(Some(query_id), requested_query_id(request.metadata()));
+        Ok(())
+    }
+}
@@ -0,0 +1,1470 @@
+//! Translation of Ballista logical plans to DataFusion logical plans, for local execution.
+//!
+//! Operators that DataFusion cannot execute, such as joins, sorts, aggregates with a memory
+//! budget and the scans of files that DataFusion cannot read, are executed while the plan
+//! is translated, and their results are registered with the DataFusion context as tables
+//! that the translated plan scans. Plans produced by DataFusion's SQL planner are
+//! translated back to Ballista plans by `from_datafusion_plan`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::aggregate::{HashAggregate, AGGREGATE_MEMORY_LIMIT};
+use crate::arrow::datatypes::{Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::datafusion::datasource::MemTable;
+use crate::datafusion::error::{ExecutionError, Result};
+use crate::datafusion::execution::context::ExecutionContext;
+use crate::datafusion::logicalplan::{
+    Expr as DFExpr, LogicalPlan as DFLogicalPlan, Operator as DFOperator,
+    ScalarValue as DFScalarValue,
+};
+use crate::datasource::avro::read_avro_batches;
+use crate::datasource::csv::{csv_splits, read_csv_batches, CsvCompression, CsvSplitTable};
+use crate::datasource::ipc::IpcTable;
+use crate::datasource::json::read_json_batches;
+use crate::datasource::object_store::ObjectStoreRegistry;
+#[cfg(feature = "orc")]
+use crate::datasource::orc::read_orc_batches;
+use crate::datasource::parquet::{
+    parquet_file_schema, read_parquet_batches, row_group_splits, RowGroupTable,
+};
+use crate::datasource::partitioned::add_partition_columns;
+use crate::datasource::sql::{conjuncts, read_sql_batches};
+use crate::datasource::{FileTable, DEFAULT_BATCH_SIZE};
+use crate::decimal::{self, decimal_precision_and_scale, is_decimal_arithmetic};
+use crate::execution_metrics::{MetricsCollector, OperatorMetrics};
+use crate::join::hash_join;
+use crate::logic::{self, is_three_valued};
+use crate::logicalplan::{
+    decimal_to_f64, exprlist_to_fields, Expr, LogicalPlan, Operator, ScalarValue,
+};
+use crate::memory::{batch_memory_size, QueryMemory};
+use crate::nested::is_nested_access;
+use crate::optimizer::{collect_outermost, replace_expr};
+use crate::physical_plan::table::UnionTable;
+use crate::sort::{sort_keys, ExternalSorter, DEFAULT_SORT_MEMORY_LIMIT, SORT_MEMORY_LIMIT};
+use crate::timezone::is_date_part;
+
+use tracing::info_span;
+
+/// Used to give the results of joins unique table names
+static NEXT_JOIN_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Used to give the results of aggregates that are executed with a memory budget unique
+/// table names
+static NEXT_AGGREGATE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Used to give the results of sorts unique table names
+static NEXT_SORT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Used to give the results of extension nodes unique table names
+static NEXT_EXTENSION_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Used to give the results of unions unique table names
+static NEXT_UNION_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Used to give the inputs of projections and selections with expressions that DataFusion
+/// cannot evaluate unique table names
+static NEXT_EVALUATED_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Translate Ballista plan to DataFusion plan
+pub fn translate_plan(ctx: &mut ExecutionContext, plan: &LogicalPlan) -> Result<DFLogicalPlan> {
+    translate_plan_with_object_stores(ctx, plan, &ObjectStoreRegistry::new(&HashMap::new()))
+}
+
+/// Translate Ballista plan to DataFusion plan, reading remote files through the given
+/// object stores. Each operator is translated in its own span, which includes staging
+/// remote files and executing joins.
+pub fn translate_plan_with_object_stores(
+    ctx: &mut ExecutionContext,
+    plan: &LogicalPlan,
+    object_stores: &ObjectStoreRegistry,
+) -> Result<DFLogicalPlan> {
+    translate_plan_with_metrics(
+        ctx,
+        plan,
+        object_stores,
+        &MetricsCollector::new(),
+        &QueryMemory::default(),
+    )
+}
+
+/// Translate Ballista plan to DataFusion plan, recording the metrics of the scans and
+/// joins, which Ballista executes while the plan is translated. The memory of the batches
+/// that are read into memory is reserved for the query.
+pub fn translate_plan_with_metrics(
+    ctx: &mut ExecutionContext,
+    plan: &LogicalPlan,
+    object_stores: &ObjectStoreRegistry,
+    metrics: &MetricsCollector,
+    memory: &QueryMemory,
+) -> Result<DFLogicalPlan> {
+    let span = info_span!("translate", operator = plan.operator_name());
+    let _enter = span.enter();
+    let start = Instant::now();
+    match plan {
+        LogicalPlan::MemoryScan(batches) => {
+            let mut scan = OperatorMetrics::new(plan.operator_name());
+            for batch in batches {
+                scan.rows += batch.num_rows() as u64;
+                scan.bytes_read += batch_memory_size(batch) as u64;
+            }
+            metrics.record(scan);
+
+            let table_name = "df_t0"; //TODO generate unique table name
+            let schema = plan.schema();
+            // the batches are scanned as a single partition, unlike memory tables
+            let provider = MemTable::new(Arc::new(schema.clone()), vec![batches.clone()])?;
+            ctx.register_table(table_name, Box::new(provider));
+            Ok(DFLogicalPlan::TableScan {
+                schema_name: "default".to_owned(),
+                table_name: table_name.to_owned(),
+                table_schema: Box::new(schema.clone()),
+                projected_schema: Box::new(schema.clone()),
+                projection: None,
+            })
+        }
+        LogicalPlan::FileScan {
+            path,
+            paths,
+            files,
+            partition_columns,
+            file_type,
+            schema,
+            projection,
+            projected_schema,
+            csv_options,
+            filters,
+            limit,
+        } => {
+            //TODO generate unique table name
+            let table_name = "tbd".to_owned();
+
+            // remote objects are read from local copies, while database tables are read
+            // with the queries that take the place of the files
+            let files = &if file_type == "sql" {
+                files.clone()
+            } else {
+                object_stores
+                    .stage_files(files)
+                    .map_err(|e| ExecutionError::General(format!("{:?}", e)))?
+            };
+
+            match file_type.as_str() {
+                "csv" => {
+                    let options = csv_options.clone().unwrap_or_default();
+                    if files.len() == 1
+                        && options.is_default()
+                        && options.compression_for(&files[0]) == CsvCompression::Uncompressed
+                        && limit.is_none()
+                    {
+                        ctx.register_csv(&table_name, &files[0], schema, options.has_header)
+                    } else if limit.is_none() {
+                        // the files, or byte ranges of them, are read as the partitions of
+                        // the scan
+                        let splits = csv_splits(files, &options)
+                            .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+                        let schema = Arc::new(schema.clone());
+                        let provider =
+                            CsvSplitTable::new(splits, schema, options.has_header, options);
+                        ctx.register_table(&table_name, Box::new(provider));
+                    } else {
+                        // limited scans read a file at a time so that they can stop early
+                        let mut batches = vec![];
+                        for file in files {
+                            if limit_reached(&batches, *limit) {
+                                break;
+                            }
+                            batches.extend(
+                                read_csv_batches(
+                                    file,
+                                    schema,
+                                    options.has_header,
+                                    &options,
+                                    DEFAULT_BATCH_SIZE,
+                                )
+                                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?,
+                            );
+                        }
+                        register_batches(ctx, &table_name, schema, batches, memory)?;
+                    }
+                }
+                "parquet" => {
+                    // DataFusion can scan a file or directory directly, with a partition for
+                    // each file, when every file has the same schema and at most one row
+                    // group, otherwise the files are adapted to the merged schema. Filtered
+                    // scans are read here so that row groups can be skipped, and limited
+                    // scans so that they stop reading once they have enough rows. Unlimited
+                    // scans have a partition for each row group, which is read as the
+                    // partition is executed.
+                    let footers = object_stores.footers();
+                    // decimal columns are only read by Ballista's reader
+                    let uniform = files.iter().all(|f| match parquet_file_schema(f, footers) {
+                        Ok(file_schema) => {
+                            file_schema == *schema
+                                && schema.fields().iter().all(|field| {
+                                    decimal_precision_and_scale(schema, field.name()).is_none()
+                                })
+                        }
+                        Err(_) => false,
+                    });
+                    let single_row_groups = files.iter().all(|f| match footers.footer(f) {
+                        Ok(footer) => footer.row_groups.len() <= 1,
+                        Err(_) => false,
+                    });
+                    if uniform
+                        && single_row_groups
+                        && partition_columns.is_empty()
+                        && filters.is_empty()
+                        && limit.is_none()
+                        && paths.is_empty()
+                        && Path::new(path).exists()
+                    {
+                        ctx.register_parquet(&table_name, path.as_str())?
+                    } else if limit.is_none() {
+                        let splits = row_group_splits(files, schema, filters, footers)
+                            .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+                        let provider = RowGroupTable::new(
+                            splits,
+                            Arc::new(schema.clone()),
+                            partition_columns.clone(),
+                        );
+                        ctx.register_table(&table_name, Box::new(provider));
+                    } else {
+                        let arrow_schema = Arc::new(schema.clone());
+                        let mut batches = vec![];
+                        for file in files {
+                            if limit_reached(&batches, *limit) {
+                                break;
+                            }
+                            let remaining = limit.map(|n| n.saturating_sub(num_rows(&batches)));
+                            for batch in read_parquet_batches(
+                                file,
+                                schema,
+                                filters,
+                                remaining,
+                                DEFAULT_BATCH_SIZE,
+                                footers,
+                            )
+                            .and_then(|batches| {
+                                batches
+                                    .iter()
+                                    .map(|b| {
+                                        add_partition_columns(
+                                            b,
+                                            &arrow_schema,
+                                            partition_columns,
+                                            file,
+                                        )
+                                    })
+                                    .collect::<crate::error::Result<Vec<_>>>()
+                            })
+                            .map_err(|e| ExecutionError::General(format!("{:?}", e)))?
+                            {
+                                batches.push(batch);
+                            }
+                        }
+                        register_batches(ctx, &table_name, schema, batches, memory)?;
+                    }
+                }
+                // unlimited scans read each file as it is scanned, so that operators such as
+                // aggregates receive the batches of a file at a time
+                "json" if limit.is_none() => ctx.register_table(
+                    &table_name,
+                    Box::new(FileTable::new(
+                        files.clone(),
+                        Arc::new(schema.clone()),
+                        read_json_batches,
+                    )),
+                ),
+                "avro" if limit.is_none() => ctx.register_table(
+                    &table_name,
+                    Box::new(FileTable::new(
+                        files.clone(),
+                        Arc::new(schema.clone()),
+                        read_avro_batches,
+                    )),
+                ),
+                "json" => {
+                    let mut batches = vec![];
+                    for file in files {
+                        if limit_reached(&batches, *limit) {
+                            break;
+                        }
+                        batches.extend(
+                            read_json_batches(file, schema, DEFAULT_BATCH_SIZE)
+                                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?,
+                        );
+                    }
+                    register_batches(ctx, &table_name, schema, batches, memory)?;
+                }
+                "avro" => {
+                    let mut batches = vec![];
+                    for file in files {
+                        if limit_reached(&batches, *limit) {
+                            break;
+                        }
+                        batches.extend(
+                            read_avro_batches(file, schema, DEFAULT_BATCH_SIZE)
+                                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?,
+                        );
+                    }
+                    register_batches(ctx, &table_name, schema, batches, memory)?;
+                }
+                "ipc" => ctx.register_table(
+                    &table_name,
+                    Box::new(IpcTable::new(files.clone(), Arc::new(schema.clone()))),
+                ),
+                "sql" => {
+                    let pool = object_stores.io_pool();
+                    let mut batches = vec![];
+                    for query in files {
+                        if limit_reached(&batches, *limit) {
+                            break;
+                        }
+                        batches.extend(
+                            read_sql_batches(path, query, schema, DEFAULT_BATCH_SIZE, &pool)
+                                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?,
+                        );
+                    }
+                    register_batches(ctx, &table_name, schema, batches, memory)?;
+                }
+                #[cfg(feature = "orc")]
+                "orc" => ctx.register_table(
+                    &table_name,
+                    Box::new(FileTable::new(
+                        files.clone(),
+                        Arc::new(schema.clone()),
+                        read_orc_batches,
+                    )),
+                ),
+                #[cfg(not(feature = "orc"))]
+                "orc" => {
+                    return Err(ExecutionError::NotImplemented(format!(
+                        "Reading ORC data requires the orc feature: {}",
+                        path
+                    )))
+                }
+                other => {
+                    return Err(ExecutionError::NotImplemented(format!(
+                        "Reading files of type '{}' is not supported",
+                        other
+                    )))
+                }
+            };
+
+            // files registered with DataFusion are only read when the plan is executed, so
+            // the time spent reading them is part of the time spent executing the plan
+            let mut scan = OperatorMetrics::new(plan.operator_name());
+            if file_type != "sql" {
+                scan.bytes_read = files
+                    .iter()
+                    .filter_map(|f| std::fs::metadata(f).ok())
+                    .map(|m| m.len())
+                    .sum();
+            }
+            scan.elapsed = start.elapsed();
+            metrics.record(scan);
+
+            Ok(DFLogicalPlan::TableScan {
+                schema_name: "default".to_owned(),
+                table_name: table_name.clone(),
+                table_schema: Box::new(schema.clone()),
+                projected_schema: Box::new(projected_schema.clone()),
+                projection: projection.clone(),
+            })
+        }
+        LogicalPlan::EmptyRelation { schema } => Ok(DFLogicalPlan::EmptyRelation {
+            schema: Box::new(schema.clone()),
+        }),
+        // registered tables must already be registered with the DataFusion context
+        LogicalPlan::TableScan {
+            table_name,
+            schema,
+            projection,
+            projected_schema,
+        } => Ok(DFLogicalPlan::TableScan {
+            schema_name: "default".to_owned(),
+            table_name: table_name.clone(),
+            table_schema: Box::new(schema.clone()),
+            projected_schema: Box::new(projected_schema.clone()),
+            projection: projection.clone(),
+        }),
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } if !ballista_exprs(expr, input.schema()).is_empty() => {
+            let found = ballista_exprs(expr, input.schema());
+            let (input, expr) =
+                evaluate_ballista_exprs(ctx, input, expr, found, object_stores, metrics, memory)?;
+            Ok(DFLogicalPlan::Projection {
+                expr: expr
+                    .iter()
+                    .map(|e| translate_expr(e))
+                    .collect::<Result<Vec<_>>>()?,
+                input: Box::new(input),
+                schema: Box::new(schema.clone()),
+            })
+        }
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => Ok(DFLogicalPlan::Projection {
+            expr: expr
+                .iter()
+                .map(|e| translate_expr(e))
+                .collect::<Result<Vec<_>>>()?,
+            input: Box::new(translate_plan_with_metrics(
+                ctx,
+                input,
+                object_stores,
+                metrics,
+                memory,
+            )?),
+            schema: Box::new(schema.clone()),
+        }),
+        // the conjunctions at the top of a predicate are left to DataFusion, since a row is
+        // only selected when the predicate is true
+        LogicalPlan::Selection { expr, input }
+            if !ballista_exprs(conjuncts(expr), input.schema()).is_empty() =>
+        {
+            let found = ballista_exprs(conjuncts(expr), input.schema());
+            let exprs = [expr.clone()];
+            let (scan, expr) =
+                evaluate_ballista_exprs(ctx, input, &exprs, found, object_stores, metrics, memory)?;
+            let selection = DFLogicalPlan::Selection {
+                expr: translate_expr(&expr[0])?,
+                input: Box::new(scan),
+            };
+            // the evaluated columns are projected away again
+            let width = input.schema().fields().len();
+            Ok(DFLogicalPlan::Projection {
+                expr: (0..width).map(DFExpr::Column).collect(),
+                input: Box::new(selection),
+                schema: Box::new(input.schema().clone()),
+            })
+        }
+        LogicalPlan::Selection { expr, input } => Ok(DFLogicalPlan::Selection {
+            expr: translate_expr(expr)?,
+            input: Box::new(translate_plan_with_metrics(
+                ctx,
+                input,
+                object_stores,
+                metrics,
+                memory,
+            )?),
+        }),
+        LogicalPlan::Aggregate {
+            group_expr,
+            aggr_expr,
+            input,
+            schema,
+        } => {
+            // aggregates with a memory budget are executed here, so that their groups can be
+            // spilled to disk, as long as their expressions are supported. Aggregates of
+            // decimals are executed here too, since DataFusion cannot compute with them, and
+            // the expressions that they aggregate are projected first.
+            let decimals = aggr_expr
+                .iter()
+                .any(|e| decimal::aggregate_type(e, input.schema()).is_some());
+            let limit = object_stores
+                .setting(AGGREGATE_MEMORY_LIMIT)
+                .and_then(|n| n.parse::<usize>().ok())
+                .or(if decimals { Some(usize::MAX) } else { None });
+            let projected;
+            let (aggregate_input, aggregate_groups, aggregate_exprs): (
+                &LogicalPlan,
+                &[Expr],
+                &[Expr],
+            ) = if decimals {
+                projected = project_aggregate_inputs(input, group_expr, aggr_expr)?;
+                (&projected.0, projected.1.as_slice(), projected.2.as_slice())
+            } else {
+                (&**input, group_expr.as_slice(), aggr_expr.as_slice())
+            };
+            let aggregate = limit.and_then(|limit| {
+                HashAggregate::try_new(
+                    aggregate_groups,
+                    aggregate_exprs,
+                    aggregate_input.schema(),
+                    schema,
+                    limit,
+                )
+            });
+            if let Some(mut aggregate) = aggregate {
+                let span = info_span!("hash_aggregate");
+                let input = aggregate_input;
+                execute_plan(ctx, input, object_stores, metrics, memory, &mut |batch| {
+                    span.in_scope(|| aggregate.update(batch))
+                })?;
+                let spills = aggregate.spills();
+                let batches = span
+                    .in_scope(|| aggregate.finish())
+                    .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+                let mut operator = OperatorMetrics::new(plan.operator_name());
+                operator.rows = num_rows(&batches) as u64;
+                operator.memory = batches_memory_size(&batches);
+                operator.spills = spills;
+                operator.elapsed = start.elapsed();
+                metrics.record(operator);
+
+                let table_name = format!(
+                    "aggregate_{}",
+                    NEXT_AGGREGATE_ID.fetch_add(1, Ordering::SeqCst)
+                );
+                register_batches(ctx, &table_name, schema, batches, memory)?;
+                return Ok(DFLogicalPlan::TableScan {
+                    schema_name: "default".to_owned(),
+                    table_name,
+                    table_schema: Box::new(schema.clone()),
+                    projected_schema: Box::new(schema.clone()),
+                    projection: None,
+                });
+            }
+            Ok(DFLogicalPlan::Aggregate {
+                group_expr: group_expr
+                    .iter()
+                    .map(|e| translate_expr(e))
+                    .collect::<Result<Vec<_>>>()?,
+                aggr_expr: aggr_expr
+                    .iter()
+                    .map(|e| translate_expr(e))
+                    .collect::<Result<Vec<_>>>()?,
+                input: Box::new(translate_plan_with_metrics(
+                    ctx,
+                    input,
+                    object_stores,
+                    metrics,
+                    memory,
+                )?),
+                schema: Box::new(schema.clone()),
+            })
+        }
+        LogicalPlan::Sort {
+            expr,
+            input,
+            schema,
+        } => {
+            // DataFusion cannot sort yet, so the input is executed and sorted here, spilling
+            // sorted runs to disk when the input does not fit in the memory budget
+            let keys = sort_keys(expr, input.schema())
+                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+            let memory_limit = object_stores
+                .setting(SORT_MEMORY_LIMIT)
+                .and_then(|n| n.parse::<usize>().ok())
+                .unwrap_or(DEFAULT_SORT_MEMORY_LIMIT);
+            let mut sorter = ExternalSorter::new(schema, keys, memory_limit, DEFAULT_BATCH_SIZE);
+            let span = info_span!("external_sort");
+            let (mut rows, mut memory) = (0, 0);
+            execute_plan(ctx, input, object_stores, metrics, memory, &mut |batch| {
+                rows += batch.num_rows() as u64;
+                memory += batch_memory_size(batch) as u64;
+                span.in_scope(|| sorter.insert(batch.clone()))
+            })?;
+            let mut sort = OperatorMetrics::new(plan.operator_name());
+            sort.rows = rows;
+            sort.memory = memory;
+            sort.spills = sorter.spills();
+            let table = span
+                .in_scope(|| sorter.finish())
+                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+            sort.elapsed = start.elapsed();
+            metrics.record(sort);
+
+            let table_name = format!("sort_{}", NEXT_SORT_ID.fetch_add(1, Ordering::SeqCst));
+            ctx.register_table(&table_name, table);
+            Ok(DFLogicalPlan::TableScan {
+                schema_name: "default".to_owned(),
+                table_name,
+                table_schema: Box::new(schema.clone()),
+                projected_schema: Box::new(schema.clone()),
+                projection: None,
+            })
+        }
+        LogicalPlan::Limit {
+            expr,
+            input,
+            schema,
+        } => Ok(DFLogicalPlan::Limit {
+            expr: translate_expr(expr)?,
+            input: Box::new(translate_plan_with_metrics(
+                ctx,
+                input,
+                object_stores,
+                metrics,
+                memory,
+            )?),
+            schema: Box::new(schema.clone()),
+        }),
+        // the hint only affects how distributed queries are planned
+        LogicalPlan::Broadcast { input } => {
+            translate_plan_with_metrics(ctx, input, object_stores, metrics, memory)
+        }
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            null_equals_null,
+            schema,
+        } => {
+            // DataFusion does not support joins yet, so both inputs are executed and joined
+            // in memory. The inputs are executed one at a time because scans of files are
+            // registered with the same table name.
+            // the memory of the inputs is released once they have been joined
+            let left = collect_plan(ctx, left, object_stores, metrics, memory)?;
+            let left_memory = memory
+                .reserve(&left)
+                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+            let right = collect_plan(ctx, right, object_stores, metrics, memory)?;
+            let right_memory = memory
+                .reserve(&right)
+                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+            let batches = info_span!("hash_join", left_batches = left.len())
+                .in_scope(|| hash_join(&left, &right, on, *null_equals_null, schema))
+                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+            let mut join = OperatorMetrics::new(plan.operator_name());
+            join.rows = batches.iter().map(|b| b.num_rows() as u64).sum();
+            join.memory = batches_memory_size(&batches);
+            join.elapsed = start.elapsed();
+            metrics.record(join);
+            drop((left, right, left_memory, right_memory));
+
+            let table_name = format!("join_{}", NEXT_JOIN_ID.fetch_add(1, Ordering::SeqCst));
+            register_batches(ctx, &table_name, schema, batches, memory)?;
+            Ok(DFLogicalPlan::TableScan {
+                schema_name: "default".to_owned(),
+                table_name,
+                table_schema: Box::new(schema.clone()),
+                projected_schema: Box::new(schema.clone()),
+                projection: None,
+            })
+        }
+        LogicalPlan::Union { inputs, schema } => {
+            // the inputs are planned one at a time because scans of files are registered with
+            // the same table name, and the partitions of their plans are read as the union
+            // is scanned, adapting the columns of each input to the union by name
+            let mut partitions = vec![];
+            for input in inputs {
+                if let LogicalPlan::EmptyRelation { .. } = input {
+                    continue;
+                }
+                let input =
+                    translate_plan_with_metrics(ctx, input, object_stores, metrics, memory)?;
+                let input = info_span!("optimize").in_scope(|| ctx.optimize(&input))?;
+                let input = info_span!("create_physical_plan")
+                    .in_scope(|| ctx.create_physical_plan(&input, DEFAULT_BATCH_SIZE))?;
+                partitions.extend(input.partitions()?);
+            }
+            let mut union = OperatorMetrics::new(plan.operator_name());
+            union.elapsed = start.elapsed();
+            metrics.record(union);
+
+            let table_name = format!("union_{}", NEXT_UNION_ID.fetch_add(1, Ordering::SeqCst));
+            let provider = UnionTable::new(Arc::new(schema.clone()), partitions);
+            ctx.register_table(&table_name, Box::new(provider));
+            Ok(DFLogicalPlan::TableScan {
+                schema_name: "default".to_owned(),
+                table_name,
+                table_schema: Box::new(schema.clone()),
+                projected_schema: Box::new(schema.clone()),
+                projection: None,
+            })
+        }
+        LogicalPlan::Extension { node } => {
+            // extension nodes are executed on the batches of their inputs, one input at a
+            // time like the inputs of joins
+            let mut inputs = vec![];
+            let mut input_memory = vec![];
+            for input in node.inputs() {
+                let batches = collect_plan(ctx, input, object_stores, metrics, memory)?;
+                input_memory.push(
+                    memory
+                        .reserve(&batches)
+                        .map_err(|e| ExecutionError::General(format!("{:?}", e)))?,
+                );
+                inputs.push(batches);
+            }
+            let batches = info_span!("extension", name = node.name())
+                .in_scope(|| node.execute(&inputs))
+                .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+            let mut extension = OperatorMetrics::new(plan.operator_name());
+            extension.rows = num_rows(&batches) as u64;
+            extension.memory = batches_memory_size(&batches);
+            extension.elapsed = start.elapsed();
+            metrics.record(extension);
+            drop((inputs, input_memory));
+
+            let table_name = format!(
+                "extension_{}",
+                NEXT_EXTENSION_ID.fetch_add(1, Ordering::SeqCst)
+            );
+            let schema = node.schema();
+            register_batches(ctx, &table_name, schema, batches, memory)?;
+            Ok(DFLogicalPlan::TableScan {
+                schema_name: "default".to_owned(),
+                table_name,
+                table_schema: Box::new(schema.clone()),
+                projected_schema: Box::new(schema.clone()),
+                projection: None,
+            })
+        }
+        other => Err(ExecutionError::General(format!(
+            "Cannot translate operator to DataFusion: {:?}",
+            other
+        ))),
+    }
+}
+
+/// Project the grouping expressions of an aggregate and the arguments of its aggregate
+/// functions that are not columns or literals, after the columns of the input, so that the
+/// aggregate only groups and aggregates columns
+fn project_aggregate_inputs(
+    input: &LogicalPlan,
+    group_expr: &[Expr],
+    aggr_expr: &[Expr],
+) -> Result<(LogicalPlan, Vec<Expr>, Vec<Expr>)> {
+    let input_schema = input.schema();
+    let width = input_schema.fields().len();
+    let mut projected: Vec<Expr> = vec![];
+    let mut column = |expr: &Expr| match expr {
+        Expr::Column(_) | Expr::Literal(_) => expr.clone(),
+        Expr::UnresolvedColumn(name) if input_schema.index_of(name).is_ok() => {
+            Expr::Column(input_schema.index_of(name).unwrap())
+        }
+        expr => {
+            let i = match projected.iter().position(|e| e == expr) {
+                Some(i) => i,
+                None => {
+                    projected.push(expr.clone());
+                    projected.len() - 1
+                }
+            };
+            Expr::Column(width + i)
+        }
+    };
+    let group_expr: Vec<Expr> = group_expr.iter().map(|e| column(e)).collect();
+    let aggr_expr: Vec<Expr> = aggr_expr
+        .iter()
+        .map(|e| {
+            let (e, alias) = match e {
+                Expr::Alias(e, alias) => (e.as_ref(), Some(alias)),
+                e => (e, None),
+            };
+            let e = match e {
+                Expr::AggregateFunction {
+                    name,
+                    args,
+                    return_type,
+                } => Expr::AggregateFunction {
+                    name: name.clone(),
+                    args: args.iter().map(|arg| column(arg)).collect(),
+                    return_type: return_type.clone(),
+                },
+                e => e.clone(),
+            };
+            match alias {
+                Some(alias) => Expr::Alias(Box::new(e), alias.clone()),
+                None => e,
+            }
+        })
+        .collect();
+
+    let mut expr: Vec<Expr> = (0..width).map(Expr::Column).collect();
+    expr.extend(
+        projected
+            .into_iter()
+            .enumerate()
+            .map(|(i, e)| Expr::Alias(Box::new(e), format!("__aggregate_input_{}", i))),
+    );
+    let fields = exprlist_to_fields(&expr, input_schema)?;
+    let schema = decimal::schema_with_decimals(fields, &expr, input_schema);
+    let projection = LogicalPlan::Projection {
+        expr,
+        input: Box::new(input.clone()),
+        schema,
+    };
+    Ok((projection, group_expr, aggr_expr))
+}
+
+/// Execute a plan in the DataFusion context, passing each batch of the results to a
+/// function as it is produced rather than collecting the results
+fn execute_plan(
+    ctx: &mut ExecutionContext,
+    plan: &LogicalPlan,
+    object_stores: &ObjectStoreRegistry,
+    metrics: &MetricsCollector,
+    memory: &QueryMemory,
+    f: &mut dyn FnMut(&RecordBatch) -> crate::error::Result<()>,
+) -> Result<()> {
+    if let LogicalPlan::EmptyRelation { .. } = plan {
+        return Ok(());
+    }
+    let plan = translate_plan_with_metrics(ctx, plan, object_stores, metrics, memory)?;
+    let plan = info_span!("optimize").in_scope(|| ctx.optimize(&plan))?;
+    let plan = info_span!("create_physical_plan")
+        .in_scope(|| ctx.create_physical_plan(&plan, DEFAULT_BATCH_SIZE))?;
+    for partition in plan.partitions()? {
+        let reader = partition.execute()?;
+        let mut reader = reader.lock().unwrap();
+        while let Some(batch) = reader.next_batch()? {
+            f(&batch).map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Execute a plan in the DataFusion context, collecting the results
+fn collect_plan(
+    ctx: &mut ExecutionContext,
+    plan: &LogicalPlan,
+    object_stores: &ObjectStoreRegistry,
+    metrics: &MetricsCollector,
+    memory: &QueryMemory,
+) -> Result<Vec<RecordBatch>> {
+    if let LogicalPlan::EmptyRelation { .. } = plan {
+        return Ok(vec![]);
+    }
+    let plan = translate_plan_with_metrics(ctx, plan, object_stores, metrics, memory)?;
+    let plan = info_span!("optimize").in_scope(|| ctx.optimize(&plan))?;
+    let plan = info_span!("create_physical_plan")
+        .in_scope(|| ctx.create_physical_plan(&plan, DEFAULT_BATCH_SIZE))?;
+    info_span!("collect").in_scope(|| ctx.collect(plan.as_ref()))
+}
+
+fn num_rows(batches: &[RecordBatch]) -> usize {
+    batches.iter().map(|b| b.num_rows()).sum()
+}
+
+fn batches_memory_size(batches: &[RecordBatch]) -> u64 {
+    batches.iter().map(|b| batch_memory_size(b) as u64).sum()
+}
+
+/// Whether a scan has read at least as many rows as its limit
+fn limit_reached(batches: &[RecordBatch], limit: Option<usize>) -> bool {
+    match limit {
+        Some(n) => num_rows(batches) >= n,
+        None => false,
+    }
+}
+
+/// The outermost subexpressions of expressions on the columns of the schema that Ballista
+/// evaluates rather than DataFusion, which are nested accesses, calls of `date_part`,
+/// expressions with three-valued logic and arithmetic on decimals
+fn ballista_exprs<'a>(exprs: impl IntoIterator<Item = &'a Expr>, schema: &Schema) -> Vec<Expr> {
+    let mut found = vec![];
+    for expr in exprs {
+        collect_outermost(
+            expr,
+            &|e| {
+                is_nested_access(e)
+                    || is_date_part(e)
+                    || is_decimal_arithmetic(e, schema)
+                    || is_three_valued(e, schema)
+            },
+            &mut found,
+        );
+    }
+    found
+}
+
+/// Evaluate subexpressions of the expressions of a projection or selection that DataFusion
+/// cannot evaluate. The operands of expressions with three-valued logic are computed by a
+/// projection of the input first. Returns a scan of the input with a column for each
+/// subexpression, which follow the columns of the input and of the operands, and the
+/// expressions with the subexpressions replaced by those columns.
+fn evaluate_ballista_exprs(
+    ctx: &mut ExecutionContext,
+    input: &LogicalPlan,
+    exprs: &[Expr],
+    found: Vec<Expr>,
+    object_stores: &ObjectStoreRegistry,
+    metrics: &MetricsCollector,
+    memory: &QueryMemory,
+) -> Result<(DFLogicalPlan, Vec<Expr>)> {
+    let start = Instant::now();
+    let input_schema = input.schema();
+    let width = input_schema.fields().len();
+    // nested accesses and arithmetic on decimals are evaluated on the columns of the input
+    let on_input = |e: &Expr| is_nested_access(e) || is_decimal_arithmetic(e, input_schema);
+    let mut operands = vec![];
+    for expr in &found {
+        if !on_input(expr) {
+            logic::operands(expr, &mut operands);
+        }
+    }
+    let projection;
+    let source = if operands.is_empty() {
+        input
+    } else {
+        let mut fields = input_schema.fields().clone();
+        for (i, operand) in operands.iter().enumerate() {
+            let data_type = operand.get_type(input_schema)?;
+            fields.push(Field::new(&format!("__operand_{}", i), data_type, true));
+        }
+        let mut expr: Vec<Expr> = (0..width).map(Expr::Column).collect();
+        expr.extend(operands.iter().cloned());
+        projection = LogicalPlan::Projection {
+            expr,
+            input: Box::new(input.clone()),
+            schema: Schema::new(fields),
+        };
+        &projection
+    };
+    let evaluated: Vec<Expr> = found
+        .iter()
+        .map(|e| {
+            if on_input(e) {
+                e.clone()
+            } else {
+                logic::replace_operands(e, &operands, width)
+            }
+        })
+        .collect();
+
+    let source_width = source.schema().fields().len();
+    let mut fields = source.schema().fields().clone();
+    for (i, expr) in found.iter().enumerate() {
+        let data_type = expr.get_type(input_schema)?;
+        fields.push(Field::new(&format!("__evaluated_{}", i), data_type, true));
+    }
+    let schema = Schema::new(fields);
+    let batch_schema = Arc::new(schema.clone());
+
+    let mut batches = vec![];
+    execute_plan(ctx, source, object_stores, metrics, memory, &mut |batch| {
+        let mut columns = batch.columns().to_vec();
+        for expr in &evaluated {
+            columns.push(if is_decimal_arithmetic(expr, input_schema) {
+                decimal::evaluate(expr, batch, input_schema)?
+            } else {
+                logic::evaluate(expr, batch)?
+            });
+        }
+        batches.push(RecordBatch::try_new(batch_schema.clone(), columns)?);
+        Ok(())
+    })?;
+    let mut operator = OperatorMetrics::new("Evaluate");
+    operator.rows = num_rows(&batches) as u64;
+    operator.memory = batches_memory_size(&batches);
+    operator.elapsed = start.elapsed();
+    metrics.record(operator);
+
+    let table_name = format!(
+        "evaluated_{}",
+        NEXT_EVALUATED_ID.fetch_add(1, Ordering::SeqCst)
+    );
+    register_batches(ctx, &table_name, &schema, batches, memory)?;
+    let exprs = exprs
+        .iter()
+        .map(|expr| {
+            found.iter().enumerate().fold(expr.clone(), |expr, (i, e)| {
+                replace_expr(&expr, e, &Expr::Column(source_width + i))
+            })
+        })
+        .collect();
+    let scan = DFLogicalPlan::TableScan {
+        schema_name: "default".to_owned(),
+        table_name,
+        table_schema: Box::new(schema.clone()),
+        projected_schema: Box::new(schema),
+        projection: None,
+    };
+    Ok((scan, exprs))
+}
+
+/// Register batches that have been read into memory as a table, holding their memory until
+/// the query is dropped
+fn register_batches(
+    ctx: &mut ExecutionContext,
+    table_name: &str,
+    schema: &Schema,
+    batches: Vec<RecordBatch>,
+    memory: &QueryMemory,
+) -> Result<()> {
+    memory
+        .hold(&batches)
+        .map_err(|e| ExecutionError::General(format!("{:?}", e)))?;
+    let provider = MemTable::new(Arc::new(schema.clone()), vec![batches])?;
+    ctx.register_table(table_name, Box::new(provider));
+    Ok(())
+}
+
+/// Translate Ballista expression to DataFusion expression
+fn translate_expr(expr: &Expr) -> Result<DFExpr> {
+    match expr {
+        Expr::Alias(expr, alias) => Ok(DFExpr::Alias(
+            Box::new(translate_expr(expr.as_ref())?),
+            alias.clone(),
+        )),
+        Expr::Column(index) => Ok(DFExpr::Column(*index)),
+        Expr::UnresolvedColumn(name) => Ok(DFExpr::UnresolvedColumn(name.clone())),
+        Expr::Cast { expr, data_type } => Ok(DFExpr::Cast {
+            expr: Box::new(translate_expr(expr)?),
+            data_type: data_type.clone(),
+        }),
+        Expr::Literal(value) => {
+            // DataFusion has no temporal literals, so they are integers cast to the type
+            let integer = match value {
+                ScalarValue::Date32(v) => Some(DFScalarValue::Int32(*v)),
+                ScalarValue::Date64(v) | ScalarValue::Timestamp(v, _) => {
+                    Some(DFScalarValue::Int64(*v))
+                }
+                _ => None,
+            };
+            match integer {
+                Some(integer) => Ok(DFExpr::Cast {
+                    expr: Box::new(DFExpr::Literal(integer)),
+                    data_type: value.get_datatype(),
+                }),
+                None => Ok(DFExpr::Literal(translate_scalar_value(value)?)),
+            }
+        }
+        Expr::BinaryExpr { left, op, right } => {
+            let left = translate_expr(left)?;
+            let right = translate_expr(right)?;
+            let op = translate_operator(op)?;
+            Ok(DFExpr::BinaryExpr {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            })
+        }
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => {
+            let args = args
+                .iter()
+                .map(|e| translate_expr(e))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(DFExpr::AggregateFunction {
+                name: name.to_owned(),
+                args,
+                return_type: return_type.clone(),
+            })
+        }
+        other => Err(ExecutionError::General(format!(
+            "Cannot translate expression to DataFusion: {:?}",
+            other
+        ))),
+    }
+}
+
+fn translate_operator(op: &Operator) -> Result<DFOperator> {
+    match op {
+        Operator::Eq => Ok(DFOperator::Eq),
+        Operator::NotEq => Ok(DFOperator::NotEq),
+        Operator::Lt => Ok(DFOperator::Lt),
+        Operator::LtEq => Ok(DFOperator::LtEq),
+        Operator::Gt => Ok(DFOperator::Gt),
+        Operator::GtEq => Ok(DFOperator::GtEq),
+        Operator::And => Ok(DFOperator::And),
+        Operator::Or => Ok(DFOperator::Or),
+        Operator::Plus => Ok(DFOperator::Plus),
+        Operator::Minus => Ok(DFOperator::Minus),
+        Operator::Multiply => Ok(DFOperator::Multiply),
+        Operator::Divide => Ok(DFOperator::Divide),
+        Operator::Like => Ok(DFOperator::Like),
+        Operator::NotLike => Ok(DFOperator::NotLike),
+        Operator::Modulus => Ok(DFOperator::Modulus),
+        other => Err(ExecutionError::General(format!(
+            "Cannot translate binary operator to DataFusion: {:?}",
+            other
+        ))),
+    }
+}
+
+fn translate_scalar_value(value: &ScalarValue) -> Result<DFScalarValue> {
+    match value {
+        ScalarValue::Boolean(v) => Ok(DFScalarValue::Boolean(*v)),
+        ScalarValue::UInt8(v) => Ok(DFScalarValue::UInt8(*v)),
+        ScalarValue::UInt16(v) => Ok(DFScalarValue::UInt16(*v)),
+        ScalarValue::UInt32(v) => Ok(DFScalarValue::UInt32(*v)),
+        ScalarValue::UInt64(v) => Ok(DFScalarValue::UInt64(*v)),
+        ScalarValue::Int8(v) => Ok(DFScalarValue::Int8(*v)),
+        ScalarValue::Int16(v) => Ok(DFScalarValue::Int16(*v)),
+        ScalarValue::Int32(v) => Ok(DFScalarValue::Int32(*v)),
+        ScalarValue::Int64(v) => Ok(DFScalarValue::Int64(*v)),
+        ScalarValue::Float32(v) => Ok(DFScalarValue::Float32(*v)),
+        ScalarValue::Float64(v) => Ok(DFScalarValue::Float64(*v)),
+        ScalarValue::Utf8(v) => Ok(DFScalarValue::Utf8(v.clone())),
+        ScalarValue::Decimal(v, _, scale) => Ok(DFScalarValue::Float64(decimal_to_f64(*v, *scale))),
+        other => Err(ExecutionError::General(format!(
+            "Cannot translate scalar value to DataFusion: {:?}",
+            other
+        ))),
+    }
+}
+
+/// Translate a DataFusion plan to a Ballista plan, such as a plan that DataFusion's SQL
+/// planner produced. DataFusion table scans become scans of the tables of the same name,
+/// which must be registered with the Ballista context that executes the plan.
+pub fn from_datafusion_plan(plan: &DFLogicalPlan) -> Result<LogicalPlan> {
+    let input = |input: &DFLogicalPlan| -> Result<Box<LogicalPlan>> {
+        Ok(Box::new(from_datafusion_plan(input)?))
+    };
+    let exprs = |exprs: &[DFExpr]| -> Result<Vec<Expr>> {
+        exprs.iter().map(from_datafusion_expr).collect()
+    };
+    match plan {
+        DFLogicalPlan::Projection {
+            expr,
+            input: projection_input,
+            schema,
+        } => Ok(LogicalPlan::Projection {
+            expr: exprs(expr)?,
+            input: input(projection_input)?,
+            schema: schema.as_ref().clone(),
+        }),
+        DFLogicalPlan::Selection {
+            expr,
+            input: selection_input,
+        } => Ok(LogicalPlan::Selection {
+            expr: from_datafusion_expr(expr)?,
+            input: input(selection_input)?,
+        }),
+        DFLogicalPlan::Aggregate {
+            group_expr,
+            aggr_expr,
+            input: aggregate_input,
+            schema,
+        } => Ok(LogicalPlan::Aggregate {
+            input: input(aggregate_input)?,
+            group_expr: exprs(group_expr)?,
+            aggr_expr: exprs(aggr_expr)?,
+            schema: schema.as_ref().clone(),
+        }),
+        DFLogicalPlan::Sort {
+            expr,
+            input: sort_input,
+            schema,
+        } => Ok(LogicalPlan::Sort {
+            expr: exprs(expr)?,
+            input: input(sort_input)?,
+            schema: schema.as_ref().clone(),
+        }),
+        DFLogicalPlan::Limit {
+            expr,
+            input: limit_input,
+            schema,
+        } => Ok(LogicalPlan::Limit {
+            expr: from_datafusion_expr(expr)?,
+            input: input(limit_input)?,
+            schema: schema.as_ref().clone(),
+        }),
+        DFLogicalPlan::TableScan {
+            table_name,
+            table_schema,
+            projected_schema,
+            projection,
+            ..
+        } => Ok(LogicalPlan::TableScan {
+            table_name: table_name.clone(),
+            schema: table_schema.as_ref().clone(),
+            projection: projection.clone(),
+            projected_schema: projected_schema.as_ref().clone(),
+        }),
+        DFLogicalPlan::EmptyRelation { schema } => Ok(LogicalPlan::EmptyRelation {
+            schema: schema.as_ref().clone(),
+        }),
+        other => Err(ExecutionError::General(format!(
+            "Cannot translate DataFusion operator to Ballista: {:?}",
+            other
+        ))),
+    }
+}
+
+/// Translate DataFusion expression to Ballista expression
+fn from_datafusion_expr(expr: &DFExpr) -> Result<Expr> {
+    let boxed = |expr: &DFExpr| -> Result<Box<Expr>> { Ok(Box::new(from_datafusion_expr(expr)?)) };
+    let exprs = |exprs: &[DFExpr]| -> Result<Vec<Expr>> {
+        exprs.iter().map(from_datafusion_expr).collect()
+    };
+    match expr {
+        DFExpr::Alias(expr, alias) => Ok(Expr::Alias(boxed(expr)?, alias.clone())),
+        DFExpr::Column(index) => Ok(Expr::Column(*index)),
+        DFExpr::UnresolvedColumn(name) => Ok(Expr::UnresolvedColumn(name.clone())),
+        DFExpr::Literal(value) => Ok(Expr::Literal(from_datafusion_scalar_value(value)?)),
+        DFExpr::BinaryExpr { left, op, right } => Ok(Expr::BinaryExpr {
+            left: boxed(left)?,
+            op: from_datafusion_operator(op)?,
+            right: boxed(right)?,
+        }),
+        DFExpr::Not(expr) => Ok(Expr::Not(boxed(expr)?)),
+        DFExpr::IsNull(expr) => Ok(Expr::IsNull(boxed(expr)?)),
+        DFExpr::IsNotNull(expr) => Ok(Expr::IsNotNull(boxed(expr)?)),
+        DFExpr::Cast { expr, data_type } => Ok(Expr::Cast {
+            expr: boxed(expr)?,
+            data_type: data_type.clone(),
+        }),
+        DFExpr::Sort { expr, asc, .. } => Ok(Expr::Sort {
+            expr: boxed(expr)?,
+            asc: *asc,
+        }),
+        DFExpr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } => Ok(Expr::ScalarFunction {
+            name: name.clone(),
+            args: exprs(args)?,
+            return_type: return_type.clone(),
+        }),
+        DFExpr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => Ok(Expr::AggregateFunction {
+            name: name.clone(),
+            args: exprs(args)?,
+            return_type: return_type.clone(),
+        }),
+        other => Err(ExecutionError::General(format!(
+            "Cannot translate DataFusion expression to Ballista: {:?}",
+            other
+        ))),
+    }
+}
+
+fn from_datafusion_operator(op: &DFOperator) -> Result<Operator> {
+    match op {
+        DFOperator::Eq => Ok(Operator::Eq),
+        DFOperator::NotEq => Ok(Operator::NotEq),
+        DFOperator::Lt => Ok(Operator::Lt),
+        DFOperator::LtEq => Ok(Operator::LtEq),
+        DFOperator::Gt => Ok(Operator::Gt),
+        DFOperator::GtEq => Ok(Operator::GtEq),
+        DFOperator::And => Ok(Operator::And),
+        DFOperator::Or => Ok(Operator::Or),
+        DFOperator::Plus => Ok(Operator::Plus),
+        DFOperator::Minus => Ok(Operator::Minus),
+        DFOperator::Multiply => Ok(Operator::Multiply),
+        DFOperator::Divide => Ok(Operator::Divide),
+        DFOperator::Like => Ok(Operator::Like),
+        DFOperator::NotLike => Ok(Operator::NotLike),
+        DFOperator::Modulus => Ok(Operator::Modulus),
+        other => Err(ExecutionError::General(format!(
+            "Cannot translate DataFusion binary operator to Ballista: {:?}",
+            other
+        ))),
+    }
+}
+
+fn from_datafusion_scalar_value(value: &DFScalarValue) -> Result<ScalarValue> {
+    match value {
+        DFScalarValue::Boolean(v) => Ok(ScalarValue::Boolean(*v)),
+        DFScalarValue::UInt8(v) => Ok(ScalarValue::UInt8(*v)),
+        DFScalarValue::UInt16(v) => Ok(ScalarValue::UInt16(*v)),
+        DFScalarValue::UInt32(v) => Ok(ScalarValue::UInt32(*v)),
+        DFScalarValue::UInt64(v) => Ok(ScalarValue::UInt64(*v)),
+        DFScalarValue::Int8(v) => Ok(ScalarValue::Int8(*v)),
+        DFScalarValue::Int16(v) => Ok(ScalarValue::Int16(*v)),
+        DFScalarValue::Int32(v) => Ok(ScalarValue::Int32(*v)),
+        DFScalarValue::Int64(v) => Ok(ScalarValue::Int64(*v)),
+        DFScalarValue::Float32(v) => Ok(ScalarValue::Float32(*v)),
+        DFScalarValue::Float64(v) => Ok(ScalarValue::Float64(*v)),
+        DFScalarValue::Utf8(v) => Ok(ScalarValue::Utf8(v.clone())),
+        other => Err(ExecutionError::General(format!(
+            "Cannot translate DataFusion scalar value to Ballista: {:?}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::DataType;
+    use crate::logicalplan::{aggregate_expr, col_index, lit_str, LogicalPlanBuilder};
+
+    #[test]
+    fn roundtrip_datafusion_plan() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("state", DataType::Utf8, true),
+            Field::new("salary", DataType::Float64, true),
+        ]);
+        let plan = LogicalPlanBuilder::from(&LogicalPlan::TableScan {
+            table_name: "employee".to_owned(),
+            schema: schema.clone(),
+            projection: None,
+            projected_schema: schema,
+        })
+        .filter(col_index(0).eq(&lit_str("CO")))?
+        .aggregate(
+            vec![col_index(0)],
+            vec![aggregate_expr("MAX", col_index(1), DataType::Float64)],
+        )?
+        .project(vec![col_index(1).alias("max_salary")])?
+        .limit(Expr::Literal(ScalarValue::UInt64(10)))?
+        .build()?;
+
+        let mut ctx = ExecutionContext::new();
+        let df_plan = translate_plan(&mut ctx, &plan)?;
+        let plan2 = from_datafusion_plan(&df_plan)?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+        assert_eq!(plan.schema(), plan2.schema());
+        Ok(())
+    }
+
+    #[test]
+    fn union_by_name_fills_missing_columns() -> Result<()> {
+        use crate::arrow::array::{Array, ArrayRef, Int32Array, Int64Array, StringArray};
+
+        let batch = |fields: Vec<Field>, columns: Vec<ArrayRef>| {
+            LogicalPlan::MemoryScan(vec![RecordBatch::try_new(
+                Arc::new(Schema::new(fields)),
+                columns,
+            )
+            .unwrap()])
+        };
+        // the second export has its columns in a different order, a wider type for `id`
+        // and no `name`
+        let first = batch(
+            vec![
+                Field::new("id", DataType::Int32, false),
+                Field::new("name", DataType::Utf8, false),
+            ],
+            vec![
+                Arc::new(Int32Array::from(vec![1])),
+                Arc::new(StringArray::from(vec!["a"])),
+            ],
+        );
+        let second = batch(
+            vec![
+                Field::new("score", DataType::Int32, false),
+                Field::new("id", DataType::Int64, false),
+            ],
+            vec![
+                Arc::new(Int32Array::from(vec![7])),
+                Arc::new(Int64Array::from(vec![2])),
+            ],
+        );
+        assert!(LogicalPlanBuilder::from(&first)
+            .union_by_name(&second, false)
+            .is_err());
+        let plan = LogicalPlanBuilder::from(&first)
+            .union_by_name(&second, true)?
+            .build()?;
+        let names: Vec<&str> = plan
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().as_str())
+            .collect();
+        assert_eq!(names, vec!["id", "name", "score"]);
+        assert_eq!(plan.schema().field(0).data_type(), &DataType::Int64);
+
+        let mut ctx = ExecutionContext::new();
+        let object_stores = ObjectStoreRegistry::new(&HashMap::new());
+        let metrics = MetricsCollector::new();
+        let batches = collect_plan(
+            &mut ctx,
+            &plan,
+            &object_stores,
+            &metrics,
+            &QueryMemory::default(),
+        )?;
+        let rows: Vec<(i64, Option<String>, Option<i32>)> = batches
+            .iter()
+            .flat_map(|batch| {
+                let ids = batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap();
+                let names = batch
+                    .column(1)
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .unwrap();
+                let scores = batch
+                    .column(2)
+                    .as_any()
+                    .downcast_ref::<Int32Array>()
+                    .unwrap();
+                (0..batch.num_rows())
+                    .map(|i| {
+                        (
+                            ids.value(i),
+                            Some(names.value(i).to_owned()).filter(|_| !names.is_null(i)),
+                            Some(scores.value(i)).filter(|_| !scores.is_null(i)),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![(1, Some("a".to_owned()), None), (2, None, Some(7))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sum_decimal_products_exactly() -> Result<()> {
+        use crate::arrow::array::{Array, Int32Array, StringArray};
+        use crate::decimal::DECIMAL_METADATA_PREFIX;
+
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            format!("{}price", DECIMAL_METADATA_PREFIX),
+            "20,2".to_owned(),
+        );
+        let schema = Schema::new_with_metadata(
+            vec![
+                Field::new("price", DataType::Utf8, false),
+                Field::new("quantity", DataType::Int32, false),
+            ],
+            metadata,
+        );
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringArray::from(vec!["12345678901234567.89", "0.10"])),
+                Arc::new(Int32Array::from(vec![3, 2])),
+            ],
+        )?;
+        let plan = LogicalPlanBuilder::from(&LogicalPlan::MemoryScan(vec![batch]))
+            .aggregate(
+                vec![],
+                vec![aggregate_expr(
+                    "SUM",
+                    col_index(0).multiply(&col_index(1)),
+                    DataType::Float64,
+                )],
+            )?
+            .build()?;
+        // the products are DECIMAL(31, 2) and their sum a DECIMAL(38, 2)
+        let field = plan.schema().field(0);
+        assert_eq!(field.data_type(), &DataType::Utf8);
+        assert_eq!(
+            decimal_precision_and_scale(plan.schema(), field.name()),
+            Some((38, 2))
+        );
+
+        let mut ctx = ExecutionContext::new();
+        let object_stores = ObjectStoreRegistry::new(&HashMap::new());
+        let metrics = MetricsCollector::new();
+        let batches = collect_plan(
+            &mut ctx,
+            &plan,
+            &object_stores,
+            &metrics,
+            &QueryMemory::default(),
+        )?;
+        let sums = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(sums.value(0), "37037036703703703.87");
+        Ok(())
+    }
+}
@@ -0,0 +1,409 @@
+//! Rendering of logical plans as SQL.
+//!
+//! Plans are rendered as a `SELECT` query in the dialect of a database, so that the parts
+//! of a plan that scan a database table can be sent to the database, and so that plans that
+//! were built with the DataFrame methods can be read as SQL. Operators are combined into a
+//! single `SELECT` where SQL evaluates its clauses in the same order as the plan, and the
+//! query of the input is used as a subquery otherwise. Joins and database scans are always
+//! read from subqueries. Scans of files are rendered as tables named by their path, and
+//! shuffles, in-memory batches and extension nodes cannot be rendered.
+
+use crate::arrow::datatypes::{Field, Schema};
+use crate::datasource::sql::SqlDialect;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::logicalplan::{Expr, LogicalPlan, Operator, ScalarValue};
+
+/// Render a plan as a query in the dialect of a database
+pub fn plan_to_sql(plan: &LogicalPlan, dialect: SqlDialect) -> Result<String> {
+    let mut unparser = Unparser {
+        dialect,
+        aliases: 0,
+    };
+    Ok(unparser.select(plan)?.to_sql())
+}
+
+/// Render an expression over the columns of a schema in the dialect of a database
+pub fn expr_to_sql(expr: &Expr, schema: &Schema, dialect: SqlDialect) -> Result<String> {
+    let sql = |e: &Expr| expr_to_sql(e, schema, dialect);
+    match expr {
+        Expr::Column(i) => match schema.fields().get(*i) {
+            Some(field) => Ok(dialect.quote_identifier(field.name())),
+            None => Err(ballista_error(&format!(
+                "Column {} is not in the schema",
+                i
+            ))),
+        },
+        Expr::UnresolvedColumn(name) => Ok(dialect.quote_identifier(name)),
+        Expr::Literal(ScalarValue::Null) => Ok("NULL".to_owned()),
+        Expr::Literal(value) => dialect.literal(value).ok_or_else(|| {
+            BallistaError::NotImplemented(format!("Literal {:?} cannot be rendered as SQL", value))
+        }),
+        Expr::BinaryExpr { left, op, right } => {
+            let op = match op {
+                Operator::Eq => "=",
+                Operator::NotEq => "<>",
+                Operator::Lt => "<",
+                Operator::LtEq => "<=",
+                Operator::Gt => ">",
+                Operator::GtEq => ">=",
+                Operator::Plus => "+",
+                Operator::Minus => "-",
+                Operator::Multiply => "*",
+                Operator::Divide => "/",
+                Operator::Modulus => "%",
+                Operator::And => "AND",
+                Operator::Or => "OR",
+                Operator::Like => "LIKE",
+                Operator::NotLike => "NOT LIKE",
+                Operator::IsNotDistinctFrom => dialect.not_distinct_operator(),
+                other => {
+                    return Err(BallistaError::NotImplemented(format!(
+                        "Operator {:?} cannot be rendered as SQL",
+                        other
+                    )))
+                }
+            };
+            Ok(format!("({} {} {})", sql(left)?, op, sql(right)?))
+        }
+        Expr::Not(e) => Ok(format!("(NOT {})", sql(e)?)),
+        Expr::IsNull(e) => Ok(format!("({} IS NULL)", sql(e)?)),
+        Expr::IsNotNull(e) => Ok(format!("({} IS NOT NULL)", sql(e)?)),
+        Expr::Cast { expr, data_type } => match dialect.cast_type(data_type) {
+            Some(sql_type) => Ok(format!("CAST({} AS {})", sql(expr)?, sql_type)),
+            None => Err(BallistaError::NotImplemented(format!(
+                "Casts to {:?} cannot be rendered as SQL for {:?}",
+                data_type, dialect
+            ))),
+        },
+        Expr::Sort { expr, asc } => Ok(format!(
+            "{} {}",
+            sql(expr)?,
+            if *asc { "ASC" } else { "DESC" }
+        )),
+        // the names of columns are given by the schemas of the operators
+        Expr::Alias(expr, _) => sql(expr),
+        Expr::ScalarFunction { name, args, .. } | Expr::AggregateFunction { name, args, .. } => {
+            let args = args.iter().map(sql).collect::<Result<Vec<_>>>()?;
+            Ok(format!("{}({})", name.to_uppercase(), args.join(", ")))
+        }
+        Expr::Wildcard => Ok("*".to_owned()),
+        Expr::GetField { expr, name } if dialect == SqlDialect::Postgres => Ok(format!(
+            "({}).{}",
+            sql(expr)?,
+            dialect.quote_identifier(name)
+        )),
+        // arrays in PostgreSQL are indexed from one
+        Expr::GetIndex { expr, index } if dialect == SqlDialect::Postgres => {
+            Ok(format!("({})[{}]", sql(expr)?, index + 1))
+        }
+        Expr::GetField { .. } | Expr::GetIndex { .. } => Err(BallistaError::NotImplemented(
+            format!("{:?} cannot be rendered as SQL for {:?}", expr, dialect),
+        )),
+    }
+}
+
+/// The clauses of a `SELECT` query
+#[derive(Default)]
+struct Select {
+    /// The selected columns, or `None` for every column of the relations
+    projection: Option<Vec<String>>,
+    /// Whether the projection computes columns or renames them, in which case the other
+    /// clauses cannot refer to the columns of the query by name
+    computed: bool,
+    from: String,
+    selection: Vec<String>,
+    group_by: Vec<String>,
+    order_by: Vec<String>,
+    limit: Option<u64>,
+}
+
+impl Select {
+    /// Whether the columns of the query are the columns of its relations, so that clauses
+    /// can be added that refer to them
+    fn is_plain(&self) -> bool {
+        !self.computed && self.group_by.is_empty()
+    }
+
+    fn to_sql(&self) -> String {
+        let mut sql = format!(
+            "SELECT {} FROM {}",
+            self.projection
+                .as_ref()
+                .map(|columns| columns.join(", "))
+                .unwrap_or_else(|| "*".to_owned()),
+            self.from
+        );
+        if !self.selection.is_empty() {
+            sql.push_str(&format!(" WHERE {}", self.selection.join(" AND ")));
+        }
+        if !self.group_by.is_empty() {
+            sql.push_str(&format!(" GROUP BY {}", self.group_by.join(", ")));
+        }
+        if !self.order_by.is_empty() {
+            sql.push_str(&format!(" ORDER BY {}", self.order_by.join(", ")));
+        }
+        if let Some(n) = self.limit {
+            sql.push_str(&format!(" LIMIT {}", n));
+        }
+        sql
+    }
+}
+
+struct Unparser {
+    dialect: SqlDialect,
+    /// Used to give subqueries unique names
+    aliases: usize,
+}
+
+impl Unparser {
+    fn select(&mut self, plan: &LogicalPlan) -> Result<Select> {
+        match plan {
+            LogicalPlan::FileScan {
+                path,
+                files,
+                file_type,
+                schema,
+                projection,
+                ..
+            } => {
+                let from = match (file_type.as_str(), files.as_slice()) {
+                    ("sql", [query]) => self.subquery(query),
+                    ("sql", _) => {
+                        return Err(BallistaError::NotImplemented(
+                            "Database scans with more than one query cannot be rendered as SQL"
+                                .to_owned(),
+                        ))
+                    }
+                    _ => self.dialect.quote_identifier(path),
+                };
+                Ok(self.scan(from, schema, projection))
+            }
+            LogicalPlan::TableScan {
+                table_name,
+                schema,
+                projection,
+                ..
+            } => Ok(self.scan(self.dialect.quote_table(table_name), schema, projection)),
+            LogicalPlan::Selection { expr, input } => {
+                let mut select = self.select(input)?;
+                if !select.is_plain() || select.limit.is_some() {
+                    select = self.wrap(select);
+                }
+                select.selection.push(self.expr(expr, input.schema())?);
+                Ok(select)
+            }
+            LogicalPlan::Projection {
+                expr,
+                input,
+                schema,
+            } => {
+                let columns = expr
+                    .iter()
+                    .zip(schema.fields())
+                    .map(|(e, field)| self.column(e, field, input.schema()))
+                    .collect::<Result<Vec<_>>>()?;
+                let computed = columns.iter().any(|(_, plain)| !plain);
+                let mut select = self.select(input)?;
+                // the names in an `ORDER BY` refer to the columns of the projection
+                if !select.is_plain() || (computed && !select.order_by.is_empty()) {
+                    select = self.wrap(select);
+                }
+                select.computed = computed;
+                select.projection = Some(columns.into_iter().map(|(sql, _)| sql).collect());
+                Ok(select)
+            }
+            LogicalPlan::Aggregate {
+                input,
+                group_expr,
+                aggr_expr,
+                schema,
+            } => {
+                let mut select = self.select(input)?;
+                if !select.is_plain() || select.limit.is_some() || !select.order_by.is_empty() {
+                    select = self.wrap(select);
+                }
+                let columns = group_expr
+                    .iter()
+                    .chain(aggr_expr)
+                    .zip(schema.fields())
+                    .map(|(e, field)| Ok(self.column(e, field, input.schema())?.0))
+                    .collect::<Result<Vec<_>>>()?;
+                select.group_by = group_expr
+                    .iter()
+                    .map(|e| self.expr(e, input.schema()))
+                    .collect::<Result<Vec<_>>>()?;
+                select.projection = Some(columns);
+                select.computed = true;
+                Ok(select)
+            }
+            LogicalPlan::Sort { expr, input, .. } => {
+                let mut select = self.select(input)?;
+                if !select.is_plain() || select.limit.is_some() {
+                    select = self.wrap(select);
+                }
+                select.order_by = expr
+                    .iter()
+                    .map(|e| self.expr(e, input.schema()))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(select)
+            }
+            LogicalPlan::Limit { expr, input, .. } => {
+                let n = match expr {
+                    Expr::Literal(ScalarValue::UInt64(n)) => *n,
+                    other => {
+                        return Err(BallistaError::NotImplemented(format!(
+                            "Limits of {:?} cannot be rendered as SQL",
+                            other
+                        )))
+                    }
+                };
+                let mut select = self.select(input)?;
+                select.limit = Some(select.limit.map_or(n, |m| m.min(n)));
+                Ok(select)
+            }
+            LogicalPlan::Join {
+                left,
+                right,
+                on,
+                null_equals_null,
+                ..
+            } => {
+                let left_alias = self.alias();
+                let right_alias = self.alias();
+                let op = if *null_equals_null {
+                    self.dialect.not_distinct_operator()
+                } else {
+                    "="
+                };
+                let condition = on
+                    .iter()
+                    .map(|(l, r)| {
+                        format!(
+                            "{}.{} {} {}.{}",
+                            left_alias,
+                            self.dialect
+                                .quote_identifier(left.schema().field(*l).name()),
+                            op,
+                            right_alias,
+                            self.dialect
+                                .quote_identifier(right.schema().field(*r).name())
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let from = format!(
+                    "({}) AS {} JOIN ({}) AS {} ON {}",
+                    self.select(left)?.to_sql(),
+                    left_alias,
+                    self.select(right)?.to_sql(),
+                    right_alias,
+                    condition.join(" AND ")
+                );
+                Ok(Select {
+                    from,
+                    ..Select::default()
+                })
+            }
+            LogicalPlan::Broadcast { input } => self.select(input),
+            other => Err(BallistaError::NotImplemented(format!(
+                "{} operators cannot be rendered as SQL",
+                other.operator_name()
+            ))),
+        }
+    }
+
+    fn scan(&self, from: String, schema: &Schema, projection: &Option<Vec<usize>>) -> Select {
+        Select {
+            projection: projection.as_ref().map(|p| {
+                p.iter()
+                    .map(|i| self.dialect.quote_identifier(schema.field(*i).name()))
+                    .collect()
+            }),
+            from,
+            ..Select::default()
+        }
+    }
+
+    /// Select from the query of an operator, so that other clauses can be added
+    fn wrap(&mut self, select: Select) -> Select {
+        Select {
+            from: self.subquery(&select.to_sql()),
+            ..Select::default()
+        }
+    }
+
+    fn subquery(&mut self, query: &str) -> String {
+        format!("({}) AS {}", query, self.alias())
+    }
+
+    fn alias(&mut self) -> String {
+        let alias = self.dialect.quote_identifier(&format!("t{}", self.aliases));
+        self.aliases += 1;
+        alias
+    }
+
+    fn expr(&self, expr: &Expr, schema: &Schema) -> Result<String> {
+        expr_to_sql(expr, schema, self.dialect)
+    }
+
+    /// A column of a projection, and whether it is a column of the input under its own name
+    fn column(&self, expr: &Expr, field: &Field, schema: &Schema) -> Result<(String, bool)> {
+        let sql = self.expr(expr, schema)?;
+        let name = self.dialect.quote_identifier(field.name());
+        let plain = match expr {
+            Expr::Column(_) | Expr::UnresolvedColumn(_) => sql == name,
+            _ => false,
+        };
+        if plain {
+            Ok((sql, true))
+        } else {
+            Ok((format!("{} AS {}", sql, name), false))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::DataType;
+    use crate::logicalplan::{col, lit_str, LogicalPlanBuilder};
+
+    #[test]
+    fn render_plans() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("state", DataType::Utf8, false),
+            Field::new("price", DataType::Float64, true),
+        ]);
+        let plan = LogicalPlanBuilder::scan_csv("orders.csv", &schema, None)?
+            .filter(col("state").eq(&lit_str("CA")))?
+            .aggregate(
+                vec![col("state")],
+                vec![Expr::AggregateFunction {
+                    name: "SUM".to_owned(),
+                    args: vec![col("price")],
+                    return_type: DataType::Float64,
+                }],
+            )?
+            .sort(vec![Expr::Sort {
+                expr: Box::new(Expr::Column(1)),
+                asc: false,
+            }])?
+            .limit(Expr::Literal(ScalarValue::UInt64(10)))?
+            .build()?;
+        assert_eq!(
+            "SELECT * FROM (SELECT \"state\", SUM(\"price\") AS \"SUM\" FROM \"orders.csv\" \
+             WHERE (\"state\" = 'CA') GROUP BY \"state\") AS \"t0\" ORDER BY \"SUM\" DESC LIMIT 10",
+            plan_to_sql(&plan, SqlDialect::Postgres)?
+        );
+
+        let join = LogicalPlanBuilder::scan_csv("orders.csv", &schema, Some(vec![1]))?
+            .join(&plan, vec![(0, 0)])?
+            .build()?;
+        assert!(plan_to_sql(&join, SqlDialect::MySql)?
+            .starts_with("SELECT * FROM (SELECT `state` FROM `orders.csv`) AS `t0` JOIN ("));
+
+        let memory = LogicalPlan::MemoryScan(vec![]);
+        assert!(plan_to_sql(&memory, SqlDialect::Postgres).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+//! Validation of logical plans.
+//!
+//! Plans are validated as the DataFrame methods build them, and again before they are
+//! executed or sent to the scheduler or executors, so that invalid plans fail with an error
+//! that names the problem instead of failing while DataFusion executes them. Validation
+//! checks that columns refer to the schema of the input of each operator, that the operands
+//! of expressions have compatible types, that filters are boolean, and that aggregate
+//! functions are only used in the aggregate expressions of aggregates.
+
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::decimal;
+use crate::error::{ballista_error, Result};
+use crate::logicalplan::{get_supertype, Expr, LogicalPlan, Operator, ScalarValue};
+use crate::visitor::{PlanVisitor, Recursion};
+
+/// Validate every operator of a plan
+pub fn validate_plan(plan: &LogicalPlan) -> Result<()> {
+    plan.accept(&mut Validator)?;
+    Ok(())
+}
+
+/// Validate an operator against the schemas of its inputs, without validating the inputs
+pub fn validate_operator(plan: &LogicalPlan) -> Result<()> {
+    match plan {
+        LogicalPlan::Projection { expr, input, .. } => validate_projection(expr, input.schema()),
+        LogicalPlan::Selection { expr, input } => validate_filter(expr, input.schema()),
+        LogicalPlan::Aggregate {
+            group_expr,
+            aggr_expr,
+            input,
+            ..
+        } => validate_aggregate(group_expr, aggr_expr, input.schema()),
+        LogicalPlan::Sort { expr, input, .. } => {
+            for expr in expr {
+                expr_type(expr, input.schema(), Some("a sort"))?;
+            }
+            Ok(())
+        }
+        LogicalPlan::Limit { expr, .. } => match expr {
+            Expr::Literal(ScalarValue::UInt8(_))
+            | Expr::Literal(ScalarValue::UInt16(_))
+            | Expr::Literal(ScalarValue::UInt32(_))
+            | Expr::Literal(ScalarValue::UInt64(_)) => Ok(()),
+            Expr::Literal(ScalarValue::Int8(n)) if *n >= 0 => Ok(()),
+            Expr::Literal(ScalarValue::Int16(n)) if *n >= 0 => Ok(()),
+            Expr::Literal(ScalarValue::Int32(n)) if *n >= 0 => Ok(()),
+            Expr::Literal(ScalarValue::Int64(n)) if *n >= 0 => Ok(()),
+            other => Err(ballista_error(&format!(
+                "A limit must be a non-negative integer literal, but it is {:?}",
+                other
+            ))),
+        },
+        LogicalPlan::FileScan {
+            path,
+            schema,
+            projection,
+            filters,
+            ..
+        } => {
+            if let Some(projection) = projection {
+                for i in projection {
+                    if *i >= schema.fields().len() {
+                        return Err(ballista_error(&format!(
+                            "Projected column {} is out of bounds for {}, which has the columns {}",
+                            i,
+                            path,
+                            column_names(schema)
+                        )));
+                    }
+                }
+            }
+            for filter in filters {
+                validate_filter(filter, schema)?;
+            }
+            Ok(())
+        }
+        LogicalPlan::Join {
+            left, right, on, ..
+        } => {
+            for (l, r) in on {
+                if *l >= left.schema().fields().len() || *r >= right.schema().fields().len() {
+                    return Err(ballista_error(&format!(
+                        "Invalid join columns ({}, {})",
+                        l, r
+                    )));
+                }
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Validate the expressions of a projection against the schema of its input
+pub fn validate_projection(expr: &[Expr], schema: &Schema) -> Result<()> {
+    for expr in expr {
+        // wildcards are expanded when the projection is built
+        if *expr != Expr::Wildcard {
+            expr_type(expr, schema, Some("a projection"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Validate the predicate of a filter against the schema of its input
+pub fn validate_filter(expr: &Expr, schema: &Schema) -> Result<()> {
+    match expr_type(expr, schema, Some("a filter"))? {
+        DataType::Boolean | DataType::Null => Ok(()),
+        other => Err(ballista_error(&format!(
+            "Filter predicate {:?} must be boolean, but it is {:?}",
+            expr, other
+        ))),
+    }
+}
+
+/// Validate the grouping and aggregate expressions of an aggregate against the schema of
+/// its input
+pub fn validate_aggregate(group_expr: &[Expr], aggr_expr: &[Expr], schema: &Schema) -> Result<()> {
+    for expr in group_expr {
+        expr_type(expr, schema, Some("a grouping expression"))?;
+    }
+    for expr in aggr_expr {
+        expr_type(expr, schema, None)?;
+        if !contains_aggregate(expr) {
+            return Err(ballista_error(&format!(
+                "Aggregate expression {:?} does not use an aggregate function",
+                expr
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Validates each operator of a plan
+struct Validator;
+
+impl PlanVisitor for Validator {
+    fn post_visit(&mut self, plan: &LogicalPlan) -> Result<Recursion> {
+        validate_operator(plan)?;
+        Ok(Recursion::Continue)
+    }
+}
+
+/// The type of an expression, checking the columns and the types of the operands of the
+/// expression. Aggregate functions are rejected unless `clause` is `None`, and the clause
+/// names where the expression is used otherwise.
+fn expr_type(expr: &Expr, schema: &Schema, clause: Option<&str>) -> Result<DataType> {
+    match expr {
+        Expr::Alias(expr, _) | Expr::Sort { expr, .. } => expr_type(expr, schema, clause),
+        Expr::Column(i) => match schema.fields().get(*i) {
+            Some(field) => Ok(field.data_type().clone()),
+            None => Err(ballista_error(&format!(
+                "Column index {} is out of bounds for the columns {}",
+                i,
+                column_names(schema)
+            ))),
+        },
+        Expr::UnresolvedColumn(name) => match schema.index_of(name) {
+            Ok(i) => Ok(schema.field(i).data_type().clone()),
+            Err(_) => Err(ballista_error(&format!(
+                "No column named {}, the columns are {}",
+                name,
+                column_names(schema)
+            ))),
+        },
+        // the types of nulls and structs are not known until they are cast
+        Expr::Literal(ScalarValue::Null) | Expr::Literal(ScalarValue::Struct(_)) => {
+            Ok(DataType::Null)
+        }
+        Expr::Literal(value) => Ok(value.get_datatype()),
+        // arithmetic on decimals produces decimal text
+        Expr::BinaryExpr { left, right, .. } if decimal::is_decimal_arithmetic(expr, schema) => {
+            expr_type(left, schema, clause)?;
+            expr_type(right, schema, clause)?;
+            Ok(DataType::Utf8)
+        }
+        Expr::BinaryExpr { left, op, right } => {
+            let left_type = expr_type(left, schema, clause)?;
+            let right_type = expr_type(right, schema, clause)?;
+            binary_type(expr, op, &left_type, &right_type)
+        }
+        Expr::Not(inner) => match expr_type(inner, schema, clause)? {
+            DataType::Boolean | DataType::Null => Ok(DataType::Boolean),
+            other => Err(ballista_error(&format!(
+                "The operand of NOT must be boolean, but {:?} is {:?}",
+                inner, other
+            ))),
+        },
+        Expr::IsNull(inner) | Expr::IsNotNull(inner) => {
+            expr_type(inner, schema, clause)?;
+            Ok(DataType::Boolean)
+        }
+        Expr::Cast { expr, data_type } => {
+            expr_type(expr, schema, clause)?;
+            Ok(data_type.clone())
+        }
+        Expr::ScalarFunction {
+            args, return_type, ..
+        } => {
+            for arg in args {
+                expr_type(arg, schema, clause)?;
+            }
+            Ok(return_type.clone())
+        }
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => match clause {
+            Some(clause) => Err(ballista_error(&format!(
+                "Aggregate function {} cannot be used in {}, only in the aggregate \
+                 expressions of an aggregate",
+                name, clause
+            ))),
+            None => {
+                for arg in args {
+                    expr_type(arg, schema, Some("the arguments of another aggregate"))?;
+                }
+                match decimal::aggregate_type(expr, schema) {
+                    Some(_) => Ok(DataType::Utf8),
+                    None => Ok(return_type.clone()),
+                }
+            }
+        },
+        Expr::Wildcard => Err(ballista_error(
+            "Wildcard expressions can only be used in projections",
+        )),
+        Expr::GetField { expr: inner, name } => match expr_type(inner, schema, clause)? {
+            DataType::Struct(fields) => match fields.iter().find(|f| f.name() == name) {
+                Some(field) => Ok(field.data_type().clone()),
+                None => Err(ballista_error(&format!(
+                    "{:?} has no field named {}, the fields are {}",
+                    inner,
+                    name,
+                    column_names(&Schema::new(fields))
+                ))),
+            },
+            other => Err(ballista_error(&format!(
+                "Fields can only be accessed on structs, but {:?} is {:?}",
+                inner, other
+            ))),
+        },
+        Expr::GetIndex { expr: inner, .. } => match expr_type(inner, schema, clause)? {
+            DataType::List(item_type) => Ok(*item_type),
+            other => Err(ballista_error(&format!(
+                "Only lists can be indexed, but {:?} is {:?}",
+                inner, other
+            ))),
+        },
+    }
+}
+
+/// The type of a binary expression whose operands have the given types
+fn binary_type(expr: &Expr, op: &Operator, left: &DataType, right: &DataType) -> Result<DataType> {
+    let incompatible = |what: &str| {
+        Err(ballista_error(&format!(
+            "Cannot {} {:?} and {:?} in {:?}",
+            what, left, right, expr
+        )))
+    };
+    let nulls = *left == DataType::Null || *right == DataType::Null;
+    match op {
+        Operator::Eq
+        | Operator::NotEq
+        | Operator::IsNotDistinctFrom
+        | Operator::Lt
+        | Operator::LtEq
+        | Operator::Gt
+        | Operator::GtEq => {
+            if nulls || left == right || get_supertype(left, right).is_ok() {
+                Ok(DataType::Boolean)
+            } else {
+                incompatible("compare")
+            }
+        }
+        Operator::And | Operator::Or | Operator::Not => {
+            let boolean = |t: &DataType| *t == DataType::Boolean || *t == DataType::Null;
+            if boolean(left) && boolean(right) {
+                Ok(DataType::Boolean)
+            } else {
+                incompatible("apply a boolean operator to")
+            }
+        }
+        Operator::Like | Operator::NotLike => {
+            let string = |t: &DataType| *t == DataType::Utf8 || *t == DataType::Null;
+            if string(left) && string(right) {
+                Ok(DataType::Boolean)
+            } else {
+                incompatible("match the patterns of")
+            }
+        }
+        Operator::Plus
+        | Operator::Minus
+        | Operator::Multiply
+        | Operator::Divide
+        | Operator::Modulus => {
+            if *left == DataType::Null {
+                Ok(right.clone())
+            } else if *right == DataType::Null {
+                Ok(left.clone())
+            } else if is_numeric(left) && is_numeric(right) {
+                Ok(get_supertype(left, right).unwrap_or_else(|_| left.clone()))
+            } else {
+                incompatible("apply an arithmetic operator to")
+            }
+        }
+    }
+}
+
+fn is_numeric(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Float32
+        | DataType::Float64 => true,
+        _ => false,
+    }
+}
+
+/// Whether an expression uses an aggregate function
+fn contains_aggregate(expr: &Expr) -> bool {
+    match expr {
+        Expr::AggregateFunction { .. } => true,
+        Expr::Alias(expr, _)
+        | Expr::Not(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::Sort { expr, .. }
+        | Expr::GetField { expr, .. }
+        | Expr::GetIndex { expr, .. } => contains_aggregate(expr),
+        Expr::BinaryExpr { left, right, .. } => {
+            contains_aggregate(left) || contains_aggregate(right)
+        }
+        Expr::ScalarFunction { args, .. } => args.iter().any(contains_aggregate),
+        _ => false,
+    }
+}
+
+/// The names of the columns of a schema, for error messages
+fn column_names(schema: &Schema) -> String {
+    let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    format!("[{}]", names.join(", "))
+}
+
+#[cfg(all(test, feature = "local-execution"))]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::Field;
+    use crate::dataframe::{max, Context};
+    use crate::logicalplan::{col, lit_str};
+    use std::collections::HashMap;
+
+    #[test]
+    fn validate_dataframes() -> Result<()> {
+        let ctx = Context::local(HashMap::new());
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("state", DataType::Utf8, true),
+            Field::new("salary", DataType::Float64, true),
+        ]);
+        let df = ctx.read_csv("employee.csv", Some(schema), None, true)?;
+
+        let error = |result: Result<_>| match result {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("the plan is valid"),
+        };
+        assert_eq!(
+            "General error: No column named name, the columns are [id, state, salary]",
+            error(df.filter(col("name").eq(&lit_str("x"))))
+        );
+        assert_eq!(
+            "General error: Filter predicate #salary must be boolean, but it is Float64",
+            error(df.filter(col("salary")))
+        );
+        assert!(error(df.filter(Expr::Column(1).and(&Expr::Column(0)))).contains("boolean"));
+        assert!(error(df.filter(max(col("salary")).gt(&col("salary"))))
+            .contains("Aggregate function MAX cannot be used in a filter"));
+        assert!(error(df.aggregate(vec![col("state")], vec![col("salary")]))
+            .contains("does not use an aggregate function"));
+        assert!(error(df.aggregate(vec![], vec![max(max(col("salary")))]))
+            .contains("the arguments of another aggregate"));
+
+        df.filter(col("state").eq(&lit_str("CO")))?
+            .aggregate(vec![col("state")], vec![max(col("salary"))])?;
+        Ok(())
+    }
+}
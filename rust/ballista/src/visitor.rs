@@ -0,0 +1,190 @@
+//! Traversal and rewriting of logical plans.
+//!
+//! A `PlanVisitor` is called for each operator of a plan before and after the inputs of
+//! the operator are visited, and can skip the inputs of an operator or stop the traversal.
+//! A `PlanRewriter` replaces the operators of a plan from the bottom up, so that each
+//! operator is rewritten after its inputs have been. Both rely on `LogicalPlan::inputs()`
+//! and `LogicalPlan::with_new_inputs()`, so implementations only need to match the
+//! operators that they are interested in.
+
+use crate::error::Result;
+use crate::logicalplan::LogicalPlan;
+
+/// How a traversal continues after an operator is visited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recursion {
+    /// Continue with the inputs of the operator, or with the next operator after it
+    Continue,
+    /// Skip the inputs of the operator. This is the same as `Continue` after the inputs
+    /// have been visited.
+    Skip,
+    /// Stop the traversal
+    Stop,
+}
+
+/// Visits the operators of a plan, in pre-order and post-order
+pub trait PlanVisitor {
+    /// Called before the inputs of the operator are visited
+    fn pre_visit(&mut self, _plan: &LogicalPlan) -> Result<Recursion> {
+        Ok(Recursion::Continue)
+    }
+
+    /// Called after the inputs of the operator are visited
+    fn post_visit(&mut self, _plan: &LogicalPlan) -> Result<Recursion> {
+        Ok(Recursion::Continue)
+    }
+}
+
+/// Rewrites the operators of a plan from the bottom up
+pub trait PlanRewriter {
+    /// Called before the inputs of the operator are rewritten, returning false if they
+    /// should be left as they are
+    fn pre_visit(&mut self, _plan: &LogicalPlan) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// Rewrite an operator whose inputs have been rewritten
+    fn mutate(&mut self, plan: LogicalPlan) -> Result<LogicalPlan>;
+}
+
+impl LogicalPlan {
+    /// Visit the operators of the plan, returning false if the visitor stopped the
+    /// traversal
+    pub fn accept<V: PlanVisitor>(&self, visitor: &mut V) -> Result<bool> {
+        match visitor.pre_visit(self)? {
+            Recursion::Continue => {
+                for input in self.inputs() {
+                    if !input.accept(visitor)? {
+                        return Ok(false);
+                    }
+                }
+            }
+            Recursion::Skip => {}
+            Recursion::Stop => return Ok(false),
+        }
+        Ok(visitor.post_visit(self)? != Recursion::Stop)
+    }
+
+    /// Rewrite the operators of the plan from the bottom up
+    pub fn rewrite<R: PlanRewriter>(&self, rewriter: &mut R) -> Result<LogicalPlan> {
+        let plan = if rewriter.pre_visit(self)? {
+            let inputs = self
+                .inputs()
+                .into_iter()
+                .map(|input| input.rewrite(rewriter))
+                .collect::<Result<Vec<_>>>()?;
+            self.with_new_inputs(inputs)
+        } else {
+            self.clone()
+        };
+        rewriter.mutate(plan)
+    }
+
+    /// Rewrite each operator of the plan with a function, from the bottom up
+    pub fn transform_up<F>(&self, f: F) -> Result<LogicalPlan>
+    where
+        F: FnMut(LogicalPlan) -> Result<LogicalPlan>,
+    {
+        self.rewrite(&mut FnRewriter(f))
+    }
+}
+
+/// Rewrites each operator with a function
+struct FnRewriter<F>(F);
+
+impl<F> PlanRewriter for FnRewriter<F>
+where
+    F: FnMut(LogicalPlan) -> Result<LogicalPlan>,
+{
+    fn mutate(&mut self, plan: LogicalPlan) -> Result<LogicalPlan> {
+        (self.0)(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::logicalplan::{col, lit_str, Expr, LogicalPlanBuilder, ScalarValue};
+
+    /// Records the operators that it visits, stopping at the first scan if asked to
+    struct Recorder {
+        visited: Vec<String>,
+        skip_selections: bool,
+        stop_at_scan: bool,
+    }
+
+    impl PlanVisitor for Recorder {
+        fn pre_visit(&mut self, plan: &LogicalPlan) -> Result<Recursion> {
+            self.visited.push(format!("pre {}", plan.operator_name()));
+            match plan {
+                LogicalPlan::Selection { .. } if self.skip_selections => Ok(Recursion::Skip),
+                LogicalPlan::FileScan { .. } if self.stop_at_scan => Ok(Recursion::Stop),
+                _ => Ok(Recursion::Continue),
+            }
+        }
+
+        fn post_visit(&mut self, plan: &LogicalPlan) -> Result<Recursion> {
+            self.visited.push(format!("post {}", plan.operator_name()));
+            Ok(Recursion::Continue)
+        }
+    }
+
+    #[test]
+    fn visit_and_rewrite_plans() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("a", DataType::Utf8, false)]);
+        let plan = LogicalPlanBuilder::scan_csv("a.csv", &schema, None)?
+            .filter(col("a").eq(&lit_str("x")))?
+            .limit(Expr::Literal(ScalarValue::UInt64(1)))?
+            .build()?;
+
+        let mut recorder = Recorder {
+            visited: vec![],
+            skip_selections: false,
+            stop_at_scan: false,
+        };
+        assert!(plan.accept(&mut recorder)?);
+        assert_eq!(
+            vec![
+                "pre Limit",
+                "pre Selection",
+                "pre FileScan",
+                "post FileScan",
+                "post Selection",
+                "post Limit"
+            ],
+            recorder.visited
+        );
+
+        recorder.visited.clear();
+        recorder.skip_selections = true;
+        assert!(plan.accept(&mut recorder)?);
+        assert_eq!(
+            vec!["pre Limit", "pre Selection", "post Selection", "post Limit"],
+            recorder.visited
+        );
+
+        recorder.visited.clear();
+        recorder.skip_selections = false;
+        recorder.stop_at_scan = true;
+        assert!(!plan.accept(&mut recorder)?);
+        assert_eq!(
+            vec!["pre Limit", "pre Selection", "pre FileScan"],
+            recorder.visited
+        );
+
+        // filters are replaced by their inputs, leaving the limit above the scan
+        let rewritten = plan.transform_up(|plan| match plan {
+            LogicalPlan::Selection { input, .. } => Ok(*input),
+            other => Ok(other),
+        })?;
+        match rewritten {
+            LogicalPlan::Limit { input, .. } => match *input {
+                LogicalPlan::FileScan { .. } => {}
+                other => panic!("unexpected plan {:?}", other),
+            },
+            other => panic!("unexpected plan {:?}", other),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,879 @@
+//! Window aggregations over an event-time column.
+//!
+//! `DataFrame::window` assigns each row to the windows of its event time, adding the
+//! `window_start` and `window_end` columns with the bounds of each window in milliseconds
+//! since the epoch, so that an aggregate that groups by them computes a result for each
+//! window. Tumbling windows have a fixed size and do not overlap, sliding windows have a
+//! fixed size and start every `slide`, so that a row belongs to several of them, and session
+//! windows group the rows of each key that are within `gap` of each other.
+//!
+//! In a streaming query, the windows are held open until the watermark passes their end.
+//! The watermark is declared on the source with `DataFrame::with_watermark()`, as the latest
+//! time seen in a column minus a delay, and is otherwise the latest event time of the window
+//! minus its allowed lateness. The rows of the windows that close are then passed to the
+//! operators above the window, so the results of each window are produced once. Rows that
+//! arrive after their windows have closed are late, and are dropped unless the window routes
+//! them to `StreamingQuery::late_records()`. The watermark only advances after each
+//! micro-batch of the trigger of the query, so the rows of a micro-batch are all judged by
+//! the watermark of the micro-batches before it. In other queries every window closes once
+//! the input has been read.
+//!
+//! Window and watermark nodes are extension nodes, which are decoded by the codecs that are
+//! registered for them in every process.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::arrow::array::*;
+use crate::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::dictionary;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::extension::{ExtensionCodec, UserDefinedLogicalNode};
+use crate::join::{column_values, KeyValue};
+use crate::logicalplan::LogicalPlan;
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the extension nodes that assign windows
+pub const WINDOW_NODE: &str = "window";
+
+/// Column with the start of the window of each row
+pub const WINDOW_START: &str = "window_start";
+
+/// Column with the end of the window of each row
+pub const WINDOW_END: &str = "window_end";
+
+/// Name of the extension nodes that declare watermarks
+pub const WATERMARK_NODE: &str = "watermark";
+
+/// How rows are grouped into windows
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WindowKind {
+    /// Windows of a fixed size that do not overlap
+    Tumbling { size: Duration },
+    /// Windows of a fixed size that start every `slide`
+    Sliding { size: Duration, slide: Duration },
+    /// Windows of the rows of a key that are within `gap` of each other, which end `gap`
+    /// after their last row
+    Session { gap: Duration },
+}
+
+/// The windows of an event-time column
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowSpec {
+    /// Column with the event time of each row, of a timestamp type, or an `Int64` of
+    /// milliseconds since the epoch
+    pub time_column: String,
+    pub kind: WindowKind,
+    /// Columns whose values identify the sessions of session windows
+    pub keys: Vec<String>,
+    /// How long a streaming query waits for rows that arrive out of order before the
+    /// windows that they belong to are closed, unless the input declares a watermark
+    pub allowed_lateness: Duration,
+    /// What happens to the rows that arrive after their windows have closed
+    #[serde(default)]
+    pub late_records: LateRecords,
+}
+
+/// What a streaming query does with the rows that arrive after their windows have closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LateRecords {
+    Drop,
+    /// Send the rows to the query returned by `StreamingQuery::late_records()`, with the
+    /// schema of the input of the window
+    Route,
+}
+
+impl Default for LateRecords {
+    fn default() -> Self {
+        LateRecords::Drop
+    }
+}
+
+impl WindowSpec {
+    /// Tumbling windows of the given size
+    pub fn tumbling(time_column: &str, size: Duration) -> Self {
+        Self::new(time_column, WindowKind::Tumbling { size }, vec![])
+    }
+
+    /// Sliding windows of the given size that start every `slide`
+    pub fn sliding(time_column: &str, size: Duration, slide: Duration) -> Self {
+        Self::new(time_column, WindowKind::Sliding { size, slide }, vec![])
+    }
+
+    /// Session windows of each value of the key columns
+    pub fn session(time_column: &str, gap: Duration, keys: &[&str]) -> Self {
+        let keys = keys.iter().map(|key| (*key).to_owned()).collect();
+        Self::new(time_column, WindowKind::Session { gap }, keys)
+    }
+
+    /// Wait for rows that arrive out of order for the given time
+    pub fn with_allowed_lateness(mut self, allowed_lateness: Duration) -> Self {
+        self.allowed_lateness = allowed_lateness;
+        self
+    }
+
+    /// Set what happens to rows that arrive after their windows have closed
+    pub fn with_late_records(mut self, late_records: LateRecords) -> Self {
+        self.late_records = late_records;
+        self
+    }
+
+    fn new(time_column: &str, kind: WindowKind, keys: Vec<String>) -> Self {
+        Self {
+            time_column: time_column.to_owned(),
+            kind,
+            keys,
+            allowed_lateness: Duration::from_secs(0),
+            late_records: LateRecords::Drop,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let positive = |d: &Duration| d.as_millis() > 0;
+        let valid = match &self.kind {
+            WindowKind::Tumbling { size } => positive(size),
+            WindowKind::Sliding { size, slide } => positive(size) && positive(slide),
+            WindowKind::Session { gap } => positive(gap),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(ballista_error("Windows must last at least a millisecond"))
+        }
+    }
+}
+
+/// Extension node that assigns the rows of its input to windows
+#[derive(Debug)]
+pub struct WindowNode {
+    pub spec: WindowSpec,
+    pub input: LogicalPlan,
+    schema: Schema,
+}
+
+impl WindowNode {
+    pub fn try_new(input: LogicalPlan, spec: WindowSpec) -> Result<Self> {
+        spec.validate()?;
+        let input_schema = input.schema();
+        validate_time_column(input_schema, &spec.time_column)?;
+        for key in &spec.keys {
+            input_schema.index_of(key)?;
+        }
+        if input_schema.index_of(WINDOW_START).is_ok() || input_schema.index_of(WINDOW_END).is_ok()
+        {
+            return Err(BallistaError::General(format!(
+                "The input of a window cannot have {} or {} columns",
+                WINDOW_START, WINDOW_END
+            )));
+        }
+        let schema = window_schema(input_schema);
+        Ok(Self {
+            spec,
+            input,
+            schema,
+        })
+    }
+}
+
+impl UserDefinedLogicalNode for WindowNode {
+    fn name(&self) -> &str {
+        WINDOW_NODE
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn inputs(&self) -> Vec<&LogicalPlan> {
+        vec![&self.input]
+    }
+
+    fn with_new_inputs(&self, mut inputs: Vec<LogicalPlan>) -> Arc<dyn UserDefinedLogicalNode> {
+        Arc::new(Self {
+            spec: self.spec.clone(),
+            input: inputs.remove(0),
+            schema: self.schema.clone(),
+        })
+    }
+
+    fn execute(&self, inputs: &[Vec<RecordBatch>]) -> Result<Vec<RecordBatch>> {
+        let mut assigner = WindowAssigner::try_new(&self.spec, self.input.schema())?;
+        for batch in &inputs[0] {
+            assigner.push(batch)?;
+        }
+        assigner.finish()
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(&self.spec).map_err(BallistaError::from)
+    }
+}
+
+/// Decodes window nodes
+pub struct WindowCodec;
+
+impl ExtensionCodec for WindowCodec {
+    fn decode(
+        &self,
+        node: &[u8],
+        mut inputs: Vec<LogicalPlan>,
+    ) -> Result<Arc<dyn UserDefinedLogicalNode>> {
+        let spec: WindowSpec = serde_json::from_slice(node)?;
+        if inputs.len() != 1 {
+            return Err(ballista_error("Window nodes have one input"));
+        }
+        Ok(Arc::new(WindowNode::try_new(inputs.remove(0), spec)?))
+    }
+}
+
+/// The latest time seen in a column of a stream, minus the delay that its rows may arrive
+/// out of order by
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Watermark {
+    pub column: String,
+    pub delay: Duration,
+}
+
+/// Extension node that declares the watermark of its input, whose rows it passes through
+#[derive(Debug)]
+pub struct WatermarkNode {
+    pub watermark: Watermark,
+    pub input: LogicalPlan,
+}
+
+impl WatermarkNode {
+    pub fn try_new(input: LogicalPlan, watermark: Watermark) -> Result<Self> {
+        validate_time_column(input.schema(), &watermark.column)?;
+        Ok(Self { watermark, input })
+    }
+}
+
+impl UserDefinedLogicalNode for WatermarkNode {
+    fn name(&self) -> &str {
+        WATERMARK_NODE
+    }
+
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn inputs(&self) -> Vec<&LogicalPlan> {
+        vec![&self.input]
+    }
+
+    fn with_new_inputs(&self, mut inputs: Vec<LogicalPlan>) -> Arc<dyn UserDefinedLogicalNode> {
+        Arc::new(Self {
+            watermark: self.watermark.clone(),
+            input: inputs.remove(0),
+        })
+    }
+
+    fn execute(&self, inputs: &[Vec<RecordBatch>]) -> Result<Vec<RecordBatch>> {
+        Ok(inputs[0].clone())
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(&self.watermark).map_err(BallistaError::from)
+    }
+}
+
+/// Decodes watermark nodes
+pub struct WatermarkCodec;
+
+impl ExtensionCodec for WatermarkCodec {
+    fn decode(
+        &self,
+        node: &[u8],
+        mut inputs: Vec<LogicalPlan>,
+    ) -> Result<Arc<dyn UserDefinedLogicalNode>> {
+        let watermark: Watermark = serde_json::from_slice(node)?;
+        if inputs.len() != 1 {
+            return Err(ballista_error("Watermark nodes have one input"));
+        }
+        Ok(Arc::new(WatermarkNode::try_new(
+            inputs.remove(0),
+            watermark,
+        )?))
+    }
+}
+
+/// The watermark of a plan that is a watermark node
+pub fn watermark_of(plan: &LogicalPlan) -> Result<Option<Watermark>> {
+    match plan {
+        LogicalPlan::Extension { node } if node.name() == WATERMARK_NODE => {
+            let watermark = serde_json::from_slice(&node.encode()?)?;
+            Ok(Some(watermark))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The first watermark that is declared in a plan
+pub fn find_watermark(plan: &LogicalPlan) -> Result<Option<Watermark>> {
+    if let Some(watermark) = watermark_of(plan)? {
+        return Ok(Some(watermark));
+    }
+    for input in plan.inputs() {
+        if let Some(watermark) = find_watermark(input)? {
+            return Ok(Some(watermark));
+        }
+    }
+    Ok(None)
+}
+
+/// Remove the watermark nodes of a plan, which only affect the windows above them and would
+/// otherwise collect their unbounded input when the plan is translated
+#[cfg(feature = "local-execution")]
+pub(crate) fn remove_watermarks(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    plan.transform_up(|plan| match watermark_of(&plan)? {
+        Some(_) => Ok(plan.inputs()[0].clone()),
+        None => Ok(plan),
+    })
+}
+
+/// The spec and input of a plan that is a window node
+pub fn window_of(plan: &LogicalPlan) -> Result<Option<(WindowSpec, &LogicalPlan)>> {
+    match plan {
+        LogicalPlan::Extension { node } if node.name() == WINDOW_NODE => {
+            let spec = serde_json::from_slice(&node.encode()?)?;
+            Ok(Some((spec, node.inputs()[0])))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The spec and input of the first window node of a plan
+pub fn find_window(plan: &LogicalPlan) -> Result<Option<(WindowSpec, &LogicalPlan)>> {
+    if let Some(window) = window_of(plan)? {
+        return Ok(Some(window));
+    }
+    for input in plan.inputs() {
+        if let Some(window) = find_window(input)? {
+            return Ok(Some(window));
+        }
+    }
+    Ok(None)
+}
+
+/// Replace the window node of a plan with a scan of the rows of the windows that closed
+#[cfg(feature = "local-execution")]
+pub(crate) fn scan_closed_windows(
+    plan: &LogicalPlan,
+    batches: Vec<RecordBatch>,
+) -> Result<LogicalPlan> {
+    let mut batches = Some(batches);
+    plan.transform_up(|plan| {
+        if window_of(&plan)?.is_none() {
+            return Ok(plan);
+        }
+        Ok(match batches.take() {
+            Some(batches) if !batches.is_empty() => LogicalPlan::MemoryScan(batches),
+            _ => LogicalPlan::EmptyRelation {
+                schema: plan.schema().clone(),
+            },
+        })
+    })
+}
+
+/// The schema of the input with the window columns
+fn window_schema(input: &Schema) -> Schema {
+    let mut fields = input.fields().clone();
+    fields.push(Field::new(WINDOW_START, DataType::Int64, false));
+    fields.push(Field::new(WINDOW_END, DataType::Int64, false));
+    Schema::new(fields)
+}
+
+/// The open windows of a `WindowAssigner` and the progress of its watermark
+#[derive(Debug, Clone)]
+pub struct WindowState {
+    /// The latest time that has been seen in the watermark column
+    pub max_time: Option<i64>,
+    pub watermark: Option<i64>,
+    pub windows: Vec<SavedWindow>,
+}
+
+/// A window that has not closed yet, with the rows that belong to it, which have the schema
+/// of the input of the window
+#[derive(Debug, Clone)]
+pub struct SavedWindow {
+    pub start: i64,
+    pub end: i64,
+    pub batches: Vec<RecordBatch>,
+}
+
+/// A window that has not closed yet, with the rows that belong to it
+struct OpenWindow {
+    id: usize,
+    start: i64,
+    end: i64,
+    batches: Vec<RecordBatch>,
+}
+
+/// Assigns the rows of batches to windows as they are received, holding each window open
+/// until the watermark passes its end
+pub struct WindowAssigner {
+    kind: WindowKind,
+    schema: SchemaRef,
+    time_index: usize,
+    key_indices: Vec<usize>,
+    /// The column that the watermark follows, and its delay
+    watermark_index: usize,
+    delay: i64,
+    late_records: LateRecords,
+    /// Late rows that have not been taken yet
+    late: Vec<RecordBatch>,
+    /// The open windows of each key, by key. Tumbling and sliding windows have no keys.
+    windows: HashMap<Vec<Option<KeyValue>>, Vec<OpenWindow>>,
+    next_id: usize,
+    /// The latest time that has been seen in the watermark column
+    max_time: Option<i64>,
+    /// Windows that end at or before the watermark have been closed
+    watermark: Option<i64>,
+}
+
+impl WindowAssigner {
+    pub fn try_new(spec: &WindowSpec, input: &Schema) -> Result<Self> {
+        spec.validate()?;
+        let time_index = input.index_of(&spec.time_column)?;
+        Ok(Self {
+            kind: spec.kind.clone(),
+            schema: Arc::new(window_schema(input)),
+            time_index,
+            watermark_index: time_index,
+            delay: spec.allowed_lateness.as_millis() as i64,
+            late_records: spec.late_records,
+            late: vec![],
+            key_indices: spec
+                .keys
+                .iter()
+                .map(|key| input.index_of(key))
+                .collect::<std::result::Result<_, _>>()?,
+            windows: HashMap::new(),
+            next_id: 0,
+            max_time: None,
+            watermark: None,
+        })
+    }
+
+    /// Follow a watermark that is declared on the input instead of the event times of the
+    /// window and its allowed lateness
+    pub fn with_watermark(mut self, input: &Schema, watermark: &Watermark) -> Result<Self> {
+        self.watermark_index = input.index_of(&watermark.column)?;
+        self.delay = watermark.delay.as_millis() as i64;
+        Ok(self)
+    }
+
+    /// The schema of the rows of the windows
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Whether late rows are kept to be taken with `take_late()`
+    pub fn routes_late_records(&self) -> bool {
+        self.late_records == LateRecords::Route
+    }
+
+    /// Assign the rows of a batch to their windows, dropping rows without an event time and
+    /// keeping rows whose windows have closed if they are routed
+    pub fn push(&mut self, batch: &RecordBatch) -> Result<()> {
+        let times = event_times(batch.column(self.time_index))?;
+        let keys = self.row_keys(batch)?;
+        // the rows of each window, by the id of the window
+        let mut rows: HashMap<usize, Vec<u32>> = HashMap::new();
+        let mut late = vec![];
+        for (row, (time, key)) in times.into_iter().zip(keys).enumerate() {
+            let time = match time {
+                Some(time) => time,
+                None => continue,
+            };
+            let ids = self.assign(time, key);
+            if ids.is_empty() {
+                late.push(row as u32);
+            }
+            for id in ids {
+                rows.entry(id).or_default().push(row as u32);
+            }
+        }
+        let watermark_times = event_times(batch.column(self.watermark_index))?;
+        if let Some(max) = watermark_times.into_iter().flatten().max() {
+            self.max_time = Some(self.max_time.map_or(max, |time| time.max(max)));
+        }
+        for window in self.windows.values_mut().flatten() {
+            if let Some(rows) = rows.remove(&window.id) {
+                window.batches.push(take_rows(batch, rows)?);
+            }
+        }
+        if self.routes_late_records() && !late.is_empty() {
+            self.late.push(take_rows(batch, late)?);
+        }
+        Ok(())
+    }
+
+    /// Take the late rows that have been received since the last call, if they are routed
+    pub fn take_late(&mut self) -> Vec<RecordBatch> {
+        std::mem::take(&mut self.late)
+    }
+
+    /// Close the windows that end at or before the watermark of the rows received so far,
+    /// returning their rows
+    pub fn close_ready(&mut self) -> Result<Vec<RecordBatch>> {
+        match self.max_time {
+            Some(max_time) => {
+                let watermark = max_time - self.delay;
+                let watermark = self.watermark.map_or(watermark, |w| w.max(watermark));
+                self.watermark = Some(watermark);
+                self.close(|window| window.end <= watermark)
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Close every window, returning their rows
+    pub fn finish(&mut self) -> Result<Vec<RecordBatch>> {
+        self.close(|_| true)
+    }
+
+    /// The open windows and the progress of the watermark, to be saved in a checkpoint
+    pub fn state(&self) -> WindowState {
+        let mut windows: Vec<SavedWindow> = self
+            .windows
+            .values()
+            .flatten()
+            .map(|window| SavedWindow {
+                start: window.start,
+                end: window.end,
+                batches: window.batches.clone(),
+            })
+            .collect();
+        windows.sort_by_key(|window| (window.end, window.start));
+        WindowState {
+            max_time: self.max_time,
+            watermark: self.watermark,
+            windows,
+        }
+    }
+
+    /// Replace the open windows and the progress of the watermark with a saved state
+    pub fn restore(&mut self, state: WindowState) -> Result<()> {
+        self.windows.clear();
+        self.max_time = state.max_time;
+        self.watermark = state.watermark;
+        for saved in state.windows {
+            // the rows of a session all have its key
+            let key = match saved.batches.first() {
+                Some(batch) if batch.num_rows() > 0 => self.row_keys(batch)?.remove(0),
+                _ => vec![],
+            };
+            self.windows.entry(key).or_default().push(OpenWindow {
+                id: self.next_id,
+                start: saved.start,
+                end: saved.end,
+                batches: saved.batches,
+            });
+            self.next_id += 1;
+        }
+        Ok(())
+    }
+
+    /// The ids of the windows of a row, opening windows and extending sessions as needed
+    fn assign(&mut self, time: i64, key: Vec<Option<KeyValue>>) -> Vec<usize> {
+        let watermark = self.watermark.unwrap_or(i64::MIN);
+        let bounds = match self.kind {
+            WindowKind::Tumbling { size } => {
+                let size = size.as_millis() as i64;
+                let start = time.div_euclid(size) * size;
+                vec![(start, start + size)]
+            }
+            WindowKind::Sliding { size, slide } => {
+                let (size, slide) = (size.as_millis() as i64, slide.as_millis() as i64);
+                let mut start = time.div_euclid(slide) * slide;
+                let mut bounds = vec![];
+                while start + size > time {
+                    bounds.push((start, start + size));
+                    start -= slide;
+                }
+                bounds
+            }
+            WindowKind::Session { gap } => {
+                let gap = gap.as_millis() as i64;
+                if time + gap <= watermark {
+                    return vec![];
+                }
+                return vec![self.extend_session(time, gap, key)];
+            }
+        };
+        let windows = self.windows.entry(vec![]).or_default();
+        let mut ids = vec![];
+        for (start, end) in bounds.into_iter().filter(|(_, end)| *end > watermark) {
+            match windows.iter().find(|w| w.start == start) {
+                Some(window) => ids.push(window.id),
+                None => {
+                    windows.push(OpenWindow {
+                        id: self.next_id,
+                        start,
+                        end,
+                        batches: vec![],
+                    });
+                    ids.push(self.next_id);
+                    self.next_id += 1;
+                }
+            }
+        }
+        ids
+    }
+
+    /// Add a row to the sessions of its key that its own session would overlap, merging them,
+    /// or start a new session, returning the id of the session
+    fn extend_session(&mut self, time: i64, gap: i64, key: Vec<Option<KeyValue>>) -> usize {
+        let sessions = self.windows.entry(key).or_default();
+        let (joined, mut rest): (Vec<OpenWindow>, Vec<OpenWindow>) = sessions
+            .drain(..)
+            .partition(|s| s.start - gap < time && time < s.end);
+        let session = joined
+            .into_iter()
+            .fold(None, |merged, session| match merged {
+                None => Some(session),
+                Some(mut merged) => {
+                    let OpenWindow {
+                        start,
+                        end,
+                        mut batches,
+                        ..
+                    } = session;
+                    merged.start = merged.start.min(start);
+                    merged.end = merged.end.max(end);
+                    merged.batches.append(&mut batches);
+                    Some(merged)
+                }
+            });
+        let mut session = match session {
+            Some(session) => session,
+            None => {
+                self.next_id += 1;
+                OpenWindow {
+                    id: self.next_id - 1,
+                    start: time,
+                    end: time + gap,
+                    batches: vec![],
+                }
+            }
+        };
+        session.start = session.start.min(time);
+        session.end = session.end.max(time + gap);
+        let id = session.id;
+        rest.push(session);
+        *sessions = rest;
+        id
+    }
+
+    /// Remove the windows that match a predicate, returning their rows with the bounds of
+    /// the windows, ordered by the end of the windows
+    fn close<F: Fn(&OpenWindow) -> bool>(&mut self, ready: F) -> Result<Vec<RecordBatch>> {
+        let mut closed = vec![];
+        for windows in self.windows.values_mut() {
+            let (done, open): (Vec<OpenWindow>, Vec<OpenWindow>) =
+                windows.drain(..).partition(|w| ready(w));
+            *windows = open;
+            closed.extend(done);
+        }
+        self.windows.retain(|_, windows| !windows.is_empty());
+        closed.sort_by_key(|w| (w.end, w.start));
+
+        let mut batches = vec![];
+        for window in closed {
+            for batch in window.batches {
+                let mut columns = batch.columns().to_vec();
+                let n = batch.num_rows();
+                columns.push(Arc::new(Int64Array::from(vec![window.start; n])));
+                columns.push(Arc::new(Int64Array::from(vec![window.end; n])));
+                batches.push(RecordBatch::try_new(self.schema.clone(), columns)?);
+            }
+        }
+        Ok(batches)
+    }
+
+    /// The values of the key columns of each row
+    fn row_keys(&self, batch: &RecordBatch) -> Result<Vec<Vec<Option<KeyValue>>>> {
+        let mut keys = vec![vec![]; batch.num_rows()];
+        for i in &self.key_indices {
+            let values = column_values(batch.column(*i)).ok_or_else(|| {
+                BallistaError::NotImplemented(format!(
+                    "Session keys of type {:?}",
+                    batch.column(*i).data_type()
+                ))
+            })?;
+            for (key, value) in keys.iter_mut().zip(values) {
+                key.push(value);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// The given rows of a batch
+fn take_rows(batch: &RecordBatch, rows: Vec<u32>) -> Result<RecordBatch> {
+    let indices = UInt32Array::from(rows);
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| dictionary::take(column, &indices))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+/// Check that a column exists and has event times
+fn validate_time_column(schema: &Schema, column: &str) -> Result<()> {
+    match schema.field_with_name(column)?.data_type() {
+        DataType::Int64 | DataType::Date64(_) | DataType::Timestamp(_, _) => Ok(()),
+        other => Err(BallistaError::General(format!(
+            "Event times must be timestamps but {} is {:?}",
+            column, other
+        ))),
+    }
+}
+
+/// The event times of a column in milliseconds since the epoch
+fn event_times(column: &ArrayRef) -> Result<Vec<Option<i64>>> {
+    macro_rules! times {
+        ($ARRAY:ty, $TO_MILLIS:expr) => {{
+            let array = column.as_any().downcast_ref::<$ARRAY>().unwrap();
+            Ok((0..array.len())
+                .map(|i| {
+                    if array.is_null(i) {
+                        None
+                    } else {
+                        Some($TO_MILLIS(array.value(i)))
+                    }
+                })
+                .collect())
+        }};
+    }
+    match column.data_type() {
+        DataType::Int64 => times!(Int64Array, |v| v),
+        DataType::Date64(_) => times!(Date64Array, |v| v),
+        DataType::Timestamp(TimeUnit::Second, _) => times!(TimestampSecondArray, |v| v * 1000),
+        DataType::Timestamp(TimeUnit::Millisecond, _) => times!(TimestampMillisecondArray, |v| v),
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            times!(TimestampMicrosecondArray, |v| v / 1000)
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            times!(TimestampNanosecondArray, |v| v / 1_000_000)
+        }
+        other => Err(BallistaError::General(format!(
+            "Event times must be timestamps but the column is {:?}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(times: Vec<i64>, keys: Vec<&str>) -> Result<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("t", DataType::Int64, false),
+            Field::new("k", DataType::Utf8, false),
+        ]));
+        Ok(RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(times)),
+                Arc::new(StringArray::from(keys)),
+            ],
+        )?)
+    }
+
+    /// The start, end and event time of each row
+    fn bounds(batches: &[RecordBatch]) -> Vec<(i64, i64, i64)> {
+        let mut bounds = vec![];
+        for batch in batches {
+            let column = |i: usize| {
+                let array = batch.column(i);
+                let array = array.as_any().downcast_ref::<Int64Array>().unwrap();
+                (0..array.len()).map(|j| array.value(j)).collect::<Vec<_>>()
+            };
+            let (times, starts, ends) = (column(0), column(2), column(3));
+            for i in 0..batch.num_rows() {
+                bounds.push((starts[i], ends[i], times[i]));
+            }
+        }
+        bounds
+    }
+
+    #[test]
+    fn close_windows_behind_the_watermark() -> Result<()> {
+        let input = batch(vec![0, 5, 12], vec!["a", "a", "a"])?;
+        let spec = WindowSpec::tumbling("t", Duration::from_millis(10));
+        let mut assigner = WindowAssigner::try_new(&spec, &input.schema())?;
+        assigner.push(&input)?;
+        assert_eq!(
+            bounds(&assigner.close_ready()?),
+            vec![(0, 10, 0), (0, 10, 5)]
+        );
+        // rows of closed windows are dropped
+        assigner.push(&batch(vec![3, 15], vec!["a", "a"])?)?;
+        assert!(assigner.close_ready()?.is_empty());
+        assert_eq!(
+            bounds(&assigner.finish()?),
+            vec![(10, 20, 12), (10, 20, 15)]
+        );
+
+        let spec = WindowSpec::sliding("t", Duration::from_millis(10), Duration::from_millis(5));
+        let mut assigner = WindowAssigner::try_new(&spec, &input.schema())?;
+        assigner.push(&batch(vec![7], vec!["a"])?)?;
+        assert_eq!(bounds(&assigner.finish()?), vec![(0, 10, 7), (5, 15, 7)]);
+        Ok(())
+    }
+
+    #[test]
+    fn route_rows_behind_a_declared_watermark() -> Result<()> {
+        let input = batch(vec![0, 5, 12], vec!["a", "a", "a"])?;
+        let spec = WindowSpec::tumbling("t", Duration::from_millis(10))
+            .with_late_records(LateRecords::Route);
+        let watermark = Watermark {
+            column: "t".to_owned(),
+            delay: Duration::from_millis(5),
+        };
+        let mut assigner = WindowAssigner::try_new(&spec, &input.schema())?
+            .with_watermark(&input.schema(), &watermark)?;
+        assigner.push(&input)?;
+        assert!(assigner.close_ready()?.is_empty());
+        // the row at 3 is within the delay of the watermark
+        assigner.push(&batch(vec![3, 18], vec!["a", "a"])?)?;
+        assert_eq!(
+            bounds(&assigner.close_ready()?),
+            vec![(0, 10, 0), (0, 10, 5), (0, 10, 3)]
+        );
+        assigner.push(&batch(vec![8], vec!["a"])?)?;
+        let late = assigner.take_late();
+        assert_eq!(late.len(), 1);
+        assert_eq!(late[0].num_rows(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_sessions_of_each_key() -> Result<()> {
+        let input = batch(vec![0, 15, 2, 8, 31], vec!["a", "a", "b", "a", "a"])?;
+        let spec = WindowSpec::session("t", Duration::from_millis(10), &["k"]);
+        let mut assigner = WindowAssigner::try_new(&spec, &input.schema())?;
+        assigner.push(&input)?;
+        let mut rows = bounds(&assigner.finish()?);
+        rows.sort();
+        // the row at 8 joins the sessions of the rows at 0 and 15
+        assert_eq!(
+            rows,
+            vec![
+                (0, 25, 0),
+                (0, 25, 8),
+                (0, 25, 15),
+                (2, 12, 2),
+                (31, 41, 31)
+            ]
+        );
+        Ok(())
+    }
+}
@@ -2,18 +2,24 @@ use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::process;
+use std::sync::Arc;
 use std::time::Instant;
 
 extern crate ballista;
 
+use ballista::arrow::array::{ArrayRef, Float64Array};
+use ballista::arrow::compute::kernels::arithmetic::{add, multiply};
+use ballista::arrow::compute::kernels::comparison::{gt, lt_eq};
+use ballista::arrow::compute::kernels::filter::filter;
 use ballista::arrow::datatypes::{DataType, Field, Schema};
+use ballista::arrow::error::Result as ArrowResult;
 use ballista::arrow::record_batch::RecordBatch;
 use ballista::arrow::util::pretty;
 use ballista::cluster;
 use ballista::dataframe::{max, min, sum, Context, DataFrame, CSV_BATCH_SIZE};
 use ballista::error::{BallistaError, Result};
 use ballista::logicalplan::*;
-use ballista::BALLISTA_VERSION;
+use ballista::{BALLISTA_VERSION, SIMD_ENABLED};
 
 use std::collections::HashMap;
 use tokio::task;
@@ -23,9 +29,12 @@ async fn main() -> Result<()> {
     println!("Ballista Rust Benchmarks v{}", BALLISTA_VERSION);
 
     let mode = env::var("BENCH_MODE").unwrap();
+    let result_filename = env::var("BENCH_RESULT_FILE").unwrap();
+    if mode == "kernels" {
+        return kernel_benchmark(&result_filename);
+    }
     let path = env::var("BENCH_PATH").unwrap();
     let format = env::var("BENCH_FORMAT").unwrap();
-    let result_filename = env::var("BENCH_RESULT_FILE").unwrap();
 
     match mode.as_str() {
         "local" => local_mode_benchmark(&path, &result_filename, &format).await?,
@@ -61,6 +70,63 @@ async fn local_mode_benchmark(path: &str, results_filename: &str, format: &str)
     Ok(())
 }
 
+/// Number of values in the arrays of the kernel benchmark
+const KERNEL_ARRAY_LEN: usize = 1024 * 1024;
+
+/// Number of times that each kernel is run
+const KERNEL_ITERATIONS: usize = 100;
+
+/// Time the compute kernels that filters and projections spend most of their time in, to
+/// compare builds with and without the `simd` feature
+fn kernel_benchmark(results_filename: &str) -> Result<()> {
+    println!("SIMD kernels enabled: {}", SIMD_ENABLED);
+    let len = KERNEL_ARRAY_LEN;
+    let a = Float64Array::from((0..len).map(|i| i as f64).collect::<Vec<_>>());
+    let b = Float64Array::from((0..len).map(|i| (len - i) as f64).collect::<Vec<_>>());
+    let values: ArrayRef = Arc::new(a.clone());
+    let mask = gt(&a, &b)?;
+
+    let results = vec![
+        ("add", time_kernel("add", || add(&a, &b))?),
+        ("multiply", time_kernel("multiply", || multiply(&a, &b))?),
+        ("gt", time_kernel("gt", || gt(&a, &b))?),
+        ("lt_eq", time_kernel("lt_eq", || lt_eq(&a, &b))?),
+        (
+            "filter",
+            time_kernel("filter", || filter(values.as_ref(), &mask))?,
+        ),
+    ];
+
+    let mut file = File::create(results_filename).unwrap();
+    file.write_all(b"kernel,simd,iterations,time_millis\n")
+        .unwrap();
+    for (kernel, millis) in results {
+        let line = format!(
+            "{},{},{},{}\n",
+            kernel, SIMD_ENABLED, KERNEL_ITERATIONS, millis
+        );
+        file.write_all(line.as_bytes()).unwrap();
+    }
+    Ok(())
+}
+
+/// Run a kernel repeatedly, returning the total time in milliseconds
+fn time_kernel<T, F>(name: &str, mut kernel: F) -> Result<u128>
+where
+    F: FnMut() -> ArrowResult<T>,
+{
+    let start = Instant::now();
+    for _ in 0..KERNEL_ITERATIONS {
+        kernel()?;
+    }
+    let millis = start.elapsed().as_millis();
+    println!(
+        "{} took {} ms for {} iterations",
+        name, millis, KERNEL_ITERATIONS
+    );
+    Ok(millis)
+}
+
 async fn k8s(path: &str, format: &str) -> Result<()> {
     let cluster_name = "ballista";
     let namespace = "default";
@@ -19,7 +19,7 @@ async fn main() -> Result<()> {
     spark_settings.insert("spark.executor.memory", "4g");
     spark_settings.insert("spark.executor.cores", "4");
 
-    let ctx = Context::spark(spark_master, spark_settings);
+    let ctx = Context::spark(spark_master, spark_settings)?;
 
     let path = "/mnt/nyctaxi/csv/yellow/2019/yellow_tripdata_2019-01.csv";
 
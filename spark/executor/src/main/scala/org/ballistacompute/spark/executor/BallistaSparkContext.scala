@@ -48,6 +48,20 @@ class BallistaSparkContext(spark: SparkSession) {
         val df = createDataFrame(l.getInput, input)
         df.limit(l.getLimit)
 
+      case s: ballista.Sort =>
+        val df = createDataFrame(s.getInput, input)
+        val sortExpr = s.getExpr.asScala.map(e => createExpression(e, df))
+        df.orderBy(sortExpr: _*)
+
+      case j: ballista.Join =>
+        val left = createDataFrame(j.getLeft, input)
+        val right = createDataFrame(j.getRight, input)
+        // join columns are referenced by index into the columns of each input
+        val joinExpr = j.getOn.asScala
+          .map(on => left.col(left.columns(on.getFirst)) === right.col(right.columns(on.getSecond)))
+          .reduce(_ && _)
+        left.join(right, joinExpr)
+
       case a: ballista.Aggregate =>
         val df = createDataFrame(a.getInput, input)
         val groupExpr = a.getGroupExpr.asScala.map(e => createExpression(e, df))
@@ -62,6 +76,7 @@ class BallistaSparkContext(spark: SparkSession) {
             case "max" => max(col(fieldName))
             case "sum" => sum(col(fieldName))
             case "avg" => avg(col(fieldName))
+            case "count" => count(col(fieldName))
           }
         }
 
@@ -84,10 +99,25 @@ class BallistaSparkContext(spark: SparkSession) {
       case c: ballista.LiteralDouble => lit(c.getN)
       case c: ballista.LiteralLong => lit(c.getN)
       case c: ballista.LiteralString => lit(c.getStr)
+      case c: ballista.LiteralBoolean => lit(c.getB)
 
       case c: ballista.Column =>
         input.col(c.getName)
 
+      case a: ballista.Alias =>
+        createExpression(a.getExpr, input).as(a.getAlias)
+
+      case c: ballista.CastExpr =>
+        createExpression(c.getExpr, input).cast(ArrowUtils.fromArrowType(c.getDataType))
+
+      case s: ballista.SortExpr =>
+        val e = createExpression(s.getExpr, input)
+        if (s.getAsc) e.asc else e.desc
+
+      case n: ballista.Not => not(createExpression(n.getExpr, input))
+      case n: ballista.IsNull => createExpression(n.getExpr, input).isNull
+      case n: ballista.IsNotNull => createExpression(n.getExpr, input).isNotNull
+
       case b: org.ballistacompute.logical.BinaryExpr =>
         val l = createExpression(b.getL, input)
         val r = createExpression(b.getR, input)
@@ -97,6 +127,7 @@ class BallistaSparkContext(spark: SparkSession) {
           case _: ballista.Subtract => l.minus(r)
           case _: ballista.Multiply => l.multiply(r)
           case _: ballista.Divide => l.divide(r)
+          case _: ballista.Modulus => l.mod(r)
 
           case _: ballista.Eq => l.equalTo(r)
           case _: ballista.Neq => l.notEqual(r)
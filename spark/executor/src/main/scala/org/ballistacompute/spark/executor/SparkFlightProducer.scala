@@ -10,8 +10,11 @@ import scala.collection.JavaConverters._
 import org.apache.arrow.flight.{Action, ActionType, Criteria, FlightDescriptor, FlightInfo, FlightProducer, FlightStream, PutResult, Result, Ticket}
 import org.apache.arrow.memory.RootAllocator
 import org.apache.arrow.vector.ipc.message.{ArrowFieldNode, ArrowRecordBatch}
-import org.apache.arrow.vector.{FieldVector, Float4Vector, Float8Vector, IntVector, TypeLayout, VarBinaryVector, VarCharVector, VectorLoader, VectorSchemaRoot}
-import org.apache.spark.sql.SparkSession
+import org.apache.arrow.vector.types.pojo.{ArrowType, Field, FieldType, Schema}
+import org.apache.arrow.vector.{FieldVector, Float4Vector, Float8Vector, IntVector, TypeLayout, UInt8Vector, VarBinaryVector, VarCharVector, VectorLoader, VectorSchemaRoot}
+import org.apache.hadoop.fs.Path
+import org.apache.spark.sql.functions.input_file_name
+import org.apache.spark.sql.{DataFrame, SparkSession}
 import org.ballistacompute.protobuf
 
 
@@ -23,70 +26,38 @@ class SparkFlightProducer(spark: SparkSession) extends FlightProducer {
     try {
       val action: protobuf.Action = protobuf.Action.parseFrom(ticket.getBytes)
 
-      val logicalPlan = new protobuf.ProtobufDeserializer().fromProto(action.getQuery)
+      // write actions give the format and directory that the output is written to
+      val (plan, output) = if (action.hasWriteParquet) {
+        (action.getWriteParquet.getPlan, Some(("parquet", action.getWriteParquet.getPath)))
+      } else if (action.hasWriteCsv) {
+        (action.getWriteCsv.getPlan, Some(("csv", action.getWriteCsv.getPath)))
+      } else {
+        (action.getQuery, None)
+      }
 
-      println(s"Ballista logical plan:\n${logicalPlan.pretty()}")
+      val logicalPlan = new protobuf.ProtobufDeserializer().fromProto(plan)
 
-      val ctx = new BallistaSparkContext(spark)
-      val df = ctx.createDataFrame(logicalPlan, None)
-      df.explain()
+      println(s"Ballista logical plan:\n${logicalPlan.pretty()}")
 
-      // collect entire result set into memory - not scalable
-      val rows = df.collect()
-      rows.foreach(println)
-
-      val sparkSchema = df.schema
-
-      //TODO should be able to delegate to common code in ballista-jvm for most of this, rather than duplicate here
-      val allocator = new RootAllocator(Long.MaxValue)
-
-      val root = VectorSchemaRoot.create(logicalPlan.schema().toArrow(), allocator)
-      root.getFieldVectors.asScala.foreach(_.setInitialCapacity(rows.size))
-      root.allocateNew()
-
-      listener.start(root, null)
-
-      rows.zipWithIndex.foreach {
-
-        //TODO null handling
-
-        case (row, row_index) =>
-          for (i <- 0 until sparkSchema.length) {
-            root.getVector(i) match {
-              case v: IntVector =>
-                if (row.isNullAt(i)) {
-                  v.setNull(row_index)
-                } else {
-                  v.set(row_index, row.getInt(i))
-                }
-              case v: Float4Vector =>
-                if (row.isNullAt(i)) {
-                  v.setNull(row_index)
-                } else {
-                  v.set(row_index, row.getFloat(i))
-                }
-              case v: Float8Vector =>
-                if (row.isNullAt(i)) {
-                  v.setNull(row_index)
-                } else {
-                  v.set(row_index, row.getDouble(i))
-                }
-              case v: VarCharVector =>
-                if (row.isNullAt(i)) {
-                  v.setNull(row_index)
-                } else {
-                  v.set(row_index, row.getString(i).getBytes)
-                }
-              case other =>
-                println(s"No support for $other yet")
-            }
+      // each action runs in its own session, with the settings that the client sent
+      val session = spark.newSession()
+      action.getSettingsMap.asScala.foreach {
+        case (key, value) =>
+          if (session.conf.isModifiable(key)) {
+            session.conf.set(key, value)
+          } else {
+            println(s"Ignoring setting $key, which cannot be changed for a session")
           }
       }
 
-      root.setRowCount(rows.length)
-      listener.putNext()
+      val ctx = new BallistaSparkContext(session)
+      val df = ctx.createDataFrame(logicalPlan, None)
+      df.explain()
 
-      listener.completed()
+      output match {
+        case Some((format, path)) => writeFiles(session, df, format, path, listener)
+        case None => sendRows(df, logicalPlan, listener)
+      }
 
     } catch {
       case e: Exception =>
@@ -96,6 +67,113 @@ class SparkFlightProducer(spark: SparkSession) extends FlightProducer {
 
   }
 
+  /** Collect the results of a query and send them to the client */
+  private def sendRows(df: DataFrame, logicalPlan: org.ballistacompute.logical.LogicalPlan, listener: FlightProducer.ServerStreamListener): Unit = {
+
+    // collect entire result set into memory - not scalable
+    val rows = df.collect()
+    rows.foreach(println)
+
+    val sparkSchema = df.schema
+
+    //TODO should be able to delegate to common code in ballista-jvm for most of this, rather than duplicate here
+    val allocator = new RootAllocator(Long.MaxValue)
+
+    val root = VectorSchemaRoot.create(logicalPlan.schema().toArrow(), allocator)
+    root.getFieldVectors.asScala.foreach(_.setInitialCapacity(rows.size))
+    root.allocateNew()
+
+    listener.start(root, null)
+
+    rows.zipWithIndex.foreach {
+
+      //TODO null handling
+
+      case (row, row_index) =>
+        for (i <- 0 until sparkSchema.length) {
+          root.getVector(i) match {
+            case v: IntVector =>
+              if (row.isNullAt(i)) {
+                v.setNull(row_index)
+              } else {
+                v.set(row_index, row.getInt(i))
+              }
+            case v: Float4Vector =>
+              if (row.isNullAt(i)) {
+                v.setNull(row_index)
+              } else {
+                v.set(row_index, row.getFloat(i))
+              }
+            case v: Float8Vector =>
+              if (row.isNullAt(i)) {
+                v.setNull(row_index)
+              } else {
+                v.set(row_index, row.getDouble(i))
+              }
+            case v: VarCharVector =>
+              if (row.isNullAt(i)) {
+                v.setNull(row_index)
+              } else {
+                v.set(row_index, row.getString(i).getBytes)
+              }
+            case other =>
+              println(s"No support for $other yet")
+          }
+        }
+    }
+
+    root.setRowCount(rows.length)
+    listener.putNext()
+
+    listener.completed()
+  }
+
+  /**
+   * Write the results of a query as files of the given format to a directory and send the
+   * manifest of the files, with the path, number of rows and size of each file, to the client
+   */
+  private def writeFiles(session: SparkSession, df: DataFrame, format: String, path: String, listener: FlightProducer.ServerStreamListener): Unit = {
+    df.write.format(format).option("header", "true").save(path)
+
+    // count the rows of each file by reading the output back
+    val rowCounts = session.read.format(format).option("header", "true").schema(df.schema).load(path)
+      .groupBy(input_file_name()).count().collect()
+      .map(row => new Path(row.getString(0)).getName -> row.getLong(1)).toMap
+
+    val outputPath = new Path(path)
+    val fs = outputPath.getFileSystem(session.sparkContext.hadoopConfiguration)
+    val files = fs.listStatus(outputPath)
+      .filter(f => f.isFile && f.getPath.getName.startsWith("part-"))
+      .sortBy(_.getPath.getName)
+
+    val schema = new Schema(Seq(
+      new Field("path", FieldType.notNullable(new ArrowType.Utf8()), null),
+      new Field("num_rows", FieldType.notNullable(new ArrowType.Int(64, false)), null),
+      new Field("size", FieldType.notNullable(new ArrowType.Int(64, false)), null)
+    ).asJava)
+
+    val allocator = new RootAllocator(Long.MaxValue)
+    val root = VectorSchemaRoot.create(schema, allocator)
+    root.allocateNew()
+
+    listener.start(root, null)
+
+    val paths = root.getVector("path").asInstanceOf[VarCharVector]
+    val numRows = root.getVector("num_rows").asInstanceOf[UInt8Vector]
+    val sizes = root.getVector("size").asInstanceOf[UInt8Vector]
+    files.zipWithIndex.foreach {
+      case (file, i) =>
+        paths.setSafe(i, file.getPath.toString.getBytes)
+        numRows.setSafe(i, rowCounts.getOrElse(file.getPath.getName, 0L))
+        sizes.setSafe(i, file.getLen)
+    }
+
+    root.setRowCount(files.length)
+    listener.putNext()
+
+    listener.completed()
+  }
+
   override def getFlightInfo(context: FlightProducer.CallContext, descriptor: FlightDescriptor): FlightInfo = ???
 
   override def listFlights(context: FlightProducer.CallContext, criteria: Criteria, listener: FlightProducer.StreamListener[FlightInfo]): Unit = ???